@@ -0,0 +1,87 @@
+//! Benchmarks of win detection and of the messages sent after every turn.
+//! Run with `cargo bench --features bench`, optionally followed by `--` and
+//! a filter, e.g. `cargo bench --features bench -- sync`. Criterion saves
+//! the results in `target/criterion` and compares later runs against them.
+
+#![warn(clippy::all, clippy::pedantic)]
+
+use std::hint::black_box;
+
+use connect_four_server::game::{Game, GameRules, FIELD_SIZE};
+use connect_four_server::server::protocol::{OutgoingGameSync, OutgoingMessage};
+use criterion::{criterion_group, criterion_main, Criterion};
+use rand::{rngs::StdRng, seq::SliceRandom, SeedableRng};
+
+/// Returns a game with every column but one full and no result, so that the
+/// last move checks the full board. The position is random, but the same in
+/// every run.
+fn almost_full_game() -> Game {
+    let rules = GameRules {
+        allow_draws: true,
+        win_len: FIELD_SIZE,
+        ..GameRules::default()
+    };
+    let mut rng = StdRng::seed_from_u64(0);
+    loop {
+        let mut cols: Vec<usize> = (0..FIELD_SIZE).flat_map(|col| [col; FIELD_SIZE]).collect();
+        cols.shuffle(&mut rng);
+
+        let mut game = Game::new(rules.clone());
+        let last = cols.pop().unwrap();
+        for col in cols {
+            game.end_turn(Some(col)).unwrap();
+            if game.state().result.is_some() {
+                break;
+            }
+        }
+
+        let mut full = game.clone();
+        if game.state().result.is_none() && full.end_turn(Some(last)).is_ok() {
+            return game;
+        }
+    }
+}
+
+fn end_turn(c: &mut Criterion) {
+    let opening = [3, 3, 2, 4, 4];
+    let mut mid_game = Game::default();
+    for col in opening {
+        mid_game.end_turn(Some(col)).unwrap();
+    }
+    c.bench_function("end_turn", |b| {
+        b.iter(|| {
+            let mut game = black_box(&mid_game).clone();
+            game.end_turn(Some(2))
+        });
+    });
+}
+
+fn full_board(c: &mut Criterion) {
+    let almost_full = almost_full_game();
+    let last = (0..FIELD_SIZE)
+        .find(|col| !almost_full.is_column_full(*col))
+        .unwrap();
+    c.bench_function("end_turn_full_board", |b| {
+        b.iter(|| {
+            let mut game = black_box(&almost_full).clone();
+            game.end_turn(Some(last))
+        });
+    });
+    // Part of the time of `end_turn_full_board`
+    c.bench_function("clone_full_board", |b| {
+        b.iter(|| black_box(&almost_full).clone());
+    });
+}
+
+fn serialize_sync(c: &mut Criterion) {
+    let almost_full = almost_full_game();
+    c.bench_function("serialize_sync", |b| {
+        b.iter(|| {
+            let sync = OutgoingGameSync::new(0, black_box(&almost_full), None, None);
+            serde_json::to_string(&OutgoingMessage::GameSync(sync)).unwrap()
+        });
+    });
+}
+
+criterion_group!(benches, end_turn, full_board, serialize_sync);
+criterion_main!(benches);
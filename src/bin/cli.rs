@@ -1,11 +1,20 @@
 #![warn(clippy::all, clippy::pedantic)]
 
-use connect_four_server::game::{EndTurnError, Game, GameRules, GameWinner, Player, FIELD_SIZE};
+use connect_four_server::game::analysis::{self, WIN_SCORE};
+use connect_four_server::game::{Action, EndTurnError, Game, GameRules, Player};
+use connect_four_server::replay::Replay;
 use std::io::BufRead;
+use std::process::ExitCode;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// How many turns ahead `hint` and `eval` look if no depth is given.
+const DEFAULT_DEPTH: u32 = 6;
 
 struct App {
     game: Game,
     moves: Vec<usize>,
+    /// Side played by the computer and how many turns ahead it looks.
+    ai: Option<(Player, u32)>,
 }
 
 impl App {
@@ -13,6 +22,7 @@ impl App {
         Self {
             game: Game::new(GameRules::default()),
             moves: Vec::new(),
+            ai: None,
         }
     }
 
@@ -30,11 +40,73 @@ impl App {
         }
     }
 
+    /// Starts from a game read from a file in the text notation.
+    fn import(path: &str) -> Result<Self, String> {
+        let contents = std::fs::read_to_string(path).map_err(|e| format!("{path}: {e}"))?;
+        let game = Game::from_notation(&contents).map_err(|e| format!("{path}: {e:?}"))?;
+        let moves = game.moves().iter().filter_map(|m| m.col).collect();
+        Ok(Self {
+            game,
+            moves,
+            ai: None,
+        })
+    }
+
     fn restart(&mut self) {
         self.game = Game::new(self.game.rules().clone());
         self.moves.clear();
     }
 
+    /// Drops a chip into the column, returns true if the move was made.
+    fn drop_chip(&mut self, m: usize) -> bool {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| u64::try_from(d.as_millis()).unwrap_or(u64::MAX));
+        let res = self.game.play_at(Some(Action::Drop(m)), now);
+        if let Err(EndTurnError::GameOver) = res {
+            println!("Game over!");
+            return false;
+        } else if let Err(EndTurnError::ColumnFilled) = res {
+            println!("Not enough space!");
+            return false;
+        }
+
+        self.moves.push(m);
+        true
+    }
+
+    /// Makes the move of the computer if it is its turn.
+    fn play_ai(&mut self) {
+        let Some((player, depth)) = self.ai else {
+            return;
+        };
+        if self.game.state().result.is_some() || self.game.state().player != player {
+            return;
+        }
+
+        let (moves, _) = analysis::best_moves(&self.game, depth);
+        if let Some(&m) = moves.first() {
+            println!("Computer plays {}", m + 1);
+            self.drop_chip(m);
+        }
+    }
+
+    fn print_hint(&self, depth: u32) {
+        let (moves, score) = analysis::best_moves(&self.game, depth);
+        if moves.is_empty() {
+            println!("No moves left");
+            return;
+        }
+
+        let cols: Vec<usize> = moves.iter().map(|m| m + 1).collect();
+        println!("Best columns: {cols:?} ({})", describe_score(score, depth));
+    }
+
+    fn print_evaluation(&self, depth: u32) {
+        let score = analysis::evaluate(&self.game, depth);
+        println!("{}", describe_score(score, depth));
+    }
+
     fn process_move(&mut self, cmd: &str) {
         if cmd.chars().count() == 1 {
             let c = cmd.chars().next().unwrap();
@@ -43,20 +115,28 @@ impl App {
             }
 
             let m = (c as usize) - ('1' as usize);
-            let res = self.game.end_turn(Some(m));
-            if let Err(EndTurnError::GameOver) = res {
-                println!("Game over!");
-                return;
-            } else if let Err(EndTurnError::ColumnFilled) = res {
-                println!("Not enough space!");
-                return;
-            };
-
-            self.moves.push(m);
+            if self.drop_chip(m) {
+                self.play_ai();
+            }
             return;
         }
 
+        let mut words = cmd.split_whitespace();
+        let (cmd, arg) = (words.next().unwrap_or_default(), words.next());
+        let depth = arg.and_then(|arg| arg.parse::<u32>().ok());
+
         match cmd {
+            "hint" => self.print_hint(depth.unwrap_or(DEFAULT_DEPTH)),
+            "eval" => self.print_evaluation(depth.unwrap_or(DEFAULT_DEPTH)),
+            "ai" if arg == Some("off") => self.ai = None,
+            "ai" => {
+                let Some(depth) = depth else {
+                    println!("Usage: ai <DEPTH> | ai off");
+                    return;
+                };
+                self.ai = Some((self.game.state().player, depth));
+                self.play_ai();
+            }
             "restart" => self.restart(),
             "moves" => {
                 let moves = self
@@ -67,15 +147,612 @@ impl App {
                     .collect::<Vec<usize>>();
                 println!("{moves:?}");
             }
+            "history" => {
+                for (i, m) in self.game.moves().iter().enumerate() {
+                    let player = player_name(m.player);
+                    match m.col {
+                        Some(col) => println!("{}. {player} {} @ {}", i + 1, col + 1, m.timestamp),
+                        None => println!("{}. {player} skipped @ {}", i + 1, m.timestamp),
+                    }
+                }
+            }
             "json" => println!("{}", serde_json::to_string_pretty(&self.game).unwrap()),
+            "export" => print!("{}", self.game.to_notation()),
+            "import" => match arg.map(Self::import) {
+                Some(Ok(app)) => *self = app,
+                Some(Err(e)) => println!("{e}"),
+                None => println!("Usage: import <FILE>"),
+            },
             "exit" => std::process::exit(0),
             _ => (),
         }
     }
 }
 
-fn main() {
-    App::new().run();
+/// Describes a score returned by `analysis` when looking `depth` turns
+/// ahead, from the perspective of the current player.
+fn describe_score(score: i32, depth: u32) -> String {
+    // Won positions are scored higher the fewer turns they take
+    let turns = depth.saturating_sub(
+        score
+            .unsigned_abs()
+            .saturating_sub(WIN_SCORE.unsigned_abs()),
+    );
+    let plural = if turns == 1 { "" } else { "s" };
+    if score >= WIN_SCORE {
+        format!("forced win in {turns} turn{plural}")
+    } else if score <= -WIN_SCORE {
+        format!("forced loss in {turns} turn{plural}")
+    } else {
+        format!("score {score:+}")
+    }
+}
+
+fn player_name(player: Player) -> &'static str {
+    match player {
+        Player::P1 => "(Player 1)",
+        Player::P2 => "[Player 2]",
+        Player::P3 => "{Player 3}",
+        Player::P4 => "<Player 4>",
+    }
+}
+
+/// Steps through a replay or an archived game.
+struct ReplayViewer {
+    replay: Replay,
+    /// Amount of moves shown.
+    pos: usize,
+}
+
+impl ReplayViewer {
+    fn open(path: &str) -> Result<Self, String> {
+        let contents = std::fs::read_to_string(path).map_err(|e| format!("{path}: {e}"))?;
+        let replay: Replay = serde_json::from_str(&contents).map_err(|e| format!("{path}: {e}"))?;
+        replay
+            .validate()
+            .map_err(|e| format!("{path}: invalid replay: {e:?}"))?;
+        Ok(Self { replay, pos: 0 })
+    }
+
+    fn run(&mut self) {
+        self.show();
+
+        let stdin = std::io::stdin();
+        for line in stdin.lock().lines() {
+            let Ok(line) = line else {
+                continue;
+            };
+
+            let len = self.replay.moves.len();
+            let mut words = line.split_whitespace();
+            match (words.next(), words.next()) {
+                (Some("next" | "n") | None, _) => self.pos = (self.pos + 1).min(len),
+                (Some("prev" | "p"), _) => self.pos = self.pos.saturating_sub(1),
+                (Some("goto" | "g"), Some(n)) => match n.parse::<usize>() {
+                    Ok(n) => self.pos = n.min(len),
+                    Err(_) => println!("Not a move number: {n}"),
+                },
+                (Some("exit"), _) => std::process::exit(0),
+                _ => println!("Commands: next, prev, goto <MOVE>, exit"),
+            }
+            self.show();
+        }
+    }
+
+    fn show(&self) {
+        // Validated when opened, every prefix is valid as well
+        let game = self.replay.game_at(self.pos).unwrap();
+        print!("{}", game.to_string());
+        println!("Move {}/{}", self.pos, self.replay.moves.len());
+        let Some(turn) = self.pos.checked_sub(1).and_then(|t| u32::try_from(t).ok()) else {
+            return;
+        };
+        for annotation in self.replay.annotations_of(turn) {
+            let author = match (&annotation.name, annotation.player) {
+                (Some(name), _) => name.clone(),
+                (None, Some(player)) => format!("{player:?}"),
+                (None, None) => String::from("Spectator"),
+            };
+            println!("  {author}: {}", annotation.text);
+        }
+    }
+}
+
+/// Terminal client for a running server.
+#[cfg(feature = "server")]
+mod online {
+    use std::error::Error;
+    use std::io::BufRead;
+    use std::sync::mpsc::{self, TryRecvError};
+    use std::thread;
+    use std::time::Duration;
+
+    use base64::{engine::general_purpose, Engine as _};
+    use connect_four_server::game::{field_size, Action, Game, MoveKind, Player, MAX_PLAYERS};
+    use connect_four_server::game_config::PartialGameConfig;
+    use connect_four_server::server::client::{self, Socket};
+    use connect_four_server::server::protocol::{
+        IncomingEndTurn, IncomingMessage, IncomingPickPlayer, IncomingPlayerSelectionVote,
+        IncomingRestart, OutgoingGameMove, OutgoingLobbyLink, OutgoingMessage, QrFormat, Role, QR,
+    };
+    use tungstenite::Message;
+    use url::Url;
+
+    use super::{player_name, ToString};
+
+    /// How long to wait for server messages before checking for commands.
+    const POLL_INTERVAL: Duration = Duration::from_millis(20);
+    /// Protocol version requested if the URL does not name one.
+    const PROTOCOL_VERSION: &str = "2";
+    /// Width of the light border around the QR code, in modules.
+    const QR_QUIET_ZONE: u32 = 2;
+
+    const COMMANDS: &str = "\
+Commands:
+  1-7                    Drop a chip into the column
+  pop <1-7>              Pop your chip out of the column
+  place <1-7> <1-7>      Place a chip into the column and row, counted from
+                         the top (free placement games only)
+  swap                   Take over the first move (pie rule games only)
+  first, second          Vote for who starts the game
+  pick <CODE> [p1|p2]    Start a game with a player from the lobby (host only)
+  ready, unready         Tell the host whether you are ready to be picked
+  kick <CODE> [block]    Remove a player from the lobby, blocking them from
+                         joining again with `block` (host only)
+  restart                Ask for a new round
+  accept, reject         Respond to a restart request
+  resign                 Concede the game
+  sync                   Ask for the complete state of the game
+  say <TEXT>             Send a chat message
+  exit                   Disconnect
+";
+
+    pub struct OnlineClient {
+        socket: Socket,
+        /// URL the client connected with, used to print the invite.
+        url: Url,
+        role: Option<Role>,
+        round: u32,
+        game: Option<Game>,
+    }
+
+    impl OnlineClient {
+        /// Connects to the server. Joins the lobby named in the URL or
+        /// creates a new one.
+        pub fn connect(url: &str, insecure: bool) -> Result<Self, Box<dyn Error>> {
+            let mut url = Url::parse(url)?;
+            if !url.query_pairs().any(|(k, _)| k == "version") {
+                url.query_pairs_mut()
+                    .append_pair("version", PROTOCOL_VERSION);
+            }
+
+            let socket = client::connect(&url, insecure, POLL_INTERVAL)?;
+            Ok(Self {
+                socket,
+                url,
+                role: None,
+                round: 0,
+                game: None,
+            })
+        }
+
+        pub fn run(&mut self) -> Result<(), Box<dyn Error>> {
+            let (tx, lines) = mpsc::channel();
+            thread::spawn(move || {
+                for line in std::io::stdin().lock().lines() {
+                    let Ok(line) = line else {
+                        continue;
+                    };
+                    if tx.send(line).is_err() {
+                        return;
+                    }
+                }
+            });
+
+            print!("{COMMANDS}");
+            loop {
+                match self.socket.read() {
+                    Ok(Message::Text(text)) => self.process_message(&text)?,
+                    Ok(Message::Close(frame)) => {
+                        let reason = frame.map(|f| f.reason.into_owned()).unwrap_or_default();
+                        println!("Disconnected: {reason}");
+                        return Ok(());
+                    }
+                    Ok(_) => (),
+                    Err(e) if client::is_timeout(&e) => (),
+                    Err(tungstenite::Error::ConnectionClosed) => return Ok(()),
+                    Err(e) => return Err(e.into()),
+                }
+
+                loop {
+                    match lines.try_recv() {
+                        Ok(line) if line.trim() != "exit" => self.process_command(&line)?,
+                        Err(TryRecvError::Empty) => break,
+                        Ok(_) | Err(TryRecvError::Disconnected) => {
+                            self.socket.close(None)?;
+                            return Ok(());
+                        }
+                    }
+                }
+            }
+        }
+
+        fn send(&mut self, msg: &IncomingMessage) -> Result<(), Box<dyn Error>> {
+            let text = serde_json::to_string(msg)?;
+            self.socket.send(Message::Text(text))?;
+            Ok(())
+        }
+
+        fn process_message(&mut self, text: &str) -> Result<(), Box<dyn Error>> {
+            let Ok(msg) = serde_json::from_str::<OutgoingMessage>(text) else {
+                return Ok(());
+            };
+
+            match msg {
+                OutgoingMessage::LobbyLink(link) => self.show_invite(&link),
+                OutgoingMessage::LobbySync { players, ready, .. } => {
+                    let codes: Vec<String> = players
+                        .iter()
+                        .map(|code| {
+                            if ready.contains(code) {
+                                format!("{code} (ready)")
+                            } else {
+                                code.to_string()
+                            }
+                        })
+                        .collect();
+                    if codes.is_empty() {
+                        println!("Waiting for players to join");
+                    } else {
+                        println!("Players in the lobby: {}", codes.join(", "));
+                    }
+                }
+                OutgoingMessage::LobbyCode { code } => {
+                    println!("Joined the lobby with code {code}, waiting for the host");
+                }
+                OutgoingMessage::LobbyPickRejected { code, reason } => {
+                    println!("Could not start a game with {code}: {reason:?}");
+                }
+                OutgoingMessage::GameSetup(setup) => {
+                    if let Some(role) = setup.role {
+                        self.role = Some(role);
+                        match role {
+                            Role::Player(player) => println!("You are {}", player_name(player)),
+                            Role::Spectator => println!("You are spectating"),
+                        }
+                    }
+                }
+                OutgoingMessage::GamePlayerSelection(selection) => {
+                    let voted = match self.role {
+                        Some(Role::Player(player)) => selection.has_voted(player),
+                        Some(Role::Spectator) | None => true,
+                    };
+                    if !voted {
+                        println!("Who starts? Type `first` or `second`");
+                    }
+                }
+                OutgoingMessage::GameSync(sync) => {
+                    self.round = sync.round;
+                    self.game = Some(sync.game.into_owned());
+                    self.show();
+                }
+                OutgoingMessage::GameMove(mv) => return self.apply_move(&mv),
+                OutgoingMessage::GameEndTurnRejected { reason, .. } => {
+                    println!("Move rejected: {reason:?}");
+                }
+                OutgoingMessage::GameRestartRequest(request) => {
+                    let own = self.role == Some(Role::Player(request.player));
+                    match (&request.req, own) {
+                        (Some(_), true) => println!("Asked for a restart"),
+                        (Some(_), false) => println!(
+                            "{} wants to restart, type `accept` or `reject`",
+                            player_name(request.player)
+                        ),
+                        (None, _) => println!("The restart request expired"),
+                    }
+                }
+                OutgoingMessage::GameChat { player, text, .. } => {
+                    println!("{}: {text}", player_name(player));
+                }
+                OutgoingMessage::LobbyChat { code, text, .. } => match code {
+                    Some(code) => println!("{code}: {text}"),
+                    None => println!("Host: {text}"),
+                },
+                OutgoingMessage::GameInProgress { .. } => {
+                    println!("A game is in progress, you are spectating");
+                }
+                OutgoingMessage::GamePlayerDisconnected { player, .. } => {
+                    println!("{} lost connection", player_name(player));
+                }
+                OutgoingMessage::GamePlayerReconnected { player, .. } => {
+                    println!("{} reconnected", player_name(player));
+                }
+                OutgoingMessage::ConfigRejected {
+                    rejection,
+                    description,
+                } => {
+                    if let Some(description) = description {
+                        println!("Configuration rejected: {description}");
+                    } else {
+                        let (field, reason) = (rejection.field, rejection.reason);
+                        println!("Configuration rejected: {field:?} is {reason:?}");
+                    }
+                }
+                OutgoingMessage::MatchmakingQueued { .. } => {
+                    println!("Waiting for an opponent...");
+                }
+                _ => (),
+            }
+            Ok(())
+        }
+
+        /// Prints the command and QR code others can use to join the lobby.
+        fn show_invite(&self, link: &OutgoingLobbyLink) {
+            let pairs: Vec<(String, String)> = self
+                .url
+                .query_pairs()
+                .filter(|(k, _)| k != "lobby")
+                .map(|(k, v)| (k.into_owned(), v.into_owned()))
+                .collect();
+            let mut invite = self.url.clone();
+            invite
+                .query_pairs_mut()
+                .clear()
+                .extend_pairs(pairs)
+                .append_pair("lobby", &link.lobby);
+            println!(
+                "Created lobby {} (join code {}), others can join with:",
+                link.lobby, link.join_code
+            );
+            println!("  cli --connect '{invite}'");
+            if let Some(qr) = render_qr(&link.qr_code) {
+                print!("{qr}");
+            }
+        }
+
+        /// Makes the move on the local copy of the game, asking for the
+        /// complete state if it is out of date.
+        fn apply_move(&mut self, mv: &OutgoingGameMove) -> Result<(), Box<dyn Error>> {
+            let Some(game) = self.game.as_mut().filter(|_| mv.round == self.round) else {
+                return self.send(&IncomingMessage::GameRequestSync);
+            };
+            if mv.turn < game.state().turn {
+                return Ok(());
+            }
+
+            let action = mv.col.and_then(|col| Action::new(mv.kind, col, mv.row));
+            let applied = mv.turn == game.state().turn && game.play(action).is_ok();
+            if !applied || game.state().turn != mv.state.turn {
+                return self.send(&IncomingMessage::GameRequestSync);
+            }
+            self.show();
+            Ok(())
+        }
+
+        fn process_command(&mut self, line: &str) -> Result<(), Box<dyn Error>> {
+            let line = line.trim();
+            let (cmd, rest) = line.split_once(' ').unwrap_or((line, ""));
+            let is_move =
+                matches!(cmd, "pop" | "place") || (rest.is_empty() && parse_column(cmd).is_some());
+            if is_move && self.game.is_none() {
+                println!("No game in progress");
+                return Ok(());
+            }
+
+            let msg = match (cmd, rest.trim()) {
+                ("", _) => return Ok(()),
+                ("pop", col) => self.end_turn(MoveKind::Pop, col, None),
+                ("place", args) => {
+                    let (col, row) = args.split_once(' ').unwrap_or((args, ""));
+                    self.end_turn(MoveKind::Place, col, Some(row.trim()))
+                }
+                (col, "") if parse_column(col).is_some() => {
+                    self.end_turn(MoveKind::Drop, col, None)
+                }
+                ("first" | "second", _) => Some(IncomingMessage::GamePlayerSelectionVote(
+                    IncomingPlayerSelectionVote {
+                        wants_to_start: cmd == "first",
+                        seq: None,
+                    },
+                )),
+                ("pick", args) => pick(args),
+                ("kick", args) => kick(args),
+                ("ready" | "unready", _) => Some(IncomingMessage::LobbyReady {
+                    ready: cmd == "ready",
+                }),
+                ("restart", _) => Some(IncomingMessage::GameRestart(IncomingRestart {
+                    partial: None,
+                    seq: None,
+                })),
+                ("accept" | "reject", _) => Some(IncomingMessage::GameRestartResponse {
+                    accepted: cmd == "accept",
+                    seq: None,
+                }),
+                ("resign", _) => Some(IncomingMessage::GameResign { seq: None }),
+                ("swap", _) => self
+                    .game
+                    .as_ref()
+                    .map(|game| IncomingMessage::GameSwapSides {
+                        turn: game.state().turn,
+                        seq: None,
+                    }),
+                ("sync", _) => Some(IncomingMessage::GameRequestSync),
+                ("say", text) => match self.game {
+                    Some(_) => Some(IncomingMessage::GameChat {
+                        text: String::from(text),
+                    }),
+                    None => Some(IncomingMessage::LobbyChat {
+                        text: String::from(text),
+                    }),
+                },
+                _ => None,
+            };
+
+            let Some(msg) = msg else {
+                print!("{COMMANDS}");
+                return Ok(());
+            };
+            self.send(&msg)
+        }
+
+        fn end_turn(
+            &self,
+            kind: MoveKind,
+            col: &str,
+            row: Option<&str>,
+        ) -> Option<IncomingMessage> {
+            let game = self.game.as_ref()?;
+            let row = match row {
+                Some(row) => Some(parse_column(row)?),
+                None => None,
+            };
+            Some(IncomingMessage::GameEndTurn(IncomingEndTurn {
+                turn: game.state().turn,
+                col: Some(parse_column(col)?),
+                kind,
+                row,
+                seq: None,
+            }))
+        }
+
+        fn show(&self) {
+            let Some(game) = &self.game else {
+                return;
+            };
+            print!("{}", game.to_string());
+            let state = game.state();
+            if state.result.is_none() && self.role == Some(Role::Player(state.player)) {
+                println!("Your move");
+            }
+        }
+    }
+
+    /// Starts a game with the player from the lobby, who is assigned player 2
+    /// unless `p1` is given.
+    fn pick(args: &str) -> Option<IncomingMessage> {
+        let mut args = args.split_whitespace();
+        let code = args.next()?.parse().ok()?;
+        let role = match args.next() {
+            None | Some("p2") => Player::P2,
+            Some("p1") => Player::P1,
+            Some(_) => return None,
+        };
+        Some(IncomingMessage::LobbyPickPlayer(IncomingPickPlayer {
+            code,
+            role,
+            game: None,
+            config: PartialGameConfig::default(),
+            round: 0,
+            extra_time: None,
+            elapsed: None,
+            winner_stays: false,
+        }))
+    }
+
+    fn kick(args: &str) -> Option<IncomingMessage> {
+        let mut args = args.split_whitespace();
+        let code = args.next()?.parse().ok()?;
+        let block = match args.next() {
+            None => false,
+            Some("block") => true,
+            Some(_) => return None,
+        };
+        Some(IncomingMessage::LobbyKick { code, block })
+    }
+
+    fn parse_column(col: &str) -> Option<usize> {
+        let col: usize = col.parse().ok()?;
+        // Columns of the widest field, the server checks the actual width
+        (1..=field_size(MAX_PLAYERS).0)
+            .contains(&col)
+            .then(|| col - 1)
+    }
+
+    /// Draws the QR code with two rows of modules per line, light modules
+    /// as blocks, so that it can be scanned from a dark terminal.
+    fn render_qr(qr: &QR) -> Option<String> {
+        if qr.format != QrFormat::Png {
+            return None;
+        }
+        let png = general_purpose::STANDARD.decode(&qr.img).ok()?;
+        let img = image::load_from_memory(&png).ok()?.to_luma8();
+        let modules = u32::try_from(qr.width).ok()?;
+        let scale = img.width() / modules.max(1);
+        if scale == 0 {
+            return None;
+        }
+
+        let size = modules + 2 * QR_QUIET_ZONE;
+        let light = |x: u32, y: u32| {
+            let (Some(x), Some(y)) = (x.checked_sub(QR_QUIET_ZONE), y.checked_sub(QR_QUIET_ZONE))
+            else {
+                return true;
+            };
+            x >= modules || y >= modules || img.get_pixel(x * scale, y * scale).0[0] > 127
+        };
+
+        let mut res = String::new();
+        for y in (0..size).step_by(2) {
+            for x in 0..size {
+                res.push(match (light(x, y), y + 1 < size && light(x, y + 1)) {
+                    (true, true) => '█',
+                    (true, false) => '▀',
+                    (false, true) => '▄',
+                    (false, false) => ' ',
+                });
+            }
+            res.push('\n');
+        }
+        Some(res)
+    }
+}
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match args.as_slice() {
+        [] => App::new().run(),
+        [cmd, path] if cmd == "replay" => match ReplayViewer::open(path) {
+            Ok(mut viewer) => viewer.run(),
+            Err(e) => {
+                eprintln!("{e}");
+                return ExitCode::FAILURE;
+            }
+        },
+        [cmd, path] if cmd == "import" => match App::import(path) {
+            Ok(mut app) => app.run(),
+            Err(e) => {
+                eprintln!("{e}");
+                return ExitCode::FAILURE;
+            }
+        },
+        [cmd, path] if cmd == "export" => match ReplayViewer::open(path) {
+            // Validated when opened
+            Ok(viewer) => {
+                let game = viewer.replay.game_at(viewer.replay.moves.len()).unwrap();
+                print!("{}", game.to_notation());
+            }
+            Err(e) => {
+                eprintln!("{e}");
+                return ExitCode::FAILURE;
+            }
+        },
+        #[cfg(feature = "server")]
+        [flag, url, rest @ ..] if flag == "--connect" && rest.iter().all(|a| a == "--insecure") => {
+            let insecure = !rest.is_empty();
+            let res = online::OnlineClient::connect(url, insecure).and_then(|mut c| c.run());
+            if let Err(e) = res {
+                eprintln!("{e}");
+                return ExitCode::FAILURE;
+            }
+        }
+        _ => {
+            eprintln!(
+                "USAGE: cli [replay <FILE> | export <FILE> | import <FILE> | --connect <URL> [--insecure]]"
+            );
+            return ExitCode::FAILURE;
+        }
+    }
+    ExitCode::SUCCESS
 }
 
 trait ToString {
@@ -86,29 +763,29 @@ impl ToString for Game {
     fn to_string(&self) -> String {
         let mut res = String::with_capacity(1024);
 
-        res.push_str(&"-".repeat(14));
+        let (width, height) = self.rules().field_size();
+        res.push_str(&"-".repeat(width * 2));
         res.push('\n');
 
         let player = match &self.state().result {
-            Some(res) => match res.winner {
-                GameWinner::P1 => "(Player 1) has won!",
-                GameWinner::P2 => "[Player 2] has won!",
-                GameWinner::Draw => "It's a draw!",
-            },
-            None => match self.state().player {
-                Player::P1 => "(Player 1)'s turn",
-                Player::P2 => "[Player 2]'s turn",
+            Some(res) => match res.winner.player() {
+                Some(winner) => format!("{} has won!", player_name(winner)),
+                None => String::from("It's a draw!"),
             },
+            None => format!("{}'s turn", player_name(self.state().player)),
         };
 
-        res.push_str(player);
+        res.push_str(&player);
         res.push('\n');
 
-        for y in 0..FIELD_SIZE {
-            for x in 0..FIELD_SIZE {
-                match self.field()[x][y] {
+        let field = self.field();
+        for y in 0..height {
+            for column in &field {
+                match column[y] {
                     Some(Player::P1) => res.push_str("()"),
                     Some(Player::P2) => res.push_str("[]"),
+                    Some(Player::P3) => res.push_str("{}"),
+                    Some(Player::P4) => res.push_str("<>"),
                     None => res.push_str("  "),
                 }
             }
@@ -116,7 +793,9 @@ impl ToString for Game {
             res.push('\n');
         }
 
-        res.push_str("1 2 3 4 5 6 7\n");
+        let columns: Vec<_> = (1..=width).map(|x| x.to_string()).collect();
+        res.push_str(&columns.join(" "));
+        res.push('\n');
         res
     }
 }
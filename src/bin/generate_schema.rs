@@ -0,0 +1,52 @@
+#![warn(clippy::all, clippy::pedantic)]
+
+use std::{fs, path::PathBuf, process::ExitCode};
+
+use connect_four_server::schema;
+
+const HELP: &str = "\
+Writes the JSON Schema and TypeScript definitions of every WebSocket message.
+
+USAGE:
+  generate_schema [OPTIONS]
+
+OPTIONS:
+  -o --out-dir <DIR>    Where to write protocol.schema.json and protocol.d.ts [default: .]
+  -h --help             Show this message and exit
+";
+
+const SCHEMA_FILE: &str = "protocol.schema.json";
+const TYPESCRIPT_FILE: &str = "protocol.d.ts";
+
+fn main() -> ExitCode {
+    let mut pargs = pico_args::Arguments::from_env();
+    if pargs.contains(["-h", "--help"]) {
+        print!("{HELP}");
+        return ExitCode::SUCCESS;
+    }
+
+    let out_dir: PathBuf = match pargs.opt_value_from_str(["-o", "--out-dir"]) {
+        Ok(dir) => dir.unwrap_or_else(|| PathBuf::from(".")),
+        Err(e) => {
+            eprintln!("{e}\n\n{HELP}");
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let root = schema::protocol_schema();
+    let json = serde_json::to_string_pretty(&root).expect("schema should serialize");
+    let files = [
+        (SCHEMA_FILE, json + "\n"),
+        (TYPESCRIPT_FILE, schema::typescript(&root)),
+    ];
+    for (name, contents) in files {
+        let path = out_dir.join(name);
+        if let Err(e) = fs::write(&path, contents) {
+            eprintln!("Failed to write {}: {e}", path.display());
+            return ExitCode::FAILURE;
+        }
+        println!("Wrote {}", path.display());
+    }
+
+    ExitCode::SUCCESS
+}
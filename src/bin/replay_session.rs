@@ -0,0 +1,164 @@
+#![warn(clippy::all, clippy::pedantic)]
+
+use std::{
+    path::PathBuf,
+    process::ExitCode,
+    thread,
+    time::{Duration, Instant},
+};
+
+use connect_four_server::server::client::{self, Socket};
+use connect_four_server::server::recording::{read_recording, Direction, RecordingEntry, REDACTED};
+use qstring::QString;
+use tungstenite::Message;
+use url::Url;
+
+const HELP: &str = "\
+Replays a session recording against a running server.
+
+USAGE:
+  replay_session <FILE> [OPTIONS]
+
+OPTIONS:
+  -u --url <URL>         Server URL [default: wss://localhost:8080/ws]
+  -l --lobby <ID>        Lobby to join, replaces the redacted lobby ID
+  -s --speed <FACTOR>    Playback speed multiplier [default: 1]
+  -k --insecure          Do not verify the server certificate
+  -h --help              Show this message and exit
+";
+
+/// How long to wait for server messages before checking the schedule again.
+const POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+struct Args {
+    file: PathBuf,
+    url: Url,
+    lobby: Option<String>,
+    speed: f64,
+    insecure: bool,
+}
+
+fn parse_args() -> Result<Args, pico_args::Error> {
+    let mut pargs = pico_args::Arguments::from_env();
+
+    if pargs.contains(["-h", "--help"]) {
+        print!("{HELP}");
+        std::process::exit(0);
+    }
+
+    let default_url = Url::parse("wss://localhost:8080/ws").unwrap();
+    Ok(Args {
+        url: pargs
+            .opt_value_from_str(["-u", "--url"])?
+            .unwrap_or(default_url),
+        lobby: pargs.opt_value_from_str(["-l", "--lobby"])?,
+        speed: pargs.opt_value_from_str(["-s", "--speed"])?.unwrap_or(1.0),
+        insecure: pargs.contains(["-k", "--insecure"]),
+        file: pargs.free_from_str()?,
+    })
+}
+
+/// Reconnect tokens cannot be restored, so the parameter is always removed.
+const RECONNECT_PARAMETER: &str = "reconnect";
+
+/// Builds the connection URL from the recorded query string. Redacted lobby
+/// IDs are replaced with `lobby` or removed, in which case a new lobby will
+/// be created.
+fn connection_url(base: &Url, query: &str, lobby: Option<&str>) -> Url {
+    let pairs = QString::from(query)
+        .into_pairs()
+        .into_iter()
+        .filter_map(|(k, v)| match (v.as_str(), lobby) {
+            _ if k == RECONNECT_PARAMETER => None,
+            (REDACTED, Some(lobby)) => Some((k, String::from(lobby))),
+            (REDACTED, None) => None,
+            _ => Some((k, v)),
+        })
+        .collect();
+
+    let mut url = base.clone();
+    url.set_query(Some(&QString::new(pairs).to_string()));
+    url
+}
+
+/// Prints every message received from the server until `deadline`.
+fn receive_until(socket: &mut Socket, deadline: Instant) -> Result<(), Box<dyn std::error::Error>> {
+    loop {
+        match socket.read() {
+            Ok(Message::Text(text)) => println!("< {text}"),
+            Ok(Message::Close(frame)) => {
+                let reason = frame.map(|f| f.reason.into_owned()).unwrap_or_default();
+                println!("< (closed: {reason})");
+            }
+            Ok(_) => (),
+            Err(e) if client::is_timeout(&e) => (),
+            Err(e) => return Err(e.into()),
+        }
+
+        if Instant::now() >= deadline {
+            return Ok(());
+        }
+    }
+}
+
+fn replay(args: &Args) -> Result<(), Box<dyn std::error::Error>> {
+    let entries = read_recording(&args.file)?;
+    let Some(RecordingEntry::Header { started, query }) = entries.first() else {
+        return Err("recording has no header".into());
+    };
+
+    let url = connection_url(&args.url, query, args.lobby.as_deref());
+    println!("Replaying session started at {started} against {url}");
+    let mut socket = client::connect(&url, args.insecure, POLL_INTERVAL)?;
+    let start = Instant::now();
+
+    for entry in &entries[1..] {
+        match entry {
+            RecordingEntry::Frame {
+                elapsed_ms,
+                dir,
+                data,
+            } => {
+                let at = Duration::from_millis(*elapsed_ms).div_f64(args.speed);
+                receive_until(&mut socket, start + at)?;
+                match dir {
+                    Direction::Inbound => {
+                        println!("> {data}");
+                        socket.send(Message::Text(data.clone()))?;
+                    }
+                    Direction::Outbound => println!("  (recorded) < {data}"),
+                }
+            }
+            RecordingEntry::Close { elapsed_ms, reason } => {
+                let at = Duration::from_millis(*elapsed_ms).div_f64(args.speed);
+                receive_until(&mut socket, start + at)?;
+                let reason = reason.as_deref().unwrap_or_default();
+                println!("  (recorded) closed: {reason}");
+                break;
+            }
+            RecordingEntry::Header { .. } => (),
+        }
+    }
+
+    socket.close(None)?;
+    thread::sleep(POLL_INTERVAL);
+    Ok(())
+}
+
+fn main() -> ExitCode {
+    let args = match parse_args() {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("{e}\n\n{HELP}");
+            return ExitCode::FAILURE;
+        }
+    };
+
+    match replay(&args) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("{e}");
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -1,34 +1,45 @@
 #![warn(clippy::all, clippy::pedantic)]
-#![allow(clippy::similar_names)] // allow the usage of `req` and `res`
-#![allow(clippy::unused_async)]
 
 use std::{
-    fmt, io,
+    fs,
+    io::{self, IsTerminal},
+    path::Path,
     process::{self, ExitCode},
-    str::FromStr,
+    sync::Arc,
+    time::Duration,
 };
 
-use actix::{Actor, Addr, MailboxError};
-use actix_web::{rt, web};
-use actix_web::{App, HttpRequest, HttpResponse, HttpServer};
-use actix_web_actors::ws::WsResponseBuilder;
-use log::debug;
-use openssl::ssl::{SslAcceptor, SslFiletype, SslMethod};
-use qstring::QString;
-use uuid::Uuid;
-
-use web::Data;
-
-use actor::lobby_router::{CreateLobby, JoinLobby};
-use actor::player::Disconnect;
-use connect_four_server::server::{actor, AppArgs, AppConfig};
-
-/// Specifies the supported protocol version when requesting a connection.
-const URL_VERSION_PARAMETER: &str = "version";
-/// Supported protocol version.
-const PROTOCOL_VERSION: &str = "1";
+use actix_web::{rt, HttpServer};
+use tracing::{error, info};
+use tracing_subscriber::EnvFilter;
+
+use connect_four_server::server::acme::{CertificateManager, Challenges};
+use connect_four_server::server::config::{AppConfigError, AppConfigPartial, LogFormat};
+use connect_four_server::server::error::ServerError;
+use connect_four_server::server::http::{build_app, AppState};
+#[cfg(unix)]
+use connect_four_server::server::reload::LiveConfig;
+use connect_four_server::server::tls::Certificates;
+use connect_four_server::server::{AppArgs, AppConfig};
+
+/// Permissions of the Unix domain socket, so that a reverse proxy running in
+/// the same group can connect to it.
+#[cfg(unix)]
+const UNIX_SOCKET_MODE: u32 = 0o660;
+
+/// Reads the configuration file, then applies the environment variables and
+/// the command line options on top of it.
+fn load_config(args: &AppArgs) -> Result<AppConfig, AppConfigError> {
+    let mut cfg = match &args.config {
+        Some(path) => AppConfig::from_file(path)?,
+        None => AppConfig::default(),
+    };
+    cfg.apply_partial(AppConfigPartial::from_env()?);
+    cfg.apply_partial(args.partial_config.clone());
+    Ok(cfg)
+}
 
-fn get_config() -> AppConfig {
+fn get_config() -> (AppConfig, AppArgs) {
     let args = match AppArgs::from_env() {
         Ok(args) => args,
         Err(e) => {
@@ -37,19 +48,14 @@ fn get_config() -> AppConfig {
         }
     };
 
-    let mut cfg = match &args.config {
-        Some(path) => match AppConfig::from_file(path) {
-            Ok(cfg) => cfg,
-            Err(e) => {
-                eprintln!("{e}");
-                process::exit(1);
-            }
-        },
-        None => AppConfig::default(),
+    let cfg = match load_config(&args) {
+        Ok(cfg) => cfg,
+        Err(e) => {
+            eprintln!("{e}");
+            process::exit(1);
+        }
     };
 
-    cfg.apply_partial(args.partial_config);
-
     if args.print_config {
         match toml::to_string_pretty(&cfg) {
             Ok(cfg_contents) => {
@@ -63,15 +69,15 @@ fn get_config() -> AppConfig {
         }
     }
 
-    cfg
+    (cfg, args)
 }
 
 fn main() -> ExitCode {
-    let cfg = get_config();
+    let (cfg, args) = get_config();
 
-    env_logger::init();
+    init_logging(cfg.log_format);
 
-    match rt::System::new().block_on(main_actix(cfg)) {
+    match rt::System::new().block_on(main_actix(cfg, args)) {
         Ok(()) => ExitCode::SUCCESS,
         Err(e) => {
             eprintln!("{e}");
@@ -80,97 +86,159 @@ fn main() -> ExitCode {
     }
 }
 
-async fn main_actix(cfg: AppConfig) -> Result<(), ServerError> {
-    let cfg = Data::new(cfg);
-
-    let mut builder = SslAcceptor::mozilla_intermediate(SslMethod::tls()).unwrap();
-    builder
-        .set_private_key_file(&cfg.private_key_file, SslFiletype::PEM)
-        .map_err(ServerError::OpenSsl)?;
-    builder
-        .set_certificate_chain_file(&cfg.certificate_chain_file)
-        .map_err(ServerError::OpenSsl)?;
-
-    let lobby_router = actor::LobbyRouter::new(Data::clone(&cfg).into_inner()).start();
-    let cfg_1 = Data::clone(&cfg);
-    HttpServer::new(move || {
-        App::new()
-            .app_data(Data::new(lobby_router.clone()))
-            .app_data(Data::clone(&cfg_1))
-            .route("/", web::get().to(ws_route))
-            .default_service(web::get().to(not_found))
-    })
-    .bind_openssl((cfg.address, cfg.socket), builder)
-    .map_err(ServerError::IO)?
-    .run()
-    .await
-    .map_err(ServerError::IO)
+/// Writes log events to stderr, filtered by the `RUST_LOG` environment
+/// variable.
+fn init_logging(format: LogFormat) {
+    let subscriber = tracing_subscriber::fmt()
+        .with_env_filter(EnvFilter::from_default_env())
+        .with_ansi(io::stderr().is_terminal())
+        .with_writer(io::stderr);
+    match format {
+        LogFormat::Text => subscriber.init(),
+        LogFormat::Json => subscriber.json().init(),
+    }
 }
 
-async fn not_found() -> HttpResponse {
-    HttpResponse::NotFound().body("404 Not Found")
-}
+async fn main_actix(cfg: AppConfig, args: AppArgs) -> Result<(), ServerError> {
+    let state = AppState::new(cfg)?.with_config_loader(Box::new(move || load_config(&args)));
+    #[cfg(unix)]
+    reload_config_on_hangup(&state.live_config);
+    let cfg = &state.cfg;
+    let app_state = state.clone();
+    let server = HttpServer::new(move || build_app(&app_state));
+
+    let certificates = start_tls(cfg, &state.challenges)?;
+    let mut server = server;
+    for (addr, tls) in cfg.listeners() {
+        server = match &certificates {
+            Some(certificates) if tls => {
+                let acceptor = certificates.acceptor()?;
+                server.bind_openssl(addr, acceptor)
+            }
+            _ => server.bind(addr),
+        }?;
+    }
+    #[cfg(unix)]
+    if let Some(path) = &cfg.listen_unix_socket {
+        use std::os::unix::fs::PermissionsExt;
 
-async fn ws_route(
-    req: HttpRequest,
-    stream: web::Payload,
-    cfg: Data<AppConfig>,
-    router: Data<Addr<actor::LobbyRouter>>,
-) -> Result<HttpResponse, actix_web::Error> {
-    let qs = QString::from(req.query_string());
-    let Some(PROTOCOL_VERSION) = qs.get(URL_VERSION_PARAMETER) else {
-        return Ok(HttpResponse::BadRequest().finish());
-    };
+        remove_stale_socket(path)?;
+        server = server.bind_uds(path)?;
+        fs::set_permissions(path, fs::Permissions::from_mode(UNIX_SOCKET_MODE))?;
+    }
+    #[cfg(not(unix))]
+    if cfg.listen_unix_socket.is_some() {
+        return Err(ServerError::IO(io::ErrorKind::Unsupported.into()));
+    }
 
-    let actor_cfg = Data::clone(&cfg).into_inner();
-    let actor = actor::Player::new(actor_cfg);
-    let (addr, res) = WsResponseBuilder::new(actor, &req, stream).start_with_addr()?;
-
-    let id_str = qs.get(&cfg.url_lobby_parameter);
-    if let Some(Ok(id)) = id_str.map(Uuid::from_str) {
-        let msg = JoinLobby {
-            id,
-            player: addr.clone(),
-        };
-
-        match router.send(msg).await {
-            Ok(()) => (),
-            Err(MailboxError::Closed) => addr.do_send(Disconnect::ShuttingDown),
-            Err(MailboxError::Timeout) => {
-                debug!("Encountered an error while trying to route player to lobby {}, connection will be terminated", id);
-                addr.do_send(Disconnect::ServerOverloaded);
-            }
+    let server = server.run();
+    let _ = state.server_handle.set(server.handle());
+    let res = server.await.map_err(ServerError::IO);
+    if let Some(path) = &cfg.listen_unix_socket {
+        if let Err(e) = fs::remove_file(path) {
+            error!("Failed to remove the Unix socket: {e}");
         }
-    } else if id_str.is_some() {
-        addr.do_send(Disconnect::InviteInvalid);
+    }
+    res
+}
+
+/// Loads the certificates if any listener serves HTTPS, and keeps them
+/// renewed if ACME is enabled.
+fn start_tls(
+    cfg: &AppConfig,
+    challenges: &Arc<Challenges>,
+) -> Result<Option<Arc<Certificates>>, ServerError> {
+    let manager = (!cfg.acme.domains.is_empty())
+        .then(|| CertificateManager::new(&cfg.acme, &cfg.tls, Arc::clone(challenges)));
+    if let Some(manager) = &manager {
+        manager.write_placeholder()?;
+    }
+
+    let certificates = if cfg.listeners().iter().any(|&(_, tls)| tls) {
+        let certificates = Certificates::load(&cfg.tls)?;
+        let certificates = Arc::new(certificates);
+        reload_certificates(&certificates, cfg.tls.reload_interval);
+        Some(certificates)
     } else {
-        let msg = CreateLobby { host: addr.clone() };
-        match router.send(msg).await {
-            Ok(()) => (),
-            Err(MailboxError::Closed) => addr.do_send(Disconnect::ShuttingDown),
-            Err(MailboxError::Timeout) => {
-                debug!("Encountered an error while trying to route player to a new lobby, connection will be terminated");
-                addr.do_send(Disconnect::ServerOverloaded);
+        None
+    };
+
+    if let Some(manager) = manager {
+        manager.with_certificates(certificates.clone()).spawn();
+    }
+    Ok(certificates)
+}
+
+/// Reloads the certificates on SIGHUP and whenever the files change, checking
+/// them every `interval`.
+fn reload_certificates(certificates: &Arc<Certificates>, interval: Duration) {
+    #[cfg(unix)]
+    {
+        use rt::signal::unix::{signal, SignalKind};
+
+        let certificates = Arc::clone(certificates);
+        match signal(SignalKind::hangup()) {
+            Ok(mut hangup) => {
+                rt::spawn(async move {
+                    while hangup.recv().await.is_some() {
+                        match certificates.reload() {
+                            Ok(()) => info!("Reloaded the TLS certificates"),
+                            Err(e) => error!("Failed to reload the TLS certificates: {e}"),
+                        }
+                    }
+                });
             }
+            Err(e) => error!("Failed to listen for SIGHUP: {e}"),
         }
     }
 
-    Ok(res)
-}
-
-#[derive(Debug)]
-enum ServerError {
-    IO(io::Error),
-    OpenSsl(openssl::error::ErrorStack),
+    if interval.is_zero() {
+        return;
+    }
+    let certificates = Arc::clone(certificates);
+    rt::spawn(async move {
+        let mut interval = rt::time::interval(interval);
+        interval.tick().await;
+        loop {
+            interval.tick().await;
+            match certificates.reload_if_modified() {
+                Ok(true) => info!("Reloaded the modified TLS certificates"),
+                Ok(false) => {}
+                Err(e) => error!("Failed to reload the TLS certificates: {e}"),
+            }
+        }
+    });
 }
 
-impl fmt::Display for ServerError {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match self {
-            Self::IO(e) => write!(f, "io error: {e}"),
-            Self::OpenSsl(e) => write!(f, "{e}"),
+/// Reloads the settings which can change while the server runs on SIGHUP.
+#[cfg(unix)]
+fn reload_config_on_hangup(live_config: &Arc<LiveConfig>) {
+    use rt::signal::unix::{signal, SignalKind};
+
+    let live_config = Arc::clone(live_config);
+    match signal(SignalKind::hangup()) {
+        Ok(mut hangup) => {
+            rt::spawn(async move {
+                while hangup.recv().await.is_some() {
+                    match live_config.reload() {
+                        Ok(_) => info!("Reloaded the settings"),
+                        Err(e) => error!("Failed to reload the settings: {e}"),
+                    }
+                }
+            });
         }
+        Err(e) => error!("Failed to listen for SIGHUP: {e}"),
     }
 }
 
-impl std::error::Error for ServerError {}
+/// Removes the Unix domain socket left behind by a server which did not shut
+/// down cleanly, so that it can be bound again.
+#[cfg(unix)]
+fn remove_stale_socket(path: &Path) -> Result<(), ServerError> {
+    use std::os::unix::fs::FileTypeExt;
+
+    if fs::symlink_metadata(path).is_ok_and(|meta| meta.file_type().is_socket()) {
+        fs::remove_file(path)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,116 @@
+use std::{fmt, str::FromStr};
+
+use rand::{seq::SliceRandom, Rng};
+use serde::{Deserialize, Serialize};
+
+use crate::game::{analysis, Game};
+
+/// How well the computer opponent plays.
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub enum Difficulty {
+    Easy,
+    Medium,
+    Hard,
+}
+
+impl Difficulty {
+    /// How many turns ahead the bot looks.
+    #[must_use]
+    const fn depth(self) -> u32 {
+        match self {
+            Self::Easy => 2,
+            Self::Medium => 4,
+            Self::Hard => 6,
+        }
+    }
+
+    /// Probability of playing a random move instead of the best one.
+    #[must_use]
+    const fn blunder_chance(self) -> f64 {
+        match self {
+            Self::Easy => 0.3,
+            Self::Medium => 0.1,
+            Self::Hard => 0.0,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct ParseDifficultyError;
+
+impl fmt::Display for ParseDifficultyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "difficulty must be one of: easy, medium, hard")
+    }
+}
+
+impl std::error::Error for ParseDifficultyError {}
+
+impl FromStr for Difficulty {
+    type Err = ParseDifficultyError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "easy" => Ok(Self::Easy),
+            "medium" => Ok(Self::Medium),
+            "hard" => Ok(Self::Hard),
+            _ => Err(ParseDifficultyError),
+        }
+    }
+}
+
+/// Returns the column the current player should drop their chip into, or
+/// `None` if there are no legal moves left.
+#[must_use]
+pub fn best_move<R: Rng>(game: &Game, difficulty: Difficulty, rng: &mut R) -> Option<usize> {
+    let moves = analysis::legal_moves(game);
+    if moves.is_empty() {
+        return None;
+    }
+
+    if rng.gen_bool(difficulty.blunder_chance()) {
+        return moves.choose(rng).copied();
+    }
+
+    let (best_moves, _) = analysis::best_moves(game, difficulty.depth());
+    best_moves.choose(rng).copied()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn play(moves: &[usize]) -> Game {
+        let mut game = Game::default();
+        for col in moves {
+            game.end_turn(Some(*col)).unwrap();
+        }
+        game
+    }
+
+    #[test]
+    fn bot_takes_win() {
+        let game = play(&[0, 6, 1, 6, 2, 6]);
+        let mut rng = rand::thread_rng();
+        assert_eq!(best_move(&game, Difficulty::Hard, &mut rng), Some(3));
+    }
+
+    #[test]
+    fn bot_blocks_loss() {
+        let game = play(&[0, 6, 1, 6, 2]);
+        let mut rng = rand::thread_rng();
+        assert_eq!(best_move(&game, Difficulty::Hard, &mut rng), Some(3));
+    }
+
+    #[test]
+    fn same_seed_picks_same_moves() {
+        use rand::{rngs::StdRng, SeedableRng};
+
+        let game = play(&[3, 3]);
+        for seed in 0..20 {
+            let pick = |seed| best_move(&game, Difficulty::Easy, &mut StdRng::seed_from_u64(seed));
+            assert_eq!(pick(seed), pick(seed), "seed {seed}");
+        }
+    }
+}
@@ -1,3 +1,5 @@
+//! Serde helpers for `Duration` fields.
+
 use std::time::Duration;
 
 use serde::de::{Deserialize, Deserializer};
@@ -24,6 +26,25 @@ pub mod as_secs {
     }
 }
 
+pub mod as_secs_list {
+    use super::*;
+
+    pub fn serialize<S>(value: &[Duration], serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.collect_seq(value.iter().map(Duration::as_secs_f64))
+    }
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<Vec<Duration>, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let secs = Vec::<f64>::deserialize(deserializer)?;
+        Ok(secs.into_iter().map(Duration::from_secs_f64).collect())
+    }
+}
+
 pub mod as_secs_optional {
     use super::*;
 
@@ -99,3 +120,28 @@ pub mod as_millis_optional_tuple {
         Ok(Some(v.map(|v| Duration::from_secs_f64(v / MILLIS))))
     }
 }
+
+pub mod as_millis_optional_list {
+    use super::*;
+
+    pub fn serialize<S>(value: &Option<Vec<Duration>>, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let value = value.as_deref().unwrap_or_default();
+        serializer.collect_seq(value.iter().map(|d| d.as_secs_f64() * MILLIS))
+    }
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<Option<Vec<Duration>>, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let millis = Vec::<f64>::deserialize(deserializer)?;
+        Ok(Some(
+            millis
+                .into_iter()
+                .map(|v| Duration::from_secs_f64(v / MILLIS))
+                .collect(),
+        ))
+    }
+}
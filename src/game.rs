@@ -1,39 +1,87 @@
+use std::time::Duration;
+
 use serde::{Deserialize, Serialize};
 use serde_repr::{Deserialize_repr, Serialize_repr};
 
-use Player::{P1, P2};
+use bitboard::Bitboard;
+use Player::{P1, P2, P3, P4};
+
+pub mod analysis;
+mod bitboard;
+pub mod notation;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
 
+/// Width and height of the field of a two player game.
 pub const FIELD_SIZE: usize = 7;
+/// Default amount of chips in a row needed to win.
 pub const WIN_LEN: usize = 4;
+/// The shortest row allowed by `GameRules::win_len`.
+pub const MIN_WIN_LEN: usize = 3;
+/// The most players a game can have, see `GameRules::players`.
+pub const MAX_PLAYERS: u8 = 4;
 
-type GameField = [[Option<Player>; FIELD_SIZE]; FIELD_SIZE];
+/// Columns of the field from left to right, each from top to bottom.
+type GameField = Vec<Vec<Option<Player>>>;
 type GameMatch = ((usize, usize), (usize, usize));
 
-const LAST_MOVE: u32 = (FIELD_SIZE * FIELD_SIZE) as u32 - 1;
-const EMPTY_FIELD: GameField = [[None; FIELD_SIZE]; FIELD_SIZE];
-
-#[derive(Serialize, Deserialize)]
+#[derive(Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct Game {
-    field: GameField,
+    /// Serialized as a `GameField`.
+    #[cfg_attr(feature = "schema", schemars(with = "GameField"))]
+    field: Bitboard,
     state: GameState,
     rules: GameRules,
+    /// Every turn ended with `Game::play` since the game was created. Not a
+    /// part of the serialized game, as it is sent separately.
+    #[serde(skip)]
+    history: Vec<Move>,
 }
 
-#[derive(Clone, Serialize, Deserialize)]
+#[derive(Clone, Serialize, Deserialize, Debug)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 #[serde(rename_all = "camelCase")]
+#[allow(clippy::struct_excessive_bools)] // independent rule toggles
 pub struct GameRules {
     pub starting_player: Player,
     pub allow_draws: bool,
+    /// Amount of chips in a row needed to win, must fit the field, see
+    /// `is_win_len_valid`.
+    #[serde(default = "default_win_len")]
+    pub win_len: usize,
+    /// Allows players to remove their own chip from the bottom row instead
+    /// of dropping a new one.
+    #[serde(default)]
+    pub pop_out: bool,
+    /// Allows players to place a chip into any empty cell instead of
+    /// dropping it to the bottom of a column. Chips cannot be popped out in
+    /// these games, since the chips above would be left floating.
+    #[serde(default)]
+    pub free_placement: bool,
+    /// Lets rows continue from the right edge of the field onto the left
+    /// one, as if the field was wrapped around a cylinder.
+    #[serde(default)]
+    pub wrap_horizontal: bool,
+    /// Amount of players taking turns, in `2..=MAX_PLAYERS` range. Games
+    /// with more players are played on a larger field, see `field_size`.
+    #[serde(default = "default_players", skip_serializing_if = "is_two_players")]
+    pub players: u8,
 }
 
+/// A player, numbered from 0 in the order they take turns from the start of
+/// a round. Games with fewer than `MAX_PLAYERS` players use the first ones.
 #[derive(Clone, Copy, PartialEq, Eq, Serialize_repr, Deserialize_repr, Debug)]
 #[repr(u8)]
 pub enum Player {
     P1 = 0,
     P2 = 1,
+    P3 = 2,
+    P4 = 3,
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 #[serde(rename_all = "camelCase")]
 pub struct GameState {
     pub player: Player,
@@ -44,12 +92,173 @@ pub struct GameState {
     pub result: Option<GameResult>,
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub last_move: Option<usize>,
+    #[serde(default, skip_serializing_if = "MoveKind::is_drop")]
+    pub last_move_kind: MoveKind,
+    /// Row of the cell the last chip was placed into, only set for
+    /// `MoveKind::Place`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_move_row: Option<usize>,
+    /// Players who left a game of more than two players which went on
+    /// without them, see `Game::leave`. Their turns are skipped.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub left: Vec<Player>,
+}
+
+/// Whether a chip is added to or removed from a column.
+#[derive(Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize, Debug)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "camelCase")]
+pub enum MoveKind {
+    #[default]
+    Drop,
+    /// Removes the bottom chip of the column, only allowed by
+    /// `GameRules::pop_out`.
+    Pop,
+    /// Puts a chip into an empty cell of the column without letting it
+    /// fall, only allowed by `GameRules::free_placement`.
+    Place,
+}
+
+/// A move made by the current player.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Action {
+    /// Drops a chip into the column.
+    Drop(usize),
+    /// Removes the player's own chip from the bottom of the column.
+    Pop(usize),
+    /// Puts a chip into the empty cell at the column and row.
+    Place(usize, usize),
+}
+
+/// A single ended turn.
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Debug)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct Move {
+    pub player: Player,
+    /// Column the chip was dropped into, `None` if the turn was skipped.
+    pub col: Option<usize>,
+    #[serde(default, skip_serializing_if = "MoveKind::is_drop")]
+    pub kind: MoveKind,
+    /// Row the chip was placed into, only set for `MoveKind::Place`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub row: Option<usize>,
+    /// Milliseconds since the Unix epoch, 0 if unknown.
+    pub timestamp: u64,
+}
+
+impl Move {
+    #[must_use]
+    fn new(player: Player, action: Option<Action>, timestamp: u64) -> Self {
+        Self {
+            player,
+            col: action.map(Action::col),
+            kind: action.map(Action::kind).unwrap_or_default(),
+            row: action.and_then(Action::row),
+            timestamp,
+        }
+    }
+}
+
+impl MoveKind {
+    #[must_use]
+    pub const fn is_drop(&self) -> bool {
+        matches!(self, Self::Drop)
+    }
+}
+
+impl Action {
+    /// Returns the action of the kind, or `None` if a placement is missing
+    /// its row. The row is ignored by other kinds.
+    #[must_use]
+    pub const fn new(kind: MoveKind, col: usize, row: Option<usize>) -> Option<Self> {
+        match (kind, row) {
+            (MoveKind::Drop, _) => Some(Self::Drop(col)),
+            (MoveKind::Pop, _) => Some(Self::Pop(col)),
+            (MoveKind::Place, Some(row)) => Some(Self::Place(col, row)),
+            (MoveKind::Place, None) => None,
+        }
+    }
+
+    #[must_use]
+    pub const fn col(self) -> usize {
+        match self {
+            Self::Drop(col) | Self::Pop(col) | Self::Place(col, _) => col,
+        }
+    }
+
+    /// Returns the row of a placement.
+    #[must_use]
+    pub const fn row(self) -> Option<usize> {
+        match self {
+            Self::Place(_, row) => Some(row),
+            Self::Drop(_) | Self::Pop(_) => None,
+        }
+    }
+
+    #[must_use]
+    pub const fn kind(self) -> MoveKind {
+        match self {
+            Self::Drop(_) => MoveKind::Drop,
+            Self::Pop(_) => MoveKind::Pop,
+            Self::Place(..) => MoveKind::Place,
+        }
+    }
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "camelCase")]
 pub struct GameResult {
     pub winner: GameWinner,
+    /// Start and end cell of every completed row. Rows which wrap around
+    /// the field with `GameRules::wrap_horizontal` end in a column past the
+    /// right edge, which is taken modulo the width of the field.
     pub matches: Vec<GameMatch>,
+    /// Whether the loser gave up with `Game::resign`.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub resigned: bool,
+    #[serde(default)]
+    pub reason: ResultReason,
+    /// Milliseconds from the start of the first turn to the end of the
+    /// game, see `Game::record_duration`. `None` if unknown.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub duration: Option<u64>,
+    /// Milliseconds every turn took, oldest first. Empty if unknown.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub turn_times: Vec<u64>,
+}
+
+/// How a game ended.
+#[derive(Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize, Debug)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "camelCase")]
+pub enum ResultReason {
+    /// A player completed a row, or the field filled up.
+    #[default]
+    Connect,
+    /// The loser ran out of time, see `Game::time_out`.
+    Timeout,
+    /// The loser gave up, see `Game::resign`.
+    Resignation,
+    /// The loser left the game, see `Game::forfeit`.
+    Abandonment,
+    /// Every player agreed to a draw, see `Game::agree_draw`.
+    AgreedDraw,
+}
+
+impl GameResult {
+    #[must_use]
+    const fn new(winner: GameWinner, matches: Vec<GameMatch>, reason: ResultReason) -> Self {
+        Self {
+            winner,
+            matches,
+            resigned: matches!(reason, ResultReason::Resignation),
+            reason,
+            duration: None,
+            turn_times: Vec::new(),
+        }
+    }
 }
 
 #[derive(Clone, Copy, PartialEq, Eq, Serialize_repr, Deserialize_repr, Debug)]
@@ -58,6 +267,40 @@ pub enum GameWinner {
     P1 = P1 as u8,
     P2 = P2 as u8,
     Draw = 2,
+    P3 = 3,
+    P4 = 4,
+}
+
+/// Something that happened during a turn, returned by `Game::play` in the
+/// order it happened.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum GameEvent {
+    /// A chip was dropped or placed into the cell.
+    ChipPlaced {
+        player: Player,
+        col: usize,
+        row: usize,
+        kind: MoveKind,
+    },
+    /// The bottom chip of the column was removed, see `GameRules::pop_out`.
+    ChipPopped {
+        player: Player,
+        col: usize,
+    },
+    /// The player skipped their turn.
+    TurnSkipped {
+        player: Player,
+    },
+    /// It is the turn of `player` now. Not sent once the game is over.
+    TurnChanged {
+        player: Player,
+    },
+    /// The game was won with the rows in `matches`, see `GameResult`.
+    GameWon {
+        winner: Player,
+        matches: Vec<GameMatch>,
+    },
+    Drawn,
 }
 
 #[derive(Clone, Copy, PartialEq, Eq, Debug)]
@@ -65,149 +308,147 @@ pub enum EndTurnError {
     IndexOutOfBounds,
     GameOver,
     ColumnFilled,
+    /// Popping chips is not allowed by the rules.
+    PopNotAllowed,
+    /// The bottom chip of the column does not belong to the player.
+    PopNotOwnChip,
+    /// Placing chips is not allowed by the rules.
+    PlaceNotAllowed,
+    /// The cell a chip was placed into is taken.
+    CellTaken,
 }
 
-/// Adds horizontal and vertical matches to the vector.
-fn get_horizontal_and_vertical_matches(matches: &mut Vec<GameMatch>, field: &GameField) {
-    for i in 0..FIELD_SIZE {
-        let mut v_len = 0;
-        let mut v_last_player = None;
-
-        let mut h_len = 0;
-        let mut h_last_player = None;
+/// Reason why `Game::from_moves` rejected a list of moves.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum FromMovesError {
+    /// The move at this index was made by the wrong player.
+    WrongPlayer(usize),
+    /// The move at this index was made earlier than the previous one.
+    TimestampOutOfOrder(usize),
+    /// The move at this index could not be made.
+    InvalidMove(usize, EndTurnError),
+}
 
-        for j in 0..FIELD_SIZE {
-            let v_player = field[i][j];
-            let h_player = field[j][i];
+/// Reason why `Game::validated` rejected a game.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum InvalidStateError {
+    /// A chip is not resting on the bottom row or another chip.
+    FloatingChip,
+    /// `GameState::moves` is not the amount of chips on the field.
+    WrongMoveCount,
+    /// `GameState::player` does not follow from the turn and the starting
+    /// player, or has left the game.
+    WrongPlayer,
+    /// A player has more chips than turns they have played.
+    TooManyChips,
+    /// The result does not follow from the field.
+    WrongResult,
+    /// The rules have an unsupported amount of players, or a starting
+    /// player who is not one of them.
+    InvalidRules,
+    /// The field does not have the size of the amount of players, see
+    /// `field_size`.
+    WrongFieldSize,
+}
 
-            if v_player == v_last_player && v_player.is_some() {
-                v_len += 1;
-            } else {
-                if v_len >= WIN_LEN {
-                    matches.push(((i, j - v_len), (i, j - 1)));
-                }
-                v_last_player = v_player;
-                v_len = v_player.is_some().into();
-            }
+const fn default_win_len() -> usize {
+    WIN_LEN
+}
 
-            if h_player == h_last_player && h_player.is_some() {
-                h_len += 1;
-            } else {
-                if h_len >= WIN_LEN {
-                    matches.push(((j - h_len, i), (j - 1, i)));
-                }
-                h_last_player = h_player;
-                h_len = h_player.is_some().into();
-            }
-        }
+const fn default_players() -> u8 {
+    2
+}
 
-        if v_len >= WIN_LEN {
-            matches.push(((i, FIELD_SIZE - v_len), (i, FIELD_SIZE - 1)));
-        }
+#[allow(clippy::trivially_copy_pass_by_ref)] // called by serde with a reference
+const fn is_two_players(players: &u8) -> bool {
+    *players == 2
+}
 
-        if h_len >= WIN_LEN {
-            matches.push(((FIELD_SIZE - h_len, i), (FIELD_SIZE - 1, i)));
-        }
+/// Returns the width and height of the field of a game with the amount of
+/// players.
+#[must_use]
+pub const fn field_size(players: u8) -> (usize, usize) {
+    match players {
+        0..=2 => (FIELD_SIZE, FIELD_SIZE),
+        3 => (9, 7),
+        _ => (11, 8),
     }
 }
 
-/// Adds diagonal matches to the vector.
-fn get_diagonal_matches(matches: &mut Vec<GameMatch>, field: &GameField) {
-    const D: isize = (FIELD_SIZE - WIN_LEN) as isize;
-    for d in -D..=D {
-        let dx = (-d.min(0)) as usize;
-        let dy = d.max(0) as usize;
-
-        let mut last_p1 = None;
-        let mut len1 = 0;
+/// Returns true if rows of `win_len` chips fit the field of a game with the
+/// amount of players.
+#[must_use]
+pub const fn is_win_len_valid(players: u8, win_len: usize) -> bool {
+    let (width, height) = field_size(players);
+    let max = if width < height { width } else { height };
+    MIN_WIN_LEN <= win_len && win_len <= max
+}
 
-        let mut last_p2 = None;
-        let mut len2 = 0;
+/// Returns the start and end cell of every row of at least `win_len` chips
+/// of the same player. With `wrap`, rows may continue from the right edge
+/// of the field onto the left one. The start of a match is always on the
+/// field, while its end may then be past the right edge.
+fn get_matches(board: &Bitboard, win_len: usize, wrap: bool) -> Vec<GameMatch> {
+    let (width, height) = (board.width() as isize, board.height() as isize);
+    let owner = |x: isize, y: isize| {
+        let x = if wrap { x.rem_euclid(width) } else { x };
+        ((0..width).contains(&x) && (0..height).contains(&y))
+            .then(|| board.get(x as usize, y as usize))
+            .flatten()
+    };
 
-        let b_max = FIELD_SIZE - d.unsigned_abs();
-        for b in 0..b_max {
-            let p1 = field[b + dx][b + dy];
-            let p2 = field[FIELD_SIZE - 1 - b - dx][b + dy];
+    let mut matches = Vec::new();
+    for (dx, dy) in [(0, 1), (1, 0), (1, 1), (1, -1)] {
+        let max_len = if dx == 0 { height } else { width };
+        for x in 0..width {
+            for y in 0..height {
+                let player = owner(x, y);
+                if player.is_none() {
+                    continue;
+                }
 
-            if p1 == last_p1 && p1.is_some() {
-                len1 += 1;
-            } else {
-                if len1 >= WIN_LEN {
-                    let x1 = b + dx - len1;
-                    let y1 = b + dy - len1;
-                    let x2 = b + dx - 1;
-                    let y2 = b + dy - 1;
-                    matches.push(((x1, y1), (x2, y2)));
+                // A row around the whole field has no start, so it is
+                // reported from the left edge
+                let full_row =
+                    wrap && dy == 0 && x == 0 && (1..width).all(|i| owner(i, y) == player);
+                if owner(x - dx, y - dy) == player && !full_row {
+                    continue;
                 }
-                last_p1 = p1;
-                len1 = p1.is_some().into();
-            }
 
-            if p2 == last_p2 && p2.is_some() {
-                len2 += 1;
-            } else {
-                if len2 >= WIN_LEN {
-                    let x1 = FIELD_SIZE + len2 - 1 - b - dx;
-                    let y1 = b + dy - len2;
-                    let x2 = FIELD_SIZE - b - dx;
-                    let y2 = b + dy - 1;
-                    matches.push(((x1, y1), (x2, y2)));
+                let len = (1..max_len)
+                    .find(|i| owner(x + i * dx, y + i * dy) != player)
+                    .unwrap_or(max_len);
+                if len >= win_len as isize {
+                    let end = (x + (len - 1) * dx, y + (len - 1) * dy);
+                    matches.push(((x as usize, y as usize), (end.0 as usize, end.1 as usize)));
                 }
-                last_p2 = p2;
-                len2 = p2.is_some().into();
             }
         }
-
-        if len1 >= WIN_LEN {
-            let x1 = b_max + dx - len1;
-            let y1 = b_max + dy - len1;
-            let x2 = b_max + dx - 1;
-            let y2 = b_max + dy - 1;
-            matches.push(((x1, y1), (x2, y2)));
-        }
-
-        if len2 >= WIN_LEN {
-            let x1 = FIELD_SIZE + len2 - 1 - b_max - dx;
-            let y1 = b_max + dy - len2;
-            let x2 = FIELD_SIZE - b_max - dx;
-            let y2 = b_max + dy - 1;
-            matches.push(((x1, y1), (x2, y2)));
-        }
     }
+    matches
 }
 
+/// Returns the result if a player has a row, a draw if several do, or a
+/// draw if the move being made after `moves` chips fills the field.
 #[must_use]
-fn get_result(field: &GameField, moves: u32) -> Option<GameResult> {
-    let mut matches = Vec::new();
-
-    get_horizontal_and_vertical_matches(&mut matches, field);
-    get_diagonal_matches(&mut matches, field);
-
-    if !matches.is_empty() {
-        let winner = matches
-            .iter()
-            .copied()
-            .fold((false, false), |(p1, p2), ((x, y), _)| match field[x][y] {
-                Some(P1) => (true, p2),
-                Some(P2) => (p1, true),
-                None => (p1, p2),
-            });
-
-        let winner = match winner {
-            (true, true) => GameWinner::Draw,
-            (true, false) => GameWinner::P1,
-            (false, true) => GameWinner::P2,
-            (false, false) => return None,
+fn get_result(board: &Bitboard, moves: u32, rules: &GameRules) -> Option<GameResult> {
+    let matches = get_matches(board, rules.win_len, rules.wrap_horizontal);
+    let mut owners = matches.iter().filter_map(|((x, y), _)| board.get(*x, *y));
+    if let Some(first) = owners.next() {
+        let winner = if owners.all(|p| p == first) {
+            first.into()
+        } else {
+            GameWinner::Draw
         };
-
-        return Some(GameResult { winner, matches });
+        return Some(GameResult::new(winner, matches, ResultReason::Connect));
     }
 
-    if moves >= LAST_MOVE {
-        return Some(GameResult {
-            winner: GameWinner::Draw,
-            matches: Vec::new(),
-        });
+    if moves as usize + 1 >= board.cells() {
+        return Some(GameResult::new(
+            GameWinner::Draw,
+            Vec::new(),
+            ResultReason::Connect,
+        ));
     }
 
     None
@@ -216,211 +457,602 @@ fn get_result(field: &GameField, moves: u32) -> Option<GameResult> {
 impl Game {
     #[must_use]
     pub const fn new(rules: GameRules) -> Self {
+        let (width, height) = rules.field_size();
         Self {
-            field: EMPTY_FIELD,
+            field: Bitboard::new(width, height),
             state: GameState::new(rules.starting_player),
             rules,
+            history: Vec::new(),
         }
     }
 
-    /// Returns true if the most recent move was winning.
-    #[must_use]
-    fn was_last_move_winning(&self) -> bool {
-        let Some(x) = self.state.last_move else {
-            return false;
-        };
-        let other_player = self.state.player.other();
-        self.field[x]
-            .iter()
-            .position(|y| *y == Some(other_player))
-            .map_or(false, |y| self.is_move_winning(x, y, other_player))
+    /// Plays back the moves, keeping their original timestamps.
+    ///
+    /// Errors:
+    ///
+    /// - `WrongPlayer` when a move was not made by the player whose turn it was
+    /// - `TimestampOutOfOrder` when a move is older than the one before it
+    /// - `InvalidMove` when a move could not be made
+    pub fn from_moves(rules: GameRules, moves: &[Move]) -> Result<Self, FromMovesError> {
+        let mut game = Self::new(rules);
+        let mut last_timestamp = 0;
+        for (i, m) in moves.iter().enumerate() {
+            if m.player != game.state.player {
+                return Err(FromMovesError::WrongPlayer(i));
+            }
+            if m.timestamp < last_timestamp {
+                return Err(FromMovesError::TimestampOutOfOrder(i));
+            }
+
+            let action = match m.col {
+                Some(col) => Some(Action::new(m.kind, col, m.row).ok_or(
+                    FromMovesError::InvalidMove(i, EndTurnError::IndexOutOfBounds),
+                )?),
+                None => None,
+            };
+            game.play_at(action, m.timestamp)
+                .map_err(|e| FromMovesError::InvalidMove(i, e))?;
+            last_timestamp = m.timestamp;
+        }
+
+        Ok(game)
     }
 
-    /// Returns a result if the current round has resolved the game.
+    /// Checks that the game, e.g. one sent by a client, could have been
+    /// reached by playing it. The matches of the result are recomputed from
+    /// the field.
     ///
-    /// This function respects `GameRules`. It always runs `is_move_winning()`
-    /// before `get_results()`.
-    #[must_use]
-    fn get_result(&self, point: Option<(usize, usize)>) -> Option<GameResult> {
+    /// Errors:
+    ///
+    /// - `FloatingChip` when a chip has an empty cell below it
+    /// - `WrongMoveCount` when the move counter does not match the field
+    /// - `WrongPlayer` when the wrong player is to move
+    /// - `TooManyChips` when a player has more chips than turns played
+    /// - `WrongResult` when the result does not match the field
+    /// - `InvalidRules` when the amount of players is not supported
+    /// - `WrongFieldSize` when the field does not fit the amount of players
+    pub fn validated(mut self) -> Result<Self, InvalidStateError> {
         let Self {
             field,
             state,
             rules,
+            ..
         } = &self;
-        let player = state.player;
-        let moves = state.moves;
+        let players = Player::all(rules.players);
+        if !(2..=MAX_PLAYERS).contains(&rules.players) || !players.contains(&rules.starting_player)
+        {
+            return Err(InvalidStateError::InvalidRules);
+        }
+        if (field.width(), field.height()) != rules.field_size() {
+            return Err(InvalidStateError::WrongFieldSize);
+        }
+        if !rules.free_placement && !field.is_settled() {
+            return Err(InvalidStateError::FloatingChip);
+        }
 
-        let Some((x, y)) = point else {
-            return if rules.allow_draws && player == rules.starting_player.other() && self.was_last_move_winning() {
-                match get_result(field, moves) {
-                    Some(res) => Some(res),
-                    None => unreachable!(),
-                }
-            } else {
-                None
+        let chips: u32 = Player::ALL.into_iter().map(|p| field.count(p)).sum();
+        if state.moves != chips {
+            return Err(InvalidStateError::WrongMoveCount);
+        }
+
+        let n = u32::from(rules.players);
+        let expected_player = rules.starting_player.after(state.turn % n, rules.players);
+        let left_wrongly = state
+            .left
+            .iter()
+            .any(|p| !players.contains(p) || (state.result.is_none() && *p == state.player));
+        if state.player != expected_player || left_wrongly {
+            return Err(InvalidStateError::WrongPlayer);
+        }
+
+        // Players later in the round have played one turn less until the
+        // round is over
+        let turns_played = |p: Player| {
+            if !players.contains(&p) {
+                return 0;
             }
+            state.turn / n + u32::from((rules.position(p) as u32) < state.turn % n)
         };
+        if Player::ALL
+            .into_iter()
+            .any(|p| field.count(p) > turns_played(p))
+        {
+            return Err(InvalidStateError::TooManyChips);
+        }
 
-        if moves >= LAST_MOVE {
-            return match get_result(field, moves) {
-                Some(res) => Some(res),
-                None => unreachable!(),
+        let claimed = state.result.as_ref().map(|r| r.winner);
+        // Forfeits end the game without a row
+        let forfeit = state
+            .result
+            .as_ref()
+            .is_some_and(|r| r.winner != GameWinner::Draw && r.matches.is_empty());
+        let agreed = state
+            .result
+            .as_ref()
+            .is_some_and(|r| r.reason == ResultReason::AgreedDraw);
+        let Some(expected) = get_result(field, 0, rules) else {
+            let full = state.moves as usize == field.cells();
+            let valid = match claimed {
+                None => !full,
+                Some(GameWinner::Draw) => full || agreed,
+                Some(_) => forfeit,
+            };
+            return if valid {
+                Ok(self)
+            } else {
+                Err(InvalidStateError::WrongResult)
             };
+        };
+
+        // When draws are allowed, the round is played to its end after a
+        // player completes a row, so every row has to be completed by a
+        // player who already moved in this round
+        let has_row = |p: Player| field.has_row(p, rules.win_len, rules.wrap_horizontal);
+        let position = rules.position(state.player);
+        let pending = rules.allow_draws
+            && position > 0
+            && players
+                .iter()
+                .all(|p| !has_row(*p) || rules.position(*p) < position);
+        // When a pop completes rows for several players, the player who
+        // popped wins if they are one of them, unless draws are allowed
+        let pop_win = |winner: GameWinner| {
+            rules.pop_out
+                && !rules.allow_draws
+                && expected.winner == GameWinner::Draw
+                && winner.player().is_some_and(has_row)
+        };
+        let valid = match claimed {
+            None => pending,
+            Some(_) if forfeit && pending => true,
+            Some(GameWinner::Draw) => expected.winner == GameWinner::Draw,
+            Some(winner) => winner == expected.winner || pop_win(winner),
+        };
+        if !valid {
+            return Err(InvalidStateError::WrongResult);
         }
 
-        if rules.allow_draws {
-            if player == rules.starting_player {
-                return None;
+        if let Some(result) = &mut self.state.result {
+            if !(forfeit && pending) {
+                result.matches = expected.matches;
             }
+        }
+        Ok(self)
+    }
 
-            if self.was_last_move_winning() {
-                return match get_result(field, moves) {
-                    Some(res) => Some(res),
-                    None => unreachable!(),
-                };
-            }
+    /// Returns a result if the current turn has resolved the game, `point`
+    /// being the cell a chip was put into.
+    ///
+    /// A player wins as soon as they complete a row. With
+    /// `GameRules::allow_draws`, the game is only resolved at the end of the
+    /// round instead, so that the players after them can still complete a
+    /// row for a draw.
+    #[must_use]
+    fn get_result(&self, point: Option<(usize, usize)>) -> Option<GameResult> {
+        let Self {
+            field,
+            state,
+            rules,
+            ..
+        } = &self;
+        let moves = state.moves;
+
+        if point.is_some() && moves as usize + 1 >= field.cells() {
+            return get_result(field, moves, rules);
         }
 
-        if self.is_move_winning(x, y, player) {
-            return match get_result(field, moves) {
-                Some(res) => Some(res),
-                None => unreachable!(),
+        if rules.allow_draws {
+            let has_row = Player::all(rules.players)
+                .iter()
+                .any(|p| field.has_row(*p, rules.win_len, rules.wrap_horizontal));
+            return if has_row && self.is_round_ending() {
+                get_result(field, moves, rules)
+            } else {
+                None
             };
         }
 
+        let (x, y) = point?;
+        if self.is_move_winning(x, y, state.player) {
+            return get_result(field, moves, rules);
+        }
+
         None
     }
 
-    /// Ends the current turn.
+    /// Returns true if every player after the current one in the round has
+    /// left, so that the round ends with the current turn.
+    #[must_use]
+    fn is_round_ending(&self) -> bool {
+        let position = self.rules.position(self.state.player);
+        Player::all(self.rules.players)
+            .iter()
+            .all(|p| self.rules.position(*p) <= position || self.state.left.contains(p))
+    }
+
+    /// Returns a result after the current player has popped a chip.
+    ///
+    /// A pop can complete rows for several players at once, so the whole
+    /// field is checked right away. If several players have a row, the game
+    /// is a draw when draws are allowed. Otherwise it is won by the player
+    /// who popped if they are one of them.
+    #[must_use]
+    fn get_pop_result(&self) -> Option<GameResult> {
+        let GameRules {
+            win_len,
+            wrap_horizontal: wrap,
+            ..
+        } = self.rules;
+        let has_row = |p: Player| self.field.has_row(p, win_len, wrap);
+        if !Player::all(self.rules.players).iter().any(|p| has_row(*p)) {
+            return None;
+        }
+
+        let mut result = get_result(&self.field, 0, &self.rules)?;
+        if result.winner == GameWinner::Draw
+            && !self.rules.allow_draws
+            && has_row(self.state.player)
+        {
+            result.winner = self.state.player.into();
+        }
+        Some(result)
+    }
+
+    /// Ends the current turn by dropping a chip into `col`, or skips it if
+    /// `col` is `None`. Returns what happened during the turn.
+    ///
+    /// Errors:
+    ///
+    /// - `GameOver` when the game is resolved
+    /// - `IndexOutOfBounds` if `col` is outside of the field
+    /// - `ColumnFilled` when there no space left in the column
+    pub fn end_turn(&mut self, col: Option<usize>) -> Result<Vec<GameEvent>, EndTurnError> {
+        self.play(col.map(Action::Drop))
+    }
+
+    /// Ends the current turn with the given action, or skips it if `action`
+    /// is `None`. Returns what happened during the turn: the move and the
+    /// skipped turns of players who left, followed by the result or the next
+    /// player. The moves are added to `Game::moves` without a timestamp.
+    ///
+    /// Errors are the same as the ones of `Game::apply`.
+    pub fn play(&mut self, action: Option<Action>) -> Result<Vec<GameEvent>, EndTurnError> {
+        self.play_at(action, 0)
+    }
+
+    /// Ends the current turn like `Game::play`, recording the move with the
+    /// time it was made in milliseconds since the Unix epoch.
+    ///
+    /// Errors are the same as the ones of `Game::apply`.
+    pub fn play_at(
+        &mut self,
+        action: Option<Action>,
+        timestamp: u64,
+    ) -> Result<Vec<GameEvent>, EndTurnError> {
+        let player = self.state.player;
+        let cell = self.make_move(action)?;
+        self.history.push(Move::new(player, action, timestamp));
+        let mut events = vec![match (action, cell) {
+            (Some(action), Some((col, row))) => GameEvent::ChipPlaced {
+                player,
+                col,
+                row,
+                kind: action.kind(),
+            },
+            (Some(action), None) => GameEvent::ChipPopped {
+                player,
+                col: action.col(),
+            },
+            (None, _) => GameEvent::TurnSkipped { player },
+        }];
+        while let Some(player) = self.skip_departed() {
+            self.history.push(Move::new(player, None, timestamp));
+            events.push(GameEvent::TurnSkipped { player });
+        }
+        events.push(self.outcome_event());
+        Ok(events)
+    }
+
+    /// Returns the event announcing the result, or the next player while
+    /// the game goes on.
+    fn outcome_event(&self) -> GameEvent {
+        match &self.state.result {
+            Some(result) => match result.winner.player() {
+                Some(winner) => GameEvent::GameWon {
+                    winner,
+                    matches: result.matches.clone(),
+                },
+                None => GameEvent::Drawn,
+            },
+            None => GameEvent::TurnChanged {
+                player: self.state.player,
+            },
+        }
+    }
+
+    /// Ends the current turn like `Game::play` without collecting its
+    /// events or recording the move, e.g. for searches which only need the
+    /// new state.
     ///
     /// Errors:
     ///
     /// - `GameOver` when the game is resolved
-    /// - `IndexOutOfBounds` if `col` is outside of `0..FIELD_SIZE` range
+    /// - `IndexOutOfBounds` if the column or row is outside of the field
     /// - `ColumnFilled` when there no space left in the column
-    pub fn end_turn(&mut self, col: Option<usize>) -> Result<(), EndTurnError> {
+    /// - `PopNotAllowed` when popping while `GameRules::pop_out` is disabled
+    ///   or `GameRules::free_placement` is enabled
+    /// - `PopNotOwnChip` when the bottom chip of the column is not the
+    ///   player's
+    /// - `PlaceNotAllowed` when placing while `GameRules::free_placement`
+    ///   is disabled
+    /// - `CellTaken` when placing into a cell which is not empty
+    pub fn apply(&mut self, action: Option<Action>) -> Result<(), EndTurnError> {
+        self.make_move(action)?;
+        while self.skip_departed().is_some() {}
+        Ok(())
+    }
+
+    /// Skips the turn of the current player if they left the game. Returns
+    /// the player whose turn was skipped.
+    fn skip_departed(&mut self) -> Option<Player> {
+        let player = self.state.player;
+        if self.state.result.is_some() || !self.state.left.contains(&player) {
+            return None;
+        }
+        self.make_move(None).ok()?;
+        Some(player)
+    }
+
+    /// Returns the cell the chip was put into, `None` if the turn was
+    /// skipped or a chip was popped.
+    fn make_move(
+        &mut self,
+        action: Option<Action>,
+    ) -> Result<Option<(usize, usize)>, EndTurnError> {
         if self.state.result.is_some() {
             return Err(EndTurnError::GameOver);
         }
 
-        let Some(col) = col else {
+        let players = self.rules.player_count();
+        let Some(action) = action else {
             self.state.result = self.get_result(None);
-            self.state.next_turn(None);
-            return Ok(());
+            self.state.next_turn(None, players);
+            return Ok(None);
         };
 
-        if col >= self.field.len() {
+        if action.col() >= self.field.width()
+            || action.row().is_some_and(|row| row >= self.field.height())
+        {
             return Err(EndTurnError::IndexOutOfBounds);
         }
 
-        for i in (0..FIELD_SIZE).rev() {
-            if self.field[col][i].is_some() {
+        match action {
+            Action::Drop(col) => self.drop_chip(col).map(Some),
+            Action::Pop(col) => self.pop_chip(col).map(|()| None),
+            Action::Place(col, row) => self.place_chip(col, row).map(Some),
+        }
+    }
+
+    fn drop_chip(&mut self, col: usize) -> Result<(usize, usize), EndTurnError> {
+        for i in (0..self.field.height()).rev() {
+            if self.field.get(col, i).is_some() {
                 continue;
             }
 
-            self.field[col][i] = Some(self.state.player);
+            self.field.set(col, i, Some(self.state.player));
             self.state.result = self.get_result(Some((col, i)));
-            self.state.next_turn(Some(col));
-            return Ok(());
+            self.state
+                .next_turn(Some(Action::Drop(col)), self.rules.player_count());
+            return Ok((col, i));
         }
 
         Err(EndTurnError::ColumnFilled)
     }
 
-    #[must_use]
-    fn len_horizontal(&self, x: usize, y: usize, player: Player) -> usize {
-        let mut len = 1;
-
-        for x in (0..x).rev() {
-            match self.field[x][y] {
-                Some(p) if player == p => len += 1,
-                _ => break,
-            }
+    /// Puts a chip into the cell, where it stays regardless of the cells
+    /// below it.
+    fn place_chip(&mut self, col: usize, row: usize) -> Result<(usize, usize), EndTurnError> {
+        if !self.rules.free_placement {
+            return Err(EndTurnError::PlaceNotAllowed);
         }
 
-        for x in (x + 1)..FIELD_SIZE {
-            match self.field[x][y] {
-                Some(p) if player == p => len += 1,
-                _ => break,
-            }
+        if self.field.get(col, row).is_some() {
+            return Err(EndTurnError::CellTaken);
         }
 
-        len
+        let action = Action::Place(col, row);
+        self.field.set(col, row, Some(self.state.player));
+        self.state.result = self.get_result(Some((col, row)));
+        self.state
+            .next_turn(Some(action), self.rules.player_count());
+        Ok((col, row))
     }
 
-    #[must_use]
-    fn len_vertical(&self, x: usize, y: usize, player: Player) -> usize {
-        let mut len = 1;
-
-        for y in (0..y).rev() {
-            match self.field[x][y] {
-                Some(p) if player == p => len += 1,
-                _ => break,
-            }
+    /// Removes the bottom chip of the column and lets the chips above it fall.
+    fn pop_chip(&mut self, col: usize) -> Result<(), EndTurnError> {
+        if !self.rules.pop_out || self.rules.free_placement {
+            return Err(EndTurnError::PopNotAllowed);
         }
 
-        for y in (y + 1)..FIELD_SIZE {
-            match self.field[x][y] {
-                Some(p) if player == p => len += 1,
-                _ => break,
-            }
+        if self.field.get(col, self.field.height() - 1) != Some(self.state.player) {
+            return Err(EndTurnError::PopNotOwnChip);
         }
 
-        len
+        self.field.pop(col);
+        self.state.result = self.get_pop_result();
+        self.state
+            .next_turn(Some(Action::Pop(col)), self.rules.player_count());
+        Ok(())
     }
 
-    #[must_use]
-    fn len_diagonal_tl_br(&self, x: usize, y: usize, player: Player) -> usize {
-        let mut len = 1;
-        for d in 1..=(x.min(y)) {
-            match self.field[x - d][y - d] {
-                Some(p) if player == p => len += 1,
-                _ => break,
-            }
+    /// Takes the player out of the game, who left it, ran out of time or
+    /// gave up as given by `reason`. Once a single player is left, they win.
+    /// Until then, the game goes on without the player and their turns are
+    /// skipped, recorded at `timestamp`. Returns what happened, like
+    /// `Game::play`, nothing if the player has already left.
+    ///
+    /// Errors:
+    ///
+    /// - `GameOver` when the game is resolved
+    /// - `IndexOutOfBounds` if `player` does not take part in the game
+    pub fn leave(
+        &mut self,
+        player: Player,
+        reason: ResultReason,
+        timestamp: u64,
+    ) -> Result<Vec<GameEvent>, EndTurnError> {
+        if self.state.result.is_some() {
+            return Err(EndTurnError::GameOver);
+        }
+        let players = Player::all(self.rules.players);
+        if !players.contains(&player) {
+            return Err(EndTurnError::IndexOutOfBounds);
+        }
+        if self.state.left.contains(&player) {
+            return Ok(Vec::new());
         }
 
-        for d in 1..(FIELD_SIZE - x.max(y)) {
-            match self.field[x + d][y + d] {
-                Some(p) if player == p => len += 1,
-                _ => break,
-            }
+        let remaining: Vec<_> = players
+            .iter()
+            .filter(|p| **p != player && !self.state.left.contains(p))
+            .collect();
+        if let [winner] = remaining[..] {
+            self.end_early((*winner).into(), reason)?;
+            return Ok(vec![self.outcome_event()]);
         }
 
-        len
+        self.state.left.push(player);
+        let mut events = Vec::new();
+        while let Some(player) = self.skip_departed() {
+            self.history.push(Move::new(player, None, timestamp));
+            events.push(GameEvent::TurnSkipped { player });
+        }
+        events.push(self.outcome_event());
+        Ok(events)
     }
 
-    #[must_use]
-    fn len_diagonal_tr_bl(&self, x: usize, y: usize, player: Player) -> usize {
-        let mut len = 1;
+    /// Takes `player`, who left the game, out of it like `Game::leave`. In
+    /// a two player game, this ends it in favor of the opponent.
+    ///
+    /// Errors:
+    ///
+    /// - `GameOver` when the game is resolved
+    /// - `IndexOutOfBounds` if `player` does not take part in the game
+    pub fn forfeit(&mut self, player: Player) -> Result<(), EndTurnError> {
+        self.leave(player, ResultReason::Abandonment, 0).map(|_| ())
+    }
 
-        for d in 1..=(y.min(FIELD_SIZE - 1 - x)) {
-            match self.field[x + d][y - d] {
-                Some(p) if player == p => len += 1,
-                _ => break,
-            }
-        }
+    /// Takes `player`, who ran out of time, out of the game like
+    /// `Game::leave`. In a two player game, this ends it in favor of the
+    /// opponent.
+    ///
+    /// Errors:
+    ///
+    /// - `GameOver` when the game is resolved
+    /// - `IndexOutOfBounds` if `player` does not take part in the game
+    pub fn time_out(&mut self, player: Player) -> Result<(), EndTurnError> {
+        self.leave(player, ResultReason::Timeout, 0).map(|_| ())
+    }
 
-        for d in 1..=(x.min(FIELD_SIZE - 1 - y)) {
-            match self.field[x - d][y + d] {
-                Some(p) if player == p => len += 1,
-                _ => break,
-            }
+    /// Takes `player`, who concedes, out of the game like `Game::leave`. In
+    /// a two player game, this ends it in favor of the opponent.
+    ///
+    /// Errors:
+    ///
+    /// - `GameOver` when the game is resolved
+    /// - `IndexOutOfBounds` if `player` does not take part in the game
+    pub fn resign(&mut self, player: Player) -> Result<(), EndTurnError> {
+        self.leave(player, ResultReason::Resignation, 0).map(|_| ())
+    }
+
+    /// Ends the game in a draw every player agreed to.
+    ///
+    /// Errors:
+    ///
+    /// - `GameOver` when the game is resolved
+    pub fn agree_draw(&mut self) -> Result<(), EndTurnError> {
+        self.end_early(GameWinner::Draw, ResultReason::AgreedDraw)
+    }
+
+    fn end_early(&mut self, winner: GameWinner, reason: ResultReason) -> Result<(), EndTurnError> {
+        if self.state.result.is_some() {
+            return Err(EndTurnError::GameOver);
         }
 
-        len
+        self.state.result = Some(GameResult::new(winner, Vec::new(), reason));
+        Ok(())
+    }
+
+    /// Records in the result how long the game took, ending at `ended_at`
+    /// milliseconds since the Unix epoch, and how long each turn took
+    /// according to the timestamps of the moves. Turn times are left out if
+    /// a move has no timestamp. Does nothing while the game is in progress.
+    pub fn record_duration(&mut self, duration: Duration, ended_at: u64) {
+        let Some(result) = &mut self.state.result else {
+            return;
+        };
+        let duration = u64::try_from(duration.as_millis()).unwrap_or(u64::MAX);
+        let mut turn_started = ended_at.saturating_sub(duration);
+        result.turn_times = if self.history.iter().all(|m| m.timestamp != 0) {
+            self.history
+                .iter()
+                .map(|m| {
+                    let time = m.timestamp.saturating_sub(turn_started);
+                    turn_started = m.timestamp;
+                    time
+                })
+                .collect()
+        } else {
+            Vec::new()
+        };
+        result.duration = Some(duration);
     }
 
     #[must_use]
     fn is_move_winning(&self, x: usize, y: usize, player: Player) -> bool {
-        self.len_horizontal(x, y, player) >= WIN_LEN
-            || self.len_vertical(x, y, player) >= WIN_LEN
-            || self.len_diagonal_tl_br(x, y, player) >= WIN_LEN
-            || self.len_diagonal_tr_bl(x, y, player) >= WIN_LEN
+        self.field
+            .has_row_through(x, y, player, self.rules.win_len, self.rules.wrap_horizontal)
+    }
+
+    #[must_use]
+    pub fn field(&self) -> GameField {
+        self.field.into()
+    }
+
+    /// Returns true if no more chips can be dropped into the column.
+    #[must_use]
+    pub const fn is_column_full(&self, col: usize) -> bool {
+        self.field.is_column_full(col)
+    }
+
+    /// Returns the amount of chips in every column.
+    #[must_use]
+    pub fn filled(&self) -> Vec<u8> {
+        (0..self.field.width())
+            .map(|col| self.field.column_len(col) as u8)
+            .collect()
     }
 
+    /// Returns for every column whether the current player can make a move
+    /// in it: drop a chip, place one into an empty cell, or pop out their own
+    /// chip. All false once the game is over.
     #[must_use]
-    pub fn field(&self) -> &GameField {
-        &self.field
+    pub fn legal_moves(&self) -> Vec<bool> {
+        let (width, height) = (self.field.width(), self.field.height());
+        if self.state.result.is_some() {
+            return vec![false; width];
+        }
+        let can_pop = self.rules.pop_out && !self.rules.free_placement;
+        (0..width)
+            .map(|col| {
+                let has_space = if self.rules.free_placement {
+                    self.field.column_len(col) < height as u32
+                } else {
+                    !self.is_column_full(col)
+                };
+                has_space || (can_pop && self.field.get(col, height - 1) == Some(self.state.player))
+            })
+            .collect()
     }
 
     #[must_use]
@@ -432,6 +1064,76 @@ impl Game {
     pub fn state(&self) -> &GameState {
         &self.state
     }
+
+    /// Returns a copy of the game without its moves, so that searches do not
+    /// copy them at every node.
+    #[must_use]
+    fn without_history(&self) -> Self {
+        Self {
+            field: self.field,
+            state: self.state.clone(),
+            rules: self.rules.clone(),
+            history: Vec::new(),
+        }
+    }
+
+    /// Returns every turn ended since the game was created or deserialized,
+    /// oldest first. The first of them ended [`Game::first_turn`].
+    #[must_use]
+    pub fn moves(&self) -> &[Move] {
+        &self.history
+    }
+
+    /// Returns the turn ended by the first stored move. Deserialized games
+    /// do not keep their moves, so this is the turn they were resumed at.
+    #[must_use]
+    pub fn first_turn(&self) -> u32 {
+        self.state.turn - self.history.len() as u32
+    }
+
+    /// Returns the move which ended `turn`, if it is stored.
+    #[must_use]
+    pub fn move_at(&self, turn: u32) -> Option<&Move> {
+        let index = turn.checked_sub(self.first_turn())?;
+        self.history.get(index as usize)
+    }
+
+    /// Returns the game as it was at the start of `turn`, after the first
+    /// `turn` moves, by playing back the stored moves. Returns `None` if
+    /// fewer moves have been made, or if an earlier turn is asked of a game
+    /// without every move since turn 0, e.g. a deserialized one.
+    #[must_use]
+    pub fn state_at_turn(&self, turn: u32) -> Option<Self> {
+        if turn == self.state.turn {
+            return Some(self.clone());
+        }
+        if self.first_turn() != 0 {
+            return None;
+        }
+
+        let len = turn as usize;
+        let moves = self.history.get(..len)?;
+        let mut game = Self::new(self.rules.clone());
+        for m in moves {
+            let action = m.col.and_then(|col| Action::new(m.kind, col, m.row));
+            game.apply(action).ok()?;
+        }
+        game.history = moves.to_vec();
+        Some(game)
+    }
+
+    /// Takes back the moves made in `turn` and later, e.g. to undo them.
+    /// Returns false and leaves the game unchanged if fewer moves have been
+    /// made.
+    pub fn truncate_to_turn(&mut self, turn: u32) -> bool {
+        match self.state_at_turn(turn) {
+            Some(game) => {
+                *self = game;
+                true
+            }
+            None => false,
+        }
+    }
 }
 
 impl GameState {
@@ -443,25 +1145,100 @@ impl GameState {
             moves: 0,
             result: None,
             last_move: None,
+            last_move_kind: MoveKind::Drop,
+            last_move_row: None,
+            left: Vec::new(),
         }
     }
 
-    fn next_turn(&mut self, col: Option<usize>) {
+    fn next_turn(&mut self, action: Option<Action>, players: u8) {
         self.turn += 1;
-        if col.is_some() {
-            self.moves += 1;
+        match action {
+            Some(Action::Drop(_) | Action::Place(..)) => self.moves += 1,
+            Some(Action::Pop(_)) => self.moves -= 1,
+            None => (),
         }
-        self.player = self.player.other();
-        self.last_move = col;
+        self.player = self.player.next(players);
+        self.last_move = action.map(Action::col);
+        self.last_move_kind = action.map(Action::kind).unwrap_or_default();
+        self.last_move_row = action.and_then(Action::row);
+    }
+}
+
+impl GameRules {
+    /// Returns `GameRules::players`, clamped to the supported range.
+    #[must_use]
+    pub const fn player_count(&self) -> u8 {
+        if self.players < 2 {
+            2
+        } else if self.players > MAX_PLAYERS {
+            MAX_PLAYERS
+        } else {
+            self.players
+        }
+    }
+
+    /// Returns the width and height of the field.
+    #[must_use]
+    pub const fn field_size(&self) -> (usize, usize) {
+        field_size(self.player_count())
+    }
+
+    /// Returns how many turns into a round the turn of the player comes.
+    #[must_use]
+    const fn position(&self, player: Player) -> usize {
+        let players = self.player_count() as usize;
+        (players + player.index() % players - self.starting_player.index() % players) % players
     }
 }
 
 impl Player {
+    /// Every player in turn order.
+    pub const ALL: [Self; MAX_PLAYERS as usize] = [P1, P2, P3, P4];
+
+    /// Returns the players of a game with the amount of players in turn
+    /// order.
+    #[must_use]
+    pub fn all(players: u8) -> &'static [Self] {
+        &Self::ALL[..usize::from(players.clamp(2, MAX_PLAYERS))]
+    }
+
+    #[must_use]
+    pub const fn index(self) -> usize {
+        self as usize
+    }
+
+    /// Returns the player whose turn comes after this one in a game with
+    /// the amount of players.
+    #[must_use]
+    pub const fn next(self, players: u8) -> Self {
+        self.after(1, players)
+    }
+
+    /// Returns the player whose turn comes `turns` turns after this one in a
+    /// game with the amount of players.
+    #[must_use]
+    const fn after(self, turns: u32, players: u8) -> Self {
+        Self::ALL[(self.index() + turns as usize) % players as usize]
+    }
+
+    /// Returns the opponent in a two player game.
     #[must_use]
     pub const fn other(&self) -> Self {
+        self.next(2)
+    }
+}
+
+impl GameWinner {
+    /// Returns the player who won, `None` for a draw.
+    #[must_use]
+    pub const fn player(self) -> Option<Player> {
         match self {
-            Self::P1 => Self::P2,
-            Self::P2 => Self::P1,
+            Self::P1 => Some(P1),
+            Self::P2 => Some(P2),
+            Self::P3 => Some(P3),
+            Self::P4 => Some(P4),
+            Self::Draw => None,
         }
     }
 }
@@ -477,6 +1254,11 @@ impl Default for GameRules {
         Self {
             starting_player: P1,
             allow_draws: false,
+            win_len: WIN_LEN,
+            pop_out: false,
+            free_placement: false,
+            wrap_horizontal: false,
+            players: default_players(),
         }
     }
 }
@@ -486,6 +1268,8 @@ impl From<Player> for GameWinner {
         match player {
             P1 => Self::P1,
             P2 => Self::P2,
+            P3 => Self::P3,
+            P4 => Self::P4,
         }
     }
 }
@@ -494,6 +1278,122 @@ impl From<Player> for GameWinner {
 mod tests {
     use super::*;
 
+    #[test]
+    fn field_serialization() {
+        let game = won_game_diagonal1(GameRules::default());
+        let json = serde_json::to_value(&game).unwrap();
+        assert_eq!(json["field"][3][FIELD_SIZE - 1], 0);
+        assert_eq!(json["field"][4][FIELD_SIZE - 1], 1);
+        assert_eq!(json["field"][0][0], serde_json::Value::Null);
+
+        let read: Game = serde_json::from_value(json).unwrap();
+        assert_eq!(read.field(), game.field());
+    }
+
+    #[test]
+    fn state_at_turn() {
+        let mut game = won_game_vertical(GameRules::default());
+        let earlier = game.state_at_turn(3).unwrap();
+        let replayed = fast_forward_game(GameRules::default(), &[4, 5, 4]);
+        assert_eq!(earlier.field(), replayed.field());
+        assert_eq!(earlier.state().player, P2);
+        assert_eq!(earlier.moves(), &game.moves()[..3]);
+        assert!(game.state_at_turn(7).unwrap().state().result.is_some());
+        assert!(game.state_at_turn(8).is_none());
+
+        assert!(!game.truncate_to_turn(8));
+        assert!(game.truncate_to_turn(6));
+        assert!(game.state().result.is_none());
+        assert_eq!(game.state().turn, 6);
+        assert_eq!(game.moves().len(), 6);
+        game.end_turn(Some(0)).unwrap();
+        assert!(game.state().result.is_none());
+    }
+
+    #[test]
+    fn resumed_games_only_know_their_later_moves() {
+        let played = fast_forward_game(GameRules::default(), &[4, 5, 4]);
+        let mut game: Game =
+            serde_json::from_value(serde_json::to_value(&played).unwrap()).unwrap();
+        assert_eq!(game.first_turn(), 3);
+        assert!(game.move_at(2).is_none());
+
+        game.end_turn(Some(5)).unwrap();
+        assert_eq!(game.first_turn(), 3);
+        assert_eq!(game.move_at(3).and_then(|m| m.col), Some(5));
+        assert!(game.move_at(4).is_none());
+
+        assert!(game.state_at_turn(4).is_some());
+        assert!(game.state_at_turn(3).is_none());
+        assert!(game.state_at_turn(0).is_none());
+        assert!(!game.truncate_to_turn(3));
+        assert_eq!(game.state().turn, 4);
+    }
+
+    #[test]
+    fn end_turn_events() {
+        let mut game = fast_forward_game(GameRules::default(), &[4, 5, 4, 5, 4]);
+        assert_eq!(
+            game.end_turn(None),
+            Ok(vec![
+                GameEvent::TurnSkipped { player: P2 },
+                GameEvent::TurnChanged { player: P1 },
+            ])
+        );
+
+        let events = game.end_turn(Some(3)).unwrap();
+        let matches = game.state.result.as_ref().unwrap().matches.clone();
+        assert_eq!(
+            events,
+            [
+                GameEvent::ChipPlaced {
+                    player: P1,
+                    col: 3,
+                    row: FIELD_SIZE - 4,
+                    kind: MoveKind::Drop,
+                },
+                GameEvent::GameWon {
+                    winner: P1,
+                    matches,
+                },
+            ]
+        );
+
+        let rules = GameRules {
+            allow_draws: true,
+            ..Default::default()
+        };
+        let mut game = won_game_horizontal(rules);
+        let events = game.end_turn(Some(6)).unwrap();
+        assert_eq!(events.last(), Some(&GameEvent::Drawn));
+    }
+
+    #[test]
+    fn from_moves() {
+        let played = won_game_vertical(GameRules::default());
+        let replayed = Game::from_moves(GameRules::default(), played.moves()).unwrap();
+        assert_eq!(replayed.field(), played.field());
+        assert_eq!(replayed.state().turn, played.state().turn);
+        assert!(replayed.state().result.is_some());
+        assert_eq!(replayed.moves(), played.moves());
+
+        let mut moves = played.moves().to_vec();
+        moves.swap(0, 1);
+        let res = Game::from_moves(GameRules::default(), &moves);
+        assert_eq!(res.err(), Some(FromMovesError::WrongPlayer(0)));
+
+        let mut moves = played.moves().to_vec();
+        moves[0].timestamp = 1;
+        let res = Game::from_moves(GameRules::default(), &moves);
+        assert_eq!(res.err(), Some(FromMovesError::TimestampOutOfOrder(1)));
+
+        let mut moves = played.moves().to_vec();
+        moves[2].col = Some(FIELD_SIZE);
+        let res = Game::from_moves(GameRules::default(), &moves);
+        let err = FromMovesError::InvalidMove(2, EndTurnError::IndexOutOfBounds);
+        assert_eq!(res.err(), Some(err));
+    }
+
     fn fast_forward_game(rules: GameRules, moves: &[usize]) -> Game {
         let mut game = Game::new(rules);
         for i in moves.iter().map(|i| Some(i - 1)) {
@@ -536,7 +1436,7 @@ mod tests {
 
     fn drawn_game(rules: GameRules) -> (Game, Result<(), ()>) {
         let mut game = won_game_horizontal(rules);
-        let drawn = game.end_turn(Some(6)).map_err(|_| ());
+        let drawn = game.apply(Some(Action::Drop(6))).map_err(|_| ());
         (game, drawn)
     }
 
@@ -581,6 +1481,28 @@ mod tests {
         assert_eq!(game.end_turn(Some(3)), Err(EndTurnError::ColumnFilled));
     }
 
+    #[test]
+    fn legal_moves_and_filled_columns() {
+        let game = fast_forward_game(GameRules::default(), &[4; FIELD_SIZE]);
+        assert_eq!(game.filled(), [0, 0, 0, 7, 0, 0, 0]);
+        assert_eq!(
+            game.legal_moves(),
+            [true, true, true, false, true, true, true]
+        );
+
+        let rules = GameRules {
+            pop_out: true,
+            ..Default::default()
+        };
+        let mut game = fast_forward_game(rules, &[4; FIELD_SIZE]);
+        assert!(!game.legal_moves()[3]);
+        game.end_turn(Some(0)).unwrap();
+        assert!(game.legal_moves()[3]);
+
+        let game = won_game_horizontal(GameRules::default());
+        assert_eq!(game.legal_moves(), [false; FIELD_SIZE]);
+    }
+
     #[test]
     fn is_game_over_horizontal() {
         let game = won_game_horizontal(GameRules::default());
@@ -629,10 +1551,14 @@ mod tests {
             let rules = GameRules {
                 starting_player,
                 allow_draws: false,
+                ..Default::default()
             };
             let (game, res) = drawn_game(rules);
             assert!(res.is_err());
-            assert_eq!(game.state.result.map(|r| r.winner), Some(starting_player.into()));
+            assert_eq!(
+                game.state.result.map(|r| r.winner),
+                Some(starting_player.into())
+            );
         }
     }
 
@@ -642,6 +1568,7 @@ mod tests {
             let rules = GameRules {
                 starting_player,
                 allow_draws: true,
+                ..Default::default()
             };
             let (game, res) = drawn_game(rules);
             assert!(res.is_ok());
@@ -706,10 +1633,419 @@ mod tests {
         assert!(game.is_move_winning(5, 3, P1));
     }
 
+    #[test]
+    fn game_moves_recorded() {
+        let mut game = won_game_vertical(GameRules::default());
+        let cols: Vec<_> = game.moves().iter().map(|m| m.col).collect();
+        let expected: Vec<_> = [4, 5, 4, 5, 4, 5, 4].iter().map(|c| Some(c - 1)).collect();
+        assert_eq!(cols, expected);
+        assert_eq!(game.moves()[1].player, P2);
+
+        assert!(game.end_turn(Some(0)).is_err());
+        assert_eq!(game.moves().len(), 7);
+    }
+
+    #[test]
+    fn game_forfeit() {
+        let mut game = fast_forward_game(GameRules::default(), &[1, 2]);
+        assert!(game.forfeit(P1).is_ok());
+        let result = game.state.result.as_ref().unwrap();
+        assert_eq!(result.winner, GameWinner::P2);
+        assert_eq!(result.reason, ResultReason::Abandonment);
+        assert!(result.matches.is_empty());
+        assert_eq!(game.forfeit(P2), Err(EndTurnError::GameOver));
+        assert_eq!(game.end_turn(Some(0)), Err(EndTurnError::GameOver));
+    }
+
+    #[test]
+    fn game_resign() {
+        let mut game = fast_forward_game(GameRules::default(), &[1, 2]);
+        assert!(game.resign(P2).is_ok());
+        let result = game.state.result.as_ref().unwrap();
+        assert_eq!(result.winner, GameWinner::P1);
+        assert!(result.resigned);
+        assert_eq!(result.reason, ResultReason::Resignation);
+        assert_eq!(game.resign(P1), Err(EndTurnError::GameOver));
+    }
+
+    #[test]
+    fn game_time_out_and_agree_draw() {
+        let mut game = fast_forward_game(GameRules::default(), &[1, 2]);
+        assert!(game.time_out(P1).is_ok());
+        let result = game.state.result.as_ref().unwrap();
+        assert_eq!(result.winner, GameWinner::P2);
+        assert_eq!(result.reason, ResultReason::Timeout);
+        assert!(!result.resigned);
+
+        let mut game = fast_forward_game(GameRules::default(), &[1, 2]);
+        assert!(game.agree_draw().is_ok());
+        let result = game.state.result.as_ref().unwrap();
+        assert_eq!(result.winner, GameWinner::Draw);
+        assert_eq!(result.reason, ResultReason::AgreedDraw);
+        assert!(game.clone().validated().is_ok());
+        assert_eq!(game.agree_draw(), Err(EndTurnError::GameOver));
+    }
+
+    #[test]
+    fn records_duration_of_finished_games() {
+        let mut game = Game::default();
+        game.play_at(Some(Action::Drop(0)), 1_080_000).unwrap();
+        game.play_at(Some(Action::Drop(1)), 1_089_500).unwrap();
+        game.record_duration(Duration::from_secs(90), 1_090_000);
+        assert!(game.state.result.is_none());
+
+        game.resign(P1).unwrap();
+        game.record_duration(Duration::from_secs(90), 1_090_000);
+        let result = game.state.result.as_ref().unwrap();
+        assert_eq!(result.duration, Some(90_000));
+        assert_eq!(result.turn_times, [80_000, 9500]);
+
+        // Turn times are unknown without timestamps
+        let mut game = fast_forward_game(GameRules::default(), &[1, 2]);
+        game.resign(P1).unwrap();
+        game.record_duration(Duration::from_secs(90), 1_090_000);
+        let result = game.state.result.as_ref().unwrap();
+        assert_eq!(result.duration, Some(90_000));
+        assert!(result.turn_times.is_empty());
+    }
+
+    #[test]
+    fn only_records_played_moves() {
+        let mut game = Game::default();
+        game.apply(Some(Action::Drop(3))).unwrap();
+        assert!(game.moves().is_empty());
+        game.play(Some(Action::Drop(3))).unwrap();
+        assert_eq!(game.moves(), [Move::new(P2, Some(Action::Drop(3)), 0)]);
+    }
+
+    #[test]
+    fn rule_pop_out() {
+        let mut game = fast_forward_game(GameRules::default(), &[1, 2]);
+        assert_eq!(
+            game.play(Some(Action::Pop(0))),
+            Err(EndTurnError::PopNotAllowed)
+        );
+
+        let rules = GameRules {
+            pop_out: true,
+            ..Default::default()
+        };
+        let mut game = fast_forward_game(rules, &[1, 4, 2, 4, 3]);
+        assert_eq!(
+            game.play(Some(Action::Pop(0))),
+            Err(EndTurnError::PopNotOwnChip)
+        );
+        game.end_turn(Some(6)).unwrap();
+        assert_eq!(
+            game.play(Some(Action::Pop(0))),
+            Ok(vec![
+                GameEvent::ChipPopped { player: P1, col: 0 },
+                GameEvent::TurnChanged { player: P2 },
+            ])
+        );
+        assert_eq!(game.field.get(0, FIELD_SIZE - 1), None);
+        assert_eq!(game.state.moves, 5);
+        assert_eq!(game.state.last_move_kind, MoveKind::Pop);
+        assert_eq!(game.moves().last().unwrap().kind, MoveKind::Pop);
+        assert!(game.state.result.is_none());
+    }
+
+    #[test]
+    fn rule_free_placement() {
+        let mut game = Game::default();
+        assert_eq!(
+            game.play(Some(Action::Place(0, 0))),
+            Err(EndTurnError::PlaceNotAllowed)
+        );
+
+        let rules = GameRules {
+            free_placement: true,
+            pop_out: true,
+            ..Default::default()
+        };
+        let mut game = Game::new(rules.clone());
+        game.play(Some(Action::Place(3, 0))).unwrap();
+        assert_eq!(game.field.get(3, 0), Some(P1));
+        assert_eq!(game.state.last_move_row, Some(0));
+        assert_eq!(
+            game.play(Some(Action::Place(3, 0))),
+            Err(EndTurnError::CellTaken)
+        );
+        assert_eq!(
+            game.play(Some(Action::Place(0, FIELD_SIZE))),
+            Err(EndTurnError::IndexOutOfBounds)
+        );
+        game.end_turn(Some(0)).unwrap();
+        assert_eq!(
+            game.play(Some(Action::Pop(0))),
+            Err(EndTurnError::PopNotAllowed)
+        );
+
+        // P1 completes a row along the top while P2 drops chips
+        for (col, drop) in [(4, 1), (5, 2)] {
+            game.play(Some(Action::Place(col, 0))).unwrap();
+            game.end_turn(Some(drop)).unwrap();
+        }
+        game.play(Some(Action::Place(6, 0))).unwrap();
+        let result = game.state.result.as_ref().unwrap();
+        assert_eq!(result.winner, GameWinner::P1);
+        assert_eq!(result.matches, [((3, 0), (6, 0))]);
+
+        let replayed = Game::from_moves(rules, game.moves()).unwrap();
+        assert_eq!(replayed.field(), game.field());
+        assert!(replayed.validated().is_ok());
+    }
+
+    #[test]
+    fn rule_free_placement_draws() {
+        let rules = GameRules {
+            free_placement: true,
+            allow_draws: true,
+            ..Default::default()
+        };
+        let mut game = Game::new(rules);
+        for col in 0..3 {
+            game.play(Some(Action::Place(col, 2))).unwrap();
+            game.play(Some(Action::Place(col, 4))).unwrap();
+        }
+        game.play(Some(Action::Place(3, 2))).unwrap();
+        assert!(game.state.result.is_none());
+        game.play(Some(Action::Place(3, 4))).unwrap();
+        let result = game.state.result.as_ref().unwrap();
+        assert_eq!(result.winner, GameWinner::Draw);
+    }
+
+    #[test]
+    fn rule_pop_out_win() {
+        let rules = GameRules {
+            pop_out: true,
+            ..Default::default()
+        };
+        // Column 4 holds P2 under P1. Once P2 pops it, P1's chip falls and
+        // completes the bottom row of P1 in columns 1-4.
+        let mut game = fast_forward_game(rules, &[1, 4, 2, 7, 3, 7, 4]);
+        assert_eq!(game.field.get(3, FIELD_SIZE - 2), Some(P1));
+        game.play(Some(Action::Pop(3))).unwrap();
+        assert_eq!(game.field.get(3, FIELD_SIZE - 1), Some(P1));
+        assert_eq!(game.state.result.unwrap().winner, GameWinner::P1);
+    }
+
+    #[test]
+    fn rule_win_len_three() {
+        let rules = GameRules {
+            win_len: 3,
+            ..Default::default()
+        };
+        let game = fast_forward_game(rules, &[1, 1, 2, 2, 3]);
+        let result = game.state.result.unwrap();
+        assert_eq!(result.winner, GameWinner::P1);
+        assert_eq!(result.matches, vec![((0, 6), (2, 6))]);
+    }
+
+    #[test]
+    fn rule_win_len_five() {
+        let rules = GameRules {
+            win_len: 5,
+            ..Default::default()
+        };
+        let mut game = won_game_horizontal(rules);
+        assert!(game.state.result.is_none());
+        game.end_turn(Some(6)).unwrap();
+        game.end_turn(Some(2)).unwrap();
+        let result = game.state.result.unwrap();
+        assert_eq!(result.winner, GameWinner::P1);
+        assert_eq!(result.matches, vec![((2, 6), (6, 6))]);
+    }
+
+    #[test]
+    fn rule_wrap_horizontal() {
+        let moves = [6, 6, 7, 7, 1, 1, 2];
+        let game = fast_forward_game(GameRules::default(), &moves);
+        assert!(game.state.result.is_none());
+
+        let rules = GameRules {
+            wrap_horizontal: true,
+            ..Default::default()
+        };
+        let game = fast_forward_game(rules.clone(), &moves);
+        let result = game.state.result.as_ref().unwrap();
+        assert_eq!(result.winner, GameWinner::P1);
+        assert_eq!(result.matches, vec![((5, 6), (8, 6))]);
+        assert!(game.validated().is_ok());
+
+        let rules = GameRules {
+            free_placement: true,
+            ..rules
+        };
+        let mut game = Game::new(rules);
+        for (p1, p2) in [((5, 6), (3, 0)), ((6, 5), (3, 1)), ((0, 4), (3, 2))] {
+            game.play(Some(Action::Place(p1.0, p1.1))).unwrap();
+            game.play(Some(Action::Place(p2.0, p2.1))).unwrap();
+        }
+        game.play(Some(Action::Place(1, 3))).unwrap();
+        let result = game.state.result.unwrap();
+        assert_eq!(result.winner, GameWinner::P1);
+        assert_eq!(result.matches, vec![((5, 6), (8, 3))]);
+    }
+
+    #[test]
+    fn validate_game_state() {
+        let rules = GameRules::default;
+        let draws = || GameRules {
+            allow_draws: true,
+            ..GameRules::default()
+        };
+        let mut forfeited = fast_forward_game(rules(), &[4]);
+        forfeited.forfeit(P2).unwrap();
+        let mut agreed = fast_forward_game(rules(), &[4]);
+        agreed.agree_draw().unwrap();
+        let valid = [
+            Game::default(),
+            fast_forward_game(rules(), &[4, 4, 5]),
+            won_game_vertical(rules()),
+            won_game_diagonal1(rules()),
+            won_game_diagonal2(rules()),
+            won_game_1(rules()),
+            won_game_horizontal(draws()),
+            drawn_game(draws()).0,
+            filled_game(rules()),
+            forfeited,
+            agreed,
+        ];
+        for game in valid {
+            assert_eq!(game.validated().err(), None);
+        }
+
+        let mut floating = Game::default();
+        floating.field.set(0, 0, Some(P1));
+        floating.state.next_turn(Some(Action::Drop(0)), 2);
+        let err = floating.validated().err();
+        assert_eq!(err, Some(InvalidStateError::FloatingChip));
+
+        let mut game = won_game_vertical(rules());
+        game.state.moves += 1;
+        let err = game.validated().err();
+        assert_eq!(err, Some(InvalidStateError::WrongMoveCount));
+
+        let mut game = fast_forward_game(rules(), &[4]);
+        game.state.player = P1;
+        let err = game.validated().err();
+        assert_eq!(err, Some(InvalidStateError::WrongPlayer));
+
+        let mut game = Game::default();
+        game.field.set(0, FIELD_SIZE - 1, Some(P1));
+        game.field.set(1, FIELD_SIZE - 1, Some(P1));
+        game.state.turn = 2;
+        game.state.moves = 2;
+        let err = game.validated().err();
+        assert_eq!(err, Some(InvalidStateError::TooManyChips));
+
+        let mut game = won_game_vertical(rules());
+        game.state.result = None;
+        let err = game.validated().err();
+        assert_eq!(err, Some(InvalidStateError::WrongResult));
+    }
+
+    #[test]
+    fn win_len_validation() {
+        assert!(!is_win_len_valid(2, MIN_WIN_LEN - 1));
+        assert!(is_win_len_valid(2, WIN_LEN));
+        assert!(is_win_len_valid(2, FIELD_SIZE));
+        assert!(!is_win_len_valid(2, FIELD_SIZE + 1));
+        assert!(is_win_len_valid(4, 8));
+        assert!(!is_win_len_valid(4, 9));
+    }
+
     #[test]
     fn is_game_over_when_filled() {
         let game = filled_game(GameRules::default());
         assert_eq!(game.state.turn, 49);
         assert!(game.state.result.is_some());
     }
+
+    fn multiplayer_rules(players: u8, starting_player: Player) -> GameRules {
+        GameRules {
+            starting_player,
+            players,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn players_take_turns_in_order() {
+        let mut game = Game::new(multiplayer_rules(4, P3));
+        let mut order = Vec::new();
+        for _ in 0..6 {
+            order.push(game.state.player);
+            game.end_turn(None).unwrap();
+        }
+        assert_eq!(order, [P3, P4, P1, P2, P3, P4]);
+    }
+
+    #[test]
+    fn multiplayer_field_size() {
+        let mut game = Game::new(multiplayer_rules(3, P1));
+        assert_eq!(game.rules().field_size(), (9, 7));
+        assert_eq!(game.legal_moves().len(), 9);
+        assert_eq!(game.end_turn(Some(9)), Err(EndTurnError::IndexOutOfBounds));
+        for _ in 0..7 {
+            game.end_turn(Some(8)).unwrap();
+        }
+        assert_eq!(game.end_turn(Some(8)), Err(EndTurnError::ColumnFilled));
+        assert_eq!(Game::new(multiplayer_rules(4, P1)).field().len(), 11);
+    }
+
+    #[test]
+    fn first_completed_row_wins_multiplayer() {
+        let mut game = fast_forward_game(multiplayer_rules(3, P1), &[1, 2, 3, 1, 2, 3, 1, 2, 3]);
+        assert!(game.state.result.is_none());
+        game.end_turn(Some(0)).unwrap();
+
+        let result = game.state.result.as_ref().unwrap();
+        assert_eq!(result.winner, GameWinner::P1);
+        assert_eq!(result.matches, [((0, 3), (0, 6))]);
+        assert_eq!(game.end_turn(Some(3)), Err(EndTurnError::GameOver));
+    }
+
+    #[test]
+    fn multiplayer_draws_end_with_the_round() {
+        let rules = GameRules {
+            allow_draws: true,
+            ..multiplayer_rules(3, P1)
+        };
+        let mut game = fast_forward_game(rules, &[1, 2, 3, 1, 2, 3, 1, 2, 3, 1]);
+        assert!(game.state.result.is_none());
+        game.end_turn(Some(1)).unwrap();
+        assert!(game.state.result.is_none());
+        game.end_turn(Some(2)).unwrap();
+        let result = game.state.result.as_ref().unwrap();
+        assert_eq!(result.winner, GameWinner::Draw);
+        assert_eq!(result.matches.len(), 3);
+    }
+
+    #[test]
+    fn players_who_left_are_skipped() {
+        let mut game = Game::new(multiplayer_rules(3, P1));
+        assert_eq!(
+            game.leave(P2, ResultReason::Abandonment, 0),
+            Ok(vec![GameEvent::TurnChanged { player: P1 }])
+        );
+        assert_eq!(game.leave(P2, ResultReason::Abandonment, 0), Ok(vec![]));
+        let events = game.end_turn(None).unwrap();
+        assert_eq!(events[1], GameEvent::TurnSkipped { player: P2 });
+        assert_eq!(game.state.player, P3);
+
+        let json = serde_json::to_value(&game).unwrap();
+        let read: Game = serde_json::from_value(json).unwrap();
+        assert_eq!(read.validated().unwrap().state().left, [P2]);
+
+        game.leave(P3, ResultReason::Resignation, 0).unwrap();
+        let result = game.state.result.as_ref().unwrap();
+        assert_eq!(result.winner, GameWinner::P1);
+        assert!(result.matches.is_empty());
+        assert_eq!(
+            game.leave(P1, ResultReason::Abandonment, 0),
+            Err(EndTurnError::GameOver)
+        );
+    }
 }
@@ -0,0 +1,220 @@
+//! Functions for inspecting positions, used by the bot and for hints. Scores
+//! are from the perspective of a single player against everyone else, so
+//! they are only meaningful in two player games.
+
+use super::{Action, Game, GameWinner, Player};
+
+/// Score of a won position, before adding the remaining depth.
+pub const WIN_SCORE: i32 = 1_000_000;
+/// Bonus for every chip in the center column.
+const CENTER_SCORE: i32 = 3;
+
+/// Returns every column a chip can be dropped into, center columns first.
+/// Empty if the game is over.
+#[must_use]
+pub fn legal_moves(game: &Game) -> Vec<usize> {
+    if game.state.result.is_some() {
+        return Vec::new();
+    }
+
+    let center = game.field.width() / 2;
+    let mut moves: Vec<usize> = (0..game.field.width())
+        .filter(|col| !game.is_column_full(*col))
+        .collect();
+    moves.sort_by_key(|col| col.abs_diff(center));
+    moves
+}
+
+/// Returns true if dropping a chip into `col` completes a row for `player`.
+#[must_use]
+fn completes_row(game: &Game, col: usize, player: Player) -> bool {
+    if col >= game.field.width() {
+        return false;
+    }
+    let Some(row) = (0..game.field.height())
+        .rev()
+        .find(|y| game.field.get(col, *y).is_none())
+    else {
+        return false;
+    };
+
+    let mut field = game.field;
+    field.set(col, row, Some(player));
+    field.has_row_through(
+        col,
+        row,
+        player,
+        game.rules.win_len,
+        game.rules.wrap_horizontal,
+    )
+}
+
+/// Returns true if the current player completes a row by dropping a chip
+/// into `col`.
+#[must_use]
+pub fn is_winning_move(game: &Game, col: usize) -> bool {
+    game.state.result.is_none() && completes_row(game, col, game.state.player)
+}
+
+/// Returns every column where `player` would complete a row with their
+/// next chip.
+#[must_use]
+pub fn immediate_threats(game: &Game, player: Player) -> Vec<usize> {
+    (0..game.field.width())
+        .filter(|col| completes_row(game, *col, player))
+        .collect()
+}
+
+/// Scores the position from the perspective of the current player by
+/// looking `depth` turns ahead. Positive scores favor the current player,
+/// scores of at least `WIN_SCORE` mean a forced win.
+#[must_use]
+pub fn evaluate(game: &Game, depth: u32) -> i32 {
+    negamax(&game.without_history(), depth, -i32::MAX, i32::MAX)
+}
+
+/// Returns the columns with the highest score for the current player
+/// together with that score, looking `depth` turns ahead.
+#[must_use]
+pub fn best_moves(game: &Game, depth: u32) -> (Vec<usize>, i32) {
+    let game = &game.without_history();
+    let mut best_score = -i32::MAX;
+    let mut best_moves = Vec::new();
+    for col in legal_moves(game) {
+        let mut next = game.clone();
+        if next.apply(Some(Action::Drop(col))).is_err() {
+            continue;
+        }
+
+        let score = -evaluate(&next, depth.saturating_sub(1));
+        if score > best_score {
+            best_score = score;
+            best_moves.clear();
+        }
+        if score == best_score {
+            best_moves.push(col);
+        }
+    }
+
+    (best_moves, best_score)
+}
+
+/// Scores the position from the perspective of the current player, using
+/// alpha-beta pruning.
+#[must_use]
+fn negamax(game: &Game, depth: u32, mut alpha: i32, beta: i32) -> i32 {
+    let player = game.state.player;
+    if let Some(result) = &game.state.result {
+        let score = WIN_SCORE + depth as i32;
+        return match result.winner {
+            GameWinner::Draw => 0,
+            winner if winner == GameWinner::from(player) => score,
+            _ => -score,
+        };
+    }
+
+    if depth == 0 {
+        return heuristic(game, player);
+    }
+
+    let mut best = None;
+    for col in legal_moves(game) {
+        let mut next = game.clone();
+        if next.apply(Some(Action::Drop(col))).is_err() {
+            continue;
+        }
+
+        let score = -negamax(&next, depth - 1, -beta, -alpha);
+        best = Some(best.map_or(score, |b: i32| b.max(score)));
+        alpha = alpha.max(score);
+        if alpha >= beta {
+            break;
+        }
+    }
+
+    best.unwrap_or(0)
+}
+
+/// Estimates how good the position is for `player` by counting rows which
+/// can still be completed, including the ones wrapping around the field
+/// with `GameRules::wrap_horizontal`.
+#[must_use]
+fn heuristic(game: &Game, player: Player) -> i32 {
+    const DIRECTIONS: [(isize, isize); 4] = [(1, 0), (0, 1), (1, 1), (1, -1)];
+
+    let field = &game.field;
+    let win_len = game.rules.win_len as isize;
+    let (width, height) = (field.width() as isize, field.height() as isize);
+    let wrap = game.rules.wrap_horizontal;
+    let in_bounds =
+        |x: isize, y: isize| (wrap || (0..width).contains(&x)) && (0..height).contains(&y);
+
+    let mut score = 0;
+    for x in 0..width {
+        for y in 0..height {
+            for (dx, dy) in DIRECTIONS {
+                let (end_x, end_y) = (x + dx * (win_len - 1), y + dy * (win_len - 1));
+                if !in_bounds(end_x, end_y) {
+                    continue;
+                }
+
+                let mut own = 0;
+                let mut other = 0;
+                for i in 0..win_len {
+                    match field.get(
+                        (x + dx * i).rem_euclid(width) as usize,
+                        (y + dy * i) as usize,
+                    ) {
+                        Some(p) if p == player => own += 1,
+                        Some(_) => other += 1,
+                        None => (),
+                    }
+                }
+
+                match (own, other) {
+                    (0, 0) => (),
+                    (n, 0) => score += n * n,
+                    (0, n) => score -= n * n,
+                    _ => (),
+                }
+            }
+        }
+    }
+
+    let center = field.width() / 2;
+    for chip in (0..field.height()).filter_map(|y| field.get(center, y)) {
+        score += if chip == player {
+            CENTER_SCORE
+        } else {
+            -CENTER_SCORE
+        };
+    }
+
+    score
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn play(moves: &[usize]) -> Game {
+        let mut game = Game::default();
+        for col in moves {
+            game.end_turn(Some(*col)).unwrap();
+        }
+        game
+    }
+
+    #[test]
+    fn threats_and_winning_moves() {
+        let game = play(&[0, 6, 1, 6, 2]);
+        assert_eq!(immediate_threats(&game, Player::P1), vec![3]);
+        assert_eq!(immediate_threats(&game, Player::P2), Vec::<usize>::new());
+        assert!(!is_winning_move(&game, 3));
+
+        let game = play(&[0, 6, 1, 6, 2, 5]);
+        assert!(is_winning_move(&game, 3));
+        assert!(evaluate(&game, 1) >= WIN_SCORE);
+        assert_eq!(best_moves(&game, 2).0, vec![3]);
+    }
+}
@@ -0,0 +1,239 @@
+//! Board stored as one bit mask per player.
+//!
+//! Cell `(x, y)` is bit `x * stride + y`, where the stride is the height of
+//! the board plus one. Every column is followed by an extra bit which is
+//! always empty, so that rows cannot wrap around from one column to the next
+//! when the masks are shifted.
+//!
+//! When the board wraps around horizontally, rows crossing the right edge
+//! are searched for in copies of the board with its columns rotated, so that
+//! they no longer cross it.
+
+use serde::{Deserialize, Serialize};
+
+use super::{GameField, Player, MAX_PLAYERS};
+
+/// Neighbors of a cell: vertical, horizontal and both diagonals.
+const DIRECTIONS: [(isize, isize); 4] = [(0, 1), (1, 0), (1, 1), (1, -1)];
+
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "GameField", into = "GameField")]
+pub struct Bitboard {
+    players: [u128; MAX_PLAYERS as usize],
+    width: usize,
+    height: usize,
+}
+
+impl Bitboard {
+    /// Returns an empty board. The columns of the board, each with a
+    /// separator bit, have to fit into 128 bits.
+    #[must_use]
+    pub const fn new(width: usize, height: usize) -> Self {
+        assert!(width * (height + 1) <= u128::BITS as usize);
+        Self {
+            players: [0; MAX_PLAYERS as usize],
+            width,
+            height,
+        }
+    }
+
+    #[must_use]
+    pub const fn width(&self) -> usize {
+        self.width
+    }
+
+    #[must_use]
+    pub const fn height(&self) -> usize {
+        self.height
+    }
+
+    /// Returns the amount of cells on the board.
+    #[must_use]
+    pub const fn cells(&self) -> usize {
+        self.width * self.height
+    }
+
+    /// Bits per column, including the empty separator bit.
+    const fn stride(&self) -> usize {
+        self.height + 1
+    }
+
+    /// Mask of every cell in the first column.
+    const fn column(&self) -> u128 {
+        (1 << self.height) - 1
+    }
+
+    const fn bit(&self, x: usize, y: usize) -> u128 {
+        1 << (x * self.stride() + y)
+    }
+
+    /// Returns the chips of every player.
+    const fn taken(&self) -> u128 {
+        let [p1, p2, p3, p4] = self.players;
+        p1 | p2 | p3 | p4
+    }
+
+    #[must_use]
+    pub fn get(&self, x: usize, y: usize) -> Option<Player> {
+        let bit = self.bit(x, y);
+        Player::ALL
+            .into_iter()
+            .find(|p| self.players[p.index()] & bit != 0)
+    }
+
+    pub fn set(&mut self, x: usize, y: usize, player: Option<Player>) {
+        let bit = self.bit(x, y);
+        for board in &mut self.players {
+            *board &= !bit;
+        }
+        if let Some(player) = player {
+            self.players[player.index()] |= bit;
+        }
+    }
+
+    /// Removes the bottom chip of the column and moves the chips above it
+    /// one row down.
+    pub fn pop(&mut self, x: usize) {
+        let column = self.column() << (x * self.stride());
+        for board in &mut self.players {
+            let moved = ((*board & column) << 1) & column;
+            *board = (*board & !column) | moved;
+        }
+    }
+
+    /// Returns true if the top cell of the column is taken.
+    #[must_use]
+    pub const fn is_column_full(&self, x: usize) -> bool {
+        self.taken() & self.bit(x, 0) != 0
+    }
+
+    /// Returns the amount of chips in the column.
+    #[must_use]
+    pub const fn column_len(&self, x: usize) -> u32 {
+        ((self.taken() >> (x * self.stride())) & self.column()).count_ones()
+    }
+
+    /// Returns the amount of chips the player has on the board.
+    #[must_use]
+    pub const fn count(&self, player: Player) -> u32 {
+        self.players[player.index()].count_ones()
+    }
+
+    /// Returns true if every chip rests on the bottom row or another chip.
+    #[must_use]
+    pub fn is_settled(&self) -> bool {
+        let board = self.taken();
+        (0..self.width).all(|x| {
+            let column = (board >> (x * self.stride())) & self.column();
+            // The cell below every chip has to be taken as well
+            (column << 1) & self.column() & !column == 0
+        })
+    }
+
+    /// Returns true if the player has at least `win_len` chips in a row
+    /// anywhere on the board. Rows may cross the left and right edge if
+    /// `wrap` is set.
+    #[must_use]
+    pub fn has_row(&self, player: Player, win_len: usize, wrap: bool) -> bool {
+        let board = self.players[player.index()];
+        let stride = self.stride();
+        let shifts = [1, stride, stride + 1, stride - 1];
+        // Rotating the columns by up to `win_len - 1` moves every row which
+        // crosses the edge back onto the board
+        let rotations = if wrap { win_len.min(self.width) } else { 1 };
+        (0..rotations).any(|r| {
+            let board = self.rotate(board, r);
+            shifts
+                .into_iter()
+                .any(|d| row_starts(board, d, win_len) != 0)
+        })
+    }
+
+    /// Returns true if the player has at least `win_len` chips in a row
+    /// going through `(x, y)`. Rows may cross the left and right edge if
+    /// `wrap` is set.
+    #[must_use]
+    pub fn has_row_through(
+        &self,
+        x: usize,
+        y: usize,
+        player: Player,
+        win_len: usize,
+        wrap: bool,
+    ) -> bool {
+        let board = self.players[player.index()];
+        let (width, height) = (self.width as isize, self.height as isize);
+        let owned = |x: isize, y: isize| {
+            let x = if wrap { x.rem_euclid(width) } else { x };
+            (0..width).contains(&x)
+                && (0..height).contains(&y)
+                && board & self.bit(x as usize, y as usize) != 0
+        };
+
+        let (x, y) = (x as isize, y as isize);
+        DIRECTIONS.into_iter().any(|(dx, dy)| {
+            // Chips on both sides of the point, each side only counted up to
+            // the length of a row so that a full ring is not walked forever
+            let len = |sign: isize| {
+                (1..win_len as isize)
+                    .take_while(|i| owned(x + sign * i * dx, y + sign * i * dy))
+                    .count()
+            };
+            owned(x, y) && 1 + len(1) + len(-1) >= win_len
+        })
+    }
+
+    /// Moves every column of the mask `r` columns to the left, the leftmost
+    /// ones continuing at the right edge.
+    const fn rotate(&self, board: u128, r: usize) -> u128 {
+        if r == 0 {
+            return board;
+        }
+        let stride = self.stride();
+        let cells = self.width * stride;
+        let mask = if cells == u128::BITS as usize {
+            u128::MAX
+        } else {
+            (1 << cells) - 1
+        };
+        ((board >> (r * stride)) | (board << ((self.width - r) * stride))) & mask
+    }
+}
+
+/// Returns a mask of every cell which starts `win_len` chips in a row in
+/// the direction given by the shift `d`.
+#[must_use]
+fn row_starts(board: u128, d: usize, win_len: usize) -> u128 {
+    (1..win_len).fold(board, |starts, i| starts & (board >> (i * d)))
+}
+
+impl TryFrom<GameField> for Bitboard {
+    type Error = &'static str;
+
+    fn try_from(field: GameField) -> Result<Self, Self::Error> {
+        let width = field.len();
+        let height = field.first().map_or(0, Vec::len);
+        if width == 0 || height == 0 || field.iter().any(|column| column.len() != height) {
+            return Err("field columns must be of the same, non-zero height");
+        }
+        if width * (height + 1) > u128::BITS as usize {
+            return Err("field is too large");
+        }
+
+        let mut board = Self::new(width, height);
+        for (x, column) in field.iter().enumerate() {
+            for (y, cell) in column.iter().enumerate() {
+                board.set(x, y, *cell);
+            }
+        }
+        Ok(board)
+    }
+}
+
+impl From<Bitboard> for GameField {
+    fn from(board: Bitboard) -> Self {
+        (0..board.width)
+            .map(|x| (0..board.height).map(|y| board.get(x, y)).collect())
+            .collect()
+    }
+}
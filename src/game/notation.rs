@@ -0,0 +1,444 @@
+//! Text notation for games, modelled on the portable game notation of chess.
+//! A game is written as tag pairs describing the rules and the result,
+//! followed by its moves:
+//!
+//! ```text
+//! [Starting "1"]
+//! [WinLen "4"]
+//! [Rules "popOut"]
+//! [Result "1-0"]
+//!
+//! 1. d e 2. d e 3. d e 4. d 1-0
+//! ```
+//!
+//! Columns are letters from `a` on the left. A chip dropped into a column is
+//! written as its letter, a popped chip as the letter followed by `^` and a
+//! placed chip as the letter followed by its row, counted from 1 at the
+//! bottom. Skipped turns are written as `-`. The result is `1-0` if player 1
+//! won, `0-1` if player 2 won, `1/2-1/2` for a draw or `*` if the game is
+//! still in progress.
+//!
+//! Games of more than two players have a `Players` tag, and their results
+//! have one score per player, such as `0-0-1` if player 3 won or
+//! `1/3-1/3-1/3` for a draw.
+
+use std::fmt::Write;
+
+use super::{
+    is_win_len_valid, Action, EndTurnError, Game, GameRules, GameWinner, MoveKind, Player,
+    ResultReason, MAX_PLAYERS,
+};
+
+/// Names of the rules listed in the `Rules` tag, in the order they are
+/// written.
+const RULES: [&str; 4] = ["allowDraws", "popOut", "freePlacement", "wrapHorizontal"];
+
+/// Reason why `Game::from_notation` rejected a game.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum NotationError {
+    /// A line starting with `[` is not written as `[Name "value"]`.
+    MalformedTag(String),
+    /// The value of the tag with this name is not valid.
+    InvalidTag(String),
+    /// A token of the move text is neither a move, a move number nor a
+    /// result.
+    InvalidToken(String),
+    /// The move at this index could not be made.
+    InvalidMove(usize, EndTurnError),
+    /// The result does not follow from the moves, or the result token does
+    /// not match the `Result` tag.
+    WrongResult,
+}
+
+/// Values of the `Termination` tag, written for games which ended without a
+/// completed row or a full field.
+const TERMINATIONS: [(ResultReason, &str); 4] = [
+    (ResultReason::Resignation, "resign"),
+    (ResultReason::Abandonment, "forfeit"),
+    (ResultReason::Timeout, "timeout"),
+    (ResultReason::AgreedDraw, "agreed"),
+];
+
+impl Game {
+    /// Writes the rules, the result and every move of the game in the text
+    /// notation described in `game::notation`. Games which ended early, e.g.
+    /// by resigning or running out of time, get a `Termination` tag.
+    #[must_use]
+    pub fn to_notation(&self) -> String {
+        let mut res = String::new();
+        let players = self.rules.player_count();
+        if players != 2 {
+            let _ = writeln!(res, "[Players \"{players}\"]");
+        }
+        let starting = self.rules.starting_player.index() + 1;
+        let _ = writeln!(res, "[Starting \"{starting}\"]");
+        let _ = writeln!(res, "[WinLen \"{}\"]", self.rules.win_len);
+
+        let enabled = [
+            self.rules.allow_draws,
+            self.rules.pop_out,
+            self.rules.free_placement,
+            self.rules.wrap_horizontal,
+        ];
+        let rules: Vec<_> = RULES
+            .iter()
+            .zip(enabled)
+            .filter_map(|(name, enabled)| enabled.then_some(*name))
+            .collect();
+        if !rules.is_empty() {
+            let _ = writeln!(res, "[Rules \"{}\"]", rules.join(" "));
+        }
+
+        let result = result_token(self.state.result.as_ref().map(|r| r.winner), players);
+        let _ = writeln!(res, "[Result \"{result}\"]");
+        let reason = self.state.result.as_ref().map(|r| r.reason);
+        if let Some((_, termination)) = TERMINATIONS.iter().find(|(r, _)| Some(*r) == reason) {
+            let _ = writeln!(res, "[Termination \"{termination}\"]");
+        }
+        res.push('\n');
+
+        let height = self.field.height();
+        let round = usize::from(players);
+        for (i, m) in self.history.iter().enumerate() {
+            if i % round == 0 {
+                let _ = write!(res, "{}. ", i / round + 1);
+            }
+            let action = m.col.and_then(|col| Action::new(m.kind, col, m.row));
+            res.push_str(&move_token(action, height));
+            res.push(' ');
+        }
+        res.push_str(&result);
+        res.push('\n');
+        res
+    }
+
+    /// Reads a game written with `Game::to_notation`. Unknown tags are
+    /// ignored, missing ones take their default value. The moves are
+    /// played back without timestamps.
+    ///
+    /// Errors:
+    ///
+    /// - `MalformedTag` and `InvalidTag` if a tag cannot be read
+    /// - `InvalidToken` if the move text cannot be read
+    /// - `InvalidMove` if a move cannot be made
+    /// - `WrongResult` if the result does not follow from the moves
+    pub fn from_notation(text: &str) -> Result<Self, NotationError> {
+        let mut rules = GameRules::default();
+        let mut starting = None;
+        let mut result_tag = None;
+        let mut termination = None;
+        let mut tokens = Vec::new();
+
+        for line in text.lines().map(str::trim) {
+            if !line.starts_with('[') {
+                tokens.extend(line.split_whitespace());
+                continue;
+            }
+
+            let (name, value) = parse_tag(line)?;
+            let invalid = || NotationError::InvalidTag(String::from(name));
+            match name {
+                "Players" => {
+                    rules.players = value.parse().map_err(|_| invalid())?;
+                    if !(2..=MAX_PLAYERS).contains(&rules.players) {
+                        return Err(invalid());
+                    }
+                }
+                "Starting" => starting = Some(value.parse::<usize>().map_err(|_| invalid())?),
+                "WinLen" => rules.win_len = value.parse().map_err(|_| invalid())?,
+                "Rules" => {
+                    for rule in value.split_whitespace() {
+                        match RULES.iter().position(|r| *r == rule) {
+                            Some(0) => rules.allow_draws = true,
+                            Some(1) => rules.pop_out = true,
+                            Some(2) => rules.free_placement = true,
+                            Some(3) => rules.wrap_horizontal = true,
+                            _ => return Err(invalid()),
+                        }
+                    }
+                }
+                "Result" => result_tag = Some(value),
+                "Termination" => {
+                    let (reason, _) = TERMINATIONS
+                        .iter()
+                        .find(|(_, t)| *t == value)
+                        .ok_or_else(invalid)?;
+                    termination = Some(*reason);
+                }
+                _ => (),
+            }
+        }
+
+        // Both depend on the amount of players, which may be given later
+        let players = Player::all(rules.players);
+        if let Some(starting) = starting {
+            rules.starting_player = starting
+                .checked_sub(1)
+                .and_then(|i| players.get(i).copied())
+                .ok_or_else(|| NotationError::InvalidTag(String::from("Starting")))?;
+        }
+        if !is_win_len_valid(rules.players, rules.win_len) {
+            return Err(NotationError::InvalidTag(String::from("WinLen")));
+        }
+        let result_tag = result_tag
+            .map(|value| parse_result(value, rules.players))
+            .transpose()
+            .map_err(|_| NotationError::InvalidTag(String::from("Result")))?;
+
+        let mut game = Self::new(rules);
+        let height = game.field.height();
+        let mut result_token = None;
+        for token in tokens {
+            if let Ok(result) = parse_result(token, game.rules.players) {
+                result_token = Some(result);
+                continue;
+            }
+            if is_move_number(token) {
+                continue;
+            }
+
+            let action = parse_move(token, height)?;
+            game.play(action)
+                .map_err(|e| NotationError::InvalidMove(game.history.len(), e))?;
+        }
+
+        let expected = match (result_tag, result_token) {
+            (Some(tag), Some(token)) if tag != token => return Err(NotationError::WrongResult),
+            (tag, token) => tag.or(token),
+        };
+        let winner = game.state.result.as_ref().map(|r| r.winner);
+        match expected {
+            None => (),
+            Some(expected) if expected == winner => (),
+            // Games ending early are not moves, they are only known from the
+            // result
+            Some(Some(GameWinner::Draw))
+                if winner.is_none() && termination == Some(ResultReason::AgreedDraw) =>
+            {
+                game.agree_draw().map_err(|_| NotationError::WrongResult)?;
+            }
+            Some(Some(expected)) if winner.is_none() && expected != GameWinner::Draw => {
+                let reason = match termination {
+                    Some(reason @ (ResultReason::Resignation | ResultReason::Timeout)) => reason,
+                    _ => ResultReason::Abandonment,
+                };
+                game.end_early(expected, reason)
+                    .map_err(|_| NotationError::WrongResult)?;
+            }
+            Some(_) => return Err(NotationError::WrongResult),
+        }
+
+        Ok(game)
+    }
+}
+
+/// Splits a line written as `[Name "value"]` into its name and value.
+fn parse_tag(line: &str) -> Result<(&str, &str), NotationError> {
+    line.strip_prefix('[')
+        .and_then(|tag| tag.strip_suffix(']'))
+        .and_then(|tag| tag.split_once(' '))
+        .and_then(|(name, value)| {
+            let value = value.trim().strip_prefix('"')?.strip_suffix('"')?;
+            Some((name, value))
+        })
+        .ok_or_else(|| NotationError::MalformedTag(String::from(line)))
+}
+
+/// Returns the winner written as a result token of a game with the amount
+/// of players, `None` for a game in progress.
+fn parse_result(token: &str, players: u8) -> Result<Option<GameWinner>, NotationError> {
+    if token == "*" {
+        return Ok(None);
+    }
+    [GameWinner::Draw]
+        .into_iter()
+        .chain(Player::all(players).iter().map(|p| GameWinner::from(*p)))
+        .find(|winner| result_token(Some(*winner), players) == token)
+        .map(Some)
+        .ok_or_else(|| NotationError::InvalidToken(String::from(token)))
+}
+
+/// Writes the score of every player, `1` for the winner and `0` for everyone
+/// else, or an equal share of `1` for a draw.
+fn result_token(winner: Option<GameWinner>, players: u8) -> String {
+    let Some(winner) = winner else {
+        return String::from("*");
+    };
+    let scores: Vec<_> = Player::all(players)
+        .iter()
+        .map(|p| match winner.player() {
+            Some(winner) if winner == *p => String::from("1"),
+            Some(_) => String::from("0"),
+            None => format!("1/{players}"),
+        })
+        .collect();
+    scores.join("-")
+}
+
+/// Returns true for move numbers such as `12.`.
+fn is_move_number(token: &str) -> bool {
+    token
+        .strip_suffix('.')
+        .is_some_and(|n| !n.is_empty() && n.bytes().all(|b| b.is_ascii_digit()))
+}
+
+/// Reads a move on a field of the height, `None` for a skipped turn.
+fn parse_move(token: &str, height: usize) -> Result<Option<Action>, NotationError> {
+    if token == "-" {
+        return Ok(None);
+    }
+
+    let invalid = || NotationError::InvalidToken(String::from(token));
+    let mut chars = token.chars();
+    let col = match chars.next() {
+        Some(c @ 'a'..='z') => c as usize - 'a' as usize,
+        _ => return Err(invalid()),
+    };
+    let rest = chars.as_str();
+    let action = match rest {
+        "" => Action::Drop(col),
+        "^" => Action::Pop(col),
+        _ => {
+            let row: usize = rest.parse().map_err(|_| invalid())?;
+            // Rows are counted from the bottom, the field from the top
+            Action::Place(col, height.checked_sub(row).ok_or_else(invalid)?)
+        }
+    };
+    Ok(Some(action))
+}
+
+fn move_token(action: Option<Action>, height: usize) -> String {
+    let Some(action) = action else {
+        return String::from("-");
+    };
+    let col = char::from(b'a' + action.col() as u8);
+    match action.kind() {
+        MoveKind::Drop => String::from(col),
+        MoveKind::Pop => format!("{col}^"),
+        MoveKind::Place => format!("{col}{}", height - action.row().unwrap_or(0)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let rules = GameRules {
+            pop_out: true,
+            ..GameRules::default()
+        };
+        let mut game = Game::new(rules);
+        for action in [
+            Some(Action::Drop(3)),
+            Some(Action::Drop(4)),
+            None,
+            Some(Action::Drop(4)),
+            Some(Action::Pop(3)),
+        ] {
+            game.play(action).unwrap();
+        }
+        game.resign(Player::P2).unwrap();
+
+        let text = game.to_notation();
+        assert_eq!(
+            text,
+            "[Starting \"1\"]\n[WinLen \"4\"]\n[Rules \"popOut\"]\n[Result \"1-0\"]\n\
+             [Termination \"resign\"]\n\n1. d e 2. - e 3. d^ 1-0\n"
+        );
+
+        let read = Game::from_notation(&text).unwrap();
+        assert_eq!(read.field(), game.field());
+        assert!(read.rules().pop_out);
+        let result = read.state().result.as_ref().unwrap();
+        assert_eq!(result.winner, GameWinner::P1);
+        assert!(result.resigned);
+    }
+
+    #[test]
+    fn round_trips_multiplayer_games() {
+        let rules = GameRules {
+            players: 3,
+            starting_player: Player::P2,
+            ..GameRules::default()
+        };
+        let mut game = Game::new(rules);
+        for col in [0, 1, 8, 0, 1, 8, 0, 1, 8, 0] {
+            game.end_turn(Some(col)).unwrap();
+        }
+
+        let text = game.to_notation();
+        assert_eq!(
+            text,
+            "[Players \"3\"]\n[Starting \"2\"]\n[WinLen \"4\"]\n[Result \"0-1-0\"]\n\n\
+             1. a b i 2. a b i 3. a b i 4. a 0-1-0\n"
+        );
+
+        let read = Game::from_notation(&text).unwrap();
+        assert_eq!(read.rules().players, 3);
+        assert_eq!(read.field(), game.field());
+        let result = read.state().result.as_ref().unwrap();
+        assert_eq!(result.winner, GameWinner::P2);
+    }
+
+    #[test]
+    fn round_trips_termination() {
+        let mut timed_out = Game::new(GameRules::default());
+        timed_out.play(Some(Action::Drop(3))).unwrap();
+        let mut agreed = timed_out.clone();
+        timed_out.time_out(Player::P2).unwrap();
+        agreed.agree_draw().unwrap();
+
+        for (game, termination) in [(timed_out, "timeout"), (agreed, "agreed")] {
+            let text = game.to_notation();
+            assert!(text.contains(&format!("[Termination \"{termination}\"]")));
+            let read = Game::from_notation(&text).unwrap();
+            let result = read.state().result.as_ref().unwrap();
+            assert_eq!(
+                Some(result.winner),
+                game.state().result.as_ref().map(|r| r.winner)
+            );
+            assert_eq!(
+                Some(result.reason),
+                game.state().result.as_ref().map(|r| r.reason)
+            );
+        }
+    }
+
+    #[test]
+    fn reads_placements_and_finished_games() {
+        let text = "[Rules \"freePlacement\"]\n\n1. a1 a7 2. b1 b7 3. c1 c7 4. d1 1-0";
+        let game = Game::from_notation(text).unwrap();
+        assert_eq!(game.field()[0][0], Some(Player::P2));
+        let result = game.state().result.as_ref().unwrap();
+        assert_eq!(result.winner, GameWinner::P1);
+        assert!(!result.matches.is_empty());
+        assert_eq!(
+            Game::from_notation(&game.to_notation()).unwrap().field(),
+            game.field()
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_games() {
+        use NotationError::*;
+        let read = |text| Game::from_notation(text).err();
+        assert_eq!(
+            read("[WinLen 4]"),
+            Some(MalformedTag(String::from("[WinLen 4]")))
+        );
+        assert_eq!(
+            read("[WinLen \"9\"]"),
+            Some(InvalidTag(String::from("WinLen")))
+        );
+        assert_eq!(read("1. d ?"), Some(InvalidToken(String::from("?"))));
+        assert_eq!(
+            read("1. d^"),
+            Some(InvalidMove(0, EndTurnError::PopNotAllowed))
+        );
+        assert_eq!(read("[Result \"0-1\"]\n\n1. d e 1-0"), Some(WrongResult));
+        assert_eq!(read("1. d d 2. d d 1/2-1/2"), Some(WrongResult));
+        assert_eq!(read("[Event \"Club night\"]\n\n1. d e *"), None);
+    }
+}
@@ -0,0 +1,334 @@
+//! Random games and invariants for testing rule changes, enabled with the
+//! `testing` feature.
+//!
+//! ```
+//! use connect_four_server::game::testing;
+//! use rand::{rngs::StdRng, SeedableRng};
+//!
+//! let mut rng = StdRng::seed_from_u64(1);
+//! let rules = testing::random_rules(&mut rng);
+//! testing::play_random_game(rules, &mut rng).unwrap();
+//! ```
+
+use rand::{seq::SliceRandom, Rng};
+
+use super::{
+    field_size, Action, EndTurnError, Game, GameMatch, GameRules, Player, MAX_PLAYERS, MIN_WIN_LEN,
+};
+
+/// Random games are cut off after this many turns, since games with
+/// `GameRules::pop_out` can go on forever.
+pub const MAX_TURNS: usize = 200;
+/// Chance of skipping a turn instead of making a move, as if it timed out.
+const SKIP_CHANCE: f64 = 0.05;
+
+/// Invariant of `Game` which did not hold.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum InvariantViolation {
+    /// `GameState::moves` is not the amount of chips on the field.
+    MoveCount { counted: u32, expected: u32 },
+    /// `GameState::turn` is not the amount of ended turns.
+    TurnCount { counted: u32, expected: usize },
+    /// The game was not valid according to `Game::validated`.
+    InvalidState,
+    /// A match starts or ends outside of the field.
+    MatchOffBoard(GameMatch),
+    /// A match is not a horizontal, vertical or diagonal line.
+    MatchNotStraight(GameMatch),
+    /// A match is shorter than `GameRules::win_len`.
+    MatchTooShort(GameMatch),
+    /// A match contains empty cells or chips of several players.
+    MatchNotFilled(GameMatch),
+    /// A move was accepted or the result changed after the game ended.
+    ResultChanged,
+}
+
+/// Returns random rules, with every setting allowed.
+pub fn random_rules(rng: &mut impl Rng) -> GameRules {
+    let players = rng.gen_range(2..=MAX_PLAYERS);
+    let (width, height) = field_size(players);
+    GameRules {
+        starting_player: Player::ALL[rng.gen_range(0..usize::from(players))],
+        allow_draws: rng.gen(),
+        win_len: rng.gen_range(MIN_WIN_LEN..=width.min(height)),
+        pop_out: rng.gen(),
+        free_placement: rng.gen(),
+        wrap_horizontal: rng.gen(),
+        players,
+    }
+}
+
+/// Returns every action the current player can take. Empty if the game is
+/// over.
+#[must_use]
+pub fn legal_actions(game: &Game) -> Vec<Action> {
+    if game.state.result.is_some() {
+        return Vec::new();
+    }
+
+    let player = game.state.player;
+    let (width, height) = (game.field.width(), game.field.height());
+    let drops = (0..width)
+        .filter(|col| !game.is_column_full(*col))
+        .map(Action::Drop);
+    let can_pop = game.rules.pop_out && !game.rules.free_placement;
+    let pops = (0..width)
+        .filter(|col| can_pop && game.field.get(*col, height - 1) == Some(player))
+        .map(Action::Pop);
+    let places = (0..width)
+        .flat_map(|col| (0..height).map(move |row| (col, row)))
+        .filter(|(col, row)| game.rules.free_placement && game.field.get(*col, *row).is_none())
+        .map(|(col, row)| Action::Place(col, row));
+    drops.chain(pops).chain(places).collect()
+}
+
+/// Returns a random legal action, or `None` to skip the turn.
+pub fn random_action(game: &Game, rng: &mut impl Rng) -> Option<Action> {
+    if rng.gen_bool(SKIP_CHANCE) {
+        return None;
+    }
+    legal_actions(game).choose(rng).copied()
+}
+
+/// Returns random legal actions played from the start of a game with the
+/// rules, until it ends or `MAX_TURNS` is reached.
+pub fn random_actions(rules: GameRules, rng: &mut impl Rng) -> Vec<Option<Action>> {
+    let mut game = Game::new(rules);
+    let mut actions = Vec::new();
+    while game.state.result.is_none() && actions.len() < MAX_TURNS {
+        let action = random_action(&game, rng);
+        game.apply(action)
+            .expect("legal actions should always be accepted");
+        actions.push(action);
+    }
+    actions
+}
+
+/// Plays a random game, checking the invariants after every turn. Once the
+/// game is over, checks that no further action changes it.
+pub fn play_random_game(rules: GameRules, rng: &mut impl Rng) -> Result<Game, InvariantViolation> {
+    let mut game = Game::new(rules);
+    check_invariants(&game)?;
+    while game.state.result.is_none() && game.history.len() < MAX_TURNS {
+        let action = random_action(&game, rng);
+        game.play(action)
+            .expect("legal actions should always be accepted");
+        check_invariants(&game)?;
+    }
+
+    if game.state.result.is_some() {
+        check_game_over(&game)?;
+    }
+    Ok(game)
+}
+
+/// Checks every invariant which holds after any turn for a game played with
+/// `Game::play` from its start.
+pub fn check_invariants(game: &Game) -> Result<(), InvariantViolation> {
+    let field = game.field();
+    let chips = field.iter().flatten().filter(|c| c.is_some()).count();
+    if game.state.moves as usize != chips {
+        return Err(InvariantViolation::MoveCount {
+            counted: game.state.moves,
+            expected: u32::try_from(chips).unwrap_or(u32::MAX),
+        });
+    }
+
+    // Turns of players who left are skipped with a move as well
+    if game.state.turn as usize != game.history.len() {
+        return Err(InvariantViolation::TurnCount {
+            counted: game.state.turn,
+            expected: game.history.len(),
+        });
+    }
+
+    let Some(result) = &game.state.result else {
+        return Ok(());
+    };
+    for &m in &result.matches {
+        check_match(game, m)?;
+    }
+
+    // Rows completed by popping may belong to several players, which
+    // `validated` does not expect
+    if !game.rules.pop_out {
+        game.clone()
+            .validated()
+            .map_err(|_| InvariantViolation::InvalidState)?;
+    }
+    Ok(())
+}
+
+/// Checks that a game which is over rejects every action and keeps its
+/// result.
+pub fn check_game_over(game: &Game) -> Result<(), InvariantViolation> {
+    let actions = (0..game.field.width())
+        .flat_map(|col| [Action::Drop(col), Action::Pop(col), Action::Place(col, 0)])
+        .map(Some)
+        .chain([None]);
+    for action in actions {
+        let mut next = game.clone();
+        if next.play(action) != Err(EndTurnError::GameOver) {
+            return Err(InvariantViolation::ResultChanged);
+        }
+    }
+
+    let mut next = game.clone();
+    if next.forfeit(game.state.player) != Err(EndTurnError::GameOver) {
+        return Err(InvariantViolation::ResultChanged);
+    }
+    Ok(())
+}
+
+fn check_match(game: &Game, m: GameMatch) -> Result<(), InvariantViolation> {
+    let ((x1, y1), (x2, y2)) = m;
+    let (width, height) = (game.field.width(), game.field.height());
+    // Rows wrapping around the field end past its right edge
+    let max_x = if game.rules.wrap_horizontal {
+        2 * width - 1
+    } else {
+        width
+    };
+    if x1 >= width || x2 >= max_x || y1 >= height || y2 >= height {
+        return Err(InvariantViolation::MatchOffBoard(m));
+    }
+
+    let (dx, dy) = (x2.abs_diff(x1), y2.abs_diff(y1));
+    if dx != 0 && dy != 0 && dx != dy {
+        return Err(InvariantViolation::MatchNotStraight(m));
+    }
+
+    let len = dx.max(dy) + 1;
+    if len < game.rules.win_len {
+        return Err(InvariantViolation::MatchTooShort(m));
+    }
+
+    let step = |a: usize, b: usize, i: usize| if b >= a { a + i } else { a - i };
+    let field = game.field();
+    let cells: Vec<_> = (0..len)
+        .map(|i| {
+            let x = if dx == 0 { x1 } else { step(x1, x2, i) };
+            let y = if dy == 0 { y1 } else { step(y1, y2, i) };
+            field[x % width][y]
+        })
+        .collect();
+    if cells[0].is_none() || cells.iter().any(|c| *c != cells[0]) {
+        return Err(InvariantViolation::MatchNotFilled(m));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+
+    use super::*;
+
+    prop_compose! {
+        fn any_rules()(players in 2..=MAX_PLAYERS)(
+            players in Just(players),
+            starting_player in 0..usize::from(players),
+            allow_draws in any::<bool>(),
+            win_len in {
+                let (width, height) = field_size(players);
+                MIN_WIN_LEN..=width.min(height)
+            },
+            pop_out in any::<bool>(),
+            free_placement in any::<bool>(),
+            wrap_horizontal in any::<bool>(),
+        ) -> GameRules {
+            GameRules {
+                starting_player: Player::ALL[starting_player],
+                allow_draws,
+                win_len,
+                pop_out,
+                free_placement,
+                wrap_horizontal,
+                players,
+            }
+        }
+    }
+
+    /// Turns of a game, each picking one of the legal actions by its index,
+    /// or skipping the turn. Shrunk turns stay legal.
+    fn any_turns() -> impl Strategy<Value = Vec<Option<usize>>> {
+        let turn = prop::option::weighted(1.0 - SKIP_CHANCE, any::<usize>());
+        prop::collection::vec(turn, 0..=MAX_TURNS)
+    }
+
+    /// Plays the turns until the game ends, passing the game to `check`
+    /// after every turn.
+    fn play(
+        rules: GameRules,
+        turns: &[Option<usize>],
+        mut check: impl FnMut(&Game) -> Result<(), TestCaseError>,
+    ) -> Result<Game, TestCaseError> {
+        let mut game = Game::new(rules);
+        check(&game)?;
+        for turn in turns {
+            if game.state.result.is_some() {
+                break;
+            }
+            let actions = legal_actions(&game);
+            let action = match turn {
+                Some(i) if !actions.is_empty() => Some(actions[i % actions.len()]),
+                _ => None,
+            };
+            let res = game.play(action);
+            prop_assert!(res.is_ok(), "{action:?} was rejected: {res:?}");
+            check(&game)?;
+        }
+        Ok(game)
+    }
+
+    proptest! {
+        #[test]
+        fn games_hold_invariants(rules in any_rules(), turns in any_turns()) {
+            let game = play(rules, &turns, |game| {
+                prop_assert_eq!(check_invariants(game), Ok(()));
+                Ok(())
+            })?;
+            if game.state.result.is_some() {
+                prop_assert_eq!(check_game_over(&game), Ok(()));
+            }
+        }
+
+        #[test]
+        fn games_can_be_replayed(rules in any_rules(), turns in any_turns()) {
+            let game = play(rules.clone(), &turns, |_| Ok(()))?;
+            let replayed = Game::from_moves(rules, game.moves()).unwrap();
+            prop_assert_eq!(replayed.field(), game.field());
+            prop_assert_eq!(replayed.state.turn, game.state.turn);
+            prop_assert_eq!(replayed.state.result.is_some(), game.state.result.is_some());
+        }
+    }
+
+    #[test]
+    fn rejects_broken_matches() {
+        let mut game = Game::default();
+        for col in [0, 1, 0, 1, 0, 1, 0] {
+            game.end_turn(Some(col)).unwrap();
+        }
+        assert_eq!(check_invariants(&game), Ok(()));
+
+        let result = game.state.result.as_mut().unwrap();
+        result.matches = vec![((0, 3), (0, 5))];
+        assert_eq!(
+            check_invariants(&game),
+            Err(InvariantViolation::MatchTooShort(((0, 3), (0, 5))))
+        );
+
+        let result = game.state.result.as_mut().unwrap();
+        result.matches = vec![((1, 3), (1, 6))];
+        assert_eq!(
+            check_invariants(&game),
+            Err(InvariantViolation::MatchNotFilled(((1, 3), (1, 6))))
+        );
+
+        game.state.moves += 1;
+        assert!(matches!(
+            check_invariants(&game),
+            Err(InvariantViolation::MoveCount { .. })
+        ));
+    }
+}
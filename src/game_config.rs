@@ -0,0 +1,321 @@
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::duration::{as_millis, as_millis_optional};
+use crate::game::{self, MAX_PLAYERS, WIN_LEN};
+
+/// Largest seed accepted in `GameConfig::seed`, the largest integer
+/// JavaScript clients can represent exactly.
+pub const MAX_SEED: u64 = (1 << 53) - 1;
+
+/// How the turn timer gives players time, see `GameConfig::time_per_turn`.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "camelCase")]
+pub enum TimerMode {
+    /// Every turn lasts `time_per_turn`, plus the time left over from the
+    /// player's previous turns, up to `time_cap`.
+    #[default]
+    CarryOver,
+    /// Every turn lasts `time_per_turn`, time left over is lost.
+    PerMove,
+    /// Players start with `time_per_turn`, and `increment` is added to the
+    /// time left after every move they make, up to `time_cap`.
+    Fischer,
+    /// Once the clock runs out, or right away in games without one, every
+    /// turn must be made within `time_per_turn`. Each time a player takes
+    /// longer they use up one of `byo_yomi_periods`, and lose once none are
+    /// left.
+    ByoYomi,
+}
+
+/// A subset of `GameRules` used for starting a new game.
+#[derive(Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "camelCase", default)]
+#[allow(clippy::struct_excessive_bools)] // independent rule toggles
+pub struct GameConfig {
+    #[serde(with = "as_millis")]
+    #[cfg_attr(feature = "schema", schemars(with = "f64"))]
+    pub time_per_turn: Duration,
+    #[serde(with = "as_millis")]
+    #[cfg_attr(feature = "schema", schemars(with = "f64"))]
+    pub time_cap: Duration,
+    /// Total time each player has for all of their turns, `0` to disable.
+    #[serde(with = "as_millis")]
+    #[cfg_attr(feature = "schema", schemars(with = "f64"))]
+    pub total_time: Duration,
+    /// Time added to the total after every turn.
+    #[serde(with = "as_millis")]
+    #[cfg_attr(feature = "schema", schemars(with = "f64"))]
+    pub increment: Duration,
+    pub timer_mode: TimerMode,
+    /// Amount of byo-yomi periods, only used with `TimerMode::ByoYomi`.
+    pub byo_yomi_periods: u8,
+    pub allow_draws: bool,
+    pub win_len: usize,
+    /// Allows removing own chips from the bottom row.
+    pub pop_out: bool,
+    /// Seed of every random choice in the game, such as who starts when both
+    /// or neither player wants to. `None` to pick one at random.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub seed: Option<u64>,
+    /// Amount of players, up to `MAX_PLAYERS`. Games with more than two
+    /// players are started with `lobbyPickPlayers` and played on a larger
+    /// field, see `GameRules::players`.
+    pub players: u8,
+    /// Lets players place chips into any empty cell, see
+    /// `GameRules::free_placement`.
+    pub free_placement: bool,
+    /// Lets rows wrap around from the right edge of the field to the left
+    /// one, see `GameRules::wrap_horizontal`.
+    pub wrap_horizontal: bool,
+    /// Lets the second player take over the first move instead of
+    /// answering it, so that the first player has no reason to open with
+    /// the strongest move.
+    pub pie_rule: bool,
+}
+
+/// A subset of `GameRules` used for starting a new game. All fields are optional.
+#[derive(Clone, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "camelCase", default)]
+pub struct PartialGameConfig {
+    #[serde(with = "as_millis_optional", skip_serializing_if = "Option::is_none")]
+    #[cfg_attr(feature = "schema", schemars(with = "Option<f64>"))]
+    pub time_per_turn: Option<Duration>,
+    #[serde(with = "as_millis_optional", skip_serializing_if = "Option::is_none")]
+    #[cfg_attr(feature = "schema", schemars(with = "Option<f64>"))]
+    pub time_cap: Option<Duration>,
+    #[serde(with = "as_millis_optional", skip_serializing_if = "Option::is_none")]
+    #[cfg_attr(feature = "schema", schemars(with = "Option<f64>"))]
+    pub total_time: Option<Duration>,
+    #[serde(with = "as_millis_optional", skip_serializing_if = "Option::is_none")]
+    #[cfg_attr(feature = "schema", schemars(with = "Option<f64>"))]
+    pub increment: Option<Duration>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timer_mode: Option<TimerMode>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub byo_yomi_periods: Option<u8>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub allow_draws: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub win_len: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pop_out: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub seed: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub players: Option<u8>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub free_placement: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub wrap_horizontal: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pie_rule: Option<bool>,
+}
+
+impl GameConfig {
+    /// Create a new `GameConfig` with values copied from `PartialGameConfig`,
+    /// where possible. If a value is missing, default value will be used instead.
+    #[must_use]
+    pub fn from_partial(partial: &PartialGameConfig) -> Self {
+        Self {
+            time_per_turn: partial.time_per_turn.unwrap_or_default(),
+            time_cap: partial.time_cap.unwrap_or_default(),
+            total_time: partial.total_time.unwrap_or_default(),
+            increment: partial.increment.unwrap_or_default(),
+            timer_mode: partial.timer_mode.unwrap_or_default(),
+            byo_yomi_periods: partial.byo_yomi_periods.unwrap_or_default(),
+            allow_draws: partial.allow_draws.unwrap_or_default(),
+            win_len: partial.win_len.unwrap_or(WIN_LEN),
+            pop_out: partial.pop_out.unwrap_or_default(),
+            seed: partial.seed,
+            players: partial.players.unwrap_or(2),
+            free_placement: partial.free_placement.unwrap_or_default(),
+            wrap_horizontal: partial.wrap_horizontal.unwrap_or_default(),
+            pie_rule: partial.pie_rule.unwrap_or_default(),
+        }
+    }
+
+    /// Returns true if the configuration can be used to start a game.
+    #[must_use]
+    pub const fn is_valid(&self) -> bool {
+        self.is_win_len_valid()
+            && self.is_seed_valid()
+            && self.is_timer_valid()
+            && self.are_players_valid()
+            && self.is_placement_valid()
+    }
+
+    /// Returns true if rows of `win_len` chips fit the field.
+    #[must_use]
+    pub const fn is_win_len_valid(&self) -> bool {
+        game::is_win_len_valid(self.players, self.win_len)
+    }
+
+    #[must_use]
+    pub const fn is_seed_valid(&self) -> bool {
+        matches!(self.seed, None | Some(0..=MAX_SEED))
+    }
+
+    /// Returns false for byo-yomi without any periods.
+    #[must_use]
+    pub const fn is_timer_valid(&self) -> bool {
+        !matches!(self.timer_mode, TimerMode::ByoYomi) || self.byo_yomi_periods > 0
+    }
+
+    /// Returns true if the game can be played by `players`. The pie rule
+    /// lets the second player take over the first move, so it is only
+    /// supported in two player games.
+    #[must_use]
+    pub const fn are_players_valid(&self) -> bool {
+        match self.players {
+            2 => true,
+            3..=MAX_PLAYERS => !self.pie_rule,
+            _ => false,
+        }
+    }
+
+    /// Returns false if free placement is combined with popping chips out,
+    /// which would leave chips floating.
+    #[must_use]
+    pub const fn is_placement_valid(&self) -> bool {
+        !(self.free_placement && self.pop_out)
+    }
+
+    /// Overwrites any settings contained within a `PartialGameConfig`.
+    pub fn apply_partial(&mut self, partial: &PartialGameConfig) {
+        if let Some(time_per_turn) = partial.time_per_turn {
+            self.time_per_turn = time_per_turn;
+        }
+
+        if let Some(time_cap) = partial.time_cap {
+            self.time_cap = time_cap;
+        }
+
+        if let Some(total_time) = partial.total_time {
+            self.total_time = total_time;
+        }
+
+        if let Some(increment) = partial.increment {
+            self.increment = increment;
+        }
+
+        if let Some(timer_mode) = partial.timer_mode {
+            self.timer_mode = timer_mode;
+        }
+
+        if let Some(byo_yomi_periods) = partial.byo_yomi_periods {
+            self.byo_yomi_periods = byo_yomi_periods;
+        }
+
+        if let Some(allow_draws) = partial.allow_draws {
+            self.allow_draws = allow_draws;
+        }
+
+        if let Some(win_len) = partial.win_len {
+            self.win_len = win_len;
+        }
+
+        if let Some(pop_out) = partial.pop_out {
+            self.pop_out = pop_out;
+        }
+
+        if let Some(seed) = partial.seed {
+            self.seed = Some(seed);
+        }
+
+        if let Some(players) = partial.players {
+            self.players = players;
+        }
+
+        if let Some(free_placement) = partial.free_placement {
+            self.free_placement = free_placement;
+        }
+
+        if let Some(wrap_horizontal) = partial.wrap_horizontal {
+            self.wrap_horizontal = wrap_horizontal;
+        }
+
+        if let Some(pie_rule) = partial.pie_rule {
+            self.pie_rule = pie_rule;
+        }
+    }
+}
+
+impl Default for GameConfig {
+    fn default() -> Self {
+        Self {
+            time_per_turn: Duration::ZERO,
+            time_cap: Duration::ZERO,
+            total_time: Duration::ZERO,
+            increment: Duration::ZERO,
+            timer_mode: TimerMode::CarryOver,
+            byo_yomi_periods: 0,
+            allow_draws: false,
+            win_len: WIN_LEN,
+            pop_out: false,
+            seed: None,
+            players: 2,
+            free_placement: false,
+            wrap_horizontal: false,
+            pie_rule: false,
+        }
+    }
+}
+
+impl From<PartialGameConfig> for GameConfig {
+    fn from(partial: PartialGameConfig) -> Self {
+        Self::from_partial(&partial)
+    }
+}
+
+impl PartialGameConfig {
+    /// Create a new `PartialGameConfig` with values copied from `GameConfig`.
+    #[must_use]
+    fn from_full(config: &GameConfig) -> Self {
+        Self {
+            time_per_turn: Some(config.time_per_turn),
+            time_cap: Some(config.time_cap),
+            total_time: Some(config.total_time),
+            increment: Some(config.increment),
+            timer_mode: Some(config.timer_mode),
+            byo_yomi_periods: Some(config.byo_yomi_periods),
+            allow_draws: Some(config.allow_draws),
+            win_len: Some(config.win_len),
+            pop_out: Some(config.pop_out),
+            seed: config.seed,
+            players: Some(config.players),
+            free_placement: Some(config.free_placement),
+            wrap_horizontal: Some(config.wrap_horizontal),
+            pie_rule: Some(config.pie_rule),
+        }
+    }
+}
+
+impl From<GameConfig> for PartialGameConfig {
+    fn from(config: GameConfig) -> Self {
+        Self::from_full(&config)
+    }
+}
+
+impl PartialEq for GameConfig {
+    fn eq(&self, other: &Self) -> bool {
+        self.time_per_turn == other.time_per_turn
+            && self.time_cap == other.time_cap
+            && self.total_time == other.total_time
+            && self.increment == other.increment
+            && self.timer_mode == other.timer_mode
+            && self.byo_yomi_periods == other.byo_yomi_periods
+            && self.allow_draws == other.allow_draws
+            && self.win_len == other.win_len
+            && self.pop_out == other.pop_out
+            && self.seed == other.seed
+            && self.players == other.players
+            && self.free_placement == other.free_placement
+            && self.wrap_horizontal == other.wrap_horizontal
+            && self.pie_rule == other.pie_rule
+    }
+}
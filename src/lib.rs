@@ -10,6 +10,14 @@
 #![allow(clippy::wildcard_imports)]
 // TODO: Remove this and add documentation
 #![allow(clippy::missing_errors_doc)]
+#![allow(clippy::missing_panics_doc)]
 
+pub mod bot;
+pub mod duration;
 pub mod game;
+pub mod game_config;
+pub mod replay;
+#[cfg(feature = "schema")]
+pub mod schema;
+#[cfg(feature = "server")]
 pub mod server;
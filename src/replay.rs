@@ -0,0 +1,102 @@
+//! Compact record of a game which can be played back move by move.
+
+use serde::{Deserialize, Serialize};
+
+use crate::game::{FromMovesError, Game, GameRules, Move, Player};
+use crate::game_config::GameConfig;
+
+/// Version of the replay format, increased on incompatible changes.
+pub const REPLAY_VERSION: u32 = 1;
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Replay {
+    pub version: u32,
+    pub config: GameConfig,
+    pub starting_player: Player,
+    /// Every turn ended in the game, oldest first. Forfeits are not moves,
+    /// so a forfeited game ends at the last move made.
+    pub moves: Vec<Move>,
+    /// Comments on the moves, oldest first.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub annotations: Vec<Annotation>,
+}
+
+/// A comment on a single turn, e.g. by a coach watching the game.
+#[derive(Clone, PartialEq, Eq, Serialize, Deserialize, Debug)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct Annotation {
+    /// Turn of the move the comment is about.
+    pub turn: u32,
+    pub text: String,
+    /// Player who wrote the comment, `None` for spectators.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub player: Option<Player>,
+    /// Name from the profile of the author, if they set one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// Milliseconds since the Unix epoch.
+    pub timestamp: u64,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ReplayError {
+    UnsupportedVersion(u32),
+    InvalidMoves(FromMovesError),
+}
+
+impl Replay {
+    /// Records the moves made in the game so far.
+    #[must_use]
+    pub fn new(config: GameConfig, game: &Game) -> Self {
+        Self {
+            version: REPLAY_VERSION,
+            config,
+            starting_player: game.rules().starting_player,
+            moves: game.moves().to_vec(),
+            annotations: Vec::new(),
+        }
+    }
+
+    /// Adds the comments made on the moves.
+    #[must_use]
+    pub fn with_annotations(mut self, annotations: Vec<Annotation>) -> Self {
+        self.annotations = annotations;
+        self
+    }
+
+    /// Returns the comments on the move made in the turn.
+    pub fn annotations_of(&self, turn: u32) -> impl Iterator<Item = &Annotation> {
+        self.annotations.iter().filter(move |a| a.turn == turn)
+    }
+
+    #[must_use]
+    pub fn rules(&self) -> GameRules {
+        GameRules {
+            starting_player: self.starting_player,
+            allow_draws: self.config.allow_draws,
+            win_len: self.config.win_len,
+            pop_out: self.config.pop_out,
+            free_placement: self.config.free_placement,
+            wrap_horizontal: self.config.wrap_horizontal,
+            players: self.config.players,
+        }
+    }
+
+    /// Returns the game after the first `len` moves, or all of them if there
+    /// are fewer.
+    pub fn game_at(&self, len: usize) -> Result<Game, ReplayError> {
+        if self.version != REPLAY_VERSION {
+            return Err(ReplayError::UnsupportedVersion(self.version));
+        }
+
+        let moves = &self.moves[..len.min(self.moves.len())];
+        Game::from_moves(self.rules(), moves).map_err(ReplayError::InvalidMoves)
+    }
+
+    /// Checks that every move can be played back.
+    pub fn validate(&self) -> Result<(), ReplayError> {
+        self.game_at(self.moves.len()).map(|_| ())
+    }
+}
@@ -0,0 +1,274 @@
+//! JSON Schema and TypeScript definitions of the WebSocket protocol, used to
+//! keep clients in sync with the server.
+
+use std::fmt::Write;
+
+use schemars::gen::{SchemaGenerator, SchemaSettings};
+use schemars::schema::{
+    InstanceType, Metadata, RootSchema, Schema, SchemaObject, SubschemaValidation,
+};
+use schemars::JsonSchema;
+use serde_json::{Map, Value};
+
+use crate::game::{GameWinner, Player};
+use crate::server::protocol::{IncomingMessage, OutgoingMessage, ProtocolVersion, Role};
+
+/// Implements `JsonSchema` for enums serialized as their discriminant with
+/// `serde_repr`.
+macro_rules! integer_enum_schema {
+    ($ty:ty, $name:literal, [$($value:literal),+]) => {
+        impl JsonSchema for $ty {
+            fn schema_name() -> String {
+                String::from($name)
+            }
+
+            fn json_schema(_: &mut SchemaGenerator) -> Schema {
+                integer_enum(&[$($value),+])
+            }
+        }
+    };
+}
+
+integer_enum_schema!(Player, "Player", [0, 1, 2, 3]);
+integer_enum_schema!(GameWinner, "GameWinner", [0, 1, 2, 3, 4]);
+integer_enum_schema!(ProtocolVersion, "ProtocolVersion", [1, 2]);
+
+fn integer_enum(values: &[u8]) -> Schema {
+    SchemaObject {
+        instance_type: Some(InstanceType::Integer.into()),
+        enum_values: Some(values.iter().map(|&v| v.into()).collect()),
+        ..Default::default()
+    }
+    .into()
+}
+
+impl JsonSchema for Role {
+    fn schema_name() -> String {
+        String::from("Role")
+    }
+
+    fn json_schema(gen: &mut SchemaGenerator) -> Schema {
+        let spectator = SchemaObject {
+            instance_type: Some(InstanceType::String.into()),
+            enum_values: Some(vec!["spectator".into()]),
+            ..Default::default()
+        };
+        SchemaObject {
+            subschemas: Some(Box::new(SubschemaValidation {
+                any_of: Some(vec![gen.subschema_for::<Player>(), spectator.into()]),
+                ..Default::default()
+            })),
+            ..Default::default()
+        }
+        .into()
+    }
+}
+
+/// Returns a schema matching every message sent by clients or the server.
+#[must_use]
+pub fn protocol_schema() -> RootSchema {
+    let mut gen = SchemaSettings::draft07().into_generator();
+    let incoming = gen.subschema_for::<IncomingMessage>();
+    let outgoing = gen.subschema_for::<OutgoingMessage<'static>>();
+    let schema = SchemaObject {
+        metadata: Some(Box::new(Metadata {
+            title: Some(String::from("Connect Four protocol")),
+            ..Default::default()
+        })),
+        subschemas: Some(Box::new(SubschemaValidation {
+            any_of: Some(vec![incoming, outgoing]),
+            ..Default::default()
+        })),
+        ..Default::default()
+    };
+
+    RootSchema {
+        meta_schema: gen.settings().meta_schema.clone(),
+        schema,
+        definitions: gen.take_definitions(),
+    }
+}
+
+/// Converts every definition of the schema into an exported TypeScript type.
+#[must_use]
+pub fn typescript(schema: &RootSchema) -> String {
+    let mut out = String::from("// Generated by `generate_schema`, do not edit.\n");
+    for (name, definition) in &schema.definitions {
+        let definition = serde_json::to_value(definition).unwrap_or_default();
+        out.push('\n');
+        write_doc(&mut out, &definition, 0);
+        let _ = writeln!(out, "export type {name} = {};", ts_type(&definition, 0));
+    }
+    out
+}
+
+fn write_doc(out: &mut String, schema: &Value, depth: usize) {
+    let Some(description) = schema.get("description").and_then(Value::as_str) else {
+        return;
+    };
+    let indent = "  ".repeat(depth);
+    let _ = writeln!(out, "{indent}/**");
+    for line in description.lines() {
+        let _ = writeln!(out, "{indent} * {line}");
+    }
+    let _ = writeln!(out, "{indent} */");
+}
+
+/// Returns the TypeScript type accepting the same values as the schema.
+fn ts_type(schema: &Value, depth: usize) -> String {
+    let Some(obj) = schema.as_object() else {
+        // `true` accepts anything, `false` nothing
+        return String::from(if schema == &Value::Bool(false) {
+            "never"
+        } else {
+            "unknown"
+        });
+    };
+
+    if let Some(path) = obj.get("$ref").and_then(Value::as_str) {
+        return String::from(path.rsplit('/').next().unwrap_or(path));
+    }
+    if let Some(value) = obj.get("const") {
+        return value.to_string();
+    }
+    if let Some(Value::Array(values)) = obj.get("enum") {
+        return values
+            .iter()
+            .map(Value::to_string)
+            .collect::<Vec<_>>()
+            .join(" | ");
+    }
+
+    let mut parts = Vec::new();
+    match obj.get("type") {
+        Some(Value::String(ty)) => parts.push(instance_type(ty, obj, depth)),
+        Some(Value::Array(types)) => {
+            let union = types
+                .iter()
+                .filter_map(Value::as_str)
+                .map(|ty| instance_type(ty, obj, depth))
+                .collect::<Vec<_>>();
+            parts.push(union.join(" | "));
+        }
+        _ if obj.contains_key("properties") => parts.push(instance_type("object", obj, depth)),
+        _ => (),
+    }
+    for key in ["oneOf", "anyOf"] {
+        if let Some(Value::Array(schemas)) = obj.get(key) {
+            let union = schemas
+                .iter()
+                .map(|s| ts_type(s, depth))
+                .collect::<Vec<_>>();
+            parts.push(union.join(" | "));
+        }
+    }
+    if let Some(Value::Array(schemas)) = obj.get("allOf") {
+        parts.extend(schemas.iter().map(|s| ts_type(s, depth)));
+    }
+
+    match parts.len() {
+        0 => String::from("unknown"),
+        1 => parts.remove(0),
+        _ => parts
+            .iter()
+            .map(|part| parenthesize(part))
+            .collect::<Vec<_>>()
+            .join(" & "),
+    }
+}
+
+fn instance_type(ty: &str, obj: &Map<String, Value>, depth: usize) -> String {
+    match ty {
+        "integer" | "number" => String::from("number"),
+        "array" => match obj.get("items") {
+            Some(Value::Array(items)) => {
+                let items = items.iter().map(|s| ts_type(s, depth)).collect::<Vec<_>>();
+                format!("[{}]", items.join(", "))
+            }
+            Some(items) => format!("{}[]", parenthesize(&ts_type(items, depth))),
+            None => String::from("unknown[]"),
+        },
+        "object" => object_type(obj, depth),
+        // string, boolean and null have the same names
+        _ => String::from(ty),
+    }
+}
+
+fn object_type(obj: &Map<String, Value>, depth: usize) -> String {
+    let additional = match obj.get("additionalProperties") {
+        None | Some(Value::Bool(true)) => Some(String::from("unknown")),
+        Some(Value::Bool(false)) => None,
+        Some(schema) => Some(ts_type(schema, depth + 1)),
+    };
+    let Some(Value::Object(properties)) = obj.get("properties") else {
+        let value = additional.unwrap_or_else(|| String::from("never"));
+        return format!("Record<string, {value}>");
+    };
+
+    let required = |name: &str| {
+        obj.get("required")
+            .and_then(Value::as_array)
+            .is_some_and(|r| r.iter().any(|v| v == name))
+    };
+    let indent = "  ".repeat(depth + 1);
+    let mut out = String::from("{\n");
+    for (name, schema) in properties {
+        write_doc(&mut out, schema, depth + 1);
+        let optional = if required(name) { "" } else { "?" };
+        let ty = ts_type(schema, depth + 1);
+        let _ = writeln!(out, "{indent}{name}{optional}: {ty};");
+    }
+    let _ = write!(out, "{}}}", "  ".repeat(depth));
+    out
+}
+
+/// Wraps unions and intersections in parentheses, so that they can be
+/// combined with other types.
+fn parenthesize(ty: &str) -> String {
+    let mut depth = 0;
+    let mut top_level = false;
+    for (i, c) in ty.char_indices() {
+        match c {
+            '(' | '[' | '{' => depth += 1,
+            ')' | ']' | '}' => depth -= 1,
+            '|' | '&' if depth == 0 && ty[..i].ends_with(' ') => top_level = true,
+            _ => (),
+        }
+    }
+
+    if top_level {
+        format!("({ty})")
+    } else {
+        String::from(ty)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn typescript_from_schema() {
+        let schema = serde_json::json!({
+            "type": "object",
+            "required": ["type", "col"],
+            "properties": {
+                "type": { "type": "string", "enum": ["gameEndTurn"] },
+                "col": { "type": ["integer", "null"] },
+                "clocks": { "type": "array", "items": { "type": "number" } },
+                "role": { "anyOf": [{ "$ref": "#/definitions/Player" }, { "type": "null" }] },
+            },
+        });
+        let expected = "{\n  \
+            clocks?: number[];\n  \
+            col: number | null;\n  \
+            role?: Player | null;\n  \
+            type: \"gameEndTurn\";\n\
+            }";
+        assert_eq!(ts_type(&schema, 0), expected);
+
+        let ts = typescript(&protocol_schema());
+        assert!(ts.contains("export type IncomingMessage = "));
+        assert!(ts.contains("export type OutgoingMessage = "));
+    }
+}
@@ -0,0 +1,699 @@
+//! Certificates obtained automatically from an ACME server, e.g. Let's
+//! Encrypt, using HTTP-01 challenges (RFC 8555).
+
+use std::{
+    collections::HashMap,
+    fs,
+    io::{self, Read},
+    path::{Path, PathBuf},
+    sync::{Arc, PoisonError, RwLock},
+    thread,
+    time::Duration,
+};
+
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use openssl::asn1::Asn1Time;
+use openssl::bn::{BigNum, BigNumContext, MsbOption};
+use openssl::ec::{EcGroup, EcKey};
+use openssl::ecdsa::EcdsaSig;
+use openssl::error::ErrorStack;
+use openssl::hash::{hash, MessageDigest};
+use openssl::nid::Nid;
+use openssl::pkey::{PKey, Private};
+use openssl::stack::Stack;
+use openssl::x509::extension::SubjectAlternativeName;
+use openssl::x509::{X509NameBuilder, X509ReqBuilder, X509VerifyResult, X509};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::{json, Value};
+use tracing::{error, info};
+use url::Url;
+
+use crate::server::config::{AcmeConfig, TlsConfig};
+use crate::server::http_client;
+use crate::server::tls::Certificates;
+
+/// How often the certificate is checked for expiry.
+const CHECK_INTERVAL: Duration = Duration::from_hours(12);
+/// How long to wait before trying again after a failed renewal.
+const RETRY_INTERVAL: Duration = Duration::from_hours(1);
+/// How long to wait between checks of a pending order or authorization.
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+/// How many times a pending order or authorization is checked.
+const POLL_ATTEMPTS: u32 = 30;
+/// Timeout of every request sent to the ACME server.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Responses to HTTP-01 challenges which are in progress, served at
+/// `/.well-known/acme-challenge/{token}`.
+#[derive(Default)]
+pub struct Challenges {
+    key_authorizations: RwLock<HashMap<String, String>>,
+}
+
+impl Challenges {
+    /// Returns the key authorization the ACME server expects for `token`.
+    pub fn get(&self, token: &str) -> Option<String> {
+        let key_authorizations = self
+            .key_authorizations
+            .read()
+            .unwrap_or_else(PoisonError::into_inner);
+        key_authorizations.get(token).cloned()
+    }
+
+    fn insert(&self, token: String, key_authorization: String) {
+        let mut key_authorizations = self
+            .key_authorizations
+            .write()
+            .unwrap_or_else(PoisonError::into_inner);
+        key_authorizations.insert(token, key_authorization);
+    }
+
+    fn remove(&self, token: &str) {
+        let mut key_authorizations = self
+            .key_authorizations
+            .write()
+            .unwrap_or_else(PoisonError::into_inner);
+        key_authorizations.remove(token);
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum AcmeError {
+    #[error("io error: {0}")]
+    IO(#[from] io::Error),
+    #[error(transparent)]
+    OpenSsl(#[from] ErrorStack),
+    #[error("invalid response: {0}")]
+    Json(#[from] serde_json::Error),
+    /// The request could not be sent, or the response was cut short.
+    #[error("request failed: {0}")]
+    Transport(#[from] Box<ureq::Transport>),
+    /// The ACME server responded with an error status.
+    #[error("server responded with {0}: {1}")]
+    Status(u16, String),
+    /// The ACME server responded with something unexpected.
+    #[error("{0}")]
+    Protocol(String),
+}
+
+/// Keeps the certificate of the server valid, renewing it in a background
+/// thread whenever it is about to expire.
+pub struct CertificateManager {
+    acme: AcmeConfig,
+    private_key_file: PathBuf,
+    certificate_chain_file: PathBuf,
+    challenges: Arc<Challenges>,
+    certificates: Option<Arc<Certificates>>,
+}
+
+impl CertificateManager {
+    #[must_use]
+    pub fn new(acme: &AcmeConfig, tls: &TlsConfig, challenges: Arc<Challenges>) -> Self {
+        Self {
+            acme: acme.clone(),
+            private_key_file: tls.private_key_file.clone(),
+            certificate_chain_file: tls.certificate_chain_file.clone(),
+            challenges,
+            certificates: None,
+        }
+    }
+
+    /// Reloads these certificates after every renewal.
+    #[must_use]
+    pub fn with_certificates(mut self, certificates: Option<Arc<Certificates>>) -> Self {
+        self.certificates = certificates;
+        self
+    }
+
+    /// Writes a self-signed certificate if there is none yet, so that the
+    /// server can start before the first certificate is issued.
+    pub fn write_placeholder(&self) -> Result<(), AcmeError> {
+        if self.certificate_chain_file.exists() {
+            return Ok(());
+        }
+
+        let key = generate_key()?;
+        let domain = self
+            .acme
+            .domains
+            .first()
+            .map_or("localhost", String::as_str);
+        let mut name = X509NameBuilder::new()?;
+        name.append_entry_by_text("CN", domain)?;
+        let name = name.build();
+
+        let mut serial = BigNum::new()?;
+        serial.rand(128, MsbOption::MAYBE_ZERO, false)?;
+        let serial = serial.to_asn1_integer()?;
+        let not_before = Asn1Time::days_from_now(0)?;
+        let not_after = Asn1Time::days_from_now(1)?;
+        let mut cert = X509::builder()?;
+        cert.set_version(2)?;
+        cert.set_serial_number(&serial)?;
+        cert.set_subject_name(&name)?;
+        cert.set_issuer_name(&name)?;
+        cert.set_pubkey(&key)?;
+        cert.set_not_before(&not_before)?;
+        cert.set_not_after(&not_after)?;
+        cert.sign(&key, MessageDigest::sha256())?;
+
+        write_private_key(&self.private_key_file, &key)?;
+        write_atomic(&self.certificate_chain_file, &cert.build().to_pem()?)?;
+        Ok(())
+    }
+
+    /// Checks the certificate in a background thread for as long as the
+    /// server runs.
+    pub fn spawn(self) {
+        thread::spawn(move || loop {
+            let wait = if self.needs_renewal() {
+                match self.renew() {
+                    Ok(()) => CHECK_INTERVAL,
+                    Err(e) => {
+                        error!("Failed to obtain a certificate: {e}");
+                        RETRY_INTERVAL
+                    }
+                }
+            } else {
+                CHECK_INTERVAL
+            };
+            thread::sleep(wait);
+        });
+    }
+
+    /// Returns true if the certificate is missing, self-signed or about to
+    /// expire.
+    fn needs_renewal(&self) -> bool {
+        let Ok(pem) = fs::read(&self.certificate_chain_file) else {
+            return true;
+        };
+        let Ok(cert) = X509::from_pem(&pem) else {
+            return true;
+        };
+        let Ok(deadline) = Asn1Time::days_from_now(self.acme.renew_before_days) else {
+            return true;
+        };
+        cert.issued(&cert) == X509VerifyResult::OK || cert.not_after() < deadline
+    }
+
+    fn renew(&self) -> Result<(), AcmeError> {
+        info!(
+            "Requesting a certificate for {}",
+            self.acme.domains.join(", ")
+        );
+        let account_key = load_account_key(&self.acme.account_key_file)?;
+        let mut client = AcmeClient::new(&self.acme.directory_url, account_key)?;
+        client.register(self.acme.contact_email.as_deref())?;
+        let (key, chain) = client.order_certificate(&self.acme.domains, &self.challenges)?;
+
+        write_private_key(&self.private_key_file, &key)?;
+        write_atomic(&self.certificate_chain_file, &chain)?;
+        info!("Obtained a new certificate");
+        if let Some(certificates) = &self.certificates {
+            certificates.reload()?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+#[allow(clippy::struct_field_names)] // named after the resources of RFC 8555
+struct Directory {
+    new_nonce: Url,
+    new_account: Url,
+    new_order: Url,
+}
+
+#[derive(Deserialize)]
+struct Order {
+    status: String,
+    authorizations: Vec<Url>,
+    finalize: Url,
+    certificate: Option<Url>,
+}
+
+#[derive(Deserialize)]
+struct Authorization {
+    status: String,
+    challenges: Vec<Challenge>,
+}
+
+#[derive(Deserialize)]
+struct Challenge {
+    #[serde(rename = "type")]
+    kind: String,
+    url: Url,
+    token: String,
+}
+
+/// Public part of the account key. The fields are in lexicographic order,
+/// as required by the thumbprint (RFC 7638).
+#[derive(Serialize)]
+struct Jwk {
+    crv: &'static str,
+    kty: &'static str,
+    x: String,
+    y: String,
+}
+
+/// Session with an ACME server, authenticated with the account key.
+struct AcmeClient {
+    agent: ureq::Agent,
+    key: EcKey<Private>,
+    directory: Directory,
+    nonce: Option<String>,
+    /// URL of the account, sent instead of the key once registered.
+    account: Option<String>,
+}
+
+impl AcmeClient {
+    fn new(directory_url: &Url, key: EcKey<Private>) -> Result<Self, AcmeError> {
+        let agent = http_client::agent(REQUEST_TIMEOUT);
+        let directory = request(&agent, "GET", directory_url, None)?.json()?;
+        Ok(Self {
+            agent,
+            key,
+            directory,
+            nonce: None,
+            account: None,
+        })
+    }
+
+    /// Creates the account, or finds the existing one of the key.
+    fn register(&mut self, contact_email: Option<&str>) -> Result<(), AcmeError> {
+        let contact: Vec<_> = contact_email
+            .map(|e| format!("mailto:{e}"))
+            .into_iter()
+            .collect();
+        let payload = json!({ "termsOfServiceAgreed": true, "contact": contact });
+        let url = self.directory.new_account.clone();
+        let res = self.post(&url, Some(&payload))?;
+        let account = res.header("location").ok_or_else(|| {
+            AcmeError::Protocol(String::from("account URL missing from the response"))
+        })?;
+        self.account = Some(String::from(account));
+        Ok(())
+    }
+
+    /// Proves control of the domains and returns a new private key along
+    /// with its PEM encoded certificate chain.
+    fn order_certificate(
+        &mut self,
+        domains: &[String],
+        challenges: &Challenges,
+    ) -> Result<(PKey<Private>, Vec<u8>), AcmeError> {
+        let identifiers: Vec<_> = domains
+            .iter()
+            .map(|d| json!({ "type": "dns", "value": d }))
+            .collect();
+        let url = self.directory.new_order.clone();
+        let res = self.post(&url, Some(&json!({ "identifiers": identifiers })))?;
+        let order_url = res.header("location").map(Url::parse);
+        let Some(Ok(order_url)) = order_url else {
+            return Err(AcmeError::Protocol(String::from(
+                "order URL missing from the response",
+            )));
+        };
+        let order: Order = res.json()?;
+
+        for authorization in &order.authorizations {
+            self.authorize(authorization, challenges)?;
+        }
+
+        let key = generate_key()?;
+        let csr = URL_SAFE_NO_PAD.encode(certificate_request(&key, domains)?);
+        self.post(&order.finalize, Some(&json!({ "csr": csr })))?;
+        let order: Order = self.poll(&order_url, "processing")?;
+        let certificate = match (order.status.as_str(), order.certificate) {
+            ("valid", Some(url)) => url,
+            (status, _) => {
+                let msg = format!("order is {status} instead of valid");
+                return Err(AcmeError::Protocol(msg));
+            }
+        };
+        let chain = self.post(&certificate, None)?.body;
+        Ok((key, chain))
+    }
+
+    /// Completes the HTTP-01 challenge of an authorization.
+    fn authorize(&mut self, url: &Url, challenges: &Challenges) -> Result<(), AcmeError> {
+        let authorization: Authorization = self.post(url, None)?.json()?;
+        if authorization.status == "valid" {
+            return Ok(());
+        }
+        let challenge = authorization
+            .challenges
+            .into_iter()
+            .find(|c| c.kind == "http-01")
+            .ok_or_else(|| AcmeError::Protocol(String::from("no http-01 challenge offered")))?;
+
+        let key_authorization = format!("{}.{}", challenge.token, self.thumbprint()?);
+        challenges.insert(challenge.token.clone(), key_authorization);
+        let res = self
+            .post(&challenge.url, Some(&json!({})))
+            .and_then(|_| self.poll::<Authorization>(url, "pending"));
+        challenges.remove(&challenge.token);
+
+        match res?.status.as_str() {
+            "valid" => Ok(()),
+            status => {
+                let msg = format!("authorization of {url} is {status} instead of valid");
+                Err(AcmeError::Protocol(msg))
+            }
+        }
+    }
+
+    /// Fetches the resource at `url` until its status is no longer
+    /// `pending`.
+    fn poll<T: DeserializeOwned>(&mut self, url: &Url, pending: &str) -> Result<T, AcmeError> {
+        for _ in 0..POLL_ATTEMPTS {
+            let value: Value = self.post(url, None)?.json()?;
+            if value["status"] != pending {
+                return Ok(serde_json::from_value(value)?);
+            }
+            thread::sleep(POLL_INTERVAL);
+        }
+        Err(AcmeError::Protocol(format!("{url} is still {pending}")))
+    }
+
+    /// Sends a signed request. Without a payload, this fetches the resource
+    /// at `url` (POST-as-GET).
+    fn post(&mut self, url: &Url, payload: Option<&Value>) -> Result<Response, AcmeError> {
+        let payload = match payload {
+            Some(payload) => serde_json::to_vec(payload)?,
+            None => Vec::new(),
+        };
+
+        // A nonce can be rejected if it expired, so try once more with the
+        // fresh one from the rejection
+        let mut retried = false;
+        loop {
+            let body = self.sign(url, &payload)?;
+            let res = request(&self.agent, "POST", url, Some(&body))?;
+            if let Some(nonce) = res.header("replay-nonce") {
+                self.nonce = Some(String::from(nonce));
+            }
+            if res.status == 400 && !retried && res.problem_type().as_deref() == Some("badNonce") {
+                retried = true;
+                continue;
+            }
+            return res.error_for_status();
+        }
+    }
+
+    /// Returns the request as a JSON Web Signature in flattened form.
+    fn sign(&mut self, url: &Url, payload: &[u8]) -> Result<Vec<u8>, AcmeError> {
+        let nonce = match self.nonce.take() {
+            Some(nonce) => nonce,
+            None => self.new_nonce()?,
+        };
+        let mut protected = json!({ "alg": "ES256", "nonce": nonce, "url": url });
+        match &self.account {
+            Some(account) => protected["kid"] = json!(account),
+            None => protected["jwk"] = serde_json::to_value(self.jwk()?)?,
+        }
+
+        let protected = URL_SAFE_NO_PAD.encode(serde_json::to_vec(&protected)?);
+        let payload = URL_SAFE_NO_PAD.encode(payload);
+        let digest = hash(
+            MessageDigest::sha256(),
+            format!("{protected}.{payload}").as_bytes(),
+        )?;
+        let signature = EcdsaSig::sign(&digest, &self.key)?;
+        let mut raw = signature.r().to_vec_padded(32)?;
+        raw.extend(signature.s().to_vec_padded(32)?);
+
+        let jws = json!({
+            "protected": protected,
+            "payload": payload,
+            "signature": URL_SAFE_NO_PAD.encode(raw),
+        });
+        Ok(serde_json::to_vec(&jws)?)
+    }
+
+    fn new_nonce(&self) -> Result<String, AcmeError> {
+        let res = request(&self.agent, "HEAD", &self.directory.new_nonce, None)?;
+        let nonce = res
+            .header("replay-nonce")
+            .ok_or_else(|| AcmeError::Protocol(String::from("nonce missing from the response")))?;
+        Ok(String::from(nonce))
+    }
+
+    fn jwk(&self) -> Result<Jwk, AcmeError> {
+        let mut ctx = BigNumContext::new()?;
+        let mut x = BigNum::new()?;
+        let mut y = BigNum::new()?;
+        self.key
+            .public_key()
+            .affine_coordinates(self.key.group(), &mut x, &mut y, &mut ctx)?;
+        Ok(Jwk {
+            crv: "P-256",
+            kty: "EC",
+            x: URL_SAFE_NO_PAD.encode(x.to_vec_padded(32)?),
+            y: URL_SAFE_NO_PAD.encode(y.to_vec_padded(32)?),
+        })
+    }
+
+    fn thumbprint(&self) -> Result<String, AcmeError> {
+        let jwk = serde_json::to_vec(&self.jwk()?)?;
+        Ok(URL_SAFE_NO_PAD.encode(hash(MessageDigest::sha256(), &jwk)?))
+    }
+}
+
+fn generate_key() -> Result<PKey<Private>, ErrorStack> {
+    let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1)?;
+    PKey::from_ec_key(EcKey::generate(&group)?)
+}
+
+/// Reads the account key from `path`, creating a new one if it does not
+/// exist.
+fn load_account_key(path: &Path) -> Result<EcKey<Private>, AcmeError> {
+    match fs::read(path) {
+        Ok(pem) => Ok(EcKey::private_key_from_pem(&pem)?),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            let key = generate_key()?;
+            write_private_key(path, &key)?;
+            Ok(key.ec_key()?)
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Returns a DER encoded certificate signing request for the domains.
+fn certificate_request(key: &PKey<Private>, domains: &[String]) -> Result<Vec<u8>, ErrorStack> {
+    let mut req = X509ReqBuilder::new()?;
+    req.set_pubkey(key)?;
+    let mut alt_names = SubjectAlternativeName::new();
+    for domain in domains {
+        alt_names.dns(domain);
+    }
+    let mut extensions = Stack::new()?;
+    extensions.push(alt_names.build(&req.x509v3_context(None))?)?;
+    req.add_extensions(&extensions)?;
+    req.sign(key, MessageDigest::sha256())?;
+    req.build().to_der()
+}
+
+/// Writes the key so that only the owner can read it.
+fn write_private_key(path: &Path, key: &PKey<Private>) -> Result<(), AcmeError> {
+    write_atomic(path, &key.private_key_to_pem_pkcs8()?)?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+
+        fs::set_permissions(path, fs::Permissions::from_mode(0o600))?;
+    }
+    Ok(())
+}
+
+/// Writes to a temporary file first, so that the listener never reads a
+/// file which is only partially written.
+fn write_atomic(path: &Path, contents: &[u8]) -> io::Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let tmp = path.with_extension("tmp");
+    fs::write(&tmp, contents)?;
+    fs::rename(&tmp, path)
+}
+
+struct Response {
+    status: u16,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+impl Response {
+    /// Reads the whole response, failing if it ends before its length.
+    fn read(res: ureq::Response) -> io::Result<Self> {
+        let status = res.status();
+        let headers = res
+            .headers_names()
+            .into_iter()
+            .filter_map(|name| {
+                let value = String::from(res.header(&name)?);
+                Some((name.to_ascii_lowercase(), value))
+            })
+            .collect();
+        let mut body = Vec::new();
+        res.into_reader().read_to_end(&mut body)?;
+        Ok(Self {
+            status,
+            headers,
+            body,
+        })
+    }
+
+    /// Returns the value of a header, `name` in lowercase.
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    fn json<T: DeserializeOwned>(&self) -> Result<T, AcmeError> {
+        Ok(serde_json::from_slice(&self.body)?)
+    }
+
+    /// Returns the type of the problem reported by the server, without the
+    /// `urn:ietf:params:acme:error:` prefix.
+    fn problem_type(&self) -> Option<String> {
+        let problem: Value = serde_json::from_slice(&self.body).ok()?;
+        let kind = problem["type"].as_str()?;
+        kind.strip_prefix("urn:ietf:params:acme:error:")
+            .map(String::from)
+    }
+
+    fn error_for_status(self) -> Result<Self, AcmeError> {
+        if (200..300).contains(&self.status) {
+            return Ok(self);
+        }
+        let body = String::from_utf8_lossy(&self.body).into_owned();
+        Err(AcmeError::Status(self.status, body))
+    }
+}
+
+/// Sends a request and waits for the whole response, also when its status
+/// is an error.
+fn request(
+    agent: &ureq::Agent,
+    method: &str,
+    url: &Url,
+    body: Option<&[u8]>,
+) -> Result<Response, AcmeError> {
+    let req = agent.request_url(method, url).set(
+        "Accept",
+        "application/json, application/pem-certificate-chain",
+    );
+    let res = match body {
+        Some(body) => req
+            .set("Content-Type", "application/jose+json")
+            .send_bytes(body),
+        None => req.call(),
+    };
+    match res {
+        Ok(res) | Err(ureq::Error::Status(_, res)) => Ok(Response::read(res)?),
+        Err(ureq::Error::Transport(e)) => Err(Box::new(e).into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Answers a single request with the raw response and closes the
+    /// connection.
+    fn serve_once(response: &'static [u8]) -> Url {
+        use std::io::Write;
+        use std::net::TcpListener;
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = Vec::new();
+            let mut chunk = [0; 1024];
+            while !request.windows(4).any(|w| w == b"\r\n\r\n") {
+                let read = stream.read(&mut chunk).unwrap();
+                request.extend_from_slice(&chunk[..read]);
+            }
+            stream.write_all(response).unwrap();
+        });
+        Url::parse(&url).unwrap()
+    }
+
+    #[test]
+    fn reads_chunked_and_error_responses() {
+        let agent = http_client::agent(REQUEST_TIMEOUT);
+        let url = serve_once(
+            b"HTTP/1.1 201 Created\r\nReplay-Nonce: abc\r\n\
+            Transfer-Encoding: chunked\r\n\r\n5\r\nhello\r\n6\r\n world\r\n0\r\n\r\n",
+        );
+        let res = request(&agent, "GET", &url, None).unwrap();
+        assert_eq!(res.status, 201);
+        assert_eq!(res.header("replay-nonce"), Some("abc"));
+        assert_eq!(res.body, b"hello world");
+
+        let url = serve_once(
+            b"HTTP/1.1 400 Bad Request\r\nContent-Length: 46\r\n\r\n\
+            {\"type\":\"urn:ietf:params:acme:error:badNonce\"}",
+        );
+        let res = request(&agent, "POST", &url, Some(b"{}")).unwrap();
+        assert_eq!(res.problem_type().as_deref(), Some("badNonce"));
+        assert!(res.error_for_status().is_err());
+    }
+
+    #[test]
+    fn fails_on_truncated_responses() {
+        let agent = http_client::agent(REQUEST_TIMEOUT);
+        let url = serve_once(b"HTTP/1.1 200 OK\r\nContent-Length: 10\r\n\r\nhello");
+        assert!(matches!(
+            request(&agent, "GET", &url, None),
+            Err(AcmeError::IO(_))
+        ));
+    }
+
+    #[test]
+    fn signs_requests_with_the_account_key() {
+        let key = generate_key().unwrap().ec_key().unwrap();
+        let directory = Directory {
+            new_nonce: Url::parse("https://acme.test/nonce").unwrap(),
+            new_account: Url::parse("https://acme.test/account").unwrap(),
+            new_order: Url::parse("https://acme.test/order").unwrap(),
+        };
+        let mut client = AcmeClient {
+            agent: http_client::agent(REQUEST_TIMEOUT),
+            key: key.clone(),
+            directory,
+            nonce: Some(String::from("nonce")),
+            account: None,
+        };
+
+        let url = Url::parse("https://acme.test/account").unwrap();
+        let jws: Value = serde_json::from_slice(&client.sign(&url, b"{}").unwrap()).unwrap();
+        let decode = |field: &str| {
+            URL_SAFE_NO_PAD
+                .decode(jws[field].as_str().unwrap())
+                .unwrap()
+        };
+        let protected: Value = serde_json::from_slice(&decode("protected")).unwrap();
+        assert_eq!(protected["jwk"]["kty"], "EC");
+        assert_eq!(decode("payload"), b"{}");
+
+        let signature = decode("signature");
+        let r = BigNum::from_slice(&signature[..32]).unwrap();
+        let s = BigNum::from_slice(&signature[32..]).unwrap();
+        let signature = EcdsaSig::from_private_components(r, s).unwrap();
+        let input = format!(
+            "{}.{}",
+            jws["protected"].as_str().unwrap(),
+            jws["payload"].as_str().unwrap()
+        );
+        let digest = hash(MessageDigest::sha256(), input.as_bytes()).unwrap();
+        assert!(signature.verify(&digest, &key).unwrap());
+        assert_eq!(client.thumbprint().unwrap().len(), 43);
+    }
+}
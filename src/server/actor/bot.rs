@@ -0,0 +1,53 @@
+use actix::prelude::*;
+use rand::{rngs::StdRng, SeedableRng};
+
+use crate::bot::{self, Difficulty};
+use crate::game::Game as InternalGame;
+use crate::server::actor;
+
+/// Asks the bot to pick a move for the current player.
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct ComputeMove {
+    pub game: InternalGame,
+    pub round: u32,
+    pub difficulty: Difficulty,
+    /// Seeds the random choices of the bot, drawn from the game's seeded
+    /// generator.
+    pub seed: u64,
+    pub reply_to: Addr<actor::Game>,
+}
+
+/// Sent back to the game once the bot has picked a move.
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct BotMove {
+    pub round: u32,
+    pub turn: u32,
+    pub col: Option<usize>,
+}
+
+/// Computes moves of the computer opponent. Runs inside a `SyncArbiter`, so
+/// that searching for a move does not block other actors.
+pub struct Bot;
+
+impl Actor for Bot {
+    type Context = SyncContext<Self>;
+}
+
+impl Handler<ComputeMove> for Bot {
+    type Result = ();
+
+    fn handle(&mut self, msg: ComputeMove, _: &mut Self::Context) {
+        let col = bot::best_move(
+            &msg.game,
+            msg.difficulty,
+            &mut StdRng::seed_from_u64(msg.seed),
+        );
+        msg.reply_to.do_send(BotMove {
+            round: msg.round,
+            turn: msg.game.state().turn,
+            col,
+        });
+    }
+}
@@ -1,23 +1,80 @@
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::sync::Arc;
 use std::time::{Duration, Instant};
 
 use actix::prelude::*;
-use chrono::{DateTime, Utc};
-use log::debug;
-use rand::Rng;
-
-use crate::game::{Game as InternalGame, GameRules, Player};
-use crate::server::{actor, AppConfig, GameConfig, PartialGameConfig, PlayerTuple};
-use actor::player::{self, AttachController, Disconnect, Disconnected, OutgoingMessage};
+use chrono::{DateTime, TimeDelta, Utc};
+use rand::{rngs::StdRng, seq::SliceRandom, Rng, SeedableRng};
+use serde::Serialize;
+use tokio::sync::broadcast;
+use tracing::{debug, error, info_span, Span};
+use uuid::Uuid;
+
+use crate::bot::Difficulty;
+use crate::duration::as_millis_optional_list;
+use crate::game::{
+    analysis, Action, Game as InternalGame, GameRules, GameWinner, Player, ResultReason,
+};
+use crate::game_config::{TimerMode, MAX_SEED};
+use crate::replay::{Annotation, Replay};
+use crate::server::archive::{ArchivedGame, GameArchive};
+use crate::server::auth;
+use crate::server::chat::{self, ChatLimiter};
+use crate::server::game_log::{GameLog, RoundSummary};
+use crate::server::latency::LatencyTracker;
+use crate::server::load::LoadGuard;
+use crate::server::profile::Profile;
+use crate::server::protocol::{
+    self, ConfigField, ConfigRejection, ConfigRejectionReason, Disconnect, EndTurnRejection,
+    IncomingChangeTiming, LateJoinOption, Notice, OutgoingGameMove, OutgoingGameSetup,
+    OutgoingGameSync, OutgoingMessage, Role, ISO_8601_TIMESTAMP,
+};
+use crate::server::rate_limit::TokenBucket;
+use crate::server::rating::RatingStore;
+use crate::server::reload::ReloadConfig;
+use crate::server::series::Pairing;
+use crate::server::stats::{GameStats, StatsCollector};
+use crate::server::timer::{Expiry, TurnClock, TIME_PER_TURN_MIN};
+use crate::server::token;
+use crate::server::webhook::{WebhookEvent, Webhooks};
+use crate::server::{actor, AppConfig, GameConfig, PartialGameConfig, PlayerArray};
+use actor::bot::{BotMove, ComputeMove};
+use actor::lobby::SeriesGameOver;
+use actor::lobby_router::{RegisterGame, RemoveGame};
+use actor::player::{
+    self, AttachController, Authenticated, Backpressure, BeginBatch, Chat, Disconnected, EndBatch,
+    RejectConfig, RequestFullSync, SendNotice, SerializedOutgoingMessage, UpdateProfile,
+    VersionedOutgoingMessage,
+};
+use actor::supervisor;
 use Player::{P1, P2};
 
-const TIME_PER_TURN_MIN: Duration = Duration::from_secs(3);
+/// How long the result of a round is shown before the loser is replaced in
+/// winner stays games.
+const WINNER_STAYS_DELAY: Duration = Duration::from_secs(5);
+/// How long the result of a series game is shown before the players return
+/// to the lobby.
+const SERIES_RESULT_DELAY: Duration = Duration::from_secs(5);
+/// How many events are kept for subscribers which fall behind. Every event
+/// contains the whole state, so missing some is harmless.
+const EVENT_CAPACITY: usize = 16;
+/// How long before closing an idle game everyone is warned.
+const IDLE_WARNING: Duration = Duration::from_mins(1);
+/// Longest annotation in characters.
+const ANNOTATION_MAX_LENGTH: usize = 500;
+/// Most annotations kept for a single round.
+const MAX_ANNOTATIONS: usize = 256;
+/// Hovers relayed per second from a single player, the rest are dropped.
+const HOVERS_PER_SECOND: f64 = 5.0;
+const HOVER_BURST: f64 = 5.0;
 
 #[derive(Message)]
 #[rtype(result = "()")]
 pub struct PlayerSelectionVote {
     pub player: Addr<actor::Player>,
     pub wants_to_start: bool,
+    pub seq: Option<u32>,
 }
 
 #[derive(Message)]
@@ -25,7 +82,8 @@ pub struct PlayerSelectionVote {
 pub struct EndTurn {
     pub player: Addr<actor::Player>,
     pub turn: u32,
-    pub col: Option<usize>,
+    pub action: Option<Action>,
+    pub seq: Option<u32>,
 }
 
 #[derive(Message)]
@@ -33,6 +91,7 @@ pub struct EndTurn {
 pub struct Restart {
     pub addr: Addr<actor::Player>,
     pub partial: Option<PartialGameConfig>,
+    pub seq: Option<u32>,
 }
 
 #[derive(Message)]
@@ -40,82 +99,286 @@ pub struct Restart {
 pub struct RestartResponse {
     pub addr: Addr<actor::Player>,
     pub accepted: bool,
+    pub seq: Option<u32>,
+}
+
+/// Proposes a new turn timer for the round in progress.
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct ChangeTiming {
+    pub addr: Addr<actor::Player>,
+    pub timing: IncomingChangeTiming,
+}
+
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct ChangeTimingResponse {
+    pub addr: Addr<actor::Player>,
+    pub accepted: bool,
+    pub seq: Option<u32>,
+}
+
+/// Takes over the first move of the opponent, see `GameConfig::pie_rule`.
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct SwapSides {
+    pub addr: Addr<actor::Player>,
+    pub turn: u32,
+    pub seq: Option<u32>,
+}
+
+/// Comments on a move, see `AppConfig::annotations`.
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct Annotate {
+    pub addr: Addr<actor::Player>,
+    pub turn: u32,
+    pub text: String,
+}
+
+/// Adds a player who joined the lobby after the game has started.
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct JoinInProgress(pub Addr<actor::Player>);
+
+/// Adds or removes a spectator from the queue for the next free seat.
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct Queue {
+    pub addr: Addr<actor::Player>,
+    pub queued: bool,
+}
+
+/// Sends the complete state of the game to the player or spectator.
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct RequestSync(pub Addr<actor::Player>);
+
+/// Shows the opponent the column the player points at. Ignored unless it is
+/// the player's turn.
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct Hover {
+    pub addr: Addr<actor::Player>,
+    pub col: Option<usize>,
+}
+
+/// Ends the game in progress with a loss for the player who sent it.
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct Resign {
+    pub addr: Addr<actor::Player>,
+    pub seq: Option<u32>,
+}
+
+/// Sent by a player who connected with a player ID once the game has
+/// become their controller.
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct Identify {
+    pub addr: Addr<actor::Player>,
+    pub player_id: String,
+}
+
+/// Sent by a player whenever its latency or clock offset is measured.
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct UpdateLatency {
+    pub addr: Addr<actor::Player>,
+    pub latency: LatencyTracker,
+}
+
+/// Returns the statistics of the game if the token belongs to the host.
+#[derive(Message)]
+#[rtype(result = "Option<GameStats>")]
+pub struct GetStats {
+    pub token: String,
+}
+
+/// Returns the state of the game if the token is its viewer token.
+#[derive(Message)]
+#[rtype(result = "Option<GameSnapshot>")]
+pub struct GetSnapshot {
+    pub token: String,
+}
+
+/// Read-only state of the game returned by `GET /game/{id}/snapshot`.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GameSnapshot {
+    pub round: u32,
+    pub config: GameConfig,
+    /// Board and state of the round, `None` during player selection.
+    pub game: Option<InternalGame>,
+    /// ISO 8601 timestamp of when the turn will be ended automatically.
+    pub timeout: Option<String>,
+    /// Total time left for each player in milliseconds, if the game is
+    /// played with clocks.
+    #[serde(
+        with = "as_millis_optional_list",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub clocks: Option<Vec<Duration>>,
+    pub profiles: Vec<Option<Profile>>,
+}
+
+/// Subscribes to the events of the game if the token is its viewer token.
+#[derive(Message)]
+#[rtype(result = "Option<EventSubscription>")]
+pub struct SubscribeEvents {
+    pub token: String,
+}
+
+/// State of the game, sent to subscribers whenever it changes.
+#[derive(Clone)]
+pub struct GameEvent {
+    /// Name of the message, e.g. `gameSync`.
+    pub name: &'static str,
+    /// The message serialized as JSON.
+    pub data: Arc<str>,
+}
+
+/// Events of a game, starting with its current state.
+pub struct EventSubscription {
+    pub initial: GameEvent,
+    pub events: broadcast::Receiver<GameEvent>,
+}
+
+/// Returns the state of the game for the admin API.
+#[derive(Message)]
+#[rtype(result = "GameInfo")]
+pub struct GetGameInfo;
+
+/// A game listed in `GET /admin/games`.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GameInfo {
+    /// ID of the lobby the game was started from.
+    pub id: Option<String>,
+    pub round: u32,
+    /// Current turn, `None` during player selection.
+    pub turn: Option<u32>,
+    pub game_over: bool,
+    /// Whether each player is connected, in turn order.
+    pub connected: Vec<bool>,
+    pub spectators: usize,
+    pub queued: usize,
+}
+
+/// Ends the game and disconnects everyone.
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct Shutdown;
+
+/// Replaces the connection of the player the token was issued to.
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct Rejoin {
+    pub token: String,
+    pub player: Addr<actor::Player>,
 }
 
 struct PlayerSelectionStage {
-    p1_vote: Option<bool>,
-    p2_vote: Option<bool>,
+    /// Whether each player wants to start, once they voted.
+    votes: PlayerArray<Option<bool>>,
 }
 
 impl PlayerSelectionStage {
-    fn new() -> Self {
+    fn new(players: u8) -> Self {
         Self {
-            p1_vote: None,
-            p2_vote: None,
+            votes: PlayerArray::repeat(None, players),
         }
     }
 }
 
 struct InGameStage {
     game: InternalGame,
-    extra_time: PlayerTuple<Duration>,
+    clock: TurnClock,
+    /// Scheduled for the timer of `clock`, if it is running.
     timeout: Option<TurnTimeout>,
+    /// Whether the second player may still swap sides instead of answering
+    /// the first move, see `GameConfig::pie_rule`.
+    can_swap: bool,
 }
 
 struct TurnTimeout {
+    /// ID of the timer the timeout was scheduled for.
+    id: u64,
     handle: SpawnHandle,
-    chrono: DateTime<Utc>,
-    instant: Instant,
+    /// Pending `GameTimeWarning`s.
+    warnings: Vec<SpawnHandle>,
 }
 
 impl InGameStage {
+    /// Picks one of the players who want to start, or any player if none
+    /// of them do.
     #[must_use]
-    fn starting_player(p1_vote: bool, p2_vote: bool) -> Player {
-        if p1_vote && !p2_vote {
-            P1
-        } else if p2_vote && !p1_vote {
-            P2
-        } else if rand::thread_rng().gen::<bool>() {
-            P1
+    fn starting_player(votes: &PlayerArray<bool>, rng: &mut impl Rng) -> Player {
+        let volunteers: Vec<_> = votes.iter().filter(|(_, v)| **v).map(|(p, _)| p).collect();
+        let candidates = if volunteers.is_empty() {
+            votes.players().collect()
         } else {
-            P2
-        }
+            volunteers
+        };
+        candidates.choose(rng).copied().unwrap_or(P1)
     }
 
     #[must_use]
-    const fn new(
-        game: InternalGame,
-        extra_time: PlayerTuple<Duration>,
-        timeout: Option<TurnTimeout>,
-    ) -> Self {
+    const fn new(game: InternalGame, clock: TurnClock) -> Self {
         Self {
             game,
-            extra_time,
-            timeout,
+            clock,
+            timeout: None,
+            can_swap: false,
         }
     }
 
     #[must_use]
-    fn from_votes(p1_vote: bool, p2_vote: bool, rules: &GameConfig) -> Self {
-        let starting_player = Self::starting_player(p1_vote, p2_vote);
+    fn from_votes(votes: &PlayerArray<bool>, config: &GameConfig, rng: &mut impl Rng) -> Self {
+        let starting_player = Self::starting_player(votes, rng);
         let rules = GameRules {
+            players: config.players,
             starting_player,
-            allow_draws: rules.allow_draws,
+            allow_draws: config.allow_draws,
+            win_len: config.win_len,
+            pop_out: config.pop_out,
+            free_placement: config.free_placement,
+            wrap_horizontal: config.wrap_horizontal,
         };
-        InternalGame::new(rules).into()
+        Self::new(InternalGame::new(rules), TurnClock::new(config, None))
     }
-}
 
-impl From<InternalGame> for InGameStage {
-    fn from(game: InternalGame) -> Self {
-        Self::new(
-            game,
-            PlayerTuple::new([Duration::ZERO, Duration::ZERO]),
-            None,
-        )
+    /// Returns when the turn times out according to a clock which is
+    /// `clock_offset` ahead of the server's.
+    #[must_use]
+    fn timeout_for(&self, clock_offset: Option<TimeDelta>) -> Option<DateTime<Utc>> {
+        let timeout = self.clock.deadline()?;
+        Some(clock_offset.map_or(timeout, |offset| timeout + offset))
+    }
+
+    /// Returns how long until the turn times out, measured with the
+    /// monotonic clock of the server.
+    #[must_use]
+    fn remaining(&self) -> Option<Duration> {
+        Some(self.clock.timer(Instant::now())?.remaining)
+    }
+
+    /// Returns how much total time every player has left right now.
+    #[must_use]
+    fn clocks_remaining(&self) -> Option<Vec<Duration>> {
+        self.clock.clocks(self.game.state().player, Instant::now())
     }
 }
 
+/// Details of the state of the game which depend on the recipient.
+struct SyncOptions {
+    /// Round trip time of every player.
+    latency: Vec<Option<Duration>>,
+    /// How far the clock of the recipient is ahead of the server's.
+    clock_offset: Option<TimeDelta>,
+}
+
+#[allow(clippy::large_enum_variant)]
 enum GameStage {
     PlayerSelection(PlayerSelectionStage),
     InGame(InGameStage),
@@ -131,21 +394,42 @@ impl GameStage {
         }
     }
 
+    /// Returns the state of the game, with the turn timeout moved by the
+    /// clock offset of the recipient.
     #[must_use]
-    fn outgoing_message(&self, round: u32) -> OutgoingMessage {
+    fn outgoing_message(&self, round: u32, sync: &SyncOptions) -> OutgoingMessage<'_> {
         match self {
             Self::PlayerSelection(stage) => {
-                let p1_voted = stage.p1_vote.is_some();
-                let p2_voted = stage.p2_vote.is_some();
-                OutgoingMessage::game_player_selection(p1_voted, p2_voted)
+                let voted: Vec<_> = stage.votes.as_slice().iter().map(Option::is_some).collect();
+                OutgoingMessage::game_player_selection(&voted)
             }
             Self::InGame(stage) => {
                 let game = &stage.game;
-                let timeout = stage.timeout.as_ref().map(|t| t.chrono);
-                OutgoingMessage::game_sync(round, game, timeout)
+                let timeout = stage.timeout_for(sync.clock_offset);
+                OutgoingGameSync::new(round, game, timeout, stage.clocks_remaining())
+                    .latency(&sync.latency)
+                    .can_swap(stage.can_swap)
+                    .periods(stage.clock.periods())
+                    .remaining(stage.remaining())
+                    .into()
             }
         }
     }
+
+    /// Returns `OutgoingMessage::GameMove` describing the last move, or
+    /// `None` if the game has not started or no moves were made.
+    #[must_use]
+    fn outgoing_move(&self, round: u32, sync: &SyncOptions) -> Option<OutgoingMessage<'_>> {
+        let Self::InGame(stage) = self else {
+            return None;
+        };
+        let timeout = stage.timeout_for(sync.clock_offset);
+        OutgoingGameMove::new(round, &stage.game, timeout, stage.clocks_remaining()).map(|msg| {
+            msg.can_swap(stage.can_swap)
+                .remaining(stage.remaining())
+                .into()
+        })
+    }
 }
 
 impl From<PlayerSelectionStage> for GameStage {
@@ -164,6 +448,8 @@ impl From<InGameStage> for GameStage {
 struct RestartRequest {
     /// Changed config.
     config: Option<GameConfig>,
+    /// Other players who accepted the request so far.
+    accepted: Vec<Player>,
     /// Timeout handle.
     handle: SpawnHandle,
     /// Timeout timestamp.
@@ -171,8 +457,62 @@ struct RestartRequest {
 }
 
 impl RestartRequest {
-    fn to_outgoing(&self) -> player::RestartRequest {
-        player::RestartRequest::new(self.config.as_ref(), self.timestamp)
+    fn to_outgoing(&self) -> protocol::RestartRequest<'_> {
+        protocol::RestartRequest::new(self.config.as_ref(), self.timestamp)
+    }
+}
+
+/// Request to continue the round with a different turn timer.
+struct TimingRequest {
+    /// Config with the new timing.
+    config: GameConfig,
+    /// Other players who accepted the request so far.
+    accepted: Vec<Player>,
+    /// Timeout handle.
+    handle: SpawnHandle,
+    /// Timeout timestamp.
+    timestamp: DateTime<Utc>,
+}
+
+impl TimingRequest {
+    fn to_outgoing(&self) -> protocol::TimingRequest<'_> {
+        protocol::TimingRequest::new(&self.config, self.timestamp)
+    }
+}
+
+/// Whoever is playing as one of the players.
+enum Seat {
+    Human(Addr<actor::Player>),
+    /// Moves are picked by the bot.
+    Bot(Difficulty, Addr<actor::Bot>),
+}
+
+impl Seat {
+    /// Sends the message to the player. Bots do not receive any messages.
+    fn do_send<M>(&self, msg: M)
+    where
+        M: Message + Send + 'static,
+        M::Result: Send,
+        actor::Player: Handler<M>,
+    {
+        if let Self::Human(addr) = self {
+            addr.do_send(msg);
+        }
+    }
+
+    /// Returns the address of the player, or None if the seat is taken by
+    /// a bot.
+    #[must_use]
+    const fn human(&self) -> Option<&Addr<actor::Player>> {
+        match self {
+            Self::Human(addr) => Some(addr),
+            Self::Bot(..) => None,
+        }
+    }
+
+    #[must_use]
+    fn is(&self, addr: &Addr<actor::Player>) -> bool {
+        self.human() == Some(addr)
     }
 }
 
@@ -180,78 +520,518 @@ pub struct Game {
     stage: GameStage,
     round: u32,
     config: GameConfig,
-    addrs: PlayerTuple<Addr<actor::Player>>,
-    restart_requests: PlayerTuple<Option<RestartRequest>>,
+    /// Seed `rng` was created from, reported to clients in
+    /// `OutgoingMessage::GameSetup`.
+    seed: u64,
+    /// Source of every random choice in the game, so that games with the
+    /// same seed can be reproduced.
+    rng: StdRng,
+    seats: PlayerArray<Seat>,
+    /// Whether the players traded seats with the pie rule, so that the
+    /// pairing of a series game lists them the other way around.
+    sides_swapped: bool,
+    spectators: Vec<Addr<actor::Player>>,
+    /// Spectators waiting to take the seat of a player who leaves.
+    queue: VecDeque<Addr<actor::Player>>,
+    /// Whether the loser of every round gives their seat to the first
+    /// queued spectator and joins the back of the queue.
+    winner_stays: bool,
+    restart_requests: PlayerArray<Option<RestartRequest>>,
+    /// Pending changes to the turn timer, applied without restarting.
+    timing_requests: PlayerArray<Option<TimingRequest>>,
+    /// Secrets used by the players to reconnect.
+    reconnect_tokens: PlayerArray<String>,
+    /// Secret sent to the players, used to read snapshots of the game and
+    /// subscribe to its events.
+    viewer_token: String,
+    events: broadcast::Sender<GameEvent>,
+    /// Warns everyone and then closes the game if no player acts for
+    /// `AppConfig::game_idle_timeout`.
+    idle_timeout: Option<SpawnHandle>,
+    /// Whether the game was closed for being idle for too long.
+    abandoned: bool,
+    /// Ends the game if a disconnected player does not return in time.
+    reconnect_timeouts: PlayerArray<Option<SpawnHandle>>,
+    /// Highest sequence number of a gameplay message processed for each
+    /// seat. Kept when a player reconnects, since they may resend messages.
+    last_seqs: PlayerArray<Option<u32>>,
+    /// ID and router the game is registered with, if any.
+    registry: Option<(Uuid, Addr<actor::LobbyRouter>)>,
+    /// Lobby the result is reported to if the game is part of a series.
+    series: Option<(Addr<actor::Lobby>, Pairing)>,
+    chat_limiters: PlayerArray<ChatLimiter>,
+    hover_limits: PlayerArray<TokenBucket>,
+    /// Comments on the moves of the current round, oldest first.
+    annotations: Vec<Annotation>,
+    stats: StatsCollector,
+    /// Secret of the host of the lobby the game was started from, needed to
+    /// read its statistics.
+    host_token: Option<String>,
+    archive: Option<Arc<dyn GameArchive>>,
+    ratings: Option<Arc<dyn RatingStore>>,
+    webhooks: Option<Arc<Webhooks>>,
+    /// Where a summary of every finished round is written to.
+    round_log: Option<Arc<GameLog>>,
+    /// Persistent IDs of players and spectators who sent one, or the IDs of
+    /// authenticated users, see `auth::rating_id`.
+    player_ids: HashMap<Addr<actor::Player>, String>,
+    /// Names and avatars of players and spectators who set them.
+    profiles: HashMap<Addr<actor::Player>, Profile>,
+    latencies: HashMap<Addr<actor::Player>, LatencyTracker>,
+    /// Players and spectators whose outgoing queue is congested. They are
+    /// skipped by syncs until they catch up.
+    congested: HashSet<Addr<actor::Player>>,
+    /// Span every log event of the game and its players is recorded in.
+    /// Nested in the span of the lobby the game was started from, if any.
+    span: Span,
+    /// Counts the game towards the load of the server until it stops.
+    _load: LoadGuard,
     cfg: Arc<AppConfig>,
 }
 
+/// Returns the current time in milliseconds since the Unix epoch, used to
+/// timestamp moves and annotations.
+fn unix_millis() -> u64 {
+    Utc::now().timestamp_millis().try_into().unwrap_or(0)
+}
+
 impl Game {
+    fn hover_limit() -> TokenBucket {
+        TokenBucket::new(HOVERS_PER_SECOND, HOVER_BURST)
+    }
+
     #[must_use]
     pub fn new(
         game: Option<InternalGame>,
         config: GameConfig,
         round: u32,
-        extra_time: Option<[Duration; 2]>,
-        addrs: PlayerTuple<Addr<actor::Player>>,
+        extra_time: Option<PlayerArray<Duration>>,
+        addrs: PlayerArray<Addr<actor::Player>>,
+        spectators: Vec<Addr<actor::Player>>,
+        cfg: Arc<AppConfig>,
+    ) -> Self {
+        let seats = addrs.map(Seat::Human);
+        Self::with_seats(game, config, round, extra_time, seats, spectators, cfg)
+    }
+
+    /// Creates a game where player 2 is controlled by the bot.
+    #[must_use]
+    pub fn against_bot(
+        player: Addr<actor::Player>,
+        difficulty: Difficulty,
+        bot: Addr<actor::Bot>,
+        cfg: Arc<AppConfig>,
+    ) -> Self {
+        let seats = PlayerArray::from([Seat::Human(player), Seat::Bot(difficulty, bot)]);
+        Self::with_seats(None, GameConfig::default(), 0, None, seats, Vec::new(), cfg)
+    }
+
+    #[must_use]
+    fn with_seats(
+        game: Option<InternalGame>,
+        config: GameConfig,
+        round: u32,
+        extra_time: Option<PlayerArray<Duration>>,
+        seats: PlayerArray<Seat>,
+        spectators: Vec<Addr<actor::Player>>,
         cfg: Arc<AppConfig>,
     ) -> Self {
+        let players = config.players;
         let stage: GameStage = if let Some(game) = game {
-            InGameStage::new(game, extra_time.unwrap_or_default().into(), None).into()
+            InGameStage::new(game, TurnClock::new(&config, extra_time)).into()
         } else {
-            PlayerSelectionStage::new().into()
+            PlayerSelectionStage::new(players).into()
         };
+        let seed = config
+            .seed
+            .unwrap_or_else(|| rand::thread_rng().gen_range(0..=MAX_SEED));
 
         Self {
             stage,
             round,
             config,
-            addrs,
-            restart_requests: PlayerTuple::new([None, None]),
+            seed,
+            rng: StdRng::seed_from_u64(seed),
+            seats,
+            sides_swapped: false,
+            spectators,
+            queue: VecDeque::new(),
+            winner_stays: false,
+            restart_requests: PlayerArray::from_fn(players, |_| None),
+            timing_requests: PlayerArray::from_fn(players, |_| None),
+            reconnect_tokens: PlayerArray::from_fn(players, |_| token::generate()),
+            viewer_token: token::generate(),
+            events: broadcast::channel(EVENT_CAPACITY).0,
+            idle_timeout: None,
+            abandoned: false,
+            reconnect_timeouts: PlayerArray::repeat(None, players),
+            last_seqs: PlayerArray::repeat(None, players),
+            registry: None,
+            series: None,
+            chat_limiters: PlayerArray::from_fn(players, |_| ChatLimiter::default()),
+            hover_limits: PlayerArray::from_fn(players, |_| Self::hover_limit()),
+            annotations: Vec::new(),
+            stats: StatsCollector::new(players),
+            host_token: None,
+            archive: None,
+            ratings: None,
+            webhooks: None,
+            round_log: None,
+            player_ids: HashMap::new(),
+            profiles: HashMap::new(),
+            latencies: HashMap::new(),
+            congested: HashSet::new(),
+            span: info_span!("game", round),
+            _load: LoadGuard::game(),
             cfg,
         }
     }
 
+    /// Allows the host to read the statistics of the game with the token.
+    #[must_use]
+    pub fn with_host_token(mut self, token: String) -> Self {
+        self.host_token = Some(token);
+        self
+    }
+
+    /// Stores every finished round in the archive.
+    #[must_use]
+    pub fn with_archive(mut self, archive: Option<Arc<dyn GameArchive>>) -> Self {
+        self.archive = archive;
+        self
+    }
+
+    /// Updates the ratings of the players after every round, if both of
+    /// them have a player ID.
+    #[must_use]
+    pub fn with_ratings(mut self, ratings: Option<Arc<dyn RatingStore>>) -> Self {
+        self.ratings = ratings;
+        self
+    }
+
+    /// Reports the start and the result of every round to the webhooks.
+    #[must_use]
+    pub fn with_webhooks(mut self, webhooks: Option<Arc<Webhooks>>) -> Self {
+        self.webhooks = webhooks;
+        self
+    }
+
+    /// Writes a summary of every finished round to the game log.
+    #[must_use]
+    pub fn with_game_log(mut self, game_log: Option<Arc<GameLog>>) -> Self {
+        self.round_log = game_log;
+        self
+    }
+
+    /// Profiles the players and spectators set before the game started.
+    #[must_use]
+    pub fn with_profiles(mut self, profiles: Vec<(Addr<actor::Player>, Profile)>) -> Self {
+        self.profiles.extend(profiles);
+        self
+    }
+
+    /// Replaces the loser of every round with the first queued spectator.
+    #[must_use]
+    pub fn with_winner_stays(mut self, winner_stays: bool) -> Self {
+        self.winner_stays = winner_stays;
+        self
+    }
+
+    /// Registers the game with the router once started, allowing players
+    /// to reconnect.
+    #[must_use]
+    pub fn register_with(mut self, id: Uuid, router: Addr<actor::LobbyRouter>) -> Self {
+        self.registry = Some((id, router));
+        self
+    }
+
+    /// Plays a single round of a series, reporting the result to the lobby
+    /// once the game ends instead of disconnecting the players.
+    #[must_use]
+    pub fn with_series(mut self, lobby: Addr<actor::Lobby>, pairing: Pairing) -> Self {
+        self.series = Some((lobby, pairing));
+        self
+    }
+
+    /// Records the sequence number of a gameplay message from the player.
+    /// Returns true if the message was already processed, in which case the
+    /// player is sent the current state instead.
+    fn is_duplicate(&mut self, player: Player, seq: Option<u32>) -> bool {
+        let Some(seq) = seq else {
+            return false;
+        };
+        if self.last_seqs[player].is_some_and(|last| seq <= last) {
+            debug!("Ignored duplicate message {seq} from {player:?}");
+            if let Some(addr) = self.seats[player].human() {
+                addr.do_send(self.sync_message(addr));
+            }
+            return true;
+        }
+
+        self.last_seqs[player] = Some(seq);
+        false
+    }
+
     /// Returns which player the address belongs to, or None if the address
-    /// does not belong to either player in this instance.
+    /// does not belong to any player in this instance.
     #[must_use]
     fn get_player(&self, player_addr: &Addr<actor::Player>) -> Option<Player> {
-        if &self.addrs[P1] == player_addr {
-            Some(P1)
-        } else if &self.addrs[P2] == player_addr {
-            Some(P2)
-        } else {
-            None
+        self.seats
+            .iter()
+            .find(|(_, seat)| seat.is(player_addr))
+            .map(|(player, _)| player)
+    }
+
+    /// Returns the profiles of every player.
+    #[must_use]
+    fn seat_profiles(&self) -> Vec<Option<&Profile>> {
+        self.seats
+            .iter()
+            .map(|(_, seat)| seat.human().and_then(|a| self.profiles.get(a)))
+            .collect()
+    }
+
+    /// Returns whether the player lost their connection for good, and was
+    /// not replaced by a queued spectator.
+    #[must_use]
+    fn has_left(&self, player: Player) -> bool {
+        self.seats[player].human().is_some_and(|a| !a.connected())
+            && self.reconnect_timeouts[player].is_none()
+    }
+
+    /// Sends the message to every player and all spectators.
+    fn broadcast<M>(&self, msg: &M)
+    where
+        M: Message + Clone + Send + 'static,
+        M::Result: Send,
+        actor::Player: Handler<M>,
+    {
+        for (_, seat) in self.seats.iter() {
+            seat.do_send(msg.clone());
+        }
+        for spectator in &self.spectators {
+            spectator.do_send(msg.clone());
+        }
+    }
+
+    /// Groups the messages sent to everyone until `end_batch` into a single
+    /// frame, for clients which support it.
+    fn begin_batch(&self) {
+        self.broadcast(&BeginBatch);
+    }
+
+    fn end_batch(&self) {
+        self.broadcast(&EndBatch);
+    }
+
+    /// Sends the message to every player and all spectators. Clients whose
+    /// clock offset is known receive the message built by `adjusted` instead.
+    /// Syncs skip congested clients, which are synced once they catch up.
+    fn broadcast_adjusted<M, F>(&self, msg: &M, adjusted: F, is_sync: bool)
+    where
+        M: Message + Clone + Send + 'static,
+        M::Result: Send,
+        actor::Player: Handler<M>,
+        F: Fn(&SyncOptions) -> M,
+    {
+        let players = self.seats.iter().filter_map(|(_, seat)| seat.human());
+        for addr in players.chain(&self.spectators) {
+            if is_sync && self.congested.contains(addr) {
+                continue;
+            }
+            let options = self.sync_options(Some(addr));
+            if options.clock_offset.is_some() {
+                addr.do_send(adjusted(&options));
+            } else {
+                addr.do_send(msg.clone());
+            }
+        }
+    }
+
+    /// Returns the latency of every player and the clock offset of the
+    /// recipient, if any.
+    #[must_use]
+    fn sync_options(&self, recipient: Option<&Addr<actor::Player>>) -> SyncOptions {
+        let latency = self
+            .seats
+            .iter()
+            .map(|(_, seat)| self.latencies.get(seat.human()?)?.rtt())
+            .collect();
+        let clock_offset = recipient
+            .and_then(|addr| self.latencies.get(addr))
+            .and_then(LatencyTracker::clock_offset);
+        SyncOptions {
+            latency,
+            clock_offset,
         }
     }
 
-    /// Sends `OutgoingMessage::GameSync` to both players.
+    /// Returns `OutgoingMessage::GameSync` for the recipient.
+    #[must_use]
+    fn sync_message(&self, recipient: &Addr<actor::Player>) -> SerializedOutgoingMessage {
+        let options = self.sync_options(Some(recipient));
+        let msg = self.stage.outgoing_message(self.round, &options);
+        msg.into_serialized().unwrap()
+    }
+
+    /// Sends `OutgoingMessage::GameSync` to everyone.
     fn sync(&self) {
         let round = self.round;
-        let sync1 = self.stage.outgoing_message(round).into_shared().unwrap();
-        let sync2 = sync1.clone();
-        self.addrs[P1].do_send(sync1);
-        self.addrs[P2].do_send(sync2);
+        let build = |options: &SyncOptions| {
+            let msg = self.stage.outgoing_message(round, options);
+            msg.into_shared().unwrap()
+        };
+        self.broadcast_adjusted(&build(&self.sync_options(None)), build, true);
+        self.publish();
+    }
+
+    /// Returns the message `sync` would send, as an event.
+    fn current_event(&self) -> GameEvent {
+        let msg = self
+            .stage
+            .outgoing_message(self.round, &self.sync_options(None));
+        GameEvent {
+            name: msg.variant_name(),
+            data: serde_json::to_string(&msg).unwrap().into(),
+        }
+    }
+
+    /// Sends the state of the game to every event subscriber.
+    fn publish(&self) {
+        if self.events.receiver_count() > 0 {
+            let _ = self.events.send(self.current_event());
+        }
+    }
+
+    /// Sends `OutgoingMessage::GameMove` to everyone who supports it and
+    /// `OutgoingMessage::GameSync` to everyone else.
+    fn sync_move(&self) {
+        let round = self.round;
+        if self
+            .stage
+            .outgoing_move(round, &self.sync_options(None))
+            .is_none()
+        {
+            self.sync();
+            return;
+        }
+        let build = |options: &SyncOptions| {
+            let latest = self.stage.outgoing_move(round, options).unwrap();
+            VersionedOutgoingMessage {
+                latest: latest.into_shared().unwrap(),
+                fallback: self
+                    .stage
+                    .outgoing_message(round, options)
+                    .into_shared()
+                    .unwrap(),
+            }
+        };
+        self.broadcast_adjusted(&build(&self.sync_options(None)), build, true);
+        self.publish();
     }
 
-    /// Sends `OutgoingMessage::GameRestartRequest` to both players.
+    /// Sends `OutgoingMessage::GameRestartRequest` to everyone.
     fn sync_restart_request(&self, player: Player) {
         let req = &self.restart_requests[player];
         let player_req = req.as_ref().map(RestartRequest::to_outgoing);
-        let msg1 = OutgoingMessage::game_restart_request(player, player_req)
+        let msg = OutgoingMessage::game_restart_request(player, player_req)
+            .into_shared()
+            .unwrap();
+        self.broadcast(&msg);
+    }
+
+    /// Sends `OutgoingMessage::GameTimingRequest` to everyone.
+    fn sync_timing_request(&self, player: Player) {
+        let req = self.timing_requests[player].as_ref();
+        let msg = OutgoingMessage::game_timing_request(player, req.map(TimingRequest::to_outgoing))
             .into_shared()
             .unwrap();
-        let msg2 = msg1.clone();
-        self.addrs[P1].do_send(msg1);
-        self.addrs[P2].do_send(msg2);
+        self.broadcast(&msg);
+    }
+
+    /// Sends `OutgoingMessage::GameHistory` to everyone.
+    fn sync_history(&self) {
+        let GameStage::InGame(InGameStage { game, .. }) = &self.stage else {
+            return;
+        };
+        let msg = OutgoingMessage::game_history(self.round, game.moves(), &self.annotations);
+        self.broadcast(&msg.into_shared().unwrap());
+    }
+
+    /// Sends `OutgoingMessage::GameSetup` with the role and reconnect token
+    /// to the player. The viewer token is only sent if the game is
+    /// registered, since snapshots are looked up by lobby ID.
+    fn send_role(&self, player: Player) {
+        let viewer_token = self.registry.as_ref().map(|_| self.viewer_token.as_str());
+        let setup = OutgoingGameSetup::new(Some(&self.config), Some(player.into()))
+            .seed(self.seed)
+            .reconnect_token(&self.reconnect_tokens[player])
+            .viewer_token(viewer_token)
+            .profiles(self.seat_profiles());
+        let msg = OutgoingMessage::from(setup).into_serialized().unwrap();
+        self.seats[player].do_send(msg);
+    }
+
+    /// Sends the complete state of the game to the player.
+    fn resync(&self, player: Player) {
+        let addr = &self.seats[player];
+        self.send_role(player);
+        if let Some(human) = addr.human() {
+            addr.do_send(self.sync_message(human));
+        }
+        for msg in self.restart_request_messages() {
+            addr.do_send(msg);
+        }
+    }
+
+    /// Returns `OutgoingMessage::GameSetup` sent to spectators.
+    fn spectator_setup(&self) -> OutgoingMessage<'_> {
+        let setup = OutgoingGameSetup::new(Some(&self.config), Some(Role::Spectator))
+            .seed(self.seed)
+            .profiles(self.seat_profiles());
+        OutgoingMessage::from(setup)
+    }
+
+    /// Sends the complete state of the game to the spectator, including
+    /// their position in the queue.
+    fn resync_spectator(&self, addr: &Addr<actor::Player>) {
+        addr.do_send(self.spectator_setup().into_serialized().unwrap());
+        addr.do_send(self.sync_message(addr));
+        for msg in self.restart_request_messages() {
+            addr.do_send(msg);
+        }
+        if let Some(i) = self.queue.iter().position(|a| a == addr) {
+            let msg = OutgoingMessage::GameQueue {
+                position: Some(i + 1),
+            };
+            addr.do_send(msg.into_serialized().unwrap());
+        }
+    }
+
+    /// Returns `OutgoingMessage::GameRestartRequest` and
+    /// `OutgoingMessage::GameTimingRequest` for every pending request.
+    fn restart_request_messages(&self) -> Vec<SerializedOutgoingMessage> {
+        let restarts = self.restart_requests.iter().filter_map(|(p, req)| {
+            let req = req.as_ref()?;
+            let msg = OutgoingMessage::game_restart_request(p, Some(req.to_outgoing()));
+            Some(msg.into_serialized().unwrap())
+        });
+        let timings = self.timing_requests.iter().filter_map(|(p, req)| {
+            let req = req.as_ref()?;
+            let msg = OutgoingMessage::game_timing_request(p, Some(req.to_outgoing()));
+            Some(msg.into_serialized().unwrap())
+        });
+        restarts.chain(timings).collect()
     }
 
-    /// Sends `OutgoingMessage::GameSetup` containing the current configuration.
+    /// Sends `OutgoingMessage::GameSetup` containing the current configuration
+    /// and profiles.
     fn sync_config(&self) {
-        let msg = OutgoingMessage::game_setup(Some(&self.config), None);
-        let msg1 = msg.into_shared().unwrap();
-        let msg2 = msg1.clone();
-        self.addrs[P1].do_send(msg1);
-        self.addrs[P2].do_send(msg2);
+        let setup = OutgoingGameSetup::new(Some(&self.config), None)
+            .seed(self.seed)
+            .profiles(self.seat_profiles());
+        let msg = OutgoingMessage::from(setup).into_shared().unwrap();
+        self.broadcast(&msg);
     }
 
     /// Applies configuration from the restart request.
@@ -262,6 +1042,10 @@ impl Game {
         self.dismiss_duplicate_restart_requests(ctx);
         ctx.cancel_future(req.handle);
         if let Some(config) = req.config {
+            if let Some(seed) = config.seed.filter(|s| self.config.seed != Some(*s)) {
+                self.seed = seed;
+                self.rng = StdRng::seed_from_u64(seed);
+            }
             self.config = config;
             self.sync_config();
         }
@@ -277,301 +1061,1802 @@ impl Game {
         self.sync_restart_request(player);
     }
 
-    /// Deletes the restart request made by player 1.
-    fn on_p1_request_timeout(&mut self, _: &mut Context<Self>) {
-        self.restart_requests[P1].take();
-        self.sync_restart_request(P1);
+    /// Records that the player accepted the restart request of `requester`.
+    /// Returns true once every other player still in the game has.
+    fn accept_restart_vote(&mut self, requester: Player, player: Player) -> bool {
+        let Some(req) = self.restart_requests[requester].as_mut() else {
+            return false;
+        };
+        if !req.accepted.contains(&player) {
+            req.accepted.push(player);
+        }
+        self.restart_requests[requester]
+            .as_ref()
+            .is_some_and(|req| self.is_accepted(requester, &req.accepted))
     }
 
-    /// Deletes the restart request made by player 2.
-    fn on_p2_request_timeout(&mut self, _: &mut Context<Self>) {
-        self.restart_requests[P2].take();
-        self.sync_restart_request(P2);
+    /// Records that the player accepted the timing request of `requester`.
+    /// Returns true once every other player still in the game has.
+    fn accept_timing_vote(&mut self, requester: Player, player: Player) -> bool {
+        let Some(req) = self.timing_requests[requester].as_mut() else {
+            return false;
+        };
+        if !req.accepted.contains(&player) {
+            req.accepted.push(player);
+        }
+        self.timing_requests[requester]
+            .as_ref()
+            .is_some_and(|req| self.is_accepted(requester, &req.accepted))
     }
 
-    /// Creates a new restart request.
+    /// Returns whether everyone but the player who made a request and the
+    /// players who left is among those who accepted it.
     #[must_use]
-    fn create_restart_request(
-        duration: Duration,
-        player: Player,
-        config: Option<GameConfig>,
-        ctx: &mut Context<Self>,
-    ) -> RestartRequest {
-        let handle = match player {
-            P1 => ctx.run_later(duration, Self::on_p1_request_timeout),
-            P2 => ctx.run_later(duration, Self::on_p2_request_timeout),
+    fn is_accepted(&self, requester: Player, accepted: &[Player]) -> bool {
+        self.seats
+            .players()
+            .all(|p| p == requester || accepted.contains(&p) || self.has_left(p))
+    }
+
+    /// Checks the config of a restart or timing request, which has to keep
+    /// the amount of players.
+    fn check_config(&self, config: &GameConfig) -> Result<(), ConfigRejection> {
+        if config.players != self.config.players {
+            return Err(ConfigRejection {
+                field: ConfigField::Players,
+                reason: ConfigRejectionReason::Invalid,
+            });
+        }
+        self.cfg.game_limits.check(config)
+    }
+
+    /// Switches to the turn timer of the request. The turn in progress keeps
+    /// its timeout, the new timing applies from the next turn on.
+    fn accept_timing_request(&mut self, player: Player, ctx: &mut Context<Self>) {
+        let Some(req) = self.timing_requests[player].take() else {
+            return;
+        };
+        ctx.cancel_future(req.handle);
+        self.config = req.config;
+        self.sync_timing_request(player);
+        self.sync_config();
+        debug!("Changed the turn timer");
+    }
+
+    /// Dismisses the timing requests of every player.
+    fn cancel_timing_requests(&mut self, ctx: &mut Context<Self>) {
+        for p in Player::all(self.config.players) {
+            let p = *p;
+            if let Some(req) = self.timing_requests[p].take() {
+                ctx.cancel_future(req.handle);
+                self.sync_timing_request(p);
+            }
+        }
+    }
+
+    /// Deletes the timing request made by the player.
+    fn on_timing_request_timeout(&mut self, player: Player) {
+        self.timing_requests[player].take();
+        self.sync_timing_request(player);
+    }
+
+    /// Deletes the restart request made by the player.
+    fn on_request_timeout(&mut self, player: Player) {
+        self.restart_requests[player].take();
+        self.sync_restart_request(player);
+    }
+
+    /// Frees the seat of the player, who did not reconnect in time.
+    fn on_reconnect_timeout(&mut self, player: Player, ctx: &mut Context<Self>) {
+        let _span = self.span.clone().entered();
+        self.reconnect_timeouts[player] = None;
+        debug!("{player:?} did not reconnect in time");
+        self.forfeit(player, ResultReason::Abandonment, ctx);
+        self.take_over_seat(player, ctx);
+    }
+
+    /// Takes the player out of the round in progress for the reason. Two
+    /// player games end with a loss for them, larger games go on without
+    /// them until a single player is left.
+    fn forfeit(&mut self, player: Player, reason: ResultReason, ctx: &mut Context<Self>) {
+        let players_away = self.players_away();
+        let GameStage::InGame(InGameStage {
+            game,
+            clock,
+            timeout,
+            ..
+        }) = &mut self.stage
+        else {
+            return;
+        };
+        let current = game.state().player;
+        match game.leave(player, reason, unix_millis()) {
+            Ok(events) if !events.is_empty() => (),
+            _ => return,
+        }
+
+        let now = Instant::now();
+        if game.state().result.is_some() {
+            clock.clear();
+        } else if game.state().player != current {
+            clock.end_turn(current, false, &self.config, now);
+            clock.start_turn(game.state().player, players_away, &self.config, now);
+        }
+        Self::update_timeout(timeout, clock, &self.cfg.time_warnings, ctx);
+        debug!("{player:?} left the round: {reason:?}");
+        if self.stage.is_game_over() {
+            self.on_game_over(ctx);
+        }
+        self.sync();
+        self.sync_history();
+        self.request_bot_move(ctx);
+    }
+
+    /// Gives the seat of a player who left to the first queued spectator and
+    /// restarts the game. If nobody is waiting, larger games finish the round
+    /// without the player, and the game ends otherwise.
+    fn take_over_seat(&mut self, player: Player, ctx: &mut Context<Self>) {
+        if self.queue.is_empty() {
+            let in_progress =
+                matches!(self.stage, GameStage::InGame(_)) && !self.stage.is_game_over();
+            if self.seats.len() > 2 && in_progress {
+                self.forfeit(player, ResultReason::Abandonment, ctx);
+                if !self.stage.is_game_over() {
+                    return;
+                }
+            }
+            ctx.stop();
+            return;
+        }
+
+        self.free_seat(player);
+        self.seat_next_in_queue(player, ctx);
+        self.restart(ctx);
+        debug!("Queued spectator took over as {player:?}");
+    }
+
+    /// Forgets the player who left the seat.
+    fn free_seat(&mut self, player: Player) {
+        if let Seat::Human(addr) = &self.seats[player] {
+            self.player_ids.remove(addr);
+            self.profiles.remove(addr);
+            self.latencies.remove(addr);
+            self.congested.remove(addr);
+        }
+    }
+
+    /// Moves the loser of the round to the back of the queue and gives their
+    /// seat to the first queued spectator. Does nothing if a new round has
+    /// started in the meantime or nobody is waiting.
+    fn replace_loser(&mut self, round: u32, ctx: &mut Context<Self>) {
+        let _span = self.span.clone().entered();
+        let GameStage::InGame(InGameStage { game, .. }) = &self.stage else {
+            return;
+        };
+        // Winner stays games are played between two players
+        let winner = game.state().result.as_ref().map(|r| r.winner);
+        let Some(loser) = winner.and_then(GameWinner::player).map(|w| w.other()) else {
+            return;
+        };
+        if self.round != round || self.queue.is_empty() {
+            return;
+        }
+        let Some(addr) = self.seats[loser].human().cloned() else {
+            return;
+        };
+
+        if let Some(handle) = self.reconnect_timeouts[loser].take() {
+            ctx.cancel_future(handle);
+        }
+        self.seat_next_in_queue(loser, ctx);
+        self.restart(ctx);
+        if addr.connected() {
+            addr.do_send(self.spectator_setup().into_serialized().unwrap());
+            self.spectators.push(addr.clone());
+            self.queue.push_back(addr);
+            self.sync_queue();
+        }
+        debug!("{loser:?} lost and was replaced by a queued spectator");
+    }
+
+    /// Seats the first queued spectator in place of the player, to be
+    /// followed by restarting the game.
+    fn seat_next_in_queue(&mut self, player: Player, ctx: &mut Context<Self>) {
+        let Some(next) = self.queue.pop_front() else {
+            return;
         };
+
+        self.spectators.retain(|s| s != &next);
+        self.seats[player] = Seat::Human(next);
+        self.reconnect_tokens[player] = token::generate();
+        self.last_seqs[player] = None;
+        self.chat_limiters[player] = ChatLimiter::default();
+        self.hover_limits[player] = Self::hover_limit();
+        self.cancel_restart_requests(ctx);
+
+        self.send_role(player);
+        self.sync_queue();
+    }
+
+    /// Dismisses the restart requests of every player.
+    fn cancel_restart_requests(&mut self, ctx: &mut Context<Self>) {
+        for p in Player::all(self.config.players) {
+            let p = *p;
+            if let Some(req) = self.restart_requests[p].take() {
+                ctx.cancel_future(req.handle);
+                self.sync_restart_request(p);
+            }
+        }
+    }
+
+    /// Sends `OutgoingMessage::GameQueue` to everyone in the queue.
+    fn sync_queue(&self) {
+        for (i, addr) in self.queue.iter().enumerate() {
+            let msg = OutgoingMessage::GameQueue {
+                position: Some(i + 1),
+            };
+            addr.do_send(msg.into_serialized().unwrap());
+        }
+    }
+
+    /// Waits for the player to reconnect while the turn timer is paused, or
+    /// frees their seat if reconnecting is disabled or the game is not
+    /// registered.
+    fn on_player_disconnected(&mut self, player: Player, ctx: &mut Context<Self>) {
+        let grace_period = self.cfg.reconnect_grace_period;
+        if grace_period.is_zero() || self.registry.is_none() {
+            self.take_over_seat(player, ctx);
+            return;
+        }
+
+        if self.reconnect_timeouts[player].is_some() {
+            return;
+        }
+
+        let handle = ctx.run_later(grace_period, move |act, ctx| {
+            act.on_reconnect_timeout(player, ctx);
+        });
+        self.reconnect_timeouts[player] = Some(handle);
+        debug!("Waiting for {player:?} to reconnect");
+
+        if self.pause_timeout(ctx) {
+            self.sync();
+        }
+        let round = self.round;
+        let deadline = Utc::now()
+            + chrono::Duration::from_std(grace_period).unwrap_or_else(|_| chrono::Duration::zero());
+        let build = |options: &SyncOptions| {
+            let deadline = options.clock_offset.map_or(deadline, |o| deadline + o);
+            let msg = OutgoingMessage::GamePlayerDisconnected {
+                round,
+                player,
+                timeout: deadline.format(ISO_8601_TIMESTAMP).to_string(),
+            };
+            msg.into_shared().unwrap()
+        };
+        self.broadcast_adjusted(&build(&self.sync_options(None)), build, false);
+        self.notify(Notice::PlayerDisconnected { player });
+    }
+
+    /// Announces the event in the chat of every player and all spectators,
+    /// if chat is enabled.
+    fn notify(&self, notice: Notice) {
+        if self.cfg.chat_max_length > 0 {
+            self.broadcast(&SendNotice::now(notice));
+        }
+    }
+
+    /// Returns whether a player is disconnected and may still reconnect.
+    #[must_use]
+    fn players_away(&self) -> bool {
+        self.reconnect_timeouts
+            .iter()
+            .any(|(_, handle)| handle.is_some())
+    }
+
+    /// Stops the turn timer and charges the time spent so far to the
+    /// player's clock. Returns `false` if no timer was running.
+    fn pause_timeout(&mut self, ctx: &mut Context<Self>) -> bool {
+        let GameStage::InGame(InGameStage {
+            game,
+            clock,
+            timeout,
+            ..
+        }) = &mut self.stage
+        else {
+            return false;
+        };
+        if !clock.pause(game.state().player, Instant::now()) {
+            return false;
+        }
+
+        Self::update_timeout(timeout, clock, &self.cfg.time_warnings, ctx);
+        debug!("Paused the turn timer");
+        true
+    }
+
+    /// Restarts the paused turn timer once every player is connected.
+    /// Players get at least `TIME_PER_TURN_MIN` to finish their turn.
+    /// Returns `false` if the timer was not paused.
+    fn resume_timeout(&mut self, ctx: &mut Context<Self>) -> bool {
+        if self.players_away() {
+            return false;
+        }
+        let GameStage::InGame(InGameStage {
+            game,
+            clock,
+            timeout,
+            ..
+        }) = &mut self.stage
+        else {
+            return false;
+        };
+        if !clock.resume(game.state().player, &self.config, Instant::now()) {
+            return false;
+        }
+
+        Self::update_timeout(timeout, clock, &self.cfg.time_warnings, ctx);
+        debug!("Resumed the turn timer");
+        true
+    }
+
+    /// Creates a new restart request.
+    #[must_use]
+    fn create_restart_request(
+        duration: Duration,
+        player: Player,
+        config: Option<GameConfig>,
+        ctx: &mut Context<Self>,
+    ) -> RestartRequest {
+        let handle = ctx.run_later(duration, move |act, _| act.on_request_timeout(player));
         let timeout =
             chrono::Duration::from_std(duration).unwrap_or_else(|_| chrono::Duration::zero());
         let timestamp = Utc::now() + timeout;
         RestartRequest {
             config,
+            accepted: Vec::new(),
             handle,
             timestamp,
         }
     }
 
-    /// Dismisses restart requests that do not change the current config.
-    fn dismiss_duplicate_restart_requests(&mut self, ctx: &mut Context<Self>) {
-        for player in [P1, P2] {
-            let Some(req) = self.restart_requests[player].as_ref() else {
+    /// Dismisses restart requests that do not change the current config.
+    fn dismiss_duplicate_restart_requests(&mut self, ctx: &mut Context<Self>) {
+        for player in Player::all(self.config.players) {
+            let player = *player;
+            let Some(req) = self.restart_requests[player].as_ref() else {
+                continue;
+            };
+            let req_config = req.config.as_ref();
+            if req_config.is_some_and(|c| c == &self.config) {
+                let req = self.restart_requests[player].take().unwrap();
+                ctx.cancel_future(req.handle);
+                self.sync_restart_request(player);
+            }
+        }
+    }
+
+    /// Dismisses the previous request and creates a new one.
+    fn update_restart_request(
+        &mut self,
+        config: Option<GameConfig>,
+        player: Player,
+        ctx: &mut Context<Self>,
+    ) {
+        if let Some(req) = self.restart_requests[player].take() {
+            ctx.cancel_future(req.handle);
+        }
+        self.restart_requests[player] = Some(Self::create_restart_request(
+            self.cfg.restart_request_timeout,
+            player,
+            config,
+            ctx,
+        ));
+        self.sync_restart_request(player);
+        self.reset_idle_timeout(ctx);
+    }
+
+    /// Restarts the countdown to closing the game. Called whenever a player
+    /// acts, even if the turn timer does so for them.
+    fn reset_idle_timeout(&mut self, ctx: &mut Context<Self>) {
+        if let Some(handle) = self.idle_timeout.take() {
+            ctx.cancel_future(handle);
+        }
+        let timeout = self.cfg.game_idle_timeout;
+        if timeout.is_zero() {
+            return;
+        }
+
+        let warning = IDLE_WARNING.min(timeout);
+        self.idle_timeout = Some(ctx.run_later(
+            timeout.saturating_sub(warning),
+            move |act, ctx| {
+                act.warn_idle(warning);
+                act.idle_timeout = Some(ctx.run_later(warning, |act, ctx| {
+                    let _span = act.span.clone().entered();
+                    debug!("No player acted for too long, game shutting down");
+                    act.abandoned = true;
+                    ctx.stop();
+                }));
+            },
+        ));
+    }
+
+    /// Sends `OutgoingMessage::GameIdleWarning` to everyone.
+    fn warn_idle(&self, remaining: Duration) {
+        let round = self.round;
+        let deadline = Utc::now()
+            + chrono::Duration::from_std(remaining).unwrap_or_else(|_| chrono::Duration::zero());
+        let build = |options: &SyncOptions| {
+            let deadline = options.clock_offset.map_or(deadline, |o| deadline + o);
+            let msg = OutgoingMessage::GameIdleWarning {
+                round,
+                timeout: deadline.format(ISO_8601_TIMESTAMP).to_string(),
+            };
+            msg.into_shared().unwrap()
+        };
+        self.broadcast_adjusted(&build(&self.sync_options(None)), build, false);
+    }
+
+    /// Called when the time has ran out.
+    fn on_timeout(&mut self, ctx: &mut Context<Self>) {
+        let _span = self.span.clone().entered();
+        let GameStage::InGame(InGameStage {
+            game,
+            clock,
+            timeout,
+            ..
+        }) = &mut self.stage
+        else {
+            return;
+        };
+        let player = game.state().player;
+        match clock.expire(player, &self.config, Instant::now()) {
+            Expiry::Skip => {
+                let turn = game.state().turn;
+                let _ = self.end_turn(player, turn, None, ctx);
+                return;
+            }
+            Expiry::Period => {
+                Self::update_timeout(timeout, clock, &self.cfg.time_warnings, ctx);
+                debug!("{player:?} used up a byo-yomi period");
+                self.sync();
+                return;
+            }
+            Expiry::Forfeit => Self::update_timeout(timeout, clock, &self.cfg.time_warnings, ctx),
+        }
+        debug!("{player:?} ran out of time");
+        self.forfeit(player, ResultReason::Timeout, ctx);
+    }
+
+    /// Ends the turn if it belongs to the player, then starts the timer for
+    /// the next one. Succeeds without doing anything if the player has
+    /// already made the same move in that turn.
+    fn end_turn(
+        &mut self,
+        player: Player,
+        turn: u32,
+        action: Option<Action>,
+        ctx: &mut Context<Self>,
+    ) -> Result<(), EndTurnRejection> {
+        let players_away = self.players_away();
+        let GameStage::InGame(InGameStage {
+            game,
+            clock,
+            timeout,
+            can_swap,
+        }) = &mut self.stage
+        else {
+            return Err(EndTurnRejection::NotStarted);
+        };
+
+        let state = game.state();
+        if turn < state.turn {
+            let repeated = game.move_at(turn).is_some_and(|m| {
+                m.player == player
+                    && m.col == action.map(Action::col)
+                    && m.kind == action.map(Action::kind).unwrap_or_default()
+                    && m.row == action.and_then(Action::row)
+            });
+            return if repeated {
+                Ok(())
+            } else {
+                Err(EndTurnRejection::WrongTurn)
+            };
+        }
+        if state.result.is_some() {
+            return Err(EndTurnRejection::GameOver);
+        }
+        if state.player != player {
+            return Err(EndTurnRejection::NotYourTurn);
+        }
+        if state.turn != turn {
+            return Err(EndTurnRejection::WrongTurn);
+        }
+
+        game.play_at(action, unix_millis())?;
+        self.stats.record_turn(action.is_none());
+        *can_swap = self.config.pie_rule
+            && action.is_some()
+            && game.state().turn == 1
+            && game.state().result.is_none();
+
+        let now = Instant::now();
+        clock.end_turn(player, turn != 0, &self.config, now);
+        if game.state().result.is_none() {
+            clock.start_turn(game.state().player, players_away, &self.config, now);
+        }
+        Self::update_timeout(timeout, clock, &self.cfg.time_warnings, ctx);
+        if self.stage.is_game_over() {
+            self.on_game_over(ctx);
+        }
+        self.sync_move();
+        if self.stage.is_game_over() {
+            self.sync_history();
+        }
+        self.request_bot_move(ctx);
+        self.send_hint();
+        self.reset_idle_timeout(ctx);
+        Ok(())
+    }
+
+    /// Lets the player take over the first move of the opponent with the
+    /// pie rule. The players trade seats, so the opponent continues as the
+    /// other player and it becomes their turn.
+    fn swap_sides(
+        &mut self,
+        player: Player,
+        turn: u32,
+        ctx: &mut Context<Self>,
+    ) -> Result<(), EndTurnRejection> {
+        let players_away = self.players_away();
+        let GameStage::InGame(InGameStage {
+            game,
+            clock,
+            timeout,
+            can_swap,
+        }) = &mut self.stage
+        else {
+            return Err(EndTurnRejection::NotStarted);
+        };
+
+        let state = game.state();
+        if state.result.is_some() {
+            return Err(EndTurnRejection::GameOver);
+        }
+        if state.player != player {
+            return Err(EndTurnRejection::NotYourTurn);
+        }
+        if state.turn != turn {
+            return Err(EndTurnRejection::WrongTurn);
+        }
+        // Reconnect timeouts belong to a seat, so they cannot be swapped
+        if !*can_swap || players_away {
+            return Err(EndTurnRejection::SwapNotAllowed);
+        }
+        *can_swap = false;
+
+        clock.swap_sides(player, &self.config, Instant::now());
+        Self::update_timeout(timeout, clock, &self.cfg.time_warnings, ctx);
+
+        self.seats.swap();
+        self.reconnect_tokens.swap();
+        self.last_seqs.swap();
+        self.chat_limiters.swap();
+        self.hover_limits.swap();
+        self.sides_swapped = !self.sides_swapped;
+        self.cancel_restart_requests(ctx);
+        self.cancel_timing_requests(ctx);
+        debug!("{player:?} swapped sides");
+
+        self.send_role(P1);
+        self.send_role(P2);
+        self.sync_config();
+        self.sync();
+        self.request_bot_move(ctx);
+        self.send_hint();
+        self.reset_idle_timeout(ctx);
+        Ok(())
+    }
+
+    /// Adds the finished round to the statistics, the ratings, the game log
+    /// and the archive. In winner stays games, schedules replacing the loser, and
+    /// series games are ended.
+    fn on_game_over(&mut self, ctx: &mut Context<Self>) {
+        self.cancel_timing_requests(ctx);
+        let GameStage::InGame(InGameStage { game, .. }) = &mut self.stage else {
+            return;
+        };
+        let Some(winner) = game.state().result.as_ref().map(|r| r.winner) else {
+            return;
+        };
+        let duration = self
+            .stats
+            .record_result(self.round, game.state().turn, winner);
+        game.record_duration(duration, unix_millis());
+        // Borrowed again, as recording the duration needed the game mutably
+        let GameStage::InGame(InGameStage { game, .. }) = &self.stage else {
+            return;
+        };
+        let state = game.state();
+        let Some(result) = &state.result else {
+            return;
+        };
+        self.record_ratings(result.winner);
+        if let Some(game_log) = self.round_log.clone() {
+            let summary = RoundSummary {
+                finished: Utc::now().format(ISO_8601_TIMESTAMP).to_string(),
+                lobby: self.registry.as_ref().map(|(id, _)| id.to_string()),
+                round: self.round,
+                winner: result.winner,
+                moves: state.turn,
+                duration,
+                config: self.config.clone(),
+            };
+            let span = self.span.clone();
+            tokio::task::spawn_blocking(move || {
+                let _span = span.entered();
+                if let Err(e) = game_log.write(&summary) {
+                    error!("Failed to write to the game log: {e}");
+                }
+            });
+        }
+        if let Some(webhooks) = &self.webhooks {
+            webhooks.send(&WebhookEvent::GameEnded {
+                game: self.registry.as_ref().map(|(id, _)| id.to_string()),
+                round: self.round,
+                players: self.seat_player_ids(),
+                winner: result.winner,
+                moves: state.turn,
+            });
+        }
+        if self.winner_stays && result.winner != GameWinner::Draw {
+            let round = self.round;
+            ctx.run_later(WINNER_STAYS_DELAY, move |act, ctx| {
+                act.replace_loser(round, ctx);
+            });
+        }
+        if self.series.is_some() {
+            ctx.run_later(SERIES_RESULT_DELAY, |_, ctx| ctx.stop());
+        }
+        if self.seats.players().any(|p| self.has_left(p)) {
+            ctx.run_later(WINNER_STAYS_DELAY, Self::fill_vacant_seats);
+        }
+
+        let Some(archive) = self.archive.clone() else {
+            return;
+        };
+        let record = ArchivedGame {
+            lobby: self.registry.as_ref().map(|(id, _)| id.to_string()),
+            round: self.round,
+            finished: Utc::now().format(ISO_8601_TIMESTAMP).to_string(),
+            game: game.clone(),
+            replay: Replay::new(self.config.clone(), game)
+                .with_annotations(self.annotations.clone()),
+            notation: self.cfg.archive.notation.then(|| game.to_notation()),
+        };
+        let span = self.span.clone();
+        tokio::task::spawn_blocking(move || {
+            let _span = span.entered();
+            if let Err(e) = archive.store(&record) {
+                error!("Failed to archive game: {e}");
+            }
+        });
+    }
+
+    /// Gives the seats of the players who left during the round to queued
+    /// spectators and restarts the game, or ends it if not enough
+    /// spectators are waiting.
+    fn fill_vacant_seats(&mut self, ctx: &mut Context<Self>) {
+        let _span = self.span.clone().entered();
+        let vacant: Vec<_> = self.seats.players().filter(|p| self.has_left(*p)).collect();
+        if vacant.is_empty() {
+            return;
+        }
+        if self.queue.len() < vacant.len() {
+            debug!("Not enough spectators to take over the seats of players who left");
+            ctx.stop();
+            return;
+        }
+
+        for player in vacant {
+            self.free_seat(player);
+            self.seat_next_in_queue(player, ctx);
+        }
+        self.restart(ctx);
+        debug!("Queued spectators took over the seats of players who left");
+    }
+
+    /// Returns the winner of a series game, as listed in its pairing.
+    /// Unfinished games are won by the player who is still connected, or
+    /// drawn.
+    fn series_winner(&self) -> GameWinner {
+        let result = match &self.stage {
+            GameStage::InGame(InGameStage { game, .. }) => game.state().result.as_ref(),
+            GameStage::PlayerSelection(_) => None,
+        };
+        // Series games are played between two players
+        let connected = [P1, P2].map(|p| self.seats[p].human().is_some_and(Addr::connected));
+        let winner = match (result, connected) {
+            (Some(result), _) => result.winner,
+            (None, [true, false]) => GameWinner::P1,
+            (None, [false, true]) => GameWinner::P2,
+            (None, _) => GameWinner::Draw,
+        };
+
+        match winner {
+            GameWinner::P1 if self.sides_swapped => GameWinner::P2,
+            GameWinner::P2 if self.sides_swapped => GameWinner::P1,
+            winner => winner,
+        }
+    }
+
+    /// Returns the IDs of the players in every seat, if they have one.
+    fn seat_player_ids(&self) -> Vec<Option<String>> {
+        self.seats
+            .iter()
+            .map(|(_, seat)| {
+                seat.human()
+                    .and_then(|addr| self.player_ids.get(addr))
+                    .cloned()
+            })
+            .collect()
+    }
+
+    /// Tells the webhooks that a round has started.
+    fn report_start(&self) {
+        let Some(webhooks) = &self.webhooks else {
+            return;
+        };
+        webhooks.send(&WebhookEvent::GameStarted {
+            game: self.registry.as_ref().map(|(id, _)| id.to_string()),
+            round: self.round,
+            config: self.config.clone(),
+            players: self.seat_player_ids(),
+        });
+    }
+
+    /// Updates the ratings of every player and sends them
+    /// `OutgoingMessage::RatingUpdate`. Games against the bot, or where
+    /// any player has no ID, are not rated.
+    fn record_ratings(&self, winner: GameWinner) {
+        let Some(ratings) = self.ratings.clone() else {
+            return;
+        };
+        let Some(addrs) = self
+            .seats
+            .iter()
+            .map(|(_, seat)| seat.human().cloned())
+            .collect::<Option<Vec<_>>>()
+        else {
+            return;
+        };
+        let Some(ids) = addrs
+            .iter()
+            .map(|addr| self.player_ids.get(addr).cloned())
+            .collect::<Option<Vec<_>>>()
+        else {
+            return;
+        };
+        if ids.iter().collect::<HashSet<_>>().len() != ids.len() {
+            debug!("Players share an ID, the game is not rated");
+            return;
+        }
+
+        let span = self.span.clone();
+        tokio::task::spawn_blocking(move || {
+            let _span = span.entered();
+            let ids: Vec<_> = ids.iter().map(String::as_str).collect();
+            let changes = match ratings.record(&ids, winner) {
+                Ok(changes) => changes,
+                Err(e) => {
+                    error!("Failed to record ratings: {e}");
+                    return;
+                }
+            };
+            for (addr, change) in addrs.iter().zip(changes) {
+                let msg = OutgoingMessage::RatingUpdate {
+                    rating: change.rating.rating,
+                    change: change.change,
+                    games: change.rating.games,
+                };
+                addr.do_send(msg.into_serialized().unwrap());
+            }
+        });
+    }
+
+    /// Sends `OutgoingMessage::GameHint` to the player whose turn it is, if
+    /// hints are enabled.
+    fn send_hint(&self) {
+        let depth = self.cfg.hint_depth;
+        let GameStage::InGame(InGameStage { game, .. }) = &self.stage else {
+            return;
+        };
+        // The analysis only knows how to score games between two players
+        if depth == 0 || game.state().result.is_some() || self.seats.len() != 2 {
+            return;
+        }
+        let player = game.state().player;
+        let Seat::Human(addr) = &self.seats[player] else {
+            return;
+        };
+
+        let (moves, score) = analysis::best_moves(game, depth);
+        let msg = OutgoingMessage::GameHint {
+            round: self.round,
+            turn: game.state().turn,
+            col: moves.first().copied(),
+            score,
+        };
+        addr.do_send(msg.into_serialized().unwrap());
+    }
+
+    /// Asks the bot for a move if it is its turn.
+    fn request_bot_move(&mut self, ctx: &mut Context<Self>) {
+        let GameStage::InGame(InGameStage { game, .. }) = &self.stage else {
+            return;
+        };
+        if game.state().result.is_some() {
+            return;
+        }
+        let Seat::Bot(difficulty, bot) = &self.seats[game.state().player] else {
+            return;
+        };
+
+        bot.do_send(ComputeMove {
+            game: game.clone(),
+            round: self.round,
+            difficulty: *difficulty,
+            seed: self.rng.gen(),
+            reply_to: ctx.address(),
+        });
+    }
+
+    /// Bots let the other players decide who starts.
+    fn cast_bot_votes(&mut self) {
+        let GameStage::PlayerSelection(stage) = &mut self.stage else {
+            return;
+        };
+        for (player, seat) in self.seats.iter() {
+            if let Seat::Bot(..) = seat {
+                stage.votes[player].get_or_insert(false);
+            }
+        }
+    }
+
+    /// Schedules a timeout for the timer of the clock, cancelling the one
+    /// scheduled for a previous timer. Does nothing if the timer has not
+    /// changed.
+    fn update_timeout(
+        timeout: &mut Option<TurnTimeout>,
+        clock: &TurnClock,
+        warnings: &[Duration],
+        ctx: &mut Context<Self>,
+    ) {
+        let timer = clock.timer(Instant::now());
+        if timeout.as_ref().map(|t| t.id) == timer.map(|t| t.id) {
+            return;
+        }
+        if let Some(timeout) = timeout.take() {
+            ctx.cancel_future(timeout.handle);
+            for handle in timeout.warnings {
+                ctx.cancel_future(handle);
+            }
+        }
+        let Some(timer) = timer else {
+            return;
+        };
+
+        let duration = timer.remaining;
+        let handle = ctx.run_later(duration, Self::on_timeout);
+        let warnings = warnings
+            .iter()
+            .copied()
+            .filter(|remaining| *remaining < duration)
+            .filter_map(|remaining| {
+                let delay = duration.checked_sub(remaining)?;
+                Some(ctx.run_later(delay, move |act, _| act.warn_time(remaining)))
+            })
+            .collect();
+        *timeout = Some(TurnTimeout {
+            id: timer.id,
+            handle,
+            warnings,
+        });
+    }
+
+    /// Lets everyone know how much time the current player has left.
+    fn warn_time(&self, remaining: Duration) {
+        let GameStage::InGame(InGameStage { game, .. }) = &self.stage else {
+            return;
+        };
+
+        let msg = OutgoingMessage::GameTimeWarning {
+            round: self.round,
+            player: game.state().player,
+            remaining_ms: remaining.as_millis() as u64,
+        };
+        self.broadcast(&msg.into_shared().unwrap());
+    }
+
+    /// Restarts the game.
+    fn restart(&mut self, ctx: &mut Context<Self>) {
+        if let GameStage::InGame(InGameStage { clock, timeout, .. }) = &mut self.stage {
+            clock.clear();
+            Self::update_timeout(timeout, clock, &self.cfg.time_warnings, ctx);
+        }
+        self.dismiss_duplicate_restart_requests(ctx);
+        self.cancel_timing_requests(ctx);
+        self.stage = PlayerSelectionStage::new(self.config.players).into();
+        self.annotations.clear();
+        self.round = self.round.wrapping_add(1);
+        self.span.record("round", self.round);
+        self.cast_bot_votes();
+        self.sync();
+        self.reset_idle_timeout(ctx);
+        debug!("Restarted");
+    }
+}
+
+impl Actor for Game {
+    type Context = actix::Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        use player::PlayerController::Game;
+        let _span = self.span.clone().entered();
+        let attached = self.seats.iter().all(|(_, seat)| {
+            seat.human().is_none_or(|addr| {
+                addr.try_send(AttachController(Game(ctx.address()), self.span.clone()))
+                    .is_ok()
+            })
+        });
+        if !attached {
+            // every controller must be registered successfully in order for WsGame to work properly
+            debug!("Failed to attach controller, shutting down");
+            ctx.stop();
+            return;
+        }
+
+        if let Some((id, router)) = &self.registry {
+            router.do_send(RegisterGame {
+                id: *id,
+                game: ctx.address(),
+            });
+        }
+
+        for player in self.seats.players() {
+            self.send_role(player);
+        }
+
+        self.spectators.retain(|s| {
+            s.try_send(AttachController(Game(ctx.address()), self.span.clone()))
+                .is_ok()
+        });
+        if !self.spectators.is_empty() {
+            let spectator_msg = self.spectator_setup().into_shared().unwrap();
+            for spectator in &self.spectators {
+                spectator.do_send(spectator_msg.clone());
+            }
+        }
+        self.cast_bot_votes();
+        self.sync();
+        self.request_bot_move(ctx);
+        self.send_hint();
+        self.reset_idle_timeout(ctx);
+        debug!("Started");
+    }
+
+    fn stopped(&mut self, _: &mut Self::Context) {
+        let _span = self.span.clone().entered();
+        debug!("Shutting down");
+        if let Some((id, router)) = &self.registry {
+            router.do_send(RemoveGame(*id));
+        }
+        let reason = if self.abandoned {
+            Disconnect::GameAbandoned
+        } else {
+            Disconnect::GameEnded
+        };
+        // Players of a series return to the lobby if it is still open
+        if let Some((lobby, pairing)) = self.series.take().filter(|(l, _)| l.connected()) {
+            lobby.do_send(SeriesGameOver {
+                pairing,
+                winner: self.series_winner(),
+                players: self
+                    .seats
+                    .iter()
+                    .filter_map(|(_, seat)| seat.human().cloned())
+                    .collect(),
+            });
+        } else {
+            for (_, seat) in self.seats.iter() {
+                seat.do_send(reason);
+            }
+        }
+        for spectator in &self.spectators {
+            spectator.do_send(reason);
+        }
+    }
+}
+
+impl Drop for Game {
+    fn drop(&mut self) {
+        if !supervisor::panicked() {
+            return;
+        }
+
+        let _span = self.span.clone().entered();
+        error!("Panicked, disconnecting everyone");
+        if let Some((id, router)) = &self.registry {
+            router.do_send(RemoveGame(*id));
+        }
+        for (_, seat) in self.seats.iter() {
+            seat.do_send(Disconnect::InternalError);
+        }
+        for spectator in &self.spectators {
+            spectator.do_send(Disconnect::InternalError);
+        }
+    }
+}
+
+impl Handler<Disconnected> for Game {
+    type Result = ();
+
+    fn handle(&mut self, msg: Disconnected, ctx: &mut Self::Context) {
+        let _span = self.span.clone().entered();
+        let Some(addr) = msg.0.upgrade() else {
+            for player in Player::all(self.config.players) {
+                let player = *player;
+                if self.seats[player].human().is_some_and(|a| !a.connected()) {
+                    self.on_player_disconnected(player, ctx);
+                }
+            }
+            return;
+        };
+
+        if self.spectators.contains(&addr) {
+            self.spectators.retain(|s| s != &addr);
+            self.player_ids.remove(&addr);
+            self.profiles.remove(&addr);
+            self.latencies.remove(&addr);
+            self.congested.remove(&addr);
+            if self.queue.contains(&addr) {
+                self.queue.retain(|s| s != &addr);
+                self.sync_queue();
+            }
+            debug!("Spectator left");
+        } else if let Some(player) = self.get_player(&addr) {
+            self.on_player_disconnected(player, ctx);
+        }
+    }
+}
+
+impl Handler<Identify> for Game {
+    type Result = ();
+
+    fn handle(&mut self, msg: Identify, _: &mut Self::Context) {
+        let _span = self.span.clone().entered();
+        self.player_ids.insert(msg.addr, msg.player_id);
+    }
+}
+
+impl Handler<Authenticated> for Game {
+    type Result = ();
+
+    fn handle(&mut self, msg: Authenticated, _: &mut Self::Context) {
+        let _span = self.span.clone().entered();
+        debug!(subject = msg.subject, "Player authenticated");
+        self.player_ids
+            .insert(msg.addr, auth::rating_id(&msg.subject));
+    }
+}
+
+impl Handler<UpdateLatency> for Game {
+    type Result = ();
+
+    fn handle(&mut self, msg: UpdateLatency, _: &mut Self::Context) {
+        let _span = self.span.clone().entered();
+        self.latencies.insert(msg.addr, msg.latency);
+    }
+}
+
+impl Handler<Backpressure> for Game {
+    type Result = ();
+
+    fn handle(&mut self, msg: Backpressure, _: &mut Self::Context) {
+        let _span = self.span.clone().entered();
+        let addr = msg.addr;
+        if self.get_player(&addr).is_none() && !self.spectators.contains(&addr) {
+            return;
+        }
+
+        if msg.congested {
+            self.congested.insert(addr);
+        } else if self.congested.remove(&addr) {
+            addr.do_send(self.sync_message(&addr));
+        }
+    }
+}
+
+impl Handler<UpdateProfile> for Game {
+    type Result = ();
+
+    fn handle(&mut self, msg: UpdateProfile, _: &mut Self::Context) {
+        let _span = self.span.clone().entered();
+        let seated = self.get_player(&msg.addr).is_some();
+        let changed = self.profiles.get(&msg.addr) != Some(&msg.profile);
+        self.profiles.insert(msg.addr, msg.profile);
+        if seated && changed {
+            self.sync_config();
+        }
+    }
+}
+
+impl Handler<PlayerSelectionVote> for Game {
+    type Result = ();
+
+    fn handle(&mut self, msg: PlayerSelectionVote, ctx: &mut Self::Context) {
+        let _span = self.span.clone().entered();
+        let Some(player) = self.get_player(&msg.player) else {
+            return;
+        };
+        if self.is_duplicate(player, msg.seq) {
+            return;
+        }
+        let GameStage::PlayerSelection(stage) = &mut self.stage else {
+            return;
+        };
+
+        if stage.votes[player].is_some() {
+            return;
+        }
+        stage.votes[player] = Some(msg.wants_to_start);
+
+        let votes = stage
+            .votes
+            .iter()
+            .map(|(_, vote)| *vote)
+            .collect::<Option<_>>();
+        if let Some(votes) = votes {
+            self.stage = InGameStage::from_votes(&votes, &self.config, &mut self.rng).into();
+            self.stats.start_round();
+            self.report_start();
+        }
+
+        self.sync();
+        self.request_bot_move(ctx);
+        self.send_hint();
+        self.reset_idle_timeout(ctx);
+    }
+}
+
+impl Handler<EndTurn> for Game {
+    type Result = ();
+
+    fn handle(&mut self, msg: EndTurn, ctx: &mut Self::Context) {
+        let _span = self.span.clone().entered();
+        let Some(player) = self.get_player(&msg.player) else {
+            return;
+        };
+        if self.is_duplicate(player, msg.seq) {
+            return;
+        }
+        self.begin_batch();
+        let msg = match self.end_turn(player, msg.turn, msg.action, ctx) {
+            Ok(()) => OutgoingMessage::GameEndTurnAccepted {
+                round: self.round,
+                turn: msg.turn,
+            },
+            Err(reason) => {
+                debug!("{player:?} could not end turn {}: {reason:?}", msg.turn);
+                OutgoingMessage::GameEndTurnRejected {
+                    round: self.round,
+                    turn: msg.turn,
+                    reason,
+                }
+            }
+        };
+        self.seats[player].do_send(msg.into_serialized().unwrap());
+        self.end_batch();
+    }
+}
+
+impl Handler<SwapSides> for Game {
+    type Result = ();
+
+    fn handle(&mut self, msg: SwapSides, ctx: &mut Self::Context) {
+        let _span = self.span.clone().entered();
+        let Some(player) = self.get_player(&msg.addr) else {
+            return;
+        };
+        if self.is_duplicate(player, msg.seq) {
+            return;
+        }
+        // The reply goes to the sender, who no longer sits in `player` once
+        // the sides are swapped
+        self.begin_batch();
+        let reply = match self.swap_sides(player, msg.turn, ctx) {
+            Ok(()) => OutgoingMessage::GameEndTurnAccepted {
+                round: self.round,
+                turn: msg.turn,
+            },
+            Err(reason) => {
+                debug!("{player:?} could not swap sides: {reason:?}");
+                OutgoingMessage::GameEndTurnRejected {
+                    round: self.round,
+                    turn: msg.turn,
+                    reason,
+                }
+            }
+        };
+        msg.addr.do_send(reply.into_serialized().unwrap());
+        self.end_batch();
+    }
+}
+
+impl Handler<BotMove> for Game {
+    type Result = ();
+
+    fn handle(&mut self, msg: BotMove, ctx: &mut Self::Context) {
+        let _span = self.span.clone().entered();
+        let GameStage::InGame(InGameStage { game, .. }) = &self.stage else {
+            return;
+        };
+        let player = game.state().player;
+        if msg.round != self.round || !matches!(self.seats[player], Seat::Bot(..)) {
+            return;
+        }
+        self.begin_batch();
+        let _ = self.end_turn(player, msg.turn, msg.col.map(Action::Drop), ctx);
+        self.end_batch();
+    }
+}
+
+impl Handler<Restart> for Game {
+    type Result = ();
+
+    fn handle(&mut self, msg: Restart, ctx: &mut Self::Context) {
+        let _span = self.span.clone().entered();
+        let Restart { addr, partial, seq } = msg;
+        let Some(player) = self.get_player(&addr) else {
+            return;
+        };
+        if self.series.is_some() {
+            debug!("Series games cannot be restarted");
+            return;
+        }
+        if self.is_duplicate(player, seq) {
+            return;
+        }
+        self.begin_batch();
+        if let Some(partial) = partial {
+            let mut config = self.config.clone();
+            config.apply_partial(&partial);
+            if let Err(rejection) = self.check_config(&config) {
+                debug!("Rejected restart request with an invalid config");
+                addr.do_send(RejectConfig(rejection));
+                self.end_batch();
+                return;
+            }
+
+            if self.config == config {
+                if self.stage.is_game_over() {
+                    self.restart(ctx);
+                } else {
+                    self.update_restart_request(None, player, ctx);
+                }
+            } else {
+                self.update_restart_request(Some(config), player, ctx);
+            }
+        } else if self.stage.is_game_over() {
+            self.restart(ctx);
+        } else {
+            self.update_restart_request(None, player, ctx);
+        }
+
+        let bot_opponents = self
+            .seats
+            .iter()
+            .all(|(p, seat)| p == player || matches!(seat, Seat::Bot(..)));
+        if bot_opponents && self.restart_requests[player].is_some() {
+            self.accept_restart_request(player, ctx);
+            self.restart(ctx);
+        }
+        self.end_batch();
+    }
+}
+
+impl Handler<RestartResponse> for Game {
+    type Result = ();
+
+    fn handle(&mut self, msg: RestartResponse, ctx: &mut Self::Context) {
+        let _span = self.span.clone().entered();
+        let Some(player) = self.get_player(&msg.addr) else {
+            return;
+        };
+        if self.is_duplicate(player, msg.seq) {
+            return;
+        }
+        self.begin_batch();
+        for requester in Player::all(self.config.players) {
+            let requester = *requester;
+            if requester == player {
                 continue;
-            };
-            let req_config = req.config.as_ref();
-            if req_config.map_or(false, |c| c == &self.config) {
-                let req = self.restart_requests[player].take().unwrap();
-                ctx.cancel_future(req.handle);
-                self.sync_restart_request(player);
+            }
+            if !msg.accepted {
+                self.reject_restart_request(requester, ctx);
+            } else if self.accept_restart_vote(requester, player) {
+                self.accept_restart_request(requester, ctx);
+                self.restart(ctx);
+                break;
             }
         }
+        self.end_batch();
     }
+}
 
-    /// Dismisses the previous request and creates a new one.
-    fn update_restart_request(
-        &mut self,
-        config: Option<GameConfig>,
-        player: Player,
-        ctx: &mut Context<Self>,
-    ) {
-        if let Some(req) = self.restart_requests[player].take() {
+impl Handler<ChangeTiming> for Game {
+    type Result = ();
+
+    fn handle(&mut self, msg: ChangeTiming, ctx: &mut Self::Context) {
+        let _span = self.span.clone().entered();
+        let ChangeTiming { addr, timing } = msg;
+        let Some(player) = self.get_player(&addr) else {
+            return;
+        };
+        if self.series.is_some() {
+            debug!("Timing of series games cannot be changed");
+            return;
+        }
+        if self.is_duplicate(player, timing.seq) {
+            return;
+        }
+        if !matches!(&self.stage, GameStage::InGame(_)) || self.stage.is_game_over() {
+            debug!("Timing can only be changed during a round");
+            return;
+        }
+
+        let config = timing.apply(&self.config);
+        let checked = self.check_config(&config).and_then(|()| {
+            // Byo-yomi periods are only handed out when the round starts
+            let enabled = |c: &GameConfig| c.time_per_turn >= TIME_PER_TURN_MIN;
+            if config.timer_mode == TimerMode::ByoYomi && enabled(&config) != enabled(&self.config)
+            {
+                return Err(ConfigRejection {
+                    field: ConfigField::TimePerTurn,
+                    reason: ConfigRejectionReason::Invalid,
+                });
+            }
+            Ok(())
+        });
+        if let Err(rejection) = checked {
+            debug!("Rejected timing request with an invalid config");
+            addr.do_send(RejectConfig(rejection));
+            return;
+        }
+        if let Some(req) = self.timing_requests[player].take() {
             ctx.cancel_future(req.handle);
         }
-        self.restart_requests[player] = Some(Self::create_restart_request(
-            self.cfg.restart_request_timeout,
-            player,
+        if config == self.config {
+            self.sync_timing_request(player);
+            return;
+        }
+
+        let duration = self.cfg.restart_request_timeout;
+        let handle = ctx.run_later(duration, move |act, _| {
+            act.on_timing_request_timeout(player);
+        });
+        let timeout = TimeDelta::from_std(duration).unwrap_or_else(|_| TimeDelta::zero());
+        self.timing_requests[player] = Some(TimingRequest {
             config,
-            ctx,
-        ));
-        self.sync_restart_request(player);
+            accepted: Vec::new(),
+            handle,
+            timestamp: Utc::now() + timeout,
+        });
+        self.sync_timing_request(player);
+        self.reset_idle_timeout(ctx);
+
+        let bot_opponents = self
+            .seats
+            .iter()
+            .all(|(p, seat)| p == player || matches!(seat, Seat::Bot(..)));
+        if bot_opponents {
+            self.accept_timing_request(player, ctx);
+        }
     }
+}
 
-    /// Called when the time has ran out.
-    fn on_timeout(&mut self, ctx: &mut Context<Self>) {
-        let GameStage::InGame(InGameStage { game, .. }) = &self.stage else {
+impl Handler<ChangeTimingResponse> for Game {
+    type Result = ();
+
+    fn handle(&mut self, msg: ChangeTimingResponse, ctx: &mut Self::Context) {
+        let _span = self.span.clone().entered();
+        let Some(player) = self.get_player(&msg.addr) else {
             return;
         };
-        let msg = EndTurn {
-            col: None,
-            player: Addr::clone(&self.addrs[game.state().player]),
-            turn: game.state().turn,
-        };
-        Self::handle(self, msg, ctx);
+        if self.is_duplicate(player, msg.seq) {
+            return;
+        }
+        for requester in Player::all(self.config.players) {
+            let requester = *requester;
+            if requester == player {
+                continue;
+            }
+            if !msg.accepted {
+                if let Some(req) = self.timing_requests[requester].take() {
+                    ctx.cancel_future(req.handle);
+                    self.sync_timing_request(requester);
+                }
+            } else if self.accept_timing_vote(requester, player) {
+                self.accept_timing_request(requester, ctx);
+            }
+        }
     }
+}
 
-    /// Returns the amount of time the current turn should take, or `0`
-    /// if timer is disabled.
-    #[must_use]
-    fn get_timeout_duration(extra_time: Duration, config: &GameConfig) -> Duration {
-        let GameConfig {
-            time_per_turn,
-            time_cap,
-            ..
-        } = *config;
+impl Handler<Chat> for Game {
+    type Result = ();
 
-        if time_per_turn < TIME_PER_TURN_MIN {
-            return Duration::ZERO;
+    fn handle(&mut self, msg: Chat, _: &mut Self::Context) {
+        let _span = self.span.clone().entered();
+        let Some(player) = self.get_player(&msg.addr) else {
+            return;
+        };
+        let Some(text) = chat::sanitize(&msg.text, self.cfg.chat_max_length) else {
+            debug!("Rejected chat message from {player:?}");
+            return;
+        };
+        if !self.chat_limiters[player].try_send() {
+            debug!("{player:?} is sending chat messages too quickly");
+            return;
         }
 
-        let time_cap = time_cap.max(time_per_turn);
-        (extra_time + time_per_turn).min(time_cap)
+        let profile = self.profiles.get(&msg.addr);
+        let msg = OutgoingMessage::game_chat(player, &text, profile)
+            .into_shared()
+            .unwrap();
+        self.broadcast(&msg);
     }
+}
 
-    /// Starts a timeout, if there is none.
-    fn start_timeout(
-        timeout: &mut Option<TurnTimeout>,
-        duration: Duration,
-        ctx: &mut Context<Self>,
-    ) {
-        if timeout.is_some() || duration < TIME_PER_TURN_MIN {
+impl Handler<Annotate> for Game {
+    type Result = ();
+
+    fn handle(&mut self, msg: Annotate, _: &mut Self::Context) {
+        let _span = self.span.clone().entered();
+        let GameStage::InGame(InGameStage { game, .. }) = &self.stage else {
             return;
-        }
+        };
+        let Some(annotated) = game.move_at(msg.turn) else {
+            debug!("Rejected annotation of turn {}", msg.turn);
+            return;
+        };
 
-        let handle = ctx.run_later(duration, Self::on_timeout);
-        let duration_chrono =
-            chrono::Duration::from_std(duration).unwrap_or_else(|_| chrono::Duration::zero());
+        let authors = self.cfg.annotations;
+        let player = self.get_player(&msg.addr);
+        let allowed = match player {
+            // Players only comment on the moves of their opponent
+            Some(player) => authors.allows_opponents() && annotated.player != player,
+            None => authors.allows_spectators() && self.spectators.contains(&msg.addr),
+        };
+        if !allowed {
+            debug!("Rejected annotation from {player:?}");
+            return;
+        }
+        if self.annotations.len() >= MAX_ANNOTATIONS {
+            debug!("Too many annotations");
+            return;
+        }
+        let Some(text) = chat::sanitize(&msg.text, ANNOTATION_MAX_LENGTH) else {
+            debug!("Rejected annotation from {player:?}");
+            return;
+        };
 
-        timeout.replace(TurnTimeout {
-            handle,
-            chrono: Utc::now() + duration_chrono,
-            instant: Instant::now() + duration,
-        });
+        let annotation = Annotation {
+            turn: msg.turn,
+            text: text.into_owned(),
+            player,
+            name: self.profiles.get(&msg.addr).map(|p| p.name.clone()),
+            timestamp: unix_millis(),
+        };
+        let out = OutgoingMessage::GameAnnotation {
+            round: self.round,
+            annotation: Cow::Borrowed(&annotation),
+        };
+        self.broadcast(&out.into_shared().unwrap());
+        self.annotations.push(annotation);
     }
+}
 
-    /// Clears timeout and returns how much time remained until it would fire.
-    fn clear_timeout(timeout: &mut Option<TurnTimeout>, ctx: &mut Context<Self>) -> Duration {
-        let Some(timeout) = timeout.take() else {
-            return Duration::ZERO;
-        };
+impl Handler<JoinInProgress> for Game {
+    type Result = ();
 
-        ctx.cancel_future(timeout.handle);
-        timeout.instant - Instant::now()
-    }
+    fn handle(&mut self, JoinInProgress(addr): JoinInProgress, ctx: &mut Self::Context) {
+        use player::PlayerController::Game;
+        let _span = self.span.clone().entered();
 
-    /// Restarts the game.
-    fn restart(&mut self, ctx: &mut Context<Self>) {
-        if let GameStage::InGame(InGameStage { timeout, .. }) = &mut self.stage {
-            Self::clear_timeout(timeout, ctx);
+        if addr
+            .try_send(AttachController(Game(ctx.address()), self.span.clone()))
+            .is_err()
+        {
+            debug!("Failed to attach controller to a late joiner");
+            return;
+        }
+
+        let options = OutgoingMessage::GameInProgress {
+            options: Cow::Borrowed(&[LateJoinOption::Spectate, LateJoinOption::Queue]),
         };
-        self.dismiss_duplicate_restart_requests(ctx);
-        self.stage = PlayerSelectionStage::new().into();
-        self.round = self.round.wrapping_add(1);
-        self.sync();
-        debug!("Restarted");
+        addr.do_send(options.into_serialized().unwrap());
+        addr.do_send(self.spectator_setup().into_serialized().unwrap());
+        addr.do_send(self.sync_message(&addr));
+        self.spectators.push(addr);
+        debug!("Late joiner is spectating");
     }
 }
 
-impl Actor for Game {
-    type Context = actix::Context<Self>;
+impl Handler<Queue> for Game {
+    type Result = ();
 
-    fn started(&mut self, ctx: &mut Self::Context) {
-        use player::PlayerController::Game;
-        let res1 = self.addrs[P1].try_send(AttachController(Game(ctx.address())));
-        let res2 = self.addrs[P2].try_send(AttachController(Game(ctx.address())));
-        if res1.is_err() || res2.is_err() {
-            // both controller must be registered successfully in order for WsGame to work properly
-            debug!("Failed to attach controller, shutting down");
-            ctx.stop();
+    fn handle(&mut self, msg: Queue, _: &mut Self::Context) {
+        let _span = self.span.clone().entered();
+        if !self.spectators.contains(&msg.addr) {
             return;
         }
 
-        let p1_role_msg = OutgoingMessage::game_setup(Some(&self.config), Some(P1))
-            .into_serialized()
-            .unwrap();
-        let p2_role_msg = OutgoingMessage::game_setup(Some(&self.config), Some(P2))
-            .into_serialized()
-            .unwrap();
-        self.addrs[P1].do_send(p1_role_msg);
-        self.addrs[P2].do_send(p2_role_msg);
-        self.sync();
-        debug!("Started");
+        let queued = self.queue.contains(&msg.addr);
+        if msg.queued && !queued {
+            self.queue.push_back(msg.addr);
+        } else if !msg.queued && queued {
+            self.queue.retain(|s| s != &msg.addr);
+            let position = OutgoingMessage::GameQueue { position: None };
+            msg.addr.do_send(position.into_serialized().unwrap());
+        } else {
+            return;
+        }
+        self.sync_queue();
     }
+}
 
-    fn stopped(&mut self, _: &mut Self::Context) {
-        debug!("Shutting down");
-        self.addrs[P1].do_send(Disconnect::GameEnded);
-        self.addrs[P2].do_send(Disconnect::GameEnded);
+impl Handler<RequestSync> for Game {
+    type Result = ();
+
+    fn handle(&mut self, msg: RequestSync, _: &mut Self::Context) {
+        let _span = self.span.clone().entered();
+        let addr = msg.0;
+        if self.get_player(&addr).is_none() && !self.spectators.contains(&addr) {
+            return;
+        }
+
+        addr.do_send(self.sync_message(&addr));
     }
 }
 
-impl Handler<Disconnected> for Game {
+impl Handler<RequestFullSync> for Game {
     type Result = ();
 
-    fn handle(&mut self, _: Disconnected, ctx: &mut Self::Context) {
-        ctx.stop();
+    fn handle(&mut self, RequestFullSync(addr): RequestFullSync, _: &mut Self::Context) {
+        let _span = self.span.clone().entered();
+        if let Some(player) = self.get_player(&addr) {
+            self.resync(player);
+        } else if self.spectators.contains(&addr) {
+            self.resync_spectator(&addr);
+        }
     }
 }
 
-impl Handler<PlayerSelectionVote> for Game {
+impl Handler<Hover> for Game {
     type Result = ();
 
-    fn handle(&mut self, msg: PlayerSelectionVote, _: &mut Self::Context) {
-        let GameStage::PlayerSelection(stage) = &mut self.stage else {
+    fn handle(&mut self, msg: Hover, _: &mut Self::Context) {
+        let _span = self.span.clone().entered();
+        let Some(player) = self.get_player(&msg.addr) else {
             return;
         };
-
-        let update_p1 = msg.player == self.addrs[P1] && stage.p1_vote.is_none();
-        let update_p2 = msg.player == self.addrs[P2] && stage.p2_vote.is_none();
-        if !(update_p1 || update_p2) {
+        let GameStage::InGame(InGameStage { game, .. }) = &self.stage else {
+            return;
+        };
+        let state = game.state();
+        if state.result.is_some() || state.player != player {
             return;
         }
-
-        if update_p1 {
-            stage.p1_vote = Some(msg.wants_to_start);
+        let (width, _) = game.rules().field_size();
+        if msg.col.is_some_and(|col| col >= width) || !self.hover_limits[player].try_take() {
+            return;
         }
 
-        if update_p2 {
-            stage.p2_vote = Some(msg.wants_to_start);
+        let msg = OutgoingMessage::OpponentHover {
+            round: self.round,
+            turn: state.turn,
+            col: msg.col,
         }
-
-        if let PlayerSelectionStage {
-            p1_vote: Some(p1_vote),
-            p2_vote: Some(p2_vote),
-            ..
-        } = *stage
-        {
-            self.stage = InGameStage::from_votes(p1_vote, p2_vote, &self.config).into();
+        .into_shared()
+        .unwrap();
+        // Hovers are not worth filling the queue of a slow reader
+        let recipients = self.seats.iter().filter(|(p, seat)| {
+            *p != player
+                && seat
+                    .human()
+                    .is_none_or(|addr| !self.congested.contains(addr))
+        });
+        for (_, seat) in recipients {
+            seat.do_send(msg.clone());
         }
-
-        self.sync();
     }
 }
 
-impl Handler<EndTurn> for Game {
+impl Handler<Resign> for Game {
     type Result = ();
 
-    fn handle(&mut self, msg: EndTurn, ctx: &mut Self::Context) {
-        let GameStage::InGame(InGameStage { game, .. }) = &self.stage else {
+    fn handle(&mut self, msg: Resign, ctx: &mut Self::Context) {
+        let _span = self.span.clone().entered();
+        let Some(player) = self.get_player(&msg.addr) else {
             return;
         };
-
-        let state = game.state();
-        let player = state.player;
-        let turn = state.turn;
-        if !(msg.player == self.addrs[player] && turn == msg.turn) {
+        if self.is_duplicate(player, msg.seq) {
             return;
         }
+        self.forfeit(player, ResultReason::Resignation, ctx);
+    }
+}
 
-        let GameStage::InGame(InGameStage {
-            game,
-            extra_time,
-            timeout,
-        }) = &mut self.stage
-        else {
-            return;
+impl Handler<GetGameInfo> for Game {
+    type Result = MessageResult<GetGameInfo>;
+
+    fn handle(&mut self, _: GetGameInfo, _: &mut Self::Context) -> Self::Result {
+        let _span = self.span.clone().entered();
+        let turn = match &self.stage {
+            GameStage::PlayerSelection(_) => None,
+            GameStage::InGame(InGameStage { game, .. }) => Some(game.state().turn),
         };
+        let connected = self
+            .seats
+            .iter()
+            .map(|(_, seat)| seat.human().is_none_or(Addr::connected))
+            .collect();
+        MessageResult(GameInfo {
+            id: self.registry.as_ref().map(|(id, _)| id.to_string()),
+            round: self.round,
+            turn,
+            game_over: self.stage.is_game_over(),
+            connected,
+            spectators: self.spectators.len(),
+            queued: self.queue.len(),
+        })
+    }
+}
 
-        if game.end_turn(msg.col).is_err() {
-            return;
-        }
+impl Handler<Shutdown> for Game {
+    type Result = ();
+
+    fn handle(&mut self, _: Shutdown, ctx: &mut Self::Context) {
+        let _span = self.span.clone().entered();
+        debug!("Game shutting down");
+        ctx.stop();
+    }
+}
+
+impl Handler<GetStats> for Game {
+    type Result = Option<GameStats>;
+
+    fn handle(&mut self, msg: GetStats, _: &mut Self::Context) -> Self::Result {
+        let _span = self.span.clone().entered();
+        let host_token = self.host_token.as_ref()?;
+        token::matches(host_token, &msg.token).then(|| self.stats.stats())
+    }
+}
 
-        let time_remaining = Self::clear_timeout(timeout, ctx);
-        if turn != 0 {
-            extra_time[player] = time_remaining;
+impl Handler<GetSnapshot> for Game {
+    type Result = Option<GameSnapshot>;
+
+    fn handle(&mut self, msg: GetSnapshot, _: &mut Self::Context) -> Self::Result {
+        let _span = self.span.clone().entered();
+        if !token::matches(&self.viewer_token, &msg.token) {
+            return None;
         }
-        if game.state().result.is_none() {
-            let extra_time = extra_time[game.state().player];
-            let duration = Self::get_timeout_duration(extra_time, &self.config);
-            Self::start_timeout(timeout, duration, ctx);
+
+        let (game, timeout, clocks) = match &self.stage {
+            GameStage::PlayerSelection(_) => (None, None, None),
+            GameStage::InGame(stage) => (
+                Some(stage.game.clone()),
+                stage
+                    .timeout_for(None)
+                    .map(|t| t.format(ISO_8601_TIMESTAMP).to_string()),
+                stage.clocks_remaining(),
+            ),
+        };
+        Some(GameSnapshot {
+            round: self.round,
+            config: self.config.clone(),
+            game,
+            timeout,
+            clocks,
+            profiles: self
+                .seat_profiles()
+                .into_iter()
+                .map(<Option<&Profile>>::cloned)
+                .collect(),
+        })
+    }
+}
+
+impl Handler<SubscribeEvents> for Game {
+    type Result = Option<EventSubscription>;
+
+    fn handle(&mut self, msg: SubscribeEvents, _: &mut Self::Context) -> Self::Result {
+        let _span = self.span.clone().entered();
+        if !token::matches(&self.viewer_token, &msg.token) {
+            return None;
         }
-        self.sync();
+
+        debug!("Event subscriber added");
+        Some(EventSubscription {
+            initial: self.current_event(),
+            events: self.events.subscribe(),
+        })
     }
 }
 
-impl Handler<Restart> for Game {
+impl Handler<Rejoin> for Game {
     type Result = ();
 
-    fn handle(&mut self, Restart { addr, partial }: Restart, ctx: &mut Self::Context) {
-        let player = self.get_player(&addr).unwrap();
-        if let Some(partial) = partial {
-            let mut config = self.config.clone();
-            config.apply_partial(&partial);
-            if self.config == config {
-                if self.stage.is_game_over() {
-                    self.restart(ctx);
-                } else {
-                    self.update_restart_request(None, player, ctx);
-                }
-            } else {
-                self.update_restart_request(Some(config), player, ctx);
-            }
-        } else if self.stage.is_game_over() {
-            self.restart(ctx);
+    fn handle(&mut self, msg: Rejoin, ctx: &mut Self::Context) {
+        use player::PlayerController::Game;
+        let _span = self.span.clone().entered();
+
+        let player = self.seats.players().find(|p| {
+            self.seats[*p].human().is_some()
+                && token::matches(&self.reconnect_tokens[*p], &msg.token)
+        });
+        let Some(player) = player else {
+            msg.player.do_send(Disconnect::ReconnectFailed);
+            debug!("Rejected reconnect attempt with an invalid token");
+            return;
+        };
+
+        if msg
+            .player
+            .try_send(AttachController(Game(ctx.address()), self.span.clone()))
+            .is_err()
+        {
+            debug!("Failed to attach controller to a reconnecting player");
+            return;
+        }
+
+        let was_away = if let Some(handle) = self.reconnect_timeouts[player].take() {
+            ctx.cancel_future(handle);
+            true
         } else {
-            self.update_restart_request(None, player, ctx);
+            false
+        };
+        let resumed = self.resume_timeout(ctx);
+
+        // The new connection reports its own ID, profile and latency
+        self.free_seat(player);
+        let old = std::mem::replace(&mut self.seats[player], Seat::Human(msg.player));
+        if old.human().is_some_and(Addr::connected) {
+            old.do_send(Disconnect::Replaced);
+        }
+
+        self.resync(player);
+        if was_away {
+            let msg = OutgoingMessage::GamePlayerReconnected {
+                round: self.round,
+                player,
+            };
+            self.broadcast(&msg.into_shared().unwrap());
+            self.notify(Notice::PlayerReconnected { player });
         }
+        if resumed {
+            self.sync();
+        }
+        debug!("{player:?} reconnected");
     }
 }
 
-impl Handler<RestartResponse> for Game {
+impl Handler<ReloadConfig> for Game {
     type Result = ();
 
-    fn handle(&mut self, msg: RestartResponse, ctx: &mut Self::Context) {
-        let opponent = self.get_player(&msg.addr).unwrap().other();
-        if msg.accepted {
-            self.accept_restart_request(opponent, ctx);
-            self.restart(ctx);
-        } else {
-            self.reject_restart_request(opponent, ctx);
-        }
+    fn handle(&mut self, msg: ReloadConfig, _: &mut Self::Context) {
+        self.cfg = msg.0;
     }
 }
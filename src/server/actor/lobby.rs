@@ -1,46 +1,277 @@
 use std::{
-    collections::HashMap,
+    borrow::Cow,
+    collections::{BTreeSet, HashMap, HashSet},
+    net::IpAddr,
     sync::Arc,
     time::{Duration, Instant},
 };
 
 use actix::prelude::*;
-use log::debug;
+use chrono::Utc;
 use rand::{rngs::ThreadRng, thread_rng, Rng};
+use serde::Serialize;
+use tracing::{debug, error, info_span, warn, Span};
 use uuid::Uuid;
 
-use crate::game::Player;
-use crate::server::actor::{self, player};
-use crate::server::AppConfig;
-use actor::lobby_router::RemoveLobby;
+use crate::game::{self, GameWinner, Player};
+use crate::server::actor::{self, player, supervisor};
+use crate::server::archive::GameArchive;
+use crate::server::ban::BanTarget;
+use crate::server::chat::{self, ChatLimiter};
+use crate::server::extra_time;
+use crate::server::game_log::GameLog;
+use crate::server::load::Load;
+use crate::server::profile::Profile;
+use crate::server::protocol::{
+    ConfigField, ConfigRejection, ConfigRejectionReason, Disconnect, IncomingPickPlayer, Notice,
+    OutgoingMessage, PickRejection, QrCodes, ISO_8601_TIMESTAMP,
+};
+use crate::server::rating::RatingStore;
+use crate::server::reload::ReloadConfig;
+use crate::server::series::{Pairing, Series};
+use crate::server::token;
+use crate::server::webhook::Webhooks;
+use crate::server::{AppConfig, GameConfig, PartialGameConfig, PlayerArray};
+use actor::game::{JoinInProgress, Queue};
+use actor::lobby_router::{ChangeLobbyId, PublicLobby, RemoveLobby, UpdatePublicLobby};
 use player::{
-    AttachController, Disconnect, Disconnected, IncomingPickPlayer, OutgoingMessage,
-    PlayerController,
+    AttachController, Authenticated, Backpressure, Chat, Disconnected, PlayerController,
+    QrOutgoingMessage, RejectConfig, RequestFullSync, SendNotice, SharedOutgoingMessage,
+    UpdateProfile,
 };
+use supervisor::StartCatchingPanics;
 
 const PLAYER_LIST_SYNC_DEBOUNCE: Duration = Duration::from_secs(1);
+/// Longest lobby password accepted from clients.
+pub const PASSWORD_MAX_LENGTH: usize = 64;
 
 #[derive(Message)]
 #[rtype(result = "()")]
-pub struct ConnectPlayer(pub Addr<actor::Player>);
+pub struct ConnectPlayer {
+    pub player: Addr<actor::Player>,
+    /// Password sent by the player, checked if the lobby has one.
+    pub password: Option<String>,
+    /// Address of the client, `None` if it is unknown.
+    pub ip: Option<IpAddr>,
+    /// User the client authenticated as, see `server::auth`.
+    pub subject: Option<String>,
+}
 
 #[derive(Message)]
 #[rtype(result = "()")]
 pub struct Shutdown;
 
+/// Lists or unlists the lobby in `GET /lobbies`. Ignored unless sent by the host.
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct SetPublic {
+    pub addr: Addr<actor::Player>,
+    pub public: bool,
+    pub config: PartialGameConfig,
+}
+
+/// Sets or removes the password needed to join the lobby. Ignored unless
+/// sent by the host.
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct SetPassword {
+    pub addr: Addr<actor::Player>,
+    pub password: Option<String>,
+}
+
+/// Sets the configuration picks in the lobby start from. Ignored unless sent
+/// by the host.
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct SetConfig {
+    pub addr: Addr<actor::Player>,
+    pub config: PartialGameConfig,
+}
+
+/// Tells the host whether the player is ready to be picked. Ignored if
+/// sent by the host.
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct SetReady {
+    pub addr: Addr<actor::Player>,
+    pub ready: bool,
+}
+
+/// Disconnects the player with the code, also keeping its address and user
+/// from joining again if `block` is set. Ignored unless sent by the host.
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct KickPlayer {
+    pub addr: Addr<actor::Player>,
+    pub code: u8,
+    pub block: bool,
+}
+
+/// Gives the lobby a new ID, so that the old invite stops working. Ignored
+/// unless sent by the host.
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct RegenerateInvite {
+    pub addr: Addr<actor::Player>,
+}
+
+/// Sent by the router with the join code of the lobby's new invite, after
+/// its ID changed.
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct SetJoinCode(pub String);
+
+/// Sends the current invite to the host again. Ignored unless sent by the
+/// host.
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct RequestLink {
+    pub addr: Addr<actor::Player>,
+}
+
+/// Starts an all-play-all series between the host and the players with the
+/// codes. Ignored unless sent by the host.
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct StartSeries {
+    pub addr: Addr<actor::Player>,
+    pub codes: Vec<u8>,
+    pub config: PartialGameConfig,
+    pub parallel: bool,
+}
+
+/// Starts a game between the host and two or three players with the codes,
+/// in turn order. Ignored unless sent by the host.
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct PickPlayers {
+    pub addr: Addr<actor::Player>,
+    pub codes: Vec<u8>,
+    pub config: PartialGameConfig,
+}
+
+/// Starts a game between the players with the codes, the first one moving
+/// first, which the host watches as a spectator. Ignored unless sent by the
+/// host.
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct PickPair {
+    pub addr: Addr<actor::Player>,
+    pub codes: [u8; 2],
+    pub config: PartialGameConfig,
+}
+
+/// Lets a waiting player watch the next game instead of being left in the
+/// lobby. Ignored unless sent by the host.
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct PickSpectator {
+    pub addr: Addr<actor::Player>,
+    pub code: u8,
+}
+
+/// Sent by a game of a series once it ends, with the players who should
+/// return to the lobby.
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct SeriesGameOver {
+    pub pairing: Pairing,
+    pub winner: GameWinner,
+    pub players: Vec<Addr<actor::Player>>,
+}
+
+/// Returns the state of the lobby for the admin API.
+#[derive(Message)]
+#[rtype(result = "LobbyInfo")]
+pub struct GetLobbyInfo;
+
+/// A lobby listed in `GET /admin/lobbies`.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LobbyInfo {
+    pub id: String,
+    /// Amount of connected players, including the host.
+    pub players: usize,
+    pub spectators: usize,
+    pub public: bool,
+    pub password_protected: bool,
+    pub created_at: String,
+    /// Subjects of the host and players who authenticated, see
+    /// `server::auth`.
+    pub authenticated: Vec<String>,
+}
+
 pub struct Lobby {
     router: Addr<actor::LobbyRouter>,
+    /// ID of the lobby, also used as the invite.
     id: Uuid,
+    /// Short code players can join with instead of the ID. Assigned by the
+    /// router, `None` while it assigns a new one.
+    join_code: Option<String>,
+    /// How many players have joined using the current invite.
+    joins: u32,
+    idle_timeout: Option<SpawnHandle>,
+    /// Whether the lobby was closed for being idle for too long.
+    expired: bool,
 
     host: Addr<actor::Player>,
     players: HashMap<u8, Addr<actor::Player>>,
+    /// When each player joined, used to pick the next host.
+    joined: HashMap<u8, Instant>,
+    /// Codes of players who will watch the game once it starts.
+    spectators: HashSet<u8>,
+    /// Codes of players who said they are ready to be picked.
+    ready: HashSet<u8>,
+    host_profile: Option<Profile>,
+    /// Whether the host's outgoing queue is congested, the player list is
+    /// not synced until it catches up.
+    host_congested: bool,
+    profiles: HashMap<u8, Profile>,
+    /// Users of the account system the host and players authenticated as,
+    /// see `server::auth`.
+    subjects: HashMap<Addr<actor::Player>, String>,
+    /// Addresses and users of the players, blocked along with them.
+    peers: HashMap<u8, Vec<BanTarget>>,
+    /// Addresses and users the host blocked, who cannot join again.
+    blocked: BTreeSet<BanTarget>,
     player_list_sync: PlayerListSync,
     rng: ThreadRng,
-    game: Option<Addr<actor::Game>>,
+    /// Whether a game was started, which everyone in the lobby moved to.
+    game_started: bool,
+    series: Option<RunningSeries>,
+    host_chat_limiter: ChatLimiter,
+    chat_limiters: HashMap<u8, ChatLimiter>,
+    /// Configuration announced in the public listing, `None` if the lobby is
+    /// private.
+    listing: Option<PartialGameConfig>,
+    /// Password players need to join, `None` if anyone can.
+    password: Option<String>,
+    /// Configuration set by the host, which the configs of picks are applied
+    /// to. `None` until the host sets one.
+    default_config: Option<GameConfig>,
+    created_at: String,
+    /// Secret sent to the host, used to read the statistics of the game.
+    host_token: String,
+    /// QR codes of the current invite.
+    qr_codes: QrCodes,
+    archive: Option<Arc<dyn GameArchive>>,
+    ratings: Option<Arc<dyn RatingStore>>,
+    webhooks: Option<Arc<Webhooks>>,
+    game_log: Option<Arc<GameLog>>,
+    /// Span every log event of the lobby and its players is recorded in.
+    span: Span,
 
     cfg: Arc<AppConfig>,
 }
 
+/// Series played in the lobby. Players stay in the lobby between games.
+struct RunningSeries {
+    series: Series,
+    /// Address of every participant, `None` once they left.
+    players: Vec<Option<Addr<actor::Player>>>,
+    config: GameConfig,
+}
+
 struct PlayerListSync {
     last_update: Instant,
     handle: Option<SpawnHandle>,
@@ -57,21 +288,91 @@ impl Lobby {
         Self {
             router,
             id,
+            join_code: None,
+            joins: 0,
+            idle_timeout: None,
+            expired: false,
             host,
             players: HashMap::new(),
+            joined: HashMap::new(),
+            spectators: HashSet::new(),
+            ready: HashSet::new(),
+            host_profile: None,
+            host_congested: false,
+            profiles: HashMap::new(),
+            subjects: HashMap::new(),
+            peers: HashMap::new(),
+            blocked: BTreeSet::new(),
             player_list_sync: PlayerListSync {
                 last_update: Instant::now(),
                 handle: None,
             },
             rng: thread_rng(),
-            game: None,
+            game_started: false,
+            series: None,
+            host_chat_limiter: ChatLimiter::default(),
+            chat_limiters: HashMap::new(),
+            listing: None,
+            password: None,
+            default_config: None,
+            created_at: Utc::now().format(ISO_8601_TIMESTAMP).to_string(),
+            host_token: token::generate(),
+            qr_codes: QrCodes::generate(id, &cfg),
+            archive: None,
+            ratings: None,
+            webhooks: None,
+            game_log: None,
+            span: info_span!("lobby", id = %id),
             cfg,
         }
     }
 
+    /// Lets players join with the code instead of the ID.
+    #[must_use]
+    pub fn with_join_code(mut self, join_code: String) -> Self {
+        self.join_code = Some(join_code);
+        self
+    }
+
+    /// Requires players to send the password to join.
+    #[must_use]
+    pub fn with_password(mut self, password: Option<String>) -> Self {
+        self.password = password;
+        self
+    }
+
+    /// Stores every finished round of the game in the archive.
+    #[must_use]
+    pub fn with_archive(mut self, archive: Option<Arc<dyn GameArchive>>) -> Self {
+        self.archive = archive;
+        self
+    }
+
+    /// Updates the ratings of the players after every round of the game.
+    #[must_use]
+    pub fn with_ratings(mut self, ratings: Option<Arc<dyn RatingStore>>) -> Self {
+        self.ratings = ratings;
+        self
+    }
+
+    /// Reports every game started from the lobby to the webhooks.
+    #[must_use]
+    pub fn with_webhooks(mut self, webhooks: Option<Arc<Webhooks>>) -> Self {
+        self.webhooks = webhooks;
+        self
+    }
+
+    /// Writes every round of the games started from the lobby to the game
+    /// log.
+    #[must_use]
+    pub fn with_game_log(mut self, game_log: Option<Arc<GameLog>>) -> Self {
+        self.game_log = game_log;
+        self
+    }
+
     #[must_use]
     fn get_id(&mut self) -> Option<u8> {
-        if self.players.len() == self.cfg.max_players {
+        if self.players.len() >= self.cfg.max_players {
             return None;
         }
 
@@ -83,11 +384,56 @@ impl Lobby {
         }
     }
 
-    fn sync_player_list(&mut self, _: &mut actix::Context<Self>) {
+    /// Sends the invite to the host, with the QR code in the format it
+    /// asked for. Sent once the router assigns a join code if it has none.
+    fn send_link(&self) {
+        let Some(join_code) = &self.join_code else {
+            return;
+        };
+        let link = |qr_code| {
+            OutgoingMessage::lobby_link(self.id, join_code, &self.host_token, qr_code)
+                .into_serialized()
+                .unwrap()
+        };
+        self.host.do_send(QrOutgoingMessage {
+            png: link(&self.qr_codes.png),
+            svg: link(&self.qr_codes.svg),
+        });
+    }
+
+    fn player_list_message(&self) -> SharedOutgoingMessage {
         let codes: Vec<u8> = self.players.keys().copied().collect();
-        let msg = OutgoingMessage::LobbySync { players: &codes }
-            .into_serialized()
-            .unwrap();
+        let spectators: Vec<u8> = self.spectators.iter().copied().collect();
+        // Picked players leave the lobby without being removed from the set
+        let ready: Vec<u8> = self
+            .ready
+            .iter()
+            .copied()
+            .filter(|code| self.players.contains_key(code))
+            .collect();
+        OutgoingMessage::LobbySync {
+            players: Cow::Borrowed(&codes),
+            spectators: Cow::Borrowed(&spectators),
+            ready: Cow::Borrowed(&ready),
+            profiles: Cow::Borrowed(&self.profiles),
+            config: self.default_config.as_ref().map(Cow::Borrowed),
+        }
+        .into_shared()
+        .unwrap()
+    }
+
+    fn sync_player_list(&mut self, _: &mut actix::Context<Self>) {
+        if self.host_congested {
+            self.player_list_sync.handle = None;
+            return;
+        }
+
+        let msg = self.player_list_message();
+        if self.default_config.is_some() {
+            for player in self.players.values() {
+                player.do_send(msg.clone());
+            }
+        }
         self.host.do_send(msg);
 
         let sync = &mut self.player_list_sync;
@@ -95,6 +441,266 @@ impl Lobby {
         sync.handle = None;
     }
 
+    /// Promotes the player who has been waiting the longest to host. Returns
+    /// false if nobody is left in the lobby.
+    fn migrate_host(&mut self, ctx: &mut actix::Context<Self>) -> bool {
+        let Some(code) = self
+            .joined
+            .iter()
+            .min_by_key(|(_, joined)| **joined)
+            .map(|(code, _)| *code)
+        else {
+            return false;
+        };
+        let Some(host) = self.players.remove(&code) else {
+            return false;
+        };
+
+        self.joined.remove(&code);
+        self.spectators.remove(&code);
+        self.ready.remove(&code);
+        self.peers.remove(&code);
+        self.chat_limiters.remove(&code);
+        self.host = host;
+        self.host_congested = false;
+        self.host_profile = self.profiles.remove(&code);
+        self.host_chat_limiter = ChatLimiter::default();
+        self.host_token = token::generate();
+
+        self.send_link();
+        if let Some(handle) = self.player_list_sync.handle.take() {
+            ctx.cancel_future(handle);
+        }
+        self.sync_player_list(ctx);
+        debug!("Player {code} is the new host");
+        true
+    }
+
+    /// Profiles of everyone in the lobby who set one, passed on to the game.
+    fn game_profiles(&self) -> Vec<(Addr<actor::Player>, Profile)> {
+        let mut profiles: Vec<_> = self
+            .profiles
+            .iter()
+            .filter_map(|(code, profile)| Some((self.players.get(code)?.clone(), profile.clone())))
+            .collect();
+        if let Some(profile) = &self.host_profile {
+            profiles.push((self.host.clone(), profile.clone()));
+        }
+        profiles
+    }
+
+    /// Tells the host why the game could not be started.
+    fn reject_pick(&self, code: u8, reason: PickRejection) {
+        let msg = OutgoingMessage::LobbyPickRejected { code, reason }
+            .into_serialized()
+            .unwrap();
+        self.host.do_send(msg);
+    }
+
+    /// Returns true if the server cannot start another game. Games of a
+    /// series which already started are always started.
+    fn is_at_game_capacity(&self) -> bool {
+        let full = Load::current().is_at_game_capacity(self.cfg.max_games);
+        if full {
+            debug!("Game could not be started: max capacity reached");
+        }
+        full
+    }
+
+    /// Updates the public listing of the lobby, if it has one.
+    fn sync_listing(&self) {
+        let Some(config) = &self.listing else {
+            return;
+        };
+
+        let lobby = PublicLobby {
+            id: self.id.to_string(),
+            players: self.players.len() + 1,
+            config: config.clone(),
+            password_protected: self.password.is_some(),
+            created_at: self.created_at.clone(),
+        };
+        self.router.do_send(UpdatePublicLobby {
+            id: self.id,
+            lobby: Some(lobby),
+        });
+    }
+
+    /// Restarts the countdown to closing the lobby. Called whenever
+    /// something happens in the lobby. The lobby does not expire while a
+    /// series is being played.
+    fn reset_idle_timeout(&mut self, ctx: &mut actix::Context<Self>) {
+        if let Some(handle) = self.idle_timeout.take() {
+            ctx.cancel_future(handle);
+        }
+        let timeout = self.cfg.lobby_idle_timeout;
+        if timeout.is_zero() || self.series.is_some() {
+            return;
+        }
+
+        self.idle_timeout = Some(ctx.run_later(timeout, |act, ctx| {
+            let _span = act.span.clone().entered();
+            debug!("Nothing happened for too long, lobby shutting down");
+            act.expired = true;
+            ctx.stop();
+        }));
+    }
+
+    /// Removes players who left from the lobby and the series, promoting a
+    /// new host if needed. Returns false if nobody is left, in which case
+    /// the lobby is stopped.
+    fn remove_departed(
+        &mut self,
+        addr: Option<&Addr<actor::Player>>,
+        ctx: &mut actix::Context<Self>,
+    ) -> bool {
+        let left = |player: &Addr<actor::Player>| !player.connected() || addr == Some(player);
+        let host_left = left(&self.host);
+
+        let departed: Vec<u8> = self
+            .players
+            .iter()
+            .filter(|(_, player)| left(player))
+            .map(|(code, _)| *code)
+            .collect();
+        self.players.retain(|_, player| !left(player));
+        self.joined
+            .retain(|code, _| self.players.contains_key(code));
+        self.spectators
+            .retain(|code| self.players.contains_key(code));
+        self.ready.retain(|code| self.players.contains_key(code));
+        self.peers.retain(|code, _| self.players.contains_key(code));
+        self.chat_limiters
+            .retain(|code, _| self.players.contains_key(code));
+        self.profiles
+            .retain(|code, _| self.players.contains_key(code));
+        self.subjects.retain(|player, _| !left(player));
+        if let Some(running) = &mut self.series {
+            for (i, player) in running.players.iter_mut().enumerate() {
+                if player.as_ref().is_some_and(left) {
+                    *player = None;
+                    running.series.withdraw(i);
+                    debug!("Participant {i} left the series");
+                }
+            }
+        }
+
+        if host_left {
+            if !self.migrate_host(ctx) {
+                debug!("Host has disconnected and nobody is left; lobby shutting down");
+                ctx.stop();
+                return false;
+            }
+        } else {
+            self.schedule_player_list_sync(ctx);
+        }
+        for code in departed {
+            self.notify(Notice::PlayerLeft { code });
+        }
+        true
+    }
+
+    /// Announces the event in the chat of everyone in the lobby, if chat is
+    /// enabled.
+    fn notify(&self, notice: Notice) {
+        if self.cfg.chat_max_length == 0 {
+            return;
+        }
+        let msg = SendNotice::now(notice);
+        self.host.do_send(msg.clone());
+        for player in self.players.values() {
+            player.do_send(msg.clone());
+        }
+    }
+
+    /// Returns `OutgoingMessage::SeriesStandings`, if a series was started.
+    fn standings_message(&self) -> Option<SharedOutgoingMessage> {
+        let series = &self.series.as_ref()?.series;
+        let msg = OutgoingMessage::SeriesStandings {
+            standings: series.standings(),
+            remaining: series.remaining(),
+            finished: series.is_finished(),
+        };
+        Some(msg.into_shared().unwrap())
+    }
+
+    /// Sends `OutgoingMessage::SeriesStandings` to everyone in the lobby.
+    fn sync_standings(&self) {
+        let Some(msg) = self.standings_message() else {
+            return;
+        };
+        self.host.do_send(msg.clone());
+        for player in self.players.values() {
+            player.do_send(msg.clone());
+        }
+    }
+
+    /// Starts every game of the series which can be played now, or ends the
+    /// series once every game has been played.
+    fn start_series_games(&mut self, ctx: &mut actix::Context<Self>) {
+        let Some(running) = &mut self.series else {
+            return;
+        };
+        if running.series.is_finished() {
+            self.series = None;
+            self.reset_idle_timeout(ctx);
+            debug!("Series finished");
+            return;
+        }
+
+        let pairings = running.series.next_pairings();
+        for pairing in pairings {
+            let Some(running) = &self.series else {
+                return;
+            };
+            let addrs = [pairing.p1, pairing.p2].map(|i| running.players[i].clone());
+            let [Some(p1), Some(p2)] = addrs else {
+                error!("Withdrawn participant was paired, skipping the game");
+                continue;
+            };
+            let profiles = [&p1, &p2]
+                .into_iter()
+                .filter_map(|addr| Some((addr.clone(), self.profile_of(addr)?.clone())))
+                .collect();
+            let cfg = Arc::clone(&self.cfg);
+            let addrs = PlayerArray::from([p1, p2]);
+            actor::Game::new(
+                None,
+                running.config.clone(),
+                0,
+                None,
+                addrs,
+                Vec::new(),
+                cfg,
+            )
+            .with_archive(self.archive.clone())
+            .with_ratings(self.ratings.clone())
+            .with_webhooks(self.webhooks.clone())
+            .with_game_log(self.game_log.clone())
+            .with_profiles(profiles)
+            .with_series(ctx.address(), pairing)
+            .start_supervised();
+            debug!("Series game {} vs {} started", pairing.p1, pairing.p2);
+        }
+    }
+
+    /// Returns the profile of the host or a player in the lobby.
+    fn profile_of(&self, addr: &Addr<actor::Player>) -> Option<&Profile> {
+        if *addr == self.host {
+            return self.host_profile.as_ref();
+        }
+        let (code, _) = self.players.iter().find(|(_, p)| *p == addr)?;
+        self.profiles.get(code)
+    }
+
+    /// Returns the configuration set by the host with the config of a pick
+    /// applied to it.
+    fn game_config(&self, partial: &PartialGameConfig) -> GameConfig {
+        let mut config = self.default_config.clone().unwrap_or_default();
+        config.apply_partial(partial);
+        config
+    }
+
     fn schedule_player_list_sync(&mut self, ctx: &mut actix::Context<Self>) {
         let sync = &mut self.player_list_sync;
         if sync.handle.is_some() {
@@ -113,35 +719,46 @@ impl Actor for Lobby {
     type Context = actix::Context<Self>;
 
     fn started(&mut self, ctx: &mut Self::Context) {
-        let msg = AttachController(PlayerController::Lobby(ctx.address()));
+        let _span = self.span.clone().entered();
+        let msg = AttachController(PlayerController::Lobby(ctx.address()), self.span.clone());
         let Ok(()) = self.host.try_send(msg) else {
             debug!("Failed to attach controller to host, shutting down");
             ctx.stop();
             return;
         };
 
-        let link_msg = OutgoingMessage::lobby_link(self.id, &self.cfg)
-            .into_serialized()
-            .unwrap();
-        self.host.do_send(link_msg);
+        self.send_link();
+        self.reset_idle_timeout(ctx);
         debug!("Started");
     }
 
     fn stopped(&mut self, ctx: &mut Self::Context) {
-        if self.game.is_none() {
-            self.host.do_send(Disconnect::LobbyClosed);
+        let _span = self.span.clone().entered();
+        let disconnect_msg = if self.game_started {
+            Disconnect::GameStarted
+        } else if self.expired {
+            Disconnect::LobbyExpired
+        } else {
+            Disconnect::LobbyClosed
+        };
+        // Players in series games finish them first
+        let playing: Vec<_> = self
+            .series
+            .iter()
+            .flat_map(|r| r.series.playing().iter().map(move |p| (r, p)))
+            .flat_map(|(r, p)| [&r.players[p.p1], &r.players[p.p2]])
+            .flatten()
+            .cloned()
+            .collect();
+        if !self.game_started && !playing.contains(&self.host) {
+            self.host.do_send(disconnect_msg);
         }
 
         if let Some(handle) = self.player_list_sync.handle {
             ctx.cancel_future(handle);
         }
 
-        let disconnect_msg = if self.game.is_none() {
-            Disconnect::LobbyClosed
-        } else {
-            Disconnect::GameStarted
-        };
-        for player in self.players.values() {
+        for player in self.players.values().filter(|p| !playing.contains(p)) {
             player.do_send(disconnect_msg);
         }
 
@@ -150,18 +767,71 @@ impl Actor for Lobby {
     }
 }
 
+impl Drop for Lobby {
+    fn drop(&mut self) {
+        if !supervisor::panicked() {
+            return;
+        }
+
+        let _span = self.span.clone().entered();
+        error!("Panicked, disconnecting everyone");
+        if !self.game_started {
+            self.host.do_send(Disconnect::InternalError);
+        }
+        for player in self.players.values() {
+            player.do_send(Disconnect::InternalError);
+        }
+        self.router.do_send(RemoveLobby(self.id));
+    }
+}
+
 impl Handler<ConnectPlayer> for Lobby {
     type Result = ();
 
     fn handle(&mut self, msg: ConnectPlayer, ctx: &mut Self::Context) {
-        let player = msg.0;
+        let _span = self.span.clone().entered();
+        let ConnectPlayer {
+            player,
+            password,
+            ip,
+            subject,
+        } = msg;
+        let peer: Vec<BanTarget> = ip
+            .map(BanTarget::Ip)
+            .into_iter()
+            .chain(subject.map(BanTarget::Subject))
+            .collect();
+        if peer.iter().any(|target| self.blocked.contains(target)) {
+            player.do_send(Disconnect::Kicked);
+            debug!("A blocked player could not join");
+            return;
+        }
+
+        if let Some(expected) = &self.password {
+            if !password.is_some_and(|p| token::matches(expected, &p)) {
+                player.do_send(Disconnect::WrongPassword);
+                debug!("A player could not join because of a wrong password");
+                return;
+            }
+        }
+
+        let max_joins = self.cfg.invite_max_joins;
+        if max_joins > 0 && self.joins >= max_joins {
+            player.do_send(Disconnect::InviteInvalid);
+            debug!("A player could not join because the invite was used up");
+            return;
+        }
+
         let Some(id) = self.get_id() else {
             player.do_send(Disconnect::LobbyFull);
             debug!("A player could not join because the lobby is full!");
             return;
         };
 
-        let Ok(()) = player.try_send(AttachController(PlayerController::Lobby(ctx.address()))) else {
+        let Ok(()) = player.try_send(AttachController(
+            PlayerController::Lobby(ctx.address()),
+            self.span.clone(),
+        )) else {
             player.do_send(Disconnect::LobbyJoinError);
             debug!("Failed to attach controller to a player");
             return;
@@ -171,9 +841,16 @@ impl Handler<ConnectPlayer> for Lobby {
             .into_serialized()
             .unwrap();
         player.do_send(msg);
+        self.notify(Notice::PlayerJoined { code: id });
         self.players.insert(id, player);
+        self.joined.insert(id, Instant::now());
+        self.ready.remove(&id);
+        self.peers.insert(id, peer);
+        self.joins += 1;
+        self.reset_idle_timeout(ctx);
         self.schedule_player_list_sync(ctx);
-        debug!("Player {} has joined", id);
+        self.sync_listing();
+        debug!("Player {id} has joined");
     }
 }
 
@@ -181,22 +858,21 @@ impl Handler<Disconnected> for Lobby {
     type Result = ();
 
     fn handle(&mut self, msg: Disconnected, ctx: &mut Self::Context) {
-        if self.game.is_some() {
+        let _span = self.span.clone().entered();
+        if self.game_started {
             return;
         }
 
         let addr = msg.0.upgrade();
-
-        if !(self.host.connected() && addr.as_ref().map_or(true, |a| a != &self.host)) {
-            debug!("Host has disconnected; lobby shutting down");
-            ctx.stop();
+        if !self.remove_departed(addr.as_ref(), ctx) {
             return;
         }
-
-        self.players
-            .retain(|_, player| player.connected() && addr.as_ref().map_or(true, |a| a != player));
-
-        self.schedule_player_list_sync(ctx);
+        if self.series.is_some() {
+            self.sync_standings();
+            self.start_series_games(ctx);
+        }
+        self.reset_idle_timeout(ctx);
+        self.sync_listing();
         debug!("Player left");
     }
 }
@@ -204,7 +880,9 @@ impl Handler<Disconnected> for Lobby {
 impl Handler<IncomingPickPlayer> for Lobby {
     type Result = ();
 
+    #[allow(clippy::too_many_lines)]
     fn handle(&mut self, msg: IncomingPickPlayer, ctx: &mut Self::Context) {
+        let _span = self.span.clone().entered();
         let IncomingPickPlayer {
             code,
             game,
@@ -212,16 +890,94 @@ impl Handler<IncomingPickPlayer> for Lobby {
             round,
             role,
             extra_time,
+            elapsed,
+            winner_stays,
         } = msg;
-        let Some(player) = self.players.remove(&code) else { return; };
-        let addrs = match role {
-            Player::P1 => [player, self.host.clone()],
-            Player::P2 => [self.host.clone(), player],
+        if self.series.is_some() {
+            self.reject_pick(code, PickRejection::SeriesInProgress);
+            return;
+        }
+        if self.is_at_game_capacity() {
+            self.reject_pick(code, PickRejection::ServerMaxGames);
+            return;
+        }
+        let config = self.game_config(&config);
+        let rules_valid = Player::all(config.players).contains(&role)
+            && game.as_ref().is_none_or(|g| {
+                let rules = g.rules();
+                rules.players == config.players
+                    && game::is_win_len_valid(rules.players, rules.win_len)
+            });
+        let checked = self.cfg.game_limits.check_two_player(&config);
+        if let Err(rejection) = checked {
+            self.host.do_send(RejectConfig(rejection));
+        }
+        if !(checked.is_ok() && rules_valid) {
+            debug!("Player {code} could not be picked: invalid config");
+            self.reject_pick(code, PickRejection::InvalidConfig);
+            return;
+        }
+        let game = match game.map(game::Game::validated).transpose() {
+            Ok(game) => game,
+            Err(e) => {
+                debug!("Player {code} could not be picked: invalid game ({e:?})");
+                self.reject_pick(code, e.into());
+                return;
+            }
+        };
+        if !self.players.contains_key(&code) {
+            self.reject_pick(code, PickRejection::UnknownPlayer);
+            return;
+        }
+        let extra_time = extra_time.zip(game.as_ref()).map(|(extra_time, game)| {
+            let checked = extra_time::check(extra_time, game, elapsed.unwrap_or_default(), &config);
+            if checked.clamped {
+                warn!(
+                    "Host claimed extra time {extra_time:?} which could not have been saved up, \
+                     lowered to {:?}",
+                    checked.extra_time
+                );
+            }
+            PlayerArray::from(checked.extra_time)
+        });
+
+        let profiles = self.game_profiles();
+        let Some(player) = self.players.remove(&code) else {
+            return;
+        };
+        let spectators = self
+            .spectators
+            .drain()
+            .filter_map(|code| self.players.remove(&code))
+            .collect();
+        // The host plays the other seat of the two player game
+        let addrs = if role == Player::P1 {
+            [player, self.host.clone()]
+        } else {
+            [self.host.clone(), player]
         }
         .into();
         let cfg = Arc::clone(&self.cfg);
-        let game = actor::Game::new(game, config.into(), round, extra_time, addrs, cfg);
-        self.game = Some(game.start());
+        let game = actor::Game::new(game, config, round, extra_time, addrs, spectators, cfg)
+            .with_host_token(self.host_token.clone())
+            .with_archive(self.archive.clone())
+            .with_ratings(self.ratings.clone())
+            .with_webhooks(self.webhooks.clone())
+            .with_game_log(self.game_log.clone())
+            .with_profiles(profiles)
+            .with_winner_stays(winner_stays)
+            .register_with(self.id, self.router.clone())
+            .start_supervised();
+        for (_, player) in self.players.drain() {
+            game.do_send(JoinInProgress(player.clone()));
+            if winner_stays {
+                game.do_send(Queue {
+                    addr: player,
+                    queued: true,
+                });
+            }
+        }
+        self.game_started = true;
         debug!(
             "Player {} was chosen as {:?}, lobby shutting down",
             msg.code, msg.role
@@ -231,11 +987,574 @@ impl Handler<IncomingPickPlayer> for Lobby {
     }
 }
 
+impl Handler<StartSeries> for Lobby {
+    type Result = ();
+
+    fn handle(&mut self, msg: StartSeries, ctx: &mut Self::Context) {
+        let _span = self.span.clone().entered();
+        let StartSeries {
+            addr,
+            mut codes,
+            config,
+            parallel,
+        } = msg;
+        if addr != self.host || self.series.is_some() {
+            return;
+        }
+
+        let config = self.game_config(&config);
+        if let Err(rejection) = self.cfg.game_limits.check_two_player(&config) {
+            debug!("Series could not be started: invalid config");
+            self.host.do_send(RejectConfig(rejection));
+            return;
+        }
+        codes.sort_unstable();
+        codes.dedup();
+        if let Some(code) = codes.iter().find(|c| !self.players.contains_key(c)) {
+            self.reject_pick(*code, PickRejection::UnknownPlayer);
+            return;
+        }
+        if codes.is_empty() {
+            debug!("Series could not be started: no players besides the host");
+            return;
+        }
+        if self.is_at_game_capacity() {
+            self.reject_pick(codes[0], PickRejection::ServerMaxGames);
+            return;
+        }
+
+        let labels: Vec<_> = [None]
+            .into_iter()
+            .chain(codes.iter().copied().map(Some))
+            .collect();
+        let players = [Some(self.host.clone())]
+            .into_iter()
+            .chain(codes.iter().map(|code| self.players.get(code).cloned()))
+            .collect();
+        self.series = Some(RunningSeries {
+            series: Series::new(&labels, parallel),
+            players,
+            config,
+        });
+        self.reset_idle_timeout(ctx);
+        self.sync_standings();
+        self.start_series_games(ctx);
+        debug!("Series started with {} players", labels.len());
+    }
+}
+
+impl Handler<PickPlayers> for Lobby {
+    type Result = ();
+
+    fn handle(&mut self, msg: PickPlayers, ctx: &mut Self::Context) {
+        let _span = self.span.clone().entered();
+        let PickPlayers {
+            addr,
+            codes,
+            config,
+        } = msg;
+        if addr != self.host {
+            return;
+        }
+        let Some(first) = codes.first().copied() else {
+            return;
+        };
+        if self.series.is_some() {
+            self.reject_pick(first, PickRejection::SeriesInProgress);
+            return;
+        }
+        if self.is_at_game_capacity() {
+            self.reject_pick(first, PickRejection::ServerMaxGames);
+            return;
+        }
+
+        let config = self.game_config(&config);
+        let unique: HashSet<_> = codes.iter().collect();
+        let checked = self.cfg.game_limits.check(&config).and_then(|()| {
+            // Duplicate codes would leave seats empty
+            if config.players == 2 || unique.len() + 1 != usize::from(config.players) {
+                return Err(ConfigRejection {
+                    field: ConfigField::Players,
+                    reason: ConfigRejectionReason::Invalid,
+                });
+            }
+            Ok(())
+        });
+        if let Err(rejection) = checked {
+            debug!("Players could not be picked: invalid config");
+            self.host.do_send(RejectConfig(rejection));
+            self.reject_pick(first, PickRejection::InvalidConfig);
+            return;
+        }
+        if let Some(code) = codes.iter().find(|c| !self.players.contains_key(c)) {
+            self.reject_pick(*code, PickRejection::UnknownPlayer);
+            return;
+        }
+
+        let profiles = self.game_profiles();
+        let addrs = [self.host.clone()]
+            .into_iter()
+            .chain(codes.iter().filter_map(|code| self.players.remove(code)))
+            .collect();
+        let spectators = self
+            .spectators
+            .drain()
+            .filter_map(|code| self.players.remove(&code))
+            .collect();
+        let cfg = Arc::clone(&self.cfg);
+        let game = actor::Game::new(None, config, 0, None, addrs, spectators, cfg)
+            .with_host_token(self.host_token.clone())
+            .with_archive(self.archive.clone())
+            .with_ratings(self.ratings.clone())
+            .with_webhooks(self.webhooks.clone())
+            .with_game_log(self.game_log.clone())
+            .with_profiles(profiles)
+            .register_with(self.id, self.router.clone())
+            .start_supervised();
+        for (_, player) in self.players.drain() {
+            game.do_send(JoinInProgress(player));
+        }
+        self.game_started = true;
+        debug!("Players {codes:?} were picked, lobby shutting down");
+
+        ctx.stop();
+    }
+}
+
+impl Handler<PickPair> for Lobby {
+    type Result = ();
+
+    fn handle(&mut self, msg: PickPair, ctx: &mut Self::Context) {
+        let _span = self.span.clone().entered();
+        let PickPair {
+            addr,
+            codes,
+            config,
+        } = msg;
+        if addr != self.host {
+            return;
+        }
+        if self.series.is_some() {
+            self.reject_pick(codes[0], PickRejection::SeriesInProgress);
+            return;
+        }
+        if self.is_at_game_capacity() {
+            self.reject_pick(codes[0], PickRejection::ServerMaxGames);
+            return;
+        }
+        let config = self.game_config(&config);
+        let checked = self.cfg.game_limits.check_two_player(&config);
+        if let Err(rejection) = checked {
+            debug!("Pair could not be picked: invalid config");
+            self.host.do_send(RejectConfig(rejection));
+            self.reject_pick(codes[0], PickRejection::InvalidConfig);
+            return;
+        }
+        if let Some(code) = codes.iter().find(|c| !self.players.contains_key(c)) {
+            self.reject_pick(*code, PickRejection::UnknownPlayer);
+            return;
+        }
+        if codes[0] == codes[1] {
+            self.reject_pick(codes[1], PickRejection::SamePlayer);
+            return;
+        }
+
+        let profiles = self.game_profiles();
+        let addrs = codes.map(|code| self.players.remove(&code).unwrap());
+        let spectators = [self.host.clone()]
+            .into_iter()
+            .chain(
+                self.spectators
+                    .drain()
+                    .filter_map(|code| self.players.remove(&code)),
+            )
+            .collect();
+        let cfg = Arc::clone(&self.cfg);
+        let game = actor::Game::new(None, config, 0, None, addrs.into(), spectators, cfg)
+            .with_host_token(self.host_token.clone())
+            .with_archive(self.archive.clone())
+            .with_ratings(self.ratings.clone())
+            .with_webhooks(self.webhooks.clone())
+            .with_game_log(self.game_log.clone())
+            .with_profiles(profiles)
+            .register_with(self.id, self.router.clone())
+            .start_supervised();
+        for (_, player) in self.players.drain() {
+            game.do_send(JoinInProgress(player));
+        }
+        self.game_started = true;
+        debug!("Players {codes:?} were paired, lobby shutting down");
+
+        ctx.stop();
+    }
+}
+
+impl Handler<SeriesGameOver> for Lobby {
+    type Result = ();
+
+    fn handle(&mut self, msg: SeriesGameOver, ctx: &mut Self::Context) {
+        let _span = self.span.clone().entered();
+        let SeriesGameOver {
+            pairing,
+            winner,
+            players,
+        } = msg;
+        let Some(running) = &mut self.series else {
+            return;
+        };
+        if !running.series.record(pairing, winner) {
+            return;
+        }
+        debug!(
+            "Series game {} vs {} ended: {winner:?}",
+            pairing.p1, pairing.p2
+        );
+
+        for addr in players {
+            let msg = AttachController(PlayerController::Lobby(ctx.address()), self.span.clone());
+            if addr == self.host {
+                if self.host.try_send(msg).is_ok() {
+                    self.send_link();
+                }
+            } else if let Some((code, player)) = self.players.iter().find(|(_, p)| **p == addr) {
+                if player.try_send(msg).is_ok() {
+                    let msg = OutgoingMessage::LobbyCode { code: *code };
+                    player.do_send(msg.into_serialized().unwrap());
+                }
+            }
+        }
+
+        if !self.remove_departed(None, ctx) {
+            return;
+        }
+        self.sync_standings();
+        self.start_series_games(ctx);
+    }
+}
+
+impl Handler<PickSpectator> for Lobby {
+    type Result = ();
+
+    fn handle(&mut self, msg: PickSpectator, ctx: &mut Self::Context) {
+        let _span = self.span.clone().entered();
+        if msg.addr != self.host {
+            return;
+        }
+        if !self.players.contains_key(&msg.code) || !self.spectators.insert(msg.code) {
+            return;
+        }
+
+        self.reset_idle_timeout(ctx);
+        self.schedule_player_list_sync(ctx);
+        debug!("Player {} will spectate", msg.code);
+    }
+}
+
+impl Handler<KickPlayer> for Lobby {
+    type Result = ();
+
+    fn handle(&mut self, msg: KickPlayer, ctx: &mut Self::Context) {
+        let _span = self.span.clone().entered();
+        if msg.addr != self.host {
+            return;
+        }
+        let Some(player) = self.players.get(&msg.code).cloned() else {
+            return;
+        };
+
+        if msg.block {
+            let peer = self.peers.get(&msg.code).into_iter().flatten().cloned();
+            self.blocked.extend(peer);
+        }
+        player.do_send(Disconnect::Kicked);
+        self.reset_idle_timeout(ctx);
+        if self.remove_departed(Some(&player), ctx) {
+            self.sync_listing();
+        }
+        debug!(block = msg.block, "Player {} was kicked", msg.code);
+    }
+}
+
+impl Handler<SetReady> for Lobby {
+    type Result = ();
+
+    fn handle(&mut self, msg: SetReady, ctx: &mut Self::Context) {
+        let _span = self.span.clone().entered();
+        let Some((code, _)) = self.players.iter().find(|(_, p)| **p == msg.addr) else {
+            return;
+        };
+        let code = *code;
+        let changed = if msg.ready {
+            self.ready.insert(code)
+        } else {
+            self.ready.remove(&code)
+        };
+        self.reset_idle_timeout(ctx);
+        if changed {
+            self.schedule_player_list_sync(ctx);
+            debug!(ready = msg.ready, "Player {code} changed their ready state");
+        }
+    }
+}
+
+impl Handler<Chat> for Lobby {
+    type Result = ();
+
+    fn handle(&mut self, msg: Chat, ctx: &mut Self::Context) {
+        let _span = self.span.clone().entered();
+        let code = if msg.addr == self.host {
+            None
+        } else if let Some((code, _)) = self.players.iter().find(|(_, p)| **p == msg.addr) {
+            Some(*code)
+        } else {
+            return;
+        };
+
+        let Some(text) = chat::sanitize(&msg.text, self.cfg.chat_max_length) else {
+            debug!("Rejected chat message");
+            return;
+        };
+        let limiter = match code {
+            Some(code) => self.chat_limiters.entry(code).or_default(),
+            None => &mut self.host_chat_limiter,
+        };
+        if !limiter.try_send() {
+            debug!("Chat messages are being sent too quickly");
+            return;
+        }
+
+        let profile = match code {
+            Some(code) => self.profiles.get(&code),
+            None => self.host_profile.as_ref(),
+        };
+        let msg = OutgoingMessage::lobby_chat(code, &text, profile)
+            .into_shared()
+            .unwrap();
+        self.host.do_send(msg.clone());
+        for player in self.players.values() {
+            player.do_send(msg.clone());
+        }
+        self.reset_idle_timeout(ctx);
+    }
+}
+
+impl Handler<UpdateProfile> for Lobby {
+    type Result = ();
+
+    fn handle(&mut self, msg: UpdateProfile, ctx: &mut Self::Context) {
+        let _span = self.span.clone().entered();
+        self.reset_idle_timeout(ctx);
+        if msg.addr == self.host {
+            self.host_profile = Some(msg.profile);
+            return;
+        }
+
+        let Some((code, _)) = self.players.iter().find(|(_, p)| **p == msg.addr) else {
+            return;
+        };
+        self.profiles.insert(*code, msg.profile);
+        self.schedule_player_list_sync(ctx);
+    }
+}
+
+impl Handler<RequestFullSync> for Lobby {
+    type Result = ();
+
+    fn handle(&mut self, RequestFullSync(addr): RequestFullSync, ctx: &mut Self::Context) {
+        let _span = self.span.clone().entered();
+        if addr == self.host {
+            self.send_link();
+            self.sync_player_list(ctx);
+        } else if let Some((code, _)) = self.players.iter().find(|(_, p)| **p == addr) {
+            let msg = OutgoingMessage::LobbyCode { code: *code }
+                .into_serialized()
+                .unwrap();
+            addr.do_send(msg);
+            if self.default_config.is_some() {
+                addr.do_send(self.player_list_message());
+            }
+        } else {
+            return;
+        }
+
+        if let Some(msg) = self.standings_message() {
+            addr.do_send(msg);
+        }
+    }
+}
+
+impl Handler<Backpressure> for Lobby {
+    type Result = ();
+
+    fn handle(&mut self, msg: Backpressure, ctx: &mut Self::Context) {
+        let _span = self.span.clone().entered();
+        if msg.addr != self.host {
+            return;
+        }
+        self.host_congested = msg.congested;
+        if !msg.congested {
+            self.sync_player_list(ctx);
+        }
+    }
+}
+
+impl Handler<SetPublic> for Lobby {
+    type Result = ();
+
+    fn handle(&mut self, msg: SetPublic, ctx: &mut Self::Context) {
+        let _span = self.span.clone().entered();
+        if msg.addr != self.host {
+            return;
+        }
+
+        self.reset_idle_timeout(ctx);
+        if msg.public {
+            self.listing = Some(msg.config);
+            self.sync_listing();
+            debug!("Lobby is now public");
+        } else if self.listing.take().is_some() {
+            self.router.do_send(UpdatePublicLobby {
+                id: self.id,
+                lobby: None,
+            });
+            debug!("Lobby is now private");
+        }
+    }
+}
+
+impl Handler<SetConfig> for Lobby {
+    type Result = ();
+
+    fn handle(&mut self, msg: SetConfig, ctx: &mut Self::Context) {
+        let _span = self.span.clone().entered();
+        if msg.addr != self.host {
+            return;
+        }
+
+        let config = GameConfig::from_partial(&msg.config);
+        if let Err(rejection) = self.cfg.game_limits.check(&config) {
+            debug!("Rejected lobby config");
+            self.host.do_send(RejectConfig(rejection));
+            return;
+        }
+        self.default_config = Some(config);
+        self.reset_idle_timeout(ctx);
+        self.schedule_player_list_sync(ctx);
+        debug!("Lobby config set");
+    }
+}
+
+impl Handler<SetPassword> for Lobby {
+    type Result = ();
+
+    fn handle(&mut self, msg: SetPassword, ctx: &mut Self::Context) {
+        let _span = self.span.clone().entered();
+        if msg.addr != self.host {
+            return;
+        }
+        if msg
+            .password
+            .as_ref()
+            .is_some_and(|p| p.len() > PASSWORD_MAX_LENGTH)
+        {
+            debug!("Rejected password: too long");
+            return;
+        }
+
+        self.password = msg.password.filter(|p| !p.is_empty());
+        self.sync_listing();
+        self.reset_idle_timeout(ctx);
+        debug!("Password set: {}", self.password.is_some());
+    }
+}
+
+impl Handler<RequestLink> for Lobby {
+    type Result = ();
+
+    fn handle(&mut self, msg: RequestLink, _: &mut Self::Context) {
+        let _span = self.span.clone().entered();
+        if msg.addr != self.host {
+            debug!("Ignored link request from a player who is not the host");
+            return;
+        }
+        self.send_link();
+    }
+}
+
+impl Handler<RegenerateInvite> for Lobby {
+    type Result = ();
+
+    fn handle(&mut self, msg: RegenerateInvite, ctx: &mut Self::Context) {
+        let _span = self.span.clone().entered();
+        if msg.addr != self.host {
+            return;
+        }
+
+        let old = self.id;
+        self.id = Uuid::new_v4();
+        self.join_code = None;
+        self.qr_codes = QrCodes::generate(self.id, &self.cfg);
+        self.joins = 0;
+        // The link is sent with the new join code
+        self.router.do_send(ChangeLobbyId { old, new: self.id });
+        self.sync_listing();
+
+        self.reset_idle_timeout(ctx);
+        debug!("Invite regenerated, new lobby ID is {}", self.id);
+    }
+}
+
+impl Handler<SetJoinCode> for Lobby {
+    type Result = ();
+
+    fn handle(&mut self, SetJoinCode(join_code): SetJoinCode, _: &mut Self::Context) {
+        let _span = self.span.clone().entered();
+        self.join_code = Some(join_code);
+        self.send_link();
+    }
+}
+
+impl Handler<Authenticated> for Lobby {
+    type Result = ();
+
+    fn handle(&mut self, msg: Authenticated, _: &mut Self::Context) {
+        let _span = self.span.clone().entered();
+        debug!(subject = msg.subject, "Player authenticated");
+        self.subjects.insert(msg.addr, msg.subject);
+    }
+}
+
+impl Handler<GetLobbyInfo> for Lobby {
+    type Result = MessageResult<GetLobbyInfo>;
+
+    fn handle(&mut self, _: GetLobbyInfo, _: &mut Self::Context) -> Self::Result {
+        let _span = self.span.clone().entered();
+        MessageResult(LobbyInfo {
+            id: self.id.to_string(),
+            players: self.players.len() + 1,
+            spectators: self.spectators.len(),
+            public: self.listing.is_some(),
+            password_protected: self.password.is_some(),
+            created_at: self.created_at.clone(),
+            authenticated: self.subjects.values().cloned().collect(),
+        })
+    }
+}
+
 impl Handler<Shutdown> for Lobby {
     type Result = ();
 
     fn handle(&mut self, _: Shutdown, ctx: &mut Self::Context) {
+        let _span = self.span.clone().entered();
         debug!("Lobby shutting down");
         ctx.stop();
     }
 }
+
+impl Handler<ReloadConfig> for Lobby {
+    type Result = ();
+
+    fn handle(&mut self, msg: ReloadConfig, _: &mut Self::Context) {
+        self.cfg = msg.0;
+    }
+}
@@ -1,17 +1,50 @@
-use std::{collections::HashMap, sync::Arc};
+use std::{collections::HashMap, net::IpAddr, sync::Arc};
 
 use actix::prelude::*;
-use log::debug;
+use rand::{seq::SliceRandom, thread_rng};
+use serde::Serialize;
+use tokio::sync::watch;
+use tracing::debug;
 use uuid::Uuid;
 
-use crate::server::{actor, AppConfig};
-use actor::lobby::{ConnectPlayer, Shutdown};
-use actor::player::Disconnect;
+use crate::bot::Difficulty;
+use crate::server::archive::GameArchive;
+use crate::server::game_log::GameLog;
+use crate::server::load::Load;
+use crate::server::protocol::Disconnect;
+use crate::server::rating::RatingStore;
+use crate::server::reload::{self, ReloadConfig};
+use crate::server::webhook::{WebhookEvent, Webhooks};
+use crate::server::{actor, AppConfig, PartialGameConfig};
+use actor::game::{self, JoinInProgress, Rejoin};
+use actor::lobby::{ConnectPlayer, SetJoinCode, Shutdown};
+use actor::matchmaker::Enqueue;
+use actor::supervisor::StartCatchingPanics;
 
 #[derive(Message)]
 #[rtype(result = "()")]
 pub struct CreateLobby {
     pub host: Addr<actor::Player>,
+    /// Password players need to join the lobby.
+    pub password: Option<String>,
+}
+
+/// Starts a single-player game against the bot.
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct CreateBotGame {
+    pub player: Addr<actor::Player>,
+    pub difficulty: Difficulty,
+}
+
+/// Queues the player for a game against another player who connected with
+/// `matchmake=1`.
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct Matchmake {
+    pub player: Addr<actor::Player>,
+    /// ID the rating of the player is kept under, if it has one.
+    pub rating_id: Option<String>,
 }
 
 #[derive(Message)]
@@ -19,14 +52,131 @@ pub struct CreateLobby {
 pub struct JoinLobby {
     pub id: Uuid,
     pub player: Addr<actor::Player>,
+    pub password: Option<String>,
+    /// Address of the client, `None` if it is unknown.
+    pub ip: Option<IpAddr>,
+    /// User the client authenticated as, see `server::auth`.
+    pub subject: Option<String>,
 }
 
 #[derive(Message)]
 #[rtype(result = "()")]
 pub struct RemoveLobby(pub Uuid);
 
+/// Returns the ID of the lobby with this join code, if it exists. Codes are
+/// not case sensitive.
+#[derive(Message)]
+#[rtype(result = "Option<Uuid>")]
+pub struct ResolveJoinCode(pub String);
+
+/// Sent by a lobby after regenerating its invite. Players can no longer join
+/// using the old ID or join code, the lobby is sent a new code.
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct ChangeLobbyId {
+    pub old: Uuid,
+    pub new: Uuid,
+}
+
+/// Sent by a game started from a lobby, so that players can reconnect to it.
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct RegisterGame {
+    pub id: Uuid,
+    pub game: Addr<actor::Game>,
+}
+
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct RemoveGame(pub Uuid);
+
+/// Returns the game started from the lobby with this ID, if it is running.
+#[derive(Message)]
+#[rtype(result = "Option<Addr<actor::Game>>")]
+pub struct GetGame(pub Uuid);
+
+/// Attempts to resume a game after the player has lost connection.
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct Reconnect {
+    pub id: Uuid,
+    pub token: String,
+    pub player: Addr<actor::Player>,
+}
+
+/// A lobby listed in `GET /lobbies`.
+#[derive(Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PublicLobby {
+    pub id: String,
+    /// Amount of connected players, including the host.
+    pub players: usize,
+    pub config: PartialGameConfig,
+    /// Whether players need a password to join.
+    pub password_protected: bool,
+    pub created_at: String,
+}
+
+/// Sent by a lobby whenever its public listing changes. `None` removes the
+/// lobby from the listing.
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct UpdatePublicLobby {
+    pub id: Uuid,
+    pub lobby: Option<PublicLobby>,
+}
+
+/// Returns every public lobby, oldest first.
+#[derive(Message)]
+#[rtype(result = "Vec<PublicLobby>")]
+pub struct ListPublicLobbies;
+
+/// Returns every lobby, used by the admin API.
+#[derive(Message)]
+#[rtype(result = "Vec<Addr<actor::Lobby>>")]
+pub struct ListLobbies;
+
+/// Returns every game started from a lobby, used by the admin API. Games
+/// against the bot are not included.
+#[derive(Message)]
+#[rtype(result = "Vec<Addr<actor::Game>>")]
+pub struct ListGames;
+
+/// Closes the lobby or game with this ID, disconnecting everyone in it.
+/// Returns false if neither exists.
+#[derive(Message)]
+#[rtype(result = "bool")]
+pub struct Close(pub Uuid);
+
+/// Closes every lobby and game.
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct CloseAll;
+
+/// Amount of threads computing moves for all bots.
+const BOT_THREADS: usize = 2;
+
+/// Length of the join codes of lobbies.
+const JOIN_CODE_LEN: usize = 6;
+/// Characters join codes are made of. Letters and digits which are easily
+/// confused when read aloud, such as `0` and `O`, are left out.
+const JOIN_CODE_CHARS: &[u8] = b"23456789ABCDEFGHJKMNPQRSTUVWXYZ";
+
 pub struct LobbyRouter {
     lobbies: HashMap<Uuid, Addr<actor::Lobby>>,
+    /// Short codes players can join lobbies with instead of their IDs.
+    join_codes: HashMap<String, Uuid>,
+    games: HashMap<Uuid, Addr<actor::Game>>,
+    public_lobbies: HashMap<Uuid, PublicLobby>,
+    bot: Addr<actor::Bot>,
+    /// Started once the first player asks to be matched.
+    matchmaker: Option<Addr<actor::Matchmaker>>,
+    archive: Option<Arc<dyn GameArchive>>,
+    ratings: Option<Arc<dyn RatingStore>>,
+    webhooks: Option<Arc<Webhooks>>,
+    game_log: Option<Arc<GameLog>>,
+    /// Notified whenever the settings are reloaded, taken once started.
+    config_updates: Option<watch::Receiver<Arc<AppConfig>>>,
     cfg: Arc<AppConfig>,
 }
 
@@ -35,14 +185,89 @@ impl LobbyRouter {
     pub fn new(cfg: Arc<AppConfig>) -> Self {
         Self {
             lobbies: HashMap::new(),
+            join_codes: HashMap::new(),
+            games: HashMap::new(),
+            public_lobbies: HashMap::new(),
+            bot: SyncArbiter::start(BOT_THREADS, || actor::Bot),
+            matchmaker: None,
+            archive: None,
+            ratings: None,
+            webhooks: None,
+            game_log: None,
+            config_updates: None,
             cfg,
         }
     }
+
+    /// Stores every finished game in the archive.
+    #[must_use]
+    pub fn with_archive(mut self, archive: Option<Arc<dyn GameArchive>>) -> Self {
+        self.archive = archive;
+        self
+    }
+
+    /// Updates the ratings of players after every game started from a
+    /// lobby. Games against the bot are not rated.
+    #[must_use]
+    pub fn with_ratings(mut self, ratings: Option<Arc<dyn RatingStore>>) -> Self {
+        self.ratings = ratings;
+        self
+    }
+
+    /// Reports new lobbies, and the games started from them or against the
+    /// bot, to the webhooks.
+    #[must_use]
+    pub fn with_webhooks(mut self, webhooks: Option<Arc<Webhooks>>) -> Self {
+        self.webhooks = webhooks;
+        self
+    }
+
+    /// Writes every round of every game, including games against the bot,
+    /// to the game log.
+    #[must_use]
+    pub fn with_game_log(mut self, game_log: Option<Arc<GameLog>>) -> Self {
+        self.game_log = game_log;
+        self
+    }
+
+    /// Passes the settings on to every lobby and game whenever they are
+    /// reloaded.
+    #[must_use]
+    pub fn with_config_updates(mut self, updates: watch::Receiver<Arc<AppConfig>>) -> Self {
+        self.config_updates = Some(updates);
+        self
+    }
+
+    /// Generates a join code which is not used by any other lobby and
+    /// assigns it to the lobby.
+    fn assign_join_code(&mut self, lobby: Uuid) -> String {
+        let mut rng = thread_rng();
+        loop {
+            let code: String = (0..JOIN_CODE_LEN)
+                .filter_map(|_| JOIN_CODE_CHARS.choose(&mut rng))
+                .map(|c| char::from(*c))
+                .collect();
+            if !self.join_codes.contains_key(&code) {
+                self.join_codes.insert(code.clone(), lobby);
+                return code;
+            }
+        }
+    }
+
+    fn remove_join_code(&mut self, lobby: Uuid) {
+        self.join_codes.retain(|_, id| *id != lobby);
+    }
 }
 
 impl Actor for LobbyRouter {
     type Context = actix::Context<Self>;
 
+    fn started(&mut self, ctx: &mut Self::Context) {
+        if let Some(updates) = self.config_updates.take() {
+            ctx.add_message_stream(reload::updates(updates));
+        }
+    }
+
     fn stopped(&mut self, _: &mut Self::Context) {
         debug!("Shutting down all lobbies");
         self.lobbies.values().for_each(|v| v.do_send(Shutdown));
@@ -60,9 +285,67 @@ impl Handler<CreateLobby> for LobbyRouter {
         }
 
         let id = Uuid::new_v4();
-        let addr = actor::Lobby::new(ctx.address(), id, msg.host, Arc::clone(&self.cfg)).start();
+        let join_code = self.assign_join_code(id);
+        let addr = actor::Lobby::new(ctx.address(), id, msg.host, Arc::clone(&self.cfg))
+            .with_join_code(join_code)
+            .with_password(msg.password)
+            .with_archive(self.archive.clone())
+            .with_ratings(self.ratings.clone())
+            .with_webhooks(self.webhooks.clone())
+            .with_game_log(self.game_log.clone())
+            .start_supervised();
         self.lobbies.insert(id, addr);
-        debug!("Created a new lobby {}", id);
+        if let Some(webhooks) = &self.webhooks {
+            webhooks.send(&WebhookEvent::LobbyCreated {
+                lobby: id.to_string(),
+            });
+        }
+        debug!("Created a new lobby {id}");
+    }
+}
+
+impl Handler<CreateBotGame> for LobbyRouter {
+    type Result = ();
+
+    fn handle(&mut self, msg: CreateBotGame, _: &mut Self::Context) {
+        if Load::current().is_at_game_capacity(self.cfg.max_games) {
+            debug!("Failed to create a new game against the bot: max capacity reached!");
+            msg.player.do_send(Disconnect::ServerMaxGames);
+            return;
+        }
+
+        let bot = self.bot.clone();
+        let cfg = Arc::clone(&self.cfg);
+        actor::Game::against_bot(msg.player, msg.difficulty, bot, cfg)
+            .with_archive(self.archive.clone())
+            .with_webhooks(self.webhooks.clone())
+            .with_game_log(self.game_log.clone())
+            .start_supervised();
+        debug!("Created a new game against {:?} bot", msg.difficulty);
+    }
+}
+
+impl Handler<Matchmake> for LobbyRouter {
+    type Result = ();
+
+    fn handle(&mut self, msg: Matchmake, ctx: &mut Self::Context) {
+        let matchmaker = self.matchmaker.get_or_insert_with(|| {
+            actor::Matchmaker::new(ctx.address(), Arc::clone(&self.cfg))
+                .with_archive(self.archive.clone())
+                .with_ratings(self.ratings.clone())
+                .with_webhooks(self.webhooks.clone())
+                .with_game_log(self.game_log.clone())
+                .start()
+        });
+        let enqueue = Enqueue {
+            player: msg.player.clone(),
+            rating_id: msg.rating_id,
+        };
+        match matchmaker.try_send(enqueue) {
+            Ok(()) => (),
+            Err(SendError::Full(_)) => msg.player.do_send(Disconnect::ServerOverloaded),
+            Err(SendError::Closed(_)) => msg.player.do_send(Disconnect::ShuttingDown),
+        }
     }
 }
 
@@ -71,12 +354,27 @@ impl Handler<JoinLobby> for LobbyRouter {
 
     fn handle(&mut self, msg: JoinLobby, _: &mut Self::Context) {
         let Some(lobby) = self.lobbies.get(&msg.id) else {
+            if let Some(game) = self.games.get(&msg.id) {
+                match game.try_send(JoinInProgress(msg.player.clone())) {
+                    Ok(()) => (),
+                    Err(SendError::Full(_)) => msg.player.do_send(Disconnect::ServerOverloaded),
+                    Err(SendError::Closed(_)) => msg.player.do_send(Disconnect::GameEnded),
+                }
+                return;
+            }
+
             msg.player.do_send(Disconnect::InviteInvalid);
             debug!("Lobby {} does not exist!", msg.id);
             return;
         };
 
-        match lobby.try_send(ConnectPlayer(msg.player.clone())) {
+        let connect = ConnectPlayer {
+            player: msg.player.clone(),
+            password: msg.password,
+            ip: msg.ip,
+            subject: msg.subject,
+        };
+        match lobby.try_send(connect) {
             Ok(()) => (),
             Err(SendError::Full(_)) => msg.player.do_send(Disconnect::LobbyOverloaded),
             Err(SendError::Closed(_)) => msg.player.do_send(Disconnect::InviteInvalid),
@@ -88,6 +386,8 @@ impl Handler<RemoveLobby> for LobbyRouter {
     type Result = ();
 
     fn handle(&mut self, msg: RemoveLobby, _: &mut Self::Context) {
+        self.public_lobbies.remove(&msg.0);
+        self.remove_join_code(msg.0);
         if let Some(lobby) = self.lobbies.remove(&msg.0) {
             if lobby.connected() {
                 lobby.do_send(Shutdown);
@@ -97,3 +397,163 @@ impl Handler<RemoveLobby> for LobbyRouter {
         }
     }
 }
+
+impl Handler<ChangeLobbyId> for LobbyRouter {
+    type Result = ();
+
+    fn handle(&mut self, msg: ChangeLobbyId, _: &mut Self::Context) {
+        // The lobby updates its listing right after
+        self.public_lobbies.remove(&msg.old);
+        self.remove_join_code(msg.old);
+        if let Some(lobby) = self.lobbies.remove(&msg.old) {
+            let join_code = self.assign_join_code(msg.new);
+            lobby.do_send(SetJoinCode(join_code));
+            self.lobbies.insert(msg.new, lobby);
+            debug!("Lobby {} is now {}", msg.old, msg.new);
+        }
+    }
+}
+
+impl Handler<ResolveJoinCode> for LobbyRouter {
+    type Result = Option<Uuid>;
+
+    fn handle(&mut self, msg: ResolveJoinCode, _: &mut Self::Context) -> Self::Result {
+        self.join_codes.get(&msg.0.to_ascii_uppercase()).copied()
+    }
+}
+
+impl Handler<UpdatePublicLobby> for LobbyRouter {
+    type Result = ();
+
+    fn handle(&mut self, msg: UpdatePublicLobby, _: &mut Self::Context) {
+        match msg.lobby {
+            Some(lobby) if self.lobbies.contains_key(&msg.id) => {
+                self.public_lobbies.insert(msg.id, lobby);
+            }
+            _ => {
+                self.public_lobbies.remove(&msg.id);
+            }
+        }
+    }
+}
+
+impl Handler<ListPublicLobbies> for LobbyRouter {
+    type Result = MessageResult<ListPublicLobbies>;
+
+    fn handle(&mut self, _: ListPublicLobbies, _: &mut Self::Context) -> Self::Result {
+        let mut lobbies: Vec<_> = self.public_lobbies.values().cloned().collect();
+        lobbies.sort_by(|a, b| a.created_at.cmp(&b.created_at));
+        MessageResult(lobbies)
+    }
+}
+
+impl Handler<RegisterGame> for LobbyRouter {
+    type Result = ();
+
+    fn handle(&mut self, msg: RegisterGame, _: &mut Self::Context) {
+        self.games.insert(msg.id, msg.game);
+        debug!("Game {} registered", msg.id);
+    }
+}
+
+impl Handler<RemoveGame> for LobbyRouter {
+    type Result = ();
+
+    fn handle(&mut self, msg: RemoveGame, _: &mut Self::Context) {
+        if self.games.remove(&msg.0).is_some() {
+            debug!("Game {} removed", msg.0);
+        }
+    }
+}
+
+impl Handler<GetGame> for LobbyRouter {
+    type Result = Option<Addr<actor::Game>>;
+
+    fn handle(&mut self, msg: GetGame, _: &mut Self::Context) -> Self::Result {
+        self.games.get(&msg.0).cloned()
+    }
+}
+
+impl Handler<ListLobbies> for LobbyRouter {
+    type Result = MessageResult<ListLobbies>;
+
+    fn handle(&mut self, _: ListLobbies, _: &mut Self::Context) -> Self::Result {
+        MessageResult(self.lobbies.values().cloned().collect())
+    }
+}
+
+impl Handler<ListGames> for LobbyRouter {
+    type Result = MessageResult<ListGames>;
+
+    fn handle(&mut self, _: ListGames, _: &mut Self::Context) -> Self::Result {
+        MessageResult(self.games.values().cloned().collect())
+    }
+}
+
+impl Handler<Close> for LobbyRouter {
+    type Result = bool;
+
+    fn handle(&mut self, msg: Close, _: &mut Self::Context) -> Self::Result {
+        if let Some(lobby) = self.lobbies.get(&msg.0) {
+            lobby.do_send(Shutdown);
+        } else if let Some(game) = self.games.get(&msg.0) {
+            game.do_send(game::Shutdown);
+        } else {
+            return false;
+        }
+
+        debug!("Closing {}", msg.0);
+        true
+    }
+}
+
+impl Handler<CloseAll> for LobbyRouter {
+    type Result = ();
+
+    fn handle(&mut self, _: CloseAll, _: &mut Self::Context) {
+        debug!("Closing all lobbies and games");
+        self.lobbies.values().for_each(|v| v.do_send(Shutdown));
+        self.games.values().for_each(|v| v.do_send(game::Shutdown));
+    }
+}
+
+impl Handler<ReloadConfig> for LobbyRouter {
+    type Result = ();
+
+    fn handle(&mut self, msg: ReloadConfig, _: &mut Self::Context) {
+        let cfg = msg.0;
+        for lobby in self.lobbies.values() {
+            lobby.do_send(ReloadConfig(Arc::clone(&cfg)));
+        }
+        for game in self.games.values() {
+            game.do_send(ReloadConfig(Arc::clone(&cfg)));
+        }
+        if let Some(matchmaker) = &self.matchmaker {
+            matchmaker.do_send(ReloadConfig(Arc::clone(&cfg)));
+        }
+        debug!("Reloaded the settings");
+        self.cfg = cfg;
+    }
+}
+
+impl Handler<Reconnect> for LobbyRouter {
+    type Result = ();
+
+    fn handle(&mut self, msg: Reconnect, _: &mut Self::Context) {
+        let Some(game) = self.games.get(&msg.id) else {
+            msg.player.do_send(Disconnect::ReconnectFailed);
+            debug!("Game {} does not exist!", msg.id);
+            return;
+        };
+
+        let rejoin = Rejoin {
+            token: msg.token,
+            player: msg.player.clone(),
+        };
+        match game.try_send(rejoin) {
+            Ok(()) => (),
+            Err(SendError::Full(_)) => msg.player.do_send(Disconnect::ServerOverloaded),
+            Err(SendError::Closed(_)) => msg.player.do_send(Disconnect::ReconnectFailed),
+        }
+    }
+}
@@ -0,0 +1,316 @@
+use std::{
+    collections::VecDeque,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use actix::prelude::*;
+use chrono::Utc;
+use tracing::{debug, info_span, Span};
+use uuid::Uuid;
+
+use crate::server::actor::{self, supervisor};
+use crate::server::archive::GameArchive;
+use crate::server::game_log::GameLog;
+use crate::server::load::Load;
+use crate::server::protocol::{Disconnect, OutgoingMessage, ISO_8601_TIMESTAMP};
+use crate::server::rating::RatingStore;
+use crate::server::reload::ReloadConfig;
+use crate::server::webhook::Webhooks;
+use crate::server::{AppConfig, GameConfig, PlayerArray};
+use actor::player::{AttachController, Disconnected, PlayerController};
+use supervisor::StartCatchingPanics;
+
+/// How often players who waited too long are disconnected, and pairs
+/// rejected because of their ratings are tried again.
+const TICK_INTERVAL: Duration = Duration::from_secs(1);
+/// Largest difference between the ratings of two players paired right after
+/// entering the queue.
+const RATING_WINDOW: f64 = 100.0;
+/// How much the largest difference grows for every second the longer
+/// waiting player has spent in the queue.
+const RATING_WINDOW_GROWTH: f64 = 10.0;
+
+/// Adds the player to the queue.
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct Enqueue {
+    pub player: Addr<actor::Player>,
+    /// ID the rating of the player is kept under, if it has one.
+    pub rating_id: Option<String>,
+}
+
+/// Removes the player from the queue and disconnects it, sent after
+/// `matchmakingCancel`.
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct LeaveQueue(pub Addr<actor::Player>);
+
+/// Sent once the rating of a queued player has been read from the store.
+#[derive(Message)]
+#[rtype(result = "()")]
+struct SetRating {
+    player: Addr<actor::Player>,
+    rating: f64,
+}
+
+/// What is known about the rating of a queued player.
+#[derive(Clone, Copy)]
+enum QueuedRating {
+    /// The player has no ID or ratings are disabled, they are paired with
+    /// whoever waits the longest.
+    Unrated,
+    /// The player is not paired until the rating is read.
+    Loading,
+    Rated(f64),
+}
+
+struct Entry {
+    player: Addr<actor::Player>,
+    rating_id: Option<String>,
+    rating: QueuedRating,
+    joined: Instant,
+}
+
+/// Holds players who connected with `matchmake=1`, and starts a game with
+/// the default configuration for every two of them. Players are paired in
+/// the order they came in, or with the player closest to their rating if
+/// both have one.
+pub struct Matchmaker {
+    /// Players waiting for an opponent, longest waiting first.
+    queue: VecDeque<Entry>,
+    router: Addr<actor::LobbyRouter>,
+    archive: Option<Arc<dyn GameArchive>>,
+    ratings: Option<Arc<dyn RatingStore>>,
+    webhooks: Option<Arc<Webhooks>>,
+    game_log: Option<Arc<GameLog>>,
+    /// Span every log event of the queue and its players is recorded in.
+    span: Span,
+    cfg: Arc<AppConfig>,
+}
+
+impl Matchmaker {
+    #[must_use]
+    pub fn new(router: Addr<actor::LobbyRouter>, cfg: Arc<AppConfig>) -> Self {
+        Self {
+            queue: VecDeque::new(),
+            router,
+            archive: None,
+            ratings: None,
+            webhooks: None,
+            game_log: None,
+            span: info_span!("matchmaker"),
+            cfg,
+        }
+    }
+
+    /// Stores every finished game in the archive.
+    #[must_use]
+    pub fn with_archive(mut self, archive: Option<Arc<dyn GameArchive>>) -> Self {
+        self.archive = archive;
+        self
+    }
+
+    /// Pairs players by their ratings, and updates them after every game.
+    #[must_use]
+    pub fn with_ratings(mut self, ratings: Option<Arc<dyn RatingStore>>) -> Self {
+        self.ratings = ratings;
+        self
+    }
+
+    /// Reports every game started from the queue to the webhooks.
+    #[must_use]
+    pub fn with_webhooks(mut self, webhooks: Option<Arc<Webhooks>>) -> Self {
+        self.webhooks = webhooks;
+        self
+    }
+
+    /// Writes every round of the games started from the queue to the game
+    /// log.
+    #[must_use]
+    pub fn with_game_log(mut self, game_log: Option<Arc<GameLog>>) -> Self {
+        self.game_log = game_log;
+        self
+    }
+
+    /// Returns true if the players can be paired, `a` having waited longer.
+    fn compatible(a: &Entry, b: &Entry, now: Instant) -> bool {
+        if a.rating_id.is_some() && a.rating_id == b.rating_id {
+            return false;
+        }
+        match (a.rating, b.rating) {
+            (QueuedRating::Loading, _) | (_, QueuedRating::Loading) => false,
+            (QueuedRating::Rated(a_rating), QueuedRating::Rated(b_rating)) => {
+                let waited = now.duration_since(a.joined).as_secs_f64();
+                (a_rating - b_rating).abs() <= RATING_WINDOW + RATING_WINDOW_GROWTH * waited
+            }
+            _ => true,
+        }
+    }
+
+    /// Returns the positions of the longest waiting player who can be paired
+    /// and of their opponent.
+    fn find_pair(&self, now: Instant) -> Option<(usize, usize)> {
+        self.queue.iter().enumerate().find_map(|(i, a)| {
+            let j = self
+                .queue
+                .iter()
+                .skip(i + 1)
+                .position(|b| Self::compatible(a, b, now))?;
+            Some((i, i + 1 + j))
+        })
+    }
+
+    /// Starts a game for every pair of players found.
+    fn pair_players(&mut self) {
+        let now = Instant::now();
+        while !Load::current().is_at_game_capacity(self.cfg.max_games) {
+            let Some((i, j)) = self.find_pair(now) else {
+                return;
+            };
+            // `j` is always after `i`, so removing it first keeps `i` valid
+            let p2 = self.queue.remove(j).unwrap().player;
+            let p1 = self.queue.remove(i).unwrap().player;
+            let cfg = Arc::clone(&self.cfg);
+            let addrs = PlayerArray::from([p1, p2]);
+            let id = Uuid::new_v4();
+            actor::Game::new(None, GameConfig::default(), 0, None, addrs, Vec::new(), cfg)
+                .with_archive(self.archive.clone())
+                .with_ratings(self.ratings.clone())
+                .with_webhooks(self.webhooks.clone())
+                .with_game_log(self.game_log.clone())
+                .register_with(id, self.router.clone())
+                .start_supervised();
+            debug!("Paired players in game {id}");
+        }
+    }
+
+    /// Disconnects players who have waited longer than
+    /// `MatchmakingConfig::queue_timeout`.
+    fn remove_timed_out(&mut self) {
+        let timeout = self.cfg.matchmaking.queue_timeout;
+        if timeout.is_zero() {
+            return;
+        }
+        self.queue.retain(|entry| {
+            if entry.joined.elapsed() < timeout {
+                return true;
+            }
+            entry.player.do_send(Disconnect::MatchmakingTimedOut);
+            debug!("Player timed out waiting for an opponent");
+            false
+        });
+    }
+
+    /// Reads the rating of the player without blocking the queue.
+    fn load_rating(&self, player: Addr<actor::Player>, id: String, ctx: &mut Context<Self>) {
+        let Some(ratings) = self.ratings.clone() else {
+            return;
+        };
+        let addr = ctx.address();
+        tokio::task::spawn_blocking(move || {
+            let rating = ratings.get(&id).unwrap_or_default().rating;
+            addr.do_send(SetRating { player, rating });
+        });
+    }
+}
+
+impl Actor for Matchmaker {
+    type Context = actix::Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        ctx.run_interval(TICK_INTERVAL, |actor, _| {
+            let _span = actor.span.clone().entered();
+            actor.remove_timed_out();
+            actor.pair_players();
+        });
+    }
+}
+
+impl Handler<Enqueue> for Matchmaker {
+    type Result = ();
+
+    fn handle(&mut self, msg: Enqueue, ctx: &mut Self::Context) {
+        let _span = self.span.clone().entered();
+        let controller = PlayerController::Matchmaker(ctx.address());
+        if msg
+            .player
+            .try_send(AttachController(controller, self.span.clone()))
+            .is_err()
+        {
+            debug!("Failed to attach controller, player was not queued");
+            return;
+        }
+
+        let timeout = self.cfg.matchmaking.queue_timeout;
+        let timeout = (!timeout.is_zero())
+            .then(|| Utc::now() + timeout)
+            .map(|t| t.format(ISO_8601_TIMESTAMP).to_string());
+        let queued = OutgoingMessage::MatchmakingQueued { timeout };
+        msg.player.do_send(queued.into_serialized().unwrap());
+
+        let rating = match &msg.rating_id {
+            Some(id) if self.ratings.is_some() => {
+                self.load_rating(msg.player.clone(), id.clone(), ctx);
+                QueuedRating::Loading
+            }
+            _ => QueuedRating::Unrated,
+        };
+        self.queue.push_back(Entry {
+            player: msg.player,
+            rating_id: msg.rating_id,
+            rating,
+            joined: Instant::now(),
+        });
+        debug!(waiting = self.queue.len(), "Player queued");
+        self.pair_players();
+    }
+}
+
+impl Handler<SetRating> for Matchmaker {
+    type Result = ();
+
+    fn handle(&mut self, msg: SetRating, _: &mut Self::Context) {
+        let _span = self.span.clone().entered();
+        let Some(entry) = self.queue.iter_mut().find(|e| e.player == msg.player) else {
+            return;
+        };
+        entry.rating = QueuedRating::Rated(msg.rating);
+        self.pair_players();
+    }
+}
+
+impl Handler<LeaveQueue> for Matchmaker {
+    type Result = ();
+
+    fn handle(&mut self, msg: LeaveQueue, _: &mut Self::Context) {
+        let _span = self.span.clone().entered();
+        let len = self.queue.len();
+        self.queue.retain(|e| e.player != msg.0);
+        if self.queue.len() < len {
+            msg.0.do_send(Disconnect::MatchmakingCancelled);
+            debug!("Player left the queue");
+        }
+    }
+}
+
+impl Handler<Disconnected> for Matchmaker {
+    type Result = ();
+
+    fn handle(&mut self, msg: Disconnected, _: &mut Self::Context) {
+        let _span = self.span.clone().entered();
+        match msg.0.upgrade() {
+            Some(addr) => self.queue.retain(|e| e.player != addr),
+            None => self.queue.retain(|e| e.player.connected()),
+        }
+        debug!(waiting = self.queue.len(), "Player disconnected");
+    }
+}
+
+impl Handler<ReloadConfig> for Matchmaker {
+    type Result = ();
+
+    fn handle(&mut self, msg: ReloadConfig, _: &mut Self::Context) {
+        self.cfg = msg.0;
+    }
+}
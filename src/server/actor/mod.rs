@@ -1,9 +1,14 @@
+pub mod bot;
 pub mod game;
 pub mod lobby;
 pub mod lobby_router;
+pub mod matchmaker;
 pub mod player;
+pub mod supervisor;
 
+pub use bot::Bot;
 pub use game::Game;
 pub use lobby::Lobby;
 pub use lobby_router::LobbyRouter;
+pub use matchmaker::Matchmaker;
 pub use player::Player;
@@ -1,447 +1,683 @@
-use std::time::Duration;
 use std::{sync::Arc, time::Instant};
 
 use actix::{prelude::*, WeakAddr};
 use actix_web_actors::ws::{self, CloseReason};
 use bytestring::ByteString;
-use chrono::{DateTime, Utc};
-use log::{debug, error};
-use serde::{Deserialize, Serialize};
-use uuid::Uuid;
-
-use crate::game::{self, Game};
-use crate::server::serde::as_millis_optional_tuple;
-use crate::server::{actor, AppConfig, GameConfig, PartialGameConfig};
-use actor::game::{EndTurn, PlayerSelectionVote, Restart, RestartResponse};
-
-const ISO_8601_TIMESTAMP: &str = "%Y-%m-%dT%H:%M:%S%.3fZ";
+use chrono::Utc;
+use tokio::sync::watch;
+use tracing::{debug, error, info_span, Span};
+
+use crate::game::Action;
+use crate::server::compression;
+use crate::server::connection_limit::ConnectionGuard;
+use crate::server::encoding::{EncodeError, Encoded, Encoding, EncodingGuard};
+use crate::server::error::ProtocolError;
+use crate::server::latency::LatencyTracker;
+use crate::server::load::LoadGuard;
+use crate::server::locale::Locale;
+use crate::server::outgoing_queue::OutgoingQueue;
+use crate::server::profile::Profile;
+use crate::server::protocol::{
+    self, ConfigRejection, Disconnect, IncomingEndTurn, IncomingMessage, IncomingPickPair,
+    IncomingPickPlayers, IncomingPickSpectator, IncomingRestart, IncomingSetPublic,
+    IncomingStartSeries, Notice, OutgoingMessage, ProtocolVersion, QrFormat, ISO_8601_TIMESTAMP,
+};
+use crate::server::rate_limit::TokenBucket;
+use crate::server::recording::{Direction, SessionRecorder};
+use crate::server::reload::{self, ReloadConfig};
+use crate::server::{actor, AppConfig};
+use actor::game::{
+    Annotate, ChangeTiming, ChangeTimingResponse, EndTurn, Hover, Identify, PlayerSelectionVote,
+    Queue, RequestSync, Resign, Restart, RestartResponse, SwapSides, UpdateLatency,
+};
+use actor::lobby::{
+    KickPlayer, PickPair, PickPlayers, PickSpectator, RegenerateInvite, RequestLink, SetConfig,
+    SetPassword, SetPublic, SetReady, StartSeries,
+};
+use actor::matchmaker::LeaveQueue;
+
+/// Payload of the pings sent after queued messages. The client has read
+/// every message written before the ping once it answers.
+const QUEUE_PROBE: &[u8] = b"queue";
 
 // Outgoing messages
 
-#[derive(Serialize)]
-#[serde(tag = "type", rename_all = "camelCase")]
-pub enum OutgoingMessage<'a> {
-    LobbyLink(OutgoingLobbyLink),
-    LobbySync { players: &'a [u8] },
-    LobbyCode { code: u8 },
-    GameSetup(OutgoingGameSetup<'a>),
-    GamePlayerSelection(OutgoingPlayerSelection),
-    GameSync(OutgoingGameSync<'a>),
-    GameRestartRequest(OutgoingRestartRequest<'a>),
-    Pong { sent: f64, received: String },
-}
-
-impl<'a> OutgoingMessage<'a> {
-    /// Constructs a new `OutgoingMessage::LobbyLink`.
-    #[must_use]
-    pub fn lobby_link(uuid: Uuid, cfg: &AppConfig) -> Self {
-        OutgoingLobbyLink::new(uuid, cfg).into()
-    }
-
-    /// Returns an `OutgoingMessage::GameSetup` builder.
-    #[must_use]
-    pub fn game_setup(config: Option<&'a GameConfig>, role: Option<game::Player>) -> Self {
-        OutgoingGameSetup { config, role }.into()
-    }
-
-    /// Constructs a new `OutgoingMessage::GamePlayerSelection`.
-    #[must_use]
-    pub fn game_player_selection(p1_voted: bool, p2_voted: bool) -> Self {
-        OutgoingPlayerSelection { p1_voted, p2_voted }.into()
-    }
-
-    /// Constructs a new `OutgoingMessage::GameSync`.
-    #[must_use]
-    pub fn game_sync(round: u32, game: &'a Game, timeout: Option<DateTime<Utc>>) -> Self {
-        OutgoingGameSync::new(round, game, timeout).into()
-    }
-
-    /// Constructs a new `OutgoingMessage::GameRestartRequest`.
-    #[must_use]
-    pub fn game_restart_request(player: game::Player, req: Option<RestartRequest<'a>>) -> Self {
-        OutgoingRestartRequest { player, req }.into()
-    }
-
+impl OutgoingMessage<'_> {
     // These messages should always be sent. Serializing is the last moment they
     // can be logged.
 
     /// Attempts to convert the message into a `SerializedOutgoingMessage`.
-    pub fn into_serialized(self) -> Result<SerializedOutgoingMessage, serde_json::Error> {
+    pub fn into_serialized(self) -> Result<SerializedOutgoingMessage, EncodeError> {
         debug!("Sending {} message (serialized)", self.variant_name());
         self.try_into()
     }
 
     /// Attempts to convert the message into a `SharedOutgoingMessage`.
-    pub fn into_shared(self) -> Result<SharedOutgoingMessage, serde_json::Error> {
+    pub fn into_shared(self) -> Result<SharedOutgoingMessage, EncodeError> {
         debug!("Sending {} message (shared)", self.variant_name());
         self.try_into()
     }
-
-    /// Returns name of the variant which will be used in the `type` property
-    /// of the message.
-    fn variant_name(&self) -> &'static str {
-        match self {
-            Self::LobbyLink(_) => "lobbyLink",
-            Self::LobbySync { .. } => "lobbySync",
-            Self::LobbyCode { .. } => "lobbyCode",
-            Self::GameSetup(_) => "gameSetup",
-            Self::GamePlayerSelection(_) => "gamePlayerSelection",
-            Self::GameSync(_) => "gameSync",
-            Self::GameRestartRequest(_) => "gameRestartRequest",
-            Self::Pong { .. } => "pong",
-        }
-    }
-}
-
-/// Contents of `OutgoingMessage::LobbyLink`.
-#[derive(Serialize)]
-#[serde(rename_all = "camelCase")]
-pub struct OutgoingLobbyLink {
-    /// Lobby ID.
-    lobby: String,
-    qr_code: QR,
-}
-
-impl OutgoingLobbyLink {
-    #[must_use]
-    pub fn new(uuid: Uuid, cfg: &AppConfig) -> Self {
-        fn generate_lobby_url(app_config: &AppConfig, lobby_id: &str) -> String {
-            use qstring::QString;
-            let mut url = app_config.url_base.clone();
-            let query = QString::new(vec![(&app_config.url_lobby_parameter, lobby_id)]);
-            url.set_query(Some(&query.to_string()));
-            url.into()
-        }
-
-        let lobby = uuid.as_hyphenated().to_string();
-        let qr_code = QR::generate(&generate_lobby_url(cfg, &lobby)).unwrap_or_default();
-        Self { lobby, qr_code }
-    }
-}
-
-impl<'a> From<OutgoingLobbyLink> for OutgoingMessage<'a> {
-    fn from(msg: OutgoingLobbyLink) -> Self {
-        Self::LobbyLink(msg)
-    }
 }
 
-/// Contents of `OutgoingMessage::GameSetup` with builder functions for
-/// setting fields.
-#[derive(Serialize)]
-#[serde(rename_all = "camelCase")]
-pub struct OutgoingGameSetup<'a> {
-    /// Game configuration.
-    #[serde(skip_serializing_if = "Option::is_none")]
-    config: Option<&'a GameConfig>,
-    /// Tells the client which player controls it - `P1` (blue) or `P2` (red)
-    #[serde(skip_serializing_if = "Option::is_none")]
-    role: Option<game::Player>,
-}
+// Internal messages
 
-impl<'a> From<OutgoingGameSetup<'a>> for OutgoingMessage<'a> {
-    fn from(msg: OutgoingGameSetup<'a>) -> Self {
-        Self::GameSetup(msg)
-    }
-}
+/// Serialized message, along with its version 1 form if it differs, see
+/// `OutgoingMessage::to_v1`.
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct SerializedOutgoingMessage(
+    Encoded,
+    ProtocolVersion,
+    Option<&'static str>,
+    Option<Encoded>,
+);
 
-/// Contents of `OutgoingMessage::PlayerSelection`.
-#[derive(Serialize)]
-#[serde(rename_all = "camelCase")]
-pub struct OutgoingPlayerSelection {
-    pub p1_voted: bool,
-    pub p2_voted: bool,
-}
+impl TryFrom<OutgoingMessage<'_>> for SerializedOutgoingMessage {
+    type Error = EncodeError;
 
-impl<'a> From<OutgoingPlayerSelection> for OutgoingMessage<'a> {
-    fn from(msg: OutgoingPlayerSelection) -> Self {
-        Self::GamePlayerSelection(msg)
+    fn try_from(msg: OutgoingMessage) -> Result<Self, Self::Error> {
+        let encoded = Encoded::new(&msg)?;
+        let v1 = msg.to_v1().map(|v1| Encoded::new(&v1)).transpose()?;
+        Ok(Self(encoded, msg.min_version(), msg.snapshot_kind(), v1))
     }
 }
 
-/// Contents of `OutgoingMessage::GameSync`.
-#[derive(Serialize)]
-#[serde(rename_all = "camelCase")]
-pub struct OutgoingGameSync<'a> {
-    round: u32,
-    game: &'a Game,
-    /// ISO 8601 timestamp of when the turn will be ended automatically.
-    timeout: Option<String>,
-}
+/// Stores the converted message as an `Arc<Encoded>`, allowing it to be sent
+/// to multiple players.
+#[derive(Message, Clone)]
+#[rtype(result = "()")]
+pub struct SharedOutgoingMessage(
+    Arc<Encoded>,
+    ProtocolVersion,
+    Option<&'static str>,
+    Option<Arc<Encoded>>,
+);
 
-impl<'a> OutgoingGameSync<'a> {
-    #[must_use]
-    pub fn new(round: u32, game: &'a Game, timeout: Option<DateTime<Utc>>) -> Self {
-        Self {
-            round,
-            game,
-            timeout: timeout.map(|t| t.format(ISO_8601_TIMESTAMP).to_string()),
-        }
-    }
-}
+impl TryFrom<OutgoingMessage<'_>> for SharedOutgoingMessage {
+    type Error = EncodeError;
 
-impl<'a> From<OutgoingGameSync<'a>> for OutgoingMessage<'a> {
-    fn from(msg: OutgoingGameSync<'a>) -> Self {
-        Self::GameSync(msg)
+    fn try_from(msg: OutgoingMessage) -> Result<Self, Self::Error> {
+        SerializedOutgoingMessage::try_from(msg).map(Self::from)
     }
 }
 
-/// Updates the status of restart request of the given player.
-#[derive(Serialize)]
-#[serde(rename_all = "camelCase")]
-pub struct OutgoingRestartRequest<'a> {
-    /// Player who made the request.
-    player: game::Player,
-    /// Restart request details; `None` if it expired.
-    #[serde(skip_serializing_if = "Option::is_none")]
-    req: Option<RestartRequest<'a>>,
-}
-
-impl<'a> From<OutgoingRestartRequest<'a>> for OutgoingMessage<'a> {
-    fn from(msg: OutgoingRestartRequest<'a>) -> Self {
-        Self::GameRestartRequest(msg)
+impl From<SerializedOutgoingMessage> for SharedOutgoingMessage {
+    fn from(
+        SerializedOutgoingMessage(encoded, version, snapshot, v1): SerializedOutgoingMessage,
+    ) -> Self {
+        Self(Arc::new(encoded), version, snapshot, v1.map(Arc::new))
     }
 }
 
-/// Restart request made when the game cannot be restarted without asking
-/// the permission of the opponent first.
-#[derive(Serialize)]
-#[serde(rename_all = "camelCase")]
-pub struct RestartRequest<'a> {
-    /// Changed configuration, if any.
-    config: Option<&'a GameConfig>,
-    /// ISO 8601 timestamp of when the restart request will expire.
-    timeout: String,
+/// Sends `latest` to players whose protocol version supports it, and
+/// `fallback` to everyone else.
+#[derive(Message, Clone)]
+#[rtype(result = "()")]
+pub struct VersionedOutgoingMessage {
+    pub latest: SharedOutgoingMessage,
+    pub fallback: SharedOutgoingMessage,
 }
 
-impl<'a> RestartRequest<'a> {
-    #[must_use]
-    pub fn new(config: Option<&'a GameConfig>, timeout: DateTime<Utc>) -> Self {
-        let timeout = timeout.format(ISO_8601_TIMESTAMP).to_string();
-        Self { config, timeout }
-    }
+/// Sends `svg` to players who asked for SVG QR codes, and `png` to everyone
+/// else.
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct QrOutgoingMessage {
+    pub png: SerializedOutgoingMessage,
+    pub svg: SerializedOutgoingMessage,
 }
 
-/// QR code representation sent over to the client.
-#[derive(Serialize, Default)]
-struct QR {
-    /// Base64-encoded PNG.
-    img: String,
-    /// The number of modules per side.
-    width: usize,
-}
+/// Holds back the messages sent to the player until `EndBatch`, to send them
+/// in a single `OutgoingMessage::Batch`. Ignored unless the client connected
+/// with `batch=1`.
+#[derive(Message, Clone)]
+#[rtype(result = "()")]
+pub struct BeginBatch;
 
-impl QR {
-    /// Attempts to generate a QR code with specified contents.
-    fn generate(contents: &str) -> Result<Self, ()> {
-        use base64::{engine::general_purpose, Engine as _};
-        use image::{png::PngEncoder, ColorType, Luma};
-        use qrcode::{EcLevel, QrCode};
-        let mut img = Vec::new();
-
-        let qr = QrCode::with_error_correction_level(contents, EcLevel::L).map_err(|_| ())?;
-        let img_buf = qr
-            .render::<Luma<u8>>()
-            .max_dimensions(0, 0)
-            .quiet_zone(false)
-            .build();
-
-        PngEncoder::new(&mut img)
-            .encode(&img_buf, img_buf.width(), img_buf.height(), ColorType::L8)
-            .map_err(|_| ())?;
-
-        Ok(Self {
-            img: general_purpose::STANDARD.encode(&img),
-            width: qr.width(),
-        })
-    }
-}
+/// Sends the messages held back since `BeginBatch`.
+#[derive(Message, Clone)]
+#[rtype(result = "()")]
+pub struct EndBatch;
 
-// Incoming messages
-
-#[derive(Deserialize)]
-#[serde(tag = "type", rename_all = "camelCase")]
-enum IncomingMessage {
-    LobbyPickPlayer(IncomingPickPlayer),
-    GamePlayerSelectionVote(IncomingPlayerSelectionVote),
-    GameEndTurn(IncomingEndTurn),
-    GameRestart(IncomingRestart),
-    GameRestartResponse { accepted: bool },
-    Ping { sent: f64 },
-}
+/// Hands the player over to a new controller. Log events of the player are
+/// recorded in the span of the controller from then on.
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct AttachController(pub PlayerController, pub Span);
 
-impl IncomingMessage {
-    fn variant_name(&self) -> &'static str {
-        match self {
-            Self::LobbyPickPlayer(_) => "lobbyPickPlayer",
-            Self::GamePlayerSelectionVote(_) => "gamePlayerSelectionVote",
-            Self::GameEndTurn(_) => "gameEndTurn",
-            Self::GameRestart(_) => "gameRestart",
-            Self::GameRestartResponse { .. } => "gameRestartResponse",
-            Self::Ping { .. } => "ping",
-        }
-    }
+/// Contains an address to the actor currently managing the connection.
+pub enum PlayerController {
+    Lobby(Addr<actor::Lobby>),
+    Game(Addr<actor::Game>),
+    Matchmaker(Addr<actor::Matchmaker>),
 }
 
-/// Contents of `IncomingMessage::LobbyPickPlayer`.
-#[derive(Message, Deserialize)]
-#[serde(rename_all = "camelCase")]
+#[derive(Message)]
 #[rtype(result = "()")]
-pub struct IncomingPickPlayer {
-    /// Player's code.
-    pub code: u8,
-    /// Role which should be assigned to the player.
-    pub role: game::Player,
-    /// State of the local game, or `None` if the client is in player selection.
-    pub game: Option<Game>,
-    /// Game configuration, any missing fields will be set to their default value.
-    pub config: PartialGameConfig,
-    pub round: u32,
-    /// In timed games, the extra time each player has in milliseconds.
-    #[serde(with = "as_millis_optional_tuple", default)]
-    pub extra_time: Option<[Duration; 2]>,
-}
+pub struct Disconnected(pub WeakAddr<Player>);
 
-/// Contents of `IncomingMessage::GamePlayerSelectionVote`.
-#[derive(Deserialize)]
-#[serde(rename_all = "camelCase")]
-struct IncomingPlayerSelectionVote {
-    wants_to_start: bool,
+/// Chat message sent by the player to its controller.
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct Chat {
+    pub addr: Addr<Player>,
+    pub text: String,
 }
 
-/// Contents of `IncomingMessage::GameEndTurn`.
-#[derive(Deserialize)]
-struct IncomingEndTurn {
-    /// The turn the player wants to end.
-    turn: u32,
-    /// Move the player wants to make, if any.
-    #[serde(default)]
-    col: Option<usize>,
-}
+/// Asks the controller to send the player its complete state.
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct RequestFullSync(pub Addr<Player>);
 
-/// Contents of `IncomingMessage::GameRestart`.
-#[derive(Deserialize)]
-#[serde(rename_all = "camelCase")]
-struct IncomingRestart {
-    /// Changes to the configuration, if any.
-    #[serde(flatten)]
-    partial: Option<PartialGameConfig>,
+/// Sent by the player to its controller once messages start piling up in its
+/// outgoing queue, and again once the queue is empty. Controllers should send
+/// fewer syncs to congested players, and a full sync once they catch up.
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct Backpressure {
+    pub addr: Addr<Player>,
+    pub congested: bool,
 }
 
-// Internal messages
-
+/// Closes the connection with the reason, like `Disconnect`, but on behalf of
+/// someone other than the controller, which is told that the player left.
 #[derive(Message)]
 #[rtype(result = "()")]
-pub struct SerializedOutgoingMessage(String);
-
-impl<'a> TryFrom<OutgoingMessage<'a>> for SerializedOutgoingMessage {
-    type Error = serde_json::Error;
+pub struct Kick(pub Disconnect);
 
-    fn try_from(msg: OutgoingMessage) -> Result<Self, Self::Error> {
-        Ok(Self(serde_json::to_string(&msg)?))
-    }
-}
+/// Sent to the player when a game configuration it picked was rejected,
+/// described in the player's locale.
+#[derive(Message, Clone, Copy)]
+#[rtype(result = "()")]
+pub struct RejectConfig(pub ConfigRejection);
 
-/// Stores the converted message as an `Arc<String>`, allowing it to be sent to
-/// multiple players.
+/// Sent to the player to announce an event in the chat, described in the
+/// player's locale.
 #[derive(Message, Clone)]
 #[rtype(result = "()")]
-pub struct SharedOutgoingMessage(Arc<String>);
-
-impl<'a> TryFrom<OutgoingMessage<'a>> for SharedOutgoingMessage {
-    type Error = serde_json::Error;
+pub struct SendNotice {
+    pub notice: Notice,
+    /// ISO 8601 timestamp of when the event happened.
+    pub timestamp: String,
+}
 
-    fn try_from(msg: OutgoingMessage) -> Result<Self, Self::Error> {
-        let msg = serde_json::to_string(&msg)?;
-        Ok(Self(Arc::new(msg)))
+impl SendNotice {
+    /// Constructs a new `SendNotice`, timestamped with the current time.
+    #[must_use]
+    pub fn now(notice: Notice) -> Self {
+        Self {
+            notice,
+            timestamp: Utc::now().format(ISO_8601_TIMESTAMP).to_string(),
+        }
     }
 }
 
+/// Sent by a player who connected with a valid token once a lobby or game
+/// has become its controller, see `server::auth`.
 #[derive(Message)]
 #[rtype(result = "()")]
-pub struct AttachController(pub PlayerController);
-
-/// Contains an address to the actor currently managing the connection.
-pub enum PlayerController {
-    Lobby(Addr<actor::Lobby>),
-    Game(Addr<actor::Game>),
+pub struct Authenticated {
+    pub addr: Addr<Player>,
+    /// The `sub` claim of the token.
+    pub subject: String,
 }
 
+/// Sent by the player to its controller whenever its profile changes, and
+/// once a new controller is attached.
 #[derive(Message)]
 #[rtype(result = "()")]
-pub struct Disconnected(pub WeakAddr<Player>);
-
-#[derive(Serialize, Message, Clone, Copy)]
-#[serde(rename_all = "camelCase")]
-#[rtype(result = "()")]
-pub enum Disconnect {
-    ServerMaxLobbies,
-    InviteInvalid,
-    LobbyJoinError,
-    LobbyFull,
-    LobbyClosed,
-    GameStarted,
-    GameEnded,
-    LobbyOverloaded,
-    ServerOverloaded,
-    ShuttingDown,
-}
-
-impl Disconnect {
-    fn as_str(self) -> &'static str {
-        match self {
-            Self::ServerMaxLobbies => "serverMaxLobbies",
-            Self::InviteInvalid => "inviteInvalid",
-            Self::LobbyJoinError => "lobbyJoinError",
-            Self::LobbyFull => "lobbyFull",
-            Self::LobbyClosed => "lobbyClosed",
-            Self::GameStarted => "gameStarted",
-            Self::GameEnded => "gameEnded",
-            Self::LobbyOverloaded => "lobbyOverloaded",
-            Self::ServerOverloaded => "serverOverloaded",
-            Self::ShuttingDown => "shuttingDown",
-        }
-    }
+pub struct UpdateProfile {
+    pub addr: Addr<Player>,
+    pub profile: Profile,
 }
 
+#[allow(clippy::struct_excessive_bools)] // independent connection options
 pub struct Player {
     hb: Instant,
+    /// Sends the heartbeat pings, `None` if heartbeats are disabled.
+    heartbeat: Option<SpawnHandle>,
+    /// When the last heartbeat ping was sent, `None` once answered.
+    ping_sent: Option<Instant>,
+    latency: LatencyTracker,
     controller: Option<PlayerController>,
     disconnected_by_controller: bool,
+    recorder: Option<SessionRecorder>,
+    /// Counts the connection towards the limit of the client's IP until the
+    /// player stops.
+    connection: Option<ConnectionGuard>,
+    /// Counts the player towards the load of the server until it stops.
+    _load: LoadGuard,
+    /// Messages newer than this version are not sent to the client.
+    protocol: ProtocolVersion,
+    /// Whether the client can decompress long messages.
+    compress: bool,
+    /// Encoding of the messages, binary encodings are never compressed.
+    encoding: Encoding,
+    /// Counts the player as a client of its encoding until it stops.
+    _encoding: EncodingGuard,
+    /// Whether the client accepts `OutgoingMessage::Batch`.
+    batching: bool,
+    /// Messages held back until `EndBatch`, and whether they are snapshots.
+    batch: Option<Vec<(Arc<Encoded>, Option<&'static str>)>>,
+    /// Image format of QR codes sent to the client.
+    qr_format: QrFormat,
+    /// Language of the descriptions sent to the client, `None` if it did not
+    /// pick one.
+    locale: Option<Locale>,
+    /// Messages waiting until the client has read the previous ones.
+    outgoing: OutgoingQueue<Arc<Encoded>>,
+    /// Whether the controller was told that the outgoing queue is
+    /// congested.
+    congested: bool,
+    /// Persistent ID used to keep track of the player's rating.
+    persistent_id: Option<String>,
+    /// User of the account system the player authenticated as.
+    subject: Option<String>,
+    profile: Option<Profile>,
+    /// Limits messages other than pings.
+    message_limit: TokenBucket,
+    ping_limit: TokenBucket,
+    /// The amount of messages dropped due to rate limiting.
+    dropped: u32,
+    /// Short random ID identifying the connection in logs.
+    connection_id: String,
+    /// Span every log event of the player is recorded in.
+    span: Span,
+    /// Notified whenever the settings are reloaded, taken once started.
+    config_updates: Option<watch::Receiver<Arc<AppConfig>>>,
     cfg: Arc<AppConfig>,
 }
 
 impl Player {
-    fn hb(&self, ctx: &mut ws::WebsocketContext<Self>) {
+    /// Sends a WebSocket ping every heartbeat interval, and disconnects the
+    /// client if nothing has been received from it within the timeout.
+    fn hb(&mut self, ctx: &mut ws::WebsocketContext<Self>) {
+        if let Some(handle) = self.heartbeat.take() {
+            ctx.cancel_future(handle);
+        }
         if self.cfg.heartbeat_interval.is_zero() || self.cfg.heartbeat_timeout.is_zero() {
             return;
         }
 
         let timeout = self.cfg.heartbeat_timeout;
-        ctx.run_interval(self.cfg.heartbeat_interval, move |actor, ctx| {
+        let handle = ctx.run_interval(self.cfg.heartbeat_interval, move |actor, ctx| {
+            let _span = actor.span.clone().entered();
             if Instant::now().duration_since(actor.hb) > timeout {
                 ctx.stop();
                 debug!("Timed out");
+                return;
             }
+            actor.ping_sent = Some(Instant::now());
+            ctx.ping(b"");
         });
+        self.heartbeat = Some(handle);
+    }
+
+    /// Sends the latest latency measurements to the game, if any.
+    fn report_latency(&self, ctx: &mut ws::WebsocketContext<Self>) {
+        if let Some(PlayerController::Game(game)) = &self.controller {
+            game.do_send(UpdateLatency {
+                addr: ctx.address(),
+                latency: self.latency,
+            });
+        }
     }
 
     #[must_use]
     pub fn new(app_config: Arc<AppConfig>) -> Self {
+        let limits = &app_config.rate_limit;
+        let connection_id = format!("{:08x}", rand::random::<u32>());
         Self {
             hb: Instant::now(),
+            heartbeat: None,
+            ping_sent: None,
+            latency: LatencyTracker::default(),
             controller: None,
             disconnected_by_controller: false,
+            recorder: None,
+            connection: None,
+            _load: LoadGuard::player(),
+            protocol: ProtocolVersion::LATEST,
+            compress: false,
+            encoding: Encoding::Json,
+            _encoding: Encoding::Json.track(),
+            batching: false,
+            batch: None,
+            qr_format: QrFormat::Png,
+            locale: None,
+            outgoing: OutgoingQueue::new(&app_config.outgoing_queue),
+            congested: false,
+            persistent_id: None,
+            subject: None,
+            profile: None,
+            message_limit: TokenBucket::new(limits.messages_per_second, limits.message_burst),
+            ping_limit: TokenBucket::new(limits.pings_per_second, limits.ping_burst),
+            dropped: 0,
+            span: info_span!("conn", id = %connection_id),
+            connection_id,
+            config_updates: None,
             cfg: app_config,
         }
     }
 
-    fn handle_text_message(&mut self, text: &ByteString, ctx: &mut ws::WebsocketContext<Self>) {
-        use PlayerController::*;
+    /// Records every frame sent and received by this player.
+    #[must_use]
+    pub fn with_recorder(mut self, recorder: SessionRecorder) -> Self {
+        self.recorder = Some(recorder);
+        self
+    }
+
+    /// Keeps the connection counted until the player stops.
+    #[must_use]
+    pub fn with_connection_guard(mut self, guard: Option<ConnectionGuard>) -> Self {
+        self.connection = guard;
+        self
+    }
+
+    /// Sets the protocol version negotiated with the client.
+    #[must_use]
+    pub fn with_protocol(mut self, protocol: ProtocolVersion) -> Self {
+        self.protocol = protocol;
+        self
+    }
+
+    /// Sends messages of `AppConfig::compression_threshold` bytes or longer
+    /// as compressed binary frames.
+    #[must_use]
+    pub fn with_compression(mut self, compress: bool) -> Self {
+        self.compress = compress;
+        self
+    }
+
+    /// Exchanges messages with the client in the encoding instead of JSON.
+    #[must_use]
+    pub fn with_encoding(self, encoding: Encoding) -> Self {
+        Self {
+            encoding,
+            _encoding: encoding.track(),
+            ..self
+        }
+    }
+
+    /// Lets the controller group messages with `BeginBatch` and `EndBatch`.
+    #[must_use]
+    pub fn with_batching(mut self, batching: bool) -> Self {
+        self.batching = batching;
+        self
+    }
+
+    /// Sends QR codes of invites in the format.
+    #[must_use]
+    pub fn with_qr_format(mut self, format: QrFormat) -> Self {
+        self.qr_format = format;
+        self
+    }
+
+    /// Describes disconnect reasons, config rejections and chat notices to
+    /// the client in the locale.
+    #[must_use]
+    pub fn with_locale(mut self, locale: Option<Locale>) -> Self {
+        self.locale = locale;
+        self
+    }
+
+    /// Identifies the player to every game they join, so that their rating
+    /// can be updated.
+    #[must_use]
+    pub fn with_persistent_id(mut self, id: Option<String>) -> Self {
+        self.persistent_id = id;
+        self
+    }
 
-        let Ok(msg) = serde_json::from_str::<IncomingMessage>(text) else {
-            debug!("Failed to parse message!");
+    /// Tells every lobby and game the player joins which user of the account
+    /// system it authenticated as. Replaces the player ID in ratings.
+    #[must_use]
+    pub fn with_subject(mut self, subject: Option<String>) -> Self {
+        self.subject = subject;
+        self
+    }
+
+    /// Applies the settings whenever they are reloaded.
+    #[must_use]
+    pub fn with_config_updates(mut self, updates: watch::Receiver<Arc<AppConfig>>) -> Self {
+        self.config_updates = Some(updates);
+        self
+    }
+
+    /// Queues the message, writing it once the client has read enough of the
+    /// previous ones. Disconnects the client if the queue overflows.
+    fn send(
+        &mut self,
+        msg: Arc<Encoded>,
+        snapshot: Option<&'static str>,
+        ctx: &mut ws::WebsocketContext<Self>,
+    ) {
+        if let Some(batch) = &mut self.batch {
+            batch.push((msg, snapshot));
             return;
+        }
+        if self.outgoing.is_disabled() {
+            self.write(&msg, ctx);
+            return;
+        }
+        if self.outgoing.push(msg, snapshot).is_err() {
+            debug!("Outgoing queue overflowed, disconnecting");
+            self.close(Disconnect::TooSlow, ctx);
+            return;
+        }
+        self.flush(ctx);
+    }
+
+    /// Sends the messages held back since `BeginBatch`, as a single
+    /// `OutgoingMessage::Batch` if there are several.
+    fn end_batch(&mut self, ctx: &mut ws::WebsocketContext<Self>) {
+        let Some(mut batch) = self.batch.take() else {
+            return;
+        };
+        if batch.len() > 1 {
+            let messages: Vec<_> = batch.iter().map(|(msg, _)| msg.as_ref()).collect();
+            self.send(Arc::new(Encoded::batch(&messages)), None, ctx);
+        } else if let Some((msg, snapshot)) = batch.pop() {
+            self.send(msg, snapshot, ctx);
+        }
+    }
+
+    /// Writes every queued message the client can receive now, and probes
+    /// the client for the ones in flight.
+    fn flush(&mut self, ctx: &mut ws::WebsocketContext<Self>) {
+        while let Some(msg) = self.outgoing.pop_ready() {
+            self.write(&msg, ctx);
+        }
+        if self.outgoing.start_probe() {
+            ctx.ping(QUEUE_PROBE);
+        }
+
+        let congested = if self.congested {
+            !self.outgoing.is_empty()
+        } else {
+            self.outgoing.is_congested()
+        };
+        if congested != self.congested {
+            self.congested = congested;
+            debug!(congested, "Outgoing queue congestion changed");
+            self.report_backpressure(ctx);
+        }
+    }
+
+    /// Tells the controller whether the outgoing queue is congested.
+    fn report_backpressure(&self, ctx: &mut ws::WebsocketContext<Self>) {
+        let msg = Backpressure {
+            addr: ctx.address(),
+            congested: self.congested,
+        };
+        match &self.controller {
+            Some(PlayerController::Lobby(lobby)) => lobby.do_send(msg),
+            Some(PlayerController::Game(game)) => game.do_send(msg),
+            Some(PlayerController::Matchmaker(_)) | None => (),
+        }
+    }
+
+    /// Sends a text frame to the client, or a compressed binary frame if the
+    /// text is long enough. Clients using a binary encoding receive the
+    /// message as a binary frame in that encoding instead.
+    fn write(&mut self, msg: &Encoded, ctx: &mut ws::WebsocketContext<Self>) {
+        let text = msg.json();
+        if let Some(recorder) = &mut self.recorder {
+            recorder.record(Direction::Outbound, text);
+        }
+
+        if self.encoding.is_binary() {
+            if let Some(encoded) = msg.binary(self.encoding) {
+                ctx.binary(encoded.to_vec());
+            } else {
+                error!("Message was not encoded in {}", self.encoding.name());
+            }
+            return;
+        }
+
+        let threshold = self.cfg.compression_threshold;
+        if self.compress && threshold > 0 && text.len() >= threshold {
+            match compression::deflate(text) {
+                Ok(compressed) => {
+                    ctx.binary(compressed);
+                    return;
+                }
+                Err(e) => error!("Failed to compress message: {e}"),
+            }
+        }
+        ctx.text(text);
+    }
+
+    /// Disconnects the client once it has exceeded the rate limit too many
+    /// times.
+    fn on_message_dropped(&mut self, ctx: &mut ws::WebsocketContext<Self>) {
+        self.dropped += 1;
+        debug!("Message dropped, rate limit exceeded");
+        let max_dropped = self.cfg.rate_limit.max_dropped;
+        if max_dropped != 0 && self.dropped >= max_dropped {
+            debug!("Too many messages dropped, disconnecting");
+            self.close(Disconnect::RateLimited, ctx);
+        }
+    }
+
+    /// Tells the client why its message was not processed. Clients which
+    /// only support protocol version 1 do not get the message.
+    fn report_error(&mut self, err: &ProtocolError, ctx: &mut ws::WebsocketContext<Self>) {
+        debug!("Rejected message: {err}");
+        let msg = OutgoingMessage::Error {
+            reason: err.reason(),
+            message: err.to_string(),
+        };
+        self.send_message(msg, ctx);
+    }
+
+    /// Sends a message built by the player itself, unless the client's
+    /// protocol version does not include it.
+    fn send_message(&mut self, msg: OutgoingMessage, ctx: &mut ws::WebsocketContext<Self>) {
+        if msg.min_version() > self.protocol {
+            return;
+        }
+        let Ok(msg) = msg.into_serialized() else {
+            debug!("Failed to serialize message");
+            return;
+        };
+        self.send_shared(msg.into(), ctx);
+    }
+
+    /// Sends a message serialized elsewhere, in its version 1 form to
+    /// version 1 clients, unless their protocol version does not include it.
+    fn send_shared(&mut self, msg: SharedOutgoingMessage, ctx: &mut ws::WebsocketContext<Self>) {
+        let SharedOutgoingMessage(encoded, min_version, snapshot, v1) = msg;
+        if min_version > self.protocol {
+            return;
+        }
+        let encoded = match v1 {
+            Some(v1) if self.protocol == ProtocolVersion::V1 => v1,
+            _ => encoded,
+        };
+        self.send(encoded, snapshot, ctx);
+    }
+
+    /// Closes the connection with the reason, after writing every queued
+    /// message.
+    fn close(&mut self, d: Disconnect, ctx: &mut ws::WebsocketContext<Self>) {
+        self.end_batch(ctx);
+        let queued: Vec<_> = self.outgoing.drain().collect();
+        for msg in queued {
+            self.write(&msg, ctx);
+        }
+        if let Some(locale) = self.locale.filter(|_| self.protocol >= ProtocolVersion::V2) {
+            let msg = OutgoingMessage::Closing {
+                reason: d.as_str().into(),
+                code: protocol::close_code(d),
+                description: locale.disconnect(d).into(),
+            };
+            if let Ok(encoded) = Encoded::new(&msg) {
+                self.write(&encoded, ctx);
+            }
+        }
+        if let Some(mut recorder) = self.recorder.take() {
+            recorder.record_close(Some(d.as_str()));
+        }
+        // Close codes are new in version 2, older clients keep getting 1000
+        let code = if self.protocol >= ProtocolVersion::V2 {
+            ws::CloseCode::Other(protocol::close_code(d))
+        } else {
+            ws::CloseCode::Normal
+        };
+        ctx.close(Some(CloseReason {
+            code,
+            description: Some(String::from(d.as_str())),
+        }));
+        ctx.stop();
+    }
+
+    fn handle_text_message(
+        &mut self,
+        text: &ByteString,
+        ctx: &mut ws::WebsocketContext<Self>,
+    ) -> Result<(), ProtocolError> {
+        if let Some(recorder) = &mut self.recorder {
+            recorder.record(Direction::Inbound, text);
+        }
+
+        let msg = serde_json::from_str::<IncomingMessage>(text).map_err(ProtocolError::from);
+        self.handle_message(msg, ctx)
+    }
+
+    /// Decodes a binary frame in the encoding of the client.
+    fn handle_binary_message(
+        &mut self,
+        bytes: &[u8],
+        ctx: &mut ws::WebsocketContext<Self>,
+    ) -> Result<(), ProtocolError> {
+        let msg = self.encoding.decode::<IncomingMessage>(bytes);
+        if let (Some(recorder), Ok(msg)) = (&mut self.recorder, &msg) {
+            if let Ok(text) = serde_json::to_string(msg) {
+                recorder.record(Direction::Inbound, &text);
+            }
+        }
+        self.handle_message(msg.map_err(ProtocolError::from), ctx)
+    }
+
+    #[allow(clippy::too_many_lines)]
+    fn handle_message(
+        &mut self,
+        msg: Result<IncomingMessage, ProtocolError>,
+        ctx: &mut ws::WebsocketContext<Self>,
+    ) -> Result<(), ProtocolError> {
+        use PlayerController::*;
+
+        let limit = match &msg {
+            Ok(IncomingMessage::Ping { .. }) => &mut self.ping_limit,
+            _ => &mut self.message_limit,
         };
+        if !limit.try_take() {
+            self.on_message_dropped(ctx);
+            return Ok(());
+        }
+
+        let msg = msg?;
 
         self.hb = Instant::now();
 
@@ -450,69 +686,330 @@ impl Player {
         #[allow(clippy::single_match_else)]
         match msg {
             IncomingMessage::Ping { .. } => (),
-            _ => debug!("Received {}", variant_name),
+            _ => debug!("Received {variant_name}"),
         }
 
         match msg {
             IncomingMessage::LobbyPickPlayer(msg) => {
                 let Some(Lobby(lobby)) = &self.controller else {
-                    debug!("No controller to handle {}", variant_name);
-                    return;
+                    return Err(ProtocolError::NoController(variant_name));
                 };
                 lobby.do_send(msg);
             }
+            IncomingMessage::LobbyPickPlayers(IncomingPickPlayers { codes, config }) => {
+                let Some(Lobby(lobby)) = &self.controller else {
+                    return Err(ProtocolError::NoController(variant_name));
+                };
+                lobby.do_send(PickPlayers {
+                    addr: ctx.address(),
+                    codes,
+                    config,
+                });
+            }
+            IncomingMessage::LobbyPickPair(IncomingPickPair { codes, config }) => {
+                let Some(Lobby(lobby)) = &self.controller else {
+                    return Err(ProtocolError::NoController(variant_name));
+                };
+                lobby.do_send(PickPair {
+                    addr: ctx.address(),
+                    codes,
+                    config,
+                });
+            }
+            IncomingMessage::LobbyPickSpectator(IncomingPickSpectator { code }) => {
+                let Some(Lobby(lobby)) = &self.controller else {
+                    return Err(ProtocolError::NoController(variant_name));
+                };
+                lobby.do_send(PickSpectator {
+                    addr: ctx.address(),
+                    code,
+                });
+            }
+            IncomingMessage::LobbyStartSeries(IncomingStartSeries {
+                codes,
+                config,
+                parallel,
+            }) => {
+                let Some(Lobby(lobby)) = &self.controller else {
+                    return Err(ProtocolError::NoController(variant_name));
+                };
+                lobby.do_send(StartSeries {
+                    addr: ctx.address(),
+                    codes,
+                    config,
+                    parallel,
+                });
+            }
             IncomingMessage::GamePlayerSelectionVote(msg) => {
                 let Some(Game(game)) = &self.controller else {
-                    debug!("No controller to handle {}", variant_name);
-                    return;
+                    return Err(ProtocolError::NoController(variant_name));
                 };
                 game.do_send(PlayerSelectionVote {
                     player: ctx.address(),
                     wants_to_start: msg.wants_to_start,
+                    seq: msg.seq,
                 });
             }
-            IncomingMessage::GameEndTurn(IncomingEndTurn { turn, col }) => {
-                let Some(Game(game)) = &self.controller else {
-                    debug!("No controller to handle {}", variant_name);
-                    return;
+            IncomingMessage::GameEndTurn(IncomingEndTurn {
+                turn,
+                col,
+                kind,
+                row,
+                seq,
+            }) => {
+                let action = match col.map(|col| Action::new(kind, col, row)) {
+                    Some(None) => {
+                        debug!("Placement is missing a row");
+                        return Ok(());
+                    }
+                    action => action.flatten(),
                 };
-                game.do_send(EndTurn {
+                let msg = EndTurn {
                     player: ctx.address(),
                     turn,
-                    col,
-                });
+                    action,
+                    seq,
+                };
+                match &self.controller {
+                    Some(Game(game)) => game.do_send(msg),
+                    _ => return Err(ProtocolError::NoController(variant_name)),
+                }
             }
-            IncomingMessage::GameRestart(IncomingRestart { partial }) => {
+            IncomingMessage::GameRestart(IncomingRestart { partial, seq }) => {
                 let Some(Game(game)) = &self.controller else {
-                    debug!("No controller to handle {}", variant_name);
-                    return;
+                    return Err(ProtocolError::NoController(variant_name));
                 };
                 game.do_send(Restart {
                     addr: ctx.address(),
                     partial,
+                    seq,
                 });
             }
-            IncomingMessage::GameRestartResponse { accepted } => {
+            IncomingMessage::GameRestartResponse { accepted, seq } => {
                 let Some(Game(game)) = &self.controller else {
-                    debug!("No controller to handle {}", variant_name);
-                    return;
+                    return Err(ProtocolError::NoController(variant_name));
                 };
                 game.do_send(RestartResponse {
                     addr: ctx.address(),
                     accepted,
+                    seq,
+                });
+            }
+            IncomingMessage::GameChangeTiming(timing) => {
+                let Some(Game(game)) = &self.controller else {
+                    return Err(ProtocolError::NoController(variant_name));
+                };
+                game.do_send(ChangeTiming {
+                    addr: ctx.address(),
+                    timing,
+                });
+            }
+            IncomingMessage::GameChangeTimingResponse { accepted, seq } => {
+                let Some(Game(game)) = &self.controller else {
+                    return Err(ProtocolError::NoController(variant_name));
+                };
+                game.do_send(ChangeTimingResponse {
+                    addr: ctx.address(),
+                    accepted,
+                    seq,
+                });
+            }
+            IncomingMessage::GameChat { text } => {
+                let Some(Game(game)) = &self.controller else {
+                    return Err(ProtocolError::NoController(variant_name));
+                };
+                game.do_send(Chat {
+                    addr: ctx.address(),
+                    text,
+                });
+            }
+            IncomingMessage::LobbyChat { text } => {
+                let Some(Lobby(lobby)) = &self.controller else {
+                    return Err(ProtocolError::NoController(variant_name));
+                };
+                lobby.do_send(Chat {
+                    addr: ctx.address(),
+                    text,
+                });
+            }
+            IncomingMessage::LobbyReady { ready } => {
+                let Some(Lobby(lobby)) = &self.controller else {
+                    return Err(ProtocolError::NoController(variant_name));
+                };
+                lobby.do_send(SetReady {
+                    addr: ctx.address(),
+                    ready,
+                });
+            }
+            IncomingMessage::LobbyKick { code, block } => {
+                let Some(Lobby(lobby)) = &self.controller else {
+                    return Err(ProtocolError::NoController(variant_name));
+                };
+                lobby.do_send(KickPlayer {
+                    addr: ctx.address(),
+                    code,
+                    block,
+                });
+            }
+            IncomingMessage::LobbySetPublic(IncomingSetPublic { public, config }) => {
+                let Some(Lobby(lobby)) = &self.controller else {
+                    return Err(ProtocolError::NoController(variant_name));
+                };
+                lobby.do_send(SetPublic {
+                    addr: ctx.address(),
+                    public,
+                    config,
+                });
+            }
+            IncomingMessage::LobbyRegenerateInvite => {
+                let Some(Lobby(lobby)) = &self.controller else {
+                    return Err(ProtocolError::NoController(variant_name));
+                };
+                lobby.do_send(RegenerateInvite {
+                    addr: ctx.address(),
+                });
+            }
+            IncomingMessage::LobbyRequestLink => {
+                let Some(Lobby(lobby)) = &self.controller else {
+                    return Err(ProtocolError::NoController(variant_name));
+                };
+                lobby.do_send(RequestLink {
+                    addr: ctx.address(),
                 });
             }
+            IncomingMessage::LobbySetPassword { password } => {
+                let Some(Lobby(lobby)) = &self.controller else {
+                    return Err(ProtocolError::NoController(variant_name));
+                };
+                lobby.do_send(SetPassword {
+                    addr: ctx.address(),
+                    password,
+                });
+            }
+            IncomingMessage::LobbySetConfig { config } => {
+                let Some(Lobby(lobby)) = &self.controller else {
+                    return Err(ProtocolError::NoController(variant_name));
+                };
+                lobby.do_send(SetConfig {
+                    addr: ctx.address(),
+                    config,
+                });
+            }
+            IncomingMessage::GameQueue { queued } => {
+                let Some(Game(game)) = &self.controller else {
+                    return Err(ProtocolError::NoController(variant_name));
+                };
+                game.do_send(Queue {
+                    addr: ctx.address(),
+                    queued,
+                });
+            }
+            IncomingMessage::GameRequestSync => {
+                let msg = RequestSync(ctx.address());
+                match &self.controller {
+                    Some(Game(game)) => game.do_send(msg),
+                    _ => return Err(ProtocolError::NoController(variant_name)),
+                }
+            }
+            IncomingMessage::RequestFullSync => {
+                let msg = RequestFullSync(ctx.address());
+                match &self.controller {
+                    Some(Lobby(lobby)) => lobby.do_send(msg),
+                    Some(Game(game)) => game.do_send(msg),
+                    Some(Matchmaker(_)) | None => {
+                        return Err(ProtocolError::NoController(variant_name))
+                    }
+                }
+            }
+            IncomingMessage::GameResign { seq } => {
+                let msg = Resign {
+                    addr: ctx.address(),
+                    seq,
+                };
+                match &self.controller {
+                    Some(Game(game)) => game.do_send(msg),
+                    _ => return Err(ProtocolError::NoController(variant_name)),
+                }
+            }
+            IncomingMessage::GameSwapSides { turn, seq } => {
+                let Some(Game(game)) = &self.controller else {
+                    return Err(ProtocolError::NoController(variant_name));
+                };
+                game.do_send(SwapSides {
+                    addr: ctx.address(),
+                    turn,
+                    seq,
+                });
+            }
+            IncomingMessage::GameHover { col } => {
+                let Some(Game(game)) = &self.controller else {
+                    return Err(ProtocolError::NoController(variant_name));
+                };
+                game.do_send(Hover {
+                    addr: ctx.address(),
+                    col,
+                });
+            }
+            IncomingMessage::GameAnnotate { turn, text } => {
+                let Some(Game(game)) = &self.controller else {
+                    return Err(ProtocolError::NoController(variant_name));
+                };
+                game.do_send(Annotate {
+                    addr: ctx.address(),
+                    turn,
+                    text,
+                });
+            }
+            IncomingMessage::MatchmakingCancel => {
+                let Some(Matchmaker(matchmaker)) = &self.controller else {
+                    return Err(ProtocolError::NoController(variant_name));
+                };
+                matchmaker.do_send(LeaveQueue(ctx.address()));
+            }
+            IncomingMessage::SetLocale { locale } => {
+                self.locale = locale.as_deref().map(Locale::from_tag);
+            }
+            IncomingMessage::SetProfile { name, avatar } => {
+                let profiles = &self.cfg.profiles;
+                if profiles.name_max_length == 0 {
+                    debug!("Profiles are disabled");
+                    return Ok(());
+                }
+                let Some(profile) = Profile::sanitize(
+                    &name,
+                    avatar,
+                    profiles.name_max_length,
+                    profiles.name_charset,
+                ) else {
+                    debug!("Rejected profile");
+                    return Ok(());
+                };
+
+                self.profile = Some(profile.clone());
+                let msg = UpdateProfile {
+                    addr: ctx.address(),
+                    profile,
+                };
+                match &self.controller {
+                    Some(Lobby(lobby)) => lobby.do_send(msg),
+                    Some(Game(game)) => game.do_send(msg),
+                    Some(Matchmaker(_)) | None => (),
+                }
+            }
             IncomingMessage::Ping { sent } => {
-                let received = Utc::now().format(ISO_8601_TIMESTAMP).to_string();
+                let now = Utc::now();
+                self.latency.record_ping(sent, now);
+                self.report_latency(ctx);
+                let received = now.format(ISO_8601_TIMESTAMP).to_string();
                 // Fail silently just to be safe
-                let Ok(msg) = serde_json::to_string(&OutgoingMessage::Pong { sent, received })
-                else {
+                let Ok(msg) = Encoded::new(&OutgoingMessage::Pong { sent, received }) else {
                     debug!("Failed to serialize message");
-                    return;
+                    return Ok(());
                 };
-                ctx.text(msg);
+                self.write(&msg, ctx);
             }
         }
+        Ok(())
     }
 }
 
@@ -520,12 +1017,29 @@ impl Actor for Player {
     type Context = ws::WebsocketContext<Self>;
 
     fn started(&mut self, ctx: &mut Self::Context) {
+        let _span = self.span.clone().entered();
         self.hb(ctx);
+        if let Some(updates) = self.config_updates.take() {
+            ctx.add_message_stream(reload::updates(updates));
+        }
+        if self.protocol >= ProtocolVersion::V2 {
+            let hello = OutgoingMessage::ProtocolHello {
+                version: self.protocol,
+                capabilities: protocol::capabilities(&self.cfg),
+            };
+            self.send_shared(hello.into_serialized().unwrap().into(), ctx);
+        }
         debug!("Started");
     }
 
     fn stopped(&mut self, ctx: &mut Self::Context) {
         use PlayerController::*;
+        let _span = self.span.clone().entered();
+
+        if let Some(mut recorder) = self.recorder.take() {
+            recorder.record_close(None);
+        }
+        self.connection.take();
 
         if self.disconnected_by_controller {
             debug!("Shut down by controller");
@@ -537,11 +1051,12 @@ impl Actor for Player {
         match &self.controller {
             Some(Lobby(lobby)) => lobby.do_send(Disconnected(weak_addr)),
             Some(Game(game)) => game.do_send(Disconnected(weak_addr)),
+            Some(Matchmaker(matchmaker)) => matchmaker.do_send(Disconnected(weak_addr)),
             None => {
                 debug!("Shut down, no controller was attached");
                 return;
             }
-        };
+        }
 
         debug!("Shut down, controller has been notified");
     }
@@ -552,14 +1067,50 @@ impl Actor for Player {
 impl Handler<AttachController> for Player {
     type Result = ();
 
-    fn handle(&mut self, msg: AttachController, _: &mut Self::Context) {
+    fn handle(&mut self, msg: AttachController, ctx: &mut Self::Context) {
+        if let Some(subject) = &self.subject {
+            let authenticated = Authenticated {
+                addr: ctx.address(),
+                subject: subject.clone(),
+            };
+            match &msg.0 {
+                PlayerController::Lobby(lobby) => lobby.do_send(authenticated),
+                PlayerController::Game(game) => game.do_send(authenticated),
+                PlayerController::Matchmaker(_) => (),
+            }
+        } else if let (PlayerController::Game(game), Some(id)) = (&msg.0, &self.persistent_id) {
+            game.do_send(Identify {
+                addr: ctx.address(),
+                player_id: id.clone(),
+            });
+        }
+        if let Some(profile) = &self.profile {
+            let update = UpdateProfile {
+                addr: ctx.address(),
+                profile: profile.clone(),
+            };
+            match &msg.0 {
+                PlayerController::Lobby(lobby) => lobby.do_send(update),
+                PlayerController::Game(game) => game.do_send(update),
+                PlayerController::Matchmaker(_) => (),
+            }
+        }
+        // The previous controller may not end its batch anymore
+        self.end_batch(ctx);
         self.controller = Some(msg.0);
+        self.report_latency(ctx);
+        if self.congested {
+            self.report_backpressure(ctx);
+        }
+        self.span = info_span!(parent: &msg.1, "conn", id = %self.connection_id);
+        let _span = self.span.clone().entered();
         debug!("Controller attached");
     }
 }
 
 impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for Player {
     fn handle(&mut self, msg: Result<ws::Message, ws::ProtocolError>, ctx: &mut Self::Context) {
+        let _span = self.span.clone().entered();
         let Ok(msg) = msg else {
             error!("WebSocket protocol error");
             ctx.stop();
@@ -567,7 +1118,11 @@ impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for Player {
         };
 
         match msg {
-            ws::Message::Text(text) => self.handle_text_message(&text, ctx),
+            ws::Message::Text(text) => {
+                if let Err(e) = self.handle_text_message(&text, ctx) {
+                    self.report_error(&e, ctx);
+                }
+            }
             ws::Message::Continuation(_) => {
                 ctx.close(Some(ws::CloseCode::Unsupported.into()));
                 ctx.stop();
@@ -577,10 +1132,32 @@ impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for Player {
                 ctx.close(reason);
                 ctx.stop();
             }
-            ws::Message::Ping(_)
-            | ws::Message::Pong(_)
-            | ws::Message::Binary(_)
-            | ws::Message::Nop => (),
+            ws::Message::Ping(payload) => {
+                if !self.ping_limit.try_take() {
+                    self.on_message_dropped(ctx);
+                    return;
+                }
+                self.hb = Instant::now();
+                ctx.pong(&payload);
+            }
+            ws::Message::Pong(payload) if payload == QUEUE_PROBE => {
+                self.hb = Instant::now();
+                self.outgoing.confirm_probe();
+                self.flush(ctx);
+            }
+            ws::Message::Pong(_) => {
+                self.hb = Instant::now();
+                if let Some(sent) = self.ping_sent.take() {
+                    self.latency.record_rtt(sent.elapsed());
+                    self.report_latency(ctx);
+                }
+            }
+            ws::Message::Binary(bytes) if self.encoding.is_binary() => {
+                if let Err(e) = self.handle_binary_message(&bytes, ctx) {
+                    self.report_error(&e, ctx);
+                }
+            }
+            ws::Message::Binary(_) | ws::Message::Nop => (),
         }
     }
 }
@@ -589,13 +1166,20 @@ impl Handler<Disconnect> for Player {
     type Result = ();
 
     fn handle(&mut self, d: Disconnect, ctx: &mut Self::Context) {
+        let _span = self.span.clone().entered();
         debug!("Controller disconnected");
         self.disconnected_by_controller = true;
-        ctx.close(Some(CloseReason {
-            code: ws::CloseCode::Normal,
-            description: Some(String::from(d.as_str())),
-        }));
-        ctx.stop();
+        self.close(d, ctx);
+    }
+}
+
+impl Handler<Kick> for Player {
+    type Result = ();
+
+    fn handle(&mut self, msg: Kick, ctx: &mut Self::Context) {
+        let _span = self.span.clone().entered();
+        debug!("Kicked");
+        self.close(msg.0, ctx);
     }
 }
 
@@ -603,7 +1187,8 @@ impl Handler<SerializedOutgoingMessage> for Player {
     type Result = ();
 
     fn handle(&mut self, msg: SerializedOutgoingMessage, ctx: &mut Self::Context) {
-        ctx.text(&msg.0[..]);
+        let _span = self.span.clone().entered();
+        self.send_shared(msg.into(), ctx);
     }
 }
 
@@ -611,6 +1196,99 @@ impl Handler<SharedOutgoingMessage> for Player {
     type Result = ();
 
     fn handle(&mut self, msg: SharedOutgoingMessage, ctx: &mut Self::Context) {
-        ctx.text(msg.0.as_str());
+        let _span = self.span.clone().entered();
+        self.send_shared(msg, ctx);
+    }
+}
+
+impl Handler<BeginBatch> for Player {
+    type Result = ();
+
+    fn handle(&mut self, _: BeginBatch, _: &mut Self::Context) {
+        if self.batching && self.protocol >= ProtocolVersion::V2 && self.batch.is_none() {
+            self.batch = Some(Vec::new());
+        }
+    }
+}
+
+impl Handler<EndBatch> for Player {
+    type Result = ();
+
+    fn handle(&mut self, _: EndBatch, ctx: &mut Self::Context) {
+        let _span = self.span.clone().entered();
+        self.end_batch(ctx);
+    }
+}
+
+impl Handler<RejectConfig> for Player {
+    type Result = ();
+
+    fn handle(&mut self, msg: RejectConfig, ctx: &mut Self::Context) {
+        let _span = self.span.clone().entered();
+        let msg = OutgoingMessage::ConfigRejected {
+            rejection: msg.0,
+            description: self.locale.map(|l| l.config_rejection(msg.0)),
+        };
+        self.send_message(msg, ctx);
+    }
+}
+
+impl Handler<SendNotice> for Player {
+    type Result = ();
+
+    fn handle(&mut self, msg: SendNotice, ctx: &mut Self::Context) {
+        let _span = self.span.clone().entered();
+        let msg = OutgoingMessage::ChatNotice {
+            notice: msg.notice,
+            timestamp: msg.timestamp,
+            description: self.locale.map(|l| l.notice(msg.notice)),
+        };
+        self.send_message(msg, ctx);
+    }
+}
+
+impl Handler<QrOutgoingMessage> for Player {
+    type Result = ();
+
+    fn handle(&mut self, msg: QrOutgoingMessage, ctx: &mut Self::Context) {
+        let _span = self.span.clone().entered();
+        let msg = match self.qr_format {
+            QrFormat::Png => msg.png,
+            QrFormat::Svg => msg.svg,
+        };
+        self.send_shared(msg.into(), ctx);
+    }
+}
+
+impl Handler<VersionedOutgoingMessage> for Player {
+    type Result = ();
+
+    fn handle(&mut self, msg: VersionedOutgoingMessage, ctx: &mut Self::Context) {
+        let _span = self.span.clone().entered();
+        let msg = if msg.latest.1 <= self.protocol {
+            msg.latest
+        } else {
+            msg.fallback
+        };
+        self.send_shared(msg, ctx);
+    }
+}
+
+impl Handler<ReloadConfig> for Player {
+    type Result = ();
+
+    /// Restarts the heartbeat and resets the rate limits if they changed.
+    fn handle(&mut self, msg: ReloadConfig, ctx: &mut Self::Context) {
+        let old = std::mem::replace(&mut self.cfg, msg.0);
+        let limits = &self.cfg.rate_limit;
+        if old.rate_limit != *limits {
+            self.message_limit = TokenBucket::new(limits.messages_per_second, limits.message_burst);
+            self.ping_limit = TokenBucket::new(limits.pings_per_second, limits.ping_burst);
+        }
+        if old.heartbeat_interval != self.cfg.heartbeat_interval
+            || old.heartbeat_timeout != self.cfg.heartbeat_timeout
+        {
+            self.hb(ctx);
+        }
     }
 }
@@ -0,0 +1,123 @@
+//! Keeps a panic in a lobby or game from taking it down silently.
+//!
+//! Actix does not catch panics: the actor is dropped along with its task and
+//! `Actor::stopped` is never called, so its players would keep waiting for a
+//! controller which no longer exists. Actors started with
+//! `StartCatchingPanics::start_supervised` are dropped while `panicked`
+//! returns true instead, so that their `Drop` implementation can tell
+//! everyone.
+
+use std::{
+    cell::Cell,
+    future::Future,
+    panic::{self, AssertUnwindSafe},
+    pin::Pin,
+    sync::atomic::{AtomicU64, Ordering},
+    task::{self, Poll},
+};
+
+use actix::prelude::*;
+
+thread_local! {
+    static PANICKED: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Amount of actors stopped by a panic since the server started.
+static PANICS: AtomicU64 = AtomicU64::new(0);
+
+/// Named apart from `actix::Supervised`, which restarts actors instead.
+pub trait StartCatchingPanics: Actor<Context = Context<Self>> {
+    /// Starts the actor like `Actor::start`, catching panics in its
+    /// handlers.
+    fn start_supervised(self) -> Addr<Self>;
+}
+
+impl<A: Actor<Context = Context<A>>> StartCatchingPanics for A {
+    fn start_supervised(self) -> Addr<Self> {
+        let ctx = Context::new();
+        let addr = ctx.address();
+        actix::spawn(CatchUnwind(Some(Box::pin(ctx.into_future(self)))));
+        addr
+    }
+}
+
+/// Returns true while an actor which panicked is being dropped.
+#[must_use]
+pub fn panicked() -> bool {
+    PANICKED.get()
+}
+
+/// Returns the amount of actors stopped by a panic since the server started.
+#[must_use]
+pub fn panics() -> u64 {
+    PANICS.load(Ordering::Relaxed)
+}
+
+struct CatchUnwind<F>(Option<Pin<Box<F>>>);
+
+impl<F: Future<Output = ()>> Future for CatchUnwind<F> {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut task::Context<'_>) -> Poll<()> {
+        let Some(fut) = &mut self.0 else {
+            return Poll::Ready(());
+        };
+        if let Ok(poll) = panic::catch_unwind(AssertUnwindSafe(|| fut.as_mut().poll(cx))) {
+            return poll;
+        }
+
+        PANICS.fetch_add(1, Ordering::Relaxed);
+        PANICKED.set(true);
+        self.0 = None;
+        PANICKED.set(false);
+        Poll::Ready(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::sync::oneshot;
+
+    use super::*;
+
+    /// Reports whether `panicked` returned true when it was dropped.
+    struct Fragile(Option<oneshot::Sender<bool>>);
+
+    impl Actor for Fragile {
+        type Context = Context<Self>;
+    }
+
+    #[derive(Message)]
+    #[rtype(result = "()")]
+    struct Break;
+
+    impl Handler<Break> for Fragile {
+        type Result = ();
+
+        fn handle(&mut self, _: Break, _: &mut Self::Context) {
+            panic!("broken");
+        }
+    }
+
+    impl Drop for Fragile {
+        fn drop(&mut self) {
+            if let Some(tx) = self.0.take() {
+                let _ = tx.send(panicked());
+            }
+        }
+    }
+
+    #[test]
+    fn drops_actors_which_panicked() {
+        System::new().block_on(async {
+            let before = panics();
+            let (tx, rx) = oneshot::channel();
+            let addr = Fragile(Some(tx)).start_supervised();
+            addr.do_send(Break);
+            assert_eq!(rx.await, Ok(true));
+            assert!(!panicked());
+            // Other tests may stop supervised actors at the same time
+            assert!(panics() > before);
+        });
+    }
+}
@@ -0,0 +1,101 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use chrono::Utc;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+use crate::game::Game;
+use crate::replay::Replay;
+
+const FILE_NAME_TIMESTAMP: &str = "%Y%m%dT%H%M%S";
+
+/// A finished round, as written to the archive.
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ArchivedGame {
+    /// ID of the lobby the game was started from, `None` for bot games.
+    pub lobby: Option<String>,
+    pub round: u32,
+    /// ISO 8601 timestamp of when the round ended.
+    pub finished: String,
+    /// Final board, state and result.
+    pub game: Game,
+    /// Config and moves, which can be played back with `cli replay <FILE>`.
+    #[serde(flatten)]
+    pub replay: Replay,
+    /// The game in the text notation of `game::notation`, only written if
+    /// `ArchiveConfig::notation` is enabled.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub notation: Option<String>,
+}
+
+/// Somewhere finished games are kept. Implementations are called from a
+/// blocking thread, so they are free to do IO.
+pub trait GameArchive: Send + Sync {
+    fn store(&self, game: &ArchivedGame) -> io::Result<()>;
+}
+
+/// Writes every game to its own JSON file.
+pub struct DirectoryArchive {
+    dir: PathBuf,
+}
+
+impl DirectoryArchive {
+    /// Creates `dir` if it does not exist yet.
+    pub fn new(dir: &Path) -> io::Result<Self> {
+        fs::create_dir_all(dir)?;
+        Ok(Self {
+            dir: dir.to_path_buf(),
+        })
+    }
+}
+
+impl GameArchive for DirectoryArchive {
+    fn store(&self, game: &ArchivedGame) -> io::Result<()> {
+        let suffix: u32 = rand::thread_rng().gen();
+        let name = format!(
+            "{}-{suffix:08x}.json",
+            Utc::now().format(FILE_NAME_TIMESTAMP)
+        );
+        let contents = serde_json::to_vec(game)?;
+        fs::write(self.dir.join(name), contents)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game_config::GameConfig;
+
+    #[test]
+    fn directory_archive_writes_json() {
+        let dir = std::env::temp_dir().join(format!("c4-archive-{}", std::process::id()));
+        let archive = DirectoryArchive::new(&dir).unwrap();
+
+        let mut game = Game::default();
+        game.end_turn(Some(3)).unwrap();
+        let record = ArchivedGame {
+            lobby: None,
+            round: 2,
+            finished: String::from("2023-01-01T00:00:00.000Z"),
+            replay: Replay::new(GameConfig::default(), &game),
+            notation: Some(game.to_notation()),
+            game,
+        };
+        archive.store(&record).unwrap();
+
+        let entry = fs::read_dir(&dir).unwrap().next().unwrap().unwrap();
+        let contents = fs::read_to_string(entry.path()).unwrap();
+        let read: ArchivedGame = serde_json::from_str(&contents).unwrap();
+        assert_eq!(read.round, 2);
+        assert_eq!(read.replay.moves, record.replay.moves);
+        assert_eq!(read.replay.validate(), Ok(()));
+        assert_eq!(read.game.field()[3], record.game.field()[3]);
+        assert_eq!(read.notation, record.notation);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
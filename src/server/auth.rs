@@ -0,0 +1,176 @@
+//! Verifies tokens issued by an existing account system, so that only its
+//! users can connect. Tokens are JWTs signed with HS256, using the secret
+//! shared between the account system and `AuthConfig::secret`.
+//!
+//! The `sub` claim identifies the user, `exp` and `nbf` are checked if set.
+
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use openssl::error::ErrorStack;
+use openssl::hash::MessageDigest;
+use openssl::pkey::PKey;
+use openssl::sign::Signer;
+use serde::Deserialize;
+use serde_json::json;
+
+/// How far the clock of the account system may be off, in seconds.
+const CLOCK_SKEW: i64 = 60;
+/// Subjects longer than this many bytes are rejected.
+pub const SUBJECT_MAX_LENGTH: usize = 256;
+
+/// Reason why a token was not accepted.
+#[derive(Debug, thiserror::Error)]
+pub enum AuthError {
+    #[error("token is not a valid JWT")]
+    Malformed,
+    #[error("token is not signed with HS256")]
+    UnsupportedAlgorithm,
+    #[error("signature does not match")]
+    InvalidSignature,
+    #[error("token has expired")]
+    Expired,
+    #[error("token is not valid yet")]
+    NotYetValid,
+    #[error("subject is missing or too long")]
+    InvalidSubject,
+    #[error(transparent)]
+    OpenSsl(#[from] ErrorStack),
+}
+
+#[derive(Deserialize)]
+struct Header {
+    alg: String,
+}
+
+#[derive(Deserialize)]
+struct Claims {
+    sub: Option<String>,
+    exp: Option<i64>,
+    nbf: Option<i64>,
+}
+
+/// Checks the signature and validity of the token at `now`, in seconds
+/// since the Unix epoch, and returns its subject.
+pub fn verify(token: &str, secret: &str, now: i64) -> Result<String, AuthError> {
+    let mut parts = token.split('.');
+    let (Some(header), Some(claims), Some(signature), None) =
+        (parts.next(), parts.next(), parts.next(), parts.next())
+    else {
+        return Err(AuthError::Malformed);
+    };
+    let unsigned = &token[..header.len() + 1 + claims.len()];
+    let header: Header = decode(header)?;
+    if header.alg != "HS256" {
+        return Err(AuthError::UnsupportedAlgorithm);
+    }
+    let signature = URL_SAFE_NO_PAD
+        .decode(signature)
+        .map_err(|_| AuthError::Malformed)?;
+    let expected = hmac(unsigned, secret)?;
+    if expected.len() != signature.len() || !openssl::memcmp::eq(&expected, &signature) {
+        return Err(AuthError::InvalidSignature);
+    }
+
+    let claims: Claims = decode(claims)?;
+    if claims.exp.is_some_and(|exp| now >= exp + CLOCK_SKEW) {
+        return Err(AuthError::Expired);
+    }
+    if claims.nbf.is_some_and(|nbf| now < nbf - CLOCK_SKEW) {
+        return Err(AuthError::NotYetValid);
+    }
+    match claims.sub {
+        Some(sub) if !sub.is_empty() && sub.len() <= SUBJECT_MAX_LENGTH => Ok(sub),
+        _ => Err(AuthError::InvalidSubject),
+    }
+}
+
+/// Issues a token for the subject, valid until `expires_at` in seconds
+/// since the Unix epoch if set. Meant for account systems written in Rust,
+/// and for tests.
+pub fn sign(subject: &str, expires_at: Option<i64>, secret: &str) -> Result<String, ErrorStack> {
+    let header = json!({ "alg": "HS256", "typ": "JWT" });
+    let claims = match expires_at {
+        Some(exp) => json!({ "sub": subject, "exp": exp }),
+        None => json!({ "sub": subject }),
+    };
+    let encode = |value: serde_json::Value| URL_SAFE_NO_PAD.encode(value.to_string());
+    let unsigned = format!("{}.{}", encode(header), encode(claims));
+    let signature = URL_SAFE_NO_PAD.encode(hmac(&unsigned, secret)?);
+    Ok(format!("{unsigned}.{signature}"))
+}
+
+/// Returns the ID the ratings of an authenticated user are kept under.
+/// Player IDs picked by clients cannot contain `:`, so they never collide
+/// with it.
+#[must_use]
+pub fn rating_id(subject: &str) -> String {
+    format!("auth:{subject}")
+}
+
+fn decode<T: for<'de> Deserialize<'de>>(part: &str) -> Result<T, AuthError> {
+    let json = URL_SAFE_NO_PAD
+        .decode(part)
+        .map_err(|_| AuthError::Malformed)?;
+    serde_json::from_slice(&json).map_err(|_| AuthError::Malformed)
+}
+
+fn hmac(data: &str, secret: &str) -> Result<Vec<u8>, ErrorStack> {
+    let key = PKey::hmac(secret.as_bytes())?;
+    let mut signer = Signer::new(MessageDigest::sha256(), &key)?;
+    signer.sign_oneshot_to_vec(data.as_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECRET: &str = "shared secret";
+    const NOW: i64 = 1_700_000_000;
+
+    #[test]
+    fn accepts_signed_tokens() {
+        let token = sign("user-1", Some(NOW + 3600), SECRET).unwrap();
+        assert_eq!(verify(&token, SECRET, NOW).unwrap(), "user-1");
+        let token = sign("user|2", None, SECRET).unwrap();
+        assert_eq!(verify(&token, SECRET, NOW).unwrap(), "user|2");
+    }
+
+    #[test]
+    fn rejects_tampered_and_expired_tokens() {
+        let token = sign("user-1", Some(NOW), SECRET).unwrap();
+        assert!(matches!(
+            verify(&token, "other secret", NOW),
+            Err(AuthError::InvalidSignature)
+        ));
+        assert!(matches!(
+            verify(&token, SECRET, NOW + CLOCK_SKEW),
+            Err(AuthError::Expired)
+        ));
+
+        let other = sign("user-2", Some(NOW), SECRET).unwrap();
+        let (claims, _) = other.rsplit_once('.').unwrap();
+        let (_, signature) = token.rsplit_once('.').unwrap();
+        let forged = format!("{claims}.{signature}");
+        assert!(matches!(
+            verify(&forged, SECRET, NOW),
+            Err(AuthError::InvalidSignature)
+        ));
+
+        let header = URL_SAFE_NO_PAD.encode(r#"{"alg":"none"}"#);
+        let (_, rest) = token.split_once('.').unwrap();
+        let unsigned = format!("{header}.{rest}");
+        assert!(matches!(
+            verify(&unsigned, SECRET, NOW),
+            Err(AuthError::UnsupportedAlgorithm)
+        ));
+        assert!(matches!(
+            verify("not a token", SECRET, NOW),
+            Err(AuthError::Malformed)
+        ));
+        let empty = sign("", None, SECRET).unwrap();
+        assert!(matches!(
+            verify(&empty, SECRET, NOW),
+            Err(AuthError::InvalidSubject)
+        ));
+    }
+}
@@ -0,0 +1,166 @@
+//! Users kept from connecting, by IP address or by the subject they
+//! authenticated as, see `server::auth`. Bans are set through the admin API
+//! and also close the connections of players already in a lobby or game.
+
+use std::{
+    collections::BTreeSet,
+    fs, io,
+    net::IpAddr,
+    path::{Path, PathBuf},
+    sync::{Mutex, PoisonError},
+};
+
+use actix::WeakAddr;
+use serde::{Deserialize, Serialize};
+
+use crate::server::actor::player::Kick;
+use crate::server::actor::Player;
+use crate::server::protocol::Disconnect;
+
+/// Who a ban applies to, `{"ip": "..."}` or `{"subject": "..."}` in JSON.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, PartialOrd, Ord, Debug)]
+#[serde(rename_all = "camelCase")]
+pub enum BanTarget {
+    Ip(IpAddr),
+    Subject(String),
+}
+
+/// A player the ban list can disconnect once it matches a new ban.
+struct Connection {
+    ip: Option<IpAddr>,
+    subject: Option<String>,
+    player: WeakAddr<Player>,
+}
+
+impl Connection {
+    fn matches(&self, target: &BanTarget) -> bool {
+        match target {
+            BanTarget::Ip(ip) => self.ip == Some(*ip),
+            BanTarget::Subject(subject) => self.subject.as_ref() == Some(subject),
+        }
+    }
+}
+
+/// Keeps bans in memory, and writes all of them to a JSON file after every
+/// change if opened with a path.
+#[derive(Default)]
+pub struct BanList {
+    path: Option<PathBuf>,
+    bans: Mutex<BTreeSet<BanTarget>>,
+    connections: Mutex<Vec<Connection>>,
+}
+
+impl BanList {
+    /// Reads the bans from `path`, starting with none if the file does not
+    /// exist yet.
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let bans = match fs::read(path) {
+            Ok(contents) => serde_json::from_slice(&contents)?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => BTreeSet::new(),
+            Err(e) => return Err(e),
+        };
+        Ok(Self {
+            path: Some(path.to_path_buf()),
+            bans: Mutex::new(bans),
+            connections: Mutex::default(),
+        })
+    }
+
+    /// Returns true if the address or the subject is banned.
+    pub fn is_banned(&self, ip: Option<IpAddr>, subject: Option<&str>) -> bool {
+        let bans = self.bans.lock().unwrap_or_else(PoisonError::into_inner);
+        ip.is_some_and(|ip| bans.contains(&BanTarget::Ip(ip)))
+            || subject.is_some_and(|s| bans.contains(&BanTarget::Subject(String::from(s))))
+    }
+
+    /// Returns every ban, addresses first.
+    pub fn list(&self) -> Vec<BanTarget> {
+        let bans = self.bans.lock().unwrap_or_else(PoisonError::into_inner);
+        bans.iter().cloned().collect()
+    }
+
+    /// Bans the target and disconnects its players. Returns false if it was
+    /// already banned.
+    pub fn ban(&self, target: BanTarget) -> io::Result<bool> {
+        let mut bans = self.bans.lock().unwrap_or_else(PoisonError::into_inner);
+        let mut connections = self
+            .connections
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        connections.retain(|c| c.player.upgrade().is_some_and(|p| p.connected()));
+        for connection in connections.iter().filter(|c| c.matches(&target)) {
+            if let Some(player) = connection.player.upgrade() {
+                player.do_send(Kick(Disconnect::Banned));
+            }
+        }
+        if !bans.insert(target) {
+            return Ok(false);
+        }
+        self.save(&bans)?;
+        Ok(true)
+    }
+
+    /// Lifts the ban. Returns false if the target was not banned.
+    pub fn unban(&self, target: &BanTarget) -> io::Result<bool> {
+        let mut bans = self.bans.lock().unwrap_or_else(PoisonError::into_inner);
+        if !bans.remove(target) {
+            return Ok(false);
+        }
+        self.save(&bans)?;
+        Ok(true)
+    }
+
+    /// Disconnects the player once its address or subject is banned.
+    pub fn watch(&self, ip: Option<IpAddr>, subject: Option<String>, player: WeakAddr<Player>) {
+        let mut connections = self
+            .connections
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        connections.retain(|c| c.player.upgrade().is_some_and(|p| p.connected()));
+        connections.push(Connection {
+            ip,
+            subject,
+            player,
+        });
+    }
+
+    fn save(&self, bans: &BTreeSet<BanTarget>) -> io::Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        // Write to a temporary file first, so that the bans are not lost if
+        // the server stops halfway through
+        let contents = serde_json::to_vec_pretty(bans)?;
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, contents)?;
+        fs::rename(&tmp, path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn file_keeps_bans() {
+        let path = std::env::temp_dir().join(format!("c4-bans-{}.json", std::process::id()));
+        let list = BanList::open(&path).unwrap();
+        let ip: IpAddr = "192.0.2.1".parse().unwrap();
+        assert!(list.ban(BanTarget::Ip(ip)).unwrap());
+        assert!(list
+            .ban(BanTarget::Subject(String::from("user-1")))
+            .unwrap());
+        assert!(!list.ban(BanTarget::Ip(ip)).unwrap());
+        assert!(list.is_banned(Some(ip), None));
+        assert!(list.is_banned(None, Some("user-1")));
+        assert!(!list.is_banned(None, Some("user-2")));
+
+        let reopened = BanList::open(&path).unwrap();
+        assert_eq!(reopened.list(), list.list());
+        assert!(reopened.unban(&BanTarget::Ip(ip)).unwrap());
+        assert!(!reopened.unban(&BanTarget::Ip(ip)).unwrap());
+        assert!(!reopened.is_banned(Some(ip), Some("user-2")));
+
+        fs::remove_file(&path).unwrap();
+    }
+}
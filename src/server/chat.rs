@@ -0,0 +1,91 @@
+use std::{
+    borrow::Cow,
+    collections::VecDeque,
+    time::{Duration, Instant},
+};
+
+/// The amount of messages a player can send within `CHAT_WINDOW`.
+const CHAT_BURST: usize = 5;
+const CHAT_WINDOW: Duration = Duration::from_secs(10);
+
+/// Limits how often a single player can send chat messages.
+#[derive(Default)]
+pub struct ChatLimiter {
+    /// When the recent messages were sent, oldest first.
+    sent: VecDeque<Instant>,
+}
+
+impl ChatLimiter {
+    /// Returns true and counts the message if the player is allowed to send
+    /// another one.
+    pub fn try_send(&mut self) -> bool {
+        let now = Instant::now();
+        while self
+            .sent
+            .front()
+            .is_some_and(|t| now.duration_since(*t) >= CHAT_WINDOW)
+        {
+            self.sent.pop_front();
+        }
+
+        if self.sent.len() >= CHAT_BURST {
+            return false;
+        }
+
+        self.sent.push_back(now);
+        true
+    }
+}
+
+/// Removes control characters other than line breaks and trims the message,
+/// returning `None` if it is empty or longer than `max_len` characters. Chat
+/// is disabled when `max_len` is 0.
+#[must_use]
+pub fn sanitize(text: &str, max_len: usize) -> Option<Cow<'_, str>> {
+    let is_stripped = |c: char| c.is_control() && c != '\n';
+    let text = if text.contains(is_stripped) {
+        Cow::Owned(text.replace(is_stripped, "").trim().to_owned())
+    } else {
+        Cow::Borrowed(text.trim())
+    };
+    if text.is_empty() || text.chars().count() > max_len {
+        return None;
+    }
+    Some(text)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn limits_bursts_within_the_window() {
+        let mut limiter = ChatLimiter::default();
+        for _ in 0..CHAT_BURST {
+            assert!(limiter.try_send());
+        }
+        assert!(!limiter.try_send());
+
+        // Messages older than the window no longer count
+        let old = Instant::now().checked_sub(CHAT_WINDOW).unwrap();
+        limiter.sent.iter_mut().take(2).for_each(|t| *t = old);
+        assert!(limiter.try_send());
+        assert!(limiter.try_send());
+        assert!(!limiter.try_send());
+    }
+
+    #[test]
+    fn sanitizes_messages() {
+        assert_eq!(sanitize("  hello ", 10).as_deref(), Some("hello"));
+        assert_eq!(
+            sanitize("a\u{0}b\u{1b}[2J\tc\nd", 10).as_deref(),
+            Some("ab[2Jc\nd")
+        );
+        assert_eq!(sanitize("\u{7}\r\n ", 10), None);
+        assert_eq!(sanitize("", 10), None);
+        assert_eq!(sanitize("ten chars!", 10).as_deref(), Some("ten chars!"));
+        assert_eq!(sanitize("eleven chars", 10), None);
+        assert_eq!(sanitize("żółć", 4).as_deref(), Some("żółć"));
+        assert_eq!(sanitize("hi", 0), None);
+    }
+}
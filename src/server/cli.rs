@@ -1,6 +1,8 @@
 use std::{path::PathBuf, time::Duration};
 
-use super::config::AppConfigPartial;
+use url::Url;
+
+use super::config::{AppConfigPartial, ListenerConfig};
 
 const VERSION: &str = "connect-four-server, version 1.1.0";
 
@@ -13,19 +15,77 @@ OPTIONS:
      --url-lobby-parameter <URL_PARAMETER>  URL lobby parameter
   -p --port <PORT>                          Port to use
   -a --address <ADDRESS>                    Address to use
+  -l --listen <ADDRESS:PORT>                Listen on this address instead, can be repeated
+     --listen-plain <ADDRESS:PORT>          Listen on this address with plain HTTP, can be repeated
+     --unix-socket <PATH>                   Listen on this Unix domain socket instead of the address and port
      --private-key-file <FILE>              Private key file
      --cert-chain-file <FILE>               Certificate chain file
+     --tls-reload-interval <SECONDS>        Check the certificate files for changes this often, 0 to only reload on SIGHUP
+     --acme-domain <DOMAIN>                 Obtain certificates for this domain from Let's Encrypt, can be repeated
+     --acme-email <EMAIL>                   Contact email of the Let's Encrypt account
+     --no-tls                               Serve plain HTTP, e.g. behind a reverse proxy terminating TLS
      --max-lobbies <AMOUNT>                 Maximum lobbies
      --max-players <AMOUNT>                 Maximum players in a lobby (0-255)
+     --max-games <AMOUNT>                   Refuse to start games while this many are running, 0 for no limit
+     --lobby-idle-timeout <SECONDS>         Close lobbies in which nothing happens for this long, 0 to disable
+     --game-idle-timeout <SECONDS>          Close games in which no player acts for this long, 0 to disable
+     --invite-max-joins <AMOUNT>            How many players can join using one invite, 0 for no limit
+     --max-connections-per-ip <AMOUNT>      Open connections allowed from one IP address, 0 for no limit
+     --lobby-joins-per-minute <AMOUNT>      Attempts to join one lobby allowed per minute, 0 for no limit
+     --max-total-players <AMOUNT>           Refuse new connections while this many players are connected, 0 for no limit
+     --max-total-games <AMOUNT>             Refuse new connections while this many games are running, 0 for no limit
+     --retry-after <SECONDS>                How long refused clients are asked to wait
      --heartbeat-interval <SECONDS>         Player ping interval in seconds, 0 to disable
      --heartbeat-timeout <SECONDS>          Player ping timeout in seconds, 0 to disable
      --restart-request-timeout <SECONDS>    The amount of time player has to respond to a restart request.
+     --reconnect-grace-period <SECONDS>     How long a game waits for a disconnected player, 0 to disable reconnecting
+     --session-recording-dir <DIR>          Record every connection to this directory (debugging only)
+     --session-recording-player <ID>        Only record connections of this player ID or account, can be repeated
+     --chat-max-length <CHARS>              Maximum length of chat messages, 0 to disable chat
+     --compression-threshold <BYTES>        Compress longer messages if the client supports it, 0 to disable
+     --hint-depth <TURNS>                   Send players the best move found this many turns ahead, 0 to disable
+     --admin-token <TOKEN>                  Enable the /admin endpoints, authenticated with this bearer token
+     --bans-file <FILE>                     Keep bans set through the admin API in this JSON file
+     --serve-from <DIR>                     Serve the web client from this directory
+     --allow-origin <ORIGIN>                Only accept connections from this origin, can be repeated
+     --log-format <FORMAT>                  Format of log lines: text, json
+     --annotations <AUTHORS>                Who can comment on moves: nobody, spectators, opponents, everyone
+     --archive-dir <DIR>                    Write every finished game to this directory
+     --archive-notation                     Include the text notation of games in the archive
+     --no-ratings                           Do not track ratings of players
+     --ratings-file <FILE>                  Keep ratings in this JSON file instead of memory
+     --name-max-length <CHARS>              Maximum length of player names, 0 to disable profiles
+     --name-charset <CHARSET>               Characters allowed in player names: ascii, unicode
+     --qr-ec-level <LEVEL>                  Error correction of invite QR codes: low, medium, quartile, high
+     --qr-size <PIXELS>                     Largest width of invite QR codes, 0 for one pixel per module
+     --auth-secret <SECRET>                 Accept tokens of an account system signed with this HS256 secret
+     --auth-required                        Reject connections without a valid token
+     --no-matchmaking                       Reject clients connecting with matchmake=1
+     --matchmaking-timeout <SECONDS>        Disconnect players waiting for an opponent this long, 0 to disable
+     --webhook <URL>                        POST game events to this URL, can be repeated
+     --game-log <FILE>                      Write a JSON line per finished round to this file, - for stdout
+     --time-warnings <SECONDS,...>          Remaining turn time at which players are warned, empty to disable
   -c --config <FILE>                        Configuration file. Any command line options override configuration settings.
      --print-config                         Print configuration file and exit
      --version                              Show version and exit
   -h --help                                 Show this message and exit
+
+ENVIRONMENT:
+  Options can also be set with C4_ variables named after them, e.g. C4_MAX_LOBBIES=10 or C4_TLS=false.
+  Lists are separated by commas. Variables override the configuration file, options override variables.
 ";
 
+/// Parses a comma separated list of seconds.
+fn parse_secs_list(s: &str) -> Result<Vec<Duration>, String> {
+    s.split(',')
+        .filter(|v| !v.trim().is_empty())
+        .map(|v| {
+            let secs: f64 = v.trim().parse().map_err(|e| format!("{v}: {e}"))?;
+            Duration::try_from_secs_f64(secs).map_err(|e| format!("{v}: {e}"))
+        })
+        .collect()
+}
+
 pub struct AppArgs {
     pub partial_config: AppConfigPartial,
     pub config: Option<PathBuf>,
@@ -33,6 +93,7 @@ pub struct AppArgs {
 }
 
 impl AppArgs {
+    #[allow(clippy::too_many_lines)]
     pub fn from_env() -> Result<Self, pico_args::Error> {
         #[inline]
         fn exit_on_err<T>(res: Result<T, pico_args::Error>) -> T {
@@ -63,19 +124,95 @@ impl AppArgs {
             exit_on_err(pargs.opt_value_from_str("--heartbeat-timeout"));
         let restart_request_timeout: Option<f64> =
             exit_on_err(pargs.opt_value_from_str("--restart-request-timeout"));
+        let reconnect_grace_period: Option<f64> =
+            exit_on_err(pargs.opt_value_from_str("--reconnect-grace-period"));
+        let tls_reload_interval: Option<f64> =
+            exit_on_err(pargs.opt_value_from_str("--tls-reload-interval"));
+        let lobby_idle_timeout: Option<f64> =
+            exit_on_err(pargs.opt_value_from_str("--lobby-idle-timeout"));
+        let game_idle_timeout: Option<f64> =
+            exit_on_err(pargs.opt_value_from_str("--game-idle-timeout"));
+        let retry_after: Option<f64> = exit_on_err(pargs.opt_value_from_str("--retry-after"));
+        let matchmaking_timeout: Option<f64> =
+            exit_on_err(pargs.opt_value_from_str("--matchmaking-timeout"));
+        let time_warnings =
+            exit_on_err(pargs.opt_value_from_fn("--time-warnings", parse_secs_list));
+        let listeners = exit_on_err(pargs.values_from_str(["-l", "--listen"]))
+            .into_iter()
+            .map(|address| ListenerConfig { address, tls: None });
+        let plain_listeners = exit_on_err(pargs.values_from_str("--listen-plain"))
+            .into_iter()
+            .map(|address| ListenerConfig {
+                address,
+                tls: Some(false),
+            });
+        let listeners: Vec<_> = listeners.chain(plain_listeners).collect();
+        let allowed_origins: Vec<String> = exit_on_err(pargs.values_from_str("--allow-origin"));
+        let recorded_players: Vec<String> =
+            exit_on_err(pargs.values_from_str("--session-recording-player"));
+        let acme_domains: Vec<String> = exit_on_err(pargs.values_from_str("--acme-domain"));
+        let webhook_urls: Vec<Url> = exit_on_err(pargs.values_from_str("--webhook"));
+        let game_log: Option<String> = exit_on_err(pargs.opt_value_from_str("--game-log"));
 
         let partial_config = AppConfigPartial {
             url_base: exit_on_err(pargs.opt_value_from_str(["-b", "--url-base"])),
             url_lobby_parameter: exit_on_err(pargs.opt_value_from_str("--url-lobby-parameter")),
             socket: exit_on_err(pargs.opt_value_from_str(["-p", "--port"])),
             address: exit_on_err(pargs.opt_value_from_str(["-a", "--address"])),
+            listeners: (!listeners.is_empty()).then_some(listeners),
+            listen_unix_socket: exit_on_err(pargs.opt_value_from_str("--unix-socket")),
             private_key_file: exit_on_err(pargs.opt_value_from_str("--private-key-file")),
             certificate_chain_file: exit_on_err(pargs.opt_value_from_str("--cert-chain-file")),
+            tls_reload_interval: tls_reload_interval.map(Duration::from_secs_f64),
+            acme_domains: (!acme_domains.is_empty()).then_some(acme_domains),
+            acme_contact_email: exit_on_err(pargs.opt_value_from_str("--acme-email")),
             max_lobbies: exit_on_err(pargs.opt_value_from_str("--max-lobbies")),
             max_players: exit_on_err(pargs.opt_value_from_str("--max-players")),
+            max_games: exit_on_err(pargs.opt_value_from_str("--max-games")),
+            lobby_idle_timeout: lobby_idle_timeout.map(Duration::from_secs_f64),
+            game_idle_timeout: game_idle_timeout.map(Duration::from_secs_f64),
+            invite_max_joins: exit_on_err(pargs.opt_value_from_str("--invite-max-joins")),
+            max_connections_per_ip: exit_on_err(
+                pargs.opt_value_from_str("--max-connections-per-ip"),
+            ),
+            lobby_joins_per_minute: exit_on_err(
+                pargs.opt_value_from_str("--lobby-joins-per-minute"),
+            ),
+            max_total_players: exit_on_err(pargs.opt_value_from_str("--max-total-players")),
+            max_total_games: exit_on_err(pargs.opt_value_from_str("--max-total-games")),
+            retry_after: retry_after.map(Duration::from_secs_f64),
             heartbeat_interval: heartbeat_interval.map(Duration::from_secs_f64),
             heartbeat_timeout: heartbeat_timeout.map(Duration::from_secs_f64),
             restart_request_timeout: restart_request_timeout.map(Duration::from_secs_f64),
+            reconnect_grace_period: reconnect_grace_period.map(Duration::from_secs_f64),
+            session_recording_dir: exit_on_err(pargs.opt_value_from_str("--session-recording-dir")),
+            session_recording_players: (!recorded_players.is_empty()).then_some(recorded_players),
+            chat_max_length: exit_on_err(pargs.opt_value_from_str("--chat-max-length")),
+            time_warnings,
+            compression_threshold: exit_on_err(pargs.opt_value_from_str("--compression-threshold")),
+            hint_depth: exit_on_err(pargs.opt_value_from_str("--hint-depth")),
+            admin_token: exit_on_err(pargs.opt_value_from_str("--admin-token")),
+            bans_file: exit_on_err(pargs.opt_value_from_str("--bans-file")),
+            serve_from: exit_on_err(pargs.opt_value_from_str("--serve-from")),
+            allowed_origins: (!allowed_origins.is_empty()).then_some(allowed_origins),
+            log_format: exit_on_err(pargs.opt_value_from_str("--log-format")),
+            annotations: exit_on_err(pargs.opt_value_from_str("--annotations")),
+            tls_enabled: pargs.contains("--no-tls").then_some(false),
+            archive_directory: exit_on_err(pargs.opt_value_from_str("--archive-dir")),
+            archive_notation: pargs.contains("--archive-notation").then_some(true),
+            ratings_enabled: pargs.contains("--no-ratings").then_some(false),
+            ratings_file: exit_on_err(pargs.opt_value_from_str("--ratings-file")),
+            name_max_length: exit_on_err(pargs.opt_value_from_str("--name-max-length")),
+            name_charset: exit_on_err(pargs.opt_value_from_str("--name-charset")),
+            qr_ec_level: exit_on_err(pargs.opt_value_from_str("--qr-ec-level")),
+            qr_size: exit_on_err(pargs.opt_value_from_str("--qr-size")),
+            auth_required: pargs.contains("--auth-required").then_some(true),
+            auth_secret: exit_on_err(pargs.opt_value_from_str("--auth-secret")),
+            matchmaking_enabled: pargs.contains("--no-matchmaking").then_some(false),
+            matchmaking_timeout: matchmaking_timeout.map(Duration::from_secs_f64),
+            webhook_urls: (!webhook_urls.is_empty()).then_some(webhook_urls),
+            game_log_enabled: game_log.as_ref().map(|_| true),
+            game_log_file: game_log.map(|f| (f != "-").then(|| PathBuf::from(f))),
         };
 
         let args = Self {
@@ -0,0 +1,56 @@
+//! Blocking WebSocket client shared by the command line tools which talk to
+//! a running server.
+
+use std::{
+    error::Error,
+    io::{self, Read, Write},
+    net::TcpStream,
+    time::Duration,
+};
+
+use openssl::ssl::{SslConnector, SslMethod, SslVerifyMode};
+use tungstenite::WebSocket;
+use url::Url;
+
+pub trait Stream: Read + Write {}
+
+impl<T: Read + Write> Stream for T {}
+
+pub type Socket = WebSocket<Box<dyn Stream>>;
+
+/// Connects to the server, using TLS for `wss` URLs. Reads time out after
+/// `read_timeout`, so that the caller can do other work in between.
+pub fn connect(
+    url: &Url,
+    insecure: bool,
+    read_timeout: Duration,
+) -> Result<Socket, Box<dyn Error>> {
+    let host = url.host_str().ok_or("URL has no host")?;
+    let port = url.port_or_known_default().ok_or("URL has no port")?;
+    let tcp = TcpStream::connect((host, port))?;
+    tcp.set_read_timeout(Some(read_timeout))?;
+
+    let stream: Box<dyn Stream> = if url.scheme() == "wss" {
+        let mut builder = SslConnector::builder(SslMethod::tls())?;
+        if insecure {
+            builder.set_verify(SslVerifyMode::NONE);
+        }
+        Box::new(builder.build().connect(host, tcp)?)
+    } else {
+        Box::new(tcp)
+    };
+
+    let (socket, _) = tungstenite::client(url.as_str(), stream)?;
+    Ok(socket)
+}
+
+/// Returns true if the error only means that no message arrived before the
+/// read timed out.
+#[must_use]
+pub fn is_timeout(err: &tungstenite::Error) -> bool {
+    matches!(
+        err,
+        tungstenite::Error::Io(e)
+            if matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut)
+    )
+}
@@ -0,0 +1,33 @@
+use std::io::{self, Write};
+
+use flate2::{write::DeflateEncoder, Compression};
+
+/// Compresses the text with raw deflate, without zlib or gzip headers.
+pub fn deflate(text: &str) -> io::Result<Vec<u8>> {
+    let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(text.as_bytes())?;
+    encoder.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use flate2::read::DeflateDecoder;
+
+    use super::*;
+
+    #[test]
+    fn deflate_round_trip() {
+        let text =
+            r#"{"type":"gameSync","field":[[null,null,null,null,null,null,null]]}"#.repeat(10);
+        let compressed = deflate(&text).unwrap();
+        assert!(compressed.len() < text.len());
+
+        let mut decompressed = String::new();
+        DeflateDecoder::new(&compressed[..])
+            .read_to_string(&mut decompressed)
+            .unwrap();
+        assert_eq!(decompressed, text);
+    }
+}
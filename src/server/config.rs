@@ -1,6 +1,6 @@
 use std::{
-    fmt, fs, io,
-    net::{IpAddr, Ipv4Addr},
+    fs, io,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
     path::PathBuf,
     str::FromStr,
     time::Duration,
@@ -9,84 +9,795 @@ use std::{
 use serde::{Deserialize, Serialize};
 use url::Url;
 
-use crate::server::serde::as_secs;
+use crate::duration::{as_secs, as_secs_list};
+use crate::game_config::TimerMode;
+use crate::server::profile::NameCharset;
+use crate::server::protocol::{ConfigField, ConfigRejection, ConfigRejectionReason};
+use crate::server::GameConfig;
 
-macro_rules! apply_if_some {
-    ($cfg:expr, $o:expr) => {
-        if let Some(v) = $o {
-            $cfg = v
+/// Settings of `TlsConfig` which used to be set at the top level.
+const LEGACY_TLS_KEYS: [&str; 2] = ["private_key_file", "certificate_chain_file"];
+
+/// Declares `AppConfigPartial` with a field for every setting which can be
+/// overridden, the `AppConfig` field it sets and the environment variable it
+/// is read from.
+macro_rules! partial_config {
+    ($($(#[$attr:meta])* $field:ident: $ty:ty => $($target:ident).+, $var:literal;)*) => {
+        /// Settings overriding the ones read from the configuration file,
+        /// `None` for those which are not set.
+        #[derive(Clone, Default)]
+        pub struct AppConfigPartial {
+            $($(#[$attr])* pub $field: Option<$ty>,)*
+        }
+
+        impl AppConfigPartial {
+            fn read_vars(var: impl Fn(&str) -> Option<String>) -> Result<Self, AppConfigError> {
+                Ok(Self {
+                    $($field: parse_var(&var, $var)?,)*
+                })
+            }
+        }
+
+        impl AppConfig {
+            #[allow(clippy::useless_conversion)]
+            pub fn apply_partial(&mut self, cfg: AppConfigPartial) {
+                $(if let Some(v) = cfg.$field {
+                    self.$($target).+ = v.into();
+                })*
+            }
         }
     };
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Clone, Serialize, Deserialize, Debug)]
 #[serde(default)]
 pub struct AppConfig {
     pub url_base: Url,
     pub url_lobby_parameter: String,
     pub socket: u16,
     pub address: IpAddr,
-    pub private_key_file: PathBuf,
-    pub certificate_chain_file: PathBuf,
+    /// Every address the server listens on. When empty, the server listens
+    /// on `address` and `socket` only.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub listeners: Vec<ListenerConfig>,
+    /// Unix domain socket the server listens on, e.g. for a reverse proxy on
+    /// the same machine. Replaces `address` and `socket` unless `listeners`
+    /// are set. Always serves plain HTTP.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub listen_unix_socket: Option<PathBuf>,
     pub max_lobbies: usize,
     pub max_players: usize,
+    /// Games running at once, including games against the bot and games of
+    /// a series. Starting more is refused, 0 for no limit.
+    pub max_games: usize,
+    /// Lobbies in which nothing happens for this long are closed, 0 to keep
+    /// them open until the host leaves.
+    #[serde(with = "as_secs")]
+    pub lobby_idle_timeout: Duration,
+    /// Games in which no player acts for this long are closed, 0 to keep
+    /// them open until the players leave. Everyone is warned shortly before.
+    #[serde(with = "as_secs")]
+    pub game_idle_timeout: Duration,
+    /// How many players can join using a single invite, 0 for no limit. The
+    /// host has to regenerate the invite to let more players in.
+    pub invite_max_joins: u32,
+    /// How often clients are sent WebSocket pings, 0 to disable.
     #[serde(with = "as_secs")]
     pub heartbeat_interval: Duration,
+    /// Clients which send nothing, not even a pong, for this long are
+    /// disconnected, 0 to disable.
     #[serde(with = "as_secs")]
     pub heartbeat_timeout: Duration,
     #[serde(with = "as_secs")]
     pub restart_request_timeout: Duration,
+    /// How long a game waits for a disconnected player to reconnect before
+    /// they forfeit, 0 to end the game immediately. The turn timer is paused
+    /// in the meantime.
+    #[serde(with = "as_secs")]
+    pub reconnect_grace_period: Duration,
+    /// Directory where every connection is recorded, `None` to disable.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub session_recording_dir: Option<PathBuf>,
+    /// Only connections with one of these player IDs or authenticated
+    /// subjects are recorded, empty to record every connection.
+    pub session_recording_players: Vec<String>,
+    /// Maximum length of a chat message in characters, 0 to disable chat.
+    pub chat_max_length: usize,
+    /// Remaining turn time at which players are warned, empty to disable.
+    #[serde(with = "as_secs_list")]
+    pub time_warnings: Vec<Duration>,
+    /// Messages at least this many bytes long are compressed for clients
+    /// which support it, 0 to disable compression.
+    pub compression_threshold: usize,
+    /// How many turns ahead hints sent to players look, 0 to disable hints.
+    /// Hints are computed by the game itself, so values above 6 slow down
+    /// every turn noticeably.
+    pub hint_depth: u32,
+    /// Bearer token required by the `/admin` endpoints, `None` to disable
+    /// them.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub admin_token: Option<String>,
+    /// JSON file the bans set through the admin API are kept in, `None` to
+    /// keep them in memory until the server stops.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bans_file: Option<PathBuf>,
+    /// Directory with the web client, served at `/` with `index.html` as
+    /// the fallback for unknown paths. `None` to serve nothing.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub serve_from: Option<PathBuf>,
+    /// Origins allowed to open WebSocket connections and to read responses
+    /// of the HTTP endpoints, e.g. `https://example.com`. Empty allows every
+    /// origin.
+    pub allowed_origins: Vec<String>,
+    /// Format of log lines written to stderr.
+    pub log_format: LogFormat,
+    /// Who can comment on the moves of a game.
+    pub annotations: AnnotationAuthors,
+    pub rate_limit: RateLimitConfig,
+    pub connection_limits: ConnectionLimitConfig,
+    pub load_shedding: LoadSheddingConfig,
+    pub outgoing_queue: OutgoingQueueConfig,
+    pub tls: TlsConfig,
+    pub acme: AcmeConfig,
+    pub archive: ArchiveConfig,
+    pub ratings: RatingConfig,
+    pub profiles: ProfileConfig,
+    pub game_limits: GameLimitsConfig,
+    pub qr: QrConfig,
+    pub auth: AuthConfig,
+    pub matchmaking: MatchmakingConfig,
+    pub webhooks: WebhookConfig,
+    pub game_log: GameLogConfig,
 }
 
-pub struct AppConfigPartial {
-    pub url_base: Option<Url>,
-    pub url_lobby_parameter: Option<String>,
-    pub socket: Option<u16>,
-    pub address: Option<IpAddr>,
-    pub private_key_file: Option<PathBuf>,
-    pub certificate_chain_file: Option<PathBuf>,
-    pub max_lobbies: Option<usize>,
-    pub max_players: Option<usize>,
-    pub heartbeat_interval: Option<Duration>,
-    pub heartbeat_timeout: Option<Duration>,
-    pub restart_request_timeout: Option<Duration>,
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub enum LogFormat {
+    /// Human readable lines.
+    Text,
+    /// One JSON object per line, for log collectors such as Loki or ELK.
+    Json,
 }
 
-#[derive(Debug)]
-pub enum AppConfigError {
-    FailedToReadFile(io::Error),
-    FailedToParseContents(toml::de::Error),
+#[derive(Debug, thiserror::Error)]
+#[error("log format must be one of: text, json")]
+pub struct ParseLogFormatError;
+
+impl FromStr for LogFormat {
+    type Err = ParseLogFormatError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(Self::Text),
+            "json" => Ok(Self::Json),
+            _ => Err(ParseLogFormatError),
+        }
+    }
 }
 
-impl fmt::Display for AppConfigError {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match self {
-            Self::FailedToReadFile(e) => write!(f, "failed to read file: {e}"),
-            Self::FailedToParseContents(e) => write!(f, "failed to parse contents: {e}"),
+/// Who can comment on the moves of a game with `gameAnnotate`.
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub enum AnnotationAuthors {
+    /// Annotations are disabled.
+    Nobody,
+    Spectators,
+    /// Players, who can only comment on the moves of their opponent.
+    Opponents,
+    /// Spectators and players.
+    Everyone,
+}
+
+impl AnnotationAuthors {
+    /// Returns true if spectators can comment on moves.
+    #[must_use]
+    pub const fn allows_spectators(self) -> bool {
+        matches!(self, Self::Spectators | Self::Everyone)
+    }
+
+    /// Returns true if players can comment on the moves of their opponent.
+    #[must_use]
+    pub const fn allows_opponents(self) -> bool {
+        matches!(self, Self::Opponents | Self::Everyone)
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("annotation authors must be one of: nobody, spectators, opponents, everyone")]
+pub struct ParseAnnotationAuthorsError;
+
+impl FromStr for AnnotationAuthors {
+    type Err = ParseAnnotationAuthorsError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "nobody" => Ok(Self::Nobody),
+            "spectators" => Ok(Self::Spectators),
+            "opponents" => Ok(Self::Opponents),
+            "everyone" => Ok(Self::Everyone),
+            _ => Err(ParseAnnotationAuthorsError),
         }
     }
 }
 
-impl std::error::Error for AppConfigError {}
+/// Limits how many messages a single client can send.
+#[derive(Clone, PartialEq, Serialize, Deserialize, Debug)]
+#[serde(default)]
+pub struct RateLimitConfig {
+    /// Messages other than pings allowed per second, 0 to disable the limit.
+    pub messages_per_second: f64,
+    /// Messages which can be sent at once before the limit applies.
+    pub message_burst: f64,
+    /// Pings allowed per second, 0 to disable the limit.
+    pub pings_per_second: f64,
+    pub ping_burst: f64,
+    /// The amount of dropped messages after which the client is
+    /// disconnected, 0 to only drop them.
+    pub max_dropped: u32,
+}
+
+/// Limits how many connections a client can open and how often a lobby can
+/// be joined.
+#[derive(Clone, Serialize, Deserialize, Debug)]
+#[serde(default)]
+pub struct ConnectionLimitConfig {
+    /// Open WebSocket connections allowed from a single IP address, 0 for no
+    /// limit.
+    pub max_per_ip: usize,
+    /// Attempts to join a single lobby allowed per minute, 0 for no limit.
+    pub lobby_joins_per_minute: u32,
+    /// Take the client address from the `Forwarded` or `X-Forwarded-For`
+    /// header. Only enable this behind a reverse proxy which sets it,
+    /// otherwise clients can pick any address.
+    pub trust_forwarded_for: bool,
+}
+
+/// Turns new connections away with `503 Service Unavailable` while the whole
+/// server holds too many players or games. Players reconnecting to a game
+/// are always let in.
+#[derive(Clone, Serialize, Deserialize, Debug)]
+#[serde(default)]
+pub struct LoadSheddingConfig {
+    /// Connected players above which new connections are refused, 0 for no
+    /// limit.
+    pub max_players: usize,
+    /// Running games above which new connections are refused, 0 for no
+    /// limit.
+    pub max_games: usize,
+    /// Sent in the `Retry-After` header of refused connections.
+    #[serde(with = "as_secs")]
+    pub retry_after: Duration,
+}
+
+/// Limits how many messages are buffered for a client which reads them
+/// slower than they are sent.
+#[derive(Clone, Serialize, Deserialize, Debug)]
+#[serde(default)]
+pub struct OutgoingQueueConfig {
+    /// Messages written to the connection before the client confirms
+    /// receiving them, the rest wait in the queue. 0 writes every message
+    /// immediately.
+    pub max_in_flight: usize,
+    /// Messages waiting in the queue at most. Once full, a full sync
+    /// replaces the oldest queued one of the same kind, and clients are
+    /// disconnected when nothing is replaced.
+    pub capacity: usize,
+}
+
+/// An address the server listens on.
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Debug)]
+pub struct ListenerConfig {
+    pub address: SocketAddr,
+    /// Whether to serve HTTPS, `None` to follow `TlsConfig::enabled`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tls: Option<bool>,
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug)]
+#[serde(default)]
+pub struct TlsConfig {
+    /// Serve plain HTTP when disabled, e.g. behind a reverse proxy which
+    /// terminates TLS. The files are not read in that case.
+    pub enabled: bool,
+    pub private_key_file: PathBuf,
+    pub certificate_chain_file: PathBuf,
+    /// How often the files are checked for changes, e.g. after a renewal, 0
+    /// to only reload them on SIGHUP.
+    #[serde(with = "as_secs")]
+    pub reload_interval: Duration,
+}
+
+/// Certificates obtained automatically from an ACME server such as Let's
+/// Encrypt. They are written to the files named in `TlsConfig`.
+#[derive(Clone, Serialize, Deserialize, Debug)]
+#[serde(default)]
+pub struct AcmeConfig {
+    /// Domains the certificate is issued for, empty to disable ACME.
+    pub domains: Vec<String>,
+    /// Email the ACME server sends expiry and account notices to.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub contact_email: Option<String>,
+    /// Directory URL of the ACME server.
+    pub directory_url: Url,
+    /// The key of the ACME account, created if it does not exist.
+    pub account_key_file: PathBuf,
+    /// Certificates which expire within this many days are renewed.
+    pub renew_before_days: u32,
+}
+
+/// Where finished games are stored.
+#[derive(Clone, Serialize, Deserialize, Default, Debug)]
+#[serde(default)]
+pub struct ArchiveConfig {
+    /// Directory where every finished game is written as a JSON file,
+    /// `None` to disable the archive.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub directory: Option<PathBuf>,
+    /// Also stores every game in the text notation of `game::notation`.
+    pub notation: bool,
+}
+
+/// Elo ratings of players who connect with a player ID.
+#[derive(Clone, Serialize, Deserialize, Debug)]
+#[serde(default)]
+pub struct RatingConfig {
+    pub enabled: bool,
+    /// JSON file the ratings are kept in, `None` to keep them in memory
+    /// until the server stops.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub file: Option<PathBuf>,
+}
+
+/// Tokens of an existing account system, see `server::auth`.
+#[derive(Clone, Serialize, Deserialize, Default, Debug)]
+#[serde(default)]
+pub struct AuthConfig {
+    /// Rejects connections without a valid token. Requires `secret`.
+    pub required: bool,
+    /// Secret the HS256 tokens are signed with, `None` to ignore tokens.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub secret: Option<String>,
+}
+
+/// Pairing of players who connect with `matchmake=1`, see
+/// `actor::Matchmaker`.
+#[derive(Clone, Serialize, Deserialize, Debug)]
+#[serde(default)]
+pub struct MatchmakingConfig {
+    pub enabled: bool,
+    /// Players waiting for an opponent this long are disconnected, 0 to let
+    /// them wait indefinitely.
+    #[serde(with = "as_secs")]
+    pub queue_timeout: Duration,
+}
+
+/// URLs game events are sent to, see `server::webhook`.
+#[derive(Clone, Serialize, Deserialize, Debug)]
+#[serde(default)]
+pub struct WebhookConfig {
+    /// Every event is sent to each of these, empty to disable webhooks.
+    pub urls: Vec<Url>,
+    /// Events waiting to be sent to a single URL at most. Newer events are
+    /// dropped while the queue is full.
+    pub queue_capacity: usize,
+    /// How many times an event is sent before giving up, with the delay
+    /// doubling after every failure.
+    pub max_attempts: u32,
+    /// Time every request has to connect and be answered.
+    #[serde(with = "as_secs")]
+    pub timeout: Duration,
+}
+
+/// One JSON line per finished round, see `server::game_log`.
+#[derive(Clone, Serialize, Deserialize, Debug)]
+#[serde(default)]
+pub struct GameLogConfig {
+    pub enabled: bool,
+    /// File the lines are appended to, `None` to write them to stdout.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub file: Option<PathBuf>,
+    /// Size in bytes above which the file is rotated, 0 to never rotate it.
+    pub max_file_size: u64,
+    /// Rotated files kept next to the file, `.1` being the newest. 0 empties
+    /// the file instead.
+    pub max_files: u32,
+}
+
+/// Display names and avatars players can set.
+#[derive(Clone, Serialize, Deserialize, Debug)]
+#[serde(default)]
+pub struct ProfileConfig {
+    /// Maximum length of a name in characters, 0 to disable profiles.
+    pub name_max_length: usize,
+    /// Characters allowed in names, `ascii` or `unicode`.
+    pub name_charset: NameCharset,
+}
+
+/// QR codes of invites sent to hosts.
+#[derive(Clone, Serialize, Deserialize, Debug)]
+#[serde(default)]
+pub struct QrConfig {
+    /// How much of the code can be damaged before it becomes unreadable.
+    /// Higher levels make the code denser.
+    pub ec_level: QrEcLevel,
+    /// Largest width of the image in pixels, rounded down to a whole amount
+    /// of pixels per module. 0 draws every module as a single pixel.
+    pub size: u32,
+}
+
+/// Error correction level of a QR code, recovering about 7%, 15%, 25% or
+/// 30% of the code.
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub enum QrEcLevel {
+    Low,
+    Medium,
+    Quartile,
+    High,
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("error correction level must be one of: low, medium, quartile, high")]
+pub struct ParseQrEcLevelError;
+
+impl FromStr for QrEcLevel {
+    type Err = ParseQrEcLevelError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "low" => Ok(Self::Low),
+            "medium" => Ok(Self::Medium),
+            "quartile" => Ok(Self::Quartile),
+            "high" => Ok(Self::High),
+            _ => Err(ParseQrEcLevelError),
+        }
+    }
+}
+
+/// Bounds of the game configurations clients can start games with. Timers
+/// set to 0 are disabled and always allowed. Maximums of 0 disable the
+/// limit.
+#[derive(Clone, Serialize, Deserialize, Debug)]
+#[serde(default)]
+pub struct GameLimitsConfig {
+    #[serde(with = "as_secs")]
+    pub min_time_per_turn: Duration,
+    #[serde(with = "as_secs")]
+    pub max_time_per_turn: Duration,
+    #[serde(with = "as_secs")]
+    pub max_time_cap: Duration,
+    #[serde(with = "as_secs")]
+    pub min_total_time: Duration,
+    #[serde(with = "as_secs")]
+    pub max_total_time: Duration,
+    #[serde(with = "as_secs")]
+    pub max_increment: Duration,
+    /// Most byo-yomi periods a game can be played with.
+    pub max_byo_yomi_periods: u8,
+}
+
+partial_config! {
+    url_base: Url => url_base, "C4_URL_BASE";
+    url_lobby_parameter: String => url_lobby_parameter, "C4_URL_LOBBY_PARAMETER";
+    socket: u16 => socket, "C4_PORT";
+    address: IpAddr => address, "C4_ADDRESS";
+    listeners: Vec<ListenerConfig> => listeners, "C4_LISTEN";
+    listen_unix_socket: PathBuf => listen_unix_socket, "C4_UNIX_SOCKET";
+    private_key_file: PathBuf => tls.private_key_file, "C4_PRIVATE_KEY_FILE";
+    certificate_chain_file: PathBuf => tls.certificate_chain_file, "C4_CERT_CHAIN_FILE";
+    tls_reload_interval: Duration => tls.reload_interval, "C4_TLS_RELOAD_INTERVAL";
+    acme_domains: Vec<String> => acme.domains, "C4_ACME_DOMAIN";
+    acme_contact_email: String => acme.contact_email, "C4_ACME_EMAIL";
+    max_lobbies: usize => max_lobbies, "C4_MAX_LOBBIES";
+    max_players: usize => max_players, "C4_MAX_PLAYERS";
+    max_games: usize => max_games, "C4_MAX_GAMES";
+    lobby_idle_timeout: Duration => lobby_idle_timeout, "C4_LOBBY_IDLE_TIMEOUT";
+    game_idle_timeout: Duration => game_idle_timeout, "C4_GAME_IDLE_TIMEOUT";
+    invite_max_joins: u32 => invite_max_joins, "C4_INVITE_MAX_JOINS";
+    max_connections_per_ip: usize => connection_limits.max_per_ip, "C4_MAX_CONNECTIONS_PER_IP";
+    lobby_joins_per_minute: u32 => connection_limits.lobby_joins_per_minute, "C4_LOBBY_JOINS_PER_MINUTE";
+    max_total_players: usize => load_shedding.max_players, "C4_MAX_TOTAL_PLAYERS";
+    max_total_games: usize => load_shedding.max_games, "C4_MAX_TOTAL_GAMES";
+    retry_after: Duration => load_shedding.retry_after, "C4_RETRY_AFTER";
+    heartbeat_interval: Duration => heartbeat_interval, "C4_HEARTBEAT_INTERVAL";
+    heartbeat_timeout: Duration => heartbeat_timeout, "C4_HEARTBEAT_TIMEOUT";
+    restart_request_timeout: Duration => restart_request_timeout, "C4_RESTART_REQUEST_TIMEOUT";
+    reconnect_grace_period: Duration => reconnect_grace_period, "C4_RECONNECT_GRACE_PERIOD";
+    session_recording_dir: PathBuf => session_recording_dir, "C4_SESSION_RECORDING_DIR";
+    session_recording_players: Vec<String> => session_recording_players, "C4_SESSION_RECORDING_PLAYER";
+    chat_max_length: usize => chat_max_length, "C4_CHAT_MAX_LENGTH";
+    time_warnings: Vec<Duration> => time_warnings, "C4_TIME_WARNINGS";
+    compression_threshold: usize => compression_threshold, "C4_COMPRESSION_THRESHOLD";
+    hint_depth: u32 => hint_depth, "C4_HINT_DEPTH";
+    admin_token: String => admin_token, "C4_ADMIN_TOKEN";
+    bans_file: PathBuf => bans_file, "C4_BANS_FILE";
+    serve_from: PathBuf => serve_from, "C4_SERVE_FROM";
+    allowed_origins: Vec<String> => allowed_origins, "C4_ALLOW_ORIGIN";
+    log_format: LogFormat => log_format, "C4_LOG_FORMAT";
+    annotations: AnnotationAuthors => annotations, "C4_ANNOTATIONS";
+    tls_enabled: bool => tls.enabled, "C4_TLS";
+    archive_directory: PathBuf => archive.directory, "C4_ARCHIVE_DIR";
+    archive_notation: bool => archive.notation, "C4_ARCHIVE_NOTATION";
+    ratings_enabled: bool => ratings.enabled, "C4_RATINGS";
+    ratings_file: PathBuf => ratings.file, "C4_RATINGS_FILE";
+    name_max_length: usize => profiles.name_max_length, "C4_NAME_MAX_LENGTH";
+    name_charset: NameCharset => profiles.name_charset, "C4_NAME_CHARSET";
+    qr_ec_level: QrEcLevel => qr.ec_level, "C4_QR_EC_LEVEL";
+    qr_size: u32 => qr.size, "C4_QR_SIZE";
+    auth_required: bool => auth.required, "C4_AUTH_REQUIRED";
+    auth_secret: String => auth.secret, "C4_AUTH_SECRET";
+    matchmaking_enabled: bool => matchmaking.enabled, "C4_MATCHMAKING";
+    matchmaking_timeout: Duration => matchmaking.queue_timeout, "C4_MATCHMAKING_TIMEOUT";
+    webhook_urls: Vec<Url> => webhooks.urls, "C4_WEBHOOK";
+    game_log_enabled: bool => game_log.enabled, "C4_GAME_LOG_ENABLED";
+    /// `Some(None)` writes the game log to stdout.
+    game_log_file: Option<PathBuf> => game_log.file, "C4_GAME_LOG";
+}
+
+/// A setting which can be read from an environment variable. Lists are
+/// separated by commas, durations are in seconds.
+trait FromVar: Sized {
+    fn from_var(value: &str) -> Result<Self, String>;
+}
+
+macro_rules! from_var_with_from_str {
+    ($($ty:ty),*) => {
+        $(impl FromVar for $ty {
+            fn from_var(value: &str) -> Result<Self, String> {
+                value.parse().map_err(|e| format!("{e}"))
+            }
+        })*
+    };
+}
+
+from_var_with_from_str!(
+    String,
+    PathBuf,
+    Url,
+    IpAddr,
+    bool,
+    u16,
+    u32,
+    usize,
+    LogFormat,
+    AnnotationAuthors,
+    NameCharset,
+    QrEcLevel
+);
+
+impl FromVar for Duration {
+    fn from_var(value: &str) -> Result<Self, String> {
+        let secs: f64 = value.trim().parse().map_err(|e| format!("{e}"))?;
+        Self::try_from_secs_f64(secs).map_err(|e| format!("{e}"))
+    }
+}
+
+impl FromVar for ListenerConfig {
+    fn from_var(value: &str) -> Result<Self, String> {
+        let address = value.trim().parse().map_err(|e| format!("{e}"))?;
+        Ok(Self { address, tls: None })
+    }
+}
+
+/// `-` stands for `None`, e.g. to write the game log to stdout.
+impl FromVar for Option<PathBuf> {
+    fn from_var(value: &str) -> Result<Self, String> {
+        Ok((value != "-").then(|| PathBuf::from(value)))
+    }
+}
+
+impl<T: FromVar> FromVar for Vec<T> {
+    fn from_var(value: &str) -> Result<Self, String> {
+        value
+            .split(',')
+            .filter(|v| !v.trim().is_empty())
+            .map(|v| T::from_var(v.trim()))
+            .collect()
+    }
+}
+
+/// Returns the value of the variable, or `None` if it is not set.
+fn parse_var<T: FromVar>(
+    var: &impl Fn(&str) -> Option<String>,
+    name: &'static str,
+) -> Result<Option<T>, AppConfigError> {
+    var(name)
+        .map(|value| {
+            T::from_var(&value).map_err(|reason| AppConfigError::InvalidVar { name, reason })
+        })
+        .transpose()
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum AppConfigError {
+    #[error("failed to read file: {0}")]
+    FailedToReadFile(#[source] io::Error),
+    #[error("failed to parse contents: {0}")]
+    FailedToParseContents(#[source] toml::de::Error),
+    #[error("invalid value of {name}: {reason}")]
+    InvalidVar { name: &'static str, reason: String },
+    #[error("{0} is set both at the top level and in the [tls] section")]
+    DuplicateTlsKey(&'static str),
+}
+
+impl AppConfigPartial {
+    /// Reads the settings set through `C4_*` environment variables.
+    pub fn from_env() -> Result<Self, AppConfigError> {
+        Self::from_vars(|name| std::env::var(name).ok())
+    }
+
+    /// Reads the settings from variables looked up with `var`. Like
+    /// `--game-log`, `C4_GAME_LOG` also enables the game log.
+    pub fn from_vars(var: impl Fn(&str) -> Option<String>) -> Result<Self, AppConfigError> {
+        let mut cfg = Self::read_vars(var)?;
+        if cfg.game_log_file.is_some() {
+            cfg.game_log_enabled.get_or_insert(true);
+        }
+        Ok(cfg)
+    }
+}
 
 impl AppConfig {
     pub fn from_file(path: &PathBuf) -> Result<Self, AppConfigError> {
         let cfg = fs::read_to_string(path).map_err(AppConfigError::FailedToReadFile)?;
-        toml::from_str::<Self>(&cfg).map_err(AppConfigError::FailedToParseContents)
+        Self::from_toml(&cfg)
+    }
+
+    /// Parses the contents of a configuration file. The certificate files
+    /// are still read from the top level, where they were before the `[tls]`
+    /// section existed.
+    pub fn from_toml(cfg: &str) -> Result<Self, AppConfigError> {
+        let mut table: toml::Table =
+            toml::from_str(cfg).map_err(AppConfigError::FailedToParseContents)?;
+        for key in LEGACY_TLS_KEYS {
+            let Some(value) = table.remove(key) else {
+                continue;
+            };
+            let tls = table
+                .entry("tls")
+                .or_insert_with(|| toml::Value::Table(toml::Table::new()));
+            if let toml::Value::Table(tls) = tls {
+                if tls.insert(String::from(key), value).is_some() {
+                    return Err(AppConfigError::DuplicateTlsKey(key));
+                }
+            }
+        }
+        toml::Value::Table(table)
+            .try_into()
+            .map_err(AppConfigError::FailedToParseContents)
+    }
+
+    /// Takes the settings which can change while the server runs from
+    /// `new`, see `server::reload`. The rest stays as the server started.
+    pub fn apply_reloadable(&mut self, new: &Self) {
+        self.max_lobbies = new.max_lobbies;
+        self.max_players = new.max_players;
+        self.heartbeat_interval = new.heartbeat_interval;
+        self.heartbeat_timeout = new.heartbeat_timeout;
+        self.restart_request_timeout = new.restart_request_timeout;
+        self.rate_limit = new.rate_limit.clone();
+    }
+
+    /// Returns every TCP address the server should listen on and whether to
+    /// serve HTTPS on it.
+    #[must_use]
+    pub fn listeners(&self) -> Vec<(SocketAddr, bool)> {
+        if self.listeners.is_empty() {
+            if self.listen_unix_socket.is_some() {
+                return Vec::new();
+            }
+            let address = SocketAddr::new(self.address, self.socket);
+            return vec![(address, self.tls.enabled)];
+        }
+
+        self.listeners
+            .iter()
+            .map(|l| (l.address, l.tls.unwrap_or(self.tls.enabled)))
+            .collect()
     }
 
-    pub fn apply_partial(&mut self, cfg: AppConfigPartial) {
-        apply_if_some!(self.url_base, cfg.url_base);
-        apply_if_some!(self.url_lobby_parameter, cfg.url_lobby_parameter);
-        apply_if_some!(self.socket, cfg.socket);
-        apply_if_some!(self.address, cfg.address);
-        apply_if_some!(self.private_key_file, cfg.private_key_file);
-        apply_if_some!(self.certificate_chain_file, cfg.certificate_chain_file);
-        apply_if_some!(self.max_lobbies, cfg.max_lobbies);
-        apply_if_some!(self.max_players, cfg.max_players);
-        apply_if_some!(self.heartbeat_interval, cfg.heartbeat_interval);
-        apply_if_some!(self.heartbeat_timeout, cfg.heartbeat_timeout);
-        apply_if_some!(self.restart_request_timeout, cfg.restart_request_timeout);
+    /// Returns true if a connection with the player ID and authenticated
+    /// subject is recorded, provided that `session_recording_dir` is set.
+    #[must_use]
+    pub fn is_session_recorded(&self, player_id: Option<&str>, subject: Option<&str>) -> bool {
+        self.session_recording_players.is_empty()
+            || [player_id, subject]
+                .into_iter()
+                .flatten()
+                .any(|id| self.session_recording_players.iter().any(|p| p == id))
+    }
+
+    /// Returns true if requests sent from `origin` are allowed.
+    #[must_use]
+    pub fn is_origin_allowed(&self, origin: &str) -> bool {
+        self.allowed_origins.is_empty()
+            || self
+                .allowed_origins
+                .iter()
+                .any(|o| o.trim_end_matches('/').eq_ignore_ascii_case(origin))
+    }
+}
+
+impl GameLimitsConfig {
+    /// Checks a game configuration requested by a client, returning the
+    /// first field which is out of bounds.
+    pub fn check(&self, config: &GameConfig) -> Result<(), ConfigRejection> {
+        let validity = [
+            (ConfigField::Players, config.are_players_valid()),
+            (ConfigField::WinLen, config.is_win_len_valid()),
+            (ConfigField::Seed, config.is_seed_valid()),
+            (ConfigField::FreePlacement, config.is_placement_valid()),
+            (ConfigField::ByoYomiPeriods, config.is_timer_valid()),
+        ];
+        if let Some((field, _)) = validity.into_iter().find(|(_, valid)| !valid) {
+            return Err(ConfigRejection {
+                field,
+                reason: ConfigRejectionReason::Invalid,
+            });
+        }
+
+        let timers = [
+            (
+                ConfigField::TimePerTurn,
+                config.time_per_turn,
+                self.min_time_per_turn,
+                self.max_time_per_turn,
+            ),
+            (
+                ConfigField::TimeCap,
+                config.time_cap,
+                Duration::ZERO,
+                self.max_time_cap,
+            ),
+            (
+                ConfigField::TotalTime,
+                config.total_time,
+                self.min_total_time,
+                self.max_total_time,
+            ),
+            (
+                ConfigField::Increment,
+                config.increment,
+                Duration::ZERO,
+                self.max_increment,
+            ),
+        ];
+        for (field, value, min, max) in timers {
+            let reason = if value.is_zero() {
+                continue;
+            } else if value < min {
+                ConfigRejectionReason::BelowMinimum
+            } else if !max.is_zero() && value > max {
+                ConfigRejectionReason::AboveMaximum
+            } else {
+                continue;
+            };
+            return Err(ConfigRejection { field, reason });
+        }
+
+        let periods = self.max_byo_yomi_periods;
+        if config.timer_mode == TimerMode::ByoYomi
+            && periods > 0
+            && config.byo_yomi_periods > periods
+        {
+            return Err(ConfigRejection {
+                field: ConfigField::ByoYomiPeriods,
+                reason: ConfigRejectionReason::AboveMaximum,
+            });
+        }
+        Ok(())
+    }
+
+    /// Checks the configuration of a game between two players, such as a
+    /// game of a series or a restarted two player game.
+    pub fn check_two_player(&self, config: &GameConfig) -> Result<(), ConfigRejection> {
+        if config.players != 2 {
+            return Err(ConfigRejection {
+                field: ConfigField::Players,
+                reason: ConfigRejectionReason::Invalid,
+            });
+        }
+        self.check(config)
     }
 }
 
@@ -96,14 +807,314 @@ impl Default for AppConfig {
             url_base: Url::from_str("https://localhost:8080").unwrap(),
             url_lobby_parameter: String::from("lobby"),
             socket: 8080,
-            address: Ipv4Addr::new(127, 0, 0, 1).into(),
-            private_key_file: PathBuf::from_str("./certs/key.pem").unwrap(),
-            certificate_chain_file: PathBuf::from_str("./certs/cert.pem").unwrap(),
+            address: Ipv4Addr::LOCALHOST.into(),
+            listeners: Vec::new(),
+            listen_unix_socket: None,
             max_lobbies: 100,
             max_players: 20,
+            max_games: 0,
+            lobby_idle_timeout: Duration::from_mins(30),
+            game_idle_timeout: Duration::from_mins(30),
+            invite_max_joins: 0,
             heartbeat_interval: Duration::from_secs(5),
             heartbeat_timeout: Duration::from_secs(30),
-            restart_request_timeout: Duration::from_secs(60),
+            restart_request_timeout: Duration::from_mins(1),
+            reconnect_grace_period: Duration::from_secs(30),
+            session_recording_dir: None,
+            session_recording_players: Vec::new(),
+            chat_max_length: 200,
+            time_warnings: [30, 10, 5].map(Duration::from_secs).to_vec(),
+            compression_threshold: 1024,
+            hint_depth: 0,
+            admin_token: None,
+            bans_file: None,
+            serve_from: None,
+            allowed_origins: Vec::new(),
+            log_format: LogFormat::Text,
+            annotations: AnnotationAuthors::Everyone,
+            rate_limit: RateLimitConfig::default(),
+            connection_limits: ConnectionLimitConfig::default(),
+            load_shedding: LoadSheddingConfig::default(),
+            outgoing_queue: OutgoingQueueConfig::default(),
+            tls: TlsConfig::default(),
+            acme: AcmeConfig::default(),
+            archive: ArchiveConfig::default(),
+            ratings: RatingConfig::default(),
+            profiles: ProfileConfig::default(),
+            game_limits: GameLimitsConfig::default(),
+            qr: QrConfig::default(),
+            auth: AuthConfig::default(),
+            matchmaking: MatchmakingConfig::default(),
+            webhooks: WebhookConfig::default(),
+            game_log: GameLogConfig::default(),
+        }
+    }
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        Self {
+            messages_per_second: 10.0,
+            message_burst: 20.0,
+            pings_per_second: 2.0,
+            ping_burst: 5.0,
+            max_dropped: 50,
+        }
+    }
+}
+
+impl Default for ConnectionLimitConfig {
+    fn default() -> Self {
+        Self {
+            max_per_ip: 20,
+            lobby_joins_per_minute: 30,
+            trust_forwarded_for: false,
+        }
+    }
+}
+
+impl Default for LoadSheddingConfig {
+    fn default() -> Self {
+        Self {
+            max_players: 0,
+            max_games: 0,
+            retry_after: Duration::from_secs(30),
+        }
+    }
+}
+
+impl Default for QrConfig {
+    fn default() -> Self {
+        Self {
+            ec_level: QrEcLevel::Low,
+            size: 0,
         }
     }
 }
+
+impl Default for OutgoingQueueConfig {
+    fn default() -> Self {
+        Self {
+            max_in_flight: 32,
+            capacity: 64,
+        }
+    }
+}
+
+impl Default for TlsConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            private_key_file: PathBuf::from_str("./certs/key.pem").unwrap(),
+            certificate_chain_file: PathBuf::from_str("./certs/cert.pem").unwrap(),
+            reload_interval: Duration::from_hours(1),
+        }
+    }
+}
+
+impl Default for AcmeConfig {
+    fn default() -> Self {
+        Self {
+            domains: Vec::new(),
+            contact_email: None,
+            directory_url: Url::from_str("https://acme-v02.api.letsencrypt.org/directory").unwrap(),
+            account_key_file: PathBuf::from_str("./certs/acme-account.pem").unwrap(),
+            renew_before_days: 30,
+        }
+    }
+}
+
+impl Default for RatingConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            file: None,
+        }
+    }
+}
+
+impl Default for MatchmakingConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            queue_timeout: Duration::from_mins(5),
+        }
+    }
+}
+
+impl Default for WebhookConfig {
+    fn default() -> Self {
+        Self {
+            urls: Vec::new(),
+            queue_capacity: 256,
+            max_attempts: 3,
+            timeout: Duration::from_secs(10),
+        }
+    }
+}
+
+impl Default for GameLogConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            file: None,
+            max_file_size: 10 * 1024 * 1024,
+            max_files: 5,
+        }
+    }
+}
+
+impl Default for ProfileConfig {
+    fn default() -> Self {
+        Self {
+            name_max_length: 20,
+            name_charset: NameCharset::Unicode,
+        }
+    }
+}
+
+impl Default for GameLimitsConfig {
+    fn default() -> Self {
+        Self {
+            min_time_per_turn: Duration::from_secs(3),
+            max_time_per_turn: Duration::from_mins(10),
+            max_time_cap: Duration::from_hours(1),
+            min_total_time: Duration::from_secs(10),
+            max_total_time: Duration::from_hours(3),
+            max_increment: Duration::from_mins(10),
+            max_byo_yomi_periods: 10,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    #[test]
+    fn reads_certificate_files_from_the_top_level() {
+        let cfg = AppConfig::from_toml(
+            "max_lobbies = 3\nprivate_key_file = \"key.pem\"\n[tls]\nenabled = false\n",
+        )
+        .unwrap();
+        assert_eq!(cfg.tls.private_key_file, PathBuf::from("key.pem"));
+        assert_eq!(
+            cfg.tls.certificate_chain_file,
+            TlsConfig::default().certificate_chain_file
+        );
+        assert!(!cfg.tls.enabled);
+        assert_eq!(cfg.max_lobbies, 3);
+
+        let cfg = AppConfig::from_toml("certificate_chain_file = \"cert.pem\"\n").unwrap();
+        assert_eq!(cfg.tls.certificate_chain_file, PathBuf::from("cert.pem"));
+
+        let err = AppConfig::from_toml(
+            "private_key_file = \"a.pem\"\n[tls]\nprivate_key_file = \"b.pem\"\n",
+        );
+        assert!(matches!(
+            err,
+            Err(AppConfigError::DuplicateTlsKey("private_key_file"))
+        ));
+    }
+
+    #[test]
+    fn records_only_listed_players() {
+        let mut cfg = AppConfig::default();
+        assert!(cfg.is_session_recorded(None, None));
+
+        cfg.session_recording_players = vec![String::from("alice"), String::from("sub-1")];
+        assert!(cfg.is_session_recorded(Some("alice"), None));
+        assert!(cfg.is_session_recorded(Some("bob"), Some("sub-1")));
+        assert!(!cfg.is_session_recorded(Some("bob"), None));
+        assert!(!cfg.is_session_recorded(None, None));
+    }
+
+    #[test]
+    fn reads_environment_variables() {
+        let vars = HashMap::from([
+            ("C4_MAX_LOBBIES", "12"),
+            ("C4_TLS", "false"),
+            ("C4_ADMIN_TOKEN", "secret"),
+            ("C4_TIME_WARNINGS", "10, 2.5"),
+            ("C4_LISTEN", "127.0.0.1:8080,[::1]:8080"),
+            ("C4_GAME_LOG", "-"),
+        ]);
+        let partial = AppConfigPartial::from_vars(|name| vars.get(name).map(ToString::to_string));
+        let mut cfg = AppConfig::default();
+        cfg.apply_partial(partial.unwrap());
+        assert_eq!(cfg.max_lobbies, 12);
+        assert!(!cfg.tls.enabled);
+        assert_eq!(cfg.admin_token.as_deref(), Some("secret"));
+        assert_eq!(
+            cfg.time_warnings,
+            [Duration::from_secs(10), Duration::from_millis(2500)]
+        );
+        assert_eq!(cfg.listeners.len(), 2);
+        assert!(cfg.game_log.enabled);
+        assert_eq!(cfg.game_log.file, None);
+
+        let err = AppConfigPartial::from_vars(|name| {
+            (name == "C4_MAX_GAMES").then(|| String::from("many"))
+        });
+        assert!(matches!(
+            err,
+            Err(AppConfigError::InvalidVar {
+                name: "C4_MAX_GAMES",
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn game_limits() {
+        let limits = GameLimitsConfig::default();
+        let mut config = GameConfig::default();
+        assert_eq!(limits.check(&config), Ok(()));
+
+        config.time_per_turn = Duration::from_millis(1);
+        let rejection = limits.check(&config).unwrap_err();
+        assert_eq!(rejection.field, ConfigField::TimePerTurn);
+        assert_eq!(rejection.reason, ConfigRejectionReason::BelowMinimum);
+
+        config.time_per_turn = Duration::from_secs(30);
+        config.time_cap = Duration::from_hours(2);
+        let rejection = limits.check(&config).unwrap_err();
+        assert_eq!(rejection.field, ConfigField::TimeCap);
+        assert_eq!(rejection.reason, ConfigRejectionReason::AboveMaximum);
+
+        config.time_cap = Duration::ZERO;
+        config.win_len = 0;
+        let rejection = limits.check(&config).unwrap_err();
+        assert_eq!(rejection.field, ConfigField::WinLen);
+
+        config.win_len = 4;
+        config.players = 3;
+        assert_eq!(limits.check(&config), Ok(()));
+        let rejection = limits.check_two_player(&config).unwrap_err();
+        assert_eq!(rejection.field, ConfigField::Players);
+        config.pop_out = true;
+        config.allow_draws = true;
+        assert_eq!(limits.check(&config), Ok(()));
+        config.pop_out = false;
+        config.allow_draws = false;
+        config.pie_rule = true;
+        assert_eq!(
+            limits.check(&config).unwrap_err().field,
+            ConfigField::Players
+        );
+
+        config.players = 2;
+        config.pie_rule = false;
+        config.timer_mode = TimerMode::ByoYomi;
+        let rejection = limits.check(&config).unwrap_err();
+        assert_eq!(rejection.field, ConfigField::ByoYomiPeriods);
+        assert_eq!(rejection.reason, ConfigRejectionReason::Invalid);
+        config.byo_yomi_periods = 11;
+        let rejection = limits.check(&config).unwrap_err();
+        assert_eq!(rejection.reason, ConfigRejectionReason::AboveMaximum);
+        config.byo_yomi_periods = 5;
+        assert_eq!(limits.check(&config), Ok(()));
+    }
+}
@@ -0,0 +1,118 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use uuid::Uuid;
+
+use crate::server::config::ConnectionLimitConfig;
+
+/// Length of the window in which join attempts of a lobby are counted.
+const JOIN_WINDOW: Duration = Duration::from_mins(1);
+
+/// Keeps track of the open connections of every client IP and of recent
+/// attempts to join every lobby. Shared by all workers of the server.
+pub struct ConnectionLimiter {
+    max_per_ip: usize,
+    lobby_joins_per_minute: u32,
+    connections: Mutex<HashMap<IpAddr, usize>>,
+    /// Start of the current window and the attempts made in it.
+    joins: Mutex<HashMap<Uuid, (Instant, u32)>>,
+}
+
+/// Open connection of a client, released once dropped.
+pub struct ConnectionGuard {
+    limiter: Arc<ConnectionLimiter>,
+    ip: IpAddr,
+}
+
+impl ConnectionLimiter {
+    #[must_use]
+    pub fn new(cfg: &ConnectionLimitConfig) -> Self {
+        Self {
+            max_per_ip: cfg.max_per_ip,
+            lobby_joins_per_minute: cfg.lobby_joins_per_minute,
+            connections: Mutex::new(HashMap::new()),
+            joins: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Counts a new connection from the IP, or returns `None` if it already
+    /// has as many open connections as allowed.
+    #[must_use]
+    pub fn try_connect(self: &Arc<Self>, ip: IpAddr) -> Option<ConnectionGuard> {
+        let mut connections = self.connections.lock().unwrap();
+        let count = connections.entry(ip).or_default();
+        if self.max_per_ip > 0 && *count >= self.max_per_ip {
+            return None;
+        }
+
+        *count += 1;
+        Some(ConnectionGuard {
+            limiter: Arc::clone(self),
+            ip,
+        })
+    }
+
+    /// Counts an attempt to join the lobby. Returns false if there have been
+    /// too many in the last minute.
+    #[must_use]
+    pub fn try_join(&self, lobby: Uuid) -> bool {
+        if self.lobby_joins_per_minute == 0 {
+            return true;
+        }
+
+        let now = Instant::now();
+        let mut joins = self.joins.lock().unwrap();
+        joins.retain(|_, (start, _)| now.duration_since(*start) < JOIN_WINDOW);
+        let (_, attempts) = joins.entry(lobby).or_insert((now, 0));
+        *attempts += 1;
+        *attempts <= self.lobby_joins_per_minute
+    }
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        let mut connections = self.limiter.connections.lock().unwrap();
+        if let Some(count) = connections.get_mut(&self.ip) {
+            *count -= 1;
+            if *count == 0 {
+                connections.remove(&self.ip);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use super::*;
+
+    #[test]
+    fn limits_connections_and_joins() {
+        let cfg = ConnectionLimitConfig {
+            max_per_ip: 2,
+            lobby_joins_per_minute: 1,
+            trust_forwarded_for: false,
+        };
+        let limiter = Arc::new(ConnectionLimiter::new(&cfg));
+        let ip = Ipv4Addr::LOCALHOST.into();
+        let connections = |ip| limiter.connections.lock().unwrap().get(&ip).copied();
+
+        let first = limiter.try_connect(ip);
+        let second = limiter.try_connect(ip);
+        assert!(first.is_some() && second.is_some());
+        assert!(limiter.try_connect(ip).is_none());
+        drop(first);
+        assert_eq!(connections(ip), Some(1));
+        assert!(limiter.try_connect(ip).is_some());
+        drop(second);
+        assert_eq!(connections(ip), None);
+
+        let lobby = Uuid::new_v4();
+        assert!(limiter.try_join(lobby));
+        assert!(!limiter.try_join(lobby));
+        assert!(limiter.try_join(Uuid::new_v4()));
+    }
+}
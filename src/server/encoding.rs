@@ -0,0 +1,346 @@
+//! Binary encodings of the protocol, negotiated with the `encoding` query
+//! parameter. Messages are serialized with serde into every encoding, so
+//! every encoding carries exactly the same message types.
+
+use std::io;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use serde::{de::DeserializeOwned, Serialize};
+
+/// Nesting deeper than this is rejected when decoding, no valid message
+/// comes close.
+const MAX_DEPTH: usize = 32;
+
+/// Clients connected with msgpack and CBOR. Messages are only serialized
+/// into the binary encodings someone uses.
+static MESSAGE_PACK_CLIENTS: AtomicUsize = AtomicUsize::new(0);
+static CBOR_CLIENTS: AtomicUsize = AtomicUsize::new(0);
+
+/// Encoding of the messages exchanged with a client.
+#[derive(Clone, Copy, PartialEq, Eq, Default, Debug)]
+pub enum Encoding {
+    /// Text frames containing JSON.
+    #[default]
+    Json,
+    /// Binary frames containing msgpack.
+    MessagePack,
+    /// Binary frames containing CBOR.
+    Cbor,
+}
+
+impl FromStr for Encoding {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "json" => Ok(Self::Json),
+            "msgpack" => Ok(Self::MessagePack),
+            "cbor" => Ok(Self::Cbor),
+            _ => Err(()),
+        }
+    }
+}
+
+impl Encoding {
+    /// Returns the name used in the `encoding` query parameter.
+    #[must_use]
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::Json => "json",
+            Self::MessagePack => "msgpack",
+            Self::Cbor => "cbor",
+        }
+    }
+}
+
+/// Why a message could not be serialized.
+#[derive(Debug, thiserror::Error)]
+pub enum EncodeError {
+    #[error("json: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("msgpack: {0}")]
+    MessagePack(#[from] rmp_serde::encode::Error),
+    #[error("cbor: {0}")]
+    Cbor(#[from] ciborium::ser::Error<io::Error>),
+}
+
+/// Why a binary message could not be decoded.
+#[derive(Debug, thiserror::Error)]
+pub enum DecodeError {
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+    #[error(transparent)]
+    MessagePack(#[from] rmp_serde::decode::Error),
+    #[error(transparent)]
+    Cbor(#[from] ciborium::de::Error<io::Error>),
+    /// Bytes were left over after the message.
+    #[error("trailing bytes after message")]
+    TrailingBytes,
+}
+
+impl Encoding {
+    /// Whether messages are sent in binary frames.
+    #[must_use]
+    pub fn is_binary(self) -> bool {
+        self != Self::Json
+    }
+
+    /// Counts a client using the encoding until the guard is dropped.
+    #[must_use]
+    pub fn track(self) -> EncodingGuard {
+        if let Some(clients) = self.clients() {
+            clients.fetch_add(1, Ordering::Relaxed);
+        }
+        EncodingGuard(self)
+    }
+
+    fn clients(self) -> Option<&'static AtomicUsize> {
+        match self {
+            Self::Json => None,
+            Self::MessagePack => Some(&MESSAGE_PACK_CLIENTS),
+            Self::Cbor => Some(&CBOR_CLIENTS),
+        }
+    }
+
+    /// Whether a connected client uses the encoding, JSON always is.
+    fn is_used(self) -> bool {
+        self.clients()
+            .is_none_or(|clients| clients.load(Ordering::Relaxed) > 0)
+    }
+
+    /// Decodes a binary message in the encoding.
+    pub fn decode<T: DeserializeOwned>(self, mut bytes: &[u8]) -> Result<T, DecodeError> {
+        let msg = match self {
+            Self::Json => return Ok(serde_json::from_slice(bytes)?),
+            Self::MessagePack => {
+                let mut de = rmp_serde::Deserializer::new(&mut bytes).with_human_readable();
+                de.set_max_depth(MAX_DEPTH);
+                T::deserialize(&mut de)?
+            }
+            Self::Cbor => ciborium::de::from_reader_with_recursion_limit(&mut bytes, MAX_DEPTH)?,
+        };
+        if !bytes.is_empty() {
+            return Err(DecodeError::TrailingBytes);
+        }
+        Ok(msg)
+    }
+}
+
+/// Client of a binary encoding, counted until dropped.
+pub struct EncodingGuard(Encoding);
+
+impl Drop for EncodingGuard {
+    fn drop(&mut self) {
+        if let Some(clients) = self.0.clients() {
+            clients.fetch_sub(1, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Message serialized as JSON, and in every binary encoding used by a client
+/// connected at the time. A client's guard exists before any message can be
+/// addressed to it, so its encoding is never missing.
+#[derive(Debug)]
+pub struct Encoded {
+    json: String,
+    msgpack: Option<Vec<u8>>,
+    cbor: Option<Vec<u8>>,
+}
+
+impl Encoded {
+    pub fn new<T: Serialize + ?Sized>(msg: &T) -> Result<Self, EncodeError> {
+        let msgpack = if Encoding::MessagePack.is_used() {
+            let mut out = Vec::new();
+            let mut ser = rmp_serde::Serializer::new(&mut out)
+                .with_struct_map()
+                .with_human_readable();
+            msg.serialize(&mut ser)?;
+            Some(out)
+        } else {
+            None
+        };
+        let cbor = if Encoding::Cbor.is_used() {
+            let mut out = Vec::new();
+            ciborium::ser::into_writer(msg, &mut out)?;
+            Some(out)
+        } else {
+            None
+        };
+        Ok(Self {
+            json: serde_json::to_string(msg)?,
+            msgpack,
+            cbor,
+        })
+    }
+
+    /// Wraps the messages in `{"type":"batch","messages":[...]}`, reusing
+    /// their serialized forms.
+    #[must_use]
+    pub fn batch(messages: &[&Self]) -> Self {
+        let json = crate::server::protocol::batch(messages.iter().map(|msg| msg.json.as_str()));
+        let msgpack = messages
+            .iter()
+            .map(|msg| msg.msgpack.as_deref())
+            .collect::<Option<Vec<_>>>()
+            .map(|parts| {
+                let mut out = Vec::new();
+                // Writing to a `Vec` cannot fail
+                let _ = rmp::encode::write_map_len(&mut out, 2);
+                let _ = rmp::encode::write_str(&mut out, "type");
+                let _ = rmp::encode::write_str(&mut out, "batch");
+                let _ = rmp::encode::write_str(&mut out, "messages");
+                let _ = rmp::encode::write_array_len(&mut out, parts.len() as u32);
+                for part in parts {
+                    out.extend_from_slice(part);
+                }
+                out
+            });
+        let cbor = messages
+            .iter()
+            .map(|msg| msg.cbor.as_deref())
+            .collect::<Option<Vec<_>>>()
+            .map(|parts| {
+                let mut out = Vec::new();
+                let mut enc = ciborium_ll::Encoder::from(&mut out);
+                let _ = enc.push(ciborium_ll::Header::Map(Some(2)));
+                let _ = enc.text("type", None);
+                let _ = enc.text("batch", None);
+                let _ = enc.text("messages", None);
+                let _ = enc.push(ciborium_ll::Header::Array(Some(parts.len())));
+                for part in parts {
+                    out.extend_from_slice(part);
+                }
+                out
+            });
+        Self {
+            json,
+            msgpack,
+            cbor,
+        }
+    }
+
+    #[must_use]
+    pub fn json(&self) -> &str {
+        &self.json
+    }
+
+    /// Returns the message in a binary encoding, `None` for JSON or if no
+    /// client used the encoding when the message was serialized.
+    #[must_use]
+    pub fn binary(&self, encoding: Encoding) -> Option<&[u8]> {
+        match encoding {
+            Encoding::Json => None,
+            Encoding::MessagePack => self.msgpack.as_deref(),
+            Encoding::Cbor => self.cbor.as_deref(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{json, Value};
+
+    use super::*;
+    use crate::server::protocol::{IncomingMessage, OutgoingMessage};
+
+    fn encode(encoding: Encoding, msg: &impl Serialize) -> Vec<u8> {
+        let _guard = encoding.track();
+        Encoded::new(msg)
+            .unwrap()
+            .binary(encoding)
+            .unwrap()
+            .to_vec()
+    }
+
+    #[test]
+    fn round_trip() {
+        let msg = OutgoingMessage::Pong {
+            sent: 1.5,
+            received: String::from("2024-01-01T00:00:00.000Z"),
+        };
+        let expected = serde_json::to_value(&msg).unwrap();
+        for encoding in [Encoding::MessagePack, Encoding::Cbor] {
+            let decoded: Value = encoding.decode(&encode(encoding, &msg)).unwrap();
+            assert_eq!(decoded, expected, "{encoding:?}");
+        }
+    }
+
+    #[test]
+    fn decodes_known_bytes() {
+        // {"type":"lobbyReady","ready":true}
+        let msgpack = [
+            [0x82, 0xa4].as_slice(),
+            b"type",
+            &[0xaa],
+            b"lobbyReady",
+            &[0xa5],
+            b"ready",
+            &[0xc3],
+        ]
+        .concat();
+        let cbor = [
+            [0xa2, 0x64].as_slice(),
+            b"type",
+            &[0x6a],
+            b"lobbyReady",
+            &[0x65],
+            b"ready",
+            &[0xf5],
+        ]
+        .concat();
+        let expected = json!({"type": "lobbyReady", "ready": true});
+        for (encoding, bytes) in [(Encoding::MessagePack, &msgpack), (Encoding::Cbor, &cbor)] {
+            let msg: IncomingMessage = encoding.decode(bytes).unwrap();
+            assert_eq!(serde_json::to_value(&msg).unwrap(), expected);
+            assert_eq!(&encode(encoding, &msg), bytes);
+        }
+
+        // 1.5 as a CBOR half precision float
+        let decoded: Value = Encoding::Cbor.decode(&[0xf9, 0x3e, 0x00]).unwrap();
+        assert_eq!(decoded, json!(1.5));
+    }
+
+    #[test]
+    fn batches_encoded_messages() {
+        let messages = [json!({"type": "a"}), json!({"type": "b", "n": 1})];
+        let expected = json!({"type": "batch", "messages": messages});
+        for encoding in [Encoding::MessagePack, Encoding::Cbor] {
+            let _guard = encoding.track();
+            let encoded: Vec<_> = messages.iter().map(|m| Encoded::new(m).unwrap()).collect();
+            let batch = Encoded::batch(&encoded.iter().collect::<Vec<_>>());
+            let decoded: Value = encoding.decode(batch.binary(encoding).unwrap()).unwrap();
+            assert_eq!(decoded, expected, "{encoding:?}");
+            assert_eq!(
+                serde_json::from_str::<Value>(batch.json()).unwrap(),
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_malformed_messages() {
+        let decode = |encoding: Encoding, bytes: &[u8]| encoding.decode::<Value>(bytes);
+
+        assert!(decode(Encoding::MessagePack, &[0x92, 0x01]).is_err());
+        assert!(matches!(
+            decode(Encoding::MessagePack, &[0x01, 0x02]),
+            Err(DecodeError::TrailingBytes)
+        ));
+        assert!(decode(Encoding::MessagePack, &[0xdd, 0xff, 0xff, 0xff, 0xff]).is_err());
+        assert!(decode(Encoding::Cbor, &[0x62, 0xff, 0xfe]).is_err());
+        assert!(decode(Encoding::Cbor, &[0x98, 0xff]).is_err());
+        assert!(matches!(
+            decode(Encoding::Cbor, &[0x01, 0x02]),
+            Err(DecodeError::TrailingBytes)
+        ));
+
+        assert!(decode(Encoding::MessagePack, &[0x91; MAX_DEPTH + 2]).is_err());
+        assert!(decode(Encoding::Cbor, &[0x81; MAX_DEPTH + 2]).is_err());
+
+        // Well formed, but not an incoming message
+        assert!(Encoding::MessagePack
+            .decode::<IncomingMessage>(&[0x81, 0xa4, b't', b'y', b'p', b'e', 0x01])
+            .is_err());
+    }
+}
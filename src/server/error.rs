@@ -0,0 +1,78 @@
+//! Errors shared by the server and its users. Errors caused by a client are
+//! reported back to it with `OutgoingMessage::Error`.
+
+use std::io;
+
+use openssl::error::ErrorStack;
+
+use crate::server::acme::AcmeError;
+use crate::server::encoding::DecodeError;
+use crate::server::protocol::ErrorReason;
+
+/// Reason why the server could not start, or stopped unexpectedly.
+#[derive(Debug, thiserror::Error)]
+pub enum ServerError {
+    #[error("io error: {0}")]
+    IO(#[from] io::Error),
+    #[error(transparent)]
+    OpenSsl(#[from] ErrorStack),
+    #[error("acme error: {0}")]
+    Acme(#[from] AcmeError),
+    /// `auth.required` is set, but no secret to verify tokens with.
+    #[error("auth.required needs auth.secret")]
+    AuthSecretMissing,
+}
+
+/// Reason why a message sent by a client was not processed.
+#[derive(Debug, thiserror::Error)]
+pub enum ProtocolError {
+    #[error("invalid message: {0}")]
+    InvalidMessage(#[from] serde_json::Error),
+    #[error("failed to decode message: {0}")]
+    Decode(#[from] DecodeError),
+    /// The message belongs to a lobby or game the client is not in, e.g. a
+    /// move sent from a lobby.
+    #[error("{0} cannot be handled right now")]
+    NoController(&'static str),
+}
+
+impl ProtocolError {
+    /// Returns the reason reported to the client.
+    #[must_use]
+    pub const fn reason(&self) -> ErrorReason {
+        match self {
+            Self::InvalidMessage(_) => ErrorReason::InvalidMessage,
+            Self::Decode(_) => ErrorReason::DecodeFailed,
+            Self::NoController(_) => ErrorReason::NoController,
+        }
+    }
+}
+
+/// Reason why the QR code of an invite could not be generated.
+#[derive(Debug, thiserror::Error)]
+pub enum QrError {
+    #[error("contents do not fit a QR code: {0}")]
+    Encode(#[from] qrcode::types::QrError),
+    #[error("failed to encode image: {0}")]
+    Image(#[from] image::ImageError),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::protocol::IncomingMessage;
+
+    #[test]
+    fn protocol_errors_map_to_reasons() {
+        let Err(err) = serde_json::from_str::<IncomingMessage>("{\"type\":\"nope\"}") else {
+            panic!("unknown message was parsed");
+        };
+        let err = ProtocolError::from(err);
+        assert_eq!(err.reason(), ErrorReason::InvalidMessage);
+        assert!(err.to_string().starts_with("invalid message: "));
+
+        let err = ProtocolError::NoController("gameEndTurn");
+        assert_eq!(err.reason(), ErrorReason::NoController);
+        assert_eq!(err.to_string(), "gameEndTurn cannot be handled right now");
+    }
+}
@@ -0,0 +1,151 @@
+//! Checks the extra time which the host claims the players of a local game
+//! saved up, before the game continues on the server.
+//!
+//! A player gains at most `time_per_turn` in every turn, or `increment` with
+//! `TimerMode::Fischer`, and can never have more than `time_cap` left, so the
+//! extra time of a player is bounded by the turns they played. Time which the
+//! host says the local game has been played for was taken out of both
+//! players' turns and lowers the bound further.
+
+use std::time::Duration;
+
+use crate::game::{Game, Player};
+use crate::game_config::TimerMode;
+use crate::server::timer::TIME_PER_TURN_MIN;
+use crate::server::GameConfig;
+
+/// Extra time of a local game after checking it.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct CheckedExtraTime {
+    /// Extra time of player 1 and player 2, lowered to what they could have
+    /// saved up.
+    pub extra_time: [Duration; 2],
+    /// Whether the host claimed more than that, which is worth logging.
+    pub clamped: bool,
+}
+
+/// Lowers the extra time of each player to the most they could have saved
+/// up in the game, which has been played for `elapsed`. Games without a
+/// turn timer, or with one which does not carry time over, have no extra
+/// time.
+#[must_use]
+pub fn check(
+    extra_time: [Duration; 2],
+    game: &Game,
+    elapsed: Duration,
+    config: &GameConfig,
+) -> CheckedExtraTime {
+    let GameConfig {
+        time_per_turn,
+        time_cap,
+        increment,
+        timer_mode,
+        ..
+    } = *config;
+    // Time players start with and gain in every turn
+    let (start, gain) = match timer_mode {
+        _ if time_per_turn < TIME_PER_TURN_MIN => (Duration::ZERO, Duration::ZERO),
+        TimerMode::CarryOver => (Duration::ZERO, time_per_turn),
+        TimerMode::Fischer => (time_per_turn, increment),
+        TimerMode::PerMove | TimerMode::ByoYomi => (Duration::ZERO, Duration::ZERO),
+    };
+    if gain.is_zero() && start.is_zero() {
+        return CheckedExtraTime {
+            extra_time: [Duration::ZERO; 2],
+            clamped: false,
+        };
+    }
+
+    let state = game.state();
+    let starting = game.rules().starting_player;
+    let turns_played = |p: Player| {
+        if p == starting {
+            state.turn.div_ceil(2)
+        } else {
+            state.turn / 2
+        }
+    };
+    let budget = (start * 2 + gain * state.turn).saturating_sub(elapsed);
+    let time_cap = time_cap.max(time_per_turn);
+
+    let limits = [Player::P1, Player::P2]
+        .map(|p| (start + gain * turns_played(p)).min(time_cap).min(budget));
+    let checked = [0, 1].map(|i| extra_time[i].min(limits[i]));
+    CheckedExtraTime {
+        extra_time: checked,
+        clamped: checked != extra_time,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::GameRules;
+
+    fn config() -> GameConfig {
+        GameConfig {
+            time_per_turn: Duration::from_secs(10),
+            time_cap: Duration::from_secs(25),
+            ..GameConfig::default()
+        }
+    }
+
+    /// Plays `turns` moves.
+    fn game(turns: usize) -> Game {
+        let mut game = Game::new(GameRules::default());
+        for i in 0..turns {
+            game.end_turn(Some(i % 7)).unwrap();
+        }
+        game
+    }
+
+    #[test]
+    fn keeps_plausible_extra_time() {
+        let extra_time = [Duration::from_secs(8), Duration::from_secs(4)];
+        let checked = check(extra_time, &game(4), Duration::from_secs(9), &config());
+        assert_eq!(checked.extra_time, extra_time);
+        assert!(!checked.clamped);
+
+        let game = game(2);
+        let checked = check(extra_time, &game, Duration::ZERO, &GameConfig::default());
+        assert_eq!(checked.extra_time, [Duration::ZERO; 2]);
+        assert!(!checked.clamped);
+
+        let per_move = GameConfig {
+            timer_mode: TimerMode::PerMove,
+            ..config()
+        };
+        assert_eq!(
+            check(extra_time, &game, Duration::ZERO, &per_move).extra_time,
+            [Duration::ZERO; 2]
+        );
+    }
+
+    #[test]
+    fn clamps_to_turns_played_and_time_cap() {
+        let secs = Duration::from_secs;
+        let checked = check([secs(60), secs(60)], &game(3), secs(0), &config());
+        assert_eq!(checked.extra_time, [secs(20), secs(10)]);
+        assert!(checked.clamped);
+
+        let checked = check([secs(60), secs(0)], &game(8), secs(0), &config());
+        assert_eq!(checked.extra_time, [secs(25), secs(0)]);
+
+        let fischer = GameConfig {
+            timer_mode: TimerMode::Fischer,
+            increment: secs(2),
+            ..config()
+        };
+        let checked = check([secs(60), secs(60)], &game(3), secs(0), &fischer);
+        assert_eq!(checked.extra_time, [secs(14), secs(12)]);
+    }
+
+    #[test]
+    fn clamps_to_time_left_after_elapsed_time() {
+        let secs = Duration::from_secs;
+        // 40 seconds were given out over 4 turns, 36 of which were spent
+        let checked = check([secs(20), secs(20)], &game(4), secs(36), &config());
+        assert_eq!(checked.extra_time, [secs(4), secs(4)]);
+        assert!(checked.clamped);
+    }
+}
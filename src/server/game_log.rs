@@ -0,0 +1,169 @@
+//! Summaries of finished rounds, one JSON object per line, written to
+//! stdout or to a file which is rotated once it grows too large. Meant for
+//! simple analytics, and kept apart from the log of the server.
+
+use std::{
+    ffi::OsString,
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    sync::{Mutex, PoisonError},
+    time::Duration,
+};
+
+use serde::Serialize;
+
+use crate::duration::as_millis;
+use crate::game::GameWinner;
+use crate::server::config::GameLogConfig;
+use crate::server::GameConfig;
+
+/// A single line of the game log.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RoundSummary {
+    /// ISO 8601 timestamp of when the round ended.
+    pub finished: String,
+    /// ID of the lobby the game was started from, `None` for bot games.
+    pub lobby: Option<String>,
+    pub round: u32,
+    pub winner: GameWinner,
+    /// Turns played, including skipped ones.
+    pub moves: u32,
+    /// Time from the first turn to the result.
+    #[serde(with = "as_millis")]
+    pub duration: Duration,
+    pub config: GameConfig,
+}
+
+enum Output {
+    Stdout,
+    File {
+        path: PathBuf,
+        file: File,
+        /// Bytes written to the current file.
+        size: u64,
+        max_size: u64,
+        max_files: u32,
+    },
+}
+
+pub struct GameLog {
+    output: Mutex<Output>,
+}
+
+impl GameLog {
+    /// Opens the file in the config, or stdout if there is none. Returns
+    /// `None` if the game log is disabled.
+    pub fn open(cfg: &GameLogConfig) -> io::Result<Option<Self>> {
+        if !cfg.enabled {
+            return Ok(None);
+        }
+        let output = match &cfg.file {
+            Some(path) => {
+                let file = OpenOptions::new().create(true).append(true).open(path)?;
+                Output::File {
+                    path: path.clone(),
+                    size: file.metadata()?.len(),
+                    file,
+                    max_size: cfg.max_file_size,
+                    max_files: cfg.max_files,
+                }
+            }
+            None => Output::Stdout,
+        };
+        Ok(Some(Self {
+            output: Mutex::new(output),
+        }))
+    }
+
+    /// Appends the summary as a single line, rotating the file first if the
+    /// line would not fit.
+    pub fn write(&self, summary: &RoundSummary) -> io::Result<()> {
+        let mut line = serde_json::to_vec(summary)?;
+        line.push(b'\n');
+        let mut output = self.output.lock().unwrap_or_else(PoisonError::into_inner);
+        match &mut *output {
+            Output::Stdout => io::stdout().lock().write_all(&line),
+            Output::File {
+                path,
+                file,
+                size,
+                max_size,
+                max_files,
+            } => {
+                let len = line.len() as u64;
+                if *max_size > 0 && *size > 0 && *size + len > *max_size {
+                    rotate(path, *max_files)?;
+                    *file = File::create(&*path)?;
+                    *size = 0;
+                }
+                file.write_all(&line)?;
+                *size += len;
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Renames the file to `<file>.1`, shifting older files up to
+/// `<file>.<max_files>` and removing the oldest.
+fn rotate(path: &Path, max_files: u32) -> io::Result<()> {
+    if max_files == 0 {
+        return Ok(());
+    }
+    for i in (1..max_files).rev() {
+        let from = numbered(path, i);
+        if from.exists() {
+            fs::rename(from, numbered(path, i + 1))?;
+        }
+    }
+    fs::rename(path, numbered(path, 1))
+}
+
+fn numbered(path: &Path, n: u32) -> PathBuf {
+    let mut name = OsString::from(path.as_os_str());
+    name.push(format!(".{n}"));
+    name.into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rotates_full_files() {
+        let dir = std::env::temp_dir().join(format!("c4-game-log-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("games.jsonl");
+        let cfg = GameLogConfig {
+            enabled: true,
+            file: Some(path.clone()),
+            max_file_size: 300,
+            max_files: 2,
+        };
+        let log = GameLog::open(&cfg).unwrap().unwrap();
+        let summary = RoundSummary {
+            finished: String::from("2024-01-01T00:00:00.000Z"),
+            lobby: None,
+            round: 0,
+            winner: GameWinner::P1,
+            moves: 7,
+            duration: Duration::from_secs(30),
+            config: GameConfig::default(),
+        };
+        for _ in 0..4 {
+            log.write(&summary).unwrap();
+        }
+
+        let lines = fs::read_to_string(&path).unwrap();
+        let line: serde_json::Value = serde_json::from_str(lines.lines().next().unwrap()).unwrap();
+        assert_eq!(line["moves"], 7);
+        assert_eq!(line["duration"], 30_000.0);
+        assert!(numbered(&path, 1).exists());
+        assert!(numbered(&path, 2).exists());
+        assert!(!numbered(&path, 3).exists());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
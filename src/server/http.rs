@@ -0,0 +1,957 @@
+//! HTTP routes of the server. `build_app` is used by the `server` binary
+//! for every worker, and can be served on any listener, e.g. a random port
+//! in integration tests. `configure` mounts the same endpoints inside
+//! another actix app.
+
+use std::{
+    convert::Infallible,
+    net::{IpAddr, SocketAddr},
+    path::Path,
+    str::FromStr,
+    sync::{Arc, OnceLock},
+    time::Duration,
+};
+
+use actix::{Actor, Addr, MailboxError};
+use actix_cors::Cors;
+use actix_files::{Files, NamedFile};
+use actix_web::body::MessageBody;
+use actix_web::dev::{fn_service, ServerHandle, ServiceFactory, ServiceRequest, ServiceResponse};
+use actix_web::http::header::{self, HeaderValue};
+use actix_web::{rt, web, App, HttpRequest, HttpResponse, HttpResponseBuilder};
+use actix_web_actors::ws::WsResponseBuilder;
+use chrono::Utc;
+use futures_util::stream;
+use qstring::QString;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::time;
+use tracing::{debug, error};
+use uuid::Uuid;
+
+use web::Data;
+
+use crate::bot::Difficulty;
+use crate::server::acme::Challenges;
+use crate::server::archive::{DirectoryArchive, GameArchive};
+use crate::server::auth;
+use crate::server::ban::{BanList, BanTarget};
+use crate::server::connection_limit::ConnectionLimiter;
+use crate::server::encoding::Encoding;
+use crate::server::error::ServerError;
+use crate::server::game_log::GameLog;
+use crate::server::load::Load;
+use crate::server::locale::Locale;
+use crate::server::protocol::{Disconnect, ProtocolVersion, QrFormat, Subprotocol};
+use crate::server::rating::{self, FileRatingStore, MemoryRatingStore, RatingStore};
+use crate::server::recording::{SessionRecorder, REDACTED};
+use crate::server::reload::{ConfigLoader, LiveConfig, ReloadError};
+use crate::server::webhook::Webhooks;
+use crate::server::{actor, token, AppConfig};
+use actor::game::{
+    EventSubscription, GameEvent, GetGameInfo, GetSnapshot, GetStats, SubscribeEvents,
+};
+use actor::lobby::{GetLobbyInfo, PASSWORD_MAX_LENGTH};
+use actor::lobby_router::{
+    Close, CloseAll, CreateBotGame, CreateLobby, GetGame, JoinLobby, ListGames, ListLobbies,
+    ListPublicLobbies, Matchmake, Reconnect, ResolveJoinCode,
+};
+use actor::supervisor;
+
+/// Comma separated list of protocol versions supported by the client.
+const URL_VERSION_PARAMETER: &str = "version";
+/// Join code of the lobby, used instead of the lobby parameter.
+const URL_JOIN_CODE_PARAMETER: &str = "code";
+/// Reconnect token issued by the game the player wants to resume. Must be
+/// used together with the lobby parameter.
+const URL_RECONNECT_PARAMETER: &str = "reconnect";
+/// Starts a game against the bot with the given difficulty.
+const URL_AI_PARAMETER: &str = "ai";
+/// Set to `1` to be paired with another player waiting for a game, see
+/// `actor::Matchmaker`.
+const URL_MATCHMAKE_PARAMETER: &str = "matchmake";
+/// Set to `deflate` if the client can decompress long messages.
+const URL_COMPRESS_PARAMETER: &str = "compress";
+/// Set to `1` to receive the messages caused by a single event in one
+/// `batch` message.
+const URL_BATCH_PARAMETER: &str = "batch";
+/// Set to `svg` to get QR codes of invites as SVG images instead of PNG.
+const URL_QR_PARAMETER: &str = "qr";
+/// Persistent ID chosen by the client, used to keep track of its rating.
+const URL_PLAYER_ID_PARAMETER: &str = "playerId";
+/// Password needed to join the lobby, or required from others when creating
+/// one.
+const URL_PASSWORD_PARAMETER: &str = "password";
+/// Encoding of the messages, `json` (the default), `msgpack` or `cbor`.
+const URL_ENCODING_PARAMETER: &str = "encoding";
+/// Token issued by the account system, see `server::auth`.
+const URL_AUTH_PARAMETER: &str = "auth";
+/// Language tag of the descriptions sent to the client, see `server::locale`.
+const URL_LOCALE_PARAMETER: &str = "lang";
+/// Viewer token of the game, for clients which cannot set the
+/// `Authorization` header.
+const URL_VIEWER_TOKEN_PARAMETER: &str = "token";
+/// How often a comment is sent on idle event streams, so that proxies do
+/// not close them.
+const SSE_KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(15);
+
+/// Everything the routes share, cloned into every worker.
+#[derive(Clone)]
+pub struct AppState {
+    pub cfg: Data<AppConfig>,
+    pub router: Addr<actor::LobbyRouter>,
+    pub ratings: Data<Option<Arc<dyn RatingStore>>>,
+    pub limiter: Data<Arc<ConnectionLimiter>>,
+    pub bans: Data<Arc<BanList>>,
+    /// HTTP-01 challenges of the ACME server, answered by the app.
+    pub challenges: Data<Arc<Challenges>>,
+    /// Set once the server runs, so that the admin API can stop it.
+    pub server_handle: Data<OnceLock<ServerHandle>>,
+    /// Settings which can be reloaded while the server runs.
+    pub live_config: Data<Arc<LiveConfig>>,
+}
+
+impl AppState {
+    /// Opens the archive, the ratings and the game log, and starts the webhooks and the
+    /// lobby router. Must be called from within an actix system.
+    pub fn new(cfg: AppConfig) -> Result<Self, ServerError> {
+        if cfg.auth.required && cfg.auth.secret.is_none() {
+            return Err(ServerError::AuthSecretMissing);
+        }
+        let cfg = Data::new(cfg);
+        let live_config = Arc::new(LiveConfig::new(Data::clone(&cfg).into_inner()));
+        let archive = match &cfg.archive.directory {
+            Some(dir) => {
+                let archive = DirectoryArchive::new(dir)?;
+                Some(Arc::new(archive) as Arc<dyn GameArchive>)
+            }
+            None => None,
+        };
+        let ratings: Option<Arc<dyn RatingStore>> = match &cfg.ratings.file {
+            _ if !cfg.ratings.enabled => None,
+            Some(file) => Some(Arc::new(FileRatingStore::open(file)?)),
+            None => Some(Arc::new(MemoryRatingStore::default())),
+        };
+        let router = actor::LobbyRouter::new(Data::clone(&cfg).into_inner())
+            .with_archive(archive)
+            .with_ratings(ratings.clone())
+            .with_webhooks(Webhooks::start(&cfg.webhooks).map(Arc::new))
+            .with_game_log(GameLog::open(&cfg.game_log)?.map(Arc::new))
+            .with_config_updates(live_config.subscribe())
+            .start();
+        let limiter = Arc::new(ConnectionLimiter::new(&cfg.connection_limits));
+        let bans = match &cfg.bans_file {
+            Some(file) => BanList::open(file)?,
+            None => BanList::default(),
+        };
+        Ok(Self {
+            cfg,
+            router,
+            ratings: Data::new(ratings),
+            limiter: Data::new(limiter),
+            bans: Data::new(Arc::new(bans)),
+            challenges: Data::new(Arc::new(Challenges::default())),
+            server_handle: Data::new(OnceLock::new()),
+            live_config: Data::new(live_config),
+        })
+    }
+
+    /// Reads the settings again with the loader on `POST /admin/reload`.
+    #[must_use]
+    pub fn with_config_loader(self, loader: ConfigLoader) -> Self {
+        self.live_config.set_loader(loader);
+        self
+    }
+}
+
+/// Builds the app serving the WebSocket endpoint and every HTTP route.
+pub fn build_app(
+    state: &AppState,
+) -> App<
+    impl ServiceFactory<
+        ServiceRequest,
+        Config = (),
+        Response = ServiceResponse<impl MessageBody>,
+        Error = actix_web::Error,
+        InitError = (),
+    >,
+> {
+    let cfg = Data::clone(&state.cfg);
+    App::new()
+        .wrap(cors(Data::clone(&cfg)))
+        .app_data(Data::clone(&state.server_handle))
+        .app_data(Data::clone(&state.ratings))
+        .app_data(Data::clone(&state.limiter))
+        .app_data(Data::clone(&state.bans))
+        .app_data(Data::clone(&state.challenges))
+        .app_data(Data::clone(&state.live_config))
+        .configure(|app| configure(app, cfg.clone().into_inner(), state.router.clone()))
+        .route(
+            "/.well-known/acme-challenge/{token}",
+            web::get().to(acme_challenge_route),
+        )
+        .configure(|app| match &cfg.serve_from {
+            Some(dir) => {
+                app.service(static_files(dir));
+            }
+            // Older clients connect to `/`
+            None => {
+                app.route("/", web::get().to(ws_route));
+            }
+        })
+        .default_service(web::get().to(not_found))
+}
+
+/// Mounts the WebSocket endpoint at `/ws` and the HTTP API of the lobbies
+/// and games, so that they can be served next to other routes of an actix
+/// app.
+///
+/// Connections are only limited per address, ratings only served, bans only
+/// checked, the server only stopped and the settings only reloaded through
+/// the admin API if the app also has the `Arc<ConnectionLimiter>`,
+/// `Option<Arc<dyn RatingStore>>`, `Arc<BanList>`, `OnceLock<ServerHandle>`
+/// and `Arc<LiveConfig>` data used by `build_app`.
+pub fn configure(
+    cfg: &mut web::ServiceConfig,
+    app_config: Arc<AppConfig>,
+    router: Addr<actor::LobbyRouter>,
+) {
+    cfg.app_data(Data::from(app_config))
+        .app_data(Data::new(router))
+        .route("/ws", web::get().to(ws_route))
+        .route("/lobbies", web::get().to(lobbies_route))
+        .route("/health", web::get().to(health_route))
+        .route("/lobby/{id}/stats", web::get().to(stats_route))
+        .route("/game/{id}/snapshot", web::get().to(snapshot_route))
+        .route("/game/{id}/events", web::get().to(events_route))
+        .route("/ratings/{id}", web::get().to(ratings_route))
+        .service(
+            web::scope("/admin")
+                .route("/lobbies", web::get().to(admin_lobbies_route))
+                .route("/games", web::get().to(admin_games_route))
+                .route("/metrics", web::get().to(admin_metrics_route))
+                .route("/disconnect/{id}", web::post().to(admin_disconnect_route))
+                .route("/bans", web::get().to(admin_bans_route))
+                .route("/bans", web::post().to(admin_ban_route))
+                .route("/bans", web::delete().to(admin_unban_route))
+                .route("/reload", web::post().to(admin_reload_route))
+                .route("/shutdown", web::post().to(admin_shutdown_route)),
+        );
+}
+
+/// Adds CORS headers to responses for requests from allowed origins.
+fn cors(cfg: Data<AppConfig>) -> Cors {
+    Cors::default()
+        .allowed_origin_fn(move |origin, _| {
+            origin
+                .to_str()
+                .is_ok_and(|origin| cfg.is_origin_allowed(origin))
+        })
+        .allowed_methods(["GET", "POST", "DELETE"])
+        .allowed_header(header::AUTHORIZATION)
+        .max_age(3600)
+}
+
+/// Serves the web client from `dir`. Paths which do not match any file get
+/// `index.html`, so that the client can handle its own routes.
+fn static_files(dir: &Path) -> Files {
+    let index = dir.join("index.html");
+    Files::new("/", dir)
+        .index_file("index.html")
+        .default_handler(fn_service(move |req: ServiceRequest| {
+            let index = index.clone();
+            async move {
+                let (req, _) = req.into_parts();
+                let file = NamedFile::open_async(index).await?;
+                let res = file.into_response(&req);
+                Ok(ServiceResponse::new(req, res))
+            }
+        }))
+}
+
+/// Answers the HTTP-01 challenges of the ACME server.
+async fn acme_challenge_route(
+    token: web::Path<String>,
+    challenges: Data<Arc<Challenges>>,
+) -> HttpResponse {
+    match challenges.get(&token) {
+        Some(key_authorization) => HttpResponse::Ok()
+            .content_type("application/octet-stream")
+            .body(key_authorization),
+        None => HttpResponse::NotFound().finish(),
+    }
+}
+
+async fn not_found() -> HttpResponse {
+    HttpResponse::NotFound().body("404 Not Found")
+}
+
+/// Returns an error response if the request was sent from an origin which is
+/// not allowed.
+fn check_origin(req: &HttpRequest, cfg: &AppConfig) -> Option<HttpResponse> {
+    // Browsers always send the origin, other clients are free to connect
+    let origin = req.headers().get(header::ORIGIN)?;
+    let origin = origin.to_str().unwrap_or_default();
+    if cfg.is_origin_allowed(origin) {
+        return None;
+    }
+
+    debug!("Rejected connection from origin {origin}");
+    let body = format!("Origin {origin} is not allowed to connect to this server");
+    Some(HttpResponse::Forbidden().body(body))
+}
+
+/// Returns the IP address of the client, taken from the proxy headers if
+/// they are trusted.
+fn client_ip(req: &HttpRequest, cfg: &AppConfig) -> Option<IpAddr> {
+    if !cfg.connection_limits.trust_forwarded_for {
+        return req.peer_addr().map(|addr| addr.ip());
+    }
+
+    let info = req.connection_info();
+    let addr = info.realip_remote_addr()?;
+    addr.parse::<SocketAddr>()
+        .map(|addr| addr.ip())
+        .or_else(|_| addr.parse::<IpAddr>())
+        .ok()
+}
+
+#[allow(clippy::too_many_lines)]
+async fn ws_route(
+    req: HttpRequest,
+    stream: web::Payload,
+    cfg: Data<AppConfig>,
+    router: Data<Addr<actor::LobbyRouter>>,
+    limiter: Option<Data<Arc<ConnectionLimiter>>>,
+    bans: Option<Data<Arc<BanList>>>,
+    live_config: Option<Data<Arc<LiveConfig>>>,
+) -> Result<HttpResponse, actix_web::Error> {
+    if let Some(res) = check_origin(&req, &cfg) {
+        return Ok(res);
+    }
+
+    let qs = QString::from(req.query_string());
+    // The query parameters are only used if no subprotocol is supported
+    let offered = req
+        .headers()
+        .get_all(header::SEC_WEBSOCKET_PROTOCOL)
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','));
+    let subprotocol = Subprotocol::negotiate(offered);
+    let Some(protocol) = subprotocol.map(|s| s.version).or_else(|| {
+        qs.get(URL_VERSION_PARAMETER)
+            .and_then(ProtocolVersion::negotiate)
+    }) else {
+        return Ok(HttpResponse::BadRequest().finish());
+    };
+    let Ok(difficulty) = qs
+        .get(URL_AI_PARAMETER)
+        .map(Difficulty::from_str)
+        .transpose()
+    else {
+        return Ok(HttpResponse::BadRequest().finish());
+    };
+
+    let matchmake = qs.get(URL_MATCHMAKE_PARAMETER) == Some("1");
+    if matchmake && !cfg.matchmaking.enabled {
+        return Ok(HttpResponse::BadRequest().finish());
+    }
+
+    let encoding = match subprotocol {
+        Some(subprotocol) => Ok(subprotocol.encoding),
+        None => qs
+            .get(URL_ENCODING_PARAMETER)
+            .map_or(Ok(Encoding::Json), Encoding::from_str),
+    };
+    let Ok(encoding) = encoding else {
+        return Ok(HttpResponse::BadRequest().finish());
+    };
+
+    let player_id = qs.get(URL_PLAYER_ID_PARAMETER);
+    if player_id.is_some_and(|id| !rating::is_valid_player_id(id)) {
+        return Ok(HttpResponse::BadRequest().finish());
+    }
+    let password = qs
+        .get(URL_PASSWORD_PARAMETER)
+        .filter(|p| !p.is_empty())
+        .map(String::from);
+    if password
+        .as_ref()
+        .is_some_and(|p| p.len() > PASSWORD_MAX_LENGTH)
+    {
+        return Ok(HttpResponse::BadRequest().finish());
+    }
+
+    let subject = match (qs.get(URL_AUTH_PARAMETER), &cfg.auth.secret) {
+        (Some(token), Some(secret)) => match auth::verify(token, secret, Utc::now().timestamp()) {
+            Ok(subject) => Some(subject),
+            Err(e) => {
+                debug!("Rejected connection: {e}");
+                return Ok(HttpResponse::Unauthorized().finish());
+            }
+        },
+        _ => None,
+    };
+    if cfg.auth.required && subject.is_none() {
+        debug!("Rejected connection: not authenticated");
+        return Ok(HttpResponse::Unauthorized().finish());
+    }
+    let ip = client_ip(&req, &cfg);
+    let bans = bans.as_deref();
+    if bans.is_some_and(|b| b.is_banned(ip, subject.as_deref())) {
+        debug!("Rejected connection: banned");
+        return Ok(HttpResponse::Forbidden().finish());
+    }
+
+    let reconnect_token = qs.get(URL_RECONNECT_PARAMETER);
+    // Players returning to a game are let in, or they would forfeit it
+    if reconnect_token.is_none() && Load::current().is_shedding(&cfg.load_shedding) {
+        debug!("Rejected connection: the server is overloaded");
+        return Ok(service_unavailable(&cfg).finish());
+    }
+    // `Some(None)` if the client asked for a lobby which does not exist
+    let lobby = match (
+        qs.get(&cfg.url_lobby_parameter),
+        qs.get(URL_JOIN_CODE_PARAMETER),
+    ) {
+        (Some(id), _) => Some(Uuid::from_str(id).ok()),
+        (None, Some(code)) => match router.send(ResolveJoinCode(String::from(code))).await {
+            Ok(id) => Some(id),
+            Err(_) => return Ok(service_unavailable(&cfg).finish()),
+        },
+        (None, None) => None,
+    };
+    let joined_lobby = lobby
+        .flatten()
+        .filter(|_| difficulty.is_none() && !matchmake && reconnect_token.is_none());
+    let limiter = limiter.as_deref();
+    if joined_lobby.is_some_and(|id| limiter.is_some_and(|l| !l.try_join(id))) {
+        debug!("Rejected connection: too many attempts to join the lobby");
+        return Ok(HttpResponse::TooManyRequests().finish());
+    }
+    let connection = limiter.zip(ip).map(|(limiter, ip)| limiter.try_connect(ip));
+    if matches!(connection, Some(None)) {
+        debug!("Rejected connection: too many connections from the same address");
+        return Ok(HttpResponse::TooManyRequests().finish());
+    }
+
+    let actor_cfg = match &live_config {
+        Some(live) => live.current(),
+        None => Data::clone(&cfg).into_inner(),
+    };
+    let compress = qs.get(URL_COMPRESS_PARAMETER) == Some("deflate");
+    let qr_format = match qs.get(URL_QR_PARAMETER) {
+        Some("svg") => QrFormat::Svg,
+        _ => QrFormat::Png,
+    };
+    let mut actor = actor::Player::new(actor_cfg)
+        .with_protocol(protocol)
+        .with_compression(compress)
+        .with_encoding(encoding)
+        .with_batching(qs.get(URL_BATCH_PARAMETER) == Some("1"))
+        .with_qr_format(qr_format)
+        .with_locale(qs.get(URL_LOCALE_PARAMETER).map(Locale::from_tag))
+        .with_persistent_id(player_id.map(String::from))
+        .with_subject(subject.clone())
+        .with_connection_guard(connection.flatten());
+    if let Some(live) = &live_config {
+        actor = actor.with_config_updates(live.subscribe());
+    }
+    let recording_dir = cfg
+        .session_recording_dir
+        .as_ref()
+        .filter(|_| cfg.is_session_recorded(player_id, subject.as_deref()));
+    if let Some(dir) = recording_dir {
+        match SessionRecorder::create(dir, &redact_query(&qs, &cfg)) {
+            Ok(recorder) => actor = actor.with_recorder(recorder),
+            Err(e) => error!("Failed to create session recording: {e}"),
+        }
+    }
+    let rating_id = match &subject {
+        Some(subject) => Some(auth::rating_id(subject)),
+        None => player_id.map(String::from),
+    };
+    let (addr, mut res) = WsResponseBuilder::new(actor, &req, stream).start_with_addr()?;
+    // RFC 6455 requires the server to answer with the subprotocol it picked
+    if let Some(value) = subprotocol.and_then(|s| HeaderValue::from_str(&s.to_string()).ok()) {
+        res.headers_mut()
+            .insert(header::SEC_WEBSOCKET_PROTOCOL, value);
+    }
+    if let Some(bans) = bans {
+        bans.watch(ip, subject.clone(), addr.downgrade());
+    }
+
+    if let Some(difficulty) = difficulty {
+        let msg = CreateBotGame {
+            player: addr.clone(),
+            difficulty,
+        };
+        send_to_router(&router, &addr, msg, "start a game against the bot").await;
+    } else if matchmake {
+        let msg = Matchmake {
+            player: addr.clone(),
+            rating_id,
+        };
+        send_to_router(&router, &addr, msg, "queue player for matchmaking").await;
+    } else if let (Some(Some(id)), Some(token)) = (lobby, reconnect_token) {
+        let msg = Reconnect {
+            id,
+            token: String::from(token),
+            player: addr.clone(),
+        };
+        let action = format!("reconnect player to game {id}");
+        send_to_router(&router, &addr, msg, &action).await;
+    } else if reconnect_token.is_some() {
+        addr.do_send(Disconnect::ReconnectFailed);
+    } else if let Some(Some(id)) = lobby {
+        let msg = JoinLobby {
+            id,
+            player: addr.clone(),
+            password,
+            ip,
+            subject,
+        };
+        let action = format!("route player to lobby {id}");
+        send_to_router(&router, &addr, msg, &action).await;
+    } else if lobby.is_some() {
+        addr.do_send(Disconnect::InviteInvalid);
+    } else {
+        let msg = CreateLobby {
+            host: addr.clone(),
+            password,
+        };
+        send_to_router(&router, &addr, msg, "route player to a new lobby").await;
+    }
+
+    Ok(res)
+}
+
+/// Sends the message to the router, disconnecting the player if it could not
+/// be delivered.
+async fn send_to_router<M>(
+    router: &Addr<actor::LobbyRouter>,
+    player: &Addr<actor::Player>,
+    msg: M,
+    action: &str,
+) where
+    M: actix::Message<Result = ()> + Send + 'static,
+    actor::LobbyRouter: actix::Handler<M>,
+{
+    match router.send(msg).await {
+        Ok(()) => (),
+        Err(MailboxError::Closed) => player.do_send(Disconnect::ShuttingDown),
+        Err(MailboxError::Timeout) => {
+            debug!("Encountered an error while trying to {action}, connection will be terminated");
+            player.do_send(Disconnect::ServerOverloaded);
+        }
+    }
+}
+
+/// Starts a `503 Service Unavailable` response asking the client to retry
+/// after `LoadSheddingConfig::retry_after`.
+fn service_unavailable(cfg: &AppConfig) -> HttpResponseBuilder {
+    let retry_after = cfg.load_shedding.retry_after.as_secs().max(1);
+    let mut res = HttpResponse::ServiceUnavailable();
+    res.insert_header((header::RETRY_AFTER, retry_after.to_string()));
+    res
+}
+
+/// Reports how many players are connected and how many games are running,
+/// for load balancers. Answers `503 Service Unavailable` while new
+/// connections are refused.
+async fn health_route(cfg: Data<AppConfig>) -> HttpResponse {
+    let load = Load::current();
+    let shedding = load.is_shedding(&cfg.load_shedding);
+    let mut res = if shedding {
+        service_unavailable(&cfg)
+    } else {
+        HttpResponse::Ok()
+    };
+    res.json(serde_json::json!({
+        "status": if shedding { "overloaded" } else { "ok" },
+        "players": load.players,
+        "games": load.games,
+    }))
+}
+
+/// Lists lobbies which have been made public by their hosts.
+async fn lobbies_route(router: Data<Addr<actor::LobbyRouter>>) -> HttpResponse {
+    match router.send(ListPublicLobbies).await {
+        Ok(lobbies) => HttpResponse::Ok().json(lobbies),
+        Err(_) => HttpResponse::ServiceUnavailable().finish(),
+    }
+}
+
+/// Returns statistics of the game started from the lobby. The host token from
+/// `lobbyLink` must be passed in the `Authorization: Bearer` header.
+async fn stats_route(
+    req: HttpRequest,
+    id: web::Path<String>,
+    router: Data<Addr<actor::LobbyRouter>>,
+) -> HttpResponse {
+    let Some(token) = bearer_token(&req) else {
+        return HttpResponse::Unauthorized().finish();
+    };
+
+    let Ok(id) = Uuid::from_str(&id) else {
+        return not_found().await;
+    };
+    let game = match router.send(GetGame(id)).await {
+        Ok(Some(game)) => game,
+        Ok(None) => return not_found().await,
+        Err(_) => return HttpResponse::ServiceUnavailable().finish(),
+    };
+    let msg = GetStats {
+        token: String::from(token),
+    };
+    match game.send(msg).await {
+        Ok(Some(stats)) => HttpResponse::Ok().json(stats),
+        Ok(None) => HttpResponse::Forbidden().finish(),
+        Err(_) => not_found().await,
+    }
+}
+
+/// Returns the state of the game started from the lobby. The viewer token
+/// from `gameSetup` must be passed in the `Authorization: Bearer` header or
+/// the `token` query parameter.
+async fn snapshot_route(
+    req: HttpRequest,
+    id: web::Path<String>,
+    router: Data<Addr<actor::LobbyRouter>>,
+) -> HttpResponse {
+    let Some(token) = viewer_token(&req) else {
+        return HttpResponse::Unauthorized().finish();
+    };
+
+    let Ok(id) = Uuid::from_str(&id) else {
+        return not_found().await;
+    };
+    let game = match router.send(GetGame(id)).await {
+        Ok(Some(game)) => game,
+        Ok(None) => return not_found().await,
+        Err(_) => return HttpResponse::ServiceUnavailable().finish(),
+    };
+    let msg = GetSnapshot { token };
+    match game.send(msg).await {
+        Ok(Some(snapshot)) => HttpResponse::Ok().json(snapshot),
+        Ok(None) => HttpResponse::Forbidden().finish(),
+        Err(_) => not_found().await,
+    }
+}
+
+/// Streams the state of the game started from the lobby as server-sent
+/// events, for clients which cannot open a WebSocket. Every event is named
+/// after the message it contains, usually `gameSync`. Authenticated like
+/// `snapshot_route`.
+async fn events_route(
+    req: HttpRequest,
+    id: web::Path<String>,
+    router: Data<Addr<actor::LobbyRouter>>,
+) -> HttpResponse {
+    let Some(token) = viewer_token(&req) else {
+        return HttpResponse::Unauthorized().finish();
+    };
+
+    let Ok(id) = Uuid::from_str(&id) else {
+        return not_found().await;
+    };
+    let game = match router.send(GetGame(id)).await {
+        Ok(Some(game)) => game,
+        Ok(None) => return not_found().await,
+        Err(_) => return HttpResponse::ServiceUnavailable().finish(),
+    };
+    let EventSubscription { initial, events } = match game.send(SubscribeEvents { token }).await {
+        Ok(Some(subscription)) => subscription,
+        Ok(None) => return HttpResponse::Forbidden().finish(),
+        Err(_) => return not_found().await,
+    };
+
+    // Ends once the game stops and drops the sender
+    let stream = stream::unfold(
+        (Some(initial), events),
+        |(initial, mut events)| async move {
+            if let Some(event) = initial {
+                return Some((Ok::<_, Infallible>(sse_event(&event)), (None, events)));
+            }
+            loop {
+                let chunk = match time::timeout(SSE_KEEP_ALIVE_INTERVAL, events.recv()).await {
+                    Ok(Ok(event)) => sse_event(&event),
+                    // Every event contains the whole state, the next one will do
+                    Ok(Err(RecvError::Lagged(_))) => continue,
+                    Ok(Err(RecvError::Closed)) => return None,
+                    Err(_) => web::Bytes::from_static(b": keep-alive\n\n"),
+                };
+                return Some((Ok(chunk), (None, events)));
+            }
+        },
+    );
+    HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .insert_header((header::CACHE_CONTROL, "no-cache"))
+        .streaming(stream)
+}
+
+/// Formats the event as a server-sent event.
+fn sse_event(event: &GameEvent) -> web::Bytes {
+    format!("event: {}\ndata: {}\n\n", event.name, event.data).into()
+}
+
+/// Returns the viewer token from the `Authorization` header or the query.
+fn viewer_token(req: &HttpRequest) -> Option<String> {
+    if let Some(token) = bearer_token(req) {
+        return Some(String::from(token));
+    }
+    let qs = QString::from(req.query_string());
+    qs.get(URL_VIEWER_TOKEN_PARAMETER).map(String::from)
+}
+
+/// Returns the rating of the player with this ID.
+async fn ratings_route(
+    id: web::Path<String>,
+    ratings: Option<Data<Option<Arc<dyn RatingStore>>>>,
+) -> HttpResponse {
+    let Some(ratings) = ratings.and_then(|r| r.as_ref().clone()) else {
+        return not_found().await;
+    };
+    let res = web::block(move || ratings.get(&id)).await;
+    match res {
+        Ok(Some(rating)) => HttpResponse::Ok().json(rating),
+        Ok(None) => not_found().await,
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+/// Returns the bearer token from the `Authorization` header.
+fn bearer_token(req: &HttpRequest) -> Option<&str> {
+    req.headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+}
+
+/// Checks the admin token, returning the response to send if the request is
+/// not allowed. The admin API does not exist unless a token is configured.
+fn reject_admin(req: &HttpRequest, cfg: &AppConfig) -> Option<HttpResponse> {
+    let Some(expected) = &cfg.admin_token else {
+        return Some(HttpResponse::NotFound().body("404 Not Found"));
+    };
+    match bearer_token(req) {
+        None => Some(HttpResponse::Unauthorized().finish()),
+        Some(token) if !token::matches(expected, token) => Some(HttpResponse::Forbidden().finish()),
+        Some(_) => None,
+    }
+}
+
+/// Lists every lobby.
+async fn admin_lobbies_route(
+    req: HttpRequest,
+    cfg: Data<AppConfig>,
+    router: Data<Addr<actor::LobbyRouter>>,
+) -> HttpResponse {
+    if let Some(res) = reject_admin(&req, &cfg) {
+        return res;
+    }
+
+    let Ok(lobbies) = router.send(ListLobbies).await else {
+        return HttpResponse::ServiceUnavailable().finish();
+    };
+    let mut infos = Vec::with_capacity(lobbies.len());
+    for lobby in lobbies {
+        // Lobbies which shut down in the meantime are skipped
+        if let Ok(info) = lobby.send(GetLobbyInfo).await {
+            infos.push(info);
+        }
+    }
+    infos.sort_by(|a, b| a.created_at.cmp(&b.created_at));
+    HttpResponse::Ok().json(infos)
+}
+
+/// Lists every game started from a lobby.
+async fn admin_games_route(
+    req: HttpRequest,
+    cfg: Data<AppConfig>,
+    router: Data<Addr<actor::LobbyRouter>>,
+) -> HttpResponse {
+    if let Some(res) = reject_admin(&req, &cfg) {
+        return res;
+    }
+
+    let Ok(games) = router.send(ListGames).await else {
+        return HttpResponse::ServiceUnavailable().finish();
+    };
+    let mut infos = Vec::with_capacity(games.len());
+    for game in games {
+        if let Ok(info) = game.send(GetGameInfo).await {
+            infos.push(info);
+        }
+    }
+    HttpResponse::Ok().json(infos)
+}
+
+/// Counts errors since the server started.
+async fn admin_metrics_route(req: HttpRequest, cfg: Data<AppConfig>) -> HttpResponse {
+    if let Some(res) = reject_admin(&req, &cfg) {
+        return res;
+    }
+
+    HttpResponse::Ok().json(serde_json::json!({ "actorPanics": supervisor::panics() }))
+}
+
+/// Closes the lobby or game with the given ID.
+async fn admin_disconnect_route(
+    req: HttpRequest,
+    id: web::Path<String>,
+    cfg: Data<AppConfig>,
+    router: Data<Addr<actor::LobbyRouter>>,
+) -> HttpResponse {
+    if let Some(res) = reject_admin(&req, &cfg) {
+        return res;
+    }
+
+    let Ok(id) = Uuid::from_str(&id) else {
+        return not_found().await;
+    };
+    match router.send(Close(id)).await {
+        Ok(true) => HttpResponse::NoContent().finish(),
+        Ok(false) => not_found().await,
+        Err(_) => HttpResponse::ServiceUnavailable().finish(),
+    }
+}
+
+/// Lists every ban.
+async fn admin_bans_route(
+    req: HttpRequest,
+    cfg: Data<AppConfig>,
+    bans: Option<Data<Arc<BanList>>>,
+) -> HttpResponse {
+    if let Some(res) = reject_admin(&req, &cfg) {
+        return res;
+    }
+
+    let bans = bans.map(|b| b.list()).unwrap_or_default();
+    HttpResponse::Ok().json(bans)
+}
+
+/// Bans an address or a user, closing their connections.
+async fn admin_ban_route(
+    req: HttpRequest,
+    target: web::Json<BanTarget>,
+    cfg: Data<AppConfig>,
+    bans: Option<Data<Arc<BanList>>>,
+) -> HttpResponse {
+    if let Some(res) = reject_admin(&req, &cfg) {
+        return res;
+    }
+
+    let Some(bans) = bans else {
+        return not_found().await;
+    };
+    let target = target.into_inner();
+    match web::block(move || bans.ban(target)).await {
+        Ok(Ok(true)) => HttpResponse::Created().finish(),
+        Ok(Ok(false)) => HttpResponse::NoContent().finish(),
+        Ok(Err(e)) => {
+            error!("Failed to save bans: {e}");
+            HttpResponse::InternalServerError().finish()
+        }
+        Err(_) => HttpResponse::ServiceUnavailable().finish(),
+    }
+}
+
+/// Lifts a ban.
+async fn admin_unban_route(
+    req: HttpRequest,
+    target: web::Json<BanTarget>,
+    cfg: Data<AppConfig>,
+    bans: Option<Data<Arc<BanList>>>,
+) -> HttpResponse {
+    if let Some(res) = reject_admin(&req, &cfg) {
+        return res;
+    }
+
+    let Some(bans) = bans else {
+        return not_found().await;
+    };
+    let target = target.into_inner();
+    match web::block(move || bans.unban(&target)).await {
+        Ok(Ok(true)) => HttpResponse::NoContent().finish(),
+        Ok(Ok(false)) => not_found().await,
+        Ok(Err(e)) => {
+            error!("Failed to save bans: {e}");
+            HttpResponse::InternalServerError().finish()
+        }
+        Err(_) => HttpResponse::ServiceUnavailable().finish(),
+    }
+}
+
+/// Reads the settings again and applies those which can change while the
+/// server runs.
+async fn admin_reload_route(
+    req: HttpRequest,
+    cfg: Data<AppConfig>,
+    live_config: Option<Data<Arc<LiveConfig>>>,
+) -> HttpResponse {
+    if let Some(res) = reject_admin(&req, &cfg) {
+        return res;
+    }
+
+    let Some(live_config) = live_config else {
+        return not_found().await;
+    };
+    let live_config = Arc::clone(&live_config);
+    match web::block(move || live_config.reload()).await {
+        Ok(Ok(_)) => {
+            debug!("Settings reloaded through the admin API");
+            HttpResponse::NoContent().finish()
+        }
+        Ok(Err(ReloadError::NoLoader)) => not_found().await,
+        Ok(Err(e)) => {
+            error!("Failed to reload the settings: {e}");
+            HttpResponse::InternalServerError().body(e.to_string())
+        }
+        Err(_) => HttpResponse::ServiceUnavailable().finish(),
+    }
+}
+
+/// Closes every lobby and game, then stops the server.
+async fn admin_shutdown_route(
+    req: HttpRequest,
+    cfg: Data<AppConfig>,
+    router: Data<Addr<actor::LobbyRouter>>,
+    server: Option<Data<OnceLock<ServerHandle>>>,
+) -> HttpResponse {
+    if let Some(res) = reject_admin(&req, &cfg) {
+        return res;
+    }
+
+    if router.send(CloseAll).await.is_err() {
+        return HttpResponse::ServiceUnavailable().finish();
+    }
+    if let Some(handle) = server.as_deref().and_then(|s| s.get()) {
+        debug!("Shutdown requested through the admin API");
+        rt::spawn(handle.stop(true));
+    }
+    HttpResponse::Accepted().finish()
+}
+
+/// Returns the query string with the lobby ID, join code, reconnect token,
+/// player ID and password removed.
+fn redact_query(qs: &QString, cfg: &AppConfig) -> String {
+    let pairs = qs
+        .to_pairs()
+        .into_iter()
+        .map(|(k, v)| {
+            if k == cfg.url_lobby_parameter
+                || k == URL_JOIN_CODE_PARAMETER
+                || k == URL_RECONNECT_PARAMETER
+                || k == URL_PLAYER_ID_PARAMETER
+                || k == URL_PASSWORD_PARAMETER
+                || k == URL_AUTH_PARAMETER
+            {
+                (k, REDACTED)
+            } else {
+                (k, v)
+            }
+        })
+        .collect();
+    QString::new(pairs).to_string()
+}
@@ -0,0 +1,19 @@
+//! Blocking HTTP client shared by the threads which send requests to other
+//! servers, the ACME client and the webhooks.
+
+use std::time::Duration;
+
+use ureq::{Agent, AgentBuilder};
+
+/// Returns an agent whose requests fail unless they are answered within
+/// `timeout`, counting from the start of connecting until the last byte of
+/// the response is read. Redirects are not followed.
+#[must_use]
+pub fn agent(timeout: Duration) -> Agent {
+    AgentBuilder::new()
+        .timeout_connect(timeout)
+        .timeout(timeout)
+        .redirects(0)
+        .user_agent("connect-four-server")
+        .build()
+}
@@ -0,0 +1,82 @@
+use std::time::Duration;
+
+use chrono::{DateTime, TimeDelta, Utc};
+
+/// Weight of a new sample in the smoothed values, as in TCP's SRTT.
+const SMOOTHING: f64 = 0.125;
+/// Clock offsets smaller than this are within the measurement error and are
+/// not corrected.
+const CLOCK_OFFSET_MIN_MS: f64 = 100.0;
+
+/// Round trip time and clock offset of a client, smoothed over all samples.
+#[derive(Clone, Copy, Default, Debug)]
+pub struct LatencyTracker {
+    /// Smoothed round trip time in milliseconds.
+    rtt: Option<f64>,
+    /// How far the clock of the client is ahead of the server's, in
+    /// milliseconds.
+    clock_offset: Option<f64>,
+}
+
+impl LatencyTracker {
+    /// Records the time between sending a WebSocket ping and receiving the
+    /// pong.
+    pub fn record_rtt(&mut self, sample: Duration) {
+        let sample = sample.as_secs_f64() * 1000.0;
+        self.rtt = Some(smooth(self.rtt, sample));
+    }
+
+    /// Records a `ping` message, where `sent` is when the client sent it in
+    /// milliseconds since the Unix epoch according to its own clock. The
+    /// message is assumed to have taken half of the round trip to arrive.
+    pub fn record_ping(&mut self, sent: f64, received: DateTime<Utc>) {
+        if !sent.is_finite() {
+            return;
+        }
+        let arrived = sent + self.rtt.unwrap_or_default() / 2.0;
+        let sample = arrived - received.timestamp_millis() as f64;
+        self.clock_offset = Some(smooth(self.clock_offset, sample));
+    }
+
+    /// Returns the smoothed round trip time, if measured.
+    #[must_use]
+    pub fn rtt(&self) -> Option<Duration> {
+        self.rtt
+            .map(|ms| Duration::from_micros((ms * 1000.0).round() as u64))
+    }
+
+    /// Returns how far the clock of the client is ahead of the server's, or
+    /// `None` if it has not been measured or the difference is negligible.
+    #[must_use]
+    pub fn clock_offset(&self) -> Option<TimeDelta> {
+        self.clock_offset
+            .filter(|ms| ms.abs() >= CLOCK_OFFSET_MIN_MS)
+            .map(|ms| TimeDelta::milliseconds(ms.round() as i64))
+    }
+}
+
+fn smooth(old: Option<f64>, sample: f64) -> f64 {
+    old.map_or(sample, |old| old + SMOOTHING * (sample - old))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn smooths_samples() {
+        let mut latency = LatencyTracker::default();
+        assert_eq!(latency.rtt(), None);
+        latency.record_rtt(Duration::from_millis(80));
+        latency.record_rtt(Duration::from_millis(160));
+        assert_eq!(latency.rtt(), Some(Duration::from_millis(90)));
+
+        let now = Utc::now();
+        let ms = now.timestamp_millis() as f64;
+        latency.record_ping(ms + 20.0, now);
+        assert_eq!(latency.clock_offset(), None);
+        latency.record_ping(ms + 1572.0, now);
+        latency.record_ping(f64::NAN, now);
+        assert_eq!(latency.clock_offset(), Some(TimeDelta::milliseconds(259)));
+    }
+}
@@ -0,0 +1,104 @@
+//! Counts connected players and running games across the whole server, so
+//! that new connections can be turned away while it is overloaded.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::server::config::LoadSheddingConfig;
+
+static PLAYERS: AtomicUsize = AtomicUsize::new(0);
+static GAMES: AtomicUsize = AtomicUsize::new(0);
+
+/// Keeps a player or game counted until dropped. Held by the actor, so that
+/// it is released even if the actor panics.
+pub struct LoadGuard(&'static AtomicUsize);
+
+impl LoadGuard {
+    #[must_use]
+    pub fn player() -> Self {
+        Self::new(&PLAYERS)
+    }
+
+    #[must_use]
+    pub fn game() -> Self {
+        Self::new(&GAMES)
+    }
+
+    fn new(counter: &'static AtomicUsize) -> Self {
+        counter.fetch_add(1, Ordering::Relaxed);
+        Self(counter)
+    }
+}
+
+impl Drop for LoadGuard {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Players and games counted at one point in time.
+#[derive(Clone, Copy, Debug)]
+pub struct Load {
+    pub players: usize,
+    pub games: usize,
+}
+
+impl Load {
+    #[must_use]
+    pub fn current() -> Self {
+        Self {
+            players: PLAYERS.load(Ordering::Relaxed),
+            games: GAMES.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Returns true if new connections should be turned away.
+    #[must_use]
+    pub const fn is_shedding(&self, cfg: &LoadSheddingConfig) -> bool {
+        (cfg.max_players > 0 && self.players >= cfg.max_players)
+            || (cfg.max_games > 0 && self.games >= cfg.max_games)
+    }
+
+    /// Returns true if no more games can be started, see
+    /// `AppConfig::max_games`.
+    #[must_use]
+    pub const fn is_at_game_capacity(&self, max_games: usize) -> bool {
+        max_games > 0 && self.games >= max_games
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn sheds_above_either_threshold() {
+        let cfg = LoadSheddingConfig {
+            max_players: 10,
+            max_games: 0,
+            retry_after: Duration::from_secs(30),
+        };
+        let load = |players, games| Load { players, games };
+        assert!(!load(9, 1000).is_shedding(&cfg));
+        assert!(load(10, 0).is_shedding(&cfg));
+
+        let cfg = LoadSheddingConfig {
+            max_games: 5,
+            ..cfg
+        };
+        assert!(load(0, 5).is_shedding(&cfg));
+        assert!(!load(1000, 1000).is_shedding(&LoadSheddingConfig::default()));
+    }
+
+    #[test]
+    fn game_capacity() {
+        let load = Load {
+            players: 0,
+            games: 3,
+        };
+        assert!(load.is_at_game_capacity(3));
+        assert!(!load.is_at_game_capacity(4));
+        assert!(!load.is_at_game_capacity(0));
+    }
+}
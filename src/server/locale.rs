@@ -0,0 +1,279 @@
+//! Human readable descriptions of the codes sent by the server, in the
+//! languages clients can pick with the `lang` query parameter or the
+//! `setLocale` message.
+//!
+//! Descriptions are only attached for clients which picked a locale, the
+//! codes alone remain the part of the protocol meant to be parsed.
+
+use crate::game::Player;
+use crate::server::protocol::{
+    ConfigField, ConfigRejection, ConfigRejectionReason, Disconnect, Notice,
+};
+
+/// Language of the descriptions sent to a client.
+#[derive(Clone, Copy, PartialEq, Eq, Default, Debug)]
+pub enum Locale {
+    #[default]
+    En,
+    De,
+    Pl,
+}
+
+impl Locale {
+    /// Picks the locale for a BCP 47 language tag such as `pl-PL`, going by
+    /// its primary language. Languages without a catalog fall back to
+    /// English.
+    #[must_use]
+    pub fn from_tag(tag: &str) -> Self {
+        let language = tag.split(['-', '_']).next().unwrap_or_default();
+        match language.to_ascii_lowercase().as_str() {
+            "de" => Self::De,
+            "pl" => Self::Pl,
+            _ => Self::En,
+        }
+    }
+
+    /// Describes why the connection is being closed.
+    #[must_use]
+    pub fn disconnect(self, d: Disconnect) -> &'static str {
+        match self {
+            Self::En => disconnect_en(d),
+            Self::De => disconnect_de(d),
+            Self::Pl => disconnect_pl(d),
+        }
+    }
+
+    /// Describes which field of a game configuration was rejected and why.
+    #[must_use]
+    pub fn config_rejection(self, rejection: ConfigRejection) -> String {
+        let (field, reason) = match self {
+            Self::En => (field_en(rejection.field), reason_en(rejection.reason)),
+            Self::De => (field_de(rejection.field), reason_de(rejection.reason)),
+            Self::Pl => (field_pl(rejection.field), reason_pl(rejection.reason)),
+        };
+        format!("{field}: {reason}.")
+    }
+
+    /// Describes the event announced in the chat.
+    #[must_use]
+    pub fn notice(self, notice: Notice) -> String {
+        let player = |p: Player| p as u8 + 1;
+        match (self, notice) {
+            (Self::En, Notice::PlayerJoined { code }) => format!("Player {code} joined."),
+            (Self::En, Notice::PlayerLeft { code }) => format!("Player {code} left."),
+            (Self::En, Notice::PlayerDisconnected { player: p }) => {
+                format!("Player {} lost their connection.", player(p))
+            }
+            (Self::En, Notice::PlayerReconnected { player: p }) => {
+                format!("Player {} is back.", player(p))
+            }
+            (Self::De, Notice::PlayerJoined { code }) => format!("Spieler {code} ist beigetreten."),
+            (Self::De, Notice::PlayerLeft { code }) => {
+                format!("Spieler {code} hat die Lobby verlassen.")
+            }
+            (Self::De, Notice::PlayerDisconnected { player: p }) => {
+                format!("Spieler {} hat die Verbindung verloren.", player(p))
+            }
+            (Self::De, Notice::PlayerReconnected { player: p }) => {
+                format!("Spieler {} ist wieder da.", player(p))
+            }
+            (Self::Pl, Notice::PlayerJoined { code }) => format!("Gracz {code} dołączył do lobby."),
+            (Self::Pl, Notice::PlayerLeft { code }) => format!("Gracz {code} opuścił lobby."),
+            (Self::Pl, Notice::PlayerDisconnected { player: p }) => {
+                format!("Gracz {} stracił połączenie.", player(p))
+            }
+            (Self::Pl, Notice::PlayerReconnected { player: p }) => {
+                format!("Gracz {} wrócił do gry.", player(p))
+            }
+        }
+    }
+}
+
+fn disconnect_en(d: Disconnect) -> &'static str {
+    match d {
+        Disconnect::ServerMaxLobbies => "The server cannot open any more lobbies right now.",
+        Disconnect::ServerMaxGames => "The server cannot start any more games right now.",
+        Disconnect::InviteInvalid => "The invite is invalid or has expired.",
+        Disconnect::LobbyJoinError => "Could not join the lobby.",
+        Disconnect::LobbyFull => "The lobby is full.",
+        Disconnect::LobbyClosed => "The lobby was closed.",
+        Disconnect::LobbyExpired => "The lobby was closed after being inactive for too long.",
+        Disconnect::GameStarted => "The game has already started.",
+        Disconnect::GameEnded => "The game has ended.",
+        Disconnect::GameAbandoned => "The game was closed because nobody played for too long.",
+        Disconnect::LobbyOverloaded => "The lobby is too busy, try again later.",
+        Disconnect::ServerOverloaded => "The server is too busy, try again later.",
+        Disconnect::ShuttingDown => "The server is shutting down.",
+        Disconnect::ReconnectFailed => "The game could not be resumed.",
+        Disconnect::Replaced => "You connected to the game from somewhere else.",
+        Disconnect::RateLimited => "Too many messages were sent.",
+        Disconnect::WrongPassword => "The password is wrong.",
+        Disconnect::TooSlow => "The connection is too slow.",
+        Disconnect::InternalError => "Something went wrong on the server.",
+        Disconnect::Banned => "You are banned from this server.",
+        Disconnect::MatchmakingTimedOut => "No opponent was found in time.",
+        Disconnect::MatchmakingCancelled => "You left the matchmaking queue.",
+        Disconnect::Kicked => "The host removed you from the lobby.",
+    }
+}
+
+fn disconnect_de(d: Disconnect) -> &'static str {
+    match d {
+        Disconnect::ServerMaxLobbies => "Der Server kann gerade keine weiteren Lobbys öffnen.",
+        Disconnect::ServerMaxGames => "Der Server kann gerade keine weiteren Spiele starten.",
+        Disconnect::InviteInvalid => "Die Einladung ist ungültig oder abgelaufen.",
+        Disconnect::LobbyJoinError => "Der Lobby konnte nicht beigetreten werden.",
+        Disconnect::LobbyFull => "Die Lobby ist voll.",
+        Disconnect::LobbyClosed => "Die Lobby wurde geschlossen.",
+        Disconnect::LobbyExpired => "Die Lobby wurde nach zu langer Inaktivität geschlossen.",
+        Disconnect::GameStarted => "Das Spiel hat bereits begonnen.",
+        Disconnect::GameEnded => "Das Spiel ist beendet.",
+        Disconnect::GameAbandoned => {
+            "Das Spiel wurde geschlossen, weil zu lange niemand gespielt hat."
+        }
+        Disconnect::LobbyOverloaded => "Die Lobby ist überlastet, versuche es später erneut.",
+        Disconnect::ServerOverloaded => "Der Server ist überlastet, versuche es später erneut.",
+        Disconnect::ShuttingDown => "Der Server wird heruntergefahren.",
+        Disconnect::ReconnectFailed => "Das Spiel konnte nicht fortgesetzt werden.",
+        Disconnect::Replaced => "Du hast dich von einem anderen Ort aus mit dem Spiel verbunden.",
+        Disconnect::RateLimited => "Es wurden zu viele Nachrichten gesendet.",
+        Disconnect::WrongPassword => "Das Passwort ist falsch.",
+        Disconnect::TooSlow => "Die Verbindung ist zu langsam.",
+        Disconnect::InternalError => "Auf dem Server ist ein Fehler aufgetreten.",
+        Disconnect::Banned => "Du bist auf diesem Server gesperrt.",
+        Disconnect::MatchmakingTimedOut => "Es wurde rechtzeitig kein Gegner gefunden.",
+        Disconnect::MatchmakingCancelled => "Du hast die Warteschlange verlassen.",
+        Disconnect::Kicked => "Der Host hat dich aus der Lobby entfernt.",
+    }
+}
+
+fn disconnect_pl(d: Disconnect) -> &'static str {
+    match d {
+        Disconnect::ServerMaxLobbies => "Serwer nie może teraz otworzyć kolejnych lobby.",
+        Disconnect::ServerMaxGames => "Serwer nie może teraz rozpocząć kolejnych gier.",
+        Disconnect::InviteInvalid => "Zaproszenie jest nieprawidłowe lub wygasło.",
+        Disconnect::LobbyJoinError => "Nie udało się dołączyć do lobby.",
+        Disconnect::LobbyFull => "Lobby jest pełne.",
+        Disconnect::LobbyClosed => "Lobby zostało zamknięte.",
+        Disconnect::LobbyExpired => "Lobby zostało zamknięte z powodu zbyt długiej bezczynności.",
+        Disconnect::GameStarted => "Gra już się rozpoczęła.",
+        Disconnect::GameEnded => "Gra się zakończyła.",
+        Disconnect::GameAbandoned => {
+            "Gra została zamknięta, ponieważ przez zbyt długi czas nikt nie grał."
+        }
+        Disconnect::LobbyOverloaded => "Lobby jest przeciążone, spróbuj ponownie później.",
+        Disconnect::ServerOverloaded => "Serwer jest przeciążony, spróbuj ponownie później.",
+        Disconnect::ShuttingDown => "Serwer jest wyłączany.",
+        Disconnect::ReconnectFailed => "Nie udało się wznowić gry.",
+        Disconnect::Replaced => "Połączono się z grą z innego miejsca.",
+        Disconnect::RateLimited => "Wysłano zbyt wiele wiadomości.",
+        Disconnect::WrongPassword => "Hasło jest nieprawidłowe.",
+        Disconnect::TooSlow => "Połączenie jest zbyt wolne.",
+        Disconnect::InternalError => "Wystąpił błąd serwera.",
+        Disconnect::Banned => "Dostęp do tego serwera został zablokowany.",
+        Disconnect::MatchmakingTimedOut => "Nie znaleziono przeciwnika na czas.",
+        Disconnect::MatchmakingCancelled => "Opuszczono kolejkę wyszukiwania gry.",
+        Disconnect::Kicked => "Gospodarz usunął cię z lobby.",
+    }
+}
+
+fn field_en(field: ConfigField) -> &'static str {
+    match field {
+        ConfigField::TimePerTurn => "Time per turn",
+        ConfigField::TimeCap => "Time cap",
+        ConfigField::TotalTime => "Total time",
+        ConfigField::Increment => "Increment",
+        ConfigField::WinLen => "Chips in a row needed to win",
+        ConfigField::Seed => "Seed",
+        ConfigField::Players => "Number of players",
+        ConfigField::FreePlacement => "Free placement",
+        ConfigField::ByoYomiPeriods => "Byo-yomi periods",
+    }
+}
+
+fn field_de(field: ConfigField) -> &'static str {
+    match field {
+        ConfigField::TimePerTurn => "Zeit pro Zug",
+        ConfigField::TimeCap => "Zeitobergrenze",
+        ConfigField::TotalTime => "Gesamtzeit",
+        ConfigField::Increment => "Zeitbonus",
+        ConfigField::WinLen => "Steine in einer Reihe zum Sieg",
+        ConfigField::Seed => "Seed",
+        ConfigField::Players => "Spieleranzahl",
+        ConfigField::FreePlacement => "Freie Platzierung",
+        ConfigField::ByoYomiPeriods => "Byo-Yomi-Perioden",
+    }
+}
+
+fn field_pl(field: ConfigField) -> &'static str {
+    match field {
+        ConfigField::TimePerTurn => "Czas na ruch",
+        ConfigField::TimeCap => "Limit czasu",
+        ConfigField::TotalTime => "Czas całkowity",
+        ConfigField::Increment => "Dodatek czasu",
+        ConfigField::WinLen => "Żetony w rzędzie potrzebne do wygranej",
+        ConfigField::Seed => "Ziarno",
+        ConfigField::Players => "Liczba graczy",
+        ConfigField::FreePlacement => "Dowolne umieszczanie",
+        ConfigField::ByoYomiPeriods => "Okresy byo-yomi",
+    }
+}
+
+fn reason_en(reason: ConfigRejectionReason) -> &'static str {
+    match reason {
+        ConfigRejectionReason::BelowMinimum => "below the minimum allowed on this server",
+        ConfigRejectionReason::AboveMaximum => "above the maximum allowed on this server",
+        ConfigRejectionReason::Invalid => "not a valid value",
+    }
+}
+
+fn reason_de(reason: ConfigRejectionReason) -> &'static str {
+    match reason {
+        ConfigRejectionReason::BelowMinimum => "unter dem Minimum dieses Servers",
+        ConfigRejectionReason::AboveMaximum => "über dem Maximum dieses Servers",
+        ConfigRejectionReason::Invalid => "kein gültiger Wert",
+    }
+}
+
+fn reason_pl(reason: ConfigRejectionReason) -> &'static str {
+    match reason {
+        ConfigRejectionReason::BelowMinimum => "poniżej minimum dozwolonego na tym serwerze",
+        ConfigRejectionReason::AboveMaximum => "powyżej maksimum dozwolonego na tym serwerze",
+        ConfigRejectionReason::Invalid => "nieprawidłowa wartość",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn picks_locale_by_primary_language() {
+        assert_eq!(Locale::from_tag("pl"), Locale::Pl);
+        assert_eq!(Locale::from_tag("pl-PL"), Locale::Pl);
+        assert_eq!(Locale::from_tag("DE_at"), Locale::De);
+        assert_eq!(Locale::from_tag("fr-FR"), Locale::En);
+        assert_eq!(Locale::from_tag(""), Locale::En);
+    }
+
+    #[test]
+    fn describes_in_the_locale() {
+        let rejection = ConfigRejection {
+            field: ConfigField::TimePerTurn,
+            reason: ConfigRejectionReason::AboveMaximum,
+        };
+        assert_eq!(
+            Locale::En.config_rejection(rejection),
+            "Time per turn: above the maximum allowed on this server."
+        );
+        assert_eq!(
+            Locale::Pl.disconnect(Disconnect::LobbyFull),
+            "Lobby jest pełne."
+        );
+        let notice = Notice::PlayerDisconnected { player: Player::P2 };
+        assert_eq!(
+            Locale::De.notice(notice),
+            "Spieler 2 hat die Verbindung verloren."
+        );
+    }
+}
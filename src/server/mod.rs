@@ -1,11 +1,40 @@
+pub mod acme;
 pub mod actor;
+pub mod archive;
+pub mod auth;
+pub mod ban;
+mod chat;
 pub mod cli;
+pub mod client;
+mod compression;
 pub mod config;
-mod game_config;
-mod player_tuple;
-pub mod serde;
+pub mod connection_limit;
+pub mod encoding;
+pub mod error;
+mod extra_time;
+pub mod game_log;
+pub mod http;
+mod http_client;
+mod latency;
+pub mod load;
+pub mod locale;
+mod outgoing_queue;
+mod player_array;
+pub mod profile;
+pub mod protocol;
+mod rate_limit;
+pub mod rating;
+pub mod recording;
+pub mod reload;
+pub mod series;
+mod stats;
+pub mod timer;
+pub mod tls;
+pub mod token;
+pub mod webhook;
 
 pub use cli::AppArgs;
 pub use config::AppConfig;
-use game_config::{GameConfig, PartialGameConfig};
-pub use player_tuple::PlayerTuple;
+pub use player_array::PlayerArray;
+
+use crate::game_config::{GameConfig, PartialGameConfig};
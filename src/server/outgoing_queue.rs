@@ -0,0 +1,165 @@
+use std::collections::VecDeque;
+
+use crate::server::config::OutgoingQueueConfig;
+
+/// The queue was full and held no snapshot which the new message replaces.
+#[derive(PartialEq, Eq, Debug)]
+pub struct QueueFull;
+
+struct Queued<T> {
+    msg: T,
+    /// Kind of the message if it holds the full state, so that a later
+    /// message of the same kind replaces it.
+    snapshot: Option<&'static str>,
+}
+
+/// Messages waiting to be written to a client. Only a limited amount of
+/// messages is written before the client confirms receiving them, by
+/// answering a probe sent after them.
+pub struct OutgoingQueue<T> {
+    queued: VecDeque<Queued<T>>,
+    /// Messages written but not confirmed yet.
+    in_flight: usize,
+    /// How many messages the probe sent last confirms, `None` if no probe
+    /// is waiting for an answer.
+    probe: Option<usize>,
+    max_in_flight: usize,
+    capacity: usize,
+}
+
+impl<T> OutgoingQueue<T> {
+    #[must_use]
+    pub fn new(cfg: &OutgoingQueueConfig) -> Self {
+        Self {
+            queued: VecDeque::new(),
+            in_flight: 0,
+            probe: None,
+            max_in_flight: cfg.max_in_flight,
+            capacity: cfg.capacity,
+        }
+    }
+
+    /// Whether messages are written immediately instead of being queued.
+    #[must_use]
+    pub fn is_disabled(&self) -> bool {
+        self.max_in_flight == 0
+    }
+
+    /// Queues the message. Once the queue is full, a snapshot makes room by
+    /// replacing the oldest queued snapshot of the same kind. Anything else
+    /// overflows the queue.
+    pub fn push(&mut self, msg: T, snapshot: Option<&'static str>) -> Result<(), QueueFull> {
+        if self.queued.len() >= self.capacity {
+            let replaced = snapshot
+                .and_then(|kind| self.queued.iter().position(|q| q.snapshot == Some(kind)))
+                .ok_or(QueueFull)?;
+            self.queued.remove(replaced);
+        }
+        self.queued.push_back(Queued { msg, snapshot });
+        Ok(())
+    }
+
+    /// Returns the next message which can be written now, counting it as in
+    /// flight.
+    pub fn pop_ready(&mut self) -> Option<T> {
+        if self.in_flight >= self.max_in_flight {
+            return None;
+        }
+        let queued = self.queued.pop_front()?;
+        self.in_flight += 1;
+        Some(queued.msg)
+    }
+
+    /// Returns every queued message regardless of the limit, e.g. before
+    /// the connection is closed.
+    pub fn drain(&mut self) -> impl Iterator<Item = T> + '_ {
+        self.queued.drain(..).map(|q| q.msg)
+    }
+
+    /// Starts a probe confirming every message in flight. Returns false if
+    /// there is nothing to confirm or a probe is already waiting.
+    pub fn start_probe(&mut self) -> bool {
+        if self.in_flight == 0 || self.probe.is_some() {
+            return false;
+        }
+        self.probe = Some(self.in_flight);
+        true
+    }
+
+    /// Called once the client answers the probe.
+    pub fn confirm_probe(&mut self) {
+        if let Some(confirmed) = self.probe.take() {
+            self.in_flight -= confirmed;
+        }
+    }
+
+    /// Whether at least half of the queue is taken.
+    #[must_use]
+    pub fn is_congested(&self) -> bool {
+        !self.queued.is_empty() && self.queued.len() * 2 >= self.capacity
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.queued.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn queue(max_in_flight: usize, capacity: usize) -> OutgoingQueue<&'static str> {
+        OutgoingQueue::new(&OutgoingQueueConfig {
+            max_in_flight,
+            capacity,
+        })
+    }
+
+    #[test]
+    fn writes_until_probe_is_answered() {
+        let mut q = queue(2, 4);
+        for s in ["a", "b", "c"] {
+            q.push(s, None).unwrap();
+        }
+        assert_eq!(q.pop_ready(), Some("a"));
+        assert_eq!(q.pop_ready(), Some("b"));
+        assert_eq!(q.pop_ready(), None);
+
+        assert!(q.start_probe());
+        assert!(!q.start_probe());
+        q.confirm_probe();
+        assert_eq!(q.pop_ready(), Some("c"));
+        assert!(q.is_empty());
+    }
+
+    #[test]
+    fn replaces_oldest_snapshot_of_the_same_kind_when_full() {
+        let mut q = queue(1, 3);
+        q.push("sent", None).unwrap();
+        q.pop_ready().unwrap();
+        q.push("sync 1", Some("gameSync")).unwrap();
+        q.push("chat", None).unwrap();
+        q.push("sync 2", Some("gameSync")).unwrap();
+        assert!(q.is_congested());
+
+        q.push("sync 3", Some("gameSync")).unwrap();
+        let queued: Vec<_> = q.drain().collect();
+        assert_eq!(queued, ["chat", "sync 2", "sync 3"]);
+    }
+
+    #[test]
+    fn full_queue_overflows_unless_a_snapshot_is_replaced() {
+        let mut q = queue(1, 2);
+        q.push("sent", None).unwrap();
+        q.pop_ready().unwrap();
+        q.push("lobby", Some("lobbySync")).unwrap();
+        q.push("a", None).unwrap();
+        assert_eq!(q.push("b", None), Err(QueueFull));
+        assert_eq!(q.push("sync", Some("gameSync")), Err(QueueFull));
+
+        q.push("lobby 2", Some("lobbySync")).unwrap();
+        let queued: Vec<_> = q.drain().collect();
+        assert_eq!(queued, ["a", "lobby 2"]);
+    }
+}
@@ -0,0 +1,99 @@
+use std::ops::{Index, IndexMut};
+
+use crate::game::Player;
+
+/// Stores one type T per player of a game, in turn order. Can be accessed by
+/// passing `Player` as index.
+#[derive(Clone, Default, PartialEq, Eq, Debug)]
+pub struct PlayerArray<T>(Vec<T>);
+
+impl<T> PlayerArray<T> {
+    /// Creates the values of every player of a game with the amount of
+    /// players.
+    #[must_use]
+    pub fn from_fn(players: u8, f: impl FnMut(Player) -> T) -> Self {
+        Self(Player::all(players).iter().copied().map(f).collect())
+    }
+
+    #[must_use]
+    pub fn into_inner(self) -> Vec<T> {
+        self.0
+    }
+
+    #[must_use]
+    pub fn as_slice(&self) -> &[T] {
+        &self.0
+    }
+
+    /// Returns the amount of players.
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Returns every player together with their value.
+    pub fn iter(&self) -> impl Iterator<Item = (Player, &T)> {
+        Player::ALL.into_iter().zip(&self.0)
+    }
+
+    /// Returns every player together with their mutable value.
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = (Player, &mut T)> {
+        Player::ALL.into_iter().zip(&mut self.0)
+    }
+
+    /// Returns the players in turn order.
+    pub fn players(&self) -> impl Iterator<Item = Player> + '_ {
+        Player::ALL.into_iter().take(self.0.len())
+    }
+
+    /// Applies `f` to the values of every player.
+    #[must_use]
+    pub fn map<U>(self, f: impl FnMut(T) -> U) -> PlayerArray<U> {
+        PlayerArray(self.0.into_iter().map(f).collect())
+    }
+
+    /// Exchanges the values of the first two players, e.g. when they swap
+    /// sides in a two player game.
+    pub fn swap(&mut self) {
+        self.0.swap(0, 1);
+    }
+}
+
+impl<T: Clone> PlayerArray<T> {
+    /// Gives every player of a game with the amount of players the value.
+    #[must_use]
+    pub fn repeat(value: T, players: u8) -> Self {
+        Self(vec![value; Player::all(players).len()])
+    }
+}
+
+impl<T, const N: usize> From<[T; N]> for PlayerArray<T> {
+    fn from(values: [T; N]) -> Self {
+        Self(Vec::from(values))
+    }
+}
+
+impl<T> FromIterator<T> for PlayerArray<T> {
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
+        Self(iter.into_iter().collect())
+    }
+}
+
+impl<T> Index<Player> for PlayerArray<T> {
+    type Output = T;
+
+    fn index(&self, player: Player) -> &Self::Output {
+        &self.0[player.index()]
+    }
+}
+
+impl<T> IndexMut<Player> for PlayerArray<T> {
+    fn index_mut(&mut self, player: Player) -> &mut Self::Output {
+        &mut self.0[player.index()]
+    }
+}
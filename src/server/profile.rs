@@ -0,0 +1,106 @@
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+/// Longest avatar ID accepted from clients.
+const AVATAR_MAX_LENGTH: usize = 32;
+
+/// Characters allowed in display names.
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub enum NameCharset {
+    /// Printable ASCII characters only.
+    Ascii,
+    /// Any characters other than control characters.
+    Unicode,
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("name charset must be one of: ascii, unicode")]
+pub struct ParseNameCharsetError;
+
+impl FromStr for NameCharset {
+    type Err = ParseNameCharsetError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "ascii" => Ok(Self::Ascii),
+            "unicode" => Ok(Self::Unicode),
+            _ => Err(ParseNameCharsetError),
+        }
+    }
+}
+
+/// How a player is shown to others.
+#[derive(Clone, PartialEq, Eq, Serialize, Deserialize, Debug)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct Profile {
+    pub name: String,
+    /// ID of an avatar picked by the client.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub avatar: Option<String>,
+}
+
+impl Profile {
+    /// Validates the profile sent by a client. The name is trimmed and runs
+    /// of whitespace are replaced with a single space. Returns `None` if the
+    /// name is empty, longer than `max_len` characters or contains
+    /// characters outside of the charset, or if the avatar is not 1 to 32
+    /// ASCII letters, digits, `-` or `_`.
+    #[must_use]
+    pub fn sanitize(
+        name: &str,
+        avatar: Option<String>,
+        max_len: usize,
+        charset: NameCharset,
+    ) -> Option<Self> {
+        let name = name.split_whitespace().collect::<Vec<_>>().join(" ");
+        let allowed = |c: char| match charset {
+            NameCharset::Ascii => c.is_ascii_graphic() || c == ' ',
+            NameCharset::Unicode => !c.is_control(),
+        };
+        if name.is_empty() || name.chars().count() > max_len || !name.chars().all(allowed) {
+            return None;
+        }
+
+        let avatar_valid = avatar.as_deref().is_none_or(|a| {
+            !a.is_empty()
+                && a.len() <= AVATAR_MAX_LENGTH
+                && a.bytes()
+                    .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_')
+        });
+        if !avatar_valid {
+            return None;
+        }
+
+        Some(Self { name, avatar })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sanitize_names() {
+        let sanitize = |name, charset| Profile::sanitize(name, None, 10, charset).map(|p| p.name);
+        assert_eq!(
+            sanitize("  Ann \t Lee ", NameCharset::Ascii).as_deref(),
+            Some("Ann Lee")
+        );
+        assert_eq!(sanitize("Zoë", NameCharset::Ascii), None);
+        assert_eq!(
+            sanitize("Zoë", NameCharset::Unicode).as_deref(),
+            Some("Zoë")
+        );
+        assert_eq!(sanitize("a\u{0}b", NameCharset::Unicode), None);
+        assert_eq!(sanitize("   ", NameCharset::Unicode), None);
+        assert_eq!(sanitize("abcdefghijk", NameCharset::Unicode), None);
+
+        let avatar =
+            |a: &str| Profile::sanitize("Ann", Some(String::from(a)), 10, NameCharset::Ascii);
+        assert!(avatar("cat-2").is_some());
+        assert!(avatar("cat 2").is_none());
+        assert!(avatar("").is_none());
+    }
+}
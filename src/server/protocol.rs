@@ -0,0 +1,2188 @@
+//! Messages exchanged with clients over the WebSocket connection. Every
+//! message can be both serialized and deserialized, so the same types can
+//! be used to write clients, bots and tests against the server.
+
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+
+use actix::Message;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_repr::{Deserialize_repr, Serialize_repr};
+use uuid::Uuid;
+
+use crate::duration::{as_millis_optional, as_millis_optional_list, as_millis_optional_tuple};
+use crate::game::{self, EndTurnError, Game, InvalidStateError, MoveKind};
+use crate::replay::Annotation;
+use crate::server::config::{AnnotationAuthors, QrConfig, QrEcLevel};
+use crate::server::encoding::Encoding;
+use crate::server::error::QrError;
+use crate::server::profile::Profile;
+use crate::server::series::Standing;
+use crate::server::{AppConfig, GameConfig, PartialGameConfig};
+
+/// Start of the names of the WebSocket subprotocols, followed by the version
+/// and the encoding, e.g. `connect-four.v2.msgpack`.
+const SUBPROTOCOL_PREFIX: &str = "connect-four.v";
+
+/// Format of the timestamps sent in messages.
+pub const ISO_8601_TIMESTAMP: &str = "%Y-%m-%dT%H:%M:%S%.3fZ";
+
+/// Versions of the WebSocket protocol. Newer versions add messages and
+/// fields, so older clients are served by leaving those messages out and
+/// sending the rest in their old form, see `OutgoingMessageV1`.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize_repr, Deserialize_repr, Debug)]
+#[repr(u8)]
+pub enum ProtocolVersion {
+    V1 = 1,
+    /// Adds `protocolHello`, chat, move history and late joining.
+    V2 = 2,
+}
+
+impl ProtocolVersion {
+    pub const LATEST: Self = Self::V2;
+
+    #[must_use]
+    const fn from_number(version: u8) -> Option<Self> {
+        match version {
+            1 => Some(Self::V1),
+            2 => Some(Self::V2),
+            _ => None,
+        }
+    }
+
+    /// Picks the newest version from a comma separated list of versions
+    /// supported by the client, or returns `None` if there are none in common.
+    #[must_use]
+    pub fn negotiate(versions: &str) -> Option<Self> {
+        versions
+            .split(',')
+            .filter_map(|v| v.trim().parse().ok())
+            .filter_map(Self::from_number)
+            .max()
+    }
+}
+
+/// Protocol version and encoding negotiated with the
+/// `Sec-WebSocket-Protocol` header instead of the query parameters.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Subprotocol {
+    pub version: ProtocolVersion,
+    pub encoding: Encoding,
+}
+
+impl Subprotocol {
+    /// Picks the newest version from the subprotocols offered by the client,
+    /// the one listed first if several have the same version. Returns `None`
+    /// if none of them is supported.
+    #[must_use]
+    pub fn negotiate<'a>(offered: impl IntoIterator<Item = &'a str>) -> Option<Self> {
+        offered
+            .into_iter()
+            .filter_map(|name| name.trim().parse::<Self>().ok())
+            .fold(None, |best, subprotocol| match best {
+                Some(best) if best.version >= subprotocol.version => Some(best),
+                _ => Some(subprotocol),
+            })
+    }
+}
+
+impl FromStr for Subprotocol {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (version, encoding) = s
+            .strip_prefix(SUBPROTOCOL_PREFIX)
+            .and_then(|rest| rest.split_once('.'))
+            .ok_or(())?;
+        let version = version.parse().ok().and_then(ProtocolVersion::from_number);
+        Ok(Self {
+            version: version.ok_or(())?,
+            encoding: encoding.parse()?,
+        })
+    }
+}
+
+impl fmt::Display for Subprotocol {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let version = self.version as u8;
+        write!(f, "{SUBPROTOCOL_PREFIX}{version}.{}", self.encoding.name())
+    }
+}
+
+/// Reason for disconnecting a client, sent to its player actor, which closes
+/// the connection.
+#[derive(Serialize, Message, Clone, Copy)]
+#[cfg_attr(test, derive(strum::EnumIter))]
+#[serde(rename_all = "camelCase")]
+#[rtype(result = "()")]
+pub enum Disconnect {
+    ServerMaxLobbies,
+    /// A game against the bot could not be started, see
+    /// `AppConfig::max_games`.
+    ServerMaxGames,
+    InviteInvalid,
+    LobbyJoinError,
+    LobbyFull,
+    LobbyClosed,
+    /// Nothing happened in the lobby for too long.
+    LobbyExpired,
+    GameStarted,
+    GameEnded,
+    /// No player acted in the game for too long.
+    GameAbandoned,
+    LobbyOverloaded,
+    ServerOverloaded,
+    ShuttingDown,
+    /// The game could not be resumed, either because it ended or the token
+    /// was invalid.
+    ReconnectFailed,
+    /// The player has reconnected using a different connection.
+    Replaced,
+    /// The client has sent too many messages.
+    RateLimited,
+    /// The password needed to join the lobby was missing or wrong.
+    WrongPassword,
+    /// The client read messages too slowly, and its outgoing queue filled
+    /// up.
+    TooSlow,
+    /// The lobby or game stopped because of a bug in the server.
+    InternalError,
+    /// The address or the user is banned, see `server::ban`.
+    Banned,
+    /// No opponent was found within `MatchmakingConfig::queue_timeout`.
+    MatchmakingTimedOut,
+    /// The player left the matchmaking queue with `matchmakingCancel`.
+    MatchmakingCancelled,
+    /// The host removed the player from the lobby, or blocked it from
+    /// joining again.
+    Kicked,
+}
+
+impl Disconnect {
+    #[must_use]
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::ServerMaxLobbies => "serverMaxLobbies",
+            Self::ServerMaxGames => "serverMaxGames",
+            Self::InviteInvalid => "inviteInvalid",
+            Self::LobbyJoinError => "lobbyJoinError",
+            Self::LobbyFull => "lobbyFull",
+            Self::LobbyClosed => "lobbyClosed",
+            Self::LobbyExpired => "lobbyExpired",
+            Self::GameStarted => "gameStarted",
+            Self::GameEnded => "gameEnded",
+            Self::GameAbandoned => "gameAbandoned",
+            Self::LobbyOverloaded => "lobbyOverloaded",
+            Self::ServerOverloaded => "serverOverloaded",
+            Self::ShuttingDown => "shuttingDown",
+            Self::ReconnectFailed => "reconnectFailed",
+            Self::Replaced => "replaced",
+            Self::RateLimited => "rateLimited",
+            Self::WrongPassword => "wrongPassword",
+            Self::TooSlow => "tooSlow",
+            Self::InternalError => "internalError",
+            Self::Banned => "banned",
+            Self::MatchmakingTimedOut => "matchmakingTimedOut",
+            Self::MatchmakingCancelled => "matchmakingCancelled",
+            Self::Kicked => "kicked",
+        }
+    }
+}
+
+/// Start of the close codes reserved for applications by RFC 6455, which
+/// end at 4999.
+pub const CLOSE_CODE_BASE: u16 = 4000;
+
+/// Code of the close frame sent when disconnecting a client for the reason.
+/// Codes are never reused, so clients can switch on them instead of the
+/// close frame's description.
+#[must_use]
+pub const fn close_code(d: Disconnect) -> u16 {
+    CLOSE_CODE_BASE
+        + match d {
+            Disconnect::ServerMaxLobbies => 0,
+            Disconnect::ServerMaxGames => 1,
+            Disconnect::InviteInvalid => 2,
+            Disconnect::LobbyJoinError => 3,
+            Disconnect::LobbyFull => 4,
+            Disconnect::LobbyClosed => 5,
+            Disconnect::LobbyExpired => 6,
+            Disconnect::GameStarted => 7,
+            Disconnect::GameEnded => 8,
+            Disconnect::GameAbandoned => 9,
+            Disconnect::LobbyOverloaded => 10,
+            Disconnect::ServerOverloaded => 11,
+            Disconnect::ShuttingDown => 12,
+            Disconnect::ReconnectFailed => 13,
+            Disconnect::Replaced => 14,
+            Disconnect::RateLimited => 15,
+            Disconnect::WrongPassword => 16,
+            Disconnect::TooSlow => 17,
+            Disconnect::InternalError => 18,
+            Disconnect::Banned => 19,
+            Disconnect::MatchmakingTimedOut => 20,
+            Disconnect::MatchmakingCancelled => 21,
+            Disconnect::Kicked => 22,
+        }
+}
+
+/// Optional features of the server, announced in `protocolHello`.
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Debug)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "camelCase")]
+pub enum Capability {
+    Timers,
+    Clocks,
+    Spectators,
+    History,
+    Bot,
+    Queue,
+    Chat,
+    Reconnect,
+    TimeWarnings,
+    /// Long messages are sent as binary frames compressed with raw deflate
+    /// if the client connects with `compress=deflate`.
+    Compression,
+    /// Players receive `gameHint` at the start of their turn.
+    Hints,
+    /// Players who connect with a player ID receive `ratingUpdate` after
+    /// every game against another player with an ID.
+    Ratings,
+    /// Players can set a name and an avatar with `setProfile`.
+    Profiles,
+    /// Hosts can replace the invite with `lobbyRegenerateInvite`, and get
+    /// the current one again with `lobbyRequestLink`.
+    Invites,
+    /// Hosts can require a password to join with `lobbySetPassword`.
+    Passwords,
+    /// Hosts can start games with `winnerStays` in `lobbyPickPlayer`.
+    WinnerStays,
+    /// `gameSync` contains the latency of both players, and turn timeouts
+    /// are converted to the clock of clients who send `ping`.
+    Latency,
+    /// Players can concede with `gameResign`.
+    Resign,
+    /// Gameplay messages can be numbered with `seq`. Messages numbered no
+    /// higher than one already received are answered with `gameSync`
+    /// instead, so clients can safely resend them after reconnecting.
+    SequenceNumbers,
+    /// Hosts can start an all-play-all series with `lobbyStartSeries`.
+    Series,
+    /// Clients can connect with `encoding=msgpack` or `encoding=cbor` to
+    /// exchange messages as msgpack or CBOR binary frames.
+    BinaryEncodings,
+    /// Clients can ask the lobby or game for its complete state with
+    /// `requestFullSync`.
+    FullSync,
+    /// Hosts can start games between three or four players with
+    /// `lobbyPickPlayers`, played with the same messages as two player
+    /// games.
+    Multiplayer,
+    /// Games can be played with `pieRule`, letting the second player take
+    /// over the first move with `gameSwapSides`.
+    PieRule,
+    /// Spectators or players can comment on moves with `gameAnnotate`, see
+    /// `AppConfig::annotations`.
+    Annotations,
+    /// Clients can connect with `matchmake=1` to be paired with another
+    /// waiting player, and leave the queue with `matchmakingCancel`.
+    Matchmaking,
+    /// Hosts can start a game between two other players with
+    /// `lobbyPickPair`, watching it as a spectator.
+    PickPair,
+    /// Players can change the turn timer of a game in progress with
+    /// `gameChangeTiming`, once their opponent accepts.
+    TimingChanges,
+    /// Hosts can set the configuration games in the lobby start with by
+    /// default with `lobbySetConfig`, shown to everyone in `lobbySync`.
+    LobbyConfig,
+    /// Clients which connect with `batch=1` receive the messages caused by a
+    /// single move or restart in one `batch`.
+    Batches,
+    /// Players waiting in a lobby can tell the host they are ready to be
+    /// picked with `lobbyReady`, listed in `ready` of `lobbySync`.
+    ReadyStates,
+    /// Hosts can remove players from the lobby with `lobbyKick`, and keep
+    /// them from joining again with `"block": true`.
+    Kick,
+    /// The column the player to move points at with `gameHover` is shown
+    /// to the other players in `opponentHover`.
+    Hover,
+}
+
+/// Returns the features enabled by the configuration.
+#[must_use]
+pub fn capabilities(cfg: &AppConfig) -> Vec<Capability> {
+    use Capability::*;
+
+    let mut capabilities = vec![
+        Timers,
+        Clocks,
+        Spectators,
+        History,
+        Bot,
+        Queue,
+        Invites,
+        Passwords,
+        WinnerStays,
+        Latency,
+        Resign,
+        SequenceNumbers,
+        Series,
+        BinaryEncodings,
+        FullSync,
+        Multiplayer,
+        PieRule,
+        PickPair,
+        TimingChanges,
+        LobbyConfig,
+        Batches,
+        ReadyStates,
+        Kick,
+        Hover,
+    ];
+    if cfg.chat_max_length > 0 {
+        capabilities.push(Chat);
+    }
+    if !cfg.reconnect_grace_period.is_zero() {
+        capabilities.push(Reconnect);
+    }
+    if !cfg.time_warnings.is_empty() {
+        capabilities.push(TimeWarnings);
+    }
+    if cfg.compression_threshold > 0 {
+        capabilities.push(Compression);
+    }
+    if cfg.hint_depth > 0 {
+        capabilities.push(Hints);
+    }
+    if cfg.ratings.enabled {
+        capabilities.push(Ratings);
+    }
+    if cfg.profiles.name_max_length > 0 {
+        capabilities.push(Profiles);
+    }
+    if cfg.annotations != AnnotationAuthors::Nobody {
+        capabilities.push(Annotations);
+    }
+    if cfg.matchmaking.enabled {
+        capabilities.push(Matchmaking);
+    }
+    capabilities
+}
+
+// Outgoing messages
+
+/// Message sent by the server to a client, tagged with its name in `type`.
+#[derive(Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum OutgoingMessage<'a> {
+    /// First message sent to clients using protocol version 2 or newer.
+    ProtocolHello {
+        version: ProtocolVersion,
+        capabilities: Vec<Capability>,
+    },
+    LobbyLink(OutgoingLobbyLink),
+    LobbySync {
+        players: Cow<'a, [u8]>,
+        spectators: Cow<'a, [u8]>,
+        /// Codes of the players who are ready to be picked, see
+        /// `Capability::ReadyStates`.
+        #[serde(default)]
+        ready: Cow<'a, [u8]>,
+        /// Names and avatars of the players who have set them, by code.
+        profiles: Cow<'a, HashMap<u8, Profile>>,
+        /// Configuration set by the host with `lobbySetConfig`. Once it is
+        /// set, every player in the lobby receives `lobbySync`, not only the
+        /// host.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        config: Option<Cow<'a, GameConfig>>,
+    },
+    LobbyCode {
+        code: u8,
+    },
+    GameSetup(OutgoingGameSetup<'a>),
+    GamePlayerSelection(OutgoingPlayerSelection),
+    GameSync(OutgoingGameSync<'a>),
+    /// Sent instead of `GameSync` after a turn ends, containing only the
+    /// move and the new state of the game.
+    GameMove(OutgoingGameMove<'a>),
+    GameRestartRequest(OutgoingRestartRequest<'a>),
+    GameTimingRequest(OutgoingTimingRequest<'a>),
+    GameHistory(OutgoingGameHistory<'a>),
+    /// Chat message sent by one of the players in the game.
+    GameChat {
+        player: game::Player,
+        text: Cow<'a, str>,
+        /// ISO 8601 timestamp of when the message was received.
+        timestamp: String,
+        /// Profile of the player, if set.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        profile: Option<Cow<'a, Profile>>,
+    },
+    /// Chat message sent by the host or one of the players waiting in the
+    /// lobby.
+    LobbyChat {
+        /// Code of the player, `None` if sent by the host.
+        code: Option<u8>,
+        text: Cow<'a, str>,
+        /// ISO 8601 timestamp of when the message was received.
+        timestamp: String,
+        /// Profile of the player, if set.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        profile: Option<Cow<'a, Profile>>,
+    },
+    /// Sent to players who joined the lobby after the game has started. They
+    /// are spectating until they join the queue.
+    GameInProgress {
+        options: Cow<'a, [LateJoinOption]>,
+    },
+    /// Position of the player in the queue for the next free seat, `None`
+    /// if not queued.
+    GameQueue {
+        position: Option<usize>,
+    },
+    /// Sent to the player once their move has been made. Repeated if the
+    /// same move is sent again.
+    GameEndTurnAccepted {
+        round: u32,
+        turn: u32,
+    },
+    /// Sent to the player if their move could not be made.
+    GameEndTurnRejected {
+        round: u32,
+        turn: u32,
+        reason: EndTurnRejection,
+    },
+    /// Sent when the current turn is about to time out.
+    #[serde(rename_all = "camelCase")]
+    GameTimeWarning {
+        round: u32,
+        player: game::Player,
+        remaining_ms: u64,
+    },
+    /// Sent when a player loses connection during a game. The turn timer is
+    /// paused until they reconnect.
+    GamePlayerDisconnected {
+        round: u32,
+        player: game::Player,
+        /// ISO 8601 timestamp of when the player forfeits if they do not
+        /// reconnect.
+        timeout: String,
+    },
+    /// Column the player whose turn it is points at, sent to the other
+    /// players. `None` once they stop. Hovers may be dropped, so clients
+    /// should not rely on them.
+    OpponentHover {
+        round: u32,
+        turn: u32,
+        col: Option<usize>,
+    },
+    /// Sent when no player has acted for almost `AppConfig::game_idle_timeout`.
+    GameIdleWarning {
+        round: u32,
+        /// ISO 8601 timestamp of when the game is closed unless a player
+        /// acts.
+        timeout: String,
+    },
+    /// Sent when a disconnected player is back and the game continues.
+    GamePlayerReconnected {
+        round: u32,
+        player: game::Player,
+    },
+    /// Best move found for the player whose turn it is.
+    GameHint {
+        round: u32,
+        turn: u32,
+        /// `None` if no move can be made.
+        col: Option<usize>,
+        /// Score of the position for the player, see `game::analysis::evaluate`.
+        score: i32,
+    },
+    /// Sent to the host if the game could not be started from
+    /// `LobbyPickPlayer`.
+    LobbyPickRejected {
+        code: u8,
+        reason: PickRejection,
+    },
+    /// Sent when a game configuration from `lobbyPickPlayer` or
+    /// `gameRestart` is outside of the limits set by the server.
+    ConfigRejected {
+        #[serde(flatten)]
+        rejection: ConfigRejection,
+        /// Human readable description in the client's locale, only sent if
+        /// the client picked one.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        description: Option<String>,
+    },
+    /// Points table of the series started with `lobbyStartSeries`, sent
+    /// to everyone in the lobby after every game of the series.
+    SeriesStandings {
+        standings: Vec<Standing>,
+        /// Amount of games which have not finished yet.
+        remaining: usize,
+        finished: bool,
+    },
+    /// Rating of the player after a game against another player who
+    /// connected with a player ID.
+    RatingUpdate {
+        rating: f64,
+        change: f64,
+        games: u32,
+    },
+    Pong {
+        sent: f64,
+        received: String,
+    },
+    /// Comment on a move, sent to everyone in the game.
+    GameAnnotation {
+        round: u32,
+        annotation: Cow<'a, Annotation>,
+    },
+    /// Event in the lobby or game, shown in the chat. Only sent if chat is
+    /// enabled.
+    ChatNotice {
+        notice: Notice,
+        /// ISO 8601 timestamp of when the event happened.
+        timestamp: String,
+        /// Human readable description in the client's locale, only sent if
+        /// the client picked one.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        description: Option<String>,
+    },
+    /// Sent right before the connection is closed to clients which picked a
+    /// locale, describing the reason given in the close frame.
+    Closing {
+        reason: Cow<'a, str>,
+        /// Code of the close frame, see `close_code`.
+        code: u16,
+        description: Cow<'a, str>,
+    },
+    /// Sent to players who connected with `matchmake=1` once they are
+    /// waiting for an opponent.
+    MatchmakingQueued {
+        /// ISO 8601 timestamp of when the player is disconnected unless an
+        /// opponent is found, `None` if they can wait indefinitely.
+        timeout: Option<String>,
+    },
+    /// Sent when a message from the recipient could not be processed.
+    Error {
+        reason: ErrorReason,
+        /// Human readable description, not meant to be parsed.
+        message: String,
+    },
+    /// Messages caused by a single event, e.g. a move, to be handled in
+    /// order. Only sent to clients which connected with `batch=1`, see
+    /// `Capability::Batches`.
+    Batch {
+        messages: Vec<OutgoingMessage<'a>>,
+    },
+}
+
+impl<'a> OutgoingMessage<'a> {
+    /// Constructs a new `OutgoingMessage::LobbyLink`.
+    #[must_use]
+    pub fn lobby_link(uuid: Uuid, join_code: &str, host_token: &str, qr_code: &QR) -> Self {
+        OutgoingLobbyLink::new(uuid, join_code, host_token, qr_code).into()
+    }
+
+    /// Returns an `OutgoingMessage::GameSetup` builder.
+    #[must_use]
+    pub fn game_setup(config: Option<&'a GameConfig>, role: Option<Role>) -> Self {
+        OutgoingGameSetup::new(config, role).into()
+    }
+
+    /// Constructs a new `OutgoingMessage::GamePlayerSelection`.
+    #[must_use]
+    pub fn game_player_selection(voted: &[bool]) -> Self {
+        let [p1_voted, p2_voted] = [0, 1].map(|i| voted.get(i).copied().unwrap_or_default());
+        let voted = if voted.len() > 2 {
+            voted.to_vec()
+        } else {
+            Vec::new()
+        };
+        OutgoingPlayerSelection {
+            p1_voted,
+            p2_voted,
+            voted,
+        }
+        .into()
+    }
+
+    /// Constructs a new `OutgoingMessage::GameSync`.
+    #[must_use]
+    pub fn game_sync(
+        round: u32,
+        game: &'a Game,
+        timeout: Option<DateTime<Utc>>,
+        clocks: Option<Vec<Duration>>,
+    ) -> Self {
+        OutgoingGameSync::new(round, game, timeout, clocks).into()
+    }
+
+    /// Constructs a new `OutgoingMessage::GameMove` describing the last move
+    /// made in the game, or `None` if no moves were made.
+    #[must_use]
+    pub fn game_move(
+        round: u32,
+        game: &'a Game,
+        timeout: Option<DateTime<Utc>>,
+        clocks: Option<Vec<Duration>>,
+    ) -> Option<Self> {
+        OutgoingGameMove::new(round, game, timeout, clocks).map(Self::GameMove)
+    }
+
+    /// Constructs a new `OutgoingMessage::GameRestartRequest`.
+    #[must_use]
+    pub fn game_restart_request(player: game::Player, req: Option<RestartRequest<'a>>) -> Self {
+        OutgoingRestartRequest { player, req }.into()
+    }
+
+    /// Constructs a new `OutgoingMessage::GameTimingRequest`.
+    #[must_use]
+    pub fn game_timing_request(player: game::Player, req: Option<TimingRequest<'a>>) -> Self {
+        OutgoingTimingRequest { player, req }.into()
+    }
+
+    /// Constructs a new `OutgoingMessage::GameHistory`.
+    #[must_use]
+    pub fn game_history(
+        round: u32,
+        moves: &'a [game::Move],
+        annotations: &'a [Annotation],
+    ) -> Self {
+        OutgoingGameHistory {
+            round,
+            moves: Cow::Borrowed(moves),
+            annotations: Cow::Borrowed(annotations),
+        }
+        .into()
+    }
+
+    /// Constructs a new `OutgoingMessage::GameChat`, timestamped with the
+    /// current time.
+    #[must_use]
+    pub fn game_chat(player: game::Player, text: &'a str, profile: Option<&'a Profile>) -> Self {
+        let timestamp = Utc::now().format(ISO_8601_TIMESTAMP).to_string();
+        Self::GameChat {
+            player,
+            text: Cow::Borrowed(text),
+            timestamp,
+            profile: profile.map(Cow::Borrowed),
+        }
+    }
+
+    /// Constructs a new `OutgoingMessage::LobbyChat`, timestamped with the
+    /// current time.
+    #[must_use]
+    pub fn lobby_chat(code: Option<u8>, text: &'a str, profile: Option<&'a Profile>) -> Self {
+        let timestamp = Utc::now().format(ISO_8601_TIMESTAMP).to_string();
+        Self::LobbyChat {
+            code,
+            text: Cow::Borrowed(text),
+            timestamp,
+            profile: profile.map(Cow::Borrowed),
+        }
+    }
+
+    /// Returns name of the variant which will be used in the `type` property
+    /// of the message.
+    #[must_use]
+    pub fn variant_name(&self) -> &'static str {
+        match self {
+            Self::ProtocolHello { .. } => "protocolHello",
+            Self::LobbyLink(_) => "lobbyLink",
+            Self::LobbySync { .. } => "lobbySync",
+            Self::LobbyCode { .. } => "lobbyCode",
+            Self::GameSetup(_) => "gameSetup",
+            Self::GamePlayerSelection(_) => "gamePlayerSelection",
+            Self::GameSync(_) => "gameSync",
+            Self::GameMove(_) => "gameMove",
+            Self::GameRestartRequest(_) => "gameRestartRequest",
+            Self::GameTimingRequest(_) => "gameTimingRequest",
+            Self::GameHistory(_) => "gameHistory",
+            Self::GameChat { .. } => "gameChat",
+            Self::LobbyChat { .. } => "lobbyChat",
+            Self::GameInProgress { .. } => "gameInProgress",
+            Self::GameQueue { .. } => "gameQueue",
+            Self::GameEndTurnAccepted { .. } => "gameEndTurnAccepted",
+            Self::GameEndTurnRejected { .. } => "gameEndTurnRejected",
+            Self::GameTimeWarning { .. } => "gameTimeWarning",
+            Self::GameIdleWarning { .. } => "gameIdleWarning",
+            Self::OpponentHover { .. } => "opponentHover",
+            Self::GamePlayerDisconnected { .. } => "gamePlayerDisconnected",
+            Self::GamePlayerReconnected { .. } => "gamePlayerReconnected",
+            Self::GameHint { .. } => "gameHint",
+            Self::LobbyPickRejected { .. } => "lobbyPickRejected",
+            Self::ConfigRejected { .. } => "configRejected",
+            Self::SeriesStandings { .. } => "seriesStandings",
+            Self::RatingUpdate { .. } => "ratingUpdate",
+            Self::Pong { .. } => "pong",
+            Self::GameAnnotation { .. } => "gameAnnotation",
+            Self::ChatNotice { .. } => "chatNotice",
+            Self::Closing { .. } => "closing",
+            Self::MatchmakingQueued { .. } => "matchmakingQueued",
+            Self::Error { .. } => "error",
+            Self::Batch { .. } => "batch",
+        }
+    }
+
+    /// Returns the oldest protocol version which includes the message.
+    #[must_use]
+    pub const fn min_version(&self) -> ProtocolVersion {
+        match self {
+            Self::ProtocolHello { .. }
+            | Self::GameMove(_)
+            | Self::GameTimingRequest(_)
+            | Self::GameHistory(_)
+            | Self::GameChat { .. }
+            | Self::LobbyChat { .. }
+            | Self::GameInProgress { .. }
+            | Self::GameQueue { .. }
+            | Self::GameEndTurnAccepted { .. }
+            | Self::GameEndTurnRejected { .. }
+            | Self::GameTimeWarning { .. }
+            | Self::GamePlayerDisconnected { .. }
+            | Self::GamePlayerReconnected { .. }
+            | Self::GameHint { .. }
+            | Self::LobbyPickRejected { .. }
+            | Self::ConfigRejected { .. }
+            | Self::SeriesStandings { .. }
+            | Self::RatingUpdate { .. }
+            | Self::GameAnnotation { .. }
+            | Self::ChatNotice { .. }
+            | Self::Closing { .. }
+            | Self::MatchmakingQueued { .. }
+            | Self::Error { .. }
+            | Self::Batch { .. }
+            | Self::OpponentHover { .. }
+            | Self::GameIdleWarning { .. } => ProtocolVersion::V2,
+            _ => ProtocolVersion::V1,
+        }
+    }
+
+    /// Returns the name of the message if it holds the full state of the
+    /// lobby or game, so that any later message of the same kind replaces
+    /// it.
+    #[must_use]
+    pub fn snapshot_kind(&self) -> Option<&'static str> {
+        matches!(self, Self::LobbySync { .. } | Self::GameSync(_)).then(|| self.variant_name())
+    }
+
+    /// Returns the message as protocol version 1 clients expect it, or
+    /// `None` if it has not changed since.
+    #[must_use]
+    pub fn to_v1(&self) -> Option<OutgoingMessageV1<'_>> {
+        Some(match self {
+            Self::LobbyLink(link) => OutgoingMessageV1::LobbyLink {
+                lobby: &link.lobby,
+                qr_code: &link.qr_code,
+            },
+            Self::LobbySync { players, .. } => OutgoingMessageV1::LobbySync { players },
+            Self::GameSetup(setup) => OutgoingMessageV1::GameSetup {
+                config: setup.config.as_deref(),
+                role: match setup.role {
+                    Some(Role::Player(player)) => Some(player),
+                    Some(Role::Spectator) | None => None,
+                },
+            },
+            Self::GameSync(sync) => OutgoingMessageV1::GameSync {
+                round: sync.round,
+                game: &sync.game,
+                timeout: sync.timeout.as_deref(),
+            },
+            _ => return None,
+        })
+    }
+}
+
+/// Messages whose fields changed since protocol version 1, in the form
+/// version 1 clients expect. Fields added since are left out, and so are
+/// spectators, which version 1 does not know about.
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum OutgoingMessageV1<'a> {
+    #[serde(rename_all = "camelCase")]
+    LobbyLink {
+        lobby: &'a str,
+        qr_code: &'a QR,
+    },
+    LobbySync {
+        players: &'a [u8],
+    },
+    GameSetup {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        config: Option<&'a GameConfig>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        role: Option<game::Player>,
+    },
+    GameSync {
+        round: u32,
+        game: &'a Game,
+        timeout: Option<&'a str>,
+    },
+}
+
+/// Joins serialized messages into a serialized `OutgoingMessage::Batch`,
+/// without parsing them again.
+#[must_use]
+pub fn batch<'a>(messages: impl IntoIterator<Item = &'a str>) -> String {
+    let mut text = String::from(r#"{"type":"batch","messages":["#);
+    for (i, msg) in messages.into_iter().enumerate() {
+        if i > 0 {
+            text.push(',');
+        }
+        text.push_str(msg);
+    }
+    text.push_str("]}");
+    text
+}
+
+/// Contents of `OutgoingMessage::LobbyLink`.
+#[derive(Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct OutgoingLobbyLink {
+    /// Lobby ID.
+    pub lobby: String,
+    /// Short code players can join the lobby with instead of its ID, easier
+    /// to read aloud.
+    pub join_code: String,
+    /// QR code of the invite link.
+    pub qr_code: QR,
+    /// Secret needed to read statistics of the game, only known to the host.
+    pub host_token: String,
+}
+
+impl OutgoingLobbyLink {
+    #[must_use]
+    pub fn new(uuid: Uuid, join_code: &str, host_token: &str, qr_code: &QR) -> Self {
+        Self {
+            lobby: uuid.as_hyphenated().to_string(),
+            join_code: String::from(join_code),
+            qr_code: qr_code.clone(),
+            host_token: String::from(host_token),
+        }
+    }
+}
+
+impl From<OutgoingLobbyLink> for OutgoingMessage<'_> {
+    fn from(msg: OutgoingLobbyLink) -> Self {
+        Self::LobbyLink(msg)
+    }
+}
+
+/// Contents of `OutgoingMessage::GameSetup` with builder functions for
+/// setting fields.
+#[derive(Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct OutgoingGameSetup<'a> {
+    /// Game configuration.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub config: Option<Cow<'a, GameConfig>>,
+    /// Tells the client which player controls it - `P1` (blue) or `P2` (red)
+    /// - or that it is only watching the game.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub role: Option<Role>,
+    /// Secret which allows the player to resume the game after losing
+    /// connection.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reconnect_token: Option<Cow<'a, str>>,
+    /// Secret which allows reading the state of the game from
+    /// `GET /game/{id}/snapshot`, e.g. for stream overlays.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub viewer_token: Option<Cow<'a, str>>,
+    /// Seed of the random choices in the game, either from the
+    /// configuration or picked by the server. Starting a game with the same
+    /// seed makes the same choices.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seed: Option<u64>,
+    /// Profile of every player in turn order, `None` for players who have
+    /// not set one.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub profiles: Option<Vec<Option<Cow<'a, Profile>>>>,
+}
+
+impl<'a> OutgoingGameSetup<'a> {
+    #[must_use]
+    pub fn new(config: Option<&'a GameConfig>, role: Option<Role>) -> Self {
+        Self {
+            config: config.map(Cow::Borrowed),
+            role,
+            reconnect_token: None,
+            viewer_token: None,
+            seed: None,
+            profiles: None,
+        }
+    }
+
+    #[must_use]
+    pub fn reconnect_token(mut self, token: &'a str) -> Self {
+        self.reconnect_token = Some(Cow::Borrowed(token));
+        self
+    }
+
+    #[must_use]
+    pub fn viewer_token(mut self, token: Option<&'a str>) -> Self {
+        self.viewer_token = token.map(Cow::Borrowed);
+        self
+    }
+
+    #[must_use]
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = Some(seed);
+        self
+    }
+
+    #[must_use]
+    pub fn profiles(mut self, profiles: impl IntoIterator<Item = Option<&'a Profile>>) -> Self {
+        self.profiles = Some(profiles.into_iter().map(|p| p.map(Cow::Borrowed)).collect());
+        self
+    }
+}
+
+/// Role of a client in a game.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Role {
+    Player(game::Player),
+    /// Receives game updates, but cannot make any moves.
+    Spectator,
+}
+
+impl Serialize for Role {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        match self {
+            Self::Player(player) => player.serialize(serializer),
+            Self::Spectator => serializer.serialize_str("spectator"),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Role {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum RoleRepr {
+            Player(game::Player),
+            Other(String),
+        }
+
+        match RoleRepr::deserialize(deserializer)? {
+            RoleRepr::Player(player) => Ok(Self::Player(player)),
+            RoleRepr::Other(s) if s == "spectator" => Ok(Self::Spectator),
+            RoleRepr::Other(s) => Err(serde::de::Error::invalid_value(
+                serde::de::Unexpected::Str(&s),
+                &"a player or \"spectator\"",
+            )),
+        }
+    }
+}
+
+impl From<game::Player> for Role {
+    fn from(player: game::Player) -> Self {
+        Self::Player(player)
+    }
+}
+
+/// What a player who joined a game in progress can do.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "camelCase")]
+pub enum LateJoinOption {
+    Spectate,
+    /// Take the seat of the next player who leaves.
+    Queue,
+}
+
+/// Why a move sent in `GameEndTurn` was not made.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "camelCase")]
+pub enum EndTurnRejection {
+    /// The players are still deciding who starts.
+    NotStarted,
+    NotYourTurn,
+    /// The turn has already ended or has not started yet.
+    WrongTurn,
+    GameOver,
+    IndexOutOfBounds,
+    ColumnFilled,
+    PopNotAllowed,
+    PopNotOwnChip,
+    PlaceNotAllowed,
+    CellTaken,
+    /// Sides can only be swapped when the game is played with `pieRule`,
+    /// right after the first move and while both players are connected.
+    SwapNotAllowed,
+}
+
+/// Why a message sent by the client was not processed, see
+/// `ProtocolError`.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "camelCase")]
+pub enum ErrorReason {
+    /// The message is not valid JSON or does not match any incoming
+    /// message.
+    InvalidMessage,
+    /// The binary message could not be decoded with the chosen encoding,
+    /// or does not match any incoming message.
+    DecodeFailed,
+    /// The message cannot be handled by the lobby or game the client is in,
+    /// or the client is in neither.
+    NoController,
+}
+
+impl From<EndTurnError> for EndTurnRejection {
+    fn from(err: EndTurnError) -> Self {
+        match err {
+            EndTurnError::IndexOutOfBounds => Self::IndexOutOfBounds,
+            EndTurnError::GameOver => Self::GameOver,
+            EndTurnError::ColumnFilled => Self::ColumnFilled,
+            EndTurnError::PopNotAllowed => Self::PopNotAllowed,
+            EndTurnError::PopNotOwnChip => Self::PopNotOwnChip,
+            EndTurnError::PlaceNotAllowed => Self::PlaceNotAllowed,
+            EndTurnError::CellTaken => Self::CellTaken,
+        }
+    }
+}
+
+/// Why the game could not be started from `LobbyPickPlayer` or
+/// `LobbyPickPair`.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "camelCase")]
+pub enum PickRejection {
+    /// The configuration or the rules of the game are invalid.
+    InvalidConfig,
+    /// No player in the lobby has the code.
+    UnknownPlayer,
+    /// Both codes of `lobbyPickPair` are the same.
+    SamePlayer,
+    /// Games cannot be started while a series is being played.
+    SeriesInProgress,
+    /// The server is running as many games as it allows, see
+    /// `AppConfig::max_games`.
+    ServerMaxGames,
+    FloatingChip,
+    WrongMoveCount,
+    WrongPlayer,
+    TooManyChips,
+    WrongResult,
+    /// The field does not have the size of the field of the configured
+    /// amount of players.
+    WrongFieldSize,
+}
+
+/// Contents of `OutgoingMessage::ConfigRejected`.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct ConfigRejection {
+    pub field: ConfigField,
+    pub reason: ConfigRejectionReason,
+}
+
+/// Event announced in the chat, see `OutgoingMessage::ChatNotice`.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(tag = "kind", rename_all = "camelCase")]
+pub enum Notice {
+    /// A player with the code joined the lobby.
+    PlayerJoined {
+        code: u8,
+    },
+    PlayerLeft {
+        code: u8,
+    },
+    /// A player lost their connection to the game, and may still reconnect.
+    PlayerDisconnected {
+        player: game::Player,
+    },
+    PlayerReconnected {
+        player: game::Player,
+    },
+}
+
+/// Field of a game configuration.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "camelCase")]
+pub enum ConfigField {
+    TimePerTurn,
+    TimeCap,
+    TotalTime,
+    Increment,
+    WinLen,
+    Seed,
+    Players,
+    FreePlacement,
+    ByoYomiPeriods,
+}
+
+/// Why a field of a game configuration was rejected.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "camelCase")]
+pub enum ConfigRejectionReason {
+    BelowMinimum,
+    AboveMaximum,
+    /// The value cannot be used at all, e.g. a row longer than the field.
+    Invalid,
+}
+
+impl From<InvalidStateError> for PickRejection {
+    fn from(err: InvalidStateError) -> Self {
+        match err {
+            InvalidStateError::FloatingChip => Self::FloatingChip,
+            InvalidStateError::WrongMoveCount => Self::WrongMoveCount,
+            InvalidStateError::WrongPlayer => Self::WrongPlayer,
+            InvalidStateError::TooManyChips => Self::TooManyChips,
+            InvalidStateError::WrongResult => Self::WrongResult,
+            InvalidStateError::InvalidRules => Self::InvalidConfig,
+            InvalidStateError::WrongFieldSize => Self::WrongFieldSize,
+        }
+    }
+}
+
+impl<'a> From<OutgoingGameSetup<'a>> for OutgoingMessage<'a> {
+    fn from(msg: OutgoingGameSetup<'a>) -> Self {
+        Self::GameSetup(msg)
+    }
+}
+
+/// Contents of `OutgoingMessage::PlayerSelection`.
+#[derive(Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct OutgoingPlayerSelection {
+    pub p1_voted: bool,
+    pub p2_voted: bool,
+    /// Whether each player has voted, in turn order. Only sent in games
+    /// between more than two players.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub voted: Vec<bool>,
+}
+
+impl OutgoingPlayerSelection {
+    /// Returns whether the player has voted.
+    #[must_use]
+    pub fn has_voted(&self, player: game::Player) -> bool {
+        match player {
+            game::Player::P1 => self.p1_voted,
+            game::Player::P2 => self.p2_voted,
+            player => self.voted.get(player.index()).copied().unwrap_or_default(),
+        }
+    }
+}
+
+impl From<OutgoingPlayerSelection> for OutgoingMessage<'_> {
+    fn from(msg: OutgoingPlayerSelection) -> Self {
+        Self::GamePlayerSelection(msg)
+    }
+}
+
+/// Contents of `OutgoingMessage::GameSync`.
+#[derive(Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct OutgoingGameSync<'a> {
+    pub round: u32,
+    pub game: Cow<'a, Game>,
+    /// ISO 8601 timestamp of when the turn will be ended automatically.
+    pub timeout: Option<String>,
+    /// Milliseconds until the turn is ended automatically, measured by the
+    /// server when the message was sent. Unlike `timeout`, this does not
+    /// depend on the clock of the client.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub remaining_ms: Option<u64>,
+    /// Total time left for each player in milliseconds, if the game is
+    /// played with clocks.
+    #[serde(
+        with = "as_millis_optional_list",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    #[cfg_attr(feature = "schema", schemars(with = "Option<Vec<f64>>"))]
+    pub clocks: Option<Vec<Duration>>,
+    /// Byo-yomi periods left for each player, if the game is played with
+    /// `TimerMode::ByoYomi`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub periods: Option<Vec<u8>>,
+    /// Smoothed round trip time of each player in milliseconds, `None` for
+    /// players whose latency has not been measured yet.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub latency: Option<Vec<Option<u32>>>,
+    /// Whether the player whose turn it is may answer the first move with
+    /// `gameSwapSides`.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub can_swap: bool,
+    /// Whether the player whose turn it is can move in each column, see
+    /// `Game::legal_moves`.
+    #[serde(default)]
+    pub legal_moves: Vec<bool>,
+    /// Amount of chips in each column.
+    #[serde(default)]
+    pub filled: Vec<u8>,
+}
+
+impl<'a> OutgoingGameSync<'a> {
+    #[must_use]
+    pub fn new(
+        round: u32,
+        game: &'a Game,
+        timeout: Option<DateTime<Utc>>,
+        clocks: Option<Vec<Duration>>,
+    ) -> Self {
+        Self {
+            round,
+            game: Cow::Borrowed(game),
+            timeout: timeout.map(|t| t.format(ISO_8601_TIMESTAMP).to_string()),
+            remaining_ms: None,
+            clocks,
+            periods: None,
+            latency: None,
+            can_swap: false,
+            legal_moves: game.legal_moves(),
+            filled: game.filled(),
+        }
+    }
+
+    #[must_use]
+    pub const fn can_swap(mut self, can_swap: bool) -> Self {
+        self.can_swap = can_swap;
+        self
+    }
+
+    #[must_use]
+    pub fn periods(mut self, periods: Option<Vec<u8>>) -> Self {
+        self.periods = periods;
+        self
+    }
+
+    #[must_use]
+    pub fn remaining(mut self, remaining: Option<Duration>) -> Self {
+        self.remaining_ms = remaining.map(|r| u64::try_from(r.as_millis()).unwrap_or(u64::MAX));
+        self
+    }
+
+    #[must_use]
+    pub fn latency(mut self, latency: &[Option<Duration>]) -> Self {
+        let millis = |d: Duration| u32::try_from(d.as_millis()).unwrap_or(u32::MAX);
+        if latency.iter().any(Option::is_some) {
+            self.latency = Some(latency.iter().map(|d| d.map(millis)).collect());
+        }
+        self
+    }
+}
+
+impl<'a> From<OutgoingGameSync<'a>> for OutgoingMessage<'a> {
+    fn from(msg: OutgoingGameSync<'a>) -> Self {
+        Self::GameSync(msg)
+    }
+}
+
+/// Contents of `OutgoingMessage::GameMove`.
+#[derive(Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct OutgoingGameMove<'a> {
+    pub round: u32,
+    /// Turn in which the move was made.
+    pub turn: u32,
+    pub player: game::Player,
+    /// `None` if the turn was skipped.
+    pub col: Option<usize>,
+    /// Row the chip was dropped into, popped out of or placed into, `None`
+    /// if the turn was skipped.
+    pub row: Option<usize>,
+    #[serde(default, skip_serializing_if = "MoveKind::is_drop")]
+    pub kind: MoveKind,
+    pub state: Cow<'a, game::GameState>,
+    /// ISO 8601 timestamp of when the turn will be ended automatically.
+    pub timeout: Option<String>,
+    /// Milliseconds until the turn is ended automatically, measured by the
+    /// server when the message was sent. Unlike `timeout`, this does not
+    /// depend on the clock of the client.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub remaining_ms: Option<u64>,
+    /// Total time left for each player in milliseconds, if the game is
+    /// played with clocks.
+    #[serde(
+        with = "as_millis_optional_list",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    #[cfg_attr(feature = "schema", schemars(with = "Option<Vec<f64>>"))]
+    pub clocks: Option<Vec<Duration>>,
+    /// Whether the player whose turn it is may answer the first move with
+    /// `gameSwapSides`.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub can_swap: bool,
+}
+
+impl<'a> OutgoingGameMove<'a> {
+    #[must_use]
+    pub fn new(
+        round: u32,
+        game: &'a Game,
+        timeout: Option<DateTime<Utc>>,
+        clocks: Option<Vec<Duration>>,
+    ) -> Option<Self> {
+        let last = game.moves().last()?;
+        let row = last.col.map(|col| match last.kind {
+            MoveKind::Drop => game.field()[col]
+                .iter()
+                .position(Option::is_some)
+                .unwrap_or_default(),
+            MoveKind::Pop => game.rules().field_size().1 - 1,
+            MoveKind::Place => last.row.unwrap_or_default(),
+        });
+        Some(Self {
+            round,
+            turn: game.state().turn - 1,
+            player: last.player,
+            col: last.col,
+            row,
+            kind: last.kind,
+            state: Cow::Borrowed(game.state()),
+            timeout: timeout.map(|t| t.format(ISO_8601_TIMESTAMP).to_string()),
+            remaining_ms: None,
+            clocks,
+            can_swap: false,
+        })
+    }
+
+    #[must_use]
+    pub const fn can_swap(mut self, can_swap: bool) -> Self {
+        self.can_swap = can_swap;
+        self
+    }
+
+    #[must_use]
+    pub fn remaining(mut self, remaining: Option<Duration>) -> Self {
+        self.remaining_ms = remaining.map(|r| u64::try_from(r.as_millis()).unwrap_or(u64::MAX));
+        self
+    }
+}
+
+impl<'a> From<OutgoingGameMove<'a>> for OutgoingMessage<'a> {
+    fn from(msg: OutgoingGameMove<'a>) -> Self {
+        Self::GameMove(msg)
+    }
+}
+
+/// Updates the status of restart request of the given player.
+#[derive(Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct OutgoingRestartRequest<'a> {
+    /// Player who made the request.
+    pub player: game::Player,
+    /// Restart request details; `None` if it expired.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub req: Option<RestartRequest<'a>>,
+}
+
+impl<'a> From<OutgoingRestartRequest<'a>> for OutgoingMessage<'a> {
+    fn from(msg: OutgoingRestartRequest<'a>) -> Self {
+        Self::GameRestartRequest(msg)
+    }
+}
+
+/// Updates the status of the timing change request of the given player.
+#[derive(Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct OutgoingTimingRequest<'a> {
+    /// Player who made the request.
+    pub player: game::Player,
+    /// Request details; `None` if it was accepted, rejected or expired.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub req: Option<TimingRequest<'a>>,
+}
+
+impl<'a> From<OutgoingTimingRequest<'a>> for OutgoingMessage<'a> {
+    fn from(msg: OutgoingTimingRequest<'a>) -> Self {
+        Self::GameTimingRequest(msg)
+    }
+}
+
+/// Contents of `OutgoingMessage::GameHistory`, sent once the game is over.
+#[derive(Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct OutgoingGameHistory<'a> {
+    pub round: u32,
+    /// Every move made in this round, oldest first.
+    pub moves: Cow<'a, [game::Move]>,
+    /// Comments on the moves, oldest first.
+    #[serde(default, skip_serializing_if = "<[Annotation]>::is_empty")]
+    pub annotations: Cow<'a, [Annotation]>,
+}
+
+impl<'a> From<OutgoingGameHistory<'a>> for OutgoingMessage<'a> {
+    fn from(msg: OutgoingGameHistory<'a>) -> Self {
+        Self::GameHistory(msg)
+    }
+}
+
+/// Restart request made when the game cannot be restarted without asking
+/// the permission of the opponent first.
+#[derive(Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct RestartRequest<'a> {
+    /// Changed configuration, if any.
+    pub config: Option<Cow<'a, GameConfig>>,
+    /// ISO 8601 timestamp of when the restart request will expire.
+    pub timeout: String,
+}
+
+impl<'a> RestartRequest<'a> {
+    #[must_use]
+    pub fn new(config: Option<&'a GameConfig>, timeout: DateTime<Utc>) -> Self {
+        let timeout = timeout.format(ISO_8601_TIMESTAMP).to_string();
+        Self {
+            config: config.map(Cow::Borrowed),
+            timeout,
+        }
+    }
+}
+
+/// Request to change the turn timer of the game in progress, applied from
+/// the turn after the one it is accepted in.
+#[derive(Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct TimingRequest<'a> {
+    /// Configuration the game continues with.
+    pub config: Cow<'a, GameConfig>,
+    /// ISO 8601 timestamp of when the request will expire.
+    pub timeout: String,
+}
+
+impl<'a> TimingRequest<'a> {
+    #[must_use]
+    pub fn new(config: &'a GameConfig, timeout: DateTime<Utc>) -> Self {
+        let timeout = timeout.format(ISO_8601_TIMESTAMP).to_string();
+        Self {
+            config: Cow::Borrowed(config),
+            timeout,
+        }
+    }
+}
+
+/// QR code representation sent over to the client.
+#[derive(Serialize, Deserialize, Clone, Default)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct QR {
+    /// Base64-encoded image, a PNG unless `format` says otherwise.
+    pub img: String,
+    /// The number of modules per side.
+    pub width: usize,
+    #[serde(default)]
+    pub format: QrFormat,
+}
+
+/// Image format of a QR code. Clients connecting with `qr=svg` get SVG
+/// images, everyone else gets PNG images.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default, Debug)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "camelCase")]
+pub enum QrFormat {
+    #[default]
+    Png,
+    Svg,
+}
+
+impl QR {
+    /// Attempts to generate a QR code with specified contents.
+    pub fn generate(contents: &str, format: QrFormat, cfg: &QrConfig) -> Result<Self, QrError> {
+        use base64::{engine::general_purpose, Engine as _};
+        use image::{png::PngEncoder, ColorType, Luma};
+        use qrcode::{render::svg, EcLevel, QrCode};
+
+        let ec_level = match cfg.ec_level {
+            QrEcLevel::Low => EcLevel::L,
+            QrEcLevel::Medium => EcLevel::M,
+            QrEcLevel::Quartile => EcLevel::Q,
+            QrEcLevel::High => EcLevel::H,
+        };
+        let qr = QrCode::with_error_correction_level(contents, ec_level)?;
+
+        let img = match format {
+            QrFormat::Png => {
+                let img_buf = qr
+                    .render::<Luma<u8>>()
+                    .quiet_zone(false)
+                    .max_dimensions(cfg.size, cfg.size)
+                    .build();
+                let mut img = Vec::new();
+                PngEncoder::new(&mut img).encode(
+                    &img_buf,
+                    img_buf.width(),
+                    img_buf.height(),
+                    ColorType::L8,
+                )?;
+                img
+            }
+            QrFormat::Svg => qr
+                .render::<svg::Color>()
+                .quiet_zone(false)
+                .max_dimensions(cfg.size, cfg.size)
+                .build()
+                .into_bytes(),
+        };
+
+        Ok(Self {
+            img: general_purpose::STANDARD.encode(&img),
+            width: qr.width(),
+            format,
+        })
+    }
+}
+
+/// QR codes of an invite in every format. Generated once per lobby ID, so
+/// that sending the invite again does not encode it again.
+#[derive(Default)]
+pub struct QrCodes {
+    pub png: QR,
+    pub svg: QR,
+}
+
+impl QrCodes {
+    /// Generates QR codes of the invite link of the lobby. Codes which could
+    /// not be generated are left empty.
+    #[must_use]
+    pub fn generate(uuid: Uuid, cfg: &AppConfig) -> Self {
+        use qstring::QString;
+        let mut url = cfg.url_base.clone();
+        let lobby = uuid.as_hyphenated().to_string();
+        let query = QString::new(vec![(&cfg.url_lobby_parameter, lobby)]);
+        url.set_query(Some(&query.to_string()));
+
+        let generate = |format| QR::generate(url.as_str(), format, &cfg.qr).unwrap_or_default();
+        Self {
+            png: generate(QrFormat::Png),
+            svg: generate(QrFormat::Svg),
+        }
+    }
+}
+
+// Incoming messages
+
+/// Message sent by a client to the server, tagged with its name in `type`.
+#[derive(Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(tag = "type", rename_all = "camelCase")]
+#[allow(clippy::large_enum_variant)]
+pub enum IncomingMessage {
+    LobbyPickPlayer(IncomingPickPlayer),
+    LobbyPickSpectator(IncomingPickSpectator),
+    LobbyStartSeries(IncomingStartSeries),
+    LobbyPickPlayers(IncomingPickPlayers),
+    LobbyPickPair(IncomingPickPair),
+    GamePlayerSelectionVote(IncomingPlayerSelectionVote),
+    GameEndTurn(IncomingEndTurn),
+    GameRestart(IncomingRestart),
+    GameRestartResponse {
+        accepted: bool,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        seq: Option<u32>,
+    },
+    /// Asks the opponent to change the turn timer without restarting, see
+    /// `Capability::TimingChanges`.
+    GameChangeTiming(IncomingChangeTiming),
+    GameChangeTimingResponse {
+        accepted: bool,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        seq: Option<u32>,
+    },
+    GameChat {
+        text: String,
+    },
+    LobbyChat {
+        text: String,
+    },
+    /// Tells the host whether the player is ready to be picked, see
+    /// `Capability::ReadyStates`.
+    LobbyReady {
+        ready: bool,
+    },
+    /// Disconnects the player with the code from the lobby with `kicked`.
+    /// Players who are blocked are disconnected with `kicked` whenever they
+    /// try to join again, for as long as the lobby is open.
+    LobbyKick {
+        code: u8,
+        #[serde(default)]
+        block: bool,
+    },
+    LobbySetPublic(IncomingSetPublic),
+    /// Replaces the invite, so that the old link stops working.
+    LobbyRegenerateInvite,
+    /// Asks for `lobbyLink` again, e.g. after the host's page was reloaded.
+    LobbyRequestLink,
+    /// Sets the password needed to join, `null` or empty to remove it.
+    LobbySetPassword {
+        password: Option<String>,
+    },
+    /// Sets the configuration picks in the lobby start from, see
+    /// `Capability::LobbyConfig`. Missing fields are set to their default
+    /// value.
+    LobbySetConfig {
+        #[serde(default)]
+        config: PartialGameConfig,
+    },
+    GameQueue {
+        queued: bool,
+    },
+    /// Asks for the complete state of the game, e.g. after a missed
+    /// `GameMove`.
+    GameRequestSync,
+    /// Asks the lobby or game for its complete state, e.g. after restoring
+    /// a suspended tab.
+    RequestFullSync,
+    /// Concedes the game in progress.
+    GameResign {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        seq: Option<u32>,
+    },
+    /// Takes over the first move of the opponent instead of answering it,
+    /// see `GameConfig::pie_rule`. Answered like `gameEndTurn`.
+    GameSwapSides {
+        /// The turn the player wants to swap in, always 1.
+        turn: u32,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        seq: Option<u32>,
+    },
+    /// Comments on the move made in `turn`, see `Capability::Annotations`.
+    GameAnnotate {
+        turn: u32,
+        text: String,
+    },
+    /// Shows the opponent the column the player points at while it is their
+    /// turn, `None` once they stop, see `Capability::Hover`.
+    GameHover {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        col: Option<usize>,
+    },
+    /// Leaves the matchmaking queue, closing the connection.
+    MatchmakingCancel,
+    /// Picks the language of the descriptions sent along with disconnect
+    /// reasons, config rejections and chat notices, as a BCP 47 tag. `None`
+    /// stops sending them.
+    SetLocale {
+        locale: Option<String>,
+    },
+    /// Sets the name and avatar shown to other players.
+    SetProfile {
+        name: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        avatar: Option<String>,
+    },
+    Ping {
+        sent: f64,
+    },
+}
+
+impl IncomingMessage {
+    /// Returns name of the variant used in the `type` property of the
+    /// message.
+    #[must_use]
+    pub fn variant_name(&self) -> &'static str {
+        match self {
+            Self::LobbyPickPlayer(_) => "lobbyPickPlayer",
+            Self::LobbyPickSpectator(_) => "lobbyPickSpectator",
+            Self::LobbyStartSeries(_) => "lobbyStartSeries",
+            Self::LobbyPickPlayers(_) => "lobbyPickPlayers",
+            Self::LobbyPickPair(_) => "lobbyPickPair",
+            Self::GamePlayerSelectionVote(_) => "gamePlayerSelectionVote",
+            Self::GameEndTurn(_) => "gameEndTurn",
+            Self::GameRestart(_) => "gameRestart",
+            Self::GameRestartResponse { .. } => "gameRestartResponse",
+            Self::GameChangeTiming(_) => "gameChangeTiming",
+            Self::GameChangeTimingResponse { .. } => "gameChangeTimingResponse",
+            Self::GameChat { .. } => "gameChat",
+            Self::LobbyChat { .. } => "lobbyChat",
+            Self::LobbyReady { .. } => "lobbyReady",
+            Self::LobbyKick { .. } => "lobbyKick",
+            Self::LobbySetPublic(_) => "lobbySetPublic",
+            Self::LobbyRegenerateInvite => "lobbyRegenerateInvite",
+            Self::LobbyRequestLink => "lobbyRequestLink",
+            Self::LobbySetPassword { .. } => "lobbySetPassword",
+            Self::LobbySetConfig { .. } => "lobbySetConfig",
+            Self::GameQueue { .. } => "gameQueue",
+            Self::GameRequestSync => "gameRequestSync",
+            Self::RequestFullSync => "requestFullSync",
+            Self::GameResign { .. } => "gameResign",
+            Self::GameSwapSides { .. } => "gameSwapSides",
+            Self::GameAnnotate { .. } => "gameAnnotate",
+            Self::GameHover { .. } => "gameHover",
+            Self::MatchmakingCancel => "matchmakingCancel",
+            Self::SetLocale { .. } => "setLocale",
+            Self::SetProfile { .. } => "setProfile",
+            Self::Ping { .. } => "ping",
+        }
+    }
+}
+
+/// Contents of `IncomingMessage::LobbyPickPlayer`.
+#[derive(Message, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "camelCase")]
+#[rtype(result = "()")]
+pub struct IncomingPickPlayer {
+    /// Player's code.
+    pub code: u8,
+    /// Role which should be assigned to the player.
+    pub role: game::Player,
+    /// State of the local game, or `None` if the client is in player selection.
+    pub game: Option<Game>,
+    /// Game configuration, any missing fields will be set to their default value.
+    pub config: PartialGameConfig,
+    pub round: u32,
+    /// In timed games, the extra time each player has in milliseconds.
+    #[serde(
+        with = "as_millis_optional_tuple",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    #[cfg_attr(feature = "schema", schemars(with = "Option<[f64; 2]>"))]
+    pub extra_time: Option<[Duration; 2]>,
+    /// How long the local game has been played in milliseconds, which
+    /// lowers the `extra_time` the players could have saved up.
+    #[serde(
+        with = "as_millis_optional",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    #[cfg_attr(feature = "schema", schemars(with = "Option<f64>"))]
+    pub elapsed: Option<Duration>,
+    /// Whether the loser of every round is replaced by the next player in
+    /// the queue. Everyone else in the lobby is queued when the game starts.
+    #[serde(default)]
+    pub winner_stays: bool,
+}
+
+/// Contents of `IncomingMessage::LobbyPickSpectator`.
+#[derive(Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct IncomingPickSpectator {
+    /// Code of the player who will watch the game once it starts.
+    pub code: u8,
+}
+
+/// Contents of `IncomingMessage::LobbyStartSeries`.
+#[derive(Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct IncomingStartSeries {
+    /// Codes of the players taking part, the host always takes part.
+    pub codes: Vec<u8>,
+    /// Game configuration used by every game of the series.
+    #[serde(default)]
+    pub config: PartialGameConfig,
+    /// Whether games between different players are played at the same
+    /// time, otherwise one game is played at a time.
+    #[serde(default)]
+    pub parallel: bool,
+}
+
+/// Contents of `IncomingMessage::LobbyPickPlayers`.
+#[derive(Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct IncomingPickPlayers {
+    /// Codes of the players joining the host, in turn order. The host is
+    /// always player 0.
+    pub codes: Vec<u8>,
+    /// Game configuration, `players` must match the amount of players
+    /// including the host.
+    pub config: PartialGameConfig,
+}
+
+/// Contents of `IncomingMessage::LobbyPickPair`.
+#[derive(Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct IncomingPickPair {
+    /// Codes of player 1 and player 2, the host spectates.
+    pub codes: [u8; 2],
+    /// Game configuration, any missing fields will be set to their default value.
+    #[serde(default)]
+    pub config: PartialGameConfig,
+}
+
+/// Contents of `IncomingMessage::LobbySetPublic`.
+#[derive(Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct IncomingSetPublic {
+    /// Whether the lobby should be listed publicly.
+    pub public: bool,
+    /// Game configuration the host intends to use, shown in the listing.
+    #[serde(default)]
+    pub config: PartialGameConfig,
+}
+
+/// Contents of `IncomingMessage::GamePlayerSelectionVote`.
+#[derive(Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct IncomingPlayerSelectionVote {
+    pub wants_to_start: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seq: Option<u32>,
+}
+
+/// Contents of `IncomingMessage::GameEndTurn`.
+#[derive(Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct IncomingEndTurn {
+    /// The turn the player wants to end.
+    pub turn: u32,
+    /// Move the player wants to make, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub col: Option<usize>,
+    /// Whether to drop a chip into `col`, to pop one out of it or to place
+    /// one into the cell at `row`.
+    #[serde(default, skip_serializing_if = "MoveKind::is_drop")]
+    pub kind: MoveKind,
+    /// Row of the cell to place a chip into, needed when `kind` is `place`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub row: Option<usize>,
+    /// Number of the message, see `Capability::SequenceNumbers`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seq: Option<u32>,
+}
+
+/// Contents of `IncomingMessage::GameRestart`.
+#[derive(Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct IncomingRestart {
+    /// Changes to the configuration, if any.
+    #[serde(flatten)]
+    pub partial: Option<PartialGameConfig>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seq: Option<u32>,
+}
+
+/// Contents of `IncomingMessage::GameChangeTiming`. Missing fields keep
+/// their current value.
+#[derive(Serialize, Deserialize, Default)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct IncomingChangeTiming {
+    #[serde(
+        with = "as_millis_optional",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    #[cfg_attr(feature = "schema", schemars(with = "Option<f64>"))]
+    pub time_per_turn: Option<Duration>,
+    #[serde(
+        with = "as_millis_optional",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    #[cfg_attr(feature = "schema", schemars(with = "Option<f64>"))]
+    pub time_cap: Option<Duration>,
+    #[serde(
+        with = "as_millis_optional",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    #[cfg_attr(feature = "schema", schemars(with = "Option<f64>"))]
+    pub increment: Option<Duration>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seq: Option<u32>,
+}
+
+impl IncomingChangeTiming {
+    /// Returns the configuration with the changed fields replaced.
+    #[must_use]
+    pub fn apply(&self, config: &GameConfig) -> GameConfig {
+        let mut config = config.clone();
+        config.time_per_turn = self.time_per_turn.unwrap_or(config.time_per_turn);
+        config.time_cap = self.time_cap.unwrap_or(config.time_cap);
+        config.increment = self.increment.unwrap_or(config.increment);
+        config
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn close_codes_are_unique_and_in_application_range() {
+        use strum::IntoEnumIterator;
+        let reasons: Vec<_> = Disconnect::iter().collect();
+        let codes: std::collections::HashSet<_> = reasons.iter().copied().map(close_code).collect();
+        assert_eq!(codes.len(), reasons.len());
+        assert!(codes
+            .iter()
+            .all(|code| (CLOSE_CODE_BASE..5000).contains(code)));
+    }
+
+    #[test]
+    fn negotiate_picks_newest_common_version() {
+        assert_eq!(ProtocolVersion::negotiate("1"), Some(ProtocolVersion::V1));
+        assert_eq!(ProtocolVersion::negotiate("1,2"), Some(ProtocolVersion::V2));
+        assert_eq!(
+            ProtocolVersion::negotiate("2, 1, 9"),
+            Some(ProtocolVersion::V2)
+        );
+        assert_eq!(ProtocolVersion::negotiate("0,x,3"), None);
+        assert_eq!(ProtocolVersion::negotiate(""), None);
+    }
+
+    #[test]
+    fn joins_messages_into_batch() {
+        let messages = [
+            serde_json::to_string(&OutgoingMessage::GameQueue { position: Some(2) }).unwrap(),
+            serde_json::to_string(&OutgoingMessage::lobby_chat(None, "hi", None)).unwrap(),
+        ];
+        let text = batch(messages.iter().map(String::as_str));
+        let OutgoingMessage::Batch { messages } = serde_json::from_str(&text).unwrap() else {
+            panic!("expected a batch");
+        };
+        assert!(matches!(
+            messages[..],
+            [
+                OutgoingMessage::GameQueue { position: Some(2) },
+                OutgoingMessage::LobbyChat { .. }
+            ]
+        ));
+    }
+
+    #[test]
+    fn negotiate_picks_newest_subprotocol() {
+        let offered = [
+            "chat",
+            "connect-four.v1.json",
+            "connect-four.v2.msgpack",
+            "connect-four.v2.json",
+            "connect-four.v9.json",
+        ];
+        let subprotocol = Subprotocol::negotiate(offered).unwrap();
+        assert_eq!(subprotocol.version, ProtocolVersion::V2);
+        assert_eq!(subprotocol.encoding, Encoding::MessagePack);
+        assert_eq!(subprotocol.to_string(), "connect-four.v2.msgpack");
+
+        assert_eq!(Subprotocol::negotiate(["connect-four.v2"]), None);
+        assert_eq!(Subprotocol::negotiate(["connect-four.v2.xml"]), None);
+        assert_eq!(Subprotocol::negotiate([]), None);
+    }
+
+    fn round_trip<T: Serialize + serde::de::DeserializeOwned>(msg: &T) {
+        let json = serde_json::to_value(msg).unwrap();
+        let parsed: T = serde_json::from_value(json.clone()).unwrap();
+        assert_eq!(serde_json::to_value(parsed).unwrap(), json);
+    }
+
+    #[test]
+    fn game_move_turn_of_resumed_game() {
+        let mut played = Game::default();
+        played.end_turn(Some(3)).unwrap();
+        played.end_turn(Some(4)).unwrap();
+        let mut game: Game =
+            serde_json::from_value(serde_json::to_value(&played).unwrap()).unwrap();
+        game.end_turn(Some(3)).unwrap();
+
+        let msg = OutgoingGameMove::new(1, &game, None, None).unwrap();
+        assert_eq!(msg.turn, 2);
+        assert_eq!(msg.row, Some(game::FIELD_SIZE - 2));
+    }
+
+    #[test]
+    fn serializes_version_1_messages_in_their_old_form() {
+        let game = Game::default();
+        let config = GameConfig::default();
+        let clocks = Some(vec![Duration::from_secs(3), Duration::from_secs(4)]);
+        let link = OutgoingLobbyLink::new(Uuid::nil(), "ABCD", "host", &QR::default());
+        let outgoing = [
+            link.into(),
+            OutgoingMessage::LobbySync {
+                players: Cow::Borrowed(&[3, 7]),
+                spectators: Cow::Borrowed(&[7]),
+                ready: Cow::Borrowed(&[3]),
+                profiles: Cow::Owned(HashMap::new()),
+                config: None,
+            },
+            OutgoingMessage::LobbyCode { code: 3 },
+            OutgoingGameSetup::new(Some(&config), Some(game::Player::P2.into()))
+                .reconnect_token("token")
+                .into(),
+            OutgoingGameSetup::new(Some(&config), Some(Role::Spectator)).into(),
+            OutgoingMessage::game_player_selection(&[true, false]),
+            OutgoingMessage::game_sync(1, &game, None, clocks),
+            OutgoingMessage::game_restart_request(game::Player::P1, None),
+            OutgoingMessage::Pong {
+                sent: 1.0,
+                received: String::from("now"),
+            },
+        ];
+
+        let v1: Vec<_> = outgoing
+            .iter()
+            .inspect(|msg| assert_eq!(msg.min_version(), ProtocolVersion::V1))
+            .map(|msg| match msg.to_v1() {
+                Some(v1) => serde_json::to_value(v1).unwrap(),
+                None => serde_json::to_value(msg).unwrap(),
+            })
+            .collect();
+        let config = serde_json::to_value(&config).unwrap();
+        assert_eq!(
+            v1[0],
+            json!({
+                "type": "lobbyLink",
+                "lobby": "00000000-0000-0000-0000-000000000000",
+                "qrCode": { "img": "", "width": 0, "format": "png" },
+            })
+        );
+        assert_eq!(v1[1], json!({ "type": "lobbySync", "players": [3, 7] }));
+        assert_eq!(v1[2], json!({ "type": "lobbyCode", "code": 3 }));
+        assert_eq!(
+            v1[3],
+            json!({ "type": "gameSetup", "config": config, "role": 1 })
+        );
+        assert_eq!(v1[4], json!({ "type": "gameSetup", "config": config }));
+        assert_eq!(
+            v1[5],
+            json!({ "type": "gamePlayerSelection", "p1Voted": true, "p2Voted": false })
+        );
+        assert_eq!(
+            v1[6],
+            json!({
+                "type": "gameSync",
+                "round": 1,
+                "game": serde_json::to_value(&game).unwrap(),
+                "timeout": null,
+            })
+        );
+        assert_eq!(v1[7], json!({ "type": "gameRestartRequest", "player": 0 }));
+        assert_eq!(
+            v1[8],
+            json!({ "type": "pong", "sent": 1.0, "received": "now" })
+        );
+    }
+
+    #[test]
+    fn messages_round_trip() {
+        let mut game = Game::default();
+        game.end_turn(Some(3)).unwrap();
+        game.end_turn(None).unwrap();
+        let config = GameConfig::default();
+        let profile = Profile {
+            name: String::from("Ann"),
+            avatar: None,
+        };
+
+        let setup = OutgoingGameSetup::new(Some(&config), Some(Role::Spectator))
+            .reconnect_token("token")
+            .viewer_token(Some("viewer"))
+            .profiles([Some(&profile), None]);
+        let clocks = Some(vec![Duration::from_secs(3), Duration::from_millis(1500)]);
+        let outgoing = [
+            OutgoingMessage::game_setup(Some(&config), Some(game::Player::P2.into())),
+            setup.into(),
+            OutgoingMessage::game_sync(1, &game, Some(Utc::now()), clocks),
+            OutgoingMessage::game_move(1, &game, None, None).unwrap(),
+            OutgoingGameSync::new(1, &game, None, None)
+                .remaining(Some(Duration::from_millis(2500)))
+                .into(),
+            OutgoingMessage::game_history(1, game.moves(), &[]),
+            OutgoingMessage::game_timing_request(
+                game::Player::P1,
+                Some(TimingRequest::new(&config, Utc::now())),
+            ),
+            OutgoingMessage::lobby_chat(None, "hi", Some(&profile)),
+            OutgoingMessage::LobbySync {
+                players: Cow::Borrowed(&[3, 7]),
+                spectators: Cow::Borrowed(&[7]),
+                ready: Cow::Borrowed(&[3]),
+                profiles: Cow::Owned(HashMap::new()),
+                config: Some(Cow::Borrowed(&config)),
+            },
+            OutgoingMessage::GameInProgress {
+                options: Cow::Borrowed(&[LateJoinOption::Spectate, LateJoinOption::Queue]),
+            },
+            OutgoingMessage::OpponentHover {
+                round: 1,
+                turn: 4,
+                col: Some(3),
+            },
+            OutgoingMessage::Closing {
+                reason: Cow::Borrowed("kicked"),
+                code: close_code(Disconnect::Kicked),
+                description: Cow::Borrowed("The host removed you from the lobby."),
+            },
+        ];
+        for msg in &outgoing {
+            round_trip(msg);
+        }
+
+        let incoming = [
+            r#"{"type":"lobbyPickPlayer","code":7,"role":1,"game":null,"config":{},"round":0}"#,
+            r#"{"type":"gameEndTurn","turn":2,"col":3,"kind":"pop"}"#,
+            r#"{"type":"gameEndTurn","turn":2,"seq":5}"#,
+            r#"{"type":"gameResign"}"#,
+            r#"{"type":"gameSwapSides","turn":1,"seq":3}"#,
+            r#"{"type":"gameAnnotate","turn":4,"text":"Blocks the diagonal"}"#,
+            r#"{"type":"gameHover","col":3}"#,
+            r#"{"type":"gameHover"}"#,
+            r#"{"type":"gameRestart","timePerTurn":15000}"#,
+            r#"{"type":"gameChangeTiming","timePerTurn":20000,"seq":4}"#,
+            r#"{"type":"gameChangeTimingResponse","accepted":true}"#,
+            r#"{"type":"lobbyRegenerateInvite"}"#,
+            r#"{"type":"lobbyRequestLink"}"#,
+            r#"{"type":"lobbyReady","ready":true}"#,
+            r#"{"type":"lobbyKick","code":7,"block":true}"#,
+            r#"{"type":"lobbySetConfig","config":{"timePerTurn":30000,"allowDraws":true}}"#,
+            r#"{"type":"lobbyStartSeries","codes":[4,9],"config":{},"parallel":true}"#,
+            r#"{"type":"setProfile","name":"Ann"}"#,
+        ];
+        for text in incoming {
+            let msg: IncomingMessage = serde_json::from_str(text).unwrap();
+            round_trip(&msg);
+        }
+    }
+
+    #[test]
+    fn qr_codes_in_every_format() {
+        use base64::{engine::general_purpose, Engine as _};
+
+        let cfg = QrConfig {
+            ec_level: QrEcLevel::High,
+            size: 100,
+        };
+        let png = QR::generate("https://example.com", QrFormat::Png, &cfg).unwrap();
+        let svg = QR::generate("https://example.com", QrFormat::Svg, &cfg).unwrap();
+        assert_eq!(png.width, svg.width);
+        assert_eq!(svg.format, QrFormat::Svg);
+        let width = 100 / svg.width * svg.width;
+
+        let png = general_purpose::STANDARD.decode(png.img).unwrap();
+        assert!(png.starts_with(b"\x89PNG"));
+        let svg = general_purpose::STANDARD.decode(svg.img).unwrap();
+        let svg = String::from_utf8(svg).unwrap();
+        assert!(svg.contains(&format!("width=\"{width}\"")));
+    }
+}
@@ -0,0 +1,78 @@
+use std::time::Instant;
+
+/// Token bucket which refills continuously at a fixed rate.
+pub struct TokenBucket {
+    /// Tokens added every second, 0 to disable the limit.
+    rate: f64,
+    capacity: f64,
+    tokens: f64,
+    updated: Instant,
+}
+
+impl TokenBucket {
+    /// Creates a full bucket.
+    #[must_use]
+    pub fn new(rate: f64, capacity: f64) -> Self {
+        Self {
+            rate,
+            capacity,
+            tokens: capacity,
+            updated: Instant::now(),
+        }
+    }
+
+    /// Takes a token if there is one left. Always succeeds if the limit is
+    /// disabled.
+    pub fn try_take(&mut self) -> bool {
+        if self.rate <= 0.0 {
+            return true;
+        }
+
+        let now = Instant::now();
+        let refill = now.duration_since(self.updated).as_secs_f64() * self.rate;
+        self.tokens = (self.tokens + refill).min(self.capacity);
+        self.updated = now;
+
+        if self.tokens < 1.0 {
+            return false;
+        }
+        self.tokens -= 1.0;
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn allows_bursts_up_to_capacity() {
+        let mut bucket = TokenBucket::new(1.0, 3.0);
+        assert!((0..3).all(|_| bucket.try_take()));
+        assert!(!bucket.try_take());
+    }
+
+    #[test]
+    fn refills_at_the_rate() {
+        let mut bucket = TokenBucket::new(2.0, 3.0);
+        while bucket.try_take() {}
+
+        bucket.updated -= Duration::from_secs(1);
+        assert!(bucket.try_take());
+        assert!(bucket.try_take());
+        assert!(!bucket.try_take());
+
+        // Never refills above the capacity
+        bucket.updated -= Duration::from_secs(10);
+        assert!((0..3).all(|_| bucket.try_take()));
+        assert!(!bucket.try_take());
+    }
+
+    #[test]
+    fn zero_rate_disables_the_limit() {
+        let mut bucket = TokenBucket::new(0.0, 0.0);
+        assert!((0..100).all(|_| bucket.try_take()));
+    }
+}
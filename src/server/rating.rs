@@ -0,0 +1,212 @@
+use std::{
+    collections::HashMap,
+    fs, io,
+    path::{Path, PathBuf},
+    sync::{Mutex, PoisonError},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::game::{GameWinner, Player};
+
+/// Rating of a player who has not finished any games yet.
+pub const INITIAL_RATING: f64 = 1500.0;
+/// The most a rating can change after a single game.
+const K_FACTOR: f64 = 32.0;
+/// Longest player ID accepted from clients.
+pub const PLAYER_ID_MAX_LENGTH: usize = 64;
+
+#[derive(Clone, Copy, PartialEq, Serialize, Deserialize, Debug)]
+pub struct Rating {
+    pub rating: f64,
+    /// Amount of rated games the player has finished.
+    pub games: u32,
+}
+
+impl Default for Rating {
+    fn default() -> Self {
+        Self {
+            rating: INITIAL_RATING,
+            games: 0,
+        }
+    }
+}
+
+/// Rating of a player after a game and how much it changed.
+#[derive(Clone, Copy, Debug)]
+pub struct RatingChange {
+    pub rating: Rating,
+    pub change: f64,
+}
+
+/// Returns true if clients can use the ID to identify themselves: 1 to
+/// `PLAYER_ID_MAX_LENGTH` ASCII letters, digits, `-` or `_`.
+#[must_use]
+pub fn is_valid_player_id(id: &str) -> bool {
+    !id.is_empty()
+        && id.len() <= PLAYER_ID_MAX_LENGTH
+        && id
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_')
+}
+
+/// Returns how much the rating of a player changes after a game against
+/// the opponent. `score` is 1 for a win, 0.5 for a draw and 0 for a loss.
+#[must_use]
+pub fn elo_delta(rating: f64, opponent: f64, score: f64) -> f64 {
+    let expected = 1.0 / (1.0 + 10_f64.powf((opponent - rating) / 400.0));
+    K_FACTOR * (score - expected)
+}
+
+/// Updates the ratings of every player after a game, in the order of
+/// `players`. Each player is rated as if they played every other one: the
+/// winner beat everyone else, and the others drew with each other. The
+/// changes against each opponent are averaged.
+fn record_game(
+    ratings: &mut HashMap<String, Rating>,
+    players: &[&str],
+    winner: GameWinner,
+) -> Vec<RatingChange> {
+    let before: Vec<_> = players
+        .iter()
+        .map(|id| ratings.get(*id).copied().unwrap_or_default())
+        .collect();
+    let winner = winner.player().map(Player::index);
+    let score = |i: usize, j: usize| match winner {
+        Some(w) if w == i => 1.0,
+        Some(w) if w == j => 0.0,
+        _ => 0.5,
+    };
+    let opponents = players.len().saturating_sub(1).max(1) as f64;
+    let changes: Vec<_> = before
+        .iter()
+        .enumerate()
+        .map(|(i, own)| {
+            let change = before
+                .iter()
+                .enumerate()
+                .filter(|(j, _)| *j != i)
+                .map(|(j, other)| elo_delta(own.rating, other.rating, score(i, j)))
+                .sum::<f64>()
+                / opponents;
+            let rating = Rating {
+                rating: own.rating + change,
+                games: own.games + 1,
+            };
+            RatingChange { rating, change }
+        })
+        .collect();
+    for (id, change) in players.iter().zip(&changes) {
+        ratings.insert(String::from(*id), change.rating);
+    }
+    changes
+}
+
+/// Somewhere ratings are kept. Implementations are called from a blocking
+/// thread, so they are free to do IO.
+pub trait RatingStore: Send + Sync {
+    fn get(&self, id: &str) -> Option<Rating>;
+
+    /// Updates the ratings of every player after a game, player 1 first.
+    fn record(&self, players: &[&str], winner: GameWinner) -> io::Result<Vec<RatingChange>>;
+}
+
+/// Keeps ratings until the server stops.
+#[derive(Default)]
+pub struct MemoryRatingStore {
+    ratings: Mutex<HashMap<String, Rating>>,
+}
+
+impl RatingStore for MemoryRatingStore {
+    fn get(&self, id: &str) -> Option<Rating> {
+        let ratings = self.ratings.lock().unwrap_or_else(PoisonError::into_inner);
+        ratings.get(id).copied()
+    }
+
+    fn record(&self, players: &[&str], winner: GameWinner) -> io::Result<Vec<RatingChange>> {
+        let mut ratings = self.ratings.lock().unwrap_or_else(PoisonError::into_inner);
+        Ok(record_game(&mut ratings, players, winner))
+    }
+}
+
+/// Keeps ratings in memory and writes all of them to a JSON file after
+/// every game.
+pub struct FileRatingStore {
+    path: PathBuf,
+    ratings: Mutex<HashMap<String, Rating>>,
+}
+
+impl FileRatingStore {
+    /// Reads the ratings from `path`, starting with none if the file does
+    /// not exist yet.
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let ratings = match fs::read(path) {
+            Ok(contents) => serde_json::from_slice(&contents)?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => HashMap::new(),
+            Err(e) => return Err(e),
+        };
+        Ok(Self {
+            path: path.to_path_buf(),
+            ratings: Mutex::new(ratings),
+        })
+    }
+}
+
+impl RatingStore for FileRatingStore {
+    fn get(&self, id: &str) -> Option<Rating> {
+        let ratings = self.ratings.lock().unwrap_or_else(PoisonError::into_inner);
+        ratings.get(id).copied()
+    }
+
+    fn record(&self, players: &[&str], winner: GameWinner) -> io::Result<Vec<RatingChange>> {
+        let mut ratings = self.ratings.lock().unwrap_or_else(PoisonError::into_inner);
+        let changes = record_game(&mut ratings, players, winner);
+
+        // Write to a temporary file first, so that the ratings are not lost
+        // if the server stops halfway through
+        let contents = serde_json::to_vec(&*ratings)?;
+        let tmp = self.path.with_extension("tmp");
+        fs::write(&tmp, contents)?;
+        fs::rename(&tmp, &self.path)?;
+        Ok(changes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn file_store_keeps_ratings() {
+        let path = std::env::temp_dir().join(format!("c4-ratings-{}.json", std::process::id()));
+        let store = FileRatingStore::open(&path).unwrap();
+        assert_eq!(store.get("alice"), None);
+
+        let changes = store.record(&["alice", "bob"], GameWinner::P1).unwrap();
+        let [alice, bob] = changes[..] else {
+            panic!("expected two changes");
+        };
+        assert!((alice.change - K_FACTOR / 2.0).abs() < 1e-9);
+        assert!((alice.change + bob.change).abs() < 1e-9);
+        let changes = store.record(&["bob", "alice"], GameWinner::Draw).unwrap();
+        let [bob, alice] = changes[..] else {
+            panic!("expected two changes");
+        };
+        assert!(bob.change > 0.0 && alice.change < 0.0);
+        assert_eq!(alice.rating.games, 2);
+
+        // The winner gains against both others, who draw with each other
+        let changes = store
+            .record(&["carol", "dave", "erin"], GameWinner::P2)
+            .unwrap();
+        assert!(changes[1].change > 0.0);
+        assert!(changes[0].change < 0.0 && changes[2].change < 0.0);
+        assert!((changes[1].change - K_FACTOR / 2.0).abs() < 1e-9);
+
+        let reopened = FileRatingStore::open(&path).unwrap();
+        assert_eq!(reopened.get("alice"), Some(alice.rating));
+        assert_eq!(reopened.get("bob"), Some(bob.rating));
+
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,193 @@
+use std::{
+    fs::{self, File},
+    io::{self, BufRead, BufReader, BufWriter, Write},
+    path::Path,
+    time::Instant,
+};
+
+use chrono::Utc;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tracing::error;
+
+/// Replaces the value of any redacted property.
+pub const REDACTED: &str = "[redacted]";
+
+/// Message properties which contain secrets and must never be written to
+/// a recording.
+const REDACTED_KEYS: &[&str] = &[
+    "lobby",
+    "joinCode",
+    "qrCode",
+    "reconnectToken",
+    "hostToken",
+    "viewerToken",
+    "password",
+];
+
+const FILE_NAME_TIMESTAMP: &str = "%Y%m%dT%H%M%S";
+
+/// Direction of a recorded frame, as seen from the server.
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub enum Direction {
+    Inbound,
+    Outbound,
+}
+
+/// A single line of a session recording.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(tag = "kind", rename_all = "camelCase")]
+pub enum RecordingEntry {
+    /// Always the first line of a recording.
+    #[serde(rename_all = "camelCase")]
+    Header {
+        /// ISO 8601 timestamp of when the connection was established.
+        started: String,
+        /// Connection query string, with the lobby parameter redacted.
+        query: String,
+    },
+    #[serde(rename_all = "camelCase")]
+    Frame {
+        elapsed_ms: u64,
+        dir: Direction,
+        data: String,
+    },
+    #[serde(rename_all = "camelCase")]
+    Close {
+        elapsed_ms: u64,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        reason: Option<String>,
+    },
+}
+
+/// Writes every frame of a single connection to a file.
+pub struct SessionRecorder {
+    writer: BufWriter<File>,
+    started: Instant,
+}
+
+impl SessionRecorder {
+    /// Creates a new recording file inside `dir`. `query` should already have
+    /// any secrets removed.
+    pub fn create(dir: &Path, query: &str) -> io::Result<Self> {
+        fs::create_dir_all(dir)?;
+        let timestamp = Utc::now();
+        let suffix: u32 = rand::thread_rng().gen();
+        let name = format!(
+            "{}-{suffix:08x}.jsonl",
+            timestamp.format(FILE_NAME_TIMESTAMP)
+        );
+        let file = File::create(dir.join(name))?;
+
+        let mut recorder = Self {
+            writer: BufWriter::new(file),
+            started: Instant::now(),
+        };
+        recorder.write(&RecordingEntry::Header {
+            started: timestamp.to_rfc3339(),
+            query: String::from(query),
+        });
+        Ok(recorder)
+    }
+
+    /// Records a text frame, redacting any secrets it contains.
+    pub fn record(&mut self, dir: Direction, data: &str) {
+        let entry = RecordingEntry::Frame {
+            elapsed_ms: self.elapsed_ms(),
+            dir,
+            data: redact(data),
+        };
+        self.write(&entry);
+    }
+
+    /// Records the end of the connection and flushes the file.
+    pub fn record_close(&mut self, reason: Option<&str>) {
+        let entry = RecordingEntry::Close {
+            elapsed_ms: self.elapsed_ms(),
+            reason: reason.map(String::from),
+        };
+        self.write(&entry);
+        if let Err(e) = self.writer.flush() {
+            error!("Failed to flush session recording: {e}");
+        }
+    }
+
+    fn elapsed_ms(&self) -> u64 {
+        self.started.elapsed().as_millis() as u64
+    }
+
+    fn write(&mut self, entry: &RecordingEntry) {
+        let res = serde_json::to_writer(&mut self.writer, entry)
+            .map_err(io::Error::from)
+            .and_then(|()| self.writer.write_all(b"\n"));
+        if let Err(e) = res {
+            error!("Failed to write session recording: {e}");
+        }
+    }
+}
+
+/// Reads all entries of a recording.
+pub fn read_recording(path: &Path) -> io::Result<Vec<RecordingEntry>> {
+    let reader = BufReader::new(File::open(path)?);
+    let mut entries = Vec::new();
+    for line in reader.lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        entries.push(serde_json::from_str(&line)?);
+    }
+    Ok(entries)
+}
+
+/// Replaces the values of secret properties in a JSON message. Text which is
+/// not valid JSON is returned unchanged.
+#[must_use]
+pub fn redact(text: &str) -> String {
+    fn redact_value(value: &mut Value) {
+        match value {
+            Value::Object(map) => {
+                for (key, value) in map.iter_mut() {
+                    if REDACTED_KEYS.contains(&key.as_str()) {
+                        *value = Value::String(String::from(REDACTED));
+                    } else {
+                        redact_value(value);
+                    }
+                }
+            }
+            Value::Array(values) => values.iter_mut().for_each(redact_value),
+            _ => (),
+        }
+    }
+
+    let Ok(mut value) = serde_json::from_str::<Value>(text) else {
+        return String::from(text);
+    };
+    redact_value(&mut value);
+    value.to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn redact_lobby_link() {
+        let msg = r#"{"type":"lobbyLink","lobby":"abc","joinCode":"K7M2QX","qrCode":{"img":"x","width":1}}"#;
+        let redacted: Value = serde_json::from_str(&redact(msg)).unwrap();
+        assert_eq!(redacted["type"], "lobbyLink");
+        assert_eq!(redacted["lobby"], REDACTED);
+        assert_eq!(redacted["joinCode"], REDACTED);
+        assert_eq!(redacted["qrCode"], REDACTED);
+    }
+
+    #[test]
+    fn redact_leaves_other_messages() {
+        let msg = r#"{"type":"ping","sent":1.5}"#;
+        let redacted: Value = serde_json::from_str(&redact(msg)).unwrap();
+        assert_eq!(redacted, serde_json::from_str::<Value>(msg).unwrap());
+        assert_eq!(redact("not json"), "not json");
+    }
+}
@@ -0,0 +1,117 @@
+//! Settings which can change while the server runs. They are read again on
+//! SIGHUP or through `POST /admin/reload`, and handed to the running actors
+//! without dropping any connections. Only the settings taken by
+//! `AppConfig::apply_reloadable` change, the rest needs a restart.
+
+use std::sync::{Arc, OnceLock};
+
+use actix::Message;
+use futures_util::{stream, Stream};
+use tokio::sync::watch;
+
+use crate::server::config::AppConfigError;
+use crate::server::AppConfig;
+
+/// Reads the settings from their sources, e.g. the configuration file, the
+/// environment and the command line.
+pub type ConfigLoader = Box<dyn Fn() -> Result<AppConfig, AppConfigError> + Send + Sync>;
+
+/// Sent to actors once the settings have been reloaded.
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct ReloadConfig(pub Arc<AppConfig>);
+
+#[derive(Debug, thiserror::Error)]
+pub enum ReloadError {
+    #[error("the settings were not loaded from anywhere")]
+    NoLoader,
+    #[error(transparent)]
+    Config(#[from] AppConfigError),
+}
+
+/// The current settings, which actors can subscribe to.
+pub struct LiveConfig {
+    current: watch::Sender<Arc<AppConfig>>,
+    loader: OnceLock<ConfigLoader>,
+}
+
+impl LiveConfig {
+    #[must_use]
+    pub fn new(cfg: Arc<AppConfig>) -> Self {
+        Self {
+            current: watch::Sender::new(cfg),
+            loader: OnceLock::new(),
+        }
+    }
+
+    /// Sets how the settings are read again by `reload`. Returns `false` if
+    /// a loader has already been set.
+    pub fn set_loader(&self, loader: ConfigLoader) -> bool {
+        self.loader.set(loader).is_ok()
+    }
+
+    #[must_use]
+    pub fn current(&self) -> Arc<AppConfig> {
+        Arc::clone(&self.current.borrow())
+    }
+
+    /// Returns a receiver notified whenever the settings change.
+    #[must_use]
+    pub fn subscribe(&self) -> watch::Receiver<Arc<AppConfig>> {
+        self.current.subscribe()
+    }
+
+    /// Takes the reloadable settings from `new` and notifies the
+    /// subscribers. Returns the updated settings.
+    pub fn apply(&self, new: &AppConfig) -> Arc<AppConfig> {
+        let mut cfg = AppConfig::clone(&self.current());
+        cfg.apply_reloadable(new);
+        let cfg = Arc::new(cfg);
+        self.current.send_replace(Arc::clone(&cfg));
+        cfg
+    }
+
+    /// Reads the settings again with the loader and applies them.
+    pub fn reload(&self) -> Result<Arc<AppConfig>, ReloadError> {
+        let loader = self.loader.get().ok_or(ReloadError::NoLoader)?;
+        Ok(self.apply(&loader()?))
+    }
+}
+
+/// Turns the changes seen by the receiver into messages for an actor, to be
+/// added with `AsyncContext::add_message_stream`.
+pub fn updates(receiver: watch::Receiver<Arc<AppConfig>>) -> impl Stream<Item = ReloadConfig> {
+    stream::unfold(receiver, |mut receiver| async move {
+        receiver.changed().await.ok()?;
+        let cfg = Arc::clone(&receiver.borrow_and_update());
+        Some((ReloadConfig(cfg), receiver))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn applies_reloadable_settings_only() {
+        let live = LiveConfig::new(Arc::new(AppConfig::default()));
+        let mut receiver = live.subscribe();
+        let new = AppConfig {
+            max_lobbies: 3,
+            heartbeat_timeout: Duration::from_secs(1),
+            chat_max_length: 1,
+            ..AppConfig::default()
+        };
+        assert!(matches!(live.reload(), Err(ReloadError::NoLoader)));
+
+        live.apply(&new);
+        assert!(receiver.has_changed().unwrap());
+        let cfg = receiver.borrow_and_update();
+        assert_eq!(cfg.max_lobbies, 3);
+        assert_eq!(cfg.heartbeat_timeout, Duration::from_secs(1));
+        assert_eq!(cfg.chat_max_length, AppConfig::default().chat_max_length);
+        assert_eq!(live.current().max_lobbies, 3);
+    }
+}
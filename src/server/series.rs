@@ -0,0 +1,316 @@
+//! Pairings and standings of an all-play-all series between lobby members.
+
+use std::collections::VecDeque;
+
+use serde::{Deserialize, Serialize};
+
+use crate::game::GameWinner;
+
+/// Points awarded for winning a game of the series.
+pub const WIN_POINTS: u32 = 2;
+/// Points awarded to both players for a draw.
+pub const DRAW_POINTS: u32 = 1;
+
+/// A game of the series, `p1` and `p2` are indices of the participants.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Pairing {
+    pub p1: usize,
+    pub p2: usize,
+}
+
+impl Pairing {
+    fn includes(self, participant: usize) -> bool {
+        self.p1 == participant || self.p2 == participant
+    }
+}
+
+/// Results of a participant of the series.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct Standing {
+    /// Code of the player in the lobby when the series started, `None` for
+    /// the host.
+    pub code: Option<u8>,
+    pub played: u32,
+    pub wins: u32,
+    pub draws: u32,
+    pub losses: u32,
+    pub points: u32,
+    /// Whether the player left, forfeiting their remaining games.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub withdrawn: bool,
+}
+
+/// Schedule and points table of an all-play-all series, where every
+/// participant plays every other participant once.
+pub struct Series {
+    standings: Vec<Standing>,
+    /// Pairings which have not started yet, in the order they are played.
+    pending: VecDeque<Pairing>,
+    playing: Vec<Pairing>,
+    /// Whether games between different participants are played at the same
+    /// time, otherwise only one game is played at a time.
+    parallel: bool,
+}
+
+impl Series {
+    /// Schedules the series between players with the codes, `None` being
+    /// the host.
+    #[must_use]
+    pub fn new(codes: &[Option<u8>], parallel: bool) -> Self {
+        let standings = codes
+            .iter()
+            .map(|&code| Standing {
+                code,
+                played: 0,
+                wins: 0,
+                draws: 0,
+                losses: 0,
+                points: 0,
+                withdrawn: false,
+            })
+            .collect();
+        Self {
+            standings,
+            pending: schedule(codes.len()).into(),
+            playing: Vec::new(),
+            parallel,
+        }
+    }
+
+    /// Returns the pairings which can be started now and marks them as
+    /// being played.
+    pub fn next_pairings(&mut self) -> Vec<Pairing> {
+        let mut started = Vec::new();
+        if self.parallel {
+            let mut waiting = VecDeque::new();
+            for pairing in self.pending.drain(..) {
+                let busy = self
+                    .playing
+                    .iter()
+                    .chain(&started)
+                    .any(|p| p.includes(pairing.p1) || p.includes(pairing.p2));
+                if busy {
+                    waiting.push_back(pairing);
+                } else {
+                    started.push(pairing);
+                }
+            }
+            self.pending = waiting;
+        } else if self.playing.is_empty() {
+            started.extend(self.pending.pop_front());
+        }
+
+        self.playing.extend(&started);
+        started
+    }
+
+    /// Records the result of a game being played. Returns false if the
+    /// pairing was not being played.
+    pub fn record(&mut self, pairing: Pairing, winner: GameWinner) -> bool {
+        let Some(i) = self.playing.iter().position(|p| *p == pairing) else {
+            return false;
+        };
+        self.playing.swap_remove(i);
+
+        match winner {
+            GameWinner::P1 => self.record_win(pairing.p1, pairing.p2),
+            GameWinner::P2 => self.record_win(pairing.p2, pairing.p1),
+            GameWinner::Draw => {
+                for participant in [pairing.p1, pairing.p2] {
+                    let standing = &mut self.standings[participant];
+                    standing.played += 1;
+                    standing.draws += 1;
+                    standing.points += DRAW_POINTS;
+                }
+            }
+            // Series games are played between two players
+            GameWinner::P3 | GameWinner::P4 => (),
+        }
+        true
+    }
+
+    /// Removes a participant who left, their games which have not started
+    /// are won by the opponent. A game being played still has to be
+    /// recorded.
+    pub fn withdraw(&mut self, participant: usize) {
+        let Some(standing) = self.standings.get_mut(participant) else {
+            return;
+        };
+        standing.withdrawn = true;
+
+        let forfeited: Vec<_> = self
+            .pending
+            .iter()
+            .filter(|p| p.includes(participant))
+            .copied()
+            .collect();
+        self.pending.retain(|p| !p.includes(participant));
+        for pairing in forfeited {
+            let opponent = if pairing.p1 == participant {
+                pairing.p2
+            } else {
+                pairing.p1
+            };
+            self.record_win(opponent, participant);
+        }
+    }
+
+    fn record_win(&mut self, winner: usize, loser: usize) {
+        let standing = &mut self.standings[winner];
+        standing.played += 1;
+        standing.wins += 1;
+        standing.points += WIN_POINTS;
+
+        let standing = &mut self.standings[loser];
+        standing.played += 1;
+        standing.losses += 1;
+    }
+
+    /// Pairings whose games are being played.
+    #[must_use]
+    pub fn playing(&self) -> &[Pairing] {
+        &self.playing
+    }
+
+    /// Amount of games which have not finished yet.
+    #[must_use]
+    pub fn remaining(&self) -> usize {
+        self.pending.len() + self.playing.len()
+    }
+
+    #[must_use]
+    pub fn is_finished(&self) -> bool {
+        self.remaining() == 0
+    }
+
+    /// Returns the standings of every participant, ordered by points and
+    /// then by wins.
+    #[must_use]
+    pub fn standings(&self) -> Vec<Standing> {
+        let mut standings = self.standings.clone();
+        standings.sort_by(|a, b| b.points.cmp(&a.points).then(b.wins.cmp(&a.wins)));
+        standings
+    }
+}
+
+/// Returns the pairings of an all-play-all series in rounds, using the
+/// circle method. Colors alternate between rounds, so that nobody plays
+/// first in every game.
+fn schedule(participants: usize) -> Vec<Pairing> {
+    // An odd amount of participants gets a bye, which sits out a round
+    let slots = participants + participants % 2;
+    let mut circle: Vec<usize> = (0..slots).collect();
+    let mut pairings = Vec::new();
+    for round in 0..slots.saturating_sub(1) {
+        for i in 0..slots / 2 {
+            let (a, b) = (circle[i], circle[slots - 1 - i]);
+            if a >= participants || b >= participants {
+                continue;
+            }
+            let (p1, p2) = if (round + i) % 2 == 0 { (a, b) } else { (b, a) };
+            pairings.push(Pairing { p1, p2 });
+        }
+        circle[1..].rotate_right(1);
+    }
+    pairings
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn play_all(series: &mut Series, winner: GameWinner) -> usize {
+        let mut games = 0;
+        while !series.is_finished() {
+            let pairings = series.next_pairings();
+            assert!(!pairings.is_empty());
+            for pairing in pairings {
+                assert!(series.record(pairing, winner));
+                games += 1;
+            }
+        }
+        games
+    }
+
+    #[test]
+    fn everyone_plays_everyone_once() {
+        for participants in 2..=7 {
+            let pairings = schedule(participants);
+            assert_eq!(pairings.len(), participants * (participants - 1) / 2);
+            for a in 0..participants {
+                for b in a + 1..participants {
+                    let count = pairings
+                        .iter()
+                        .filter(|p| p.includes(a) && p.includes(b))
+                        .count();
+                    assert_eq!(count, 1, "{participants} participants: {a} vs {b}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn sequential_series_plays_one_game_at_a_time() {
+        let mut series = Series::new(&[None, Some(1), Some(2)], false);
+        assert_eq!(series.next_pairings().len(), 1);
+        assert!(series.next_pairings().is_empty());
+        assert_eq!(series.remaining(), 3);
+    }
+
+    #[test]
+    fn parallel_series_never_double_books() {
+        let codes: Vec<_> = (0..6).map(Some).collect();
+        let mut series = Series::new(&codes, true);
+        let pairings = series.next_pairings();
+        assert_eq!(pairings.len(), 3);
+        for (i, a) in pairings.iter().enumerate() {
+            for b in &pairings[i + 1..] {
+                assert!(!a.includes(b.p1) && !a.includes(b.p2));
+            }
+        }
+        assert!(series.next_pairings().is_empty());
+        for pairing in pairings {
+            assert!(series.record(pairing, GameWinner::Draw));
+        }
+        assert_eq!(play_all(&mut series, GameWinner::Draw) + 3, 15);
+    }
+
+    #[test]
+    fn standings_are_ordered_by_points() {
+        let mut series = Series::new(&[None, Some(1), Some(2)], false);
+        assert_eq!(play_all(&mut series, GameWinner::Draw), 3);
+        assert!(series
+            .standings()
+            .iter()
+            .all(|s| s.points == 2 && s.played == 2));
+
+        let mut series = Series::new(&[None, Some(1), Some(2)], true);
+        play_all(&mut series, GameWinner::P1);
+        let points: Vec<_> = series.standings().iter().map(|s| s.points).collect();
+        assert_eq!(points.iter().sum::<u32>(), 3 * WIN_POINTS);
+        assert!(points.windows(2).all(|w| w[0] >= w[1]));
+    }
+
+    #[test]
+    fn withdrawing_forfeits_remaining_games() {
+        let mut series = Series::new(&[None, Some(1), Some(2)], false);
+        let pairing = series.next_pairings()[0];
+        series.withdraw(pairing.p1);
+        assert_eq!(series.remaining(), 2);
+        assert!(series.record(pairing, GameWinner::P1));
+        assert!(!series.record(pairing, GameWinner::P1));
+
+        play_all(&mut series, GameWinner::P2);
+        let withdrawn = series
+            .standings()
+            .into_iter()
+            .find(|s| s.withdrawn)
+            .unwrap();
+        assert_eq!(
+            (withdrawn.played, withdrawn.wins, withdrawn.losses),
+            (2, 1, 1)
+        );
+    }
+}
@@ -0,0 +1,100 @@
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+
+use crate::duration::as_millis;
+use crate::game::GameWinner;
+
+/// Statistics of every round played in a game.
+#[derive(Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GameStats {
+    pub rounds_played: u32,
+    /// Rounds won by each player, player 1 first.
+    pub wins: Vec<u32>,
+    pub draws: u32,
+    /// Chips dropped or popped, skipped turns are not counted.
+    pub total_moves: u32,
+    #[serde(with = "as_millis")]
+    pub average_move_time: Duration,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub longest_game: Option<LongestGame>,
+}
+
+/// The round which took the most time.
+#[derive(Clone, Copy, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LongestGame {
+    pub round: u32,
+    pub turns: u32,
+    #[serde(with = "as_millis")]
+    pub duration: Duration,
+}
+
+/// Collects `GameStats` across round restarts.
+pub struct StatsCollector {
+    stats: GameStats,
+    total_move_time: Duration,
+    round_started: Instant,
+    turn_started: Instant,
+}
+
+impl StatsCollector {
+    /// Collects the statistics of a game with the amount of players.
+    #[must_use]
+    pub fn new(players: u8) -> Self {
+        Self {
+            stats: GameStats {
+                wins: vec![0; usize::from(players)],
+                ..GameStats::default()
+            },
+            total_move_time: Duration::ZERO,
+            round_started: Instant::now(),
+            turn_started: Instant::now(),
+        }
+    }
+
+    /// Called when the first turn of a round begins.
+    pub fn start_round(&mut self) {
+        self.round_started = Instant::now();
+        self.turn_started = self.round_started;
+    }
+
+    /// Called whenever a turn ends, `skipped` if no chip was moved.
+    pub fn record_turn(&mut self, skipped: bool) {
+        if !skipped {
+            self.stats.total_moves += 1;
+            self.total_move_time += self.turn_started.elapsed();
+        }
+        self.turn_started = Instant::now();
+    }
+
+    /// Called once the round has been resolved, returns how long it took.
+    pub fn record_result(&mut self, round: u32, turns: u32, winner: GameWinner) -> Duration {
+        let stats = &mut self.stats;
+        stats.rounds_played += 1;
+        match winner.player() {
+            Some(player) => stats.wins[player.index()] += 1,
+            None => stats.draws += 1,
+        }
+
+        let duration = self.round_started.elapsed();
+        if stats.longest_game.is_none_or(|g| g.duration < duration) {
+            stats.longest_game = Some(LongestGame {
+                round,
+                turns,
+                duration,
+            });
+        }
+        duration
+    }
+
+    #[must_use]
+    pub fn stats(&self) -> GameStats {
+        let mut stats = self.stats.clone();
+        if stats.total_moves > 0 {
+            stats.average_move_time = self.total_move_time / stats.total_moves;
+        }
+        stats
+    }
+}
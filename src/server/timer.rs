@@ -0,0 +1,455 @@
+//! Bookkeeping of the turn timer of games: time carried over
+//! between turns, total clocks, byo-yomi periods and pausing while a player
+//! is away.
+//!
+//! `TurnClock` only keeps track of time and never schedules anything. The
+//! game looks up the running `Timer` after every change and makes sure a
+//! single timeout is scheduled for it, calling `TurnClock::expire` once it
+//! fires.
+
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, TimeDelta, Utc};
+
+use crate::game::Player;
+use crate::game_config::TimerMode;
+use crate::server::{GameConfig, PlayerArray};
+
+/// Shortest time per turn which enables the turn timer.
+pub const TIME_PER_TURN_MIN: Duration = Duration::from_secs(3);
+
+/// The turn timer currently counting down.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Timer {
+    /// Different for every time the timer is started, so that a timeout
+    /// scheduled for an earlier turn can be told apart.
+    pub id: u64,
+    /// How long until the timer runs out.
+    pub remaining: Duration,
+}
+
+/// What happens once the timer runs out.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Expiry {
+    /// The turn is skipped.
+    Skip,
+    /// The player used up a byo-yomi period and continues in the next one.
+    Period,
+    /// The player ran out of time and loses.
+    Forfeit,
+}
+
+struct RunningTimer {
+    id: u64,
+    started: Instant,
+    /// How long after `started` the timer runs out.
+    duration: Duration,
+    /// The amount of time the player had for this turn, `0` if only the
+    /// clock is running.
+    turn_duration: Duration,
+    /// When true, the player loses once the timer runs out instead of
+    /// having their turn skipped.
+    forfeits: bool,
+    deadline: DateTime<Utc>,
+}
+
+pub struct TurnClock {
+    extra_time: PlayerArray<Duration>,
+    /// Total time left for each player at the start of their turn, `None`
+    /// if the game is not played with clocks.
+    clocks: Option<PlayerArray<Duration>>,
+    /// Byo-yomi periods left for each player, `None` unless the game is
+    /// played with `TimerMode::ByoYomi`.
+    periods: Option<PlayerArray<u8>>,
+    running: Option<RunningTimer>,
+    /// Time left in the turn while the timer is paused because a player is
+    /// disconnected, `0` if only the clock is running.
+    paused: Option<Duration>,
+    /// Amount of times the timer was started.
+    starts: u64,
+}
+
+impl TurnClock {
+    /// Gives every player the total time and byo-yomi periods from the
+    /// config, if set. Players start with `extra_time`, or with enough for
+    /// their first turn to last `time_per_turn` in every timer mode.
+    #[must_use]
+    pub fn new(config: &GameConfig, extra_time: Option<PlayerArray<Duration>>) -> Self {
+        let players = config.players;
+        let extra_time = extra_time.unwrap_or_else(|| Self::initial_extra_time(config));
+        let clocks =
+            (!config.total_time.is_zero()).then(|| PlayerArray::repeat(config.total_time, players));
+        let periods = (config.timer_mode == TimerMode::ByoYomi
+            && config.time_per_turn >= TIME_PER_TURN_MIN)
+            .then(|| PlayerArray::repeat(config.byo_yomi_periods, players));
+        Self {
+            extra_time,
+            clocks,
+            periods,
+            running: None,
+            paused: None,
+            starts: 0,
+        }
+    }
+
+    #[must_use]
+    fn initial_extra_time(config: &GameConfig) -> PlayerArray<Duration> {
+        let extra_time = match config.timer_mode {
+            TimerMode::Fischer => config.time_per_turn.saturating_sub(config.increment),
+            _ => Duration::ZERO,
+        };
+        PlayerArray::repeat(extra_time, config.players)
+    }
+
+    /// Returns the timer counting down, if any.
+    #[must_use]
+    pub fn timer(&self, now: Instant) -> Option<Timer> {
+        let running = self.running.as_ref()?;
+        let elapsed = now.saturating_duration_since(running.started);
+        Some(Timer {
+            id: running.id,
+            remaining: running.duration.saturating_sub(elapsed),
+        })
+    }
+
+    /// Returns when the timer runs out according to the wall clock.
+    #[must_use]
+    pub fn deadline(&self) -> Option<DateTime<Utc>> {
+        self.running.as_ref().map(|r| r.deadline)
+    }
+
+    /// Returns how much total time every player has left, `player` being
+    /// the one whose turn it is.
+    #[must_use]
+    pub fn clocks(&self, player: Player, now: Instant) -> Option<Vec<Duration>> {
+        let mut clocks = self.clocks.clone()?;
+        if let Some(running) = &self.running {
+            let elapsed = now.saturating_duration_since(running.started);
+            clocks[player] = clocks[player].saturating_sub(elapsed);
+        }
+        Some(clocks.into_inner())
+    }
+
+    #[must_use]
+    pub fn periods(&self) -> Option<Vec<u8>> {
+        self.periods.clone().map(PlayerArray::into_inner)
+    }
+
+    /// Starts the turn of the player, or only works out how long it lasts
+    /// if `paused`, to be started by `resume`.
+    pub fn start_turn(&mut self, player: Player, paused: bool, config: &GameConfig, now: Instant) {
+        let duration = Self::turn_duration(self.extra_time[player], config);
+        if paused {
+            self.paused = Some(duration);
+        } else {
+            let clock = self.time_until_forfeit(player, config);
+            self.start(duration, clock, now);
+        }
+    }
+
+    /// Stops the timer once the player ended their turn. Unless `charge` is
+    /// false, e.g. in the first turn of a round, the time left over is
+    /// carried over and the time spent is taken from the player's clock.
+    pub fn end_turn(&mut self, player: Player, charge: bool, config: &GameConfig, now: Instant) {
+        let (remaining, spent) = match self.paused.take() {
+            Some(remaining) => (remaining, Duration::ZERO),
+            None => self.stop(now),
+        };
+        if charge {
+            self.extra_time[player] = remaining;
+            if let Some(clocks) = &mut self.clocks {
+                clocks[player] = clocks[player].saturating_sub(spent) + config.increment;
+            }
+        }
+    }
+
+    /// Stops the timer and charges the time spent so far to the player's
+    /// clock. Returns `false` if no timer was running.
+    pub fn pause(&mut self, player: Player, now: Instant) -> bool {
+        if self.running.is_none() {
+            return false;
+        }
+        let (remaining, spent) = self.stop(now);
+        if let Some(clocks) = &mut self.clocks {
+            clocks[player] = clocks[player].saturating_sub(spent);
+        }
+        self.paused = Some(remaining);
+        true
+    }
+
+    /// Restarts the paused timer. Players get at least `TIME_PER_TURN_MIN`
+    /// to finish their turn. Returns `false` if the timer was not paused.
+    pub fn resume(&mut self, player: Player, config: &GameConfig, now: Instant) -> bool {
+        let Some(remaining) = self.paused.take() else {
+            return false;
+        };
+        let turn_duration = if remaining.is_zero() {
+            remaining
+        } else {
+            remaining.max(TIME_PER_TURN_MIN)
+        };
+        let clock = self.time_until_forfeit(player, config);
+        self.start(turn_duration, clock, now);
+        true
+    }
+
+    /// Decides what happens to the player whose timer ran out. Turns which
+    /// do not forfeit are left to be skipped with `end_turn`.
+    pub fn expire(&mut self, player: Player, config: &GameConfig, now: Instant) -> Expiry {
+        if !self.running.as_ref().is_some_and(|r| r.forfeits) {
+            return Expiry::Skip;
+        }
+
+        self.running = None;
+        if let Some(clocks) = &mut self.clocks {
+            clocks[player] = Duration::ZERO;
+        }
+        if let Some(periods) = self.periods.as_mut().filter(|p| p[player] > 1) {
+            periods[player] -= 1;
+            self.start(Duration::ZERO, Some(config.time_per_turn), now);
+            return Expiry::Period;
+        }
+        if let Some(periods) = &mut self.periods {
+            periods[player] = 0;
+        }
+        Expiry::Forfeit
+    }
+
+    /// Lets the players trade seats in the turn of `player`, who keeps their
+    /// own clock in the other seat. Time spent deciding is charged to them,
+    /// and their new turn starts right away.
+    pub fn swap_sides(&mut self, player: Player, config: &GameConfig, now: Instant) {
+        let (_, spent) = self.stop(now);
+        if let Some(clocks) = &mut self.clocks {
+            clocks[player] = clocks[player].saturating_sub(spent);
+            clocks.swap();
+        }
+        if let Some(periods) = &mut self.periods {
+            periods.swap();
+        }
+        self.extra_time.swap();
+        self.start_turn(player, false, config, now);
+    }
+
+    /// Stops the timer for good, e.g. once the game is over.
+    pub fn clear(&mut self) {
+        self.running = None;
+        self.paused = None;
+    }
+
+    /// Returns the amount of time the turn should take, or `0` if the turn
+    /// timer is disabled. Byo-yomi turns are only limited by the clock and
+    /// the periods, see `time_until_forfeit`.
+    #[must_use]
+    fn turn_duration(extra_time: Duration, config: &GameConfig) -> Duration {
+        let GameConfig {
+            time_per_turn,
+            time_cap,
+            increment,
+            timer_mode,
+            ..
+        } = *config;
+
+        if time_per_turn < TIME_PER_TURN_MIN {
+            return Duration::ZERO;
+        }
+
+        let time_cap = time_cap.max(time_per_turn);
+        match timer_mode {
+            TimerMode::CarryOver => (extra_time + time_per_turn).min(time_cap),
+            TimerMode::PerMove => time_per_turn,
+            TimerMode::Fischer => (extra_time + increment).min(time_cap),
+            TimerMode::ByoYomi => Duration::ZERO,
+        }
+    }
+
+    /// Returns how long the player has until they lose on time: what is
+    /// left on their clock, followed by a byo-yomi period in byo-yomi games.
+    /// `None` if they cannot lose on time.
+    #[must_use]
+    fn time_until_forfeit(&self, player: Player, config: &GameConfig) -> Option<Duration> {
+        let clock = self.clocks.as_ref().map(|c| c[player]);
+        if config.timer_mode != TimerMode::ByoYomi || config.time_per_turn < TIME_PER_TURN_MIN {
+            return clock;
+        }
+        Some(clock.unwrap_or_default() + config.time_per_turn)
+    }
+
+    /// Starts the timer, if it is not running. It runs out when either the
+    /// turn or the player's clock does, whichever comes first.
+    fn start(&mut self, turn_duration: Duration, clock: Option<Duration>, now: Instant) {
+        if self.running.is_some() {
+            return;
+        }
+
+        let turn_duration = if turn_duration < TIME_PER_TURN_MIN {
+            Duration::ZERO
+        } else {
+            turn_duration
+        };
+        let (duration, forfeits) = match clock {
+            Some(clock) if turn_duration.is_zero() || clock <= turn_duration => (clock, true),
+            _ if !turn_duration.is_zero() => (turn_duration, false),
+            _ => return,
+        };
+
+        self.starts += 1;
+        let delta = TimeDelta::from_std(duration).unwrap_or_else(|_| TimeDelta::zero());
+        self.running = Some(RunningTimer {
+            id: self.starts,
+            started: now,
+            duration,
+            turn_duration,
+            forfeits,
+            deadline: Utc::now() + delta,
+        });
+    }
+
+    /// Stops the timer and returns how much time remained until the turn
+    /// would end, and how long the turn took.
+    fn stop(&mut self, now: Instant) -> (Duration, Duration) {
+        let Some(running) = self.running.take() else {
+            return (Duration::ZERO, Duration::ZERO);
+        };
+        let elapsed = now.saturating_duration_since(running.started);
+        (running.turn_duration.saturating_sub(elapsed), elapsed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::Player::{P1, P2};
+
+    const fn secs(secs: u64) -> Duration {
+        Duration::from_secs(secs)
+    }
+
+    fn config(timer_mode: TimerMode) -> GameConfig {
+        GameConfig {
+            time_per_turn: secs(10),
+            time_cap: secs(25),
+            increment: secs(4),
+            timer_mode,
+            ..GameConfig::default()
+        }
+    }
+
+    #[test]
+    fn carries_time_over() {
+        let config = config(TimerMode::CarryOver);
+        let mut clock = TurnClock::new(&config, None);
+        let start = Instant::now();
+        clock.start_turn(P1, false, &config, start);
+        assert_eq!(clock.timer(start).unwrap().remaining, secs(10));
+
+        clock.end_turn(P1, true, &config, start + secs(4));
+        clock.start_turn(P2, false, &config, start + secs(4));
+        clock.end_turn(P2, true, &config, start + secs(5));
+        clock.start_turn(P1, false, &config, start + secs(5));
+        let timer = clock.timer(start + secs(5)).unwrap();
+        assert_eq!(timer.remaining, secs(16));
+        assert_eq!(timer.id, 3);
+
+        // Capped at `time_cap`
+        clock.end_turn(P1, true, &config, start + secs(5));
+        clock.start_turn(P2, false, &config, start + secs(5));
+        assert_eq!(clock.timer(start + secs(5)).unwrap().remaining, secs(19));
+        clock.end_turn(P2, true, &config, start + secs(5));
+        clock.start_turn(P1, false, &config, start + secs(5));
+        assert_eq!(clock.timer(start + secs(5)).unwrap().remaining, secs(25));
+    }
+
+    #[test]
+    fn fischer_adds_increment() {
+        let config = config(TimerMode::Fischer);
+        let mut clock = TurnClock::new(&config, None);
+        let start = Instant::now();
+        clock.start_turn(P1, false, &config, start);
+        assert_eq!(clock.timer(start).unwrap().remaining, secs(10));
+        clock.end_turn(P1, true, &config, start + secs(8));
+        clock.start_turn(P2, false, &config, start + secs(8));
+        clock.end_turn(P2, true, &config, start + secs(8));
+        clock.start_turn(P1, false, &config, start + secs(8));
+        assert_eq!(clock.timer(start + secs(8)).unwrap().remaining, secs(6));
+    }
+
+    #[test]
+    fn pauses_and_resumes() {
+        let config = GameConfig {
+            total_time: secs(60),
+            ..config(TimerMode::PerMove)
+        };
+        let mut clock = TurnClock::new(&config, None);
+        let start = Instant::now();
+        clock.start_turn(P1, false, &config, start);
+        assert!(clock.pause(P1, start + secs(9)));
+        assert!(clock.timer(start + secs(9)).is_none());
+        assert_eq!(
+            clock.clocks(P1, start + secs(30)),
+            Some(vec![secs(51), secs(60)])
+        );
+        assert!(!clock.pause(P1, start + secs(9)));
+
+        // Players get at least `TIME_PER_TURN_MIN` after coming back
+        let later = start + secs(30);
+        assert!(clock.resume(P1, &config, later));
+        assert_eq!(clock.timer(later).unwrap().remaining, TIME_PER_TURN_MIN);
+        assert!(!clock.resume(P1, &config, later));
+    }
+
+    #[test]
+    fn byo_yomi_uses_up_periods() {
+        let config = GameConfig {
+            total_time: secs(5),
+            byo_yomi_periods: 2,
+            ..config(TimerMode::ByoYomi)
+        };
+        let mut clock = TurnClock::new(&config, None);
+        let start = Instant::now();
+        clock.start_turn(P1, false, &config, start);
+        assert_eq!(clock.timer(start).unwrap().remaining, secs(15));
+
+        let end = start + secs(15);
+        assert_eq!(clock.expire(P1, &config, end), Expiry::Period);
+        assert_eq!(clock.periods(), Some(vec![1, 2]));
+        assert_eq!(clock.clocks(P1, end), Some(vec![Duration::ZERO, secs(5)]));
+        assert_eq!(clock.timer(end).unwrap().remaining, secs(10));
+        assert_eq!(clock.expire(P1, &config, end + secs(10)), Expiry::Forfeit);
+        assert_eq!(clock.periods(), Some(vec![0, 2]));
+        assert!(clock.timer(end).is_none());
+    }
+
+    #[test]
+    fn turns_without_clock_are_skipped() {
+        let config = config(TimerMode::PerMove);
+        let mut clock = TurnClock::new(&config, None);
+        let start = Instant::now();
+        clock.start_turn(P1, false, &config, start);
+        assert_eq!(clock.expire(P1, &config, start + secs(10)), Expiry::Skip);
+
+        let disabled = GameConfig::default();
+        let mut clock = TurnClock::new(&disabled, None);
+        clock.start_turn(P1, false, &disabled, start);
+        assert!(clock.timer(start).is_none());
+    }
+
+    #[test]
+    fn swapping_sides_keeps_clocks() {
+        let config = GameConfig {
+            total_time: secs(60),
+            ..config(TimerMode::PerMove)
+        };
+        let mut clock = TurnClock::new(&config, None);
+        let start = Instant::now();
+        clock.start_turn(P1, false, &config, start);
+        clock.end_turn(P1, true, &config, start + secs(2));
+        clock.start_turn(P2, false, &config, start + secs(2));
+        clock.swap_sides(P2, &config, start + secs(5));
+        assert_eq!(
+            clock.clocks(P2, start + secs(5)),
+            Some(vec![secs(57), secs(62)])
+        );
+        assert_eq!(clock.timer(start + secs(5)).unwrap().remaining, secs(10));
+    }
+}
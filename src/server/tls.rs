@@ -0,0 +1,165 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, PoisonError, RwLock},
+    time::SystemTime,
+};
+
+use openssl::error::ErrorStack;
+use openssl::ssl::{SniError, SslAcceptor, SslAcceptorBuilder, SslContext, SslFiletype, SslMethod};
+
+use crate::server::config::TlsConfig;
+
+/// Certificate and private key served by every HTTPS listener, which can be
+/// replaced while the server is running, e.g. after a renewal.
+pub struct Certificates {
+    private_key_file: PathBuf,
+    certificate_chain_file: PathBuf,
+    context: RwLock<SslContext>,
+    /// Modification times of the files when they were last loaded.
+    modified: Mutex<[Option<SystemTime>; 2]>,
+}
+
+impl Certificates {
+    /// Reads the files named in the configuration.
+    pub fn load(tls: &TlsConfig) -> Result<Self, ErrorStack> {
+        let key = &tls.private_key_file;
+        let chain = &tls.certificate_chain_file;
+        let modified = modification_times(key, chain);
+        let context = acceptor_builder(key, chain)?.build().into_context();
+        Ok(Self {
+            private_key_file: key.clone(),
+            certificate_chain_file: chain.clone(),
+            context: RwLock::new(context),
+            modified: Mutex::new(modified),
+        })
+    }
+
+    /// Returns an acceptor for a listener. Every handshake uses the
+    /// certificates loaded most recently.
+    pub fn acceptor(self: &Arc<Self>) -> Result<SslAcceptorBuilder, ErrorStack> {
+        let mut builder = acceptor_builder(&self.private_key_file, &self.certificate_chain_file)?;
+        let certificates = Arc::clone(self);
+        builder.set_servername_callback(move |ssl, _| {
+            let context = certificates
+                .context
+                .read()
+                .unwrap_or_else(PoisonError::into_inner);
+            ssl.set_ssl_context(&context)
+                .map_err(|_| SniError::ALERT_FATAL)
+        });
+        Ok(builder)
+    }
+
+    /// Reads the files again. The previous certificates stay in use if they
+    /// are invalid.
+    pub fn reload(&self) -> Result<(), ErrorStack> {
+        let key = &self.private_key_file;
+        let chain = &self.certificate_chain_file;
+        let modified = modification_times(key, chain);
+        let context = acceptor_builder(key, chain)?.build().into_context();
+        *self.context.write().unwrap_or_else(PoisonError::into_inner) = context;
+        *self.modified.lock().unwrap_or_else(PoisonError::into_inner) = modified;
+        Ok(())
+    }
+
+    /// Reloads the files if either of them changed since they were last
+    /// loaded. Returns true if they were reloaded.
+    pub fn reload_if_modified(&self) -> Result<bool, ErrorStack> {
+        let modified = modification_times(&self.private_key_file, &self.certificate_chain_file);
+        if *self.modified.lock().unwrap_or_else(PoisonError::into_inner) == modified {
+            return Ok(false);
+        }
+
+        self.reload()?;
+        Ok(true)
+    }
+}
+
+fn acceptor_builder(key: &Path, chain: &Path) -> Result<SslAcceptorBuilder, ErrorStack> {
+    let mut builder = SslAcceptor::mozilla_intermediate(SslMethod::tls())?;
+    builder.set_private_key_file(key, SslFiletype::PEM)?;
+    builder.set_certificate_chain_file(chain)?;
+    builder.check_private_key()?;
+    Ok(builder)
+}
+
+fn modification_times(key: &Path, chain: &Path) -> [Option<SystemTime>; 2] {
+    let modified = |path: &Path| fs::metadata(path).and_then(|m| m.modified()).ok();
+    [modified(key), modified(chain)]
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use openssl::asn1::Asn1Time;
+    use openssl::hash::MessageDigest;
+    use openssl::pkey::PKey;
+    use openssl::rsa::Rsa;
+    use openssl::x509::{X509NameBuilder, X509};
+
+    use super::*;
+
+    /// Writes a self-signed certificate and its key to `dir`.
+    fn write_certificate(dir: &Path, name: &str) {
+        let key = PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap();
+        let mut subject = X509NameBuilder::new().unwrap();
+        subject.append_entry_by_text("CN", name).unwrap();
+        let subject = subject.build();
+
+        let mut cert = X509::builder().unwrap();
+        cert.set_version(2).unwrap();
+        cert.set_subject_name(&subject).unwrap();
+        cert.set_issuer_name(&subject).unwrap();
+        cert.set_pubkey(&key).unwrap();
+        cert.set_not_before(&Asn1Time::days_from_now(0).unwrap())
+            .unwrap();
+        cert.set_not_after(&Asn1Time::days_from_now(1).unwrap())
+            .unwrap();
+        cert.sign(&key, MessageDigest::sha256()).unwrap();
+
+        let key_pem = key.private_key_to_pem_pkcs8().unwrap();
+        fs::write(dir.join("key.pem"), key_pem).unwrap();
+        fs::write(dir.join("cert.pem"), cert.build().to_pem().unwrap()).unwrap();
+    }
+
+    fn subject(certificates: &Certificates) -> String {
+        let context = certificates.context.read().unwrap();
+        let cert = context.certificate().unwrap();
+        let entry = cert.subject_name().entries().next().unwrap();
+        entry.data().to_string().unwrap()
+    }
+
+    #[test]
+    fn reloads_modified_files() {
+        let dir = std::env::temp_dir().join(format!("c4-tls-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        write_certificate(&dir, "first");
+        let tls = TlsConfig {
+            private_key_file: dir.join("key.pem"),
+            certificate_chain_file: dir.join("cert.pem"),
+            ..TlsConfig::default()
+        };
+
+        let certificates = Arc::new(Certificates::load(&tls).unwrap());
+        assert!(certificates.acceptor().is_ok());
+        assert!(!certificates.reload_if_modified().unwrap());
+        assert_eq!(subject(&certificates), "first");
+
+        write_certificate(&dir, "second");
+        let later = SystemTime::now() + Duration::from_secs(10);
+        let file = fs::File::options()
+            .write(true)
+            .open(&tls.certificate_chain_file);
+        file.unwrap().set_modified(later).unwrap();
+        assert!(certificates.reload_if_modified().unwrap());
+        assert_eq!(subject(&certificates), "second");
+
+        fs::write(&tls.certificate_chain_file, "not a certificate").unwrap();
+        assert!(certificates.reload().is_err());
+        assert_eq!(subject(&certificates), "second");
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,14 @@
+use uuid::Uuid;
+
+/// Generates a new secret token.
+#[must_use]
+pub fn generate() -> String {
+    Uuid::new_v4().simple().to_string()
+}
+
+/// Compares the tokens in constant time.
+#[must_use]
+pub fn matches(expected: &str, token: &str) -> bool {
+    let (expected, token) = (expected.as_bytes(), token.as_bytes());
+    expected.len() == token.len() && openssl::memcmp::eq(expected, token)
+}
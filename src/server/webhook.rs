@@ -0,0 +1,212 @@
+//! Events sent as JSON in POST requests to the URLs in `WebhookConfig`,
+//! e.g. to update a leaderboard. Every URL is served by its own thread with a bounded queue,
+//! so that slow or unreachable endpoints never hold up lobbies and games.
+//! Events which do not fit in the queue are dropped.
+
+use std::{
+    sync::mpsc::{self, Receiver, SyncSender, TrySendError},
+    thread,
+    time::Duration,
+};
+
+use chrono::Utc;
+use serde::Serialize;
+use tracing::{debug, error, warn};
+use ureq::{Agent, ErrorKind};
+use url::Url;
+
+use crate::game::GameWinner;
+use crate::server::config::WebhookConfig;
+use crate::server::http_client;
+use crate::server::protocol::ISO_8601_TIMESTAMP;
+use crate::server::GameConfig;
+
+/// How long to wait before the first retry, doubled after every attempt.
+const RETRY_DELAY: Duration = Duration::from_secs(1);
+
+/// Something that happened on the server, tagged with its name in `event`.
+#[derive(Serialize, Clone)]
+#[serde(tag = "event", rename_all = "camelCase")]
+pub enum WebhookEvent {
+    /// A host has opened a new lobby.
+    LobbyCreated { lobby: String },
+    /// A round of a game between players has started.
+    GameStarted {
+        /// ID players reconnect to the game with, `None` if it cannot be
+        /// reconnected to, e.g. games against the bot.
+        game: Option<String>,
+        round: u32,
+        config: GameConfig,
+        /// Player IDs of every player in turn order, `None` for players
+        /// without one.
+        players: Vec<Option<String>>,
+    },
+    /// A round of a game between players has ended with a result.
+    GameEnded {
+        game: Option<String>,
+        round: u32,
+        players: Vec<Option<String>>,
+        winner: GameWinner,
+        /// Amount of moves every player made.
+        moves: u32,
+    },
+}
+
+/// Body of every request.
+#[derive(Serialize)]
+struct Payload<'a> {
+    #[serde(flatten)]
+    event: &'a WebhookEvent,
+    /// ISO 8601 timestamp of when the event happened.
+    timestamp: String,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum WebhookError {
+    /// The request could not be sent, or no response arrived in time.
+    #[error(transparent)]
+    Transport(#[from] Box<ureq::Transport>),
+    #[error("endpoint answered with status {0}")]
+    Status(u16),
+}
+
+impl WebhookError {
+    /// Returns true if sending the event again may succeed.
+    fn is_retryable(&self) -> bool {
+        match self {
+            Self::Status(status) => *status >= 500 || *status == 408 || *status == 429,
+            Self::Transport(e) => {
+                !matches!(e.kind(), ErrorKind::InvalidUrl | ErrorKind::UnknownScheme)
+            }
+        }
+    }
+}
+
+/// Queues of the threads delivering events, one for every URL.
+pub struct Webhooks {
+    queues: Vec<(Url, SyncSender<String>)>,
+}
+
+impl Webhooks {
+    /// Starts a thread for every URL in the config. Returns `None` if there
+    /// are none.
+    #[must_use]
+    pub fn start(cfg: &WebhookConfig) -> Option<Self> {
+        if cfg.urls.is_empty() {
+            return None;
+        }
+        let queues = cfg
+            .urls
+            .iter()
+            .map(|url| {
+                let (tx, rx) = mpsc::sync_channel(cfg.queue_capacity.max(1));
+                let (worker_url, attempts, timeout) = (url.clone(), cfg.max_attempts, cfg.timeout);
+                thread::spawn(move || deliver_all(&worker_url, &rx, attempts, timeout));
+                (url.clone(), tx)
+            })
+            .collect();
+        Some(Self { queues })
+    }
+
+    /// Queues the event for every URL without waiting for it to be sent.
+    pub fn send(&self, event: &WebhookEvent) {
+        let payload = Payload {
+            event,
+            timestamp: Utc::now().format(ISO_8601_TIMESTAMP).to_string(),
+        };
+        let body = match serde_json::to_string(&payload) {
+            Ok(body) => body,
+            Err(e) => {
+                error!("Failed to serialize webhook event: {e}");
+                return;
+            }
+        };
+        for (url, queue) in &self.queues {
+            match queue.try_send(body.clone()) {
+                Ok(()) => (),
+                Err(TrySendError::Full(_)) => {
+                    warn!("Webhook queue of {url} is full, event dropped");
+                }
+                Err(TrySendError::Disconnected(_)) => error!("Webhook thread of {url} stopped"),
+            }
+        }
+    }
+}
+
+/// Sends every event received until the `Webhooks` are dropped.
+fn deliver_all(url: &Url, queue: &Receiver<String>, attempts: u32, timeout: Duration) {
+    let agent = http_client::agent(timeout);
+    for body in queue {
+        let mut delay = RETRY_DELAY;
+        for attempt in 1..=attempts.max(1) {
+            match post(&agent, url, &body) {
+                Ok(()) => break,
+                Err(e) if attempt < attempts && e.is_retryable() => {
+                    debug!("Failed to deliver webhook to {url}, retrying: {e}");
+                    thread::sleep(delay);
+                    delay *= 2;
+                }
+                Err(e) => {
+                    warn!("Failed to deliver webhook to {url}: {e}");
+                    break;
+                }
+            }
+        }
+    }
+}
+
+/// Sends the body and checks that the endpoint answered with 2xx.
+fn post(agent: &Agent, url: &Url, body: &str) -> Result<(), WebhookError> {
+    let res = agent
+        .request_url("POST", url)
+        .set("Content-Type", "application/json")
+        .send_string(body);
+    match res {
+        Ok(res) if (200..300).contains(&res.status()) => Ok(()),
+        Ok(res) | Err(ureq::Error::Status(_, res)) => Err(WebhookError::Status(res.status())),
+        Err(ureq::Error::Transport(e)) => Err(Box::new(e).into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn retries_server_and_connection_errors_only() {
+        assert!(WebhookError::Status(503).is_retryable());
+        assert!(WebhookError::Status(429).is_retryable());
+        assert!(!WebhookError::Status(404).is_retryable());
+
+        let agent = http_client::agent(Duration::from_secs(1));
+        let unsupported = Url::parse("ftp://127.0.0.1/events").unwrap();
+        assert!(!post(&agent, &unsupported, "{}").unwrap_err().is_retryable());
+
+        // Nothing listens on the port once the listener is dropped
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let closed = format!("http://{}/events", listener.local_addr().unwrap());
+        drop(listener);
+        let closed = Url::parse(&closed).unwrap();
+        assert!(post(&agent, &closed, "{}").unwrap_err().is_retryable());
+    }
+
+    #[test]
+    fn serializes_events() {
+        let event = WebhookEvent::GameEnded {
+            game: None,
+            round: 2,
+            players: vec![Some(String::from("alice")), None],
+            winner: GameWinner::P2,
+            moves: 17,
+        };
+        let payload = Payload {
+            event: &event,
+            timestamp: String::from("2024-01-01T00:00:00.000Z"),
+        };
+        let json = serde_json::to_value(&payload).unwrap();
+        assert_eq!(json["event"], "gameEnded");
+        assert_eq!(json["players"][0], "alice");
+        assert_eq!(json["moves"], 17);
+        assert_eq!(json["timestamp"], "2024-01-01T00:00:00.000Z");
+    }
+}
@@ -0,0 +1,1129 @@
+//! Plays through a lobby and a game with real WebSocket clients, connected to
+//! the app served over plain HTTP on a random port.
+
+use std::{
+    io::{Read, Write},
+    net::{SocketAddr, TcpListener, TcpStream},
+    sync::{mpsc, Arc},
+    thread,
+    time::{Duration, Instant},
+};
+
+use actix::Actor;
+use actix_web::{rt, web, App, HttpResponse, HttpServer};
+use serde_json::{json, Value};
+use tungstenite::client::IntoClientRequest;
+use tungstenite::Message;
+use url::Url;
+
+use connect_four_server::game::{Game, GameRules, FIELD_SIZE};
+use connect_four_server::server::actor::LobbyRouter;
+use connect_four_server::server::auth;
+use connect_four_server::server::client::{self, Socket};
+use connect_four_server::server::encoding::Encoding;
+use connect_four_server::server::http::{build_app, configure, AppState};
+use connect_four_server::server::reload::ConfigLoader;
+use connect_four_server::server::AppConfig;
+
+/// How long a client waits for an expected message before the test fails.
+const READ_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Starts the server on its own thread and returns its address. The server
+/// runs until the test process exits.
+fn start_server(cfg: AppConfig) -> SocketAddr {
+    start_server_with(cfg, None)
+}
+
+/// Starts the server, reading the settings again with the loader when they
+/// are reloaded.
+fn start_server_with(cfg: AppConfig, loader: Option<ConfigLoader>) -> SocketAddr {
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        rt::System::new().block_on(async move {
+            let mut state = AppState::new(cfg).unwrap();
+            if let Some(loader) = loader {
+                state = state.with_config_loader(loader);
+            }
+            let server = HttpServer::new(move || build_app(&state))
+                .workers(1)
+                .bind(("127.0.0.1", 0))
+                .unwrap();
+            tx.send(server.addrs()[0]).unwrap();
+            server.run().await.unwrap();
+        });
+    });
+    rx.recv().unwrap()
+}
+
+struct Client(Socket);
+
+impl Client {
+    /// Connects with protocol version 2 and the extra query parameters.
+    fn connect(addr: SocketAddr, query: &str) -> Self {
+        let url = Url::parse(&format!("ws://{addr}/ws?version=2&{query}")).unwrap();
+        Self(client::connect(&url, false, READ_TIMEOUT).unwrap())
+    }
+
+    fn send(&mut self, msg: &Value) {
+        self.0.send(Message::text(msg.to_string())).unwrap();
+    }
+
+    /// Reads messages until one of the given type arrives, skipping the
+    /// rest.
+    fn expect(&mut self, kind: &str) -> Value {
+        self.expect_any(&[kind])
+    }
+
+    fn expect_any(&mut self, kinds: &[&str]) -> Value {
+        loop {
+            let msg = match self.0.read() {
+                Ok(Message::Text(text)) => serde_json::from_str::<Value>(&text).unwrap(),
+                Ok(Message::Close(frame)) => {
+                    panic!("closed while waiting for {kinds:?}: {frame:?}")
+                }
+                Ok(_) => continue,
+                Err(e) => panic!("failed to read {kinds:?}: {e}"),
+            };
+            if kinds.iter().any(|kind| msg["type"] == *kind) {
+                return msg;
+            }
+        }
+    }
+
+    /// Reads messages until the server closes the connection, returning the
+    /// reason it gave.
+    fn expect_close(&mut self) -> String {
+        self.expect_close_frame().1
+    }
+
+    /// Like `expect_close`, but also returns the close code.
+    fn expect_close_frame(&mut self) -> (u16, String) {
+        loop {
+            match self.0.read() {
+                Ok(Message::Close(frame)) => {
+                    return frame
+                        .map(|f| (f.code.into(), f.reason.into_owned()))
+                        .unwrap_or_default()
+                }
+                Ok(_) => (),
+                Err(e) => panic!("failed to read close frame: {e}"),
+            }
+        }
+    }
+
+    /// Drops a chip and waits until the move is confirmed, returning the
+    /// state of the game after it.
+    fn drop_chip(&mut self, turn: u32, col: usize) -> Value {
+        self.send(&json!({ "type": "gameEndTurn", "turn": turn, "col": col }));
+        let state = self.expect_move();
+        self.expect("gameEndTurnAccepted");
+        state
+    }
+
+    /// Waits for the next move, sent as `gameMove`, or as `gameSync` once
+    /// the game is over, and returns the state of the game after it.
+    fn expect_move(&mut self) -> Value {
+        let msg = self.expect_any(&["gameMove", "gameSync"]);
+        if msg["type"] == "gameMove" {
+            msg["state"].clone()
+        } else {
+            msg["game"]["state"].clone()
+        }
+    }
+}
+
+#[test]
+fn lobby_to_restarted_game() {
+    let addr = start_server(AppConfig::default());
+
+    let mut host = Client::connect(addr, "");
+    let link = host.expect("lobbyLink");
+    let lobby = link["lobby"].as_str().unwrap();
+
+    let mut guest = Client::connect(addr, &format!("lobby={lobby}"));
+    let code = guest.expect("lobbyCode")["code"].as_u64().unwrap();
+
+    // The guest plays first, as player 1
+    host.send(&json!({
+        "type": "lobbyPickPlayer",
+        "code": code,
+        "role": 0,
+        "game": null,
+        "config": {},
+        "round": 0,
+    }));
+    for client in [&mut host, &mut guest] {
+        let setup = client.expect("gameSetup");
+        assert!(setup["reconnectToken"].is_string());
+        client.expect("gamePlayerSelection");
+    }
+
+    guest.send(&json!({ "type": "gamePlayerSelectionVote", "wantsToStart": true }));
+    host.send(&json!({ "type": "gamePlayerSelectionVote", "wantsToStart": false }));
+    let sync = host.expect("gameSync");
+    assert_eq!(sync["game"]["state"]["player"], 0);
+    guest.expect("gameSync");
+
+    // The guest completes a column while the host plays next to it
+    let mut turn = 0;
+    for _ in 0..3 {
+        guest.drop_chip(turn, 3);
+        host.expect_move();
+        host.drop_chip(turn + 1, 4);
+        guest.expect_move();
+        turn += 2;
+    }
+    let state = guest.drop_chip(turn, 3);
+    assert_eq!(state["result"]["winner"], 0);
+    assert_eq!(host.expect_move()["result"]["winner"], 0);
+
+    // Restarting a finished game needs no confirmation
+    guest.send(&json!({ "type": "gameRestart" }));
+    for client in [&mut host, &mut guest] {
+        let selection = client.expect("gamePlayerSelection");
+        assert_eq!(selection["p1Voted"], false);
+        assert_eq!(selection["p2Voted"], false);
+    }
+}
+
+#[test]
+fn acknowledges_retried_turns_of_resumed_games() {
+    let addr = start_server(AppConfig::default());
+
+    let mut host = Client::connect(addr, "");
+    let lobby = host.expect("lobbyLink")["lobby"]
+        .as_str()
+        .unwrap()
+        .to_owned();
+    let mut guest = Client::connect(addr, &format!("lobby={lobby}"));
+    let code = guest.expect("lobbyCode")["code"].clone();
+
+    // The host resumes a game two turns in, without its earlier moves
+    let mut game = Game::new(GameRules::default());
+    game.end_turn(Some(3)).unwrap();
+    game.end_turn(Some(4)).unwrap();
+    host.send(&json!({
+        "type": "lobbyPickPlayer",
+        "code": code,
+        "role": 0,
+        "game": game,
+        "config": {},
+        "round": 0,
+    }));
+    for client in [&mut host, &mut guest] {
+        let sync = client.expect("gameSync");
+        assert_eq!(sync["game"]["state"]["turn"], 2);
+    }
+
+    guest.drop_chip(2, 3);
+    assert_eq!(host.expect("gameMove")["turn"], 2);
+    host.send(&json!({ "type": "gameRequestSync" }));
+    let sync = host.expect("gameSync");
+    assert_eq!(sync["game"]["state"]["turn"], 3);
+    assert_eq!(sync["game"]["field"][3][FIELD_SIZE - 2], 0);
+
+    // The acknowledgement got lost, so the guest sends the turn again
+    guest.send(&json!({ "type": "gameEndTurn", "turn": 2, "col": 3 }));
+    let reply = guest.expect_any(&["gameEndTurnAccepted", "gameEndTurnRejected"]);
+    assert_eq!(reply["type"], "gameEndTurnAccepted");
+
+    // Moves made since resuming can be annotated
+    host.send(&json!({ "type": "gameAnnotate", "turn": 2, "text": "Nice" }));
+    let annotation = guest.expect("gameAnnotation");
+    assert_eq!(annotation["annotation"]["turn"], 2);
+}
+
+#[test]
+fn host_spectates_picked_pair() {
+    let addr = start_server(AppConfig::default());
+
+    let mut host = Client::connect(addr, "");
+    let lobby = host.expect("lobbyLink")["lobby"]
+        .as_str()
+        .unwrap()
+        .to_owned();
+    let mut first = Client::connect(addr, &format!("lobby={lobby}"));
+    let first_code = first.expect("lobbyCode")["code"].clone();
+    let mut second = Client::connect(addr, &format!("lobby={lobby}"));
+    let second_code = second.expect("lobbyCode")["code"].clone();
+
+    host.send(&json!({
+        "type": "lobbyPickPair",
+        "codes": [second_code, first_code],
+    }));
+    let setup = host.expect("gameSetup");
+    assert_eq!(setup["role"], "spectator");
+    assert!(setup["seed"].is_number());
+    assert_eq!(setup["profiles"], json!([null, null]));
+    assert_eq!(second.expect("gameSetup")["role"], 0);
+    assert_eq!(first.expect("gameSetup")["role"], 1);
+
+    second.send(&json!({ "type": "gamePlayerSelectionVote", "wantsToStart": true }));
+    first.send(&json!({ "type": "gamePlayerSelectionVote", "wantsToStart": false }));
+    second.expect("gameSync");
+    second.drop_chip(0, 3);
+    assert_eq!(host.expect("gameMove")["state"]["player"], 1);
+
+    // Spectators cannot move or restart in place of a player
+    host.send(&json!({ "type": "gameEndTurn", "turn": 1, "col": 0 }));
+    host.send(&json!({ "type": "gameRestart" }));
+    first.drop_chip(1, 4);
+    let msg = second.expect_any(&["gameMove", "gameRestartRequest"]);
+    assert_eq!(msg["type"], "gameMove");
+}
+
+#[test]
+fn plays_three_player_games() {
+    let addr = start_server(AppConfig::default());
+
+    let mut host = Client::connect(addr, "");
+    let lobby = host.expect("lobbyLink")["lobby"]
+        .as_str()
+        .unwrap()
+        .to_owned();
+    let mut first = Client::connect(addr, &format!("lobby={lobby}"));
+    let first_code = first.expect("lobbyCode")["code"].clone();
+    let mut second = Client::connect(addr, &format!("lobby={lobby}"));
+    let second_code = second.expect("lobbyCode")["code"].clone();
+
+    host.send(&json!({
+        "type": "lobbyPickPlayers",
+        "codes": [first_code, second_code],
+        "config": { "players": 3 },
+    }));
+    for (client, role) in [(&mut host, 0), (&mut first, 1), (&mut second, 2)] {
+        let setup = client.expect("gameSetup");
+        assert_eq!(setup["role"], role);
+        assert_eq!(setup["config"]["players"], 3);
+    }
+
+    host.send(&json!({ "type": "gamePlayerSelectionVote", "wantsToStart": false }));
+    first.send(&json!({ "type": "gamePlayerSelectionVote", "wantsToStart": true }));
+    // Waits for both votes, which may arrive in either order
+    while second.expect("gamePlayerSelection")["voted"] != json!([true, true, false]) {}
+    second.send(&json!({ "type": "gamePlayerSelectionVote", "wantsToStart": false }));
+    let sync = host.expect("gameSync");
+    assert_eq!(sync["game"]["state"]["player"], 1);
+    assert_eq!(sync["legalMoves"].as_array().unwrap().len(), 9);
+    first.expect("gameSync");
+    second.expect("gameSync");
+
+    first.drop_chip(0, 8);
+    assert_eq!(second.expect_move()["player"], 2);
+    host.expect_move();
+
+    // The game goes on without the player who resigned
+    second.send(&json!({ "type": "gameResign" }));
+    for client in [&mut host, &mut first] {
+        let state = client.expect_move();
+        assert_eq!(state["player"], 0);
+        assert_eq!(state["left"], json!([2]));
+    }
+    host.drop_chip(2, 0);
+    assert_eq!(first.expect_move()["player"], 1);
+
+    first.send(&json!({ "type": "gameResign" }));
+    let state = host.expect_move();
+    assert_eq!(state["result"]["winner"], 0);
+}
+
+#[test]
+fn only_the_host_picks_spectators() {
+    let addr = start_server(AppConfig::default());
+
+    let mut host = Client::connect(addr, "");
+    let lobby = host.expect("lobbyLink")["lobby"]
+        .as_str()
+        .unwrap()
+        .to_owned();
+    let mut first = Client::connect(addr, &format!("lobby={lobby}"));
+    let first_code = first.expect("lobbyCode")["code"].clone();
+    let mut second = Client::connect(addr, &format!("lobby={lobby}"));
+    let second_code = second.expect("lobbyCode")["code"].clone();
+
+    first.send(&json!({ "type": "lobbyPickSpectator", "code": second_code }));
+    first.send(&json!({ "type": "lobbyReady", "ready": true }));
+    let sync = host.expect("lobbySync");
+    assert_eq!(sync["ready"], json!([first_code]));
+    assert_eq!(sync["spectators"], json!([]));
+
+    host.send(&json!({ "type": "lobbyPickSpectator", "code": second_code }));
+    assert_eq!(host.expect("lobbySync")["spectators"], json!([second_code]));
+}
+
+#[test]
+fn picks_start_from_lobby_config() {
+    let addr = start_server(AppConfig::default());
+
+    let mut host = Client::connect(addr, "");
+    let lobby = host.expect("lobbyLink")["lobby"]
+        .as_str()
+        .unwrap()
+        .to_owned();
+    host.send(&json!({
+        "type": "lobbySetConfig",
+        "config": { "timePerTurn": 30_000, "allowDraws": true },
+    }));
+    let sync = host.expect("lobbySync");
+    assert_eq!(sync["config"]["timePerTurn"], 30_000.0);
+
+    // Joiners see the rules before the game starts
+    let mut guest = Client::connect(addr, &format!("lobby={lobby}"));
+    let code = guest.expect("lobbyCode")["code"].clone();
+    assert_eq!(guest.expect("lobbySync")["config"]["allowDraws"], true);
+
+    host.send(&json!({
+        "type": "lobbyPickPlayer",
+        "code": code,
+        "role": 0,
+        "game": null,
+        "config": { "allowDraws": false },
+        "round": 0,
+    }));
+    let config = &guest.expect("gameSetup")["config"];
+    assert_eq!(config["timePerTurn"], 30_000.0);
+    assert_eq!(config["allowDraws"], false);
+}
+
+#[test]
+fn lists_ready_players() {
+    let addr = start_server(AppConfig::default());
+
+    let mut host = Client::connect(addr, "");
+    let lobby = host.expect("lobbyLink")["lobby"]
+        .as_str()
+        .unwrap()
+        .to_owned();
+    let mut guest = Client::connect(addr, &format!("lobby={lobby}"));
+    let code = guest.expect("lobbyCode")["code"].clone();
+    assert_eq!(host.expect("lobbySync")["ready"], json!([]));
+
+    guest.send(&json!({ "type": "lobbyReady", "ready": true }));
+    assert_eq!(host.expect("lobbySync")["ready"], json!([code]));
+
+    guest.send(&json!({ "type": "lobbyReady", "ready": false }));
+    assert_eq!(host.expect("lobbySync")["ready"], json!([]));
+}
+
+#[test]
+fn blocks_kicked_players() {
+    let addr = start_server(AppConfig::default());
+
+    let mut host = Client::connect(addr, "");
+    let lobby = host.expect("lobbyLink")["lobby"]
+        .as_str()
+        .unwrap()
+        .to_owned();
+    let mut guest = Client::connect(addr, &format!("lobby={lobby}"));
+    let code = guest.expect("lobbyCode")["code"].clone();
+    let mut other = Client::connect(addr, &format!("lobby={lobby}"));
+    let other_code = other.expect("lobbyCode")["code"].clone();
+
+    host.send(&json!({ "type": "lobbyKick", "code": other_code }));
+    assert_eq!(other.expect_close_frame(), (4022, "kicked".to_owned()));
+    host.send(&json!({ "type": "lobbyKick", "code": code, "block": true }));
+    assert_eq!(guest.expect_close(), "kicked");
+
+    // Every client of the test connects from the same address
+    let mut rejoined = Client::connect(addr, &format!("lobby={lobby}"));
+    assert_eq!(rejoined.expect_close(), "kicked");
+}
+
+#[test]
+fn reports_how_games_ended() {
+    let addr = start_server(AppConfig::default());
+
+    let mut host = Client::connect(addr, "");
+    let lobby = host.expect("lobbyLink")["lobby"]
+        .as_str()
+        .unwrap()
+        .to_owned();
+    let mut guest = Client::connect(addr, &format!("lobby={lobby}"));
+    let code = guest.expect("lobbyCode")["code"].clone();
+    host.send(&json!({
+        "type": "lobbyPickPlayer",
+        "code": code,
+        "role": 0,
+        "game": null,
+        "config": {},
+        "round": 0,
+    }));
+    for client in [&mut host, &mut guest] {
+        client.expect("gamePlayerSelection");
+    }
+    guest.send(&json!({ "type": "gamePlayerSelectionVote", "wantsToStart": true }));
+    host.send(&json!({ "type": "gamePlayerSelectionVote", "wantsToStart": false }));
+    guest.expect("gameSync");
+    guest.drop_chip(0, 3);
+    host.expect_move();
+
+    host.send(&json!({ "type": "gameResign" }));
+    let result = &guest.expect_move()["result"];
+    assert_eq!(result["winner"], 0);
+    assert_eq!(result["reason"], "resignation");
+    assert!(result["duration"].is_u64());
+    assert_eq!(result["turnTimes"].as_array().unwrap().len(), 1);
+}
+
+#[test]
+fn relays_hovers_of_the_player_to_move() {
+    let addr = start_server(AppConfig::default());
+
+    let mut host = Client::connect(addr, "");
+    let lobby = host.expect("lobbyLink")["lobby"]
+        .as_str()
+        .unwrap()
+        .to_owned();
+    let mut guest = Client::connect(addr, &format!("lobby={lobby}"));
+    let code = guest.expect("lobbyCode")["code"].clone();
+    host.send(&json!({
+        "type": "lobbyPickPlayer",
+        "code": code,
+        "role": 0,
+        "game": null,
+        "config": {},
+        "round": 0,
+    }));
+    for client in [&mut host, &mut guest] {
+        client.expect("gamePlayerSelection");
+    }
+    guest.send(&json!({ "type": "gamePlayerSelectionVote", "wantsToStart": true }));
+    host.send(&json!({ "type": "gamePlayerSelectionVote", "wantsToStart": false }));
+    host.expect("gameSync");
+    guest.expect("gameSync");
+
+    guest.send(&json!({ "type": "gameHover", "col": 2 }));
+    let hover = host.expect("opponentHover");
+    assert_eq!(
+        (hover["turn"].clone(), hover["col"].clone()),
+        (json!(0), json!(2))
+    );
+
+    // Not the host's turn, so this one is dropped
+    host.send(&json!({ "type": "gameHover", "col": 5 }));
+    guest.drop_chip(0, 3);
+    host.expect_move();
+    host.send(&json!({ "type": "gameHover" }));
+    let hover = guest.expect("opponentHover");
+    assert_eq!(hover["turn"], 1);
+    assert!(hover["col"].is_null());
+}
+
+/// Starts a game with the config in a new lobby where the guest moves first,
+/// returning the host, the guest, the lobby ID and the guest's `gameSetup`.
+fn start_game(addr: SocketAddr, config: &Value) -> (Client, Client, String, Value) {
+    let mut host = Client::connect(addr, "");
+    let lobby = host.expect("lobbyLink")["lobby"]
+        .as_str()
+        .unwrap()
+        .to_owned();
+    let mut guest = Client::connect(addr, &format!("lobby={lobby}"));
+    let code = guest.expect("lobbyCode")["code"].clone();
+    host.send(&json!({
+        "type": "lobbyPickPlayer",
+        "code": code,
+        "role": 0,
+        "game": null,
+        "config": config,
+        "round": 0,
+    }));
+    let setup = guest.expect("gameSetup");
+    for client in [&mut host, &mut guest] {
+        client.expect("gamePlayerSelection");
+    }
+    guest.send(&json!({ "type": "gamePlayerSelectionVote", "wantsToStart": true }));
+    host.send(&json!({ "type": "gamePlayerSelectionVote", "wantsToStart": false }));
+    host.expect("gameSync");
+    guest.expect("gameSync");
+    (host, guest, lobby, setup)
+}
+
+#[test]
+fn resumes_with_reconnect_token() {
+    let addr = start_server(AppConfig::default());
+    let (mut host, mut guest, lobby, setup) = start_game(addr, &json!({}));
+    guest.drop_chip(0, 3);
+    host.expect_move();
+
+    drop(guest);
+    assert_eq!(host.expect("gamePlayerDisconnected")["player"], 0);
+
+    let mut impostor = Client::connect(addr, &format!("lobby={lobby}&reconnect=wrong"));
+    assert_eq!(impostor.expect_close(), "reconnectFailed");
+
+    let token = setup["reconnectToken"].as_str().unwrap();
+    let mut guest = Client::connect(addr, &format!("lobby={lobby}&reconnect={token}"));
+    assert_eq!(guest.expect("gameSetup")["role"], 0);
+    assert_eq!(guest.expect("gameSync")["game"]["state"]["turn"], 1);
+    assert_eq!(host.expect("gamePlayerReconnected")["player"], 0);
+
+    host.drop_chip(1, 3);
+    assert_eq!(guest.expect_move()["turn"], 2);
+}
+
+#[test]
+fn reconnected_players_report_their_profile_and_latency() {
+    let addr = start_server(AppConfig {
+        heartbeat_interval: Duration::from_millis(20),
+        ..AppConfig::default()
+    });
+    let (mut host, guest, lobby, setup) = start_game(addr, &json!({}));
+    drop(guest);
+    host.expect("gamePlayerDisconnected");
+
+    let token = setup["reconnectToken"].as_str().unwrap();
+    let mut guest = Client::connect(addr, &format!("lobby={lobby}&reconnect={token}"));
+    guest.expect("gameSync");
+    guest.send(&json!({ "type": "setProfile", "name": "Back" }));
+    let setup = loop {
+        let setup = host.expect("gameSetup");
+        if setup["profiles"][0].is_object() {
+            break setup;
+        }
+    };
+    assert_eq!(setup["profiles"][0]["name"], "Back");
+
+    // Pings are answered while the client reads
+    let measured = (0..50).any(|_| {
+        thread::sleep(Duration::from_millis(20));
+        guest.send(&json!({ "type": "gameRequestSync" }));
+        guest.expect("gameSync")["latency"][0].is_number()
+    });
+    assert!(measured);
+}
+
+#[test]
+fn forfeits_after_the_grace_period() {
+    let addr = start_server(AppConfig {
+        reconnect_grace_period: Duration::from_secs(1),
+        ..AppConfig::default()
+    });
+    let (mut host, guest, lobby, setup) = start_game(addr, &json!({}));
+
+    drop(guest);
+    host.expect("gamePlayerDisconnected");
+    let result = &host.expect_move()["result"];
+    assert_eq!(result["winner"], 1);
+    assert_eq!(result["reason"], "abandonment");
+
+    // The token is no longer valid once the game is over
+    let token = setup["reconnectToken"].as_str().unwrap();
+    let mut guest = Client::connect(addr, &format!("lobby={lobby}&reconnect={token}"));
+    assert_eq!(guest.expect_close(), "reconnectFailed");
+}
+
+#[test]
+fn queued_late_joiner_takes_over_a_free_seat() {
+    let addr = start_server(AppConfig {
+        reconnect_grace_period: Duration::from_secs(1),
+        ..AppConfig::default()
+    });
+    let (mut host, guest, lobby, _) = start_game(addr, &json!({}));
+
+    let mut late = Client::connect(addr, &format!("lobby={lobby}"));
+    assert_eq!(
+        late.expect("gameInProgress")["options"],
+        json!(["spectate", "queue"])
+    );
+    assert_eq!(late.expect("gameSetup")["role"], "spectator");
+    late.send(&json!({ "type": "gameQueue", "queued": true }));
+    assert_eq!(late.expect("gameQueue")["position"], 1);
+
+    drop(guest);
+    assert_eq!(host.expect_move()["result"]["reason"], "abandonment");
+    assert_eq!(late.expect("gameSetup")["role"], 0);
+    late.expect("gamePlayerSelection");
+    host.expect("gamePlayerSelection");
+
+    late.send(&json!({ "type": "gamePlayerSelectionVote", "wantsToStart": true }));
+    host.send(&json!({ "type": "gamePlayerSelectionVote", "wantsToStart": false }));
+    late.expect("gameSync");
+    late.drop_chip(0, 3);
+    assert_eq!(host.expect("gameMove")["state"]["turn"], 1);
+}
+
+#[test]
+fn warns_about_the_remaining_turn_time() {
+    let addr = start_server(AppConfig {
+        time_warnings: vec![Duration::from_secs(2), Duration::from_secs(1)],
+        ..AppConfig::default()
+    });
+    let (mut host, mut guest, _, _) = start_game(addr, &json!({ "timePerTurn": 3000 }));
+
+    // Warnings of a turn which ended are never sent
+    guest.drop_chip(0, 3);
+    let started = Instant::now();
+    let warning = host.expect("gameTimeWarning");
+    assert_eq!(
+        (warning["player"].clone(), warning["remainingMs"].clone()),
+        (json!(1), json!(2000))
+    );
+    assert!(started.elapsed() >= Duration::from_millis(900));
+    let warning = host.expect("gameTimeWarning");
+    assert_eq!(warning["remainingMs"], 1000);
+    assert!(started.elapsed() >= Duration::from_millis(1900));
+
+    host.drop_chip(1, 3);
+    guest.expect("gameMove");
+    let warning = guest.expect("gameTimeWarning");
+    assert_eq!(
+        (warning["player"].clone(), warning["remainingMs"].clone()),
+        (json!(0), json!(2000))
+    );
+}
+
+#[test]
+fn changes_timing_mid_game() {
+    let addr = start_server(AppConfig::default());
+
+    let mut host = Client::connect(addr, "");
+    let lobby = host.expect("lobbyLink")["lobby"]
+        .as_str()
+        .unwrap()
+        .to_owned();
+    let mut guest = Client::connect(addr, &format!("lobby={lobby}"));
+    let code = guest.expect("lobbyCode")["code"].clone();
+    host.send(&json!({
+        "type": "lobbyPickPlayer",
+        "code": code,
+        "role": 0,
+        "game": null,
+        "config": { "timePerTurn": 10000 },
+        "round": 0,
+    }));
+    for client in [&mut host, &mut guest] {
+        client.expect("gamePlayerSelection");
+    }
+    guest.send(&json!({ "type": "gamePlayerSelectionVote", "wantsToStart": true }));
+    host.send(&json!({ "type": "gamePlayerSelectionVote", "wantsToStart": false }));
+    guest.expect("gameSync");
+    guest.drop_chip(0, 3);
+    // The countdown does not depend on the clock of the client
+    let remaining = host.expect("gameMove")["remainingMs"].as_u64().unwrap();
+    assert!(remaining > 0 && remaining <= 10_000);
+
+    host.send(&json!({ "type": "gameChangeTiming", "timePerTurn": 20000 }));
+    let req = guest.expect("gameTimingRequest");
+    assert_eq!(req["player"], 1);
+    assert_eq!(req["req"]["config"]["timePerTurn"], 20000.0);
+
+    guest.send(&json!({ "type": "gameChangeTimingResponse", "accepted": true }));
+    host.expect("gameTimingRequest");
+    assert!(host.expect("gameTimingRequest")["req"].is_null());
+    let setup = host.expect("gameSetup");
+    assert_eq!(setup["config"]["timePerTurn"], 20000.0);
+
+    // The board is kept
+    let state = host.drop_chip(1, 4);
+    assert_eq!(state["turn"], 2);
+}
+
+#[test]
+fn joins_with_join_code() {
+    let addr = start_server(AppConfig::default());
+
+    let mut host = Client::connect(addr, "");
+    let join_code = host.expect("lobbyLink")["joinCode"]
+        .as_str()
+        .unwrap()
+        .to_owned();
+    assert_eq!(join_code.len(), 6);
+
+    let query = format!("code={}", join_code.to_ascii_lowercase());
+    let mut guest = Client::connect(addr, &query);
+    guest.expect("lobbyCode");
+
+    // Regenerating the invite replaces the join code
+    host.send(&json!({ "type": "lobbyRegenerateInvite" }));
+    let new_code = host.expect("lobbyLink")["joinCode"].clone();
+    assert_ne!(new_code, join_code.as_str());
+    let mut late = Client::connect(addr, &format!("code={join_code}"));
+    assert_eq!(late.expect_close(), "inviteInvalid");
+}
+
+#[test]
+fn negotiates_subprotocol() {
+    let addr = start_server(AppConfig::default());
+
+    let mut req = format!("ws://{addr}/ws").into_client_request().unwrap();
+    let offered = "chat, connect-four.v1.json, connect-four.v2.msgpack";
+    req.headers_mut()
+        .insert("Sec-WebSocket-Protocol", offered.parse().unwrap());
+    let tcp = TcpStream::connect(addr).unwrap();
+    tcp.set_read_timeout(Some(READ_TIMEOUT)).unwrap();
+    let (mut socket, res) = tungstenite::client(req, tcp).unwrap();
+    assert_eq!(
+        res.headers()["Sec-WebSocket-Protocol"],
+        "connect-four.v2.msgpack"
+    );
+
+    let Message::Binary(bytes) = socket.read().unwrap() else {
+        panic!("expected a binary frame");
+    };
+    let hello: Value = Encoding::MessagePack.decode(&bytes).unwrap();
+    assert_eq!(hello["type"], "protocolHello");
+    assert_eq!(hello["version"], 2);
+
+    let ping = rmp_serde::to_vec_named(&json!({ "type": "ping", "sent": 1.5 })).unwrap();
+    socket.send(Message::Binary(ping)).unwrap();
+    loop {
+        let bytes = match socket.read().unwrap() {
+            Message::Binary(bytes) => bytes,
+            Message::Ping(_) => continue,
+            msg => panic!("expected a binary frame, got {msg:?}"),
+        };
+        let msg: Value = Encoding::MessagePack.decode(&bytes).unwrap();
+        if msg["type"] == "pong" {
+            assert_eq!(msg["sent"], 1.5);
+            break;
+        }
+    }
+}
+
+#[test]
+fn batches_messages_of_a_move() {
+    let addr = start_server(AppConfig::default());
+
+    let mut host = Client::connect(addr, "");
+    let lobby = host.expect("lobbyLink")["lobby"]
+        .as_str()
+        .unwrap()
+        .to_owned();
+    let mut guest = Client::connect(addr, &format!("lobby={lobby}&batch=1"));
+    let code = guest.expect("lobbyCode")["code"].clone();
+    host.send(&json!({
+        "type": "lobbyPickPlayer",
+        "code": code,
+        "role": 0,
+        "game": null,
+        "config": {},
+        "round": 0,
+    }));
+    for client in [&mut host, &mut guest] {
+        client.expect("gamePlayerSelection");
+    }
+    guest.send(&json!({ "type": "gamePlayerSelectionVote", "wantsToStart": true }));
+    host.send(&json!({ "type": "gamePlayerSelectionVote", "wantsToStart": false }));
+    guest.expect_any(&["gameSync", "batch"]);
+
+    // The move and its confirmation arrive together
+    guest.send(&json!({ "type": "gameEndTurn", "turn": 0, "col": 3 }));
+    let batch = guest.expect("batch");
+    let messages = batch["messages"].as_array().unwrap();
+    assert_eq!(messages.first().unwrap()["type"], "gameMove");
+    assert_eq!(messages.last().unwrap()["type"], "gameEndTurnAccepted");
+
+    // Clients which did not ask for batches get separate messages
+    assert_eq!(host.expect("gameMove")["state"]["player"], 1);
+}
+
+#[test]
+fn rejects_messages_outside_of_games() {
+    let addr = start_server(AppConfig::default());
+
+    let mut host = Client::connect(addr, "");
+    host.expect("lobbyLink");
+    host.send(&json!({ "type": "gameEndTurn", "turn": 0, "col": 3 }));
+    let error = host.expect("error");
+    assert_eq!(error["reason"], "noController");
+
+    host.send(&json!({ "type": "lobbyRequestLink" }));
+    host.expect("lobbyLink");
+}
+
+#[test]
+fn mounts_next_to_other_routes() {
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        rt::System::new().block_on(async move {
+            let cfg = Arc::new(AppConfig::default());
+            let router = LobbyRouter::new(Arc::clone(&cfg)).start();
+            let server = HttpServer::new(move || {
+                let (cfg, router) = (Arc::clone(&cfg), router.clone());
+                App::new()
+                    .route("/hello", web::get().to(HttpResponse::Ok))
+                    .service(
+                        web::scope("/connect-four").configure(|app| configure(app, cfg, router)),
+                    )
+            })
+            .workers(1)
+            .bind(("127.0.0.1", 0))
+            .unwrap();
+            tx.send(server.addrs()[0]).unwrap();
+            server.run().await.unwrap();
+        });
+    });
+    let addr = rx.recv().unwrap();
+
+    let url = Url::parse(&format!("ws://{addr}/connect-four/ws?version=2")).unwrap();
+    let mut host = Client(client::connect(&url, false, READ_TIMEOUT).unwrap());
+    host.expect("lobbyLink");
+
+    let mut tcp = TcpStream::connect(addr).unwrap();
+    tcp.write_all(b"GET /hello HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+        .unwrap();
+    let mut res = String::new();
+    tcp.read_to_string(&mut res).unwrap();
+    assert!(res.starts_with("HTTP/1.1 200"));
+}
+
+#[test]
+fn describes_in_the_chosen_locale() {
+    let addr = start_server(AppConfig::default());
+
+    let mut host = Client::connect(addr, "lang=pl-PL");
+    let lobby = host.expect("lobbyLink")["lobby"]
+        .as_str()
+        .unwrap()
+        .to_owned();
+    let mut guest = Client::connect(addr, &format!("lobby={lobby}"));
+    let code = guest.expect("lobbyCode")["code"].as_u64().unwrap();
+    let notice = host.expect("chatNotice");
+    assert_eq!(notice["notice"]["kind"], "playerJoined");
+    assert_eq!(
+        notice["description"],
+        format!("Gracz {code} dołączył do lobby.")
+    );
+
+    host.send(&json!({ "type": "setLocale", "locale": "en" }));
+    host.send(&json!({
+        "type": "lobbyPickPlayer",
+        "code": code,
+        "role": 0,
+        "game": null,
+        "config": { "timePerTurn": 3_600_000 },
+        "round": 0,
+    }));
+    let rejected = host.expect("configRejected");
+    assert_eq!(rejected["field"], "timePerTurn");
+    assert_eq!(
+        rejected["description"],
+        "Time per turn: above the maximum allowed on this server."
+    );
+
+    // The close frame carries only the code, with or without a locale
+    let mut late = Client::connect(addr, "lobby=00000000-0000-0000-0000-000000000000");
+    assert_eq!(late.expect_close(), "inviteInvalid");
+    let mut late = Client::connect(addr, "lobby=00000000-0000-0000-0000-000000000000&lang=de");
+    let closing = late.expect("closing");
+    assert_eq!(closing["reason"], "inviteInvalid");
+    assert_eq!(
+        closing["description"],
+        "Die Einladung ist ungültig oder abgelaufen."
+    );
+    assert_eq!(late.expect_close(), "inviteInvalid");
+}
+
+#[test]
+fn closes_version_1_clients_with_a_normal_close_code() {
+    let addr = start_server(AppConfig::default());
+    let lobby = "00000000-0000-0000-0000-000000000000";
+
+    let url = Url::parse(&format!("ws://{addr}/ws?version=1&lobby={lobby}")).unwrap();
+    let mut old = Client(client::connect(&url, false, READ_TIMEOUT).unwrap());
+    assert_eq!(old.expect_close_frame(), (1000, "inviteInvalid".to_owned()));
+
+    let mut new = Client::connect(addr, &format!("lobby={lobby}"));
+    assert_eq!(new.expect_close_frame(), (4002, "inviteInvalid".to_owned()));
+}
+
+#[test]
+fn sends_version_1_clients_the_old_messages() {
+    let addr = start_server(AppConfig::default());
+
+    let url = Url::parse(&format!("ws://{addr}/ws?version=1")).unwrap();
+    let mut host = Client(client::connect(&url, false, READ_TIMEOUT).unwrap());
+    let link = host.expect("lobbyLink");
+    assert!(link.get("joinCode").is_none());
+    let lobby = link["lobby"].as_str().unwrap();
+    let mut guest = Client::connect(addr, &format!("lobby={lobby}"));
+    let code = guest.expect("lobbyCode")["code"].clone();
+    let sync = host.expect("lobbySync");
+    assert_eq!(sync, json!({ "type": "lobbySync", "players": [code] }));
+
+    host.send(&json!({
+        "type": "lobbyPickPlayer",
+        "code": code,
+        "role": 0,
+        "game": null,
+        "config": {},
+        "round": 0,
+    }));
+    let setup = host.expect("gameSetup");
+    assert_eq!(setup["role"], 1);
+    assert!(setup.get("reconnectToken").is_none());
+    assert!(guest.expect("gameSetup")["reconnectToken"].is_string());
+}
+
+#[test]
+fn requires_a_valid_token() {
+    let mut cfg = AppConfig::default();
+    cfg.auth.required = true;
+    cfg.auth.secret = Some(String::from("shared secret"));
+    let addr = start_server(cfg);
+
+    let connect = |query: &str| {
+        let url = Url::parse(&format!("ws://{addr}/ws?version=2&{query}")).unwrap();
+        client::connect(&url, false, READ_TIMEOUT)
+    };
+    assert!(connect("").is_err());
+    let forged = auth::sign("user-1", None, "wrong secret").unwrap();
+    assert!(connect(&format!("auth={forged}")).is_err());
+
+    let token = auth::sign("user-1", None, "shared secret").unwrap();
+    let mut host = Client(connect(&format!("auth={token}")).unwrap());
+    host.expect("lobbyLink");
+}
+
+#[test]
+fn pairs_queued_players() {
+    let addr = start_server(AppConfig::default());
+
+    let mut first = Client::connect(addr, "matchmake=1&playerId=alice");
+    assert!(first.expect("matchmakingQueued")["timeout"].is_string());
+    let mut second = Client::connect(addr, "matchmake=1&playerId=bob");
+    second.expect("matchmakingQueued");
+    for client in [&mut first, &mut second] {
+        let setup = client.expect("gameSetup");
+        assert!(setup["reconnectToken"].is_string());
+        client.expect("gamePlayerSelection");
+    }
+
+    let mut third = Client::connect(addr, "matchmake=1");
+    third.expect("matchmakingQueued");
+    third.send(&json!({ "type": "matchmakingCancel" }));
+    assert_eq!(third.expect_close(), "matchmakingCancelled");
+
+    let mut cfg = AppConfig::default();
+    cfg.matchmaking.enabled = false;
+    let addr = start_server(cfg);
+    let url = Url::parse(&format!("ws://{addr}/ws?version=2&matchmake=1")).unwrap();
+    assert!(client::connect(&url, false, READ_TIMEOUT).is_err());
+}
+
+#[test]
+fn posts_events_to_webhooks() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let mut cfg = AppConfig::default();
+    let hook = format!("http://{}/hook", listener.local_addr().unwrap());
+    cfg.webhooks.urls = vec![Url::parse(&hook).unwrap()];
+    let addr = start_server(cfg);
+
+    let mut host = Client::connect(addr, "");
+    let lobby = host.expect("lobbyLink")["lobby"].clone();
+
+    // Failed deliveries are retried
+    let event = receive_webhook(&listener, "503 Service Unavailable");
+    assert_eq!(event["event"], "lobbyCreated");
+    let event = receive_webhook(&listener, "204 No Content");
+    assert_eq!(event["event"], "lobbyCreated");
+    assert_eq!(event["lobby"], lobby);
+    assert!(event["timestamp"].is_string());
+}
+
+/// Accepts a single webhook request, answers it with the status and returns
+/// its body.
+fn receive_webhook(listener: &TcpListener, status: &str) -> Value {
+    let (mut tcp, _) = listener.accept().unwrap();
+    tcp.set_read_timeout(Some(READ_TIMEOUT)).unwrap();
+    let mut req = Vec::new();
+    let mut chunk = [0; 1024];
+    let body_start = loop {
+        let read = tcp.read(&mut chunk).unwrap();
+        req.extend_from_slice(&chunk[..read]);
+        if let Some(i) = req.windows(4).position(|w| w == b"\r\n\r\n") {
+            break i + 4;
+        }
+    };
+    let head = String::from_utf8_lossy(&req[..body_start]).to_ascii_lowercase();
+    assert!(head.starts_with("post /hook "));
+    let len: usize = head
+        .lines()
+        .find_map(|l| l.strip_prefix("content-length:"))
+        .unwrap()
+        .trim()
+        .parse()
+        .unwrap();
+    while req.len() < body_start + len {
+        let read = tcp.read(&mut chunk).unwrap();
+        req.extend_from_slice(&chunk[..read]);
+    }
+    write!(tcp, "HTTP/1.1 {status}\r\nContent-Length: 0\r\n\r\n").unwrap();
+    serde_json::from_slice(&req[body_start..]).unwrap()
+}
+
+/// Sends an HTTP/1.1 request to the admin API and returns the whole
+/// response.
+fn admin_request(addr: SocketAddr, method: &str, path: &str, body: &Value) -> String {
+    let body = body.to_string();
+    let mut tcp = TcpStream::connect(addr).unwrap();
+    tcp.set_read_timeout(Some(READ_TIMEOUT)).unwrap();
+    write!(
+        tcp,
+        "{method} {path} HTTP/1.1\r\nHost: localhost\r\nAuthorization: Bearer admin\r\n\
+         Content-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    )
+    .unwrap();
+    let mut res = String::new();
+    tcp.read_to_string(&mut res).unwrap();
+    res
+}
+
+#[test]
+fn bans_connected_players() {
+    let cfg = AppConfig {
+        admin_token: Some(String::from("admin")),
+        ..AppConfig::default()
+    };
+    let addr = start_server(cfg);
+
+    let mut host = Client::connect(addr, "");
+    host.expect("lobbyLink");
+    let ban = json!({ "ip": "127.0.0.1" });
+    let res = admin_request(addr, "POST", "/admin/bans", &ban);
+    assert!(res.starts_with("HTTP/1.1 201"));
+    assert_eq!(host.expect_close(), "banned");
+
+    let url = Url::parse(&format!("ws://{addr}/ws?version=2")).unwrap();
+    assert!(client::connect(&url, false, READ_TIMEOUT).is_err());
+    let res = admin_request(addr, "GET", "/admin/bans", &Value::Null);
+    assert!(res.ends_with(r#"[{"ip":"127.0.0.1"}]"#));
+
+    let res = admin_request(addr, "DELETE", "/admin/bans", &ban);
+    assert!(res.starts_with("HTTP/1.1 204"));
+    let mut host = Client::connect(addr, "");
+    host.expect("lobbyLink");
+}
+
+#[test]
+fn reloads_settings_through_admin_api() {
+    let cfg = AppConfig {
+        admin_token: Some(String::from("admin")),
+        ..AppConfig::default()
+    };
+    let loader: ConfigLoader = Box::new(|| {
+        Ok(AppConfig {
+            max_players: 1,
+            ..AppConfig::default()
+        })
+    });
+    let addr = start_server_with(cfg, Some(loader));
+
+    let mut host = Client::connect(addr, "");
+    let lobby = host.expect("lobbyLink")["lobby"]
+        .as_str()
+        .unwrap()
+        .to_owned();
+    let mut first = Client::connect(addr, &format!("lobby={lobby}"));
+    let code = first.expect("lobbyCode")["code"].clone();
+
+    let res = admin_request(addr, "POST", "/admin/reload", &Value::Null);
+    assert!(res.starts_with("HTTP/1.1 204"));
+    let mut second = Client::connect(addr, &format!("lobby={lobby}"));
+    assert_eq!(second.expect_close(), "lobbyFull");
+
+    // Connected players stay in the lobby
+    host.send(&json!({ "type": "lobbyPickPair", "codes": [code, code] }));
+    host.expect("lobbyPickRejected");
+}